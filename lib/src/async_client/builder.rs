@@ -3,10 +3,12 @@
 // Copyright (C) 2017-2022 Adam Lock
 
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::async_client::{client::Client, config::*};
+use crate::async_client::{callbacks::OnIssuedTokenRefresh, client::Client, config::*};
 use crate::core::config::Config;
+use crate::sync::Mutex;
 
 /// The `ClientBuilder` is a builder for producing a [`Client`]. It is an alternative to constructing
 /// a [`ClientConfig`] from file or from scratch.
@@ -42,12 +44,15 @@ use crate::core::config::Config;
 ///
 pub struct ClientBuilder {
     config: ClientConfig,
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
 }
 
 impl Default for ClientBuilder {
     fn default() -> Self {
         ClientBuilder {
             config: ClientConfig::default(),
+            issued_token_refresh_callback: None,
         }
     }
 }
@@ -65,6 +70,7 @@ impl ClientBuilder {
     {
         Ok(ClientBuilder {
             config: ClientConfig::load(&path.into())?,
+            issued_token_refresh_callback: None,
         })
     }
 
@@ -74,7 +80,11 @@ impl ClientBuilder {
     /// [`Client`]: ../client/struct.Client.html
     pub fn client(self) -> Option<Client> {
         if self.is_valid() {
-            Some(Client::new(self.config))
+            let mut client = Client::new(self.config);
+            if let Some(issued_token_refresh_callback) = self.issued_token_refresh_callback {
+                client.set_shared_issued_token_refresh_callback(issued_token_refresh_callback);
+            }
+            Some(client)
         } else {
             None
         }
@@ -225,6 +235,20 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets a callback that supplies a fresh token each time a session which uses an issued
+    /// token, e.g. a JWT, is activated. The token of a [`ClientUserToken`] is only used when
+    /// there is no callback.
+    ///
+    /// [`ClientUserToken`]: ../config/struct.ClientUserToken.html
+    pub fn issued_token_refresh_callback<CB>(mut self, issued_token_refresh_callback: CB) -> Self
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+        self
+    }
+
     /// Sets the session retry limit.
     pub fn session_retry_limit(mut self, session_retry_limit: i32) -> Self {
         if session_retry_limit < 0 && session_retry_limit != -1 {
@@ -258,6 +282,13 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets the maximum number of batches sent at the same time when a service call exceeds the
+    /// server's operation limits and is split up.
+    pub fn max_concurrent_batches(mut self, max_concurrent_batches: usize) -> Self {
+        self.config.performance.max_concurrent_batches = max_concurrent_batches;
+        self
+    }
+
     /// Session name - the default name to use for a new session
     pub fn session_name<T>(mut self, session_name: T) -> Self
     where
@@ -289,6 +320,7 @@ fn client_builder() {
         .session_retry_limit(999)
         .session_timeout(777)
         .ignore_clock_skew()
+        .max_concurrent_batches(8)
         .session_name("SessionName")
         .request_timeout(Duration::from_millis(2000))
         // TODO user tokens, endpoints
@@ -314,6 +346,7 @@ fn client_builder() {
     assert_eq!(c.session_retry_limit, 999);
     assert_eq!(c.session_timeout, 777);
     assert_eq!(c.performance.ignore_clock_skew, true);
+    assert_eq!(c.performance.max_concurrent_batches, 8);
     assert_eq!(c.session_name, "SessionName");
     assert_eq!(c.request_timeout, Some(Duration::from_millis(2000)))
 }
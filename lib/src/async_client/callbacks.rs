@@ -13,7 +13,10 @@
 
 use std::fmt;
 
-use crate::types::{service_types::EventNotificationList, status_code::StatusCode};
+use crate::types::{
+    service_types::{EventNotificationList, StatusChangeNotification},
+    status_code::StatusCode,
+};
 
 use super::subscription::MonitoredItem;
 
@@ -38,6 +41,18 @@ pub trait OnSubscriptionNotification {
     /// are individual `EventFieldList` structs filled from the select clause criteria from when the
     /// event was constructed. The default implementation does nothing.
     fn on_event(&mut self, _events: &EventNotificationList) {}
+
+    /// Called by the subscription after a `StatusChangeNotification`, e.g. when the server has
+    /// deleted the subscription because its lifetime expired. The default implementation does
+    /// nothing.
+    fn on_status_change(&mut self, _status_change: &StatusChangeNotification) {}
+
+    /// Returns `true` once the notifications are no longer wanted, e.g. because whatever receives
+    /// them has been dropped. The session deletes the subscription of a closed callback the next
+    /// time it is polled. The default implementation is never closed.
+    fn is_closed(&self) -> bool {
+        false
+    }
 }
 
 /// The `OnConnectionStatusChange` trait can be used to register on the session to be notified
@@ -60,6 +75,15 @@ pub trait OnSessionClosed {
     fn on_session_closed(&mut self, status_code: StatusCode);
 }
 
+/// The `OnIssuedTokenRefresh` trait can be registered on a session or the client builder to
+/// supply the token of an `IdentityToken::IssuedToken`, e.g. a JWT obtained from an
+/// authorization service, so that a token which has expired is not used again.
+pub trait OnIssuedTokenRefresh {
+    /// Called each time the session is activated to obtain the token to activate it with. An
+    /// error prevents the session from being activated.
+    fn on_issued_token_refresh(&mut self) -> Result<String, StatusCode>;
+}
+
 /// This is a concrete implementation of [`OnSubscriptionNotification`] that calls a function when
 /// a data change occurs.
 pub struct DataChangeCallback {
@@ -159,3 +183,24 @@ impl SessionClosedCallback {
         Self { cb: Box::new(cb) }
     }
 }
+
+/// This is a concrete implementation of [`OnIssuedTokenRefresh`] that calls the supplied function.
+pub struct IssuedTokenRefreshCallback {
+    cb: Box<dyn FnMut() -> Result<String, StatusCode> + Send + Sync + 'static>,
+}
+
+impl OnIssuedTokenRefresh for IssuedTokenRefreshCallback {
+    fn on_issued_token_refresh(&mut self) -> Result<String, StatusCode> {
+        (self.cb)()
+    }
+}
+
+impl IssuedTokenRefreshCallback {
+    // Constructor
+    pub fn new<CB>(cb: CB) -> Self
+    where
+        CB: FnMut() -> Result<String, StatusCode> + Send + Sync + 'static,
+    {
+        Self { cb: Box::new(cb) }
+    }
+}
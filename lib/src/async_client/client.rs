@@ -10,6 +10,7 @@ use chrono::Duration;
 use parking_lot::Mutex;
 
 use super::{
+    callbacks::OnIssuedTokenRefresh,
     config::{ClientConfig, ClientEndpoint, ANONYMOUS_USER_TOKEN_ID},
     session::{
         services::*,
@@ -44,6 +45,9 @@ pub enum IdentityToken {
     UserName(String, String),
     /// X5090 cert - a path to the cert.der, and private.pem
     X509(PathBuf, PathBuf),
+    /// A token issued by an authorization service, e.g. a JWT. A session with an issued token
+    /// refresh callback asks it for a fresh token instead.
+    IssuedToken(String),
 }
 
 /// The `Client` defines a connection that can be used to to get end points or establish
@@ -72,6 +76,9 @@ pub struct Client {
     certificate_store: Arc<RwLock<CertificateStore>>,
     /// The session retry policy for new sessions
     session_retry_policy: SessionRetryPolicy,
+    /// Supplies fresh issued tokens to new sessions
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
 }
 
 impl Drop for Client {
@@ -164,9 +171,39 @@ impl Client {
             config,
             session_retry_policy,
             certificate_store: Arc::new(RwLock::new(certificate_store)),
+            issued_token_refresh_callback: None,
         }
     }
 
+    /// Registers a callback that supplies a fresh token to the sessions that the client creates
+    /// with an [`IdentityToken::IssuedToken`]. The callback is shared by those sessions.
+    pub fn set_issued_token_refresh_callback<CB>(&mut self, issued_token_refresh_callback: CB)
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+    }
+
+    /// Sets the session retry policy of the sessions that the client creates, e.g. one with a
+    /// `RetryStrategy` that backs off. By default the policy is made from the retry limit and
+    /// interval of the config.
+    pub fn set_session_retry_policy(&mut self, session_retry_policy: SessionRetryPolicy) {
+        self.session_retry_policy = session_retry_policy;
+    }
+
+    /// Returns the session retry policy of the sessions that the client creates
+    pub fn session_retry_policy(&self) -> &SessionRetryPolicy {
+        &self.session_retry_policy
+    }
+
+    pub(crate) fn set_shared_issued_token_refresh_callback(
+        &mut self,
+        issued_token_refresh_callback: Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>,
+    ) {
+        self.issued_token_refresh_callback = Some(issued_token_refresh_callback);
+    }
+
     /// Returns a filled OPC UA [`ApplicationDescription`] using information from the config
     ///
     /// [`ApplicationDescription`]: ../../opcua_types/service_types/application_description/struct.ApplicationDescription.html
@@ -249,8 +286,19 @@ impl Client {
     where
         T: Into<EndpointDescription>,
     {
-        let endpoint = endpoint.into();
+        let session = self
+            .connect_to_endpoint_unshared(endpoint.into(), user_identity_token)
+            .await?;
+        Ok(Arc::new(RwLock::new(session)))
+    }
 
+    /// Connects to an ad-hoc server endpoint description like `connect_to_endpoint()`, but
+    /// returns a session that is owned by the caller instead of shared behind a lock.
+    pub(crate) async fn connect_to_endpoint_unshared(
+        &mut self,
+        endpoint: EndpointDescription,
+        user_identity_token: IdentityToken,
+    ) -> Result<Session, StatusCode> {
         // Get the server endpoints
         let server_url = endpoint.endpoint_url.as_ref();
 
@@ -281,18 +329,14 @@ impl Client {
         })?;
 
         // Create a session
-        let session = self
-            .new_session_from_info((server_endpoint, user_identity_token))
+        let mut session = self
+            .make_session((server_endpoint, user_identity_token).into())
             .unwrap();
 
-        {
-            // Connect to the server
-            let mut session = session.write();
-            session.connect_and_activate().await.map_err(|err| {
-                error!("Got an error while creating the default session - {}", err);
-                err
-            })?;
-        }
+        // Connect to the server
+        session.connect_and_activate().await.inspect_err(|err| {
+            error!("Got an error while creating the default session - {}", err);
+        })?;
 
         Ok(session)
     }
@@ -382,14 +426,23 @@ impl Client {
     where
         T: Into<SessionInfo>,
     {
-        let session_info = session_info.into();
+        self.make_session(session_info.into())
+            .map(|session| Arc::new(RwLock::new(session)))
+    }
+
+    /// Creates a new [`Session`] for the session info that is owned by the caller
+    fn make_session(&mut self, session_info: SessionInfo) -> Result<Session, String> {
         if !is_opc_ua_binary_url(session_info.endpoint.endpoint_url.as_ref()) {
             Err(format!(
                 "Endpoint url {}, is not a valid / supported url",
                 session_info.endpoint.endpoint_url
             ))
         } else {
-            let session = Arc::new(RwLock::new(Session::new(
+            let issued_token = matches!(
+                session_info.user_identity_token,
+                IdentityToken::IssuedToken(_)
+            );
+            let mut session = Session::new(
                 self.application_description(),
                 self.config.session_name.clone(),
                 self.certificate_store.clone(),
@@ -398,7 +451,16 @@ impl Client {
                 self.decoding_options(),
                 self.config.performance.ignore_clock_skew,
                 self.config.request_timeout,
-            )));
+            );
+            session.set_max_concurrent_batches(self.config.performance.max_concurrent_batches);
+            if issued_token {
+                if let Some(ref issued_token_refresh_callback) = self.issued_token_refresh_callback
+                {
+                    session.set_shared_issued_token_refresh_callback(
+                        issued_token_refresh_callback.clone(),
+                    );
+                }
+            }
             Ok(session)
         }
     }
@@ -655,6 +717,8 @@ impl Client {
                     token.user.clone(),
                     password.clone(),
                 ))
+            } else if let Some(ref issued_token) = token.issued_token {
+                Some(IdentityToken::IssuedToken(issued_token.clone()))
             } else if let Some(ref cert_path) = token.cert_path {
                 token.private_key_path.as_ref().map(|private_key_path| {
                     IdentityToken::X509(PathBuf::from(cert_path), PathBuf::from(private_key_path))
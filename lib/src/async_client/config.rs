@@ -32,6 +32,9 @@ pub struct ClientUserToken {
     pub cert_path: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub private_key_path: Option<String>,
+    /// A token issued by an authorization service, e.g. a JWT
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issued_token: Option<String>,
 }
 
 impl ClientUserToken {
@@ -46,6 +49,7 @@ impl ClientUserToken {
             password: Some(password.into()),
             cert_path: None,
             private_key_path: None,
+            issued_token: None,
         }
     }
 
@@ -61,10 +65,28 @@ impl ClientUserToken {
             password: None,
             cert_path: Some(cert_path.to_string_lossy().to_string()),
             private_key_path: Some(private_key_path.to_string_lossy().to_string()),
+            issued_token: None,
+        }
+    }
+
+    /// Constructs a client token which holds a name and a token issued by an authorization
+    /// service, e.g. a JWT. The token may be replaced by a refresh callback on the session.
+    pub fn issued_token<S, T>(user: S, issued_token: T) -> Self
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        ClientUserToken {
+            user: user.into(),
+            password: None,
+            cert_path: None,
+            private_key_path: None,
+            issued_token: Some(issued_token.into()),
         }
     }
 
-    /// Test if the token, i.e. that it has a name, and either a password OR a cert path and key path.
+    /// Test if the token, i.e. that it has a name, and either a password OR a cert path and key path
+    /// OR an issued token.
     /// The paths are not validated.
     pub fn is_valid(&self) -> bool {
         let mut valid = true;
@@ -73,25 +95,33 @@ impl ClientUserToken {
             valid = false;
         }
         // A token must properly represent one kind of token or it is not valid
-        if self.password.is_some() {
-            if self.cert_path.is_some() || self.private_key_path.is_some() {
-                error!(
-                    "User token {} holds a password and certificate info - it cannot be both.",
-                    self.user
-                );
-                valid = false;
-            }
-        } else {
-            if self.cert_path.is_none() && self.private_key_path.is_none() {
-                error!(
-                    "User token {} fails to provide a password or certificate info.",
-                    self.user
-                );
-                valid = false;
-            } else if self.cert_path.is_none() || self.private_key_path.is_none() {
-                error!("User token {} fails to provide both a certificate path and a private key path.", self.user);
-                valid = false;
-            }
+        let has_cert = self.cert_path.is_some() || self.private_key_path.is_some();
+        let kinds = [
+            self.password.is_some(),
+            has_cert,
+            self.issued_token.is_some(),
+        ]
+        .iter()
+        .filter(|kind| **kind)
+        .count();
+        if kinds > 1 {
+            error!(
+                "User token {} holds more than one of a password, certificate info or issued token - it can only be one.",
+                self.user
+            );
+            valid = false;
+        } else if kinds == 0 {
+            error!(
+                "User token {} fails to provide a password, certificate info or issued token.",
+                self.user
+            );
+            valid = false;
+        } else if has_cert && (self.cert_path.is_none() || self.private_key_path.is_none()) {
+            error!(
+                "User token {} fails to provide both a certificate path and a private key path.",
+                self.user
+            );
+            valid = false;
         }
         valid
     }
@@ -156,6 +186,18 @@ pub struct Performance {
     /// Ignore clock skew allows the client to make a successful connection to the server, even
     /// when the client and server clocks are out of sync.
     pub ignore_clock_skew: bool,
+    /// Maximum number of batches sent at the same time when a service call exceeds the server's
+    /// operation limits and is split up.
+    #[serde(default = "Performance::default_max_concurrent_batches")]
+    pub max_concurrent_batches: usize,
+}
+
+impl Performance {
+    pub const DEFAULT_MAX_CONCURRENT_BATCHES: usize = 4;
+
+    fn default_max_concurrent_batches() -> usize {
+        Self::DEFAULT_MAX_CONCURRENT_BATCHES
+    }
 }
 
 /// Client OPC UA configuration
@@ -339,6 +381,7 @@ impl ClientConfig {
             },
             performance: Performance {
                 ignore_clock_skew: false,
+                max_concurrent_batches: Performance::DEFAULT_MAX_CONCURRENT_BATCHES,
             },
             session_name: "Rust OPC UA Client".into(),
             request_timeout: None,
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use tokio::sync::mpsc::{self, Sender, UnboundedReceiver, UnboundedSender};
 
@@ -13,6 +13,9 @@ pub(crate) struct MessageQueue {
     /// Basically, the sent requests reside here until the response returns at which point the entry is removed.
     /// If a response is received for which there is no entry, the response will be discarded.
     inflight_requests: HashMap<u32, Option<Sender<SupportedMessage>>>,
+    /// The requests that were cancelled by the session. Their responses arrive late, if at all,
+    /// and are dropped.
+    cancelled_requests: HashSet<u32>,
     /// A map of incoming responses waiting to be processed
     responses: HashMap<u32, SupportedMessage>,
     /// This is the queue that messages will be sent onto the transport for sending
@@ -28,6 +31,7 @@ impl MessageQueue {
     pub fn new() -> MessageQueue {
         MessageQueue {
             inflight_requests: HashMap::new(),
+            cancelled_requests: HashSet::new(),
             responses: HashMap::new(),
             sender: None,
         }
@@ -35,6 +39,7 @@ impl MessageQueue {
 
     pub(crate) fn clear(&mut self) {
         self.inflight_requests.clear();
+        self.cancelled_requests.clear();
         self.responses.clear();
     }
 
@@ -93,12 +98,31 @@ impl MessageQueue {
         let _ = self.inflight_requests.remove(&request_handle);
     }
 
+    /// Called when a session's request is cancelled. Nothing waits for the response any more, so
+    /// it is dropped when it arrives.
+    pub(crate) fn request_was_cancelled(&mut self, request_handle: u32) {
+        debug!(
+            "Request {} was cancelled and any response will be dropped",
+            request_handle
+        );
+        if self.inflight_requests.remove(&request_handle).is_some() {
+            self.cancelled_requests.insert(request_handle);
+        }
+    }
+
     /// Called by the connection to store a response for the consumption of the session.
     pub(crate) async fn store_response(&mut self, response: SupportedMessage) {
         // Remove corresponding request handle from inflight queue, add to responses
         let request_handle = response.request_handle();
         trace!("Received response {:?}", response);
         debug!("Response to Request {} has been stored", request_handle);
+        if self.cancelled_requests.remove(&request_handle) {
+            debug!(
+                "Response to cancelled request {} has been dropped",
+                request_handle
+            );
+            return;
+        }
         // Remove the inflight request
         // This true / false is slightly clunky.
         if let Some(sender) = self.inflight_requests.remove(&request_handle) {
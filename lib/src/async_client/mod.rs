@@ -102,12 +102,14 @@ mod comms;
 mod message_queue;
 mod subscription;
 mod subscription_state;
+mod subscription_stream;
 
 // Use through prelude
 mod builder;
 mod callbacks;
 mod client;
 mod config;
+mod redundant_session;
 mod session;
 mod session_retry_policy;
 
@@ -153,9 +155,18 @@ pub mod prelude {
         callbacks::*,
         client::*,
         config::*,
-        session::{services::*, session::*},
+        redundant_session::{FailoverMode, FailoverOptions, RedundantServer, RedundantSession},
+        session::{
+            browse::*, namespaces::NamespaceTable, services::*, session::*, values::MethodArguments,
+        },
+        session_retry_policy::{
+            RetryContext, RetryDecision, RetryStatistics, RetryStrategy, SessionRetryPolicy,
+        },
         subscription::MonitoredItem,
+        subscription_stream::{SubscriptionNotification, SubscriptionStream},
     };
+
+    pub use tokio_util::sync::CancellationToken;
 }
 
 #[cfg(test)]
@@ -0,0 +1,863 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides a session over a set of redundant servers, which fails over from one server to
+//! another as described in OPC UA Part 4 6.6.2. Only non-transparent redundancy needs the client
+//! to fail over. A server with transparent redundancy looks like a single server to the client.
+
+use std::{
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    sync::*,
+    types::{
+        node_ids::VariableId,
+        service_types::{MonitoredItemCreateRequest, RedundancySupport},
+        status_code::StatusCode,
+        *,
+    },
+};
+
+use super::{
+    callbacks::{DataChangeCallback, OnSubscriptionNotification},
+    client::{Client, IdentityToken},
+    session::{
+        services::{DiscoveryService, MonitoredItemService, SubscriptionService},
+        session::{Session, SubscriptionParameters},
+    },
+    session_retry_policy::SessionRetryPolicy,
+};
+
+/// How the client fails over from one server of a redundant set to another, as described in
+/// OPC UA Part 4 6.6.2.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FailoverMode {
+    /// Only the active server is connected. On failover the client connects to another server
+    /// and creates the subscriptions there.
+    Cold,
+    /// Every server is connected and its service level monitored, but only the active server
+    /// has the subscriptions. On failover they are created on the new active server.
+    Warm,
+    /// Every server is connected and has the subscriptions, with monitored items that sample on
+    /// the backup servers and report on the active server. On failover the monitored items of the
+    /// new active server are switched to report, so no data is lost.
+    Hot,
+}
+
+impl From<RedundancySupport> for FailoverMode {
+    /// The failover mode for the redundancy of a server set. A server that mirrors sessions also
+    /// supports hot failover, and one without redundancy is treated as cold so that the client
+    /// can still fail over to the other servers that it was given.
+    fn from(redundancy_support: RedundancySupport) -> Self {
+        match redundancy_support {
+            RedundancySupport::Warm => FailoverMode::Warm,
+            RedundancySupport::Hot | RedundancySupport::HotAndMirrored => FailoverMode::Hot,
+            _ => FailoverMode::Cold,
+        }
+    }
+}
+
+/// Options that control when a `RedundantSession` fails over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailoverOptions {
+    /// The failover mode, or `None` for the mode matching the `RedundancySupport` of the first
+    /// server that the session connects to.
+    pub failover_mode: Option<FailoverMode>,
+    /// A service level below which the active server is failed over from, if another server has a
+    /// higher service level. Servers in a healthy state have a service level of 200 or more.
+    pub min_service_level: u8,
+    /// The publishing interval in milliseconds of the subscriptions that monitor service levels
+    pub service_level_interval: f64,
+    /// How long to wait between attempts to connect to a server that is not connected
+    pub reconnect_interval: Duration,
+}
+
+impl Default for FailoverOptions {
+    fn default() -> Self {
+        Self {
+            failover_mode: None,
+            min_service_level: 200,
+            service_level_interval: 1000.0,
+            reconnect_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The state of a server in the redundant set
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedundantServer {
+    /// The URI of the server, which is empty until the server has been connected to
+    pub server_uri: String,
+    /// The endpoint that the client connects to
+    pub endpoint_url: String,
+    /// The last service level of the server, or 0 if it is not connected
+    pub service_level: u8,
+    /// Whether the client is connected to the server
+    pub is_connected: bool,
+    /// Whether the server is the one that the client uses
+    pub is_active: bool,
+}
+
+/// A subscription created by the application, which is created again on whichever server is
+/// active.
+struct RedundantSubscription {
+    publishing_interval: f64,
+    lifetime_count: u32,
+    max_keep_alive_count: u32,
+    max_notifications_per_publish: u32,
+    priority: u8,
+    items_to_create: Vec<MonitoredItemCreateRequest>,
+    callback: Arc<Mutex<dyn OnSubscriptionNotification + Send + Sync + 'static>>,
+}
+
+/// A subscription on one of the servers
+struct ServerSubscription {
+    subscription_id: u32,
+    monitored_item_ids: Vec<u32>,
+}
+
+struct ServerEntry {
+    endpoint: EndpointDescription,
+    server_uri: String,
+    session: Option<Session>,
+    service_level: Arc<AtomicU8>,
+    /// The subscriptions on this server, by the handle of the application's subscription
+    subscriptions: Vec<Option<ServerSubscription>>,
+    last_connect_attempt: Option<Instant>,
+}
+
+impl ServerEntry {
+    fn new(endpoint: EndpointDescription) -> ServerEntry {
+        ServerEntry {
+            endpoint,
+            server_uri: String::new(),
+            session: None,
+            service_level: Arc::new(AtomicU8::new(0)),
+            subscriptions: Vec::new(),
+            last_connect_attempt: None,
+        }
+    }
+
+    fn service_level(&self) -> u8 {
+        self.service_level.load(Ordering::Relaxed)
+    }
+
+    fn subscription(&self, handle: usize) -> Option<&ServerSubscription> {
+        self.subscriptions.get(handle).and_then(|s| s.as_ref())
+    }
+}
+
+type FailoverCallback = Box<dyn FnMut(&RedundantServer) + Send + Sync + 'static>;
+
+/// Finds the connected server with the highest service level, other than one to exclude. Of the
+/// servers with the same service level, the first is chosen.
+pub(crate) fn best_server(servers: &[RedundantServer], exclude: Option<usize>) -> Option<usize> {
+    servers
+        .iter()
+        .enumerate()
+        .filter(|(idx, server)| Some(*idx) != exclude && server.is_connected)
+        .min_by_key(|(idx, server)| (std::cmp::Reverse(server.service_level), *idx))
+        .map(|(idx, _)| idx)
+}
+
+/// Finds the server to fail over to. That is the connected server with the highest service
+/// level, if the active server has been lost or has a lower service level.
+pub(crate) fn failover_target(servers: &[RedundantServer]) -> Option<usize> {
+    let active = servers
+        .iter()
+        .position(|server| server.is_active && server.is_connected);
+    best_server(servers, active).filter(|best| match active {
+        Some(active) => servers[*best].service_level > servers[active].service_level,
+        None => true,
+    })
+}
+
+/// Decides whether to fail over, which is when there is no connected active server, or when the
+/// service level of the active server is below the minimum and another server's is higher. In
+/// cold failover the other servers are not connected, so any of them that is due a connection
+/// attempt may have a higher service level.
+pub(crate) fn should_fail_over<F>(
+    servers: &[RedundantServer],
+    failover_mode: FailoverMode,
+    min_service_level: u8,
+    reconnect_due: F,
+) -> bool
+where
+    F: Fn(usize) -> bool,
+{
+    match servers.iter().position(|server| server.is_active) {
+        Some(active) if servers[active].is_connected => {
+            if servers[active].service_level >= min_service_level {
+                false
+            } else if failover_mode == FailoverMode::Cold {
+                (0..servers.len()).any(|idx| idx != active && reconnect_due(idx))
+            } else {
+                failover_target(servers).is_some()
+            }
+        }
+        _ => true,
+    }
+}
+
+/// A session over a redundant set of servers that uses one of them, the active server, and fails
+/// over to another when the active server's connection is lost or its service level drops.
+///
+/// The session reads `RedundancySupport` from `Server/ServerRedundancy` of the first server that it
+/// connects to, which decides the `FailoverMode`. If it is given a single endpoint, the other
+/// servers of the set are found with `FindServers` from the `ServerUriArray` of that server. The
+/// service level of each connected server is monitored with a subscription. Subscriptions must
+/// be created through the redundant session so that it can create them again on another server.
+///
+/// Call `poll()` or `run()` to run the sessions, detect failures and fail over. Call
+/// `disconnect()` before dropping it.
+pub struct RedundantSession {
+    client: Client,
+    user_identity_token: IdentityToken,
+    options: FailoverOptions,
+    failover_mode: FailoverMode,
+    servers: Vec<ServerEntry>,
+    active: Option<usize>,
+    subscriptions: Vec<Option<RedundantSubscription>>,
+    failover_callback: Option<FailoverCallback>,
+}
+
+impl RedundantSession {
+    /// Creates a redundant session over the servers with the endpoints. The client's sessions to
+    /// each server do not retry to connect, since the redundant session reconnects or fails over
+    /// instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client that creates the sessions.
+    /// * `endpoints` - The endpoint of each server, or of one server of the set.
+    /// * `user_identity_token` - The identity to activate each session with.
+    /// * `options` - When to fail over.
+    ///
+    pub fn new(
+        mut client: Client,
+        endpoints: Vec<EndpointDescription>,
+        user_identity_token: IdentityToken,
+        options: FailoverOptions,
+    ) -> RedundantSession {
+        let session_timeout = client.session_retry_policy().session_timeout();
+        client.set_session_retry_policy(SessionRetryPolicy::never(session_timeout));
+        RedundantSession {
+            client,
+            user_identity_token,
+            failover_mode: options.failover_mode.unwrap_or(FailoverMode::Cold),
+            options,
+            servers: endpoints.into_iter().map(ServerEntry::new).collect(),
+            active: None,
+            subscriptions: Vec::new(),
+            failover_callback: None,
+        }
+    }
+
+    /// Registers a callback that is called with the new active server after each failover
+    pub fn set_failover_callback<CB>(&mut self, failover_callback: CB)
+    where
+        CB: FnMut(&RedundantServer) + Send + Sync + 'static,
+    {
+        self.failover_callback = Some(Box::new(failover_callback));
+    }
+
+    /// Returns the failover mode
+    pub fn failover_mode(&self) -> FailoverMode {
+        self.failover_mode
+    }
+
+    /// Returns the session of the active server, if there is one. The session belongs to the
+    /// redundant session, so it is only borrowed between polls.
+    pub fn session(&self) -> Option<&Session> {
+        self.active
+            .and_then(|idx| self.servers[idx].session.as_ref())
+    }
+
+    /// Returns the state of each server in the set
+    pub fn servers(&self) -> Vec<RedundantServer> {
+        (0..self.servers.len())
+            .map(|idx| self.server_state(idx))
+            .collect()
+    }
+
+    fn server_state(&self, idx: usize) -> RedundantServer {
+        let server = &self.servers[idx];
+        RedundantServer {
+            server_uri: server.server_uri.clone(),
+            endpoint_url: server.endpoint.endpoint_url.as_ref().to_string(),
+            service_level: server.service_level(),
+            is_connected: server.session.is_some(),
+            is_active: self.active == Some(idx),
+        }
+    }
+
+    /// Connects to the servers and makes the one with the highest service level active. In cold
+    /// failover the other servers are not kept connected.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - A server is active
+    /// * `Err(StatusCode)` - No server could be connected to
+    ///
+    pub async fn connect(&mut self) -> Result<(), StatusCode> {
+        let mut first = None;
+        for idx in 0..self.servers.len() {
+            if self.connect_server(idx).await.is_ok() {
+                first = Some(idx);
+                break;
+            }
+        }
+        let first = first.ok_or(StatusCode::BadNotConnected)?;
+        self.read_server_redundancy(first).await;
+
+        if self.failover_mode != FailoverMode::Cold {
+            for idx in 0..self.servers.len() {
+                if self.servers[idx].session.is_none() {
+                    let _ = self.connect_server(idx).await;
+                }
+            }
+        }
+        let best = best_server(&self.servers(), None).unwrap_or(first);
+        if self.failover_mode == FailoverMode::Cold {
+            self.disconnect_backups(Some(best)).await;
+        }
+        self.activate(best, None).await;
+        if self.failover_mode == FailoverMode::Hot {
+            for idx in 0..self.servers.len() {
+                self.prepare_backup(idx).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Disconnects from every server
+    pub async fn disconnect(&mut self) {
+        for idx in 0..self.servers.len() {
+            self.disconnect_server(idx).await;
+        }
+        self.active = None;
+    }
+
+    /// Creates a subscription and its monitored items on the active server, and in hot failover
+    /// on the backup servers too with monitored items that only sample. The subscription is
+    /// created again on whichever server becomes active.
+    ///
+    /// # Arguments
+    ///
+    /// * `publishing_interval` - The requested publishing interval in milliseconds.
+    /// * `lifetime_count` - The requested lifetime count.
+    /// * `max_keep_alive_count` - The requested maximum keep alive count.
+    /// * `max_notifications_per_publish` - The maximum number of notifications per publish.
+    /// * `priority` - The relative priority of the subscription.
+    /// * `items_to_create` - The monitored items of the subscription.
+    /// * `callback` - The callback that receives the notifications from every server.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - A handle to the subscription
+    /// * `Err(StatusCode)` - The subscription could not be created on the active server
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_subscription<CB>(
+        &mut self,
+        publishing_interval: f64,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        items_to_create: &[MonitoredItemCreateRequest],
+        callback: CB,
+    ) -> Result<usize, StatusCode>
+    where
+        CB: OnSubscriptionNotification + Send + Sync + 'static,
+    {
+        let active = self.active.ok_or(StatusCode::BadNotConnected)?;
+        let handle = self.subscriptions.len();
+        self.subscriptions.push(Some(RedundantSubscription {
+            publishing_interval,
+            lifetime_count,
+            max_keep_alive_count,
+            max_notifications_per_publish,
+            priority,
+            items_to_create: items_to_create.to_vec(),
+            callback: Arc::new(Mutex::new(callback)),
+        }));
+        if let Err(status_code) = self
+            .create_server_subscription(active, handle, MonitoringMode::Reporting)
+            .await
+        {
+            self.subscriptions[handle] = None;
+            return Err(status_code);
+        }
+        if self.failover_mode == FailoverMode::Hot {
+            for idx in 0..self.servers.len() {
+                self.prepare_backup(idx).await;
+            }
+        }
+        Ok(handle)
+    }
+
+    /// Deletes a subscription from every server
+    pub async fn delete_subscription(&mut self, handle: usize) -> Result<(), StatusCode> {
+        if self
+            .subscriptions
+            .get(handle)
+            .and_then(|s| s.as_ref())
+            .is_none()
+        {
+            return Err(StatusCode::BadSubscriptionIdInvalid);
+        }
+        self.subscriptions[handle] = None;
+        for idx in 0..self.servers.len() {
+            self.delete_server_subscription(idx, handle).await;
+        }
+        Ok(())
+    }
+
+    /// Polls the session of every connected server, fails over if the active server is lost or
+    /// its service level drops, and reconnects to backup servers.
+    ///
+    /// # Returns
+    ///
+    /// * `true` - if an action was performed during the poll
+    /// * `false` - if no action was performed during the poll
+    ///
+    pub async fn poll(&mut self) -> bool {
+        let mut did_something = false;
+        for idx in 0..self.servers.len() {
+            if let Some(session) = self.servers[idx].session.as_mut() {
+                let connected = session.is_connected();
+                if connected {
+                    did_something |= session.poll().await.unwrap_or(false);
+                } else {
+                    warn!(
+                        "Lost the connection to redundant server {}",
+                        self.servers[idx].endpoint.endpoint_url
+                    );
+                    self.disconnect_server(idx).await;
+                    did_something = true;
+                }
+            }
+        }
+
+        let should_fail_over = should_fail_over(
+            &self.servers(),
+            self.failover_mode,
+            self.options.min_service_level,
+            |idx| self.reconnect_due(idx),
+        );
+        if should_fail_over {
+            self.fail_over().await;
+            did_something = true;
+        }
+
+        if self.failover_mode != FailoverMode::Cold {
+            for idx in 0..self.servers.len() {
+                if self.servers[idx].session.is_none()
+                    && self.reconnect_due(idx)
+                    && self.connect_server(idx).await.is_ok()
+                {
+                    self.prepare_backup(idx).await;
+                    did_something = true;
+                }
+            }
+        }
+        did_something
+    }
+
+    /// Runs the redundant session until it is cancelled, then disconnects from every server
+    pub async fn run(&mut self, cancellation_token: CancellationToken) {
+        let mut timer = interval(Duration::from_millis(Session::POLL_SLEEP_INTERVAL));
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                did_something = self.poll() => {
+                    if !did_something {
+                        timer.tick().await;
+                    }
+                }
+            }
+        }
+        self.disconnect().await;
+    }
+
+    fn reconnect_due(&self, idx: usize) -> bool {
+        match self.servers[idx].last_connect_attempt {
+            Some(last_connect_attempt) => {
+                last_connect_attempt.elapsed() >= self.options.reconnect_interval
+            }
+            None => true,
+        }
+    }
+
+    /// Switches to the server with the highest service level if it is higher than the active
+    /// server's, or if the active server has been lost. In cold failover the other servers are
+    /// connected to first to find their service levels.
+    async fn fail_over(&mut self) {
+        let active = self
+            .active
+            .filter(|active| self.servers[*active].session.is_some());
+        if self.failover_mode == FailoverMode::Cold {
+            for idx in 0..self.servers.len() {
+                if Some(idx) != active
+                    && self.servers[idx].session.is_none()
+                    && self.reconnect_due(idx)
+                    && self.connect_server(idx).await.is_ok()
+                    && self.servers[idx].service_level() >= self.options.min_service_level
+                {
+                    break;
+                }
+            }
+        }
+
+        if let Some(best) = failover_target(&self.servers()) {
+            info!(
+                "Failing over to redundant server {}",
+                self.servers[best].endpoint.endpoint_url
+            );
+            self.activate(best, self.active).await;
+            let server = self.server_state(best);
+            if let Some(ref mut failover_callback) = self.failover_callback {
+                failover_callback(&server);
+            }
+        }
+        if self.failover_mode == FailoverMode::Cold {
+            self.disconnect_backups(self.active).await;
+        }
+    }
+
+    /// Makes a server active, moving the subscriptions to it from the server that was active
+    async fn activate(&mut self, idx: usize, old_active: Option<usize>) {
+        self.active = Some(idx);
+        for handle in 0..self.subscriptions.len() {
+            if self.subscriptions[handle].is_none() {
+                continue;
+            }
+            let result = match self.servers[idx].subscription(handle) {
+                Some(subscription) => {
+                    self.set_monitoring_mode(idx, subscription, MonitoringMode::Reporting)
+                        .await
+                }
+                None => {
+                    self.create_server_subscription(idx, handle, MonitoringMode::Reporting)
+                        .await
+                }
+            };
+            if let Err(status_code) = result {
+                error!(
+                    "Cannot move subscription {} to redundant server {}, error {}",
+                    handle, self.servers[idx].endpoint.endpoint_url, status_code
+                );
+            }
+        }
+
+        if let Some(old_active) = old_active.filter(|old_active| *old_active != idx) {
+            match self.failover_mode {
+                FailoverMode::Cold => self.disconnect_server(old_active).await,
+                FailoverMode::Warm => {
+                    for handle in 0..self.subscriptions.len() {
+                        self.delete_server_subscription(old_active, handle).await;
+                    }
+                }
+                FailoverMode::Hot => {
+                    for handle in 0..self.subscriptions.len() {
+                        if let Some(subscription) = self.servers[old_active].subscription(handle) {
+                            let _ = self
+                                .set_monitoring_mode(
+                                    old_active,
+                                    subscription,
+                                    MonitoringMode::Sampling,
+                                )
+                                .await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// In hot failover, creates the subscriptions that a connected backup server does not have,
+    /// with monitored items that only sample.
+    async fn prepare_backup(&mut self, idx: usize) {
+        if self.failover_mode != FailoverMode::Hot
+            || self.active == Some(idx)
+            || self.servers[idx].session.is_none()
+        {
+            return;
+        }
+        for handle in 0..self.subscriptions.len() {
+            if self.subscriptions[handle].is_some()
+                && self.servers[idx].subscription(handle).is_none()
+            {
+                if let Err(status_code) = self
+                    .create_server_subscription(idx, handle, MonitoringMode::Sampling)
+                    .await
+                {
+                    error!(
+                        "Cannot create subscription {} on backup server {}, error {}",
+                        handle, self.servers[idx].endpoint.endpoint_url, status_code
+                    );
+                }
+            }
+        }
+    }
+
+    /// Connects to a server, finds its URI and monitors its service level
+    async fn connect_server(&mut self, idx: usize) -> Result<(), StatusCode> {
+        self.servers[idx].last_connect_attempt = Some(Instant::now());
+        let endpoint = self.servers[idx].endpoint.clone();
+        let session = self
+            .client
+            .connect_to_endpoint_unshared(endpoint, self.user_identity_token.clone())
+            .await?;
+
+        let service_level = self.servers[idx].service_level.clone();
+        let server_uri = {
+            let service_level_id = NodeId::from(&VariableId::Server_ServiceLevel);
+            service_level.store(
+                session
+                    .read_value::<u8>(&service_level_id)
+                    .await
+                    .unwrap_or(0),
+                Ordering::Relaxed,
+            );
+            let monitor = {
+                let service_level = service_level.clone();
+                DataChangeCallback::new(move |items| {
+                    for item in items {
+                        if let Some(Variant::Byte(value)) = item.last_value().value {
+                            service_level.store(value, Ordering::Relaxed);
+                        }
+                    }
+                })
+            };
+            let subscription_id = session
+                .create_subscription(
+                    self.options.service_level_interval,
+                    30,
+                    10,
+                    0,
+                    0,
+                    true,
+                    monitor,
+                )
+                .await;
+            if let Ok(subscription_id) = subscription_id {
+                let _ = session
+                    .create_monitored_items(
+                        subscription_id,
+                        TimestampsToReturn::Neither,
+                        &[service_level_id.into()],
+                    )
+                    .await;
+            }
+            session
+                .namespace_table()
+                .server_uri(0)
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let server = &mut self.servers[idx];
+        server.server_uri = server_uri;
+        server.session = Some(session);
+        server.subscriptions.clear();
+        info!(
+            "Connected to redundant server {} with service level {}",
+            server.endpoint.endpoint_url,
+            server.service_level()
+        );
+        Ok(())
+    }
+
+    /// Disconnects from a server, forgetting its subscriptions
+    async fn disconnect_server(&mut self, idx: usize) {
+        let server = &mut self.servers[idx];
+        server.subscriptions.clear();
+        server.service_level.store(0, Ordering::Relaxed);
+        if let Some(session) = server.session.take() {
+            session.disconnect().await;
+        }
+    }
+
+    /// Disconnects from every server except the active one
+    async fn disconnect_backups(&mut self, active: Option<usize>) {
+        for idx in 0..self.servers.len() {
+            if Some(idx) != active {
+                self.disconnect_server(idx).await;
+            }
+        }
+    }
+
+    /// Reads the redundancy support of a server to decide the failover mode, and finds the
+    /// other servers of its set if the session was only given one.
+    async fn read_server_redundancy(&mut self, idx: usize) {
+        let session = match self.servers[idx].session.as_ref() {
+            Some(session) => session,
+            None => return,
+        };
+        let redundancy_support = session
+            .read_value::<RedundancySupport>(&NodeId::from(
+                &VariableId::Server_ServerRedundancy_RedundancySupport,
+            ))
+            .await
+            .unwrap_or(RedundancySupport::None);
+        self.failover_mode = self
+            .options
+            .failover_mode
+            .unwrap_or_else(|| FailoverMode::from(redundancy_support));
+        info!(
+            "Redundant server {} has redundancy support {:?}, failover mode is {:?}",
+            self.servers[idx].endpoint.endpoint_url, redundancy_support, self.failover_mode
+        );
+
+        if self.servers.len() > 1 {
+            return;
+        }
+        let server_uris = session
+            .read_value::<Vec<String>>(&NodeId::from(
+                &VariableId::Server_ServerRedundancy_ServerUriArray,
+            ))
+            .await
+            .unwrap_or_default();
+        if server_uris.is_empty() {
+            return;
+        }
+        let endpoint = self.servers[idx].endpoint.clone();
+        let servers = match session.find_servers(endpoint.endpoint_url.clone()).await {
+            Ok(servers) => servers,
+            Err(status_code) => {
+                error!(
+                    "Cannot find the redundant servers {:?}, error {}",
+                    server_uris, status_code
+                );
+                return;
+            }
+        };
+        for server_uri in server_uris {
+            if self.servers.iter().any(|s| s.server_uri == server_uri) {
+                continue;
+            }
+            let discovery_url = servers
+                .iter()
+                .find(|s| s.application_uri.as_ref() == server_uri)
+                .and_then(|s| s.discovery_urls.as_ref())
+                .and_then(|urls| urls.first());
+            match discovery_url {
+                Some(discovery_url) => {
+                    let mut server = ServerEntry::new(EndpointDescription {
+                        endpoint_url: discovery_url.clone(),
+                        ..endpoint.clone()
+                    });
+                    server.server_uri = server_uri;
+                    self.servers.push(server);
+                }
+                None => warn!(
+                    "Cannot find the endpoint of redundant server {}",
+                    server_uri
+                ),
+            }
+        }
+    }
+
+    /// Creates one of the application's subscriptions on a server
+    async fn create_server_subscription(
+        &mut self,
+        idx: usize,
+        handle: usize,
+        monitoring_mode: MonitoringMode,
+    ) -> Result<(), StatusCode> {
+        let subscription = self.subscriptions[handle]
+            .as_ref()
+            .ok_or(StatusCode::BadSubscriptionIdInvalid)?;
+        let session = self.servers[idx]
+            .session
+            .as_ref()
+            .ok_or(StatusCode::BadNotConnected)?;
+        let items_to_create = subscription
+            .items_to_create
+            .iter()
+            .map(|item| MonitoredItemCreateRequest {
+                monitoring_mode,
+                ..item.clone()
+            })
+            .collect::<Vec<_>>();
+        let server_subscription = {
+            let subscription_id = session
+                .create_subscription_inner(
+                    SubscriptionParameters {
+                        publishing_interval: subscription.publishing_interval,
+                        lifetime_count: subscription.lifetime_count,
+                        max_keep_alive_count: subscription.max_keep_alive_count,
+                        max_notifications_per_publish: subscription.max_notifications_per_publish,
+                        priority: subscription.priority,
+                        publishing_enabled: true,
+                    },
+                    subscription.callback.clone(),
+                )
+                .await?;
+            let results = session
+                .create_monitored_items(subscription_id, TimestampsToReturn::Both, &items_to_create)
+                .await?;
+            ServerSubscription {
+                subscription_id,
+                monitored_item_ids: results
+                    .iter()
+                    .filter(|r| r.status_code.is_good())
+                    .map(|r| r.monitored_item_id)
+                    .collect(),
+            }
+        };
+        let subscriptions = &mut self.servers[idx].subscriptions;
+        if subscriptions.len() <= handle {
+            subscriptions.resize_with(handle + 1, || None);
+        }
+        subscriptions[handle] = Some(server_subscription);
+        Ok(())
+    }
+
+    /// Deletes one of the application's subscriptions from a server
+    async fn delete_server_subscription(&mut self, idx: usize, handle: usize) {
+        let subscription = self.servers[idx]
+            .subscriptions
+            .get_mut(handle)
+            .and_then(|s| s.take());
+        if let (Some(subscription), Some(session)) = (subscription, &self.servers[idx].session) {
+            let _ = session
+                .delete_subscription(subscription.subscription_id)
+                .await;
+        }
+    }
+
+    async fn set_monitoring_mode(
+        &self,
+        idx: usize,
+        subscription: &ServerSubscription,
+        monitoring_mode: MonitoringMode,
+    ) -> Result<(), StatusCode> {
+        let session = self.servers[idx]
+            .session
+            .as_ref()
+            .ok_or(StatusCode::BadNotConnected)?;
+        if subscription.monitored_item_ids.is_empty() {
+            return Ok(());
+        }
+        session
+            .set_monitoring_mode(
+                subscription.subscription_id,
+                monitoring_mode,
+                &subscription.monitored_item_ids,
+            )
+            .await
+            .map(|_| ())
+    }
+}
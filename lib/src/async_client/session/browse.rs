@@ -0,0 +1,391 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Browse helpers for the session which follow continuation points, browse the address space
+//! recursively and crawl it into a tree.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::types::{node_ids::ReferenceTypeId, status_code::StatusCode, *};
+
+use super::{services::ViewService, session::Session};
+
+/// Describes which references a recursive browse follows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecursiveBrowseOptions {
+    /// The direction of references to follow
+    pub browse_direction: BrowseDirection,
+    /// The type of references to follow
+    pub reference_type_id: NodeId,
+    /// Whether subtypes of the reference type are followed too
+    pub include_subtypes: bool,
+    /// A mask of the `NodeClass` values of the nodes to return and follow, 0 for all
+    pub node_class_mask: u32,
+    /// The number of levels of references to follow from the starting node, 0 for no limit
+    pub max_depth: usize,
+}
+
+impl Default for RecursiveBrowseOptions {
+    /// Follows the hierarchical references of every node, however deep
+    fn default() -> Self {
+        RecursiveBrowseOptions {
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            max_depth: 0,
+        }
+    }
+}
+
+impl RecursiveBrowseOptions {
+    fn browse_description(&self, node_id: &NodeId) -> BrowseDescription {
+        BrowseDescription {
+            node_id: node_id.clone(),
+            browse_direction: self.browse_direction,
+            reference_type_id: self.reference_type_id.clone(),
+            include_subtypes: self.include_subtypes,
+            node_class_mask: self.node_class_mask,
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        }
+    }
+}
+
+/// A reference found by a recursive browse
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrowsedReference {
+    /// The node that the reference was browsed from
+    pub source_node_id: NodeId,
+    /// The number of references between the starting node and the target of this reference
+    pub depth: usize,
+    /// The reference
+    pub reference: ReferenceDescription,
+    /// Set on the first reference found to a node on this server, whose references are then
+    /// browsed in turn unless it is at the maximum depth. References found to the node later,
+    /// e.g. because of a cycle, are not followed.
+    pub is_first_visit: bool,
+}
+
+/// A node of the tree built by crawling the address space
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BrowseTreeNode {
+    /// The node
+    pub node_id: ExpandedNodeId,
+    /// The type of the reference from the parent to the node
+    pub reference_type_id: NodeId,
+    /// Whether the reference from the parent to the node is a forward reference
+    pub is_forward: bool,
+    /// The browse name of the node
+    pub browse_name: QualifiedName,
+    /// The display name of the node
+    pub display_name: LocalizedText,
+    /// The class of the node
+    pub node_class: NodeClass,
+    /// The type definition of the node
+    pub type_definition: ExpandedNodeId,
+    /// Set when the node appears elsewhere in the tree, which is where its children are
+    pub is_repeated: bool,
+    /// The nodes referenced by the node
+    pub children: Vec<BrowseTreeNode>,
+}
+
+/// The tree built by crawling the address space from a node. It can be serialized, e.g. to JSON,
+/// to inspect the address space offline.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BrowseTree {
+    /// The node the crawl started from
+    pub node_id: NodeId,
+    /// The nodes referenced by the starting node
+    pub children: Vec<BrowseTreeNode>,
+}
+
+impl BrowseTree {
+    /// Builds the tree from the references of a recursive browse
+    pub(crate) fn new(node_id: &NodeId, references: Vec<BrowsedReference>) -> BrowseTree {
+        let mut references_by_source = HashMap::<NodeId, Vec<BrowsedReference>>::new();
+        references.into_iter().for_each(|r| {
+            references_by_source
+                .entry(r.source_node_id.clone())
+                .or_default()
+                .push(r)
+        });
+        let children = Self::children(node_id, &mut references_by_source);
+        BrowseTree {
+            node_id: node_id.clone(),
+            children,
+        }
+    }
+
+    fn children(
+        node_id: &NodeId,
+        references_by_source: &mut HashMap<NodeId, Vec<BrowsedReference>>,
+    ) -> Vec<BrowseTreeNode> {
+        // Each node is a source once at most, so taking its references ends any cycle
+        let references = references_by_source.remove(node_id).unwrap_or_default();
+        references
+            .into_iter()
+            .map(|r| {
+                let reference = r.reference;
+                let children = if r.is_first_visit {
+                    Self::children(&reference.node_id.node_id, references_by_source)
+                } else {
+                    Vec::new()
+                };
+                BrowseTreeNode {
+                    node_id: reference.node_id,
+                    reference_type_id: reference.reference_type_id,
+                    is_forward: reference.is_forward,
+                    browse_name: reference.browse_name,
+                    display_name: reference.display_name,
+                    node_class: reference.node_class,
+                    type_definition: reference.type_definition,
+                    is_repeated: !r.is_first_visit,
+                    children,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Session {
+    /// Browses nodes and follows continuation points with `browse_next` until all the
+    /// references of every node have been returned. The results hold no continuation points.
+    ///
+    /// A node that cannot be browsed because the server has run out of continuation points is
+    /// browsed again once the other nodes are done. If browsing fails, any outstanding
+    /// continuation points are released.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes_to_browse` - A list of `BrowseDescription` describing nodes to browse.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<BrowseResult>)` - A `BrowseResult` holding all the references for each node
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn browse_all(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+    ) -> Result<Vec<BrowseResult>, StatusCode> {
+        browse_all(self, nodes_to_browse).await
+    }
+
+    /// Browses the address space from a node, level by level, following the references which
+    /// match the options. Each node is browsed once, so cycles in the address space are not
+    /// followed, and references to nodes on other servers are not followed.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The node to start browsing from.
+    /// * `options` - The references and nodes to follow, and how deep.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<BrowsedReference>)` - The references found, in the order they were browsed
+    /// * `Err(StatusCode)` - Request failed, or the starting node could not be browsed
+    ///
+    pub async fn browse_recursive(
+        &self,
+        node_id: &NodeId,
+        options: &RecursiveBrowseOptions,
+    ) -> Result<Vec<BrowsedReference>, StatusCode> {
+        let mut visited = HashSet::new();
+        visited.insert(node_id.clone());
+        let mut level = vec![node_id.clone()];
+        let mut depth = 1;
+        let mut browsed_references = Vec::new();
+        while !level.is_empty() && (options.max_depth == 0 || depth <= options.max_depth) {
+            let descriptions = level
+                .iter()
+                .map(|node_id| options.browse_description(node_id))
+                .collect::<Vec<_>>();
+            let results = self.browse_all(&descriptions).await?;
+            let mut next_level = Vec::new();
+            for (source_node_id, result) in level.into_iter().zip(results) {
+                if result.status_code.is_bad() {
+                    if depth == 1 {
+                        return Err(result.status_code);
+                    }
+                    debug!(
+                        "Cannot browse node {}, status code = {}",
+                        source_node_id, result.status_code
+                    );
+                    continue;
+                }
+                for reference in result.references.unwrap_or_default() {
+                    let target = &reference.node_id;
+                    let is_first_visit = target.server_index == 0
+                        && target.namespace_uri.is_null()
+                        && visited.insert(target.node_id.clone());
+                    if is_first_visit {
+                        next_level.push(target.node_id.clone());
+                    }
+                    browsed_references.push(BrowsedReference {
+                        source_node_id: source_node_id.clone(),
+                        depth,
+                        reference,
+                        is_first_visit,
+                    });
+                }
+            }
+            level = next_level;
+            depth += 1;
+        }
+        Ok(browsed_references)
+    }
+
+    /// Crawls the address space from a node as `browse_recursive` does, and builds a tree of
+    /// the nodes that were found. A node which is referenced more than once appears in the tree
+    /// each time, but only its first appearance holds its children.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The node to start crawling from.
+    /// * `options` - The references and nodes to follow, and how deep.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(BrowseTree)` - The tree of nodes
+    /// * `Err(StatusCode)` - Request failed, or the starting node could not be browsed
+    ///
+    pub async fn crawl(
+        &self,
+        node_id: &NodeId,
+        options: &RecursiveBrowseOptions,
+    ) -> Result<BrowseTree, StatusCode> {
+        let references = self.browse_recursive(node_id, options).await?;
+        Ok(BrowseTree::new(node_id, references))
+    }
+}
+
+/// Browses nodes through the view service and follows their continuation points, as described by
+/// `Session::browse_all`
+pub(crate) async fn browse_all<S>(
+    session: &S,
+    nodes_to_browse: &[BrowseDescription],
+) -> Result<Vec<BrowseResult>, StatusCode>
+where
+    S: ViewService,
+{
+    let mut results = vec![
+        BrowseResult {
+            status_code: StatusCode::BadNoContinuationPoints,
+            continuation_point: ByteString::null(),
+            references: None,
+        };
+        nodes_to_browse.len()
+    ];
+    let mut to_browse = (0..nodes_to_browse.len()).collect::<Vec<_>>();
+    while !to_browse.is_empty() {
+        let descriptions = to_browse
+            .iter()
+            .map(|idx| nodes_to_browse[*idx].clone())
+            .collect::<Vec<_>>();
+        let browse_results = session
+            .browse(&descriptions, None)
+            .await?
+            .unwrap_or_default();
+        if browse_results.len() != to_browse.len() {
+            release_continuation_points(session, &browse_results).await;
+            error!(
+                "Browse of {} nodes returned {} results",
+                to_browse.len(),
+                browse_results.len()
+            );
+            return Err(StatusCode::BadUnexpectedError);
+        }
+        to_browse
+            .iter()
+            .zip(browse_results)
+            .for_each(|(idx, result)| results[*idx] = result);
+        browse_continuation_points(session, &mut results, &to_browse).await?;
+
+        // Try again when other nodes have freed their continuation points
+        let no_continuation_points = to_browse
+            .iter()
+            .copied()
+            .filter(|idx| results[*idx].status_code == StatusCode::BadNoContinuationPoints)
+            .collect::<Vec<_>>();
+        if no_continuation_points.len() == to_browse.len() {
+            break;
+        }
+        to_browse = no_continuation_points;
+    }
+    Ok(results)
+}
+
+/// Follows the continuation points of the results until there are none left
+async fn browse_continuation_points<S>(
+    session: &S,
+    results: &mut [BrowseResult],
+    indices: &[usize],
+) -> Result<(), StatusCode>
+where
+    S: ViewService,
+{
+    let mut pending = indices
+        .iter()
+        .copied()
+        .filter(|idx| !results[*idx].continuation_point.is_null_or_empty())
+        .collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let continuation_points = pending
+            .iter()
+            .map(|idx| results[*idx].continuation_point.clone())
+            .collect::<Vec<_>>();
+        let next_results = match session.browse_next(false, &continuation_points).await {
+            Ok(Some(next_results)) if next_results.len() == pending.len() => next_results,
+            result => {
+                let _ = session.browse_next(true, &continuation_points).await;
+                return Err(match result {
+                    Err(status_code) => status_code,
+                    Ok(_) => {
+                        error!(
+                            "Browse next of {} continuation points returned the wrong results",
+                            continuation_points.len()
+                        );
+                        StatusCode::BadUnexpectedError
+                    }
+                });
+            }
+        };
+        pending
+            .iter()
+            .zip(next_results)
+            .for_each(|(idx, next_result)| {
+                let result = &mut results[*idx];
+                result.status_code = next_result.status_code;
+                result.continuation_point = next_result.continuation_point;
+                if let Some(references) = next_result.references {
+                    result
+                        .references
+                        .get_or_insert_with(Vec::new)
+                        .extend(references);
+                }
+            });
+        pending.retain(|idx| !results[*idx].continuation_point.is_null_or_empty());
+    }
+    Ok(())
+}
+
+/// Releases the continuation points of results which will not be browsed any further
+async fn release_continuation_points<S>(session: &S, results: &[BrowseResult])
+where
+    S: ViewService,
+{
+    let continuation_points = results
+        .iter()
+        .filter(|r| !r.continuation_point.is_null_or_empty())
+        .map(|r| r.continuation_point.clone())
+        .collect::<Vec<_>>();
+    if !continuation_points.is_empty() {
+        if let Err(status_code) = session.browse_next(true, &continuation_points).await {
+            debug!(
+                "Cannot release continuation points, status code = {}",
+                status_code
+            );
+        }
+    }
+}
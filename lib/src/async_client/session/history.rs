@@ -0,0 +1,562 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Helpers for the session which read the history of values and events as decoded `HistoryData`
+//! and `HistoryEvent`, following continuation points until the history is read, and which
+//! insert, replace and delete history.
+
+use crate::types::{
+    service_types::{
+        AggregateConfiguration, DeleteAtTimeDetails, DeleteEventDetails, DeleteRawModifiedDetails,
+        EventFilter, HistoryData, HistoryEvent, MessageInfo, PerformUpdateType, ReadAtTimeDetails,
+        ReadEventDetails, ReadProcessedDetails, ReadRawModifiedDetails, UpdateDataDetails,
+    },
+    status_code::StatusCode,
+    *,
+};
+
+use super::{
+    services::{AttributeService, HistoryReadAction, HistoryUpdateAction},
+    session::Session,
+};
+
+/// The decoded history of a node, which is read a page at a time
+pub(crate) trait HistoryPages: Sized {
+    /// A history with nothing in it
+    fn empty() -> Self;
+
+    /// Decodes a page from the `history_data` of a `HistoryReadResult`. A result without any
+    /// history data is an empty page.
+    fn decode_page(history_data: &ExtensionObject) -> Result<Self, StatusCode>;
+
+    /// The number of values or events in the history
+    fn len(&self) -> usize;
+
+    /// Appends the next page to the history
+    fn append(&mut self, page: Self);
+
+    /// Discards values or events past the length
+    fn truncate(&mut self, len: usize);
+}
+
+/// Decodes a page of history, checking that it is of the expected type
+fn decode_history_data<T>(history_data: &ExtensionObject) -> Result<Option<T>, StatusCode>
+where
+    T: MessageInfo + BinaryEncoder<T>,
+{
+    if history_data.is_empty() {
+        Ok(None)
+    } else {
+        match history_data.decode_inner::<T>(&DecodingOptions::default()) {
+            Ok(page) if history_data.node_id == page.object_id().into() => Ok(Some(page)),
+            _ => {
+                error!(
+                    "History data {:?} cannot be decoded as the expected type",
+                    history_data.node_id
+                );
+                Err(StatusCode::BadDecodingError)
+            }
+        }
+    }
+}
+
+fn append_page<T>(history: &mut Option<Vec<T>>, page: Option<Vec<T>>) {
+    if let Some(page) = page {
+        history.get_or_insert_with(Vec::new).extend(page);
+    }
+}
+
+impl HistoryPages for HistoryData {
+    fn empty() -> Self {
+        HistoryData { data_values: None }
+    }
+
+    fn decode_page(history_data: &ExtensionObject) -> Result<Self, StatusCode> {
+        decode_history_data(history_data).map(|page| page.unwrap_or_else(Self::empty))
+    }
+
+    fn len(&self) -> usize {
+        self.data_values.as_ref().map_or(0, |v| v.len())
+    }
+
+    fn append(&mut self, page: Self) {
+        append_page(&mut self.data_values, page.data_values);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if let Some(ref mut data_values) = self.data_values {
+            data_values.truncate(len);
+        }
+    }
+}
+
+impl HistoryPages for HistoryEvent {
+    fn empty() -> Self {
+        HistoryEvent { events: None }
+    }
+
+    fn decode_page(history_data: &ExtensionObject) -> Result<Self, StatusCode> {
+        decode_history_data(history_data).map(|page| page.unwrap_or_else(Self::empty))
+    }
+
+    fn len(&self) -> usize {
+        self.events.as_ref().map_or(0, |v| v.len())
+    }
+
+    fn append(&mut self, page: Self) {
+        append_page(&mut self.events, page.events);
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if let Some(ref mut events) = self.events {
+            events.truncate(len);
+        }
+    }
+}
+
+impl Session {
+    /// Reads the raw history of a variable between two times, following continuation points
+    /// until all of it, or `max_values` of it, has been read.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable to read the history of.
+    /// * `start_time` - The start of the time range. The history is returned in reverse order if
+    ///   it is after `end_time`.
+    /// * `end_time` - The end of the time range.
+    /// * `max_values` - The maximum number of values to return, or 0 for all of them.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HistoryData)` - The values
+    /// * `Err(StatusCode)` - Request failed, or the status of the history read if it is bad
+    ///
+    pub async fn read_raw(
+        &self,
+        node_id: &NodeId,
+        start_time: DateTime,
+        end_time: DateTime,
+        max_values: usize,
+    ) -> Result<HistoryData, StatusCode> {
+        let details = HistoryReadAction::ReadRawModifiedDetails(ReadRawModifiedDetails {
+            is_read_modified: false,
+            start_time,
+            end_time,
+            num_values_per_node: max_values as u32,
+            return_bounds: false,
+        });
+        self.history_read_one(details, node_id, max_values).await
+    }
+
+    /// Reads aggregates calculated by the server from the history of variables, e.g. the average
+    /// of each interval, following continuation points until all of them have been read. The
+    /// server's default aggregate configuration is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_ids` - The variables to read the history of.
+    /// * `aggregate_type` - The aggregate function, e.g. `ObjectId::AggregateFunction_Average`.
+    /// * `start_time` - The start of the time range.
+    /// * `end_time` - The end of the time range.
+    /// * `processing_interval` - The interval in milliseconds that each aggregate is
+    ///   calculated for, or 0 for one aggregate for the whole range.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Result<HistoryData, StatusCode>>)` - The aggregates of each variable, or the
+    ///   status of its history read if it is bad
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn read_processed(
+        &self,
+        node_ids: &[NodeId],
+        aggregate_type: &NodeId,
+        start_time: DateTime,
+        end_time: DateTime,
+        processing_interval: f64,
+    ) -> Result<Vec<Result<HistoryData, StatusCode>>, StatusCode> {
+        if node_ids.is_empty() {
+            return Err(StatusCode::BadNothingToDo);
+        }
+        let details = HistoryReadAction::ReadProcessedDetails(ReadProcessedDetails {
+            start_time,
+            end_time,
+            processing_interval,
+            aggregate_type: Some(vec![aggregate_type.clone(); node_ids.len()]),
+            aggregate_configuration: AggregateConfiguration {
+                use_server_capabilities_defaults: true,
+                treat_uncertain_as_bad: false,
+                percent_data_bad: 0,
+                percent_data_good: 0,
+                use_sloped_extrapolation: false,
+            },
+        });
+        self.history_read_pages(details, node_ids, 0).await
+    }
+
+    /// Reads the values of a variable at particular times from its history. The server
+    /// interpolates a value for a time that has no raw value.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable to read the history of.
+    /// * `req_times` - The times to read values at.
+    /// * `use_simple_bounds` - Whether values are interpolated from the nearest raw values
+    ///   whatever their status (true), or from the nearest good values (false).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HistoryData)` - A value for each time
+    /// * `Err(StatusCode)` - Request failed, or the status of the history read if it is bad
+    ///
+    pub async fn read_at_time(
+        &self,
+        node_id: &NodeId,
+        req_times: &[DateTime],
+        use_simple_bounds: bool,
+    ) -> Result<HistoryData, StatusCode> {
+        if req_times.is_empty() {
+            return Err(StatusCode::BadNothingToDo);
+        }
+        let details = HistoryReadAction::ReadAtTimeDetails(ReadAtTimeDetails {
+            req_times: Some(req_times.to_vec()),
+            use_simple_bounds,
+        });
+        self.history_read_one(details, node_id, 0).await
+    }
+
+    /// Reads the event history of a notifier between two times, following continuation points
+    /// until all of it, or `max_events` of it, has been read.
+    ///
+    /// # Arguments
+    ///
+    /// * `notifier_id` - The object whose events are read, e.g. the server.
+    /// * `filter` - Selects the fields of each event, and optionally which events are returned.
+    /// * `start_time` - The start of the time range.
+    /// * `end_time` - The end of the time range.
+    /// * `max_events` - The maximum number of events to return, or 0 for all of them.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(HistoryEvent)` - The fields of each event in the order of the filter's select
+    ///   clauses
+    /// * `Err(StatusCode)` - Request failed, or the status of the history read if it is bad
+    ///
+    pub async fn read_events(
+        &self,
+        notifier_id: &NodeId,
+        filter: EventFilter,
+        start_time: DateTime,
+        end_time: DateTime,
+        max_events: usize,
+    ) -> Result<HistoryEvent, StatusCode> {
+        let details = HistoryReadAction::ReadEventDetails(ReadEventDetails {
+            num_values_per_node: max_events as u32,
+            start_time,
+            end_time,
+            filter,
+        });
+        self.history_read_one(details, notifier_id, max_events)
+            .await
+    }
+
+    /// Inserts values into the history of a variable. A value at a time which already has one
+    /// is not inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable whose history is updated.
+    /// * `values` - The values, each with a source timestamp.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<StatusCode>)` - The status of inserting each value, e.g. `BadEntryExists`
+    /// * `Err(StatusCode)` - Request failed, or the status of the history update if it is bad
+    ///
+    pub async fn insert_history(
+        &self,
+        node_id: &NodeId,
+        values: &[DataValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        self.update_history_data(node_id, PerformUpdateType::Insert, values)
+            .await
+    }
+
+    /// Replaces values in the history of a variable. A value at a time which does not have one
+    /// is not inserted.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable whose history is updated.
+    /// * `values` - The values, each with a source timestamp.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<StatusCode>)` - The status of replacing each value, e.g. `BadNoEntryExists`
+    /// * `Err(StatusCode)` - Request failed, or the status of the history update if it is bad
+    ///
+    pub async fn replace_history(
+        &self,
+        node_id: &NodeId,
+        values: &[DataValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        self.update_history_data(node_id, PerformUpdateType::Replace, values)
+            .await
+    }
+
+    /// Deletes the raw values in the history of a variable between two times.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable whose history is deleted.
+    /// * `start_time` - The start of the time range.
+    /// * `end_time` - The end of the time range.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The values were deleted
+    /// * `Err(StatusCode)` - Request failed, or the status of the history update if it is bad
+    ///
+    pub async fn delete_history(
+        &self,
+        node_id: &NodeId,
+        start_time: DateTime,
+        end_time: DateTime,
+    ) -> Result<(), StatusCode> {
+        let details = HistoryUpdateAction::DeleteRawModifiedDetails(DeleteRawModifiedDetails {
+            node_id: node_id.clone(),
+            is_delete_modified: false,
+            start_time,
+            end_time,
+        });
+        self.history_update_one(details).await.map(|_| ())
+    }
+
+    /// Deletes the values at particular times from the history of a variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable whose history is deleted.
+    /// * `req_times` - The times of the values to delete.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<StatusCode>)` - The status of deleting each value, e.g. `BadNoEntryExists`
+    /// * `Err(StatusCode)` - Request failed, or the status of the history update if it is bad
+    ///
+    pub async fn delete_history_at_time(
+        &self,
+        node_id: &NodeId,
+        req_times: &[DateTime],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        if req_times.is_empty() {
+            return Err(StatusCode::BadNothingToDo);
+        }
+        let details = HistoryUpdateAction::DeleteAtTimeDetails(DeleteAtTimeDetails {
+            node_id: node_id.clone(),
+            req_times: Some(req_times.to_vec()),
+        });
+        self.history_update_one(details).await
+    }
+
+    /// Deletes events from the event history of a notifier.
+    ///
+    /// # Arguments
+    ///
+    /// * `notifier_id` - The object whose event history is deleted.
+    /// * `event_ids` - The `EventId` of each event to delete.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<StatusCode>)` - The status of deleting each event
+    /// * `Err(StatusCode)` - Request failed, or the status of the history update if it is bad
+    ///
+    pub async fn delete_event_history(
+        &self,
+        notifier_id: &NodeId,
+        event_ids: &[ByteString],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        if event_ids.is_empty() {
+            return Err(StatusCode::BadNothingToDo);
+        }
+        let details = HistoryUpdateAction::DeleteEventDetails(DeleteEventDetails {
+            node_id: notifier_id.clone(),
+            event_ids: Some(event_ids.to_vec()),
+        });
+        self.history_update_one(details).await
+    }
+
+    async fn update_history_data(
+        &self,
+        node_id: &NodeId,
+        perform_insert_replace: PerformUpdateType,
+        values: &[DataValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        if values.is_empty() {
+            return Err(StatusCode::BadNothingToDo);
+        }
+        let details = HistoryUpdateAction::UpdateDataDetails(UpdateDataDetails {
+            node_id: node_id.clone(),
+            perform_insert_replace,
+            update_values: Some(values.to_vec()),
+        });
+        self.history_update_one(details).await
+    }
+
+    /// Sends a single history update and returns the status of each of its operations
+    async fn history_update_one(
+        &self,
+        details: HistoryUpdateAction,
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let results = self.history_update(&[details]).await?;
+        match results.into_iter().next() {
+            Some(result) if result.status_code.is_bad() => Err(result.status_code),
+            Some(result) => Ok(result.operation_results.unwrap_or_default()),
+            None => Err(StatusCode::BadUnexpectedError),
+        }
+    }
+
+    /// Reads the history of a single node
+    async fn history_read_one<T>(
+        &self,
+        details: HistoryReadAction,
+        node_id: &NodeId,
+        max_values: usize,
+    ) -> Result<T, StatusCode>
+    where
+        T: HistoryPages,
+    {
+        self.history_read_pages(details, std::slice::from_ref(node_id), max_values)
+            .await?
+            .pop()
+            .ok_or(StatusCode::BadUnexpectedError)?
+    }
+
+    /// Reads the history of nodes, reading the next page of every node that returned a
+    /// continuation point until none of them have more to read. A node whose history reaches
+    /// `max_values` has its continuation point released rather than followed, as do all nodes
+    /// if a read fails.
+    async fn history_read_pages<T>(
+        &self,
+        details: HistoryReadAction,
+        node_ids: &[NodeId],
+        max_values: usize,
+    ) -> Result<Vec<Result<T, StatusCode>>, StatusCode>
+    where
+        T: HistoryPages,
+    {
+        let history_read_value_id =
+            |node_id: &NodeId, continuation_point: ByteString| HistoryReadValueId {
+                node_id: node_id.clone(),
+                index_range: UAString::null(),
+                data_encoding: QualifiedName::null(),
+                continuation_point,
+            };
+
+        let mut histories = node_ids
+            .iter()
+            .map(|_| Ok(T::empty()))
+            .collect::<Vec<Result<T, StatusCode>>>();
+        // The index of each node that has more to read, and the continuation point to read it
+        let mut pending = node_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, _)| (idx, ByteString::null()))
+            .collect::<Vec<_>>();
+
+        while !pending.is_empty() {
+            let nodes_to_read = pending
+                .iter()
+                .map(|(idx, continuation_point)| {
+                    history_read_value_id(&node_ids[*idx], continuation_point.clone())
+                })
+                .collect::<Vec<_>>();
+            let results = match self
+                .history_read(
+                    details.clone(),
+                    TimestampsToReturn::Both,
+                    false,
+                    &nodes_to_read,
+                )
+                .await
+            {
+                Ok(results) if results.len() == nodes_to_read.len() => results,
+                result => {
+                    self.release_history_continuation_points(&details, nodes_to_read)
+                        .await;
+                    return Err(result.err().unwrap_or(StatusCode::BadUnexpectedError));
+                }
+            };
+
+            let mut next_pending = Vec::new();
+            let mut to_release = Vec::new();
+            for ((idx, _), result) in pending.into_iter().zip(results) {
+                let has_more = !result.continuation_point.is_null_or_empty();
+                let page = if result.status_code.is_bad() {
+                    Err(result.status_code)
+                } else {
+                    T::decode_page(&result.history_data)
+                };
+                match (&mut histories[idx], page) {
+                    (Ok(history), Ok(page)) => {
+                        history.append(page);
+                        if max_values > 0 && history.len() >= max_values {
+                            history.truncate(max_values);
+                            if has_more {
+                                to_release.push(history_read_value_id(
+                                    &node_ids[idx],
+                                    result.continuation_point,
+                                ));
+                            }
+                        } else if has_more {
+                            next_pending.push((idx, result.continuation_point));
+                        }
+                    }
+                    (history, Err(status_code)) => {
+                        *history = Err(status_code);
+                        if has_more {
+                            to_release.push(history_read_value_id(
+                                &node_ids[idx],
+                                result.continuation_point,
+                            ));
+                        }
+                    }
+                    (Err(_), Ok(_)) => {}
+                }
+            }
+            self.release_history_continuation_points(&details, to_release)
+                .await;
+            pending = next_pending;
+        }
+        Ok(histories)
+    }
+
+    /// Releases the continuation points of history reads which are not going to be followed
+    async fn release_history_continuation_points(
+        &self,
+        details: &HistoryReadAction,
+        nodes_to_read: Vec<HistoryReadValueId>,
+    ) {
+        let nodes_to_release = nodes_to_read
+            .into_iter()
+            .filter(|n| !n.continuation_point.is_null_or_empty())
+            .collect::<Vec<_>>();
+        if !nodes_to_release.is_empty() {
+            if let Err(status_code) = self
+                .history_read(
+                    details.clone(),
+                    TimestampsToReturn::Both,
+                    true,
+                    &nodes_to_release,
+                )
+                .await
+            {
+                error!(
+                    "Cannot release history continuation points, error {}",
+                    status_code
+                );
+            }
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod browse;
+pub mod history;
+pub mod namespaces;
+pub mod operation_limits;
 pub mod services;
 pub mod session;
 pub mod session_state;
+pub mod values;
@@ -0,0 +1,315 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides the namespace table of the server, which maps namespace URIs to the indexes used in
+//! node ids, so client code does not have to hard code namespace indexes that differ between
+//! server instances.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::types::{
+    node_ids::VariableId, service_types::EventNotificationList, status_code::StatusCode, *,
+};
+
+use super::{
+    super::{callbacks::OnSubscriptionNotification, subscription::MonitoredItem},
+    services::{AttributeService, ViewService},
+    session::Session,
+};
+
+/// The namespaces and servers of the server, read from `Server/NamespaceArray` and
+/// `Server/ServerArray` once the session has been activated. The position of a URI in each array
+/// is its index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NamespaceTable {
+    /// The namespace URIs
+    namespaces: Vec<String>,
+    /// The server URIs
+    servers: Vec<String>,
+}
+
+impl NamespaceTable {
+    /// Creates a table from the namespace and server URIs
+    pub fn new(namespaces: Vec<String>, servers: Vec<String>) -> NamespaceTable {
+        NamespaceTable {
+            namespaces,
+            servers,
+        }
+    }
+
+    /// The variables holding the arrays, in the order that `from_values` expects their values
+    pub(crate) fn variable_ids() -> [VariableId; 2] {
+        [
+            VariableId::Server_NamespaceArray,
+            VariableId::Server_ServerArray,
+        ]
+    }
+
+    /// Creates the table from the values read from the variables
+    pub(crate) fn from_values(values: &[DataValue]) -> NamespaceTable {
+        let uris = |idx: usize| match values.get(idx).and_then(|v| v.value.as_ref()) {
+            Some(Variant::Array(array)) => array
+                .values
+                .iter()
+                .map(|v| match v {
+                    Variant::String(uri) => uri.as_ref().to_string(),
+                    _ => String::new(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        NamespaceTable {
+            namespaces: uris(0),
+            servers: uris(1),
+        }
+    }
+
+    /// Returns the namespace URIs. The position of each namespace is its index.
+    pub fn namespaces(&self) -> &[String] {
+        &self.namespaces
+    }
+
+    /// Returns the server URIs. The position of each server is its index.
+    pub fn servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    /// Finds the index of a namespace
+    pub fn namespace_index(&self, namespace_uri: &str) -> Option<u16> {
+        self.namespaces
+            .iter()
+            .position(|ns| ns == namespace_uri)
+            .map(|i| i as u16)
+    }
+
+    /// Finds the URI of a namespace index
+    pub fn namespace_uri(&self, namespace: u16) -> Option<&str> {
+        self.namespaces
+            .get(namespace as usize)
+            .map(|ns| ns.as_str())
+    }
+
+    /// Finds the index of a server
+    pub fn server_index(&self, server_uri: &str) -> Option<u32> {
+        self.servers
+            .iter()
+            .position(|s| s == server_uri)
+            .map(|i| i as u32)
+    }
+
+    /// Finds the URI of a server index
+    pub fn server_uri(&self, server_index: u32) -> Option<&str> {
+        self.servers.get(server_index as usize).map(|s| s.as_str())
+    }
+
+    /// Creates a node id in the namespace with the URI, or `BadNodeIdUnknown` if the server has
+    /// no such namespace.
+    pub fn node_id<T>(&self, namespace_uri: &str, value: T) -> Result<NodeId, StatusCode>
+    where
+        T: 'static + Into<Identifier>,
+    {
+        self.namespace_index(namespace_uri)
+            .map(|namespace| NodeId::new(namespace, value))
+            .ok_or(StatusCode::BadNodeIdUnknown)
+    }
+
+    /// Resolves an expanded node id into a node id on this server, looking up the index of its
+    /// namespace URI if it has one. An expanded node id of another server is `BadNodeIdUnknown`,
+    /// as is a namespace URI that the server does not have.
+    pub fn resolve(&self, node_id: &ExpandedNodeId) -> Result<NodeId, StatusCode> {
+        if node_id.server_index != 0 {
+            Err(StatusCode::BadNodeIdUnknown)
+        } else if node_id.namespace_uri.is_empty() {
+            Ok(node_id.node_id.clone())
+        } else {
+            self.namespace_index(node_id.namespace_uri.as_ref())
+                .map(|namespace| NodeId {
+                    namespace,
+                    identifier: node_id.node_id.identifier.clone(),
+                })
+                .ok_or(StatusCode::BadNodeIdUnknown)
+        }
+    }
+
+    /// Expands a node id on this server into an expanded node id holding the URI of its
+    /// namespace rather than the index. A namespace the table does not know keeps its index.
+    pub fn expand(&self, node_id: &NodeId) -> ExpandedNodeId {
+        match self.namespace_uri(node_id.namespace) {
+            Some(namespace_uri) => ExpandedNodeId {
+                node_id: NodeId {
+                    namespace: 0,
+                    identifier: node_id.identifier.clone(),
+                },
+                namespace_uri: namespace_uri.into(),
+                server_index: 0,
+            },
+            None => node_id.into(),
+        }
+    }
+
+    /// Replaces the namespace index of an expanded node id on this server with its URI
+    fn expand_expanded(&self, node_id: &mut ExpandedNodeId) {
+        if node_id.server_index == 0 && node_id.namespace_uri.is_empty() {
+            *node_id = self.expand(&node_id.node_id);
+        }
+    }
+
+    /// Replaces the namespace indexes in the references of a browse result with URIs
+    pub fn expand_browse_result(&self, result: &mut BrowseResult) {
+        if let Some(ref mut references) = result.references {
+            references.iter_mut().for_each(|r| {
+                self.expand_expanded(&mut r.node_id);
+                self.expand_expanded(&mut r.type_definition);
+            });
+        }
+    }
+
+    /// Replaces the node ids in a value, including the elements of an array, with expanded node
+    /// ids holding namespace URIs. Note that this changes the type of such a value from
+    /// `NodeId` to `ExpandedNodeId`.
+    pub fn expand_variant(&self, value: &mut Variant) {
+        match value {
+            Variant::NodeId(node_id) => {
+                *value = Variant::from(self.expand(node_id));
+            }
+            Variant::ExpandedNodeId(node_id) => self.expand_expanded(node_id),
+            Variant::Array(array) => {
+                if array.value_type == VariantTypeId::NodeId {
+                    array.value_type = VariantTypeId::ExpandedNodeId;
+                }
+                array
+                    .values
+                    .iter_mut()
+                    .for_each(|value| self.expand_variant(value));
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces the namespace URIs of the expanded node ids in a value, including the elements
+    /// of an array, with namespace indexes. It is the reverse of `expand_variant`, except that
+    /// the value is still an `ExpandedNodeId`.
+    pub fn resolve_variant(&self, value: &mut Variant) -> Result<(), StatusCode> {
+        match value {
+            Variant::ExpandedNodeId(node_id) if node_id.server_index == 0 => {
+                **node_id = self.resolve(node_id)?.into();
+                Ok(())
+            }
+            Variant::Array(array) => array
+                .values
+                .iter_mut()
+                .try_for_each(|value| self.resolve_variant(value)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The notification callback of the subscription created by `Session::monitor_namespace_table`,
+/// which marks the namespace table as stale so the session reads it again.
+pub(crate) struct NamespaceTableMonitor {
+    pub(crate) stale: Arc<AtomicBool>,
+}
+
+impl OnSubscriptionNotification for NamespaceTableMonitor {
+    fn on_data_change(&mut self, _data_change_items: &[&MonitoredItem]) {
+        self.stale.store(true, Ordering::Relaxed);
+    }
+
+    fn on_event(&mut self, _events: &EventNotificationList) {
+        self.stale.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Session {
+    /// Reads nodes as `read` does, but any node id in the values is returned as an expanded node
+    /// id holding the URI of its namespace, using the session's namespace table.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes_to_read` - A list of `ReadValueId` to be read by the server.
+    /// * `timestamps_to_return` - The `TimestampsToReturn` for each node, Both, Server, Source or None
+    /// * `max_age` - The maximum age of value to read in milliseconds.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<DataValue>)` - A list of `DataValue` corresponding to each read operation.
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure.
+    ///
+    pub async fn read_with_namespace_uris(
+        &self,
+        nodes_to_read: &[ReadValueId],
+        timestamps_to_return: TimestampsToReturn,
+        max_age: f64,
+    ) -> Result<Vec<DataValue>, StatusCode> {
+        let mut values = self
+            .read(nodes_to_read, timestamps_to_return, max_age)
+            .await?;
+        let namespace_table = self.namespace_table();
+        values.iter_mut().for_each(|v| {
+            if let Some(ref mut value) = v.value {
+                namespace_table.expand_variant(value);
+            }
+        });
+        Ok(values)
+    }
+
+    /// Writes values as `write` does, but expanded node ids in the values may hold namespace
+    /// URIs, which are replaced with indexes using the session's namespace table. A URI that the
+    /// server does not have is `BadNodeIdUnknown`.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes_to_write` - A list of `WriteValue` to be sent to the server.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<StatusCode>)` - A list of `StatusCode` results corresponding to each write operation.
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure.
+    ///
+    pub async fn write_with_namespace_uris(
+        &self,
+        nodes_to_write: &[WriteValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let namespace_table = self.namespace_table();
+        let mut nodes_to_write = nodes_to_write.to_vec();
+        for node_to_write in nodes_to_write.iter_mut() {
+            if let Some(ref mut value) = node_to_write.value.value {
+                namespace_table.resolve_variant(value)?;
+            }
+        }
+        self.write(&nodes_to_write).await
+    }
+
+    /// Browses nodes as `browse` does, but the node ids and type definitions of the references
+    /// hold the URIs of their namespaces, using the session's namespace table.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes_to_browse` - A list of `BrowseDescription` describing nodes to browse.
+    /// * `maximum_nodes_to_browse` - The maximum number of references to return for each node.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<BrowseResult>)` - A `BrowseResult` for each node
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn browse_with_namespace_uris(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+        maximum_nodes_to_browse: Option<u32>,
+    ) -> Result<Vec<BrowseResult>, StatusCode> {
+        let mut results = self
+            .browse(nodes_to_browse, maximum_nodes_to_browse)
+            .await?
+            .unwrap_or_default();
+        let namespace_table = self.namespace_table();
+        results
+            .iter_mut()
+            .for_each(|r| namespace_table.expand_browse_result(r));
+        Ok(results)
+    }
+}
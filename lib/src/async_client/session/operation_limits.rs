@@ -0,0 +1,152 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides the server's operation limits and splits the items of a service call into batches
+//! which respect those limits and the size of a request.
+
+use std::future::Future;
+
+use futures::{stream::FuturesOrdered, StreamExt};
+
+use crate::types::{node_ids::VariableId, *};
+
+/// Allowance for the message header, request header and security of a request when working out
+/// how many items fit into it.
+const REQUEST_OVERHEAD: usize = 4096;
+
+/// The operation limits of the server, read from `Server/ServerCapabilities/OperationLimits`
+/// once the session has been activated. A limit of 0 means there is no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OperationLimits {
+    /// Maximum nodes in a read
+    pub max_nodes_per_read: u32,
+    /// Maximum nodes in a write
+    pub max_nodes_per_write: u32,
+    /// Maximum nodes in a browse
+    pub max_nodes_per_browse: u32,
+    /// Maximum nodes in a register nodes
+    pub max_nodes_per_register_nodes: u32,
+    /// Maximum monitored items in a create monitored items
+    pub max_monitored_items_per_call: u32,
+}
+
+impl OperationLimits {
+    /// The variables holding the limits, in the order that `from_values` expects their values
+    pub(crate) fn variable_ids() -> [VariableId; 5] {
+        [
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerRead,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerWrite,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerBrowse,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerRegisterNodes,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxMonitoredItemsPerCall,
+        ]
+    }
+
+    /// Creates the limits from the values read from the variables. A limit which the server
+    /// does not supply is treated as no limit.
+    pub(crate) fn from_values(values: &[DataValue]) -> OperationLimits {
+        let limit = |idx: usize| match values.get(idx).and_then(|v| v.value.as_ref()) {
+            Some(Variant::UInt32(limit)) => *limit,
+            _ => 0,
+        };
+        OperationLimits {
+            max_nodes_per_read: limit(0),
+            max_nodes_per_write: limit(1),
+            max_nodes_per_browse: limit(2),
+            max_nodes_per_register_nodes: limit(3),
+            max_monitored_items_per_call: limit(4),
+        }
+    }
+}
+
+/// Splits items into batches of no more than `max_items` items whose encoded size fits into a
+/// request of `max_message_size` bytes. A limit of 0 means there is no limit. Every batch holds
+/// at least one item, even if that item alone is too large.
+pub(crate) fn batches<T>(items: &[T], max_items: usize, max_message_size: usize) -> Vec<&[T]>
+where
+    T: BinaryEncoder<T>,
+{
+    let max_bytes = if max_message_size == 0 {
+        usize::MAX
+    } else {
+        max_message_size.saturating_sub(REQUEST_OVERHEAD)
+    };
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut batch_bytes = 0usize;
+    for (idx, item) in items.iter().enumerate() {
+        let item_bytes = item.byte_len();
+        let batch_len = idx - start;
+        if batch_len > 0
+            && ((max_items > 0 && batch_len == max_items)
+                || batch_bytes.saturating_add(item_bytes) > max_bytes)
+        {
+            batches.push(&items[start..idx]);
+            start = idx;
+            batch_bytes = 0;
+        }
+        batch_bytes += item_bytes;
+    }
+    if start < items.len() {
+        batches.push(&items[start..]);
+    }
+    batches
+}
+
+/// Sends batches with up to `max_concurrent_batches` of them in flight at the same time, and
+/// merges their results in order. After a batch fails no more batches are sent, but those
+/// already in flight are waited for so the caller knows what took effect before the first
+/// error is returned.
+pub(crate) async fn send_batches<'a, T, R, F, Fut>(
+    batches: Vec<&'a [T]>,
+    max_concurrent_batches: usize,
+    send_batch: F,
+) -> Result<Vec<R>, StatusCode>
+where
+    F: Fn(&'a [T]) -> Fut,
+    Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+{
+    let mut batches = batches.into_iter();
+    let mut in_flight = FuturesOrdered::new();
+    let mut results = Vec::new();
+    let mut first_error = None;
+    loop {
+        while first_error.is_none() && in_flight.len() < max_concurrent_batches.max(1) {
+            if let Some(batch) = batches.next() {
+                in_flight.push_back(batch_results(batch.len(), send_batch(batch)));
+            } else {
+                break;
+            }
+        }
+        match in_flight.next().await {
+            Some(Ok(batch_results)) => results.extend(batch_results),
+            Some(Err(err)) => {
+                first_error.get_or_insert(err);
+            }
+            None => break,
+        }
+    }
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(results),
+    }
+}
+
+/// Waits for the results of a batch, which must hold one result for each item
+async fn batch_results<R, Fut>(batch_len: usize, results: Fut) -> Result<Vec<R>, StatusCode>
+where
+    Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+{
+    let results = results.await?;
+    if results.len() == batch_len {
+        Ok(results)
+    } else {
+        error!(
+            "Batch of {} items returned {} results",
+            batch_len,
+            results.len()
+        );
+        Err(StatusCode::BadUnexpectedError)
+    }
+}
@@ -8,7 +8,9 @@ use async_trait::async_trait;
 use tokio::sync::mpsc::Sender;
 
 use crate::{
-    async_client::callbacks::OnSubscriptionNotification,
+    async_client::{
+        callbacks::OnSubscriptionNotification, subscription_stream::SubscriptionStream,
+    },
     core::supported_message::SupportedMessage,
     types::{
         node_ids::{MethodId, ObjectId},
@@ -18,6 +20,7 @@ use crate::{
 };
 
 /// Enumeration used with Session::history_read()
+#[derive(Debug, Clone, PartialEq)]
 pub enum HistoryReadAction {
     ReadEventDetails(ReadEventDetails),
     ReadRawModifiedDetails(ReadRawModifiedDetails),
@@ -26,6 +29,7 @@ pub enum HistoryReadAction {
 }
 
 /// Enumeration used with Session::history_update()
+#[derive(Debug, Clone, PartialEq)]
 pub enum HistoryUpdateAction {
     UpdateDataDetails(UpdateDataDetails),
     UpdateStructureDataDetails(UpdateStructureDataDetails),
@@ -776,6 +780,35 @@ pub trait SubscriptionService: Service {
     where
         CB: OnSubscriptionNotification + Send + Sync + 'static;
 
+    /// Create a subscription by sending a [`CreateSubscriptionRequest`] to the server and return
+    /// a stream of its notifications instead of calling a notification callback.
+    ///
+    /// The arguments are the same as for `create_subscription` except for:
+    ///
+    /// * `buffer_size` - The number of notifications held until the stream takes them. When the
+    ///   buffer is full the oldest notification is discarded and the stream reports how many
+    ///   were discarded with a `SubscriptionNotification::Lagged`. Must be greater than zero.
+    ///
+    /// Dropping the stream deletes the subscription the next time the session is polled.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(SubscriptionStream)` - stream of notifications which holds the new subscription's id
+    /// * `Err(StatusCode)` - Status code reason for failure
+    ///
+    /// [`CreateSubscriptionRequest`]: ./struct.CreateSubscriptionRequest.html
+    ///
+    async fn create_subscription_stream(
+        &self,
+        publishing_interval: f64,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        publishing_enabled: bool,
+        buffer_size: usize,
+    ) -> Result<SubscriptionStream, StatusCode>;
+
     /// Modifies a subscription by sending a [`ModifySubscriptionRequest`] to the server.
     ///
     /// See OPC UA Part 4 - Services 5.13.3 for complete description of the service and error responses.
@@ -7,13 +7,24 @@
 //!
 //! The session also has async functionality but that is reserved for publish requests on subscriptions
 //! and events.
-use std::{collections::HashSet, result::Result, str::FromStr, sync::Arc, thread};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    result::Result,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
 
 use async_trait::async_trait;
 use tokio::{
     sync::{mpsc::Sender, oneshot},
     time::{interval, sleep, Duration, Instant},
 };
+use tokio_util::sync::CancellationToken;
 
 use crate::core::{
     comms::{
@@ -24,24 +35,35 @@ use crate::core::{
     RUNTIME,
 };
 use crate::crypto::{
-    self as crypto, user_identity::make_user_name_identity_token, CertificateStore, SecurityPolicy,
-    X509,
+    self as crypto,
+    user_identity::{make_issued_identity_token, make_user_name_identity_token},
+    CertificateStore, SecurityPolicy, X509,
 };
 use crate::sync::*;
-use crate::types::{node_ids::ObjectId, status_code::StatusCode, *};
+use crate::types::{
+    node_ids::{ObjectId, ObjectTypeId, VariableId},
+    status_code::StatusCode,
+    *,
+};
 use crate::{deregister_runtime_component, register_runtime_component};
 
 use crate::async_client::{
-    callbacks::{OnConnectionStatusChange, OnSessionClosed, OnSubscriptionNotification},
+    callbacks::{
+        OnConnectionStatusChange, OnIssuedTokenRefresh, OnSessionClosed, OnSubscriptionNotification,
+    },
     client::IdentityToken,
     comms::tcp_transport::TcpTransport,
     message_queue::MessageQueue,
     process_service_result, process_unexpected_response,
+    session::namespaces::{NamespaceTable, NamespaceTableMonitor},
+    session::values::MethodArguments,
+    session::operation_limits::{self, OperationLimits},
     session::services::*,
     session::session_state::SessionState,
-    session_retry_policy::{Answer, SessionRetryPolicy},
+    session_retry_policy::{Answer, RetryStatistics, SessionRetryPolicy},
     subscription::{self, Subscription},
     subscription_state::SubscriptionState,
+    subscription_stream::SubscriptionStream,
 };
 
 macro_rules! session_warn {
@@ -68,6 +90,18 @@ macro_rules! session_trace {
     }
 }
 
+/// The parameters of a subscription that is created by the session. See
+/// `SubscriptionService::create_subscription` for their meaning.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SubscriptionParameters {
+    pub publishing_interval: f64,
+    pub lifetime_count: u32,
+    pub max_keep_alive_count: u32,
+    pub max_notifications_per_publish: u32,
+    pub priority: u8,
+    pub publishing_enabled: bool,
+}
+
 /// Information about the server endpoint, security policy, security mode and user identity that the session will
 /// will use to establish a connection.
 #[derive(Debug)]
@@ -137,6 +171,19 @@ pub struct Session {
     session_retry_policy: Arc<Mutex<SessionRetryPolicy>>,
     /// Ignore clock skew between the client and the server.
     ignore_clock_skew: bool,
+    /// Supplies a fresh issued token each time the session is activated.
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
+    /// The server's operation limits, read after the session is activated.
+    operation_limits: OperationLimits,
+    /// Maximum number of batches of an oversized service call that are sent at the same time.
+    max_concurrent_batches: usize,
+    /// The server's namespace table, read after the session is activated.
+    namespace_table: Arc<RwLock<NamespaceTable>>,
+    /// Set when the namespace table has changed on the server and must be read again.
+    namespace_table_stale: Arc<AtomicBool>,
+    /// The arguments of methods called with `call_method`, read the first time each is called.
+    method_arguments: Arc<RwLock<HashMap<NodeId, MethodArguments>>>,
 }
 
 impl Drop for Session {
@@ -213,6 +260,12 @@ impl Session {
             message_queue,
             session_retry_policy: Arc::new(Mutex::new(session_retry_policy)),
             ignore_clock_skew,
+            issued_token_refresh_callback: None,
+            operation_limits: OperationLimits::default(),
+            max_concurrent_batches: 1,
+            namespace_table: Arc::new(RwLock::new(NamespaceTable::default())),
+            namespace_table_stale: Arc::new(AtomicBool::new(false)),
+            method_arguments: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -255,9 +308,358 @@ impl Session {
         self.connect().await?;
         self.create_session().await?;
         self.activate_session().await?;
+        self.read_operation_limits().await;
+        self.read_namespace_table().await;
         Ok(())
     }
 
+    /// Sets the maximum number of batches that are sent at the same time when a service call
+    /// exceeds the server's operation limits, or the size of a request, and is split up.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_concurrent_batches` - the maximum number of batches in flight, at least 1
+    ///
+    pub fn set_max_concurrent_batches(&mut self, max_concurrent_batches: usize) {
+        self.max_concurrent_batches = max_concurrent_batches.max(1);
+    }
+
+    /// Returns the server's operation limits as read after the session was activated. Calls to
+    /// `read`, `write`, `browse`, `register_nodes` and `create_monitored_items` which exceed
+    /// them are split into batches.
+    pub fn operation_limits(&self) -> OperationLimits {
+        self.operation_limits
+    }
+
+    /// Reads the server's operation limits. A server which does not supply them is treated as
+    /// having no limits.
+    async fn read_operation_limits(&mut self) {
+        self.operation_limits = OperationLimits::default();
+        let nodes_to_read = OperationLimits::variable_ids()
+            .iter()
+            .map(|id| NodeId::from(id).into())
+            .collect::<Vec<ReadValueId>>();
+        match self
+            .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+            .await
+        {
+            Ok(values) => {
+                self.operation_limits = OperationLimits::from_values(&values);
+                session_debug!(self, "Operation limits {:?}", self.operation_limits);
+            }
+            Err(status_code) => {
+                session_warn!(
+                    self,
+                    "Cannot read the operation limits of the server, status code = {}",
+                    status_code
+                );
+            }
+        }
+    }
+
+    /// Returns a copy of the server's namespace table, as read after the session was activated
+    /// and whenever it has been refreshed since.
+    pub fn namespace_table(&self) -> NamespaceTable {
+        trace_read_lock!(self.namespace_table).clone()
+    }
+
+    /// Reads the server's namespace table again, e.g. after adding a namespace to the server.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(NamespaceTable)` - the namespace table
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn refresh_namespace_table(&self) -> Result<NamespaceTable, StatusCode> {
+        self.namespace_table_stale.store(false, Ordering::Relaxed);
+        let nodes_to_read = NamespaceTable::variable_ids()
+            .iter()
+            .map(|id| NodeId::from(id).into())
+            .collect::<Vec<ReadValueId>>();
+        let values = self
+            .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+            .await?;
+        let namespace_table = NamespaceTable::from_values(&values);
+        session_debug!(self, "Namespace table {:?}", namespace_table);
+        *trace_write_lock!(self.namespace_table) = namespace_table.clone();
+        Ok(namespace_table)
+    }
+
+    /// Returns the arguments of methods that have been read by `call_method`
+    pub(super) fn method_arguments_cache(&self) -> &RwLock<HashMap<NodeId, MethodArguments>> {
+        &self.method_arguments
+    }
+
+    /// Reads the server's namespace table, logging a failure to do so.
+    async fn read_namespace_table(&self) {
+        if let Err(status_code) = self.refresh_namespace_table().await {
+            session_warn!(
+                self,
+                "Cannot read the namespace table of the server, status code = {}",
+                status_code
+            );
+        }
+    }
+
+    /// Creates a subscription which monitors the server's namespace and server arrays, and its
+    /// model change events. The session reads the namespace table again the next time it is
+    /// polled after either changes. Delete the subscription to stop monitoring.
+    ///
+    /// # Arguments
+    ///
+    /// * `publishing_interval` - The requested publishing interval of the subscription in
+    ///   milliseconds.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(u32)` - the id of the subscription
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn monitor_namespace_table(
+        &self,
+        publishing_interval: f64,
+    ) -> Result<u32, StatusCode> {
+        let subscription_id = self
+            .create_subscription(
+                publishing_interval,
+                60,
+                20,
+                0,
+                0,
+                true,
+                NamespaceTableMonitor {
+                    stale: self.namespace_table_stale.clone(),
+                },
+            )
+            .await?;
+        let event_filter = EventFilter {
+            select_clauses: Some(vec![SimpleAttributeOperand::new(
+                ObjectTypeId::BaseEventType,
+                "EventType",
+                AttributeId::Value,
+                UAString::null(),
+            )]),
+            where_clause: ContentFilterBuilder::new()
+                .of_type(ObjectTypeId::BaseModelChangeEventType)
+                .build(),
+        };
+        let mut events: MonitoredItemCreateRequest = NodeId::from(&ObjectId::Server).into();
+        events.item_to_monitor.attribute_id = AttributeId::EventNotifier as u32;
+        events.requested_parameters.filter = ExtensionObject::from_encodable(
+            ObjectId::EventFilter_Encoding_DefaultBinary,
+            &event_filter,
+        );
+        let items_to_create = [
+            NodeId::from(&VariableId::Server_NamespaceArray).into(),
+            NodeId::from(&VariableId::Server_ServerArray).into(),
+            events,
+        ];
+        let results = self
+            .create_monitored_items(
+                subscription_id,
+                TimestampsToReturn::Neither,
+                &items_to_create,
+            )
+            .await?;
+        if let Some(result) = results.iter().find(|r| r.status_code.is_bad()) {
+            session_warn!(
+                self,
+                "Cannot monitor the namespace table, status code = {}",
+                result.status_code
+            );
+        }
+        Ok(subscription_id)
+    }
+
+    /// Returns the size in bytes that a request must not exceed. It is the smaller of the
+    /// negotiated max message size and max chunk count, where 0 means no limit.
+    fn max_request_size(&self) -> usize {
+        let decoding_options = {
+            let secure_channel = trace_read_lock!(self.secure_channel);
+            secure_channel.decoding_options()
+        };
+        let chunks_size = if decoding_options.max_chunk_count > 0 {
+            let session_state = trace_read_lock!(self.session_state);
+            decoding_options.max_chunk_count * session_state.send_buffer_size()
+        } else {
+            0
+        };
+        match (decoding_options.max_message_size, chunks_size) {
+            (0, chunks_size) => chunks_size,
+            (max_message_size, 0) => max_message_size,
+            (max_message_size, chunks_size) => max_message_size.min(chunks_size),
+        }
+    }
+
+    /// Sends the items of a service call in batches that do not exceed the operation limit
+    /// or the size of a request, and merges the results of the batches in order. Up to
+    /// `max_concurrent_batches` batches are sent at the same time and the first error fails
+    /// the call once the batches in flight have completed.
+    async fn send_batches<'a, T, R, F, Fut>(
+        &self,
+        items: &'a [T],
+        operation_limit: u32,
+        send_batch: F,
+    ) -> Result<Vec<R>, StatusCode>
+    where
+        T: BinaryEncoder<T>,
+        F: Fn(&'a [T]) -> Fut,
+        Fut: Future<Output = Result<Vec<R>, StatusCode>>,
+    {
+        let batches =
+            operation_limits::batches(items, operation_limit as usize, self.max_request_size());
+        if batches.len() <= 1 {
+            return send_batch(items).await;
+        }
+        session_debug!(
+            self,
+            "Splitting {} items into {} batches",
+            items.len(),
+            batches.len()
+        );
+        operation_limits::send_batches(batches, self.max_concurrent_batches, send_batch).await
+    }
+
+    async fn read_batch(
+        &self,
+        nodes_to_read: &[ReadValueId],
+        timestamps_to_return: TimestampsToReturn,
+        max_age: f64,
+    ) -> Result<Vec<DataValue>, StatusCode> {
+        let request = ReadRequest {
+            request_header: self.make_request_header(),
+            max_age,
+            timestamps_to_return,
+            nodes_to_read: Some(nodes_to_read.to_vec()),
+        };
+        let response = self.send_request(request).await?;
+        if let SupportedMessage::ReadResponse(response) = response {
+            session_debug!(self, "read(), success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "read() value failed");
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn write_batch(
+        &self,
+        nodes_to_write: &[WriteValue],
+    ) -> Result<Vec<StatusCode>, StatusCode> {
+        let request = WriteRequest {
+            request_header: self.make_request_header(),
+            nodes_to_write: Some(nodes_to_write.to_vec()),
+        };
+        let response = self.send_request(request).await?;
+        if let SupportedMessage::WriteResponse(response) = response {
+            session_debug!(self, "write(), success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "write() failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn browse_batch(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+        maximum_nodes_to_browse: Option<u32>,
+    ) -> Result<Vec<BrowseResult>, StatusCode> {
+        let request = BrowseRequest {
+            request_header: self.make_request_header(),
+            view: ViewDescription {
+                view_id: NodeId::null(),
+                timestamp: DateTime::null(),
+                view_version: 0,
+            },
+            requested_max_references_per_node: maximum_nodes_to_browse
+                .unwrap_or(DEFAULT_MAX_NODES_PER_BROWSE),
+            nodes_to_browse: Some(nodes_to_browse.to_vec()),
+        };
+        let response = self.send_request(request).await?;
+        if let SupportedMessage::BrowseResponse(response) = response {
+            session_debug!(self, "browse, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.results.unwrap_or_default())
+        } else {
+            session_error!(self, "browse failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn register_nodes_batch(
+        &self,
+        nodes_to_register: &[NodeId],
+    ) -> Result<Vec<NodeId>, StatusCode> {
+        let request = RegisterNodesRequest {
+            request_header: self.make_request_header(),
+            nodes_to_register: Some(nodes_to_register.to_vec()),
+        };
+        let response = self.send_request(request).await?;
+        if let SupportedMessage::RegisterNodesResponse(response) = response {
+            session_debug!(self, "register_nodes, success");
+            process_service_result(&response.response_header)?;
+            Ok(response.registered_node_ids.unwrap())
+        } else {
+            session_error!(self, "register_nodes failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
+    async fn create_monitored_items_batch(
+        &self,
+        subscription_id: u32,
+        timestamps_to_return: TimestampsToReturn,
+        items_to_create: &[MonitoredItemCreateRequest],
+    ) -> Result<Vec<MonitoredItemCreateResult>, StatusCode> {
+        let request = CreateMonitoredItemsRequest {
+            request_header: self.make_request_header(),
+            subscription_id,
+            timestamps_to_return,
+            items_to_create: Some(items_to_create.to_vec()),
+        };
+        let response = self.send_request(request).await?;
+        if let SupportedMessage::CreateMonitoredItemsResponse(response) = response {
+            process_service_result(&response.response_header)?;
+            if let Some(ref results) = response.results {
+                session_debug!(
+                    self,
+                    "create_monitored_items, {} items created",
+                    items_to_create.len()
+                );
+                // Set the items in our internal state
+                let items_to_create = items_to_create
+                    .iter()
+                    .zip(results)
+                    .map(|(i, r)| subscription::CreateMonitoredItem {
+                        id: r.monitored_item_id,
+                        client_handle: i.requested_parameters.client_handle,
+                        discard_oldest: i.requested_parameters.discard_oldest,
+                        item_to_monitor: i.item_to_monitor.clone(),
+                        monitoring_mode: i.monitoring_mode,
+                        queue_size: r.revised_queue_size,
+                        sampling_interval: r.revised_sampling_interval,
+                    })
+                    .collect::<Vec<subscription::CreateMonitoredItem>>();
+                {
+                    let mut subscription_state = trace_write_lock!(self.subscription_state);
+                    subscription_state.insert_monitored_items(subscription_id, &items_to_create);
+                }
+            } else {
+                session_debug!(
+                    self,
+                    "create_monitored_items, success but no monitored items were created"
+                );
+            }
+            Ok(response.results.unwrap())
+        } else {
+            session_error!(self, "create_monitored_items failed {:?}", response);
+            Err(process_unexpected_response(response))
+        }
+    }
+
     /// Sets the session retry policy that dictates what this session will do if the connection
     /// fails or goes down. The retry policy enables the session to retry a connection on an
     /// interval up to a maxmimum number of times.
@@ -270,6 +672,13 @@ impl Session {
         self.session_retry_policy = Arc::new(Mutex::new(session_retry_policy));
     }
 
+    /// Returns the statistics of the session's connection attempts, e.g. how many times it has
+    /// retried and why the last attempt failed.
+    pub fn retry_statistics(&self) -> RetryStatistics {
+        let session_retry_policy = trace_lock!(self.session_retry_policy);
+        session_retry_policy.statistics().clone()
+    }
+
     /// Register a callback to be notified when the session has been closed.
     ///
     /// # Arguments
@@ -299,6 +708,29 @@ impl Session {
         session_state.set_connection_status_callback(connection_status_callback);
     }
 
+    /// Registers a callback that supplies a fresh issued token, e.g. a JWT from an authorization
+    /// service, each time the session is activated with an [`IdentityToken::IssuedToken`],
+    /// including when it is reactivated after a reconnect.
+    ///
+    /// # Arguments
+    ///
+    /// * `issued_token_refresh_callback` - the issued token refresh callback.
+    ///
+    pub fn set_issued_token_refresh_callback<CB>(&mut self, issued_token_refresh_callback: CB)
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+    }
+
+    pub(crate) fn set_shared_issued_token_refresh_callback(
+        &mut self,
+        issued_token_refresh_callback: Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>,
+    ) {
+        self.issued_token_refresh_callback = Some(issued_token_refresh_callback);
+    }
+
     /// Reconnects to the server and tries to activate the existing session. If there
     /// is a failure, it will be communicated by the status code in the result. You should not
     /// call this if there is a session retry policy associated with the session.
@@ -346,6 +778,9 @@ impl Session {
                     info!("Activation succeeded");
                 }
             }
+            self.read_operation_limits().await;
+            self.read_namespace_table().await;
+            trace_write_lock!(self.method_arguments).clear();
             session_debug!(self, "transfer_subscriptions_from_old_session");
             self.transfer_subscriptions_from_old_session().await?;
             Ok(())
@@ -397,12 +832,15 @@ impl Session {
                     // Attempt to replicate the subscription (subscription id will be new)
                     if let Ok(subscription_id) = self
                         .create_subscription_inner(
-                            subscription.publishing_interval(),
-                            subscription.lifetime_count(),
-                            subscription.max_keep_alive_count(),
-                            subscription.max_notifications_per_publish(),
-                            subscription.priority(),
-                            subscription.publishing_enabled(),
+                            SubscriptionParameters {
+                                publishing_interval: subscription.publishing_interval(),
+                                lifetime_count: subscription.lifetime_count(),
+                                max_keep_alive_count: subscription.max_keep_alive_count(),
+                                max_notifications_per_publish: subscription
+                                    .max_notifications_per_publish(),
+                                priority: subscription.priority(),
+                                publishing_enabled: subscription.publishing_enabled(),
+                            },
                             subscription.notification_callback(),
                         )
                         .await
@@ -469,31 +907,38 @@ impl Session {
     /// communicated by the status code in the result.
     pub async fn connect(&self) -> Result<(), StatusCode> {
         loop {
+            {
+                let mut session_retry_policy = trace_lock!(self.session_retry_policy);
+                session_retry_policy.record_attempt(DateTime::now());
+            }
             match self.connect_no_retry().await {
                 Ok(_) => {
                     info!("Connect was successful");
                     let mut session_retry_policy = trace_lock!(self.session_retry_policy);
-                    session_retry_policy.reset_retry_count();
+                    session_retry_policy.record_success(DateTime::now());
                     return Ok(());
                 }
                 Err(status_code) => {
-                    let mut session_retry_policy = trace_lock!(self.session_retry_policy);
-                    session_retry_policy.increment_retry_count();
-                    session_warn!(
-                        self,
-                        "Connect was unsuccessful, error = {}, retries = {}",
-                        status_code,
-                        session_retry_policy.retry_count()
-                    );
+                    let should_retry_connect = {
+                        let mut session_retry_policy = trace_lock!(self.session_retry_policy);
+                        session_retry_policy.record_failure(status_code);
+                        session_warn!(
+                            self,
+                            "Connect was unsuccessful, error = {}, retries = {}",
+                            status_code,
+                            session_retry_policy.retry_count()
+                        );
+                        session_retry_policy.should_retry_connect(DateTime::now())
+                    };
 
-                    match session_retry_policy.should_retry_connect(DateTime::now()) {
+                    match should_retry_connect {
                         Answer::GiveUp => {
+                            let session_retry_policy = trace_lock!(self.session_retry_policy);
                             session_error!(self, "Session has given up trying to connect to the server after {} retries", session_retry_policy.retry_count());
                             return Err(StatusCode::BadNotConnected);
                         }
                         Answer::Retry => {
                             info!("Retrying to connect to server...");
-                            session_retry_policy.set_last_attempt(DateTime::now());
                         }
                         Answer::WaitFor(sleep_for) => {
                             // Sleep for the instructed interval before looping around and trying
@@ -597,7 +1042,7 @@ impl Session {
     }
 
     /// Internal constant for the sleep interval used during polling
-    const POLL_SLEEP_INTERVAL: u64 = 10;
+    pub(crate) const POLL_SLEEP_INTERVAL: u64 = 10;
 
     /// Synchronously runs a polling loop over the supplied session. Running a session performs
     /// periodic actions such as receiving messages, processing subscriptions, and recovering from
@@ -705,7 +1150,14 @@ impl Session {
     ///
     pub async fn poll(&mut self) -> Result<bool, ()> {
         let did_something = if self.is_connected() {
-            self.handle_publish_responses()
+            let deleted_subscriptions = self.delete_closed_subscriptions();
+            let refreshed_namespace_table = if self.namespace_table_stale.load(Ordering::Relaxed) {
+                self.read_namespace_table().await;
+                true
+            } else {
+                false
+            };
+            self.handle_publish_responses() || deleted_subscriptions || refreshed_namespace_table
         } else {
             let should_retry_connect = {
                 let session_retry_policy = trace_lock!(self.session_retry_policy);
@@ -725,22 +1177,23 @@ impl Session {
                     info!("Retrying to reconnect to server...");
                     {
                         let mut session_retry_policy = trace_lock!(self.session_retry_policy);
-                        session_retry_policy.set_last_attempt(DateTime::now());
+                        session_retry_policy.record_attempt(DateTime::now());
                     }
-                    if self.reconnect_and_activate().await.is_ok() {
-                        info!("Retry to connect was successful");
+                    if let Err(status_code) = self.reconnect_and_activate().await {
                         let mut session_retry_policy = trace_lock!(self.session_retry_policy);
-                        session_retry_policy.reset_retry_count();
-                    } else {
-                        let mut session_retry_policy = trace_lock!(self.session_retry_policy);
-                        session_retry_policy.increment_retry_count();
+                        session_retry_policy.record_failure(status_code);
                         session_warn!(
                             self,
-                            "Reconnect was unsuccessful, retries = {}",
+                            "Reconnect was unsuccessful, error = {}, retries = {}",
+                            status_code,
                             session_retry_policy.retry_count()
                         );
                         drop(session_retry_policy);
                         self.disconnect().await;
+                    } else {
+                        info!("Retry to connect was successful");
+                        let mut session_retry_policy = trace_lock!(self.session_retry_policy);
+                        session_retry_policy.record_success(DateTime::now());
                     }
                     true
                 }
@@ -754,6 +1207,41 @@ impl Session {
         Ok(did_something)
     }
 
+    /// Deletes the subscriptions whose notification callback is closed, e.g. because the stream
+    /// of a subscription was dropped. The delete request is sent without waiting for its response.
+    fn delete_closed_subscriptions(&self) -> bool {
+        let subscription_ids = {
+            let mut subscription_state = trace_write_lock!(self.subscription_state);
+            let subscription_ids = subscription_state.closed_subscription_ids();
+            subscription_ids.iter().for_each(|subscription_id| {
+                let _ = subscription_state.delete_subscription(*subscription_id);
+            });
+            subscription_ids
+        };
+        if subscription_ids.is_empty() {
+            false
+        } else {
+            session_debug!(
+                self,
+                "Deleting subscriptions {:?} whose notifications are no longer wanted",
+                subscription_ids
+            );
+            let mut session_state = trace_write_lock!(self.session_state);
+            let request = DeleteSubscriptionsRequest {
+                request_header: session_state.make_request_header(),
+                subscription_ids: Some(subscription_ids),
+            };
+            if let Err(status_code) = session_state.async_send_request(request, None) {
+                session_error!(
+                    self,
+                    "Cannot send a request to delete subscriptions, status code = {}",
+                    status_code
+                );
+            }
+            true
+        }
+    }
+
     /// Start a task that will periodically send a publish request to keep the subscriptions alive.
     /// The request rate will be 3/4 of the shortest (revised publishing interval * the revised keep
     /// alive count) of all subscriptions that belong to a single session.
@@ -817,16 +1305,19 @@ impl Session {
     }
 
     /// This is the internal handler for create subscription that receives the callback wrapped up and reference counted.
-    async fn create_subscription_inner(
+    pub(crate) async fn create_subscription_inner(
         &self,
-        publishing_interval: f64,
-        lifetime_count: u32,
-        max_keep_alive_count: u32,
-        max_notifications_per_publish: u32,
-        priority: u8,
-        publishing_enabled: bool,
+        parameters: SubscriptionParameters,
         callback: Arc<Mutex<dyn OnSubscriptionNotification + Send + Sync + 'static>>,
     ) -> Result<u32, StatusCode> {
+        let SubscriptionParameters {
+            publishing_interval,
+            lifetime_count,
+            max_keep_alive_count,
+            max_notifications_per_publish,
+            priority,
+            publishing_enabled,
+        } = parameters;
         let request = CreateSubscriptionRequest {
             request_header: self.make_request_header(),
             requested_publishing_interval: publishing_interval,
@@ -944,6 +1435,25 @@ impl Session {
         }
     }
 
+    /// Runs a service call until it completes or the token is cancelled. When the token is
+    /// cancelled first, the service call is dropped, which asks the server to cancel its request,
+    /// and `BadRequestCancelledByClient` is returned.
+    ///
+    /// Dropping the future of a service call without a token cancels its request in the same
+    /// way, e.g. when it loses a `tokio::select!`.
+    pub async fn cancellable<F, T>(
+        token: &CancellationToken,
+        service_call: F,
+    ) -> Result<T, StatusCode>
+    where
+        F: Future<Output = Result<T, StatusCode>>,
+    {
+        tokio::select! {
+            result = service_call => result,
+            _ = token.cancelled() => Err(StatusCode::BadRequestCancelledByClient),
+        }
+    }
+
     /// Returns the subscription state object
     pub fn subscription_state(&self) -> Arc<RwLock<SubscriptionState>> {
         self.subscription_state.clone()
@@ -986,6 +1496,7 @@ impl Session {
             IdentityToken::Anonymous => UserTokenType::Anonymous,
             IdentityToken::UserName(_, _) => UserTokenType::UserName,
             IdentityToken::X509(_, _) => UserTokenType::Certificate,
+            IdentityToken::IssuedToken(_) => UserTokenType::IssuedToken,
         };
 
         let endpoint = &self.session_info.endpoint;
@@ -1042,6 +1553,22 @@ impl Session {
                             );
                             Ok((identity_token, SignatureData::null()))
                         }
+                        IdentityToken::IssuedToken(ref token) => {
+                            let token = self.issued_token(token)?;
+                            let secure_channel = trace_read_lock!(self.secure_channel);
+                            let identity_token = make_issued_identity_token(
+                                secure_channel.security_policy(),
+                                policy,
+                                secure_channel.remote_nonce(),
+                                &secure_channel.remote_cert(),
+                                &token,
+                            )?;
+                            let identity_token = ExtensionObject::from_encodable(
+                                ObjectId::IssuedIdentityToken_Encoding_DefaultBinary,
+                                &identity_token,
+                            );
+                            Ok((identity_token, SignatureData::null()))
+                        }
                         IdentityToken::X509(ref cert_path, ref private_key_path) => {
                             if let Some(ref server_cert) = server_cert {
                                 // The cert will be supplied to the server along with a signature to prove we have the private key to go with the cert
@@ -1096,6 +1623,17 @@ impl Session {
         }
     }
 
+    /// Returns the issued token to activate the session with. A refresh callback is asked for a
+    /// fresh token, otherwise the token of the session info is used as is.
+    fn issued_token(&self, token: &str) -> Result<String, StatusCode> {
+        if let Some(ref issued_token_refresh_callback) = self.issued_token_refresh_callback {
+            let mut issued_token_refresh_callback = trace_lock!(issued_token_refresh_callback);
+            issued_token_refresh_callback.on_issued_token_refresh()
+        } else {
+            Ok(token.to_string())
+        }
+    }
+
     /// Create a filled in UserNameIdentityToken by using the endpoint's token policy, the current
     /// secure channel information and the user name and password.
     fn make_user_name_identity_token(
@@ -1188,6 +1726,18 @@ impl Session {
                     }
                 }
 
+                // Process status change notifications
+                let status_changes = notification_message.status_changes(&decoding_options);
+                if !status_changes.is_empty() {
+                    session_debug!(
+                        self,
+                        "Received status changes {:?}",
+                        status_changes.iter().map(|n| n.status).collect::<Vec<_>>()
+                    );
+                    let mut subscription_state = trace_write_lock!(self.subscription_state);
+                    subscription_state.on_status_change(subscription_id, &status_changes);
+                }
+
                 // Send another publish request
                 {
                     let mut session_state = trace_write_lock!(self.session_state);
@@ -1233,6 +1783,21 @@ impl Session {
                     _ => (),
                 }
             }
+            SupportedMessage::DeleteSubscriptionsResponse(response) => {
+                session_debug!(
+                    self,
+                    "Deleted closed subscriptions with results {:?}",
+                    response.results
+                );
+            }
+            SupportedMessage::CancelResponse(response) => {
+                session_debug!(
+                    self,
+                    "Cancel request {} cancelled {} requests",
+                    response.response_header.request_handle,
+                    response.cancel_count
+                );
+            }
             _ => {
                 info!("{} unhandled response: {:?}", self.session_id(), response);
             }
@@ -1255,8 +1820,13 @@ impl Session {
         where
             T: Into<SupportedMessage> + Send + std::fmt::Debug,
     {
-        let mut session_state = trace_write_lock!(self.session_state);
-        session_state.send_request(request).await
+        // The session state is not held while the response is awaited so that the request can
+        // be cancelled and other requests can be sent meanwhile
+        let pending_request = {
+            let mut session_state = trace_write_lock!(self.session_state);
+            session_state.begin_request(request, &self.session_state)?
+        };
+        pending_request.response().await
     }
 }
 
@@ -1647,17 +2217,54 @@ impl SubscriptionService for Session {
         CB: OnSubscriptionNotification + Send + Sync + 'static,
     {
         self.create_subscription_inner(
-            publishing_interval,
-            lifetime_count,
-            max_keep_alive_count,
-            max_notifications_per_publish,
-            priority,
-            publishing_enabled,
+            SubscriptionParameters {
+                publishing_interval,
+                lifetime_count,
+                max_keep_alive_count,
+                max_notifications_per_publish,
+                priority,
+                publishing_enabled,
+            },
             Arc::new(Mutex::new(callback)),
         )
         .await
     }
 
+    async fn create_subscription_stream(
+        &self,
+        publishing_interval: f64,
+        lifetime_count: u32,
+        max_keep_alive_count: u32,
+        max_notifications_per_publish: u32,
+        priority: u8,
+        publishing_enabled: bool,
+        buffer_size: usize,
+    ) -> Result<SubscriptionStream, StatusCode> {
+        if buffer_size == 0 {
+            session_error!(
+                self,
+                "create_subscription_stream, buffer size must be greater than zero"
+            );
+            return Err(StatusCode::BadInvalidArgument);
+        }
+        let (mut stream, callback) = SubscriptionStream::new(buffer_size);
+        let subscription_id = self
+            .create_subscription_inner(
+                SubscriptionParameters {
+                    publishing_interval,
+                    lifetime_count,
+                    max_keep_alive_count,
+                    max_notifications_per_publish,
+                    priority,
+                    publishing_enabled,
+                },
+                Arc::new(Mutex::new(callback)),
+            )
+            .await?;
+        stream.set_subscription_id(subscription_id);
+        Ok(stream)
+    }
+
     async fn modify_subscription(
         &self,
         subscription_id: u32,
@@ -1960,51 +2567,51 @@ impl MonitoredItemService for Session {
                 });
             }
 
-            let request = CreateMonitoredItemsRequest {
-                request_header: self.make_request_header(),
-                subscription_id,
-                timestamps_to_return,
-                items_to_create: Some(items_to_create.clone()),
-            };
-            let response = self.send_request(request).await?;
-            if let SupportedMessage::CreateMonitoredItemsResponse(response) = response {
-                process_service_result(&response.response_header)?;
-                if let Some(ref results) = response.results {
-                    session_debug!(
-                        self,
-                        "create_monitored_items, {} items created",
-                        items_to_create.len()
-                    );
-                    // Set the items in our internal state
-                    let items_to_create = items_to_create
-                        .iter()
-                        .zip(results)
-                        .map(|(i, r)| subscription::CreateMonitoredItem {
-                            id: r.monitored_item_id,
-                            client_handle: i.requested_parameters.client_handle,
-                            discard_oldest: i.requested_parameters.discard_oldest,
-                            item_to_monitor: i.item_to_monitor.clone(),
-                            monitoring_mode: i.monitoring_mode,
-                            queue_size: r.revised_queue_size,
-                            sampling_interval: r.revised_sampling_interval,
-                        })
-                        .collect::<Vec<subscription::CreateMonitoredItem>>();
-                    {
-                        let mut subscription_state = trace_write_lock!(self.subscription_state);
-                        subscription_state
-                            .insert_monitored_items(subscription_id, &items_to_create);
-                    }
-                } else {
-                    session_debug!(
+            // Remember the items that each batch creates, so they can be deleted again if
+            // another batch fails and the call returns an error
+            let created_items = Mutex::new(Vec::new());
+            let created = &created_items;
+            let results = self
+                .send_batches(
+                    &items_to_create,
+                    self.operation_limits.max_monitored_items_per_call,
+                    |items_to_create| async move {
+                        let results = self
+                            .create_monitored_items_batch(
+                                subscription_id,
+                                timestamps_to_return,
+                                items_to_create,
+                            )
+                            .await?;
+                        trace_lock!(created).extend(
+                            results
+                                .iter()
+                                .filter(|r| r.status_code.is_good())
+                                .map(|r| r.monitored_item_id),
+                        );
+                        Ok(results)
+                    },
+                )
+                .await;
+            let created_items = created_items.into_inner();
+            if results.is_err() && !created_items.is_empty() {
+                session_error!(
+                    self,
+                    "create_monitored_items, a batch failed so deleting the {} items created by other batches",
+                    created_items.len()
+                );
+                if let Err(err) = self
+                    .delete_monitored_items(subscription_id, &created_items)
+                    .await
+                {
+                    session_error!(
                         self,
-                        "create_monitored_items, success but no monitored items were created"
+                        "create_monitored_items, could not delete created items, {}",
+                        err
                     );
                 }
-                Ok(response.results.unwrap())
-            } else {
-                session_error!(self, "create_monitored_items failed {:?}", response);
-                Err(process_unexpected_response(response))
             }
+            results
         }
     }
 
@@ -2214,25 +2821,14 @@ impl ViewService for Session {
             session_error!(self, "browse, was not supplied with any nodes to browse");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = BrowseRequest {
-                request_header: self.make_request_header(),
-                view: ViewDescription {
-                    view_id: NodeId::null(),
-                    timestamp: DateTime::null(),
-                    view_version: 0,
-                },
-                requested_max_references_per_node: maximum_nodes_to_browse.unwrap_or(DEFAULT_MAX_NODES_PER_BROWSE),
-                nodes_to_browse: Some(nodes_to_browse.to_vec()),
-            };
-            let response = self.send_request(request).await?;
-            if let SupportedMessage::BrowseResponse(response) = response {
-                session_debug!(self, "browse, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results)
-            } else {
-                session_error!(self, "browse failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            let results = self
+                .send_batches(
+                    nodes_to_browse,
+                    self.operation_limits.max_nodes_per_browse,
+                    |nodes_to_browse| self.browse_batch(nodes_to_browse, maximum_nodes_to_browse),
+                )
+                .await?;
+            Ok(Some(results))
         }
     }
 
@@ -2303,19 +2899,12 @@ impl ViewService for Session {
             );
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = RegisterNodesRequest {
-                request_header: self.make_request_header(),
-                nodes_to_register: Some(nodes_to_register.to_vec()),
-            };
-            let response = self.send_request(request).await?;
-            if let SupportedMessage::RegisterNodesResponse(response) = response {
-                session_debug!(self, "register_nodes, success");
-                process_service_result(&response.response_header)?;
-                Ok(response.registered_node_ids.unwrap())
-            } else {
-                session_error!(self, "register_nodes failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            self.send_batches(
+                nodes_to_register,
+                self.operation_limits.max_nodes_per_register_nodes,
+                |nodes_to_register| self.register_nodes_batch(nodes_to_register),
+            )
+            .await
         }
     }
 
@@ -2396,26 +2985,12 @@ impl AttributeService for Session {
             Err(StatusCode::BadNothingToDo)
         } else {
             session_debug!(self, "read() requested to read nodes {:?}", nodes_to_read);
-            let request = ReadRequest {
-                request_header: self.make_request_header(),
-                max_age,
-                timestamps_to_return,
-                nodes_to_read: Some(nodes_to_read.to_vec()),
-            };
-            let response = self.send_request(request).await?;
-            if let SupportedMessage::ReadResponse(response) = response {
-                session_debug!(self, "read(), success");
-                process_service_result(&response.response_header)?;
-                let results = if let Some(results) = response.results {
-                    results
-                } else {
-                    Vec::new()
-                };
-                Ok(results)
-            } else {
-                session_error!(self, "read() value failed");
-                Err(process_unexpected_response(response))
-            }
+            self.send_batches(
+                nodes_to_read,
+                self.operation_limits.max_nodes_per_read,
+                |nodes_to_read| self.read_batch(nodes_to_read, timestamps_to_return, max_age),
+            )
+            .await
         }
     }
 
@@ -2483,19 +3058,12 @@ impl AttributeService for Session {
             session_error!(self, "write() was not supplied with any nodes to write");
             Err(StatusCode::BadNothingToDo)
         } else {
-            let request = WriteRequest {
-                request_header: self.make_request_header(),
-                nodes_to_write: Some(nodes_to_write.to_vec()),
-            };
-            let response = self.send_request(request).await?;
-            if let SupportedMessage::WriteResponse(response) = response {
-                session_debug!(self, "write(), success");
-                process_service_result(&response.response_header)?;
-                Ok(response.results.unwrap_or_default())
-            } else {
-                session_error!(self, "write() failed {:?}", response);
-                Err(process_unexpected_response(response))
-            }
+            self.send_batches(
+                nodes_to_write,
+                self.operation_limits.max_nodes_per_write,
+                |nodes_to_write| self.write_batch(nodes_to_write),
+            )
+            .await
         }
     }
 
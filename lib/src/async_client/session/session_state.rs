@@ -5,7 +5,7 @@
 use std::{
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
+        Arc, Weak,
     },
     u32,
 };
@@ -145,6 +145,66 @@ pub(crate) struct SessionState {
     connection_status_callback: Option<Box<dyn OnConnectionStatusChange + Send + Sync + 'static>>,
 }
 
+/// A request that has been sent and whose response has not arrived. If it is dropped before the
+/// response arrives, e.g. because the future of a service call was dropped, the request is
+/// cancelled on the server.
+pub(crate) struct PendingRequest {
+    /// The handle of the request
+    request_handle: u32,
+    /// Time to wait for the response
+    request_timeout: std::time::Duration,
+    /// Receives the response
+    receiver: Receiver<SupportedMessage>,
+    /// The session state that sent the request, used to send a cancel
+    session_state: Weak<RwLock<SessionState>>,
+    /// Set once a response, an error or a timeout has finished the request
+    finished: bool,
+}
+
+impl PendingRequest {
+    /// Waits for the response to the request
+    pub(crate) async fn response(mut self) -> Result<SupportedMessage, StatusCode> {
+        let result = match timeout(self.request_timeout, self.receiver.recv()).await {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(StatusCode::BadNotConnected),
+            Err(_) => {
+                info!("Timeout waiting for response from server");
+                if let Some(session_state) = self.session_state.upgrade() {
+                    let session_state = trace_read_lock!(session_state);
+                    session_state.request_has_timed_out(self.request_handle);
+                }
+                Err(StatusCode::BadTimeout)
+            }
+        };
+        self.finished = true;
+        result
+    }
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // The lock is not waited for because the drop may happen while the session state is
+        // locked, e.g. by a future that is dropped while renewing the secure channel.
+        if let Some(session_state) = self.session_state.upgrade() {
+            if let Some(mut session_state) = session_state.try_write() {
+                debug!(
+                    "Request {} was dropped before its response and will be cancelled",
+                    self.request_handle
+                );
+                session_state.cancel_request(self.request_handle);
+            } else {
+                info!(
+                    "Request {} was dropped before its response but cannot be cancelled",
+                    self.request_handle
+                );
+            }
+        }
+    }
+}
+
 impl OnSessionClosed for SessionState {
     fn on_session_closed(&mut self, status_code: StatusCode) {
         debug!("Session was closed with status = {}", status_code);
@@ -323,6 +383,48 @@ impl SessionState {
         Ok(request_handle)
     }
 
+    /// Sends a request and returns the pending request that receives its response. The response
+    /// is awaited without the session state, so other requests, e.g. a Cancel, may be sent while
+    /// it is in flight.
+    pub(crate) fn begin_request<T>(
+        &mut self,
+        request: T,
+        session_state: &Arc<RwLock<SessionState>>,
+    ) -> Result<PendingRequest, StatusCode>
+    where
+        T: Into<SupportedMessage> + std::fmt::Debug,
+    {
+        let (sender, receiver) = mpsc::channel(1);
+        let request_handle = self.async_send_request(request, Some(sender))?;
+        Ok(PendingRequest {
+            request_handle,
+            request_timeout: self.request_timeout(),
+            receiver,
+            session_state: Arc::downgrade(session_state),
+            finished: false,
+        })
+    }
+
+    /// Stops waiting for a request and asks the server to cancel it. The server answers the
+    /// request with `BadRequestCancelledByClient`, or with its late response, and either is
+    /// dropped. The response to the Cancel itself is ignored.
+    pub(crate) fn cancel_request(&mut self, request_handle: u32) {
+        {
+            let mut message_queue = trace_write_lock!(self.message_queue);
+            message_queue.request_was_cancelled(request_handle);
+        }
+        let request = CancelRequest {
+            request_header: self.make_request_header(),
+            request_handle,
+        };
+        if let Err(status_code) = self.async_send_request(request, None) {
+            debug!(
+                "Cannot send a cancel for request {}, status code = {}",
+                request_handle, status_code
+            );
+        }
+    }
+
     /// Synchronously sends a request. The return value is the response to the request
     pub(crate) async fn send_request<T>(
         &mut self,
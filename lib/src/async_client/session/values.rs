@@ -0,0 +1,322 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Typed helpers for the session which read and write values as Rust types, and call methods
+//! with arguments that are checked against the method's argument metadata.
+
+use std::convert::TryFrom;
+
+use crate::types::{
+    node_ids::ReferenceTypeId, service_types::Argument, status_code::StatusCode, *,
+};
+
+use super::{
+    services::{AttributeService, MethodService, ViewService},
+    session::Session,
+};
+
+/// The input and output arguments of a method, read from its `InputArguments` and
+/// `OutputArguments` properties. A method without a property has no such arguments.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MethodArguments {
+    /// The input arguments
+    pub input_arguments: Vec<Argument>,
+    /// The output arguments
+    pub output_arguments: Vec<Argument>,
+}
+
+impl MethodArguments {
+    /// Checks the number, shape and data type of input arguments, and converts them to the
+    /// data types of the arguments.
+    pub fn convert_input_arguments(&self, values: &[Variant]) -> Result<Vec<Variant>, StatusCode> {
+        if values.len() < self.input_arguments.len() {
+            error!(
+                "Method expects {} input arguments but was given {}",
+                self.input_arguments.len(),
+                values.len()
+            );
+            Err(StatusCode::BadArgumentsMissing)
+        } else if values.len() > self.input_arguments.len() {
+            error!(
+                "Method expects {} input arguments but was given {}",
+                self.input_arguments.len(),
+                values.len()
+            );
+            Err(StatusCode::BadTooManyArguments)
+        } else {
+            Self::convert_arguments(&self.input_arguments, values)
+        }
+    }
+
+    /// Checks the number, shape and data type of output arguments, and converts them to the
+    /// data types of the arguments.
+    pub fn convert_output_arguments(&self, values: &[Variant]) -> Result<Vec<Variant>, StatusCode> {
+        if values.len() != self.output_arguments.len() {
+            error!(
+                "Method has {} output arguments but returned {}",
+                self.output_arguments.len(),
+                values.len()
+            );
+            Err(StatusCode::BadUnexpectedError)
+        } else {
+            Self::convert_arguments(&self.output_arguments, values)
+        }
+    }
+
+    fn convert_arguments(
+        arguments: &[Argument],
+        values: &[Variant],
+    ) -> Result<Vec<Variant>, StatusCode> {
+        arguments
+            .iter()
+            .zip(values)
+            .map(|(argument, value)| {
+                Self::convert_argument(argument, value).ok_or_else(|| {
+                    error!(
+                        "Argument {} of type {} and value rank {} does not accept value {:?}",
+                        argument.name, argument.data_type, argument.value_rank, value
+                    );
+                    StatusCode::BadTypeMismatch
+                })
+            })
+            .collect()
+    }
+
+    /// Converts a value to the data type of an argument. A value of a data type which is not
+    /// built in, e.g. a structure, is left for the server to check.
+    fn convert_argument(argument: &Argument, value: &Variant) -> Option<Variant> {
+        let array_dimensions = argument.array_dimensions.as_deref();
+        if let Variant::Empty = value {
+            Some(Variant::Empty)
+        } else if !Variant::dimensions_match(
+            argument.value_rank,
+            array_dimensions,
+            &value.dimensions(),
+        ) {
+            None
+        } else if let Ok(variant_type) = VariantTypeId::try_from(&argument.data_type) {
+            match value {
+                Variant::Array(array) => array
+                    .values
+                    .iter()
+                    .map(|v| Self::convert_value(v, variant_type))
+                    .collect::<Option<Vec<_>>>()
+                    .and_then(|values| {
+                        let array = if array.has_dimensions() {
+                            Array::new_multi(variant_type, values, array.dimensions.clone())
+                        } else {
+                            Array::new_single(variant_type, values)
+                        };
+                        array.ok().map(Variant::from)
+                    }),
+                value => Self::convert_value(value, variant_type),
+            }
+        } else {
+            Some(value.clone())
+        }
+    }
+
+    fn convert_value(value: &Variant, variant_type: VariantTypeId) -> Option<Variant> {
+        match value.convert(variant_type) {
+            Variant::Empty => None,
+            value => Some(value),
+        }
+    }
+}
+
+impl Session {
+    /// Reads the value of a variable as a Rust type, converting it from the `Variant`.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable to read.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(T)` - The value
+    /// * `Err(StatusCode)` - Request failed, the status of the value if it is bad, or
+    ///   `BadTypeMismatch` if the value cannot be converted
+    ///
+    pub async fn read_value<T>(&self, node_id: &NodeId) -> Result<T, StatusCode>
+    where
+        T: VariantValue,
+    {
+        let values = self
+            .read(&[node_id.clone().into()], TimestampsToReturn::Neither, 0.0)
+            .await?;
+        let value = values
+            .into_iter()
+            .next()
+            .ok_or(StatusCode::BadUnexpectedError)?;
+        match value.status {
+            Some(status) if status.is_bad() => Err(status),
+            _ => T::from_variant(value.value.as_ref().unwrap_or(&Variant::Empty)),
+        }
+    }
+
+    /// Writes a Rust type to the value of a variable, converting it into a `Variant`.
+    ///
+    /// # Arguments
+    ///
+    /// * `node_id` - The variable to write.
+    /// * `value` - The value to write.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - The value was written
+    /// * `Err(StatusCode)` - Request failed, or the status of the write if it is bad
+    ///
+    pub async fn write_value<T>(&self, node_id: &NodeId, value: T) -> Result<(), StatusCode>
+    where
+        T: VariantValue,
+    {
+        let write_value = WriteValue {
+            node_id: node_id.clone(),
+            attribute_id: AttributeId::Value as u32,
+            index_range: UAString::null(),
+            value: value.into_variant().into(),
+        };
+        let results = self.write(&[write_value]).await?;
+        match results.first() {
+            Some(status_code) if status_code.is_bad() => Err(*status_code),
+            Some(_) => Ok(()),
+            None => Err(StatusCode::BadUnexpectedError),
+        }
+    }
+
+    /// Returns the input and output arguments of a method. They are read from the server the
+    /// first time and remembered until the session reconnects.
+    ///
+    /// # Arguments
+    ///
+    /// * `method_id` - The method.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(MethodArguments)` - The arguments
+    /// * `Err(StatusCode)` - Request failed, status code is the reason for failure
+    ///
+    pub async fn method_arguments(
+        &self,
+        method_id: &NodeId,
+    ) -> Result<MethodArguments, StatusCode> {
+        if let Some(method_arguments) =
+            trace_read_lock!(self.method_arguments_cache()).get(method_id)
+        {
+            return Ok(method_arguments.clone());
+        }
+
+        let browse_path = |browse_name: &str| BrowsePath {
+            starting_node: method_id.clone(),
+            relative_path: RelativePath {
+                elements: Some(vec![RelativePathElement {
+                    reference_type_id: ReferenceTypeId::HasProperty.into(),
+                    is_inverse: false,
+                    include_subtypes: true,
+                    target_name: QualifiedName::new(0, browse_name),
+                }]),
+            },
+        };
+        let results = self
+            .translate_browse_paths_to_node_ids(&[
+                browse_path("InputArguments"),
+                browse_path("OutputArguments"),
+            ])
+            .await?;
+        if results.len() != 2 {
+            return Err(StatusCode::BadUnexpectedError);
+        }
+        // A method without a property has no such arguments
+        let property_ids = results
+            .iter()
+            .map(|result| {
+                if result.status_code == StatusCode::BadNoMatch {
+                    Ok(None)
+                } else if result.status_code.is_bad() {
+                    Err(result.status_code)
+                } else {
+                    Ok(result
+                        .targets
+                        .as_ref()
+                        .and_then(|targets| targets.first())
+                        .map(|target| target.target_id.node_id.clone()))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let nodes_to_read = property_ids
+            .iter()
+            .flatten()
+            .map(ReadValueId::from)
+            .collect::<Vec<_>>();
+        let mut values = if nodes_to_read.is_empty() {
+            Vec::new()
+        } else {
+            self.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)
+                .await?
+        }
+        .into_iter();
+        let mut arguments = property_ids
+            .iter()
+            .map(|property_id| match property_id {
+                Some(_) => {
+                    let value = values.next().and_then(|v| v.value).unwrap_or_default();
+                    Vec::<Argument>::from_variant(&value)
+                }
+                None => Ok(Vec::new()),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let method_arguments = MethodArguments {
+            input_arguments: arguments.next().unwrap_or_default(),
+            output_arguments: arguments.next().unwrap_or_default(),
+        };
+        trace_write_lock!(self.method_arguments_cache())
+            .insert(method_id.clone(), method_arguments.clone());
+        Ok(method_arguments)
+    }
+
+    /// Calls a method, checking the input arguments against the method's `InputArguments` and
+    /// converting them to their data types before the call, and checking and converting the
+    /// output arguments against its `OutputArguments` afterwards. The arguments of the method
+    /// are read the first time it is called.
+    ///
+    /// # Arguments
+    ///
+    /// * `object_id` - The object or object type that the method is called on.
+    /// * `method_id` - The method.
+    /// * `input_arguments` - The input arguments, e.g. made with `VariantValue::into_variant`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Variant>)` - The output arguments
+    /// * `Err(StatusCode)` - Request failed, the arguments do not match the method, or the
+    ///   status of the call if it is bad
+    ///
+    pub async fn call_method(
+        &self,
+        object_id: &NodeId,
+        method_id: &NodeId,
+        input_arguments: &[Variant],
+    ) -> Result<Vec<Variant>, StatusCode> {
+        let method_arguments = self.method_arguments(method_id).await?;
+        let input_arguments = method_arguments.convert_input_arguments(input_arguments)?;
+        let request = CallMethodRequest {
+            object_id: object_id.clone(),
+            method_id: method_id.clone(),
+            input_arguments: Some(input_arguments),
+        };
+        let result = self.call(request).await?;
+        if result.status_code.is_bad() {
+            if let Some(input_argument_results) = result.input_argument_results {
+                error!(
+                    "Call to method {} failed with {}, input argument results = {:?}",
+                    method_id, result.status_code, input_argument_results
+                );
+            }
+            Err(result.status_code)
+        } else {
+            method_arguments.convert_output_arguments(&result.output_arguments.unwrap_or_default())
+        }
+    }
+}
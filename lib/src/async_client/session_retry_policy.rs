@@ -2,9 +2,14 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
+use std::sync::Arc;
+
 use chrono::Duration;
 
-use crate::types::date_time::DateTime;
+use crate::{
+    crypto::random,
+    types::{date_time::DateTime, status_code::StatusCode},
+};
 
 #[derive(PartialEq, Debug)]
 pub enum Answer {
@@ -16,6 +21,107 @@ pub enum Answer {
     GiveUp,
 }
 
+/// What a failed connection attempt is known by, passed to a custom `RetryStrategy`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryContext {
+    /// The number of failed attempts since the last connection, including this one
+    pub retry_count: u32,
+    /// The reason the attempt failed
+    pub status_code: StatusCode,
+    /// The interval in milliseconds that was waited before the attempt
+    pub previous_interval: u32,
+    /// The retry interval of the policy in milliseconds
+    pub retry_interval: u32,
+}
+
+/// The decision of a custom `RetryStrategy` after a failed connection attempt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryDecision {
+    /// Retry after this many milliseconds
+    RetryAfter(u32),
+    /// Give up reconnecting
+    GiveUp,
+}
+
+/// A custom retry strategy
+pub type RetryStrategyFn = Arc<dyn Fn(&RetryContext) -> RetryDecision + Send + Sync>;
+
+/// The strategy decides how long to wait after each failed connection attempt. Whatever the
+/// strategy, the policy never waits less than its minimum retry interval.
+#[derive(Derivative, Clone)]
+#[derivative(Debug, PartialEq = "feature_allow_slow_enum")]
+pub enum RetryStrategy {
+    /// Wait the retry interval after every failure
+    Fixed,
+    /// Wait the retry interval after the first failure, and multiply the interval by `multiplier`
+    /// after each failure after that, up to `max_interval` milliseconds.
+    Exponential { multiplier: f64, max_interval: u32 },
+    /// Wait a random interval between the retry interval and three times the previous interval,
+    /// up to `max_interval` milliseconds. This is known as decorrelated jitter, and stops clients
+    /// that lost their connections at the same time from retrying at the same time.
+    DecorrelatedJitter { max_interval: u32 },
+    /// Call a function to decide, e.g. to wait for a longer time after some errors or to give up
+    /// after others. Two custom strategies compare as equal.
+    Custom(#[derivative(Debug = "ignore", PartialEq = "ignore")] RetryStrategyFn),
+}
+
+impl RetryStrategy {
+    /// Creates a custom strategy from a function
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&RetryContext) -> RetryDecision + Send + Sync + 'static,
+    {
+        RetryStrategy::Custom(Arc::new(f))
+    }
+
+    fn decide(&self, context: &RetryContext) -> RetryDecision {
+        match self {
+            RetryStrategy::Fixed => RetryDecision::RetryAfter(context.retry_interval),
+            RetryStrategy::Exponential {
+                multiplier,
+                max_interval,
+            } => {
+                let exponent = context.retry_count.saturating_sub(1).min(i32::MAX as u32) as i32;
+                let interval = context.retry_interval as f64 * multiplier.powi(exponent);
+                RetryDecision::RetryAfter(interval.min(*max_interval as f64) as u32)
+            }
+            RetryStrategy::DecorrelatedJitter { max_interval } => {
+                let low = context.retry_interval;
+                let high = context.previous_interval.saturating_mul(3).max(low);
+                RetryDecision::RetryAfter(Self::random_between(low, high).min(*max_interval))
+            }
+            RetryStrategy::Custom(f) => f(context),
+        }
+    }
+
+    fn random_between(low: u32, high: u32) -> u32 {
+        let mut bytes = [0u8; 4];
+        random::bytes(&mut bytes);
+        let range = (high - low).saturating_add(1);
+        low + u32::from_le_bytes(bytes) % range
+    }
+}
+
+/// Statistics of the connection attempts of a session, so an application can tell how often and
+/// why it is reconnecting.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RetryStatistics {
+    /// The number of connection attempts, including retries
+    pub connect_attempts: u64,
+    /// The number of attempts that succeeded
+    pub successful_connects: u64,
+    /// The number of attempts that failed
+    pub failed_connects: u64,
+    /// The number of attempts that were retries after a failure
+    pub retries: u64,
+    /// The reason the last failed attempt failed
+    pub last_error: Option<StatusCode>,
+    /// The time of the last attempt
+    pub last_attempt: Option<DateTime>,
+    /// The time of the last attempt that succeeded
+    pub last_connected: Option<DateTime>,
+}
+
 /// The session retry policy determines what to if the connection fails. In these circumstances,
 /// the client needs to re-establish a connection and the policy says how many times to try between
 /// failure and at what interval.
@@ -24,6 +130,11 @@ pub enum Answer {
 /// a `retry_interval` for the period of time in MS between each retry. Note that the policy retains
 /// its own minimum retry interval and will not retry any faster than that.
 ///
+/// By default the policy waits the retry interval between retries. A `RetryStrategy` can back off
+/// exponentially, add jitter, or decide by the error of each failure. The policy gives up
+/// immediately on an error set with `with_give_up_on`, e.g. `CONFIGURATION_ERRORS`, which
+/// retrying cannot fix.
+///
 /// Once a connection succeeds, the retry limit is reset.
 #[derive(Debug, PartialEq, Clone)]
 pub struct SessionRetryPolicy {
@@ -35,11 +146,21 @@ pub struct SessionRetryPolicy {
     retry_limit: Option<u32>,
     /// Interval between retries in milliseconds
     retry_interval: u32,
+    /// How long to wait after each failure
+    retry_strategy: RetryStrategy,
+    /// Errors that the policy gives up on without retrying
+    give_up_on: Vec<StatusCode>,
     /// The number of failed attempts so far since the last connection. When the connection succeeds
     /// this value is reset.
     retry_count: u32,
+    /// The interval in milliseconds to wait after the last attempt, decided after it failed
+    next_interval: u32,
+    /// Set when the strategy or an error decided to give up
+    give_up: bool,
     /// The last retry attempt timestamp.
     last_attempt: DateTime,
+    /// Statistics of the connection attempts
+    statistics: RetryStatistics,
 }
 
 impl Default for SessionRetryPolicy {
@@ -61,6 +182,21 @@ impl SessionRetryPolicy {
     pub const MIN_RETRY_INTERVAL_MS: u32 = 500;
     /// The default session timeout interval in millis
     pub const DEFAULT_SESSION_TIMEOUT_MS: f64 = std::f64::MAX;
+    /// Errors caused by the identity or certificates of the client or server, which retrying
+    /// will not fix
+    pub const CONFIGURATION_ERRORS: &'static [StatusCode] = &[
+        StatusCode::BadIdentityTokenInvalid,
+        StatusCode::BadIdentityTokenRejected,
+        StatusCode::BadUserAccessDenied,
+        StatusCode::BadCertificateInvalid,
+        StatusCode::BadCertificateUntrusted,
+        StatusCode::BadCertificateTimeInvalid,
+        StatusCode::BadCertificateHostNameInvalid,
+        StatusCode::BadCertificateUriInvalid,
+        StatusCode::BadCertificateUseNotAllowed,
+        StatusCode::BadCertificateRevoked,
+        StatusCode::BadSecurityPolicyRejected,
+    ];
 
     /// Create a `SessionRetryPolicy` with a limit and interval
     pub fn new(session_timeout: f64, retry_limit: u32, retry_interval: u32) -> Self {
@@ -77,9 +213,14 @@ impl SessionRetryPolicy {
         SessionRetryPolicy {
             session_timeout,
             retry_count: 0,
+            next_interval: retry_interval,
+            give_up: false,
             last_attempt: Self::last_attempt_default(),
             retry_limit: Some(retry_limit),
             retry_interval,
+            retry_strategy: RetryStrategy::Fixed,
+            give_up_on: Vec::new(),
+            statistics: RetryStatistics::default(),
         }
     }
 
@@ -98,9 +239,14 @@ impl SessionRetryPolicy {
         SessionRetryPolicy {
             session_timeout,
             retry_count: 0,
+            next_interval: retry_interval,
+            give_up: false,
             last_attempt: Self::last_attempt_default(),
             retry_limit: None,
             retry_interval,
+            retry_strategy: RetryStrategy::Fixed,
+            give_up_on: Vec::new(),
+            statistics: RetryStatistics::default(),
         }
     }
 
@@ -109,6 +255,18 @@ impl SessionRetryPolicy {
         Self::new(session_timeout, 0, 0)
     }
 
+    /// Sets the strategy that decides how long to wait after each failed connection attempt
+    pub fn with_retry_strategy(mut self, retry_strategy: RetryStrategy) -> Self {
+        self.retry_strategy = retry_strategy;
+        self
+    }
+
+    /// Sets the errors that the policy gives up on without retrying, e.g. `CONFIGURATION_ERRORS`
+    pub fn with_give_up_on(mut self, status_codes: &[StatusCode]) -> Self {
+        self.give_up_on = status_codes.to_vec();
+        self
+    }
+
     fn last_attempt_default() -> DateTime {
         DateTime::ymd(1900, 1, 1)
     }
@@ -117,6 +275,10 @@ impl SessionRetryPolicy {
         self.session_timeout
     }
 
+    pub fn retry_strategy(&self) -> &RetryStrategy {
+        &self.retry_strategy
+    }
+
     pub fn retry_count(&self) -> u32 {
         self.retry_count
     }
@@ -127,15 +289,74 @@ impl SessionRetryPolicy {
 
     pub fn reset_retry_count(&mut self) {
         self.retry_count = 0;
+        self.next_interval = self.retry_interval;
+        self.give_up = false;
     }
 
     pub fn set_last_attempt(&mut self, last_attempt: DateTime) {
         self.last_attempt = last_attempt;
     }
 
+    /// Returns the statistics of the connection attempts
+    pub fn statistics(&self) -> &RetryStatistics {
+        &self.statistics
+    }
+
+    /// Records that a connection attempt is starting
+    pub fn record_attempt(&mut self, now: DateTime) {
+        self.set_last_attempt(now);
+        self.statistics.connect_attempts += 1;
+        if self.retry_count > 0 {
+            self.statistics.retries += 1;
+        }
+        self.statistics.last_attempt = Some(now);
+    }
+
+    /// Records that a connection attempt succeeded, which resets the retry count
+    pub fn record_success(&mut self, now: DateTime) {
+        self.reset_retry_count();
+        self.statistics.successful_connects += 1;
+        self.statistics.last_connected = Some(now);
+    }
+
+    /// Records that a connection attempt failed, and decides from the error and the strategy
+    /// how long to wait before the next attempt, or whether to give up.
+    pub fn record_failure(&mut self, status_code: StatusCode) {
+        self.increment_retry_count();
+        self.statistics.failed_connects += 1;
+        self.statistics.last_error = Some(status_code);
+
+        let decision = if self.give_up_on.contains(&status_code.status()) {
+            RetryDecision::GiveUp
+        } else {
+            self.retry_strategy.decide(&RetryContext {
+                retry_count: self.retry_count,
+                status_code,
+                previous_interval: self.next_interval,
+                retry_interval: self.retry_interval,
+            })
+        };
+        match decision {
+            RetryDecision::RetryAfter(interval) => {
+                self.next_interval = interval.max(Self::MIN_RETRY_INTERVAL_MS);
+            }
+            RetryDecision::GiveUp => {
+                info!(
+                    "Retry policy gives up after the connection failed with {}",
+                    status_code
+                );
+                self.give_up = true;
+            }
+        }
+    }
+
     /// Asks the policy, given the last retry attempt, should we try to connect again, wait a period of time
     /// or give up entirely.
     pub fn should_retry_connect(&self, now: DateTime) -> Answer {
+        if self.give_up {
+            return Answer::GiveUp;
+        }
+
         if let Some(retry_limit) = self.retry_limit {
             if self.retry_count >= retry_limit {
                 // Number of retries have been exceeded
@@ -150,7 +371,7 @@ impl SessionRetryPolicy {
 
         // Look at how much time has elapsed since the last attempt
         let elapsed = now - self.last_attempt;
-        let retry_interval = Duration::milliseconds(self.retry_interval as i64);
+        let retry_interval = Duration::milliseconds(self.next_interval as i64);
         if retry_interval > elapsed {
             // Wait a bit
             Answer::WaitFor((retry_interval - elapsed).num_milliseconds() as u32)
@@ -207,3 +428,118 @@ fn session_retry_never() {
     assert_eq!(session_retry.should_retry_connect(now), Answer::GiveUp);
     assert_eq!(session_retry.session_timeout(), 987.123);
 }
+
+#[test]
+fn session_retry_exponential() {
+    let mut session_retry =
+        SessionRetryPolicy::new(0.0, 10, 1000).with_retry_strategy(RetryStrategy::Exponential {
+            multiplier: 2.0,
+            max_interval: 5000,
+        });
+    let now = DateTime::now();
+    for expected in [1000, 2000, 4000, 5000, 5000] {
+        session_retry.record_attempt(now);
+        session_retry.record_failure(StatusCode::BadConnectionClosed);
+        assert_eq!(
+            session_retry.should_retry_connect(now),
+            Answer::WaitFor(expected)
+        );
+    }
+
+    // Success resets the interval
+    session_retry.record_success(now);
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadConnectionClosed);
+    assert_eq!(
+        session_retry.should_retry_connect(now),
+        Answer::WaitFor(1000)
+    );
+}
+
+#[test]
+fn session_retry_jitter() {
+    let mut session_retry = SessionRetryPolicy::infinity(0.0, 1000).with_retry_strategy(
+        RetryStrategy::DecorrelatedJitter {
+            max_interval: 20000,
+        },
+    );
+    let now = DateTime::now();
+    let mut previous_interval = 1000;
+    for _ in 0..20 {
+        session_retry.record_attempt(now);
+        session_retry.record_failure(StatusCode::BadConnectionClosed);
+        match session_retry.should_retry_connect(now) {
+            Answer::WaitFor(interval) => {
+                assert!(interval >= 1000);
+                assert!(interval <= 20000);
+                assert!(interval <= previous_interval * 3);
+                previous_interval = interval;
+            }
+            answer => panic!("Unexpected answer {:?}", answer),
+        }
+    }
+}
+
+#[test]
+fn session_retry_give_up_on() {
+    let mut session_retry = SessionRetryPolicy::infinity(0.0, 1000)
+        .with_give_up_on(SessionRetryPolicy::CONFIGURATION_ERRORS);
+    let now = DateTime::now();
+
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadConnectionClosed);
+    assert_eq!(
+        session_retry.should_retry_connect(now),
+        Answer::WaitFor(1000)
+    );
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadIdentityTokenRejected);
+    assert_eq!(session_retry.should_retry_connect(now), Answer::GiveUp);
+
+    // Connecting resets the decision
+    session_retry.record_success(now);
+    assert_eq!(
+        session_retry.should_retry_connect(now),
+        Answer::WaitFor(1000)
+    );
+
+    let statistics = session_retry.statistics();
+    assert_eq!(statistics.connect_attempts, 2);
+    assert_eq!(statistics.retries, 1);
+    assert_eq!(statistics.failed_connects, 2);
+    assert_eq!(statistics.successful_connects, 1);
+    assert_eq!(
+        statistics.last_error,
+        Some(StatusCode::BadIdentityTokenRejected)
+    );
+    assert_eq!(statistics.last_connected, Some(now));
+}
+
+#[test]
+fn session_retry_custom() {
+    let mut session_retry = SessionRetryPolicy::infinity(0.0, 1000).with_retry_strategy(
+        RetryStrategy::custom(|context| match context.status_code {
+            StatusCode::BadTooManySessions => RetryDecision::RetryAfter(60000),
+            StatusCode::BadServerHalted => RetryDecision::GiveUp,
+            _ => RetryDecision::RetryAfter(context.retry_count * 100),
+        }),
+    );
+    let now = DateTime::now();
+
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadTooManySessions);
+    assert_eq!(
+        session_retry.should_retry_connect(now),
+        Answer::WaitFor(60000)
+    );
+    // The interval is never less than the minimum
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadTimeout);
+    assert_eq!(
+        session_retry.should_retry_connect(now),
+        Answer::WaitFor(SessionRetryPolicy::MIN_RETRY_INTERVAL_MS)
+    );
+    session_retry.record_attempt(now);
+    session_retry.record_failure(StatusCode::BadServerHalted);
+    assert_eq!(session_retry.should_retry_connect(now), Answer::GiveUp);
+}
@@ -20,7 +20,7 @@ use std::{
 
 use crate::sync::*;
 use crate::types::{
-    service_types::{DataChangeNotification, ReadValueId},
+    service_types::{DataChangeNotification, ReadValueId, StatusChangeNotification},
     *,
 };
 
@@ -347,6 +347,18 @@ impl Subscription {
         });
     }
 
+    pub(crate) fn on_status_change(&mut self, status_changes: &[StatusChangeNotification]) {
+        let mut cb = trace_lock!(self.notification_callback);
+        status_changes.iter().for_each(|status_change| {
+            cb.on_status_change(status_change);
+        });
+    }
+
+    /// Tests if the notification callback no longer wants notifications
+    pub(crate) fn is_closed(&self) -> bool {
+        trace_lock!(self.notification_callback).is_closed()
+    }
+
     pub(crate) fn on_data_change(&mut self, data_change_notifications: &[DataChangeNotification]) {
         let mut monitored_item_ids = HashSet::new();
         data_change_notifications.iter().for_each(|n| {
@@ -6,7 +6,10 @@ use std::collections::HashMap;
 
 use tokio::time::Instant;
 
-use crate::types::service_types::{DataChangeNotification, EventNotificationList};
+use crate::types::{
+    service_types::{DataChangeNotification, EventNotificationList, StatusChangeNotification},
+    status_code::StatusCode,
+};
 
 use super::subscription::*;
 
@@ -104,6 +107,36 @@ impl SubscriptionState {
         }
     }
 
+    pub(crate) fn on_status_change(
+        &mut self,
+        subscription_id: u32,
+        status_changes: &[StatusChangeNotification],
+    ) {
+        if let Some(ref mut subscription) = self.subscriptions.get_mut(&subscription_id) {
+            subscription.on_status_change(status_changes);
+        }
+        // A timeout means the server has deleted the subscription
+        if status_changes
+            .iter()
+            .any(|n| n.status == StatusCode::BadTimeout)
+        {
+            info!(
+                "Subscription {} has timed out on the server and is removed",
+                subscription_id
+            );
+            let _ = self.delete_subscription(subscription_id);
+        }
+    }
+
+    /// Returns the ids of subscriptions whose notification callback is closed
+    pub(crate) fn closed_subscription_ids(&self) -> Vec<u32> {
+        self.subscriptions
+            .values()
+            .filter(|s| s.is_closed())
+            .map(|s| s.subscription_id())
+            .collect()
+    }
+
     pub(crate) fn insert_monitored_items(
         &mut self,
         subscription_id: u32,
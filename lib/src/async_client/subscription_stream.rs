@@ -0,0 +1,211 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides a `Stream` of subscription notifications as an alternative to a notification callback.
+//!
+//! The notifications are held in a bounded buffer until the stream is polled. The session
+//! receives notifications inside its poll loop and cannot wait for a slow consumer, so when the
+//! buffer is full the oldest notification is discarded and the number of discarded notifications
+//! is reported by the stream as a `SubscriptionNotification::Lagged`.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use futures::Stream;
+
+use crate::sync::*;
+use crate::types::{
+    service_types::{EventFieldList, EventNotificationList, StatusChangeNotification},
+    *,
+};
+
+use super::{callbacks::OnSubscriptionNotification, subscription::MonitoredItem};
+
+/// A notification received by a subscription
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionNotification {
+    /// A new value of a monitored item
+    DataChange {
+        /// The client handle of the monitored item
+        client_handle: u32,
+        /// The node that the monitored item monitors
+        node_id: NodeId,
+        /// The value
+        value: DataValue,
+    },
+    /// An event of a monitored item. The client handle of the monitored item is in the list.
+    Event(EventFieldList),
+    /// A change to the status of the subscription. A status of `BadTimeout` means that the server
+    /// has deleted the subscription because its lifetime expired.
+    StatusChange(StatusChangeNotification),
+    /// The consumer of the stream fell behind and this many of the oldest notifications were
+    /// discarded.
+    Lagged(u64),
+}
+
+/// Notifications waiting to be taken by the stream
+struct NotificationBuffer {
+    /// The notifications, oldest first
+    notifications: VecDeque<SubscriptionNotification>,
+    /// The maximum number of notifications held before the oldest is discarded
+    capacity: usize,
+    /// The number of notifications discarded since the stream last reported a lag
+    lagged: u64,
+    /// Wakes the task waiting on the stream
+    waker: Option<Waker>,
+    /// Set when the stream has been dropped
+    stream_dropped: bool,
+    /// Set when the subscription has gone and there will be no more notifications
+    subscription_dropped: bool,
+}
+
+impl NotificationBuffer {
+    fn push(&mut self, notification: SubscriptionNotification) {
+        if self.stream_dropped {
+            // Nothing will take the notification
+            return;
+        }
+        if self.notifications.len() == self.capacity {
+            let _ = self.notifications.pop_front();
+            self.lagged += 1;
+        }
+        self.notifications.push_back(notification);
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The notification callback of a subscription created for a stream, which fills the buffer of
+/// the stream.
+pub(crate) struct StreamNotificationCallback {
+    buffer: Arc<Mutex<NotificationBuffer>>,
+}
+
+impl OnSubscriptionNotification for StreamNotificationCallback {
+    fn on_data_change(&mut self, data_change_items: &[&MonitoredItem]) {
+        let mut buffer = trace_lock!(self.buffer);
+        data_change_items.iter().for_each(|item| {
+            item.values().iter().for_each(|value| {
+                buffer.push(SubscriptionNotification::DataChange {
+                    client_handle: item.client_handle(),
+                    node_id: item.item_to_monitor().node_id.clone(),
+                    value: value.clone(),
+                });
+            });
+        });
+        buffer.wake();
+    }
+
+    fn on_event(&mut self, events: &EventNotificationList) {
+        if let Some(ref events) = events.events {
+            let mut buffer = trace_lock!(self.buffer);
+            events.iter().for_each(|event| {
+                buffer.push(SubscriptionNotification::Event(event.clone()));
+            });
+            buffer.wake();
+        }
+    }
+
+    fn on_status_change(&mut self, status_change: &StatusChangeNotification) {
+        let mut buffer = trace_lock!(self.buffer);
+        buffer.push(SubscriptionNotification::StatusChange(
+            status_change.clone(),
+        ));
+        buffer.wake();
+    }
+
+    fn is_closed(&self) -> bool {
+        trace_lock!(self.buffer).stream_dropped
+    }
+}
+
+impl Drop for StreamNotificationCallback {
+    fn drop(&mut self) {
+        // The subscription has been deleted, so the stream ends once it is empty
+        let mut buffer = trace_lock!(self.buffer);
+        buffer.subscription_dropped = true;
+        buffer.wake();
+    }
+}
+
+/// A stream of the notifications of a subscription, created by
+/// `SubscriptionService::create_subscription_stream`. Notifications are buffered up to the size
+/// that the stream was created with, and when they are not taken quickly enough the oldest are
+/// discarded and reported as a `SubscriptionNotification::Lagged`.
+///
+/// The stream ends when the subscription is deleted. Dropping the stream deletes the
+/// subscription the next time the session is polled.
+pub struct SubscriptionStream {
+    /// The id of the subscription
+    subscription_id: u32,
+    /// The buffer shared with the notification callback
+    buffer: Arc<Mutex<NotificationBuffer>>,
+}
+
+impl SubscriptionStream {
+    /// Creates a stream and the notification callback that fills it. The subscription id is set
+    /// once the subscription has been created.
+    pub(crate) fn new(buffer_size: usize) -> (SubscriptionStream, StreamNotificationCallback) {
+        let buffer = Arc::new(Mutex::new(NotificationBuffer {
+            notifications: VecDeque::with_capacity(buffer_size),
+            capacity: buffer_size,
+            lagged: 0,
+            waker: None,
+            stream_dropped: false,
+            subscription_dropped: false,
+        }));
+        let stream = SubscriptionStream {
+            subscription_id: 0,
+            buffer: buffer.clone(),
+        };
+        (stream, StreamNotificationCallback { buffer })
+    }
+
+    pub(crate) fn set_subscription_id(&mut self, subscription_id: u32) {
+        self.subscription_id = subscription_id;
+    }
+
+    /// Returns the id of the subscription, for creating its monitored items. Note that the id
+    /// changes if the subscription has to be recreated after a reconnect.
+    pub fn subscription_id(&self) -> u32 {
+        self.subscription_id
+    }
+}
+
+impl Stream for SubscriptionStream {
+    type Item = SubscriptionNotification;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut buffer = trace_lock!(self.buffer);
+        if buffer.lagged > 0 {
+            let lagged = buffer.lagged;
+            buffer.lagged = 0;
+            Poll::Ready(Some(SubscriptionNotification::Lagged(lagged)))
+        } else if let Some(notification) = buffer.notifications.pop_front() {
+            Poll::Ready(Some(notification))
+        } else if buffer.subscription_dropped {
+            Poll::Ready(None)
+        } else {
+            buffer.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for SubscriptionStream {
+    fn drop(&mut self) {
+        let mut buffer = trace_lock!(self.buffer);
+        buffer.stream_dropped = true;
+        buffer.notifications.clear();
+        buffer.waker = None;
+    }
+}
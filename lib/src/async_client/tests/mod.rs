@@ -1,12 +1,38 @@
-use std::{self, collections::BTreeMap, path::PathBuf};
+use std::{
+    self,
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use futures::{FutureExt, StreamExt};
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
 
-use crate::core::config::Config;
+use crate::core::{config::Config, supported_message::SupportedMessage};
 use crate::crypto::SecurityPolicy;
+use crate::sync::*;
 use crate::types::*;
 
 use crate::async_client::{
     builder::ClientBuilder,
     config::{ClientConfig, ClientEndpoint, ClientUserToken, ANONYMOUS_USER_TOKEN_ID},
+    message_queue::MessageQueue,
+    redundant_session::{self, FailoverMode, FailoverOptions, RedundantServer},
+    session::{
+        browse::{self, BrowseTree, BrowsedReference},
+        history::HistoryPages,
+        namespaces::NamespaceTable,
+        operation_limits::{self, OperationLimits},
+        services::{Service, ViewService},
+        session::Session,
+        values::MethodArguments,
+    },
+    subscription::{CreateMonitoredItem, Subscription},
+    subscription_state::SubscriptionState,
+    subscription_stream::{SubscriptionNotification, SubscriptionStream},
 };
 
 fn make_test_file(filename: &str) -> PathBuf {
@@ -155,7 +181,1417 @@ fn client_anonymous_user_tokens_id() {
             password: Some(String::new()),
             cert_path: None,
             private_key_path: None,
+            issued_token: None,
         },
     );
     assert!(!config.is_valid());
 }
+
+#[test]
+fn client_issued_token_user_token() {
+    let mut config = default_sample_config();
+    config.user_tokens = BTreeMap::new();
+    config.user_tokens.insert(
+        String::from("sso"),
+        ClientUserToken::issued_token("sso", "eyJhbGciOiJSUzI1NiJ9.e30.c2ln"),
+    );
+    assert!(config.is_valid());
+
+    // A token cannot hold a password and an issued token
+    let mut user_token = ClientUserToken::issued_token("sso", "eyJhbGciOiJSUzI1NiJ9.e30.c2ln");
+    user_token.password = Some(String::from("pwd"));
+    config.user_tokens.insert(String::from("sso"), user_token);
+    assert!(!config.is_valid());
+}
+
+#[test]
+fn cancelled_request_response_is_dropped() {
+    let mut message_queue = MessageQueue::new();
+    let (_tx, _rx) = message_queue.make_request_channel();
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+    let request = ReadRequest {
+        request_header: RequestHeader::new(&NodeId::null(), &DateTime::now(), 7),
+        max_age: 0f64,
+        timestamps_to_return: TimestampsToReturn::Neither,
+        nodes_to_read: None,
+    };
+    message_queue.add_request(request.into(), Some(sender));
+    message_queue.request_was_cancelled(7);
+
+    let response = ServiceFault::new(
+        &RequestHeader::new(&NodeId::null(), &DateTime::now(), 7),
+        StatusCode::BadRequestCancelledByClient,
+    );
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(message_queue.store_response(response.into()));
+
+    // Nothing waits for the response so it is dropped rather than handled as an async response
+    assert!(receiver.try_recv().is_err());
+    assert!(message_queue.async_responses().is_empty());
+}
+
+#[test]
+fn cancellable_service_call() {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let token = CancellationToken::new();
+        let result = Session::cancellable(&token, async { Ok(1) }).await;
+        assert_eq!(result, Ok(1));
+
+        let service_call = async {
+            tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            Ok(2)
+        };
+        token.cancel();
+        let result = Session::cancellable(&token, service_call).await;
+        assert_eq!(result, Err(StatusCode::BadRequestCancelledByClient));
+    });
+}
+
+fn make_stream_subscription(buffer_size: usize) -> (SubscriptionState, SubscriptionStream) {
+    let (mut stream, callback) = SubscriptionStream::new(buffer_size);
+    stream.set_subscription_id(1);
+    let mut subscription_state = SubscriptionState::new();
+    subscription_state.add_subscription(Subscription::new(
+        1,
+        100f64,
+        30,
+        10,
+        0,
+        true,
+        0,
+        Arc::new(Mutex::new(callback)),
+    ));
+    subscription_state.insert_monitored_items(
+        1,
+        &[CreateMonitoredItem {
+            id: 5,
+            client_handle: 100,
+            item_to_monitor: NodeId::new(2, "v1").into(),
+            monitoring_mode: MonitoringMode::Reporting,
+            queue_size: 1,
+            discard_oldest: true,
+            sampling_interval: 100f64,
+        }],
+    );
+    (subscription_state, stream)
+}
+
+fn make_data_changes(values: &[i32]) -> DataChangeNotification {
+    DataChangeNotification {
+        monitored_items: Some(
+            values
+                .iter()
+                .map(|v| MonitoredItemNotification {
+                    client_handle: 100,
+                    value: DataValue::new_now(*v),
+                })
+                .collect(),
+        ),
+        diagnostic_infos: None,
+    }
+}
+
+fn next_value(stream: &mut SubscriptionStream) -> Option<Variant> {
+    match stream.next().now_or_never() {
+        Some(Some(SubscriptionNotification::DataChange {
+            client_handle,
+            node_id,
+            value,
+        })) => {
+            assert_eq!(client_handle, 100);
+            assert_eq!(node_id, NodeId::new(2, "v1"));
+            value.value
+        }
+        notification => panic!("Unexpected notification {:?}", notification),
+    }
+}
+
+#[test]
+fn subscription_stream_notifications() {
+    let (mut subscription_state, mut stream) = make_stream_subscription(10);
+    assert_eq!(stream.subscription_id(), 1);
+    assert!(stream.next().now_or_never().is_none());
+
+    subscription_state.on_data_change(1, &[make_data_changes(&[1, 2])]);
+    assert_eq!(next_value(&mut stream), Some(Variant::Int32(1)));
+    assert_eq!(next_value(&mut stream), Some(Variant::Int32(2)));
+    assert!(stream.next().now_or_never().is_none());
+
+    let event = EventFieldList {
+        client_handle: 100,
+        event_fields: Some(vec![Variant::from("event")]),
+    };
+    subscription_state.on_event(
+        1,
+        &[EventNotificationList {
+            events: Some(vec![event.clone()]),
+        }],
+    );
+    assert_eq!(
+        stream.next().now_or_never(),
+        Some(Some(SubscriptionNotification::Event(event)))
+    );
+
+    // A timeout is reported and then the stream ends because the subscription has gone
+    let status_change = StatusChangeNotification {
+        status: StatusCode::BadTimeout,
+        diagnostic_info: DiagnosticInfo::null(),
+    };
+    subscription_state.on_status_change(1, std::slice::from_ref(&status_change));
+    assert!(!subscription_state.subscription_exists(1));
+    assert_eq!(
+        stream.next().now_or_never(),
+        Some(Some(SubscriptionNotification::StatusChange(status_change)))
+    );
+    assert_eq!(stream.next().now_or_never(), Some(None));
+}
+
+#[test]
+fn subscription_stream_lagged() {
+    let (mut subscription_state, mut stream) = make_stream_subscription(2);
+    subscription_state.on_data_change(1, &[make_data_changes(&[1, 2, 3, 4, 5])]);
+
+    // The oldest values are discarded and reported before the rest
+    assert_eq!(
+        stream.next().now_or_never(),
+        Some(Some(SubscriptionNotification::Lagged(3)))
+    );
+    assert_eq!(next_value(&mut stream), Some(Variant::Int32(4)));
+    assert_eq!(next_value(&mut stream), Some(Variant::Int32(5)));
+    assert!(stream.next().now_or_never().is_none());
+}
+
+#[test]
+fn subscription_stream_dropped() {
+    let (subscription_state, stream) = make_stream_subscription(2);
+    assert!(subscription_state.closed_subscription_ids().is_empty());
+    drop(stream);
+    assert_eq!(subscription_state.closed_subscription_ids(), vec![1]);
+}
+
+#[test]
+fn operation_limits_from_values() {
+    let values = vec![
+        DataValue::new_now(100u32),
+        DataValue::new_now(50u32),
+        DataValue::null(),
+        DataValue::new_now("wrong type"),
+    ];
+    let limits = OperationLimits::from_values(&values);
+    assert_eq!(
+        limits,
+        OperationLimits {
+            max_nodes_per_read: 100,
+            max_nodes_per_write: 50,
+            max_nodes_per_browse: 0,
+            max_nodes_per_register_nodes: 0,
+            max_monitored_items_per_call: 0,
+        }
+    );
+}
+
+#[test]
+fn operation_limit_batches() {
+    let nodes = (0..10)
+        .map(|i| ReadValueId::from(NodeId::new(2, i)))
+        .collect::<Vec<_>>();
+
+    // No limits
+    let batches = operation_limits::batches(&nodes, 0, 0);
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].len(), 10);
+
+    // Limited by the number of items, with the remainder in the last batch
+    let batches = operation_limits::batches(&nodes, 3, 0);
+    assert_eq!(
+        batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+        vec![3, 3, 3, 1]
+    );
+    assert_eq!(batches.concat(), nodes);
+
+    // Limited by the size of the request, after the overhead
+    let item_size = nodes[0].byte_len();
+    let batches = operation_limits::batches(&nodes, 0, 4096 + item_size * 4);
+    assert_eq!(
+        batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+        vec![4, 4, 2]
+    );
+    let batches = operation_limits::batches(&nodes, 3, 4096 + item_size * 4);
+    assert_eq!(
+        batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+        vec![3, 3, 3, 1]
+    );
+
+    // An item too large for a request still gets a batch of its own
+    let batches = operation_limits::batches(&nodes, 0, 1);
+    assert_eq!(batches.len(), 10);
+}
+
+#[test]
+fn operation_limit_send_batches_failure() {
+    let items = (0..8).collect::<Vec<u32>>();
+    let batches = items.chunks(2).collect::<Vec<_>>();
+    let sent = Mutex::new(Vec::new());
+
+    // The second batch fails while the third is in flight. The third batch completes but the
+    // fourth is never sent.
+    let result = futures::executor::block_on(operation_limits::send_batches(
+        batches.clone(),
+        2,
+        |batch: &[u32]| {
+            trace_lock!(sent).push(batch[0]);
+            let result = if batch[0] == 2 {
+                Err(StatusCode::BadTooManyOperations)
+            } else {
+                Ok(batch.to_vec())
+            };
+            async move { result }
+        },
+    ));
+    assert_eq!(result, Err(StatusCode::BadTooManyOperations));
+    assert_eq!(*trace_lock!(sent), vec![0, 2, 4]);
+
+    // Results are merged in order when every batch succeeds
+    let result = futures::executor::block_on(operation_limits::send_batches(
+        batches,
+        3,
+        |batch: &[u32]| {
+            let result = Ok(batch.to_vec());
+            async move { result }
+        },
+    ));
+    assert_eq!(result, Ok(items));
+}
+
+fn make_browsed_reference(
+    source: u32,
+    target: u32,
+    depth: usize,
+    is_first_visit: bool,
+) -> BrowsedReference {
+    let name = format!("Node{}", target);
+    BrowsedReference {
+        source_node_id: NodeId::new(2, source),
+        depth,
+        reference: ReferenceDescription {
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            is_forward: true,
+            node_id: NodeId::new(2, target).into(),
+            browse_name: QualifiedName::new(2, name.as_str()),
+            display_name: LocalizedText::new("", name.as_str()),
+            node_class: NodeClass::Object,
+            type_definition: ExpandedNodeId::null(),
+        },
+        is_first_visit,
+    }
+}
+
+#[test]
+fn browse_tree() {
+    // 1 -> 2 -> 3 -> 1 is a cycle, and 1 -> 4 -> 3 is a second reference to 3
+    let references = vec![
+        make_browsed_reference(1, 2, 1, true),
+        make_browsed_reference(1, 4, 1, true),
+        make_browsed_reference(2, 3, 2, true),
+        make_browsed_reference(4, 3, 2, false),
+        make_browsed_reference(3, 1, 3, false),
+    ];
+    let tree = BrowseTree::new(&NodeId::new(2, 1), references);
+    assert_eq!(tree.node_id, NodeId::new(2, 1));
+    assert_eq!(tree.children.len(), 2);
+
+    let node2 = &tree.children[0];
+    assert_eq!(node2.node_id, NodeId::new(2, 2).into());
+    assert!(!node2.is_repeated);
+    assert_eq!(node2.children.len(), 1);
+    let node3 = &node2.children[0];
+    assert!(!node3.is_repeated);
+    assert_eq!(node3.children.len(), 1);
+    assert_eq!(node3.children[0].node_id, NodeId::new(2, 1).into());
+    assert!(node3.children[0].is_repeated);
+    assert!(node3.children[0].children.is_empty());
+
+    let node4 = &tree.children[1];
+    assert_eq!(node4.children.len(), 1);
+    assert_eq!(node4.children[0].node_id, NodeId::new(2, 3).into());
+    assert!(node4.children[0].is_repeated);
+    assert!(node4.children[0].children.is_empty());
+
+    // The tree can be saved as JSON
+    let json = serde_json::to_value(&tree).unwrap();
+    assert_eq!(json["children"][0]["node_class"], "Object");
+    assert_eq!(json["children"][0]["children"][0]["is_repeated"], false);
+    assert_eq!(json["children"].as_array().unwrap().len(), 2);
+}
+
+/// A view service which returns the references of each node a page at a time. It hands out a
+/// limited number of continuation points and can fail a call to browse next part way through.
+#[derive(Default)]
+struct PagedViewService {
+    /// The number of references of each node
+    reference_counts: HashMap<NodeId, usize>,
+    page_size: usize,
+    max_continuation_points: usize,
+    /// The browse next call, counted from 1, which fails
+    fail_browse_next: Option<usize>,
+    state: Mutex<PagedViewState>,
+}
+
+#[derive(Default)]
+struct PagedViewState {
+    /// The node and offset of its next page for each outstanding continuation point
+    continuation_points: HashMap<ByteString, (NodeId, usize)>,
+    next_continuation_point: u32,
+    browse_next_calls: usize,
+    released: Vec<ByteString>,
+}
+
+impl PagedViewService {
+    fn new(reference_counts: &[(u32, usize)], page_size: usize) -> PagedViewService {
+        PagedViewService {
+            reference_counts: reference_counts
+                .iter()
+                .map(|(node, count)| (NodeId::new(2, *node), *count))
+                .collect(),
+            page_size,
+            max_continuation_points: usize::MAX,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the page of references of the node from the offset, with a continuation point if
+    /// there are more
+    fn page(&self, state: &mut PagedViewState, node_id: &NodeId, offset: usize) -> BrowseResult {
+        let count = self.reference_counts[node_id];
+        let end = count.min(offset + self.page_size);
+        let continuation_point = if end < count {
+            if state.continuation_points.len() >= self.max_continuation_points {
+                return BrowseResult {
+                    status_code: StatusCode::BadNoContinuationPoints,
+                    continuation_point: ByteString::null(),
+                    references: None,
+                };
+            }
+            state.next_continuation_point += 1;
+            let continuation_point =
+                ByteString::from(state.next_continuation_point.to_le_bytes().to_vec());
+            state
+                .continuation_points
+                .insert(continuation_point.clone(), (node_id.clone(), end));
+            continuation_point
+        } else {
+            ByteString::null()
+        };
+        let references = (offset..end)
+            .map(|i| {
+                let name = format!("{}-{}", node_id, i);
+                ReferenceDescription {
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    is_forward: true,
+                    node_id: NodeId::new(2, name.clone()).into(),
+                    browse_name: QualifiedName::new(2, name.as_str()),
+                    display_name: LocalizedText::new("", name.as_str()),
+                    node_class: NodeClass::Object,
+                    type_definition: ExpandedNodeId::null(),
+                }
+            })
+            .collect();
+        BrowseResult {
+            status_code: StatusCode::Good,
+            continuation_point,
+            references: Some(references),
+        }
+    }
+}
+
+#[async_trait]
+impl Service for PagedViewService {
+    fn make_request_header(&self) -> RequestHeader {
+        RequestHeader::dummy()
+    }
+
+    async fn send_request<T>(&self, _request: T) -> Result<SupportedMessage, StatusCode>
+    where
+        T: Into<SupportedMessage> + Clone + Send + std::fmt::Debug,
+    {
+        unimplemented!()
+    }
+
+    fn async_send_request<T>(
+        &self,
+        _request: T,
+        _sender: Option<Sender<SupportedMessage>>,
+    ) -> Result<u32, StatusCode>
+    where
+        T: Into<SupportedMessage> + Clone + Send + std::fmt::Debug,
+    {
+        unimplemented!()
+    }
+}
+
+#[async_trait]
+impl ViewService for PagedViewService {
+    async fn browse(
+        &self,
+        nodes_to_browse: &[BrowseDescription],
+        _maximum_nodes_per_read_limit: Option<u32>,
+    ) -> Result<Option<Vec<BrowseResult>>, StatusCode> {
+        let mut state = trace_lock!(self.state);
+        Ok(Some(
+            nodes_to_browse
+                .iter()
+                .map(|node_to_browse| self.page(&mut state, &node_to_browse.node_id, 0))
+                .collect(),
+        ))
+    }
+
+    async fn browse_next(
+        &self,
+        release_continuation_points: bool,
+        continuation_points: &[ByteString],
+    ) -> Result<Option<Vec<BrowseResult>>, StatusCode> {
+        let mut state = trace_lock!(self.state);
+        if release_continuation_points {
+            for continuation_point in continuation_points {
+                if state
+                    .continuation_points
+                    .remove(continuation_point)
+                    .is_some()
+                {
+                    state.released.push(continuation_point.clone());
+                }
+            }
+            return Ok(None);
+        }
+        state.browse_next_calls += 1;
+        if self.fail_browse_next == Some(state.browse_next_calls) {
+            return Err(StatusCode::BadCommunicationError);
+        }
+        Ok(Some(
+            continuation_points
+                .iter()
+                .map(|continuation_point| {
+                    match state.continuation_points.remove(continuation_point) {
+                        Some((node_id, offset)) => self.page(&mut state, &node_id, offset),
+                        None => BrowseResult {
+                            status_code: StatusCode::BadContinuationPointInvalid,
+                            continuation_point: ByteString::null(),
+                            references: None,
+                        },
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    async fn translate_browse_paths_to_node_ids(
+        &self,
+        _browse_paths: &[BrowsePath],
+    ) -> Result<Vec<BrowsePathResult>, StatusCode> {
+        unimplemented!()
+    }
+
+    async fn register_nodes(
+        &self,
+        _nodes_to_register: &[NodeId],
+    ) -> Result<Vec<NodeId>, StatusCode> {
+        unimplemented!()
+    }
+
+    async fn unregister_nodes(&self, _nodes_to_unregister: &[NodeId]) -> Result<(), StatusCode> {
+        unimplemented!()
+    }
+}
+
+fn make_browse_descriptions(nodes: &[u32]) -> Vec<BrowseDescription> {
+    nodes
+        .iter()
+        .map(|node| BrowseDescription {
+            node_id: NodeId::new(2, *node),
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        })
+        .collect()
+}
+
+#[test]
+fn browse_all_follows_continuation_points() {
+    // Node 1 fits in one page, node 2 takes three pages and node 3 has no references
+    let service = PagedViewService::new(&[(1, 2), (2, 7), (3, 0)], 3);
+    let results = futures::executor::block_on(browse::browse_all(
+        &service,
+        &make_browse_descriptions(&[1, 2, 3]),
+    ))
+    .unwrap();
+    let counts = results
+        .iter()
+        .map(|r| r.references.as_ref().map_or(0, |r| r.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![2, 7, 0]);
+    assert!(results
+        .iter()
+        .all(|r| r.status_code.is_good() && r.continuation_point.is_null_or_empty()));
+    let references = results[1].references.as_ref().unwrap();
+    assert_eq!(
+        references[0].browse_name,
+        QualifiedName::new(2, "ns=2;i=2-0")
+    );
+    assert_eq!(
+        references[6].browse_name,
+        QualifiedName::new(2, "ns=2;i=2-6")
+    );
+
+    let state = trace_lock!(service.state);
+    assert_eq!(state.browse_next_calls, 2);
+    assert!(state.continuation_points.is_empty());
+    assert!(state.released.is_empty());
+}
+
+#[test]
+fn browse_all_retries_nodes_without_continuation_points() {
+    // Only one continuation point is handed out at a time, so node 2 is browsed again after
+    // node 1 has been read to the end
+    let service = PagedViewService {
+        max_continuation_points: 1,
+        ..PagedViewService::new(&[(1, 5), (2, 4)], 2)
+    };
+    let results = futures::executor::block_on(browse::browse_all(
+        &service,
+        &make_browse_descriptions(&[1, 2]),
+    ))
+    .unwrap();
+    let counts = results
+        .iter()
+        .map(|r| r.references.as_ref().map_or(0, |r| r.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![5, 4]);
+    assert!(results.iter().all(|r| r.status_code.is_good()));
+    assert!(trace_lock!(service.state).continuation_points.is_empty());
+
+    // A node that never gets a continuation point is returned with the bad status
+    let service = PagedViewService {
+        max_continuation_points: 0,
+        ..PagedViewService::new(&[(1, 5), (2, 1)], 2)
+    };
+    let results = futures::executor::block_on(browse::browse_all(
+        &service,
+        &make_browse_descriptions(&[1, 2]),
+    ))
+    .unwrap();
+    assert_eq!(results[0].status_code, StatusCode::BadNoContinuationPoints);
+    assert_eq!(results[1].status_code, StatusCode::Good);
+    assert_eq!(results[1].references.as_ref().unwrap().len(), 1);
+}
+
+#[test]
+fn browse_all_releases_continuation_points_on_error() {
+    // The second browse next fails while both nodes still have continuation points
+    let service = PagedViewService {
+        fail_browse_next: Some(2),
+        ..PagedViewService::new(&[(1, 10), (2, 10)], 3)
+    };
+    let result = futures::executor::block_on(browse::browse_all(
+        &service,
+        &make_browse_descriptions(&[1, 2]),
+    ));
+    assert_eq!(result.unwrap_err(), StatusCode::BadCommunicationError);
+
+    let state = trace_lock!(service.state);
+    assert_eq!(state.browse_next_calls, 2);
+    assert_eq!(state.released.len(), 2);
+    assert!(state.continuation_points.is_empty());
+}
+
+fn make_namespace_table() -> NamespaceTable {
+    let uris = |uris: &[&str]| {
+        DataValue::from(Variant::from(
+            uris.iter().map(|uri| uri.to_string()).collect::<Vec<_>>(),
+        ))
+    };
+    NamespaceTable::from_values(&[
+        uris(&["http://opcfoundation.org/UA/", "urn:server", "urn:model"]),
+        uris(&["urn:server"]),
+    ])
+}
+
+#[test]
+fn namespace_table_from_values() {
+    let namespace_table = make_namespace_table();
+    assert_eq!(namespace_table.namespaces().len(), 3);
+    assert_eq!(namespace_table.namespace_index("urn:model"), Some(2));
+    assert_eq!(namespace_table.namespace_index("urn:missing"), None);
+    assert_eq!(namespace_table.namespace_uri(1), Some("urn:server"));
+    assert_eq!(namespace_table.namespace_uri(3), None);
+    assert_eq!(namespace_table.server_index("urn:server"), Some(0));
+    assert_eq!(namespace_table.server_uri(0), Some("urn:server"));
+
+    // Values the server does not supply make an empty table
+    let namespace_table = NamespaceTable::from_values(&[]);
+    assert!(namespace_table.namespaces().is_empty());
+    assert!(namespace_table.servers().is_empty());
+}
+
+#[test]
+fn namespace_table_node_ids() {
+    let namespace_table = make_namespace_table();
+    assert_eq!(
+        namespace_table.node_id("urn:model", "v1").unwrap(),
+        NodeId::new(2, "v1")
+    );
+    assert_eq!(
+        namespace_table.node_id("urn:missing", "v1").unwrap_err(),
+        StatusCode::BadNodeIdUnknown
+    );
+
+    // Expand and resolve round trip
+    let node_id = NodeId::new(2, 100);
+    let expanded = namespace_table.expand(&node_id);
+    assert_eq!(expanded.namespace_uri.as_ref(), "urn:model");
+    assert_eq!(expanded.node_id, NodeId::new(0, 100));
+    assert_eq!(namespace_table.resolve(&expanded).unwrap(), node_id);
+
+    // A namespace the table does not know keeps its index
+    let expanded = namespace_table.expand(&NodeId::new(5, 100));
+    assert!(expanded.namespace_uri.is_null());
+    assert_eq!(
+        namespace_table.resolve(&expanded).unwrap(),
+        NodeId::new(5, 100)
+    );
+
+    let expanded = ExpandedNodeId::from_str("svr=0;nsu=urn:missing;i=100").unwrap();
+    assert_eq!(
+        namespace_table.resolve(&expanded).unwrap_err(),
+        StatusCode::BadNodeIdUnknown
+    );
+    let expanded = ExpandedNodeId::from_str("svr=1;ns=2;i=100").unwrap();
+    assert_eq!(
+        namespace_table.resolve(&expanded).unwrap_err(),
+        StatusCode::BadNodeIdUnknown
+    );
+}
+
+#[test]
+fn namespace_table_values() {
+    let namespace_table = make_namespace_table();
+
+    let mut value = Variant::from(NodeId::new(1, "v1"));
+    namespace_table.expand_variant(&mut value);
+    let expanded = ExpandedNodeId::from_str("svr=0;nsu=urn:server;s=v1").unwrap();
+    assert_eq!(value, Variant::from(expanded.clone()));
+    namespace_table.resolve_variant(&mut value).unwrap();
+    assert_eq!(
+        value,
+        Variant::from(ExpandedNodeId::from(NodeId::new(1, "v1")))
+    );
+
+    let mut value = Variant::from((
+        VariantTypeId::NodeId,
+        vec![
+            Variant::from(NodeId::new(1, "v1")),
+            Variant::from(NodeId::new(2, "v2")),
+        ],
+    ));
+    namespace_table.expand_variant(&mut value);
+    if let Variant::Array(ref array) = value {
+        assert_eq!(array.value_type, VariantTypeId::ExpandedNodeId);
+        assert_eq!(array.values[0], Variant::from(expanded));
+    } else {
+        panic!("Expected an array");
+    }
+
+    let mut value = Variant::from(ExpandedNodeId::from_str("svr=0;nsu=urn:missing;i=100").unwrap());
+    assert_eq!(
+        namespace_table.resolve_variant(&mut value).unwrap_err(),
+        StatusCode::BadNodeIdUnknown
+    );
+
+    let mut result = BrowseResult {
+        status_code: StatusCode::Good,
+        continuation_point: ByteString::null(),
+        references: Some(vec![make_browsed_reference(1, 2, 1, true).reference]),
+    };
+    namespace_table.expand_browse_result(&mut result);
+    let reference = &result.references.unwrap()[0];
+    assert_eq!(reference.node_id.namespace_uri.as_ref(), "urn:model");
+    assert_eq!(reference.node_id.node_id, NodeId::new(0, 2));
+}
+
+fn make_argument(name: &str, data_type: DataTypeId, value_rank: i32) -> service_types::Argument {
+    service_types::Argument {
+        name: name.into(),
+        data_type: data_type.into(),
+        value_rank,
+        array_dimensions: None,
+        description: LocalizedText::null(),
+    }
+}
+
+#[test]
+fn method_arguments_convert() {
+    let method_arguments = MethodArguments {
+        input_arguments: vec![
+            make_argument("a", DataTypeId::Double, -1),
+            make_argument("b", DataTypeId::UInt32, 1),
+            make_argument("c", DataTypeId::Structure, -1),
+        ],
+        output_arguments: vec![make_argument("d", DataTypeId::Int64, -1)],
+    };
+
+    // Values are converted to the data types of the arguments
+    let converted = method_arguments
+        .convert_input_arguments(&[
+            Variant::from(1i32),
+            Variant::from(vec![1u16, 2u16]),
+            Variant::from("not built in"),
+        ])
+        .unwrap();
+    assert_eq!(converted[0], Variant::Double(1.0));
+    assert_eq!(converted[1], Variant::from(vec![1u32, 2u32]));
+    assert_eq!(converted[2], Variant::from("not built in"));
+
+    // The wrong number of arguments
+    assert_eq!(
+        method_arguments
+            .convert_input_arguments(&[Variant::from(1.0)])
+            .unwrap_err(),
+        StatusCode::BadArgumentsMissing
+    );
+    assert_eq!(
+        method_arguments
+            .convert_input_arguments(&vec![Variant::Empty; 4])
+            .unwrap_err(),
+        StatusCode::BadTooManyArguments
+    );
+
+    // A value which cannot be converted, or has the wrong shape
+    assert_eq!(
+        method_arguments
+            .convert_input_arguments(&[
+                Variant::from("text"),
+                Variant::from(vec![1u32]),
+                Variant::Empty
+            ])
+            .unwrap_err(),
+        StatusCode::BadTypeMismatch
+    );
+    assert_eq!(
+        method_arguments
+            .convert_input_arguments(&[Variant::from(1.0), Variant::from(1u32), Variant::Empty])
+            .unwrap_err(),
+        StatusCode::BadTypeMismatch
+    );
+
+    let outputs = method_arguments
+        .convert_output_arguments(&[Variant::from(5i32)])
+        .unwrap();
+    assert_eq!(i64::from_variant(&outputs[0]), Ok(5));
+    assert_eq!(
+        method_arguments.convert_output_arguments(&[]).unwrap_err(),
+        StatusCode::BadUnexpectedError
+    );
+}
+
+#[test]
+fn history_pages() {
+    let data_value = |v: i32| DataValue::new_now(v);
+
+    // A result without history data is an empty page
+    let mut history = service_types::HistoryData::decode_page(&ExtensionObject::null()).unwrap();
+    assert_eq!(history.len(), 0);
+
+    let page = service_types::HistoryData {
+        data_values: Some(vec![data_value(1), data_value(2)]),
+    };
+    let history_data = ExtensionObject::from_encodable(page.object_id(), &page);
+    history.append(service_types::HistoryData::decode_page(&history_data).unwrap());
+    history.append(service_types::HistoryData {
+        data_values: Some(vec![data_value(3)]),
+    });
+    history.append(service_types::HistoryData::empty());
+    assert_eq!(history.len(), 3);
+    history.truncate(2);
+    let values = history
+        .data_values
+        .unwrap()
+        .into_iter()
+        .map(|v| v.value.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(values, vec![Variant::from(1i32), Variant::from(2i32)]);
+
+    // History data of another type cannot be decoded
+    let history_data = ExtensionObject::from_encodable(
+        ObjectId::HistoryEvent_Encoding_DefaultBinary,
+        &service_types::HistoryEvent {
+            events: Some(vec![service_types::HistoryEventFieldList {
+                event_fields: Some(vec![Variant::from(1i32)]),
+            }]),
+        },
+    );
+    let events = service_types::HistoryEvent::decode_page(&history_data).unwrap();
+    assert_eq!(events.len(), 1);
+    assert!(service_types::HistoryData::decode_page(&history_data).is_err());
+}
+
+#[test]
+fn failover_mode_from_redundancy_support() {
+    use service_types::RedundancySupport;
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::None),
+        FailoverMode::Cold
+    );
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::Cold),
+        FailoverMode::Cold
+    );
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::Warm),
+        FailoverMode::Warm
+    );
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::Hot),
+        FailoverMode::Hot
+    );
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::HotAndMirrored),
+        FailoverMode::Hot
+    );
+    // Transparent redundancy needs no failover, but the client can still fail over to the
+    // servers that it was given
+    assert_eq!(
+        FailoverMode::from(RedundancySupport::Transparent),
+        FailoverMode::Cold
+    );
+
+    // By default the failover mode is that of the server set
+    let options = FailoverOptions::default();
+    assert_eq!(options.failover_mode, None);
+    assert_eq!(options.min_service_level, 200);
+}
+
+/// Makes the state of redundant servers from their service levels, where 0 is not connected
+fn make_redundant_servers(service_levels: &[u8], active: Option<usize>) -> Vec<RedundantServer> {
+    service_levels
+        .iter()
+        .enumerate()
+        .map(|(idx, service_level)| RedundantServer {
+            server_uri: format!("urn:server{}", idx),
+            endpoint_url: format!("opc.tcp://server{}:4855", idx),
+            service_level: *service_level,
+            is_connected: *service_level > 0,
+            is_active: active == Some(idx),
+        })
+        .collect()
+}
+
+#[test]
+fn redundant_best_server() {
+    // The highest service level, and the first of those with the same level
+    let servers = make_redundant_servers(&[200, 250, 250, 0], None);
+    assert_eq!(redundant_session::best_server(&servers, None), Some(1));
+    assert_eq!(redundant_session::best_server(&servers, Some(1)), Some(2));
+
+    // Servers that are not connected are never chosen
+    let servers = make_redundant_servers(&[0, 0], None);
+    assert_eq!(redundant_session::best_server(&servers, None), None);
+    let servers = make_redundant_servers(&[0, 100], None);
+    assert_eq!(redundant_session::best_server(&servers, Some(1)), None);
+}
+
+#[test]
+fn redundant_should_fail_over() {
+    let should_fail_over = |servers: &[RedundantServer], failover_mode, reconnect_due: bool| {
+        redundant_session::should_fail_over(servers, failover_mode, 200, |_| reconnect_due)
+    };
+
+    for failover_mode in [FailoverMode::Warm, FailoverMode::Hot] {
+        // A healthy active server is kept, even if another has a higher service level
+        let servers = make_redundant_servers(&[200, 255], Some(0));
+        assert!(!should_fail_over(&servers, failover_mode, true));
+        assert_eq!(redundant_session::failover_target(&servers), Some(1));
+
+        // A degraded active server is failed over from only to a higher service level
+        let servers = make_redundant_servers(&[150, 180], Some(0));
+        assert!(should_fail_over(&servers, failover_mode, false));
+        assert_eq!(redundant_session::failover_target(&servers), Some(1));
+        let servers = make_redundant_servers(&[150, 100], Some(0));
+        assert!(!should_fail_over(&servers, failover_mode, false));
+        assert_eq!(redundant_session::failover_target(&servers), None);
+
+        // A lost active server is failed over from to any connected server
+        let servers = make_redundant_servers(&[0, 100], Some(0));
+        assert!(should_fail_over(&servers, failover_mode, false));
+        assert_eq!(redundant_session::failover_target(&servers), Some(1));
+
+        // Without an active server the session always tries to find one
+        let servers = make_redundant_servers(&[0, 0], None);
+        assert!(should_fail_over(&servers, failover_mode, false));
+        assert_eq!(redundant_session::failover_target(&servers), None);
+    }
+
+    // In cold failover the backups are not connected, so a degraded active server is failed
+    // over from when a backup is due to be connected to
+    let servers = make_redundant_servers(&[200, 0], Some(0));
+    assert!(!should_fail_over(&servers, FailoverMode::Cold, true));
+    let servers = make_redundant_servers(&[150, 0], Some(0));
+    assert!(should_fail_over(&servers, FailoverMode::Cold, true));
+    assert!(!should_fail_over(&servers, FailoverMode::Cold, false));
+}
+
+/// Starts a server on a free port that accepts anonymous connections, calling the setup function
+/// before it is returned with its endpoint
+#[cfg(feature = "server")]
+fn start_test_server<F>(
+    name: &str,
+    setup: F,
+) -> (Arc<RwLock<crate::server::prelude::Server>>, String)
+where
+    F: FnOnce(&crate::server::prelude::Server),
+{
+    use crate::server::prelude::ServerBuilder;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let endpoint_url = format!("opc.tcp://127.0.0.1:{}/", port);
+    let server = ServerBuilder::new_anonymous(name)
+        .application_uri(format!("urn:{}", name))
+        .host_and_port("127.0.0.1", port)
+        .discovery_urls(vec![endpoint_url.clone()])
+        .discovery_server_url(None)
+        .pki_dir(make_test_file(&format!("{}-pki", name)))
+        .create_sample_keypair(true)
+        .trust_client_certs()
+        .server()
+        .unwrap();
+    setup(&server);
+    (Arc::new(RwLock::new(server)), endpoint_url)
+}
+
+/// Starts a server on a free port that accepts anonymous connections and has a variable with the
+/// value, and returns it with its endpoint
+#[cfg(feature = "server")]
+fn start_redundant_server(
+    name: &str,
+    value: i32,
+    service_level: u8,
+) -> (Arc<RwLock<crate::server::prelude::Server>>, String) {
+    use crate::server::prelude::VariableBuilder;
+
+    start_test_server(name, |server| {
+        let address_space = server.address_space();
+        let mut address_space = trace_write_lock!(address_space);
+        VariableBuilder::new(&NodeId::new(1, "v1"), "v1", "v1")
+            .data_type(DataTypeId::Int32)
+            .value(value)
+            .organized_by(ObjectId::ObjectsFolder)
+            .insert(&mut address_space);
+        address_space.set_service_level(service_level, &DateTime::now());
+    })
+}
+
+/// Runs the test with a session connected to a server that the setup function has prepared. The
+/// test returns the session so it can be disconnected before the server stops.
+#[cfg(feature = "server")]
+fn with_test_session<F, T, Fut>(name: &str, setup: F, test: T)
+where
+    F: FnOnce(&crate::server::prelude::Server),
+    T: FnOnce(Session) -> Fut,
+    Fut: std::future::Future<Output = Session>,
+{
+    use crate::async_client::client::IdentityToken;
+    use crate::server::prelude::Server;
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let (server, endpoint_url) = start_test_server(name, setup);
+    rt.spawn(Server::new_server_task(server.clone()));
+
+    let client_name = format!("{}Client", name);
+    let mut client = ClientBuilder::new()
+        .application_name(client_name.as_str())
+        .application_uri(format!("urn:{}", client_name))
+        .pki_dir(make_test_file(&format!("{}-pki", client_name)))
+        .create_sample_keypair(true)
+        .trust_server_certs(true)
+        .client()
+        .unwrap();
+    rt.block_on(async {
+        let endpoint = EndpointDescription::from((
+            endpoint_url.as_str(),
+            SecurityPolicy::None.to_uri(),
+            MessageSecurityMode::None,
+        ));
+        let session = client
+            .connect_to_endpoint_unshared(endpoint, IdentityToken::Anonymous)
+            .await
+            .unwrap();
+        test(session).await.disconnect().await;
+    });
+
+    trace_write_lock!(server).abort();
+    rt.shutdown_timeout(std::time::Duration::from_secs(1));
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn session_browse_all() {
+    use crate::server::prelude::ObjectBuilder;
+
+    // More children than the server returns for a node in one browse
+    with_test_session(
+        "BrowseAllServer",
+        |server| {
+            let address_space = server.address_space();
+            let mut address_space = trace_write_lock!(address_space);
+            let folder_id = NodeId::new(1, "folder");
+            address_space.add_folder_with_id(
+                &folder_id,
+                "folder",
+                "folder",
+                &NodeId::objects_folder_id(),
+            );
+            for i in 0..300 {
+                let name = format!("child{}", i);
+                ObjectBuilder::new(&NodeId::new(1, i), &name, &name)
+                    .organized_by(folder_id.clone())
+                    .insert(&mut address_space);
+            }
+        },
+        |session| async move {
+            let nodes_to_browse = [NodeId::new(1, "folder"), NodeId::objects_folder_id()]
+                .iter()
+                .map(|node_id| BrowseDescription {
+                    node_id: node_id.clone(),
+                    browse_direction: BrowseDirection::Forward,
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    include_subtypes: true,
+                    node_class_mask: 0,
+                    result_mask: BrowseDescriptionResultMask::all().bits(),
+                })
+                .collect::<Vec<_>>();
+
+            // A single browse stops part way through the folder's references
+            let results = session
+                .browse(&nodes_to_browse, None)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(!results[0].continuation_point.is_null_or_empty());
+            let _ = session
+                .browse_next(true, &[results[0].continuation_point.clone()])
+                .await;
+
+            let results = session.browse_all(&nodes_to_browse).await.unwrap();
+            assert!(results
+                .iter()
+                .all(|r| r.status_code.is_good() && r.continuation_point.is_null_or_empty()));
+            let children = results[0].references.as_ref().unwrap();
+            assert_eq!(children.len(), 300);
+            assert!((0..300).all(|i| children
+                .iter()
+                .any(|r| r.node_id.node_id == NodeId::new(1, i))));
+            assert!(results[1]
+                .references
+                .as_ref()
+                .unwrap()
+                .iter()
+                .any(|r| r.node_id.node_id == NodeId::new(1, "folder")));
+            session
+        },
+    );
+}
+
+/// Whether each call to a history provider released continuation points, and the continuation
+/// points it was given
+#[cfg(feature = "server")]
+type HistoryCalls = Arc<Mutex<Vec<(bool, Vec<ByteString>)>>>;
+
+/// A historical data provider which returns the values of every variable a page at a time. The
+/// continuation point is the index of the next page. It records the continuation points it is
+/// called with and fails when asked to read the page that it is set to fail on.
+#[cfg(feature = "server")]
+struct PagedHistoryProvider {
+    values: usize,
+    page_size: usize,
+    fail_page: Option<usize>,
+    calls: HistoryCalls,
+}
+
+#[cfg(feature = "server")]
+impl crate::server::prelude::HistoricalDataProvider for PagedHistoryProvider {
+    fn read_raw_modified_details(
+        &self,
+        _address_space: Arc<RwLock<crate::server::prelude::AddressSpace>>,
+        _request: ReadRawModifiedDetails,
+        _timestamps_to_return: TimestampsToReturn,
+        release_continuation_points: bool,
+        nodes_to_read: &[HistoryReadValueId],
+    ) -> Result<Vec<HistoryReadResult>, StatusCode> {
+        let continuation_points = nodes_to_read
+            .iter()
+            .map(|n| n.continuation_point.clone())
+            .collect::<Vec<_>>();
+        trace_lock!(self.calls).push((release_continuation_points, continuation_points));
+        if release_continuation_points {
+            return Ok(nodes_to_read
+                .iter()
+                .map(|_| HistoryReadResult {
+                    status_code: StatusCode::Good,
+                    continuation_point: ByteString::null(),
+                    history_data: ExtensionObject::null(),
+                })
+                .collect());
+        }
+        nodes_to_read
+            .iter()
+            .map(|node_to_read| {
+                let page_index = node_to_read
+                    .continuation_point
+                    .value
+                    .as_ref()
+                    .map_or(0, |v| v[0] as usize);
+                if self.fail_page == Some(page_index) {
+                    return Err(StatusCode::BadHistoryOperationUnsupported);
+                }
+                let start = page_index * self.page_size;
+                let end = self.values.min(start + self.page_size);
+                let page = service_types::HistoryData {
+                    data_values: Some((start..end).map(|v| DataValue::new_now(v as i32)).collect()),
+                };
+                let continuation_point = if end < self.values {
+                    ByteString::from(vec![page_index as u8 + 1])
+                } else {
+                    ByteString::null()
+                };
+                Ok(HistoryReadResult {
+                    status_code: StatusCode::Good,
+                    continuation_point,
+                    history_data: ExtensionObject::from_encodable(page.object_id(), &page),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Runs the test with a session to a server whose history is read from the provider
+#[cfg(feature = "server")]
+fn with_history_session<T, Fut>(name: &str, provider: PagedHistoryProvider, test: T)
+where
+    T: FnOnce(Session) -> Fut,
+    Fut: std::future::Future<Output = Session>,
+{
+    with_test_session(
+        name,
+        |server| {
+            let server_state = server.server_state();
+            let mut server_state = trace_write_lock!(server_state);
+            server_state.set_historical_data_provider(Box::new(provider));
+        },
+        test,
+    );
+}
+
+#[cfg(feature = "server")]
+fn history_values(history: &service_types::HistoryData) -> Vec<i32> {
+    history
+        .data_values
+        .iter()
+        .flatten()
+        .map(|v| i32::from_variant(v.value.as_ref().unwrap()).unwrap())
+        .collect()
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn session_read_raw_follows_continuation_points() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let provider = PagedHistoryProvider {
+        values: 10,
+        page_size: 3,
+        fail_page: None,
+        calls: calls.clone(),
+    };
+    with_history_session("ReadRawServer", provider, |session| {
+        let calls = calls.clone();
+        async move {
+            let node_id = NodeId::new(1, "v1");
+            let history = session
+                .read_raw(&node_id, DateTime::null(), DateTime::now(), 0)
+                .await
+                .unwrap();
+            assert_eq!(history_values(&history), (0..10).collect::<Vec<_>>());
+            // Four pages are read and nothing is released
+            assert_eq!(trace_lock!(calls).len(), 4);
+            assert!(trace_lock!(calls).iter().all(|(release, _)| !release));
+
+            // Reading stops once there are enough values, and the continuation point of the
+            // rest is released
+            trace_lock!(calls).clear();
+            let history = session
+                .read_raw(&node_id, DateTime::null(), DateTime::now(), 5)
+                .await
+                .unwrap();
+            assert_eq!(history_values(&history), (0..5).collect::<Vec<_>>());
+            assert_eq!(
+                *trace_lock!(calls),
+                vec![
+                    (false, vec![ByteString::null()]),
+                    (false, vec![ByteString::from(vec![1u8])]),
+                    (true, vec![ByteString::from(vec![2u8])]),
+                ]
+            );
+            session
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn session_read_raw_releases_continuation_points_on_error() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let provider = PagedHistoryProvider {
+        values: 10,
+        page_size: 3,
+        fail_page: Some(2),
+        calls: calls.clone(),
+    };
+    with_history_session("ReadRawErrorServer", provider, |session| {
+        let calls = calls.clone();
+        async move {
+            let result = session
+                .read_raw(&NodeId::new(1, "v1"), DateTime::null(), DateTime::now(), 0)
+                .await;
+            assert!(result.is_err());
+            // The read of the third page fails, so its continuation point is released
+            assert_eq!(
+                *trace_lock!(calls),
+                vec![
+                    (false, vec![ByteString::null()]),
+                    (false, vec![ByteString::from(vec![1u8])]),
+                    (false, vec![ByteString::from(vec![2u8])]),
+                    (true, vec![ByteString::from(vec![2u8])]),
+                ]
+            );
+            session
+        }
+    });
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn redundant_session_recreates_subscription_after_failover() {
+    use crate::async_client::{
+        callbacks::DataChangeCallback, client::IdentityToken, redundant_session::RedundantSession,
+    };
+    use crate::server::prelude::Server;
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let (server1, endpoint_url1) = start_redundant_server("RedundantServer1", 1, 255);
+    let (server2, endpoint_url2) = start_redundant_server("RedundantServer2", 2, 210);
+    rt.spawn(Server::new_server_task(server1.clone()));
+    rt.spawn(Server::new_server_task(server2.clone()));
+
+    let client = ClientBuilder::new()
+        .application_name("Redundant Client")
+        .application_uri("urn:RedundantClient")
+        .pki_dir(make_test_file("RedundantClient-pki"))
+        .create_sample_keypair(true)
+        .trust_server_certs(true)
+        .client()
+        .unwrap();
+    let endpoints = [endpoint_url1, endpoint_url2]
+        .iter()
+        .map(|endpoint_url| {
+            EndpointDescription::from((
+                endpoint_url.as_str(),
+                SecurityPolicy::None.to_uri(),
+                MessageSecurityMode::None,
+            ))
+        })
+        .collect();
+    let options = FailoverOptions {
+        failover_mode: Some(FailoverMode::Warm),
+        service_level_interval: 100.0,
+        ..Default::default()
+    };
+    let values = Arc::new(Mutex::new(Vec::new()));
+
+    rt.block_on(async {
+        let mut redundant_session =
+            RedundantSession::new(client, endpoints, IdentityToken::Anonymous, options);
+        redundant_session.connect().await.unwrap();
+        assert!(redundant_session.servers()[0].is_active);
+
+        let items_to_create = [NodeId::new(1, "v1").into()];
+        let callback = {
+            let values = values.clone();
+            DataChangeCallback::new(move |items| {
+                for item in items {
+                    if let Some(Variant::Int32(value)) = item.last_value().value {
+                        trace_lock!(values).push(value);
+                    }
+                }
+            })
+        };
+        redundant_session
+            .create_subscription(100.0, 30, 10, 0, 0, &items_to_create, callback)
+            .await
+            .unwrap();
+
+        // Polls the session until the condition holds or a timeout expires
+        macro_rules! poll_until {
+            ($condition:expr) => {
+                let deadline = std::time::Instant::now() + std::time::Duration::from_secs(20);
+                while !$condition {
+                    assert!(std::time::Instant::now() < deadline, "timed out");
+                    if !redundant_session.poll().await {
+                        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    }
+                }
+            };
+        }
+        poll_until!(trace_lock!(values).contains(&1));
+
+        // The service level of the active server drops, so the session fails over to the other
+        // server and creates the subscription there
+        {
+            let server1 = trace_read_lock!(server1);
+            let address_space = server1.address_space();
+            let mut address_space = trace_write_lock!(address_space);
+            address_space.set_service_level(100, &DateTime::now());
+        }
+        poll_until!(redundant_session.servers()[1].is_active);
+        poll_until!(trace_lock!(values).contains(&2));
+
+        // The active session has the service level subscription and the recreated one
+        let subscription_ids = {
+            let session = redundant_session.session().unwrap();
+            let subscription_state = session.subscription_state();
+            let subscription_state = trace_read_lock!(subscription_state);
+            subscription_state.subscription_ids().unwrap()
+        };
+        assert_eq!(subscription_ids.len(), 2);
+
+        redundant_session.disconnect().await;
+    });
+
+    trace_write_lock!(server1).abort();
+    trace_write_lock!(server2).abort();
+    rt.shutdown_timeout(std::time::Duration::from_secs(1));
+}
@@ -2,10 +2,11 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
-use crate::client::{client::Client, config::*};
+use crate::client::{callbacks::OnIssuedTokenRefresh, client::Client, config::*};
 use crate::core::config::Config;
+use crate::sync::Mutex;
 
 /// The `ClientBuilder` is a builder for producing a [`Client`]. It is an alternative to constructing
 /// a [`ClientConfig`] from file or from scratch.
@@ -41,12 +42,15 @@ use crate::core::config::Config;
 ///
 pub struct ClientBuilder {
     config: ClientConfig,
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
 }
 
 impl Default for ClientBuilder {
     fn default() -> Self {
         ClientBuilder {
             config: ClientConfig::default(),
+            issued_token_refresh_callback: None,
         }
     }
 }
@@ -64,6 +68,7 @@ impl ClientBuilder {
     {
         Ok(ClientBuilder {
             config: ClientConfig::load(&path.into())?,
+            issued_token_refresh_callback: None,
         })
     }
 
@@ -73,7 +78,11 @@ impl ClientBuilder {
     /// [`Client`]: ../client/struct.Client.html
     pub fn client(self) -> Option<Client> {
         if self.is_valid() {
-            Some(Client::new(self.config))
+            let mut client = Client::new(self.config);
+            if let Some(issued_token_refresh_callback) = self.issued_token_refresh_callback {
+                client.set_shared_issued_token_refresh_callback(issued_token_refresh_callback);
+            }
+            Some(client)
         } else {
             None
         }
@@ -224,6 +233,20 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets a callback that supplies a fresh token each time a session which uses an issued
+    /// token, e.g. a JWT, is activated. The token of a [`ClientUserToken`] is only used when
+    /// there is no callback.
+    ///
+    /// [`ClientUserToken`]: ../config/struct.ClientUserToken.html
+    pub fn issued_token_refresh_callback<CB>(mut self, issued_token_refresh_callback: CB) -> Self
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+        self
+    }
+
     /// Sets the session retry limit.
     pub fn session_retry_limit(mut self, session_retry_limit: i32) -> Self {
         if session_retry_limit < 0 && session_retry_limit != -1 {
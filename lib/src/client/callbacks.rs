@@ -60,6 +60,15 @@ pub trait OnSessionClosed {
     fn on_session_closed(&mut self, status_code: StatusCode);
 }
 
+/// The `OnIssuedTokenRefresh` trait can be registered on a session or the client builder to
+/// supply the token of an `IdentityToken::IssuedToken`, e.g. a JWT obtained from an
+/// authorization service, so that a token which has expired is not used again.
+pub trait OnIssuedTokenRefresh {
+    /// Called each time the session is activated to obtain the token to activate it with. An
+    /// error prevents the session from being activated.
+    fn on_issued_token_refresh(&mut self) -> Result<String, StatusCode>;
+}
+
 /// This is a concrete implementation of [`OnSubscriptionNotification`] that calls a function when
 /// a data change occurs.
 pub struct DataChangeCallback {
@@ -159,3 +168,24 @@ impl SessionClosedCallback {
         Self { cb: Box::new(cb) }
     }
 }
+
+/// This is a concrete implementation of [`OnIssuedTokenRefresh`] that calls the supplied function.
+pub struct IssuedTokenRefreshCallback {
+    cb: Box<dyn FnMut() -> Result<String, StatusCode> + Send + Sync + 'static>,
+}
+
+impl OnIssuedTokenRefresh for IssuedTokenRefreshCallback {
+    fn on_issued_token_refresh(&mut self) -> Result<String, StatusCode> {
+        (self.cb)()
+    }
+}
+
+impl IssuedTokenRefreshCallback {
+    // Constructor
+    pub fn new<CB>(cb: CB) -> Self
+    where
+        CB: FnMut() -> Result<String, StatusCode> + Send + Sync + 'static,
+    {
+        Self { cb: Box::new(cb) }
+    }
+}
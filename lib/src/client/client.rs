@@ -9,6 +9,7 @@ use std::{path::PathBuf, str::FromStr, sync::Arc};
 use chrono::Duration;
 
 use super::{
+    callbacks::OnIssuedTokenRefresh,
     config::{ClientConfig, ClientEndpoint, ANONYMOUS_USER_TOKEN_ID},
     session::{
         services::*,
@@ -26,7 +27,7 @@ use crate::{
         config::Config,
     },
     crypto::{CertificateStore, SecurityPolicy},
-    sync::RwLock,
+    sync::{Mutex, RwLock},
     trace_read_lock,
     types::{
         service_types::{ApplicationDescription, EndpointDescription, RegisteredServer},
@@ -43,6 +44,9 @@ pub enum IdentityToken {
     UserName(String, String),
     /// X5090 cert - a path to the cert.der, and private.pem
     X509(PathBuf, PathBuf),
+    /// A token issued by an authorization service, e.g. a JWT. A session with an issued token
+    /// refresh callback asks it for a fresh token instead.
+    IssuedToken(String),
 }
 
 /// The `Client` defines a connection that can be used to to get end points or establish
@@ -71,6 +75,9 @@ pub struct Client {
     certificate_store: Arc<RwLock<CertificateStore>>,
     /// The session retry policy for new sessions
     session_retry_policy: SessionRetryPolicy,
+    /// Supplies fresh issued tokens to new sessions
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
 }
 
 impl Drop for Client {
@@ -163,9 +170,27 @@ impl Client {
             config,
             session_retry_policy,
             certificate_store: Arc::new(RwLock::new(certificate_store)),
+            issued_token_refresh_callback: None,
         }
     }
 
+    /// Registers a callback that supplies a fresh token to the sessions that the client creates
+    /// with an [`IdentityToken::IssuedToken`]. The callback is shared by those sessions.
+    pub fn set_issued_token_refresh_callback<CB>(&mut self, issued_token_refresh_callback: CB)
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+    }
+
+    pub(crate) fn set_shared_issued_token_refresh_callback(
+        &mut self,
+        issued_token_refresh_callback: Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>,
+    ) {
+        self.issued_token_refresh_callback = Some(issued_token_refresh_callback);
+    }
+
     /// Returns a filled OPC UA [`ApplicationDescription`] using information from the config
     ///
     /// [`ApplicationDescription`]: ../../opcua_types/service_types/application_description/struct.ApplicationDescription.html
@@ -386,7 +411,11 @@ impl Client {
                 session_info.endpoint.endpoint_url
             ))
         } else {
-            let session = Arc::new(RwLock::new(Session::new(
+            let issued_token = matches!(
+                session_info.user_identity_token,
+                IdentityToken::IssuedToken(_)
+            );
+            let mut session = Session::new(
                 self.application_description(),
                 self.config.session_name.clone(),
                 self.certificate_store.clone(),
@@ -395,8 +424,16 @@ impl Client {
                 self.decoding_options(),
                 self.config.performance.ignore_clock_skew,
                 self.config.performance.single_threaded_executor,
-            )));
-            Ok(session)
+            );
+            if issued_token {
+                if let Some(ref issued_token_refresh_callback) = self.issued_token_refresh_callback
+                {
+                    session.set_shared_issued_token_refresh_callback(
+                        issued_token_refresh_callback.clone(),
+                    );
+                }
+            }
+            Ok(Arc::new(RwLock::new(session)))
         }
     }
 
@@ -647,6 +684,8 @@ impl Client {
                     token.user.clone(),
                     password.clone(),
                 ))
+            } else if let Some(ref issued_token) = token.issued_token {
+                Some(IdentityToken::IssuedToken(issued_token.clone()))
             } else if let Some(ref cert_path) = token.cert_path {
                 token.private_key_path.as_ref().map(|private_key_path| {
                     IdentityToken::X509(PathBuf::from(cert_path), PathBuf::from(private_key_path))
@@ -23,7 +23,10 @@ use tokio::{
 
 use crate::{
     client::{
-        callbacks::{OnConnectionStatusChange, OnSessionClosed, OnSubscriptionNotification},
+        callbacks::{
+            OnConnectionStatusChange, OnIssuedTokenRefresh, OnSessionClosed,
+            OnSubscriptionNotification,
+        },
         client::IdentityToken,
         comms::tcp_transport::TcpTransport,
         process_service_result, process_unexpected_response,
@@ -45,8 +48,9 @@ use crate::{
         RUNTIME,
     },
     crypto::{
-        self as crypto, user_identity::make_user_name_identity_token, CertificateStore,
-        SecurityPolicy, X509,
+        self as crypto,
+        user_identity::{make_issued_identity_token, make_user_name_identity_token},
+        CertificateStore, SecurityPolicy, X509,
     },
     deregister_runtime_component, register_runtime_component,
     sync::*,
@@ -119,6 +123,9 @@ pub struct Session {
     ignore_clock_skew: bool,
     /// Single threaded executor flag (for TCP transport). Unused.
     single_threaded_executor: bool,
+    /// Supplies a fresh issued token each time the session is activated.
+    issued_token_refresh_callback:
+        Option<Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>>,
     /// Tokio runtime
     runtime: Arc<Mutex<tokio::runtime::Runtime>>,
 }
@@ -197,6 +204,7 @@ impl Session {
             session_retry_policy: Arc::new(Mutex::new(session_retry_policy)),
             ignore_clock_skew,
             single_threaded_executor,
+            issued_token_refresh_callback: None,
             runtime: Arc::new(Mutex::new(runtime)),
         }
     }
@@ -275,6 +283,29 @@ impl Session {
         session_state.set_connection_status_callback(connection_status_callback);
     }
 
+    /// Registers a callback that supplies a fresh issued token, e.g. a JWT from an authorization
+    /// service, each time the session is activated with an [`IdentityToken::IssuedToken`],
+    /// including when it is reactivated after a reconnect.
+    ///
+    /// # Arguments
+    ///
+    /// * `issued_token_refresh_callback` - the issued token refresh callback.
+    ///
+    pub fn set_issued_token_refresh_callback<CB>(&mut self, issued_token_refresh_callback: CB)
+    where
+        CB: OnIssuedTokenRefresh + Send + Sync + 'static,
+    {
+        self.issued_token_refresh_callback =
+            Some(Arc::new(Mutex::new(issued_token_refresh_callback)));
+    }
+
+    pub(crate) fn set_shared_issued_token_refresh_callback(
+        &mut self,
+        issued_token_refresh_callback: Arc<Mutex<dyn OnIssuedTokenRefresh + Send + Sync + 'static>>,
+    ) {
+        self.issued_token_refresh_callback = Some(issued_token_refresh_callback);
+    }
+
     /// Reconnects to the server and tries to activate the existing session. If there
     /// is a failure, it will be communicated by the status code in the result. You should not
     /// call this if there is a session retry policy associated with the session.
@@ -1065,6 +1096,7 @@ impl Session {
             IdentityToken::Anonymous => UserTokenType::Anonymous,
             IdentityToken::UserName(_, _) => UserTokenType::UserName,
             IdentityToken::X509(_, _) => UserTokenType::Certificate,
+            IdentityToken::IssuedToken(_) => UserTokenType::IssuedToken,
         };
 
         let endpoint = &self.session_info.endpoint;
@@ -1121,6 +1153,22 @@ impl Session {
                             );
                             Ok((identity_token, SignatureData::null()))
                         }
+                        IdentityToken::IssuedToken(ref token) => {
+                            let token = self.issued_token(token)?;
+                            let secure_channel = trace_read_lock!(self.secure_channel);
+                            let identity_token = make_issued_identity_token(
+                                secure_channel.security_policy(),
+                                policy,
+                                secure_channel.remote_nonce(),
+                                &secure_channel.remote_cert(),
+                                &token,
+                            )?;
+                            let identity_token = ExtensionObject::from_encodable(
+                                ObjectId::IssuedIdentityToken_Encoding_DefaultBinary,
+                                &identity_token,
+                            );
+                            Ok((identity_token, SignatureData::null()))
+                        }
                         IdentityToken::X509(ref cert_path, ref private_key_path) => {
                             if let Some(ref server_cert) = server_cert {
                                 // The cert will be supplied to the server along with a signature to prove we have the private key to go with the cert
@@ -1175,6 +1223,17 @@ impl Session {
         }
     }
 
+    /// Returns the issued token to activate the session with. A refresh callback is asked for a
+    /// fresh token, otherwise the token of the session info is used as is.
+    fn issued_token(&self, token: &str) -> Result<String, StatusCode> {
+        if let Some(ref issued_token_refresh_callback) = self.issued_token_refresh_callback {
+            let mut issued_token_refresh_callback = trace_lock!(issued_token_refresh_callback);
+            issued_token_refresh_callback.on_issued_token_refresh()
+        } else {
+            Ok(token.to_string())
+        }
+    }
+
     /// Create a filled in UserNameIdentityToken by using the endpoint's token policy, the current
     /// secure channel information and the user name and password.
     fn make_user_name_identity_token(
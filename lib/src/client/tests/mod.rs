@@ -155,7 +155,25 @@ fn client_anonymous_user_tokens_id() {
             password: Some(String::new()),
             cert_path: None,
             private_key_path: None,
+            issued_token: None,
         },
     );
     assert!(!config.is_valid());
 }
+
+#[test]
+fn client_issued_token_user_token() {
+    let mut config = default_sample_config();
+    config.user_tokens = BTreeMap::new();
+    config.user_tokens.insert(
+        String::from("sso"),
+        ClientUserToken::issued_token("sso", "eyJhbGciOiJSUzI1NiJ9.e30.c2ln"),
+    );
+    assert!(config.is_valid());
+
+    // A token cannot hold a password and an issued token
+    let mut user_token = ClientUserToken::issued_token("sso", "eyJhbGciOiJSUzI1NiJ9.e30.c2ln");
+    user_token.password = Some(String::from("pwd"));
+    config.user_tokens.insert(String::from("sso"), user_token);
+    assert!(!config.is_valid());
+}
@@ -99,6 +99,12 @@ impl MessageWriter {
         }
     }
 
+    /// Returns the sequence number of the last chunk written. Each chunk takes a sequence number
+    /// so the difference between two calls is the number of chunks written in between.
+    pub fn last_sent_sequence_number(&self) -> u32 {
+        self.last_sent_sequence_number
+    }
+
     pub fn next_request_id(&mut self) -> u32 {
         self.last_request_id += 1;
         self.last_request_id
@@ -0,0 +1,285 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Functions related to verifying the JSON Web Tokens (JWT) that are held by an IssuedIdentityToken.
+//!
+//! Tokens are verified against the public keys of their issuer, supplied either as a PEM / DER
+//! encoded public key or certificate, or as a JSON Web Key Set (JWKS). Only RSA signatures
+//! (RS256 and PS256) are supported.
+
+use std::{fs, path::Path};
+
+use openssl::{bn::BigNum, pkey, rsa::Rsa, x509};
+use serde_json::{Map, Value};
+
+use crate::types::status_code::StatusCode;
+
+use super::{pkey::PublicKey, x509::X509};
+
+/// The issued token type of a JWT in a `UserTokenPolicy`
+pub const ISSUED_TOKEN_TYPE_JWT: &str = "http://opcfoundation.org/UA/UserToken#JWT";
+
+/// The public keys that the signature of a token may be verified with.
+#[derive(Debug, Default)]
+pub struct JwtKeys {
+    /// Keys with their optional key id
+    keys: Vec<(Option<String>, PublicKey)>,
+}
+
+impl JwtKeys {
+    /// Reads keys from a file holding a JWKS, a PEM encoded public key or certificate, or a DER
+    /// encoded certificate.
+    pub fn read(path: &Path) -> Result<JwtKeys, StatusCode> {
+        let data = fs::read(path).map_err(|err| {
+            error!(
+                "Cannot read JWT keys from {}, error = {}",
+                path.display(),
+                err
+            );
+            StatusCode::BadConfigurationError
+        })?;
+        let text = String::from_utf8_lossy(&data);
+        let text = text.trim_start();
+        if text.starts_with('{') {
+            Self::from_jwks(text)
+        } else if text.starts_with("-----BEGIN") {
+            Self::from_pem(text.as_bytes())
+        } else {
+            let cert = X509::from_der(&data).map_err(|_| StatusCode::BadConfigurationError)?;
+            let mut keys = JwtKeys::default();
+            keys.add(None, cert.public_key()?);
+            Ok(keys)
+        }
+    }
+
+    /// Creates keys from a PEM encoded public key or certificate.
+    pub fn from_pem(pem: &[u8]) -> Result<JwtKeys, StatusCode> {
+        let public_key = if let Ok(cert) = x509::X509::from_pem(pem) {
+            X509::from(cert).public_key()?
+        } else {
+            pkey::PKey::public_key_from_pem(pem)
+                .or_else(|_| Rsa::public_key_from_pem_pkcs1(pem).and_then(pkey::PKey::from_rsa))
+                .map(PublicKey::wrap_public_key)
+                .map_err(|_| {
+                    error!("Cannot read a public key or certificate from the PEM data");
+                    StatusCode::BadConfigurationError
+                })?
+        };
+        let mut keys = JwtKeys::default();
+        keys.add(None, public_key);
+        Ok(keys)
+    }
+
+    /// Creates keys from the RSA keys of a JSON Web Key Set. Keys are read from their modulus
+    /// and exponent, or failing that from the first certificate of their chain.
+    pub fn from_jwks(jwks: &str) -> Result<JwtKeys, StatusCode> {
+        let jwks: Value = serde_json::from_str(jwks).map_err(|err| {
+            error!("Cannot parse the JWKS, error = {}", err);
+            StatusCode::BadConfigurationError
+        })?;
+        let mut keys = JwtKeys::default();
+        if let Some(jwks) = jwks.get("keys").and_then(|k| k.as_array()) {
+            for jwk in jwks {
+                if jwk.get("kty").and_then(|v| v.as_str()) != Some("RSA") {
+                    continue;
+                }
+                if let Some(public_key) = Self::jwk_public_key(jwk) {
+                    let kid = jwk.get("kid").and_then(|v| v.as_str()).map(String::from);
+                    keys.add(kid, public_key);
+                } else {
+                    warn!("Ignoring a JWK which does not hold a usable RSA public key");
+                }
+            }
+        }
+        if keys.is_empty() {
+            error!("The JWKS does not hold any RSA public keys");
+            Err(StatusCode::BadConfigurationError)
+        } else {
+            Ok(keys)
+        }
+    }
+
+    fn jwk_public_key(jwk: &Value) -> Option<PublicKey> {
+        let component = |name| {
+            jwk.get(name)
+                .and_then(|v| v.as_str())
+                .and_then(|v| base64::decode_config(v, base64::URL_SAFE_NO_PAD).ok())
+                .and_then(|v| BigNum::from_slice(&v).ok())
+        };
+        if let (Some(n), Some(e)) = (component("n"), component("e")) {
+            Rsa::from_public_components(n, e)
+                .and_then(pkey::PKey::from_rsa)
+                .map(PublicKey::wrap_public_key)
+                .ok()
+        } else {
+            jwk.get("x5c")
+                .and_then(|v| v.get(0))
+                .and_then(|v| v.as_str())
+                .and_then(|v| base64::decode(v).ok())
+                .and_then(|der| X509::from_der(&der).ok())
+                .and_then(|cert| cert.public_key().ok())
+        }
+    }
+
+    /// Adds a key with an optional key id
+    pub fn add(&mut self, kid: Option<String>, public_key: PublicKey) {
+        self.keys.push((kid, public_key));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns the keys which can verify a token signed with the key id. A token without a key
+    /// id, or keys without one, may be verified by any key.
+    fn matching<'a>(&'a self, kid: Option<&'a str>) -> impl Iterator<Item = &'a PublicKey> {
+        self.keys
+            .iter()
+            .filter(move |(key_kid, _)| match (kid, key_kid) {
+                (Some(kid), Some(key_kid)) => kid == key_kid,
+                _ => true,
+            })
+            .map(|(_, public_key)| public_key)
+    }
+}
+
+/// The checks that are made on the claims of a token besides its expiry.
+#[derive(Debug, Clone, Default)]
+pub struct JwtValidation {
+    /// The issuer that the `iss` claim must match
+    pub issuer: Option<String>,
+    /// The audience that the `aud` claim must contain
+    pub audience: Option<String>,
+    /// Seconds of clock skew to allow for when testing the `exp` and `nbf` claims
+    pub leeway: u64,
+}
+
+/// A verified JSON Web Token
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jwt {
+    /// The JOSE header
+    pub header: Map<String, Value>,
+    /// The claims
+    pub claims: Map<String, Value>,
+}
+
+impl Jwt {
+    /// Returns the subject, i.e. the `sub` claim
+    pub fn subject(&self) -> Option<&str> {
+        self.claims.get("sub").and_then(|v| v.as_str())
+    }
+
+    /// Returns the string values of a claim, which may hold a single string, an array of
+    /// strings or a space separated list (such as the `scope` claim). The name may be a dot
+    /// separated path to a claim nested inside objects, e.g. `realm_access.roles`.
+    pub fn claim_values(&self, name: &str) -> Vec<String> {
+        let mut path = name.split('.');
+        let claim = path
+            .next()
+            .and_then(|first| self.claims.get(first))
+            .and_then(|claim| path.try_fold(claim, |claim, name| claim.get(name)));
+        match claim {
+            Some(Value::String(value)) => value.split_whitespace().map(String::from).collect(),
+            Some(Value::Array(values)) => values
+                .iter()
+                .filter_map(|v| v.as_str())
+                .map(String::from)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn decode_part(part: &str) -> Result<Map<String, Value>, StatusCode> {
+    base64::decode_config(part, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| {
+            error!("JWT is malformed");
+            StatusCode::BadIdentityTokenInvalid
+        })
+}
+
+/// Decodes a token in JWS compact serialization, verifies its signature against the keys and
+/// validates its claims. A token that is malformed is `BadIdentityTokenInvalid` and one that
+/// fails verification is `BadIdentityTokenRejected`.
+pub fn decode_jwt(
+    token: &str,
+    keys: &JwtKeys,
+    validation: &JwtValidation,
+) -> Result<Jwt, StatusCode> {
+    let parts = token.trim().split('.').collect::<Vec<_>>();
+    if parts.len() != 3 {
+        error!("JWT does not have a header, payload and signature");
+        return Err(StatusCode::BadIdentityTokenInvalid);
+    }
+    let header = decode_part(parts[0])?;
+    let claims = decode_part(parts[1])?;
+    let signature = base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD)
+        .map_err(|_| StatusCode::BadIdentityTokenInvalid)?;
+
+    // The algorithm must be one that is supported, in particular never "none"
+    let alg = header.get("alg").and_then(|v| v.as_str()).unwrap_or("");
+    let signed = &token.trim()[..parts[0].len() + 1 + parts[1].len()];
+    let kid = header.get("kid").and_then(|v| v.as_str());
+    let verified = keys.matching(kid).any(|public_key| {
+        let result = match alg {
+            "RS256" => public_key.verify_sha256(signed.as_bytes(), &signature),
+            "PS256" => public_key.verify_sha256_pss(signed.as_bytes(), &signature),
+            _ => Ok(false),
+        };
+        result.unwrap_or(false)
+    });
+    if !verified {
+        error!(
+            "JWT signature with algorithm \"{}\" cannot be verified",
+            alg
+        );
+        return Err(StatusCode::BadIdentityTokenRejected);
+    }
+
+    let jwt = Jwt { header, claims };
+    validate_claims(&jwt, validation, chrono::Utc::now().timestamp())?;
+    Ok(jwt)
+}
+
+fn validate_claims(jwt: &Jwt, validation: &JwtValidation, now: i64) -> Result<(), StatusCode> {
+    let leeway = validation.leeway as i64;
+    let time_claim = |name| jwt.claims.get(name).and_then(|v| v.as_f64());
+    match time_claim("exp") {
+        Some(exp) if now - leeway < exp as i64 => {}
+        Some(_) => {
+            error!("JWT has expired");
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+        None => {
+            error!("JWT does not have an expiry");
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+    }
+    if let Some(nbf) = time_claim("nbf") {
+        if now + leeway < nbf as i64 {
+            error!("JWT is not valid yet");
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+    }
+    if let Some(ref issuer) = validation.issuer {
+        if jwt.claims.get("iss").and_then(|v| v.as_str()) != Some(issuer.as_str()) {
+            error!("JWT is not issued by \"{}\"", issuer);
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+    }
+    if let Some(ref audience) = validation.audience {
+        let valid = match jwt.claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !valid {
+            error!("JWT is not intended for the audience \"{}\"", audience);
+            return Err(StatusCode::BadIdentityTokenRejected);
+        }
+    }
+    Ok(())
+}
@@ -10,7 +10,7 @@ use std::fmt;
 
 use crate::types::{service_types::SignatureData, status_code::StatusCode, ByteString, UAString};
 pub use {
-    aeskey::*, certificate_store::*, hash::*, pkey::*, security_policy::*, thumbprint::*,
+    aeskey::*, certificate_store::*, hash::*, jwt::*, pkey::*, security_policy::*, thumbprint::*,
     user_identity::*, x509::*,
 };
 
@@ -20,6 +20,7 @@ mod tests;
 pub mod aeskey;
 pub mod certificate_store;
 pub mod hash;
+pub mod jwt;
 pub mod pkey;
 pub mod random;
 pub mod security_policy;
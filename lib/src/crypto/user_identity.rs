@@ -2,7 +2,8 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
-//! Functions related to encrypting / decrypting passwords in a UserNameIdentityToken.
+//! Functions related to encrypting / decrypting passwords in a UserNameIdentityToken and the
+//! token data of an IssuedIdentityToken.
 //!
 //! The code here determines how or if to encrypt the password depending on the security policy
 //! and user token policy.
@@ -12,7 +13,10 @@ use std::str::FromStr;
 
 use crate::types::{
     encoding::{read_u32, write_u32},
-    service_types::{SignatureData, UserNameIdentityToken, UserTokenPolicy, X509IdentityToken},
+    service_types::{
+        IssuedIdentityToken, SignatureData, UserNameIdentityToken, UserTokenPolicy,
+        X509IdentityToken,
+    },
     status_code::StatusCode,
     ByteString, UAString,
 };
@@ -28,6 +32,52 @@ pub fn make_user_name_identity_token(
     user: &str,
     pass: &str,
 ) -> Result<UserNameIdentityToken, StatusCode> {
+    let (password, encryption_algorithm) = encrypt_secret(
+        channel_security_policy,
+        user_token_policy,
+        nonce,
+        cert,
+        pass,
+    )?;
+    Ok(UserNameIdentityToken {
+        policy_id: user_token_policy.policy_id.clone(),
+        user_name: UAString::from(user),
+        password,
+        encryption_algorithm,
+    })
+}
+
+/// Create a filled in IssuedIdentityToken by using the supplied channel security policy, user token policy, nonce, cert and
+/// the token issued by an authorization service, e.g. a JWT. The token is encrypted in the same way as a password.
+pub fn make_issued_identity_token(
+    channel_security_policy: SecurityPolicy,
+    user_token_policy: &UserTokenPolicy,
+    nonce: &[u8],
+    cert: &Option<X509>,
+    token: &str,
+) -> Result<IssuedIdentityToken, StatusCode> {
+    let (token_data, encryption_algorithm) = encrypt_secret(
+        channel_security_policy,
+        user_token_policy,
+        nonce,
+        cert,
+        token,
+    )?;
+    Ok(IssuedIdentityToken {
+        policy_id: user_token_policy.policy_id.clone(),
+        token_data,
+        encryption_algorithm,
+    })
+}
+
+/// Encrypts the secret of a user identity token, returning the secret and the encryption algorithm.
+fn encrypt_secret(
+    channel_security_policy: SecurityPolicy,
+    user_token_policy: &UserTokenPolicy,
+    nonce: &[u8],
+    cert: &Option<X509>,
+    secret: &str,
+) -> Result<(ByteString, UAString), StatusCode> {
     // This is a condensed version of Table 187 Opc Part 4 that details the EncryptionAlgorithm
     // selection.
     //
@@ -46,40 +96,33 @@ pub fn make_user_name_identity_token(
             .unwrap_or(SecurityPolicy::None)
     };
 
-    // Now it should be a matter of using the policy (or lack thereof) to encrypt the password
+    // Now it should be a matter of using the policy (or lack thereof) to encrypt the secret
     // using the secure channel's cert and nonce.
-    let (password, encryption_algorithm) = match security_policy {
+    match security_policy {
         SecurityPolicy::None => {
             // Plain text
             if channel_security_policy == SecurityPolicy::None {
-                warn!("A user identity's password is being sent over the network in plain text. This could be a serious security issue");
+                warn!("A user identity's secret is being sent over the network in plain text. This could be a serious security issue");
             }
-            (ByteString::from(pass.as_bytes()), UAString::null())
+            Ok((ByteString::from(secret.as_bytes()), UAString::null()))
         }
         SecurityPolicy::Unknown => {
             // This should only happen if channel_security_policy were Unknown when it shouldn't be
             panic!("Don't know how to make the token for this server");
         }
         security_policy => {
-            // Create a password which is encrypted using the secure channel info and the user token policy for the endpoint
-            let password = legacy_password_encrypt(
-                pass,
+            // Create a secret which is encrypted using the secure channel info and the user token policy for the endpoint
+            let secret = legacy_password_encrypt(
+                secret,
                 nonce,
                 cert.as_ref().unwrap(),
                 security_policy.asymmetric_encryption_padding(),
             )?;
             let encryption_algorithm =
                 UAString::from(security_policy.asymmetric_encryption_algorithm());
-            (password, encryption_algorithm)
+            Ok((secret, encryption_algorithm))
         }
-    };
-
-    Ok(UserNameIdentityToken {
-        policy_id: user_token_policy.policy_id.clone(),
-        user_name: UAString::from(user),
-        password,
-        encryption_algorithm,
-    })
+    }
 }
 
 /// Decrypt the password inside of a user identity token.
@@ -92,17 +135,7 @@ pub fn decrypt_user_identity_token_password(
         // Assumed to be UTF-8 plain text
         user_identity_token.plaintext_password()
     } else {
-        // Determine the padding from the algorithm.
-        let encryption_algorithm = user_identity_token.encryption_algorithm.as_ref();
-        let padding = match encryption_algorithm {
-            super::algorithms::ENC_RSA_15 => RsaPadding::Pkcs1,
-            super::algorithms::ENC_RSA_OAEP => RsaPadding::OaepSha1,
-            super::algorithms::ENC_RSA_OAEP_SHA256 => RsaPadding::OaepSha256,
-            _ => {
-                error!("decrypt_user_identity_token_password has rejected unsupported user identity encryption algorithm \"{}\"", encryption_algorithm);
-                return Err(StatusCode::BadIdentityTokenInvalid);
-            }
-        };
+        let padding = padding_for_algorithm(user_identity_token.encryption_algorithm.as_ref())?;
         legacy_password_decrypt(
             &user_identity_token.password,
             server_nonce,
@@ -112,6 +145,42 @@ pub fn decrypt_user_identity_token_password(
     }
 }
 
+/// Decrypt the token data inside of an issued identity token.
+pub fn decrypt_issued_identity_token(
+    issued_identity_token: &IssuedIdentityToken,
+    server_nonce: &[u8],
+    server_key: &PrivateKey,
+) -> Result<String, StatusCode> {
+    if issued_identity_token.encryption_algorithm.is_empty() {
+        // Assumed to be UTF-8 plain text
+        issued_identity_token.plaintext_token_data()
+    } else {
+        let padding = padding_for_algorithm(issued_identity_token.encryption_algorithm.as_ref())?;
+        legacy_password_decrypt(
+            &issued_identity_token.token_data,
+            server_nonce,
+            server_key,
+            padding,
+        )
+    }
+}
+
+/// Determine the padding from the encryption algorithm of a user identity token.
+fn padding_for_algorithm(encryption_algorithm: &str) -> Result<RsaPadding, StatusCode> {
+    match encryption_algorithm {
+        super::algorithms::ENC_RSA_15 => Ok(RsaPadding::Pkcs1),
+        super::algorithms::ENC_RSA_OAEP => Ok(RsaPadding::OaepSha1),
+        super::algorithms::ENC_RSA_OAEP_SHA256 => Ok(RsaPadding::OaepSha256),
+        _ => {
+            error!(
+                "User identity token has unsupported encryption algorithm \"{}\"",
+                encryption_algorithm
+            );
+            Err(StatusCode::BadIdentityTokenInvalid)
+        }
+    }
+}
+
 /// Encrypt a client side user's password using the server nonce and cert. This is described in table 176
 /// OPC UA part 4. This function is prefixed "legacy" because 1.04 describes another way of encrypting passwords.
 pub fn legacy_password_encrypt(
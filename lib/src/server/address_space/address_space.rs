@@ -3,7 +3,9 @@
 // Copyright (C) 2017-2022 Adam Lock
 
 //! Implementation of `AddressSpace`.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io;
 use std::sync::Arc;
 
 use chrono::Utc;
@@ -11,23 +13,30 @@ use chrono::Utc;
 use crate::sync::*;
 use crate::types::{
     node_ids::VariableId::*,
-    service_types::{BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass},
+    service_types::{
+        Argument, BrowseDirection, CallMethodRequest, CallMethodResult, NodeClass, Range,
+        RedundancySupport,
+    },
     status_code::StatusCode,
     *,
 };
 
 use crate::server::{
     address_space::{
-        node::{HasNodeId, NodeType},
+        instantiate::{self, InstantiateOptions, NodeIdStrategy},
+        node::{HasNodeId, NodeBase, NodeType},
+        nodeset_export::NodeSetExporter,
         object::{Object, ObjectBuilder},
         references::{Reference, ReferenceDirection, References},
-        variable::Variable,
-        AttrFnGetter,
+        variable::{ValueHandle, Variable, VariableBuilder},
+        AccessLevel, AttrFnGetter, AttrFnSetter, UserAccessLevel,
     },
     callbacks, constants,
     diagnostics::ServerDiagnostics,
     historical::HistoryServerCapabilities,
+    node_manager::NodeManager,
     session::SessionManager,
+    session_diagnostics::extension_object_array,
     state::ServerState,
 };
 
@@ -123,7 +132,33 @@ macro_rules! server_diagnostics_summary {
     };
 }
 
+/// Gets an array of diagnostics from the live diagnostics.
+macro_rules! server_diagnostics_array {
+    ($address_space: expr, $variable_id: expr, $encoding_id: expr, $array_fn: ident) => {
+        let server_diagnostics = $address_space.server_diagnostics.as_ref().unwrap().clone();
+        $address_space.set_variable_getter(
+            $variable_id,
+            move |_, timestamps_to_return, _, _, _, _| {
+                let values = server_diagnostics.write().$array_fn();
+                debug!(
+                    "Request to get server diagnostics array {}, len = {}",
+                    stringify!($variable_id),
+                    values.len()
+                );
+
+                let mut value = DataValue::from(extension_object_array($encoding_id, &values));
+                let now = DateTime::now();
+                value.set_timestamps(timestamps_to_return, now, now);
+                Ok(Some(value))
+            },
+        );
+    };
+}
+
 pub(crate) type MethodCallback = Box<dyn callbacks::Method + Send + Sync>;
+pub(crate) type AsyncMethodCallback = Arc<dyn callbacks::AsyncMethod>;
+/// A method callback that is locked on its own so it can be called without the address space lock
+pub(crate) type SharedMethodCallback = Arc<Mutex<MethodCallback>>;
 
 const OPCUA_INTERNAL_NAMESPACE_IDX: u16 = 1;
 
@@ -175,6 +210,10 @@ pub struct AddressSpace {
     internal_namespace: u16,
     /// The list of all registered namespaces.
     namespaces: Vec<String>,
+    /// Nodes whose written values are recorded in the server's journal
+    persistent_nodes: HashSet<NodeId>,
+    /// The node managers that serve the nodes of a namespace, by namespace index
+    node_managers: HashMap<u16, Arc<dyn NodeManager>>,
 }
 
 impl Default for AddressSpace {
@@ -191,6 +230,8 @@ impl Default for AddressSpace {
             // OPC UA namespace for its standard nodes. The second is the internal namespace used
             // by this implementation.
             namespaces: vec!["http://opcfoundation.org/UA/".to_string()],
+            persistent_nodes: HashSet::new(),
+            node_managers: HashMap::new(),
         }
     }
 }
@@ -238,6 +279,54 @@ impl AddressSpace {
         }
     }
 
+    /// Returns the registered namespaces. The position of each namespace is its index.
+    pub fn namespaces(&self) -> &[String] {
+        &self.namespaces
+    }
+
+    /// Registers a node manager that serves the nodes of the namespace, registering the namespace
+    /// if it does not exist. Returns the index of the namespace. Every service operation on a node
+    /// in the namespace is delegated to the node manager rather than to the nodes of this address
+    /// space. Unlike `register_namespace`, the default namespace does not change. The namespace of
+    /// OPC UA cannot be delegated, and it or a namespace that cannot be registered fails with
+    /// `BadInvalidArgument`.
+    pub fn register_node_manager(
+        &mut self,
+        namespace: &str,
+        node_manager: Arc<dyn NodeManager>,
+    ) -> Result<u16, StatusCode> {
+        let default_namespace = self.default_namespace;
+        let ns = self.register_namespace(namespace);
+        self.default_namespace = default_namespace;
+        match ns {
+            Ok(ns) if ns != 0 => {
+                self.node_managers.insert(ns, node_manager);
+                Ok(ns)
+            }
+            _ => {
+                error!(
+                    "The node manager of namespace {} cannot be registered",
+                    namespace
+                );
+                Err(StatusCode::BadInvalidArgument)
+            }
+        }
+    }
+
+    /// Returns the node manager that serves the nodes of the namespace, if there is one
+    pub fn node_manager(&self, namespace: u16) -> Option<Arc<dyn NodeManager>> {
+        self.node_managers.get(&namespace).cloned()
+    }
+
+    /// Returns the node manager that serves the node, if there is one
+    pub(crate) fn node_manager_for(&self, node_id: &NodeId) -> Option<Arc<dyn NodeManager>> {
+        if self.node_managers.is_empty() {
+            None
+        } else {
+            self.node_manager(node_id.namespace)
+        }
+    }
+
     /// Finds the namespace index of a given namespace
     pub fn namespace_index(&self, namespace: &str) -> Option<u16> {
         self.namespaces
@@ -273,6 +362,63 @@ impl AddressSpace {
         self.set_variable_value(Server_ServiceLevel, service_level, now, now);
     }
 
+    /// Sets the redundancy support of the server and the URIs of the servers in its redundant
+    /// set. A server with non-transparent redundancy has a `ServerRedundancy` object of type
+    /// `NonTransparentRedundancyType`, which has a `ServerUriArray` property.
+    pub fn set_server_redundancy(
+        &mut self,
+        redundancy_support: RedundancySupport,
+        server_uris: &[String],
+    ) {
+        let now = DateTime::now();
+        let server_redundancy_id: NodeId = ObjectId::Server_ServerRedundancy.into();
+        if !self.node_exists(&server_redundancy_id) {
+            return;
+        }
+        self.set_variable_value(
+            Server_ServerRedundancy_RedundancySupport,
+            redundancy_support as i32,
+            &now,
+            &now,
+        );
+        match redundancy_support {
+            RedundancySupport::None | RedundancySupport::Transparent => {}
+            _ => {
+                let _ = self.delete_reference(
+                    &server_redundancy_id,
+                    &ObjectTypeId::ServerRedundancyType.into(),
+                    ReferenceTypeId::HasTypeDefinition,
+                );
+                self.set_node_type(
+                    &server_redundancy_id,
+                    ObjectTypeId::NonTransparentRedundancyType,
+                );
+                let server_uri_array_id: NodeId = Server_ServerRedundancy_ServerUriArray.into();
+                if self.node_exists(&server_uri_array_id) {
+                    self.set_variable_value(
+                        Server_ServerRedundancy_ServerUriArray,
+                        Variant::from(server_uris),
+                        &now,
+                        &now,
+                    );
+                } else {
+                    let _ = VariableBuilder::new(
+                        &server_uri_array_id,
+                        "ServerUriArray",
+                        "ServerUriArray",
+                    )
+                    .property_of(server_redundancy_id)
+                    .has_type_definition(VariableTypeId::PropertyType)
+                    .data_type(DataTypeId::String)
+                    .value_rank(1)
+                    .array_dimensions(&[server_uris.len() as u32])
+                    .value(Variant::from(server_uris))
+                    .insert(self);
+                }
+            }
+        }
+    }
+
     /// Sets values for nodes representing the server.
     pub fn set_server_state(&mut self, server_state: Arc<RwLock<ServerState>>) {
         // Server state requires the generated address space, otherwise nothing
@@ -578,6 +724,62 @@ impl AddressSpace {
                     Server_ServerDiagnostics_ServerDiagnosticsSummary_RejectedRequestsCount,
                     rejected_requests_count
                 );
+                server_diagnostics_array!(
+                    self,
+                    Server_ServerDiagnostics_SessionsDiagnosticsSummary_SessionDiagnosticsArray,
+                    ObjectId::SessionDiagnosticsDataType_Encoding_DefaultBinary,
+                    session_diagnostics_array
+                );
+                server_diagnostics_array!(
+                    self,
+                    Server_ServerDiagnostics_SessionsDiagnosticsSummary_SessionSecurityDiagnosticsArray,
+                    ObjectId::SessionSecurityDiagnosticsDataType_Encoding_DefaultBinary,
+                    session_security_diagnostics_array
+                );
+                server_diagnostics_array!(
+                    self,
+                    Server_ServerDiagnostics_SubscriptionDiagnosticsArray,
+                    ObjectId::SubscriptionDiagnosticsDataType_Encoding_DefaultBinary,
+                    subscription_diagnostics_array
+                );
+                server_diagnostics_array!(
+                    self,
+                    Server_ServerDiagnostics_SamplingIntervalDiagnosticsArray,
+                    ObjectId::SamplingIntervalDiagnosticsDataType_Encoding_DefaultBinary,
+                    sampling_interval_diagnostics_array
+                );
+
+                // The enabled flag is writable so clients can switch diagnostics on or off. The
+                // session takes away write access from anonymous users.
+                let server_diagnostics = server_state.diagnostics.clone();
+                self.set_variable_getter(
+                    Server_ServerDiagnostics_EnabledFlag,
+                    move |_, timestamps_to_return, _, _, _, _| {
+                        let enabled = server_diagnostics.read().is_enabled();
+                        let mut value = DataValue::from(Variant::from(enabled));
+                        let now = DateTime::now();
+                        value.set_timestamps(timestamps_to_return, now, now);
+                        Ok(Some(value))
+                    },
+                );
+                let server_diagnostics = server_state.diagnostics.clone();
+                if let Some(ref mut v) =
+                    self.find_variable_mut(Server_ServerDiagnostics_EnabledFlag)
+                {
+                    v.set_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE);
+                    v.set_user_access_level(
+                        UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE,
+                    );
+                    v.set_value_setter(AttrFnSetter::new_boxed(
+                        move |_, _, _, data_value: DataValue| match data_value.value {
+                            Some(Variant::Boolean(enabled)) => {
+                                server_diagnostics.read().set_enabled(enabled);
+                                Ok(())
+                            }
+                            _ => Err(StatusCode::BadTypeMismatch),
+                        },
+                    ));
+                }
             }
 
             // ServiceLevel - 0-255 worst to best quality of service
@@ -846,6 +1048,57 @@ impl AddressSpace {
         );
     }
 
+    /// Creates an instance of an ObjectType or VariableType under the parent. The instance has
+    /// a child for every Mandatory InstanceDeclaration of the type and its supertypes, recursively,
+    /// with its TypeDefinition and references to its parent set as declared.
+    ///
+    /// The result maps the browse path of every created node, starting with the instance's own
+    /// browse name, e.g. `Pump1` and `Pump1/Motor/Speed`, to its node id.
+    pub fn instantiate<T, B>(
+        &mut self,
+        type_id: T,
+        parent_node_id: &NodeId,
+        browse_name: B,
+        node_id_strategy: NodeIdStrategy,
+    ) -> Result<BTreeMap<String, NodeId>, StatusCode>
+    where
+        T: Into<NodeId>,
+        B: Into<QualifiedName>,
+    {
+        self.instantiate_with_options(
+            type_id,
+            parent_node_id,
+            browse_name,
+            node_id_strategy,
+            &InstantiateOptions::default(),
+        )
+    }
+
+    /// Creates an instance of an ObjectType or VariableType under the parent like
+    /// [`instantiate`](#method.instantiate), also creating the Optional children and placeholder
+    /// nodes named in the options.
+    pub fn instantiate_with_options<T, B>(
+        &mut self,
+        type_id: T,
+        parent_node_id: &NodeId,
+        browse_name: B,
+        node_id_strategy: NodeIdStrategy,
+        options: &InstantiateOptions,
+    ) -> Result<BTreeMap<String, NodeId>, StatusCode>
+    where
+        T: Into<NodeId>,
+        B: Into<QualifiedName>,
+    {
+        instantiate::instantiate(
+            self,
+            &type_id.into(),
+            parent_node_id,
+            browse_name.into(),
+            node_id_strategy,
+            options,
+        )
+    }
+
     pub fn node_exists(&self, node_id: &NodeId) -> bool {
         self.node_map.contains_key(node_id)
     }
@@ -914,25 +1167,56 @@ impl AddressSpace {
 
     /// Deletes a node by its node id, and all of its properties and optionally any references to or from it it in the
     /// address space.
+    ///
+    /// The children of the node, i.e. the targets of HasComponent and HasProperty references, are
+    /// deleted with it unless they are also the children of another node. The references of a
+    /// deleted child are always removed so nothing is left behind of the instance.
     pub fn delete(&mut self, node_id: &NodeId, delete_target_references: bool) -> bool {
+        // Remove the node. This happens before its children so a loop of references terminates
+        let removed_node = self.node_map.remove(node_id);
+        self.persistent_nodes.remove(node_id);
         // Delete any children recursively
         if let Some(child_nodes) = self.find_aggregates_of(node_id) {
-            child_nodes.into_iter().for_each(|node_id| {
-                debug!("Deleting child node {}", node_id);
-                let _ = self.delete(&node_id, delete_target_references);
-            });
+            for child in child_nodes {
+                if self.node_exists(&child) && !self.has_other_parent(&child) {
+                    debug!("Deleting child node {}", child);
+                    let _ = self.delete(&child, true);
+                }
+            }
         }
-        // Remove the node
-        let removed_node = self.node_map.remove(node_id);
         // Remove references
         let removed_target_references = if delete_target_references {
             self.references.delete_node_references(node_id)
         } else {
             false
         };
+        self.update_last_modified();
         removed_node.is_some() || removed_target_references
     }
 
+    /// Tests if the node is aggregated by any node that still exists in the address space.
+    fn has_other_parent(&self, node_id: &NodeId) -> bool {
+        self.find_inverse_references(node_id, Some((ReferenceTypeId::Aggregates, true)))
+            .map(|references| references.iter().any(|r| self.node_exists(&r.target_node)))
+            .unwrap_or(false)
+    }
+
+    /// Marks a node as persistent, or not. Writes to a persistent node are recorded in the server's
+    /// journal, if it has one, and replayed when the server restarts. Nodes added through the
+    /// AddNodes service are always persistent.
+    pub fn set_persistent(&mut self, node_id: &NodeId, persistent: bool) {
+        if persistent {
+            self.persistent_nodes.insert(node_id.clone());
+        } else {
+            self.persistent_nodes.remove(node_id);
+        }
+    }
+
+    /// Tests if the node is persistent
+    pub fn is_persistent(&self, node_id: &NodeId) -> bool {
+        self.persistent_nodes.contains(node_id)
+    }
+
     /// Finds the matching reference and deletes it
     pub fn delete_reference<T>(
         &mut self,
@@ -1006,7 +1290,7 @@ impl AddressSpace {
     /// Set a variable value from its NodeId. The function will return false if the variable does
     /// not exist, or the node is not a variable.
     pub fn set_variable_value<N, V>(
-        &mut self,
+        &self,
         node_id: N,
         value: V,
         source_timestamp: &DateTime,
@@ -1022,7 +1306,7 @@ impl AddressSpace {
     /// Set a variable value from its NodeId. The function will return false if the variable does
     /// not exist, or the node is not a variable.
     pub fn set_variable_value_by_ref<V>(
-        &mut self,
+        &self,
         node_id: &NodeId,
         value: V,
         source_timestamp: &DateTime,
@@ -1031,7 +1315,7 @@ impl AddressSpace {
     where
         V: Into<Variant>,
     {
-        if let Some(ref mut variable) = self.find_variable_mut_by_ref(node_id) {
+        if let Some(variable) = self.find_variable_by_ref(node_id) {
             let _ = variable.set_value_direct(
                 value,
                 StatusCode::Good,
@@ -1044,6 +1328,18 @@ impl AddressSpace {
         }
     }
 
+    /// Returns a handle to the value of a variable, or None if the variable does not exist or the
+    /// node is not a variable. Values that change often can be updated through the handle
+    /// without holding any lock on the address space, so updates do not wait for, or hold up,
+    /// services that are reading from it.
+    pub fn value_handle<N>(&self, node_id: N) -> Option<ValueHandle>
+    where
+        N: Into<NodeId>,
+    {
+        self.find_variable(node_id)
+            .map(|variable| variable.value_handle())
+    }
+
     /// Gets a variable value with the supplied NodeId. The function will return Err if the
     /// NodeId does not exist or is not a variable.
     pub fn get_variable_value<N>(&self, node_id: N) -> Result<DataValue, ()>
@@ -1062,6 +1358,65 @@ impl AddressSpace {
             .ok_or_else(|| ())
     }
 
+    /// Finds the property of a node with the supplied browse name.
+    pub fn find_property(&self, node_id: &NodeId, browse_name: &str) -> Option<&Variable> {
+        self.find_references(node_id, Some((ReferenceTypeId::HasProperty, false)))?
+            .iter()
+            .filter_map(|r| self.find_variable_by_ref(&r.target_node))
+            .find(|v| v.browse_name().name.as_ref() == browse_name)
+    }
+
+    /// Returns the EURange property of an analog item as a (low, high) pair, or `None` if the node
+    /// has no EURange.
+    pub fn eu_range(&self, node_id: &NodeId) -> Option<(f64, f64)> {
+        let value = self
+            .find_property(node_id, "EURange")?
+            .value(
+                TimestampsToReturn::Neither,
+                NumericRange::None,
+                &QualifiedName::null(),
+                0.0,
+            )
+            .value;
+        if let Some(Variant::ExtensionObject(range)) = value {
+            range
+                .decode_inner::<Range>(&DecodingOptions::default())
+                .ok()
+                .map(|range| (range.low, range.high))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the arguments in the InputArguments or OutputArguments property of a method, or
+    /// `None` if the method has no such property.
+    pub fn method_arguments(&self, method_id: &NodeId, browse_name: &str) -> Option<Vec<Argument>> {
+        let value = self
+            .find_property(method_id, browse_name)?
+            .value(
+                TimestampsToReturn::Neither,
+                NumericRange::None,
+                &QualifiedName::null(),
+                0.0,
+            )
+            .value;
+        match value {
+            Some(Variant::Array(array)) => Some(
+                array
+                    .values
+                    .iter()
+                    .filter_map(|value| match value {
+                        Variant::ExtensionObject(argument) => argument
+                            .decode_inner::<Argument>(&DecodingOptions::default())
+                            .ok(),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
     /// Registers a method callback on the specified object id and method id
     pub fn register_method_handler<N>(&mut self, method_id: N, handler: MethodCallback)
     where
@@ -1079,6 +1434,23 @@ impl AddressSpace {
         }
     }
 
+    /// Registers an async method callback on the specified method id. The Call service awaits
+    /// an async callback without holding the address space lock.
+    pub fn register_async_method_handler<N>(&mut self, method_id: N, handler: AsyncMethodCallback)
+    where
+        N: Into<NodeId>,
+    {
+        let method_id = method_id.into();
+        if let Some(method) = self.find_mut(&method_id) {
+            match method {
+                NodeType::Method(method) => method.set_async_callback(handler),
+                _ => panic!("{} is not a method node", method_id),
+            }
+        } else {
+            panic!("{} method id does not exist", method_id);
+        }
+    }
+
     /// Test if the type definition is defined and valid for a class of the specified type.
     /// i.e. if we have a Variable or Object class that the type is a VariableType or ObjectType
     /// respectively.
@@ -1151,40 +1523,239 @@ impl AddressSpace {
     /// Calls require a registered handler to handle the method. If there is no handler, or if
     /// the request refers to a non existent object / method, the function will return an error.
     pub fn call_method(
-        &mut self,
+        &self,
         _server_state: &ServerState,
         session_id: &NodeId,
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
-    ) -> Result<CallMethodResult, StatusCode> {
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        let callback = self.method_callback(request)?;
+        let request = match self.validate_input_arguments(request) {
+            Ok(request) => request,
+            Err(result) => return result,
+        };
+        let result = {
+            let mut callback = trace_lock!(callback);
+            callback.call_with_diagnostics(session_id, session_manager, &request)?
+        };
+        self.validate_output_arguments(&request, result)
+    }
+
+    /// Returns the argument converted to the data type of its description, or `None` if the
+    /// argument does not match the data type, value rank and array dimensions of the description.
+    /// Only an argument of any data type may be null.
+    fn convert_argument(&self, argument: &Argument, value: &Variant) -> Option<Variant> {
+        let dimensions = value.dimensions();
+        let array_dimensions = argument.array_dimensions.as_deref();
+        if let Variant::Empty = value {
+            if argument.data_type == DataTypeId::BaseDataType.into() {
+                Some(Variant::Empty)
+            } else {
+                None
+            }
+        } else if Variant::dimensions_match(argument.value_rank, array_dimensions, &dimensions) {
+            self.convert_to_data_type(value, &argument.data_type)
+        } else {
+            None
+        }
+    }
+
+    /// Validates the input arguments of a call against the InputArguments of the method, and
+    /// returns the call with the arguments converted to their data types. A method without
+    /// InputArguments accepts any arguments.
+    ///
+    /// If the arguments are invalid, the result of the call is returned instead. It is an error
+    /// if the number of arguments is wrong, otherwise it has the status of each argument.
+    pub(crate) fn validate_input_arguments(
+        &self,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodRequest, Result<CallMethodResult, DiagnosticError>> {
+        let arguments = match self.method_arguments(&request.method_id, "InputArguments") {
+            Some(arguments) => arguments,
+            None => return Ok(request.clone()),
+        };
+        let input_arguments = request.input_arguments.as_deref().unwrap_or(&[]);
+        if input_arguments.len() != arguments.len() {
+            let status_code = if input_arguments.len() < arguments.len() {
+                StatusCode::BadArgumentsMissing
+            } else {
+                StatusCode::BadTooManyArguments
+            };
+            debug!("Method call fails {}", status_code);
+            return Err(Err(DiagnosticError::new(status_code).with_additional_info(
+                format!(
+                    "The method expects {} input arguments but was called with {}",
+                    arguments.len(),
+                    input_arguments.len()
+                ),
+            )));
+        }
+        if arguments.is_empty() {
+            return Ok(request.clone());
+        }
+        let converted = arguments
+            .iter()
+            .zip(input_arguments)
+            .map(|(argument, value)| self.convert_argument(argument, value))
+            .collect::<Vec<_>>();
+        if converted.iter().all(Option::is_some) {
+            Ok(CallMethodRequest {
+                input_arguments: Some(converted.into_iter().flatten().collect()),
+                ..request.clone()
+            })
+        } else {
+            error!(
+                "Method call to {:?} on {:?} has input arguments that do not match its InputArguments",
+                request.method_id, request.object_id
+            );
+            let input_argument_results = converted
+                .iter()
+                .map(|value| {
+                    if value.is_some() {
+                        StatusCode::Good
+                    } else {
+                        StatusCode::BadTypeMismatch
+                    }
+                })
+                .collect();
+            Err(Ok(CallMethodResult {
+                status_code: StatusCode::BadInvalidArgument,
+                input_argument_results: Some(input_argument_results),
+                input_argument_diagnostic_infos: None,
+                output_arguments: None,
+            }))
+        }
+    }
+
+    /// Validates the output arguments of a successful call against the OutputArguments of the
+    /// method, and returns the result with the arguments converted to their data types. Output
+    /// arguments that do not match are an error of the method. The result has the status of each
+    /// input argument if the method did not set them.
+    pub(crate) fn validate_output_arguments(
+        &self,
+        request: &CallMethodRequest,
+        result: CallMethodResult,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        let mut result = result;
+        if !result.status_code.is_good() {
+            return Ok(result);
+        }
+        if result.input_argument_results.is_none() {
+            if let Some(ref input_arguments) = request.input_arguments {
+                if !input_arguments.is_empty() {
+                    result.input_argument_results =
+                        Some(vec![StatusCode::Good; input_arguments.len()]);
+                }
+            }
+        }
+        let arguments = match self.method_arguments(&request.method_id, "OutputArguments") {
+            Some(arguments) => arguments,
+            None => return Ok(result),
+        };
+        let output_arguments = result.output_arguments.as_deref().unwrap_or(&[]);
+        if output_arguments.len() != arguments.len() {
+            error!(
+                "Method {:?} returned {} output arguments but has {} OutputArguments",
+                request.method_id,
+                output_arguments.len(),
+                arguments.len()
+            );
+            return Err(
+                DiagnosticError::new(StatusCode::BadInternalError).with_additional_info(format!(
+                    "The method returned {} output arguments but should return {}",
+                    output_arguments.len(),
+                    arguments.len()
+                )),
+            );
+        }
+        if arguments.is_empty() {
+            return Ok(result);
+        }
+        let output_arguments = arguments
+            .iter()
+            .zip(output_arguments)
+            .enumerate()
+            .map(|(idx, (argument, value))| {
+                self.convert_argument(argument, value).ok_or_else(|| {
+                    error!(
+                        "Method {:?} returned output argument {} of type {:?} that does not match its OutputArguments",
+                        request.method_id,
+                        idx,
+                        value.type_id()
+                    );
+                    DiagnosticError::new(StatusCode::BadInternalError).with_additional_info(
+                        format!(
+                            "The method returned output argument {} of the wrong type",
+                            argument.name
+                        ),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        result.output_arguments = Some(output_arguments);
+        Ok(result)
+    }
+
+    /// Returns the callback of the method in the request, if the request is valid. The callback
+    /// can be called after the address space lock is released.
+    pub(crate) fn method_callback(
+        &self,
+        request: &CallMethodRequest,
+    ) -> Result<SharedMethodCallback, DiagnosticError> {
+        self.validate_method_call(request)?;
+        // TODO check security - session / user may not have permission to call methods
+        if let Some(NodeType::Method(method)) = self.find_node(&request.method_id) {
+            method.callback()
+        } else {
+            Err(StatusCode::BadMethodInvalid.into())
+        }
+    }
+
+    /// Returns the async callback of the method in the request, if the request is valid and the
+    /// method has one.
+    pub(crate) fn async_method_callback(
+        &self,
+        request: &CallMethodRequest,
+    ) -> Option<AsyncMethodCallback> {
+        if self.validate_method_call(request).is_err() {
+            None
+        } else if let Some(NodeType::Method(method)) = self.find_node(&request.method_id) {
+            method.async_callback()
+        } else {
+            None
+        }
+    }
+
+    /// Tests that the object and method of the call exist and that the method is on the object
+    fn validate_method_call(&self, request: &CallMethodRequest) -> Result<(), DiagnosticError> {
         let (object_id, method_id) = (&request.object_id, &request.method_id);
-        // Handle the call
         if !is_object!(self, object_id) {
             error!(
                 "Method call to {:?} on {:?} but the node id is not recognized!",
                 method_id, object_id
             );
-            Err(StatusCode::BadNodeIdUnknown)
+            Err(DiagnosticError::new(StatusCode::BadNodeIdUnknown)
+                .with_additional_info(format!("Object {} does not exist", object_id)))
         } else if !is_method!(self, method_id) {
             error!(
                 "Method call to {:?} on {:?} but the method id is not recognized!",
                 method_id, object_id
             );
-            Err(StatusCode::BadMethodInvalid)
+            Err(DiagnosticError::new(StatusCode::BadMethodInvalid)
+                .with_additional_info(format!("Method {} does not exist", method_id)))
         } else if !self.method_exists_on_object(object_id, method_id) {
             error!(
                 "Method call to {:?} on {:?} but the method does not exist on the object!",
                 method_id, object_id
             );
-            Err(StatusCode::BadMethodInvalid)
-        } else if let Some(method) = self.find_mut(method_id) {
-            // TODO check security - session / user may not have permission to call methods
-            match method {
-                NodeType::Method(method) => method.call(session_id, session_manager, request),
-                _ => Err(StatusCode::BadMethodInvalid),
-            }
+            Err(
+                DiagnosticError::new(StatusCode::BadMethodInvalid).with_additional_info(format!(
+                    "Method {} is not a method of object {}",
+                    method_id, object_id
+                )),
+            )
         } else {
-            Err(StatusCode::BadMethodInvalid)
+            Ok(())
         }
     }
 
@@ -1205,6 +1776,56 @@ impl AddressSpace {
             }
         }
     }
+
+    /// Returns the built-in type that values of a data type are encoded as, if any
+    pub(crate) fn builtin_type(&self, data_type: &NodeId) -> Option<VariantTypeId> {
+        if self.is_subtype(data_type, &DataTypeId::Enumeration.into()) {
+            // Enumerations are encoded as Int32
+            return Some(VariantTypeId::Int32);
+        }
+        // The built-in types are the data types with ids 1 to 25 in namespace 0
+        (1..=25u32).find_map(|id| {
+            let builtin = NodeId::new(0, id);
+            match VariantTypeId::try_from(&builtin) {
+                Ok(builtin_type) if self.is_subtype(data_type, &builtin) => Some(builtin_type),
+                _ => None,
+            }
+        })
+    }
+
+    /// Returns the value if it is of the data type or a subtype of it, or else the value
+    /// implicitly converted to the built-in type of the data type. Returns `None` if the value
+    /// cannot be converted. An empty value is of any data type.
+    pub(crate) fn convert_to_data_type(
+        &self,
+        value: &Variant,
+        data_type: &NodeId,
+    ) -> Option<Variant> {
+        let value_data_type = match value {
+            Variant::Empty => return Some(Variant::Empty),
+            // An empty array has no elements of the wrong type
+            Variant::Array(array) if array.values.is_empty() => return Some(value.clone()),
+            Variant::Array(array) => match array.values[0] {
+                Variant::ExtensionObject(_) => DataTypeId::Structure.into(),
+                _ => value.array_data_type()?,
+            },
+            Variant::ExtensionObject(_) => DataTypeId::Structure.into(),
+            _ => value.scalar_data_type()?,
+        };
+        if self.is_subtype(&value_data_type, data_type)
+            || (value_data_type == DataTypeId::Structure.into()
+                && self.is_subtype(data_type, &value_data_type))
+        {
+            // The type check for structures is only as far as the data type being a structure
+            // because the type of the extension object is not known here.
+            Some(value.clone())
+        } else {
+            self.builtin_type(data_type)
+                .map(|builtin_type| value.convert(builtin_type))
+                .filter(|converted| !matches!(converted, Variant::Empty))
+        }
+    }
+
     /// Finds objects by a specified type.
     fn find_nodes_by_type<T>(
         &self,
@@ -1384,4 +2005,31 @@ impl AddressSpace {
     pub fn references(&self) -> &References {
         &self.references
     }
+
+    /// Iterates every node in the address space, in no particular order
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &NodeType> {
+        self.node_map.values()
+    }
+
+    /// Exports nodes from the address space as a NodeSet2 XML document. If `namespaces` is
+    /// supplied, only nodes in those namespaces are exported, otherwise every node is.
+    ///
+    /// The namespace indexes in the document are renumbered to only include the namespaces the
+    /// exported nodes refer to, so the document can be loaded into another server or tool
+    /// regardless of its namespace table.
+    pub fn export_nodeset<W>(&self, out: &mut W, namespaces: Option<&[u16]>) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        NodeSetExporter::new(self, namespaces).write(out)
+    }
+
+    /// Exports nodes from the address space as a NodeSet2 XML document held in a string. See
+    /// `export_nodeset()` for details.
+    pub fn export_nodeset_to_string(&self, namespaces: Option<&[u16]>) -> String {
+        let mut out = Vec::with_capacity(64 * 1024);
+        // Writing to a Vec cannot fail
+        let _ = self.export_nodeset(&mut out, namespaces);
+        String::from_utf8(out).unwrap()
+    }
 }
@@ -0,0 +1,337 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Contains builders for the variable types of the Data Access information model (Part 8), i.e.
+//! analog items with engineering units and ranges, and discrete items with named states.
+//!
+//! Each builder wraps a `VariableBuilder` which supplies the value, data type, access level and
+//! references of the item. The builder sets the type definition and inserts the properties of
+//! the item type alongside the variable.
+
+use crate::types::{
+    node_ids::{DataTypeId, ObjectId, VariableTypeId},
+    service_types::{EUInformation, EnumValueType, Range},
+    *,
+};
+
+use crate::server::address_space::{address_space::AddressSpace, variable::VariableBuilder};
+
+/// Inserts a property with the supplied value as a HasProperty child of the parent node
+fn insert_property<V>(
+    address_space: &mut AddressSpace,
+    parent_id: &NodeId,
+    browse_name: &str,
+    data_type: DataTypeId,
+    value: V,
+) -> bool
+where
+    V: Into<Variant>,
+{
+    let value = value.into();
+    // Skip ids that were assigned by hand
+    let mut property_id = NodeId::next_numeric(parent_id.namespace);
+    while address_space.node_exists(&property_id) {
+        property_id = NodeId::next_numeric(parent_id.namespace);
+    }
+    let builder = VariableBuilder::new(&property_id, browse_name, browse_name)
+        .property_of(parent_id.clone())
+        .has_type_definition(VariableTypeId::PropertyType)
+        .data_type(data_type);
+    let builder = if let Variant::Array(ref array) = value {
+        builder
+            .value_rank(1)
+            .array_dimensions(&[array.values.len() as u32])
+    } else {
+        builder
+    };
+    builder.value(value).insert(address_space)
+}
+
+fn range_value(range: &Range) -> Variant {
+    ExtensionObject::from_encodable(ObjectId::Range_Encoding_DefaultBinary, range).into()
+}
+
+/// A builder for variables of `AnalogItemType` or `AnalogUnitRangeType`. An analog item has an
+/// EURange, which is the range its value normally operates in and is the basis of percent
+/// deadband filters. It may also have an InstrumentRange and EngineeringUnits.
+pub struct AnalogItemBuilder {
+    variable: VariableBuilder,
+    type_id: VariableTypeId,
+    eu_range: Option<Range>,
+    instrument_range: Option<Range>,
+    engineering_units: Option<EUInformation>,
+}
+
+impl AnalogItemBuilder {
+    /// Creates a builder for an `AnalogItemType` variable. The EURange must be set before the
+    /// item is inserted.
+    pub fn new(variable: VariableBuilder) -> Self {
+        Self {
+            variable,
+            type_id: VariableTypeId::AnalogItemType,
+            eu_range: None,
+            instrument_range: None,
+            engineering_units: None,
+        }
+    }
+
+    /// Creates a builder for an `AnalogUnitRangeType` variable. The EURange and EngineeringUnits
+    /// must be set before the item is inserted.
+    pub fn new_unit_range(variable: VariableBuilder) -> Self {
+        Self {
+            type_id: VariableTypeId::AnalogUnitRangeType,
+            ..Self::new(variable)
+        }
+    }
+
+    /// Sets the range that the value of the item normally operates in.
+    pub fn eu_range(mut self, low: f64, high: f64) -> Self {
+        self.eu_range = Some(Range { low, high });
+        self
+    }
+
+    /// Sets the range of values that the instrument behind the item can return.
+    pub fn instrument_range(mut self, low: f64, high: f64) -> Self {
+        self.instrument_range = Some(Range { low, high });
+        self
+    }
+
+    /// Sets the units of the value, e.g. from `EUInformation::from_unece_code`.
+    pub fn engineering_units(mut self, engineering_units: EUInformation) -> Self {
+        self.engineering_units = Some(engineering_units);
+        self
+    }
+
+    /// Tests that the builder is in a valid state to insert the item, i.e. the mandatory
+    /// properties of its type are set and the variable is valid.
+    pub fn is_valid(&self) -> bool {
+        let has_units =
+            self.type_id != VariableTypeId::AnalogUnitRangeType || self.engineering_units.is_some();
+        self.eu_range.is_some() && has_units && self.variable.is_valid()
+    }
+
+    /// Inserts the item and its properties into the address space. This function will panic if
+    /// the item is in an invalid state.
+    pub fn insert(self, address_space: &mut AddressSpace) -> bool {
+        if !self.is_valid() {
+            panic!(
+                "The analog item is not valid, node id = {:?}",
+                self.variable.get_node_id()
+            );
+        }
+        let node_id = self.variable.get_node_id();
+        if !self
+            .variable
+            .has_type_definition(self.type_id)
+            .insert(address_space)
+        {
+            return false;
+        }
+        if let Some(ref eu_range) = self.eu_range {
+            insert_property(
+                address_space,
+                &node_id,
+                "EURange",
+                DataTypeId::Range,
+                range_value(eu_range),
+            );
+        }
+        if let Some(ref instrument_range) = self.instrument_range {
+            insert_property(
+                address_space,
+                &node_id,
+                "InstrumentRange",
+                DataTypeId::Range,
+                range_value(instrument_range),
+            );
+        }
+        if let Some(ref engineering_units) = self.engineering_units {
+            let value = ExtensionObject::from_encodable(
+                ObjectId::EUInformation_Encoding_DefaultBinary,
+                engineering_units,
+            );
+            insert_property(
+                address_space,
+                &node_id,
+                "EngineeringUnits",
+                DataTypeId::EUInformation,
+                value,
+            );
+        }
+        true
+    }
+}
+
+/// A builder for variables of `TwoStateDiscreteType`, i.e. a boolean with a name for each state.
+pub struct TwoStateDiscreteBuilder {
+    variable: VariableBuilder,
+    true_state: LocalizedText,
+    false_state: LocalizedText,
+}
+
+impl TwoStateDiscreteBuilder {
+    /// Creates a builder for a two state item with the names of its true and false states. The
+    /// variable should have a Boolean data type.
+    pub fn new<T, F>(variable: VariableBuilder, true_state: T, false_state: F) -> Self
+    where
+        T: Into<LocalizedText>,
+        F: Into<LocalizedText>,
+    {
+        Self {
+            variable,
+            true_state: true_state.into(),
+            false_state: false_state.into(),
+        }
+    }
+
+    /// Inserts the item and its properties into the address space. This function will panic if
+    /// the variable is in an invalid state.
+    pub fn insert(self, address_space: &mut AddressSpace) -> bool {
+        let node_id = self.variable.get_node_id();
+        if !self
+            .variable
+            .has_type_definition(VariableTypeId::TwoStateDiscreteType)
+            .insert(address_space)
+        {
+            return false;
+        }
+        insert_property(
+            address_space,
+            &node_id,
+            "TrueState",
+            DataTypeId::LocalizedText,
+            self.true_state,
+        );
+        insert_property(
+            address_space,
+            &node_id,
+            "FalseState",
+            DataTypeId::LocalizedText,
+            self.false_state,
+        );
+        true
+    }
+}
+
+/// A builder for variables of `MultiStateDiscreteType`, i.e. an unsigned integer whose value is
+/// the index of a state in its EnumStrings.
+pub struct MultiStateDiscreteBuilder {
+    variable: VariableBuilder,
+    enum_strings: Vec<LocalizedText>,
+}
+
+impl MultiStateDiscreteBuilder {
+    /// Creates a builder for a multi state item with the names of its states, starting from
+    /// the name of state 0.
+    pub fn new<I, S>(variable: VariableBuilder, enum_strings: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<LocalizedText>,
+    {
+        Self {
+            variable,
+            enum_strings: enum_strings.into_iter().map(|s| s.into()).collect(),
+        }
+    }
+
+    /// Inserts the item and its properties into the address space. This function will panic if
+    /// the variable is in an invalid state.
+    pub fn insert(self, address_space: &mut AddressSpace) -> bool {
+        let node_id = self.variable.get_node_id();
+        if !self
+            .variable
+            .has_type_definition(VariableTypeId::MultiStateDiscreteType)
+            .insert(address_space)
+        {
+            return false;
+        }
+        let enum_strings = self
+            .enum_strings
+            .into_iter()
+            .map(Variant::from)
+            .collect::<Vec<_>>();
+        insert_property(
+            address_space,
+            &node_id,
+            "EnumStrings",
+            DataTypeId::LocalizedText,
+            (VariantTypeId::LocalizedText, enum_strings),
+        );
+        true
+    }
+}
+
+/// A builder for variables of `MultiStateValueDiscreteType`, i.e. a number whose states are
+/// described by EnumValues which need not be contiguous.
+pub struct MultiStateValueDiscreteBuilder {
+    variable: VariableBuilder,
+    enum_values: Vec<EnumValueType>,
+}
+
+impl MultiStateValueDiscreteBuilder {
+    /// Creates a builder for a multi state value item with the value and name of each state.
+    pub fn new(variable: VariableBuilder, enum_values: &[EnumValueType]) -> Self {
+        Self {
+            variable,
+            enum_values: enum_values.to_vec(),
+        }
+    }
+
+    /// Inserts the item and its properties into the address space. The ValueAsText property is
+    /// set to the name of the state matching the initial value of the variable. This function
+    /// will panic if the variable is in an invalid state.
+    pub fn insert(self, address_space: &mut AddressSpace) -> bool {
+        let Self {
+            variable,
+            enum_values,
+        } = self;
+        let node_id = variable.get_node_id();
+        if !variable
+            .has_type_definition(VariableTypeId::MultiStateValueDiscreteType)
+            .insert(address_space)
+        {
+            return false;
+        }
+        let value = address_space
+            .find_variable_by_ref(&node_id)
+            .and_then(|v| {
+                v.value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0f64,
+                )
+                .value
+            })
+            .and_then(|v| v.as_f64());
+        let value_as_text = value
+            .and_then(|value| enum_values.iter().find(|e| e.value as f64 == value))
+            .map(|e| e.display_name.clone())
+            .unwrap_or_else(LocalizedText::null);
+        let enum_values = enum_values
+            .iter()
+            .map(|e| {
+                Variant::from(ExtensionObject::from_encodable(
+                    ObjectId::EnumValueType_Encoding_DefaultBinary,
+                    e,
+                ))
+            })
+            .collect::<Vec<_>>();
+        insert_property(
+            address_space,
+            &node_id,
+            "EnumValues",
+            DataTypeId::EnumValueType,
+            (VariantTypeId::ExtensionObject, enum_values),
+        );
+        insert_property(
+            address_space,
+            &node_id,
+            "ValueAsText",
+            DataTypeId::LocalizedText,
+            value_as_text,
+        );
+        true
+    }
+}
@@ -0,0 +1,651 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides functions for walking the InstanceDeclarations of a type definition and creating the
+//! corresponding children of an instance.
+
+use std::collections::BTreeMap;
+
+use crate::types::{
+    node_ids::{ObjectId, ObjectTypeId, ReferenceTypeId},
+    service_types::NodeClass,
+    status_code::StatusCode,
+    *,
+};
+
+use crate::server::address_space::{
+    address_space::AddressSpace,
+    node::{NodeBase, NodeType},
+    object::Object,
+    references::ReferenceDirection,
+    variable::Variable,
+    EventNotifier,
+};
+
+/// The attributes copied from an InstanceDeclaration to the node instantiated from it. Attributes
+/// that a node class does not have are skipped.
+const COPIED_ATTRIBUTES: [AttributeId; 11] = [
+    AttributeId::Description,
+    AttributeId::WriteMask,
+    AttributeId::UserWriteMask,
+    AttributeId::EventNotifier,
+    AttributeId::DataType,
+    AttributeId::ValueRank,
+    AttributeId::ArrayDimensions,
+    AttributeId::AccessLevel,
+    AttributeId::UserAccessLevel,
+    AttributeId::MinimumSamplingInterval,
+    AttributeId::Historizing,
+];
+
+/// The ModellingRule of an InstanceDeclaration
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ModellingRule {
+    Mandatory,
+    Optional,
+    MandatoryPlaceholder,
+    OptionalPlaceholder,
+    ExposesItsArray,
+}
+
+impl ModellingRule {
+    fn from_node_id(node_id: &NodeId) -> Option<ModellingRule> {
+        match node_id.as_object_id() {
+            Ok(ObjectId::ModellingRule_Mandatory) => Some(ModellingRule::Mandatory),
+            Ok(ObjectId::ModellingRule_Optional) => Some(ModellingRule::Optional),
+            Ok(ObjectId::ModellingRule_MandatoryPlaceholder) => {
+                Some(ModellingRule::MandatoryPlaceholder)
+            }
+            Ok(ObjectId::ModellingRule_OptionalPlaceholder) => {
+                Some(ModellingRule::OptionalPlaceholder)
+            }
+            Ok(ObjectId::ModellingRule_ExposesItsArray) => Some(ModellingRule::ExposesItsArray),
+            _ => None,
+        }
+    }
+}
+
+/// A child of a type (or of another InstanceDeclaration) that has a ModellingRule.
+#[derive(Debug, Clone)]
+pub(crate) struct InstanceDeclaration {
+    /// The node id of the declaration itself
+    pub node_id: NodeId,
+    pub browse_name: QualifiedName,
+    pub node_class: NodeClass,
+    /// The reference type from the parent to the declaration, e.g. HasComponent
+    pub reference_type_id: NodeId,
+    pub modelling_rule: ModellingRule,
+    /// The type definition of the declaration, if it is an Object or Variable
+    pub type_definition: Option<NodeId>,
+}
+
+/// Returns the type followed by each of its supertypes, most derived first.
+pub(crate) fn type_hierarchy(address_space: &AddressSpace, type_id: &NodeId) -> Vec<NodeId> {
+    let mut result = vec![];
+    let mut current = Some(type_id.clone());
+    while let Some(type_id) = current {
+        if result.contains(&type_id) {
+            // A loop in the hierarchy, which is a broken address space
+            error!("Type {} is its own supertype", type_id);
+            break;
+        }
+        current = address_space
+            .find_inverse_references(&type_id, Some((ReferenceTypeId::HasSubtype, false)))
+            .and_then(|references| references.first().map(|r| r.target_node.clone()));
+        result.push(type_id);
+    }
+    result
+}
+
+/// Finds the InstanceDeclarations of the sources, which are searched in order. A declaration
+/// in an earlier source overrides a declaration with the same browse name in a later one, so
+/// sources should be supplied most derived first.
+pub(crate) fn instance_declarations(
+    address_space: &AddressSpace,
+    sources: &[NodeId],
+) -> Vec<InstanceDeclaration> {
+    let mut browse_names = Vec::new();
+    let mut result = Vec::new();
+    for source in sources {
+        if let Some(references) =
+            address_space.find_references(source, Some((ReferenceTypeId::Aggregates, true)))
+        {
+            for r in references {
+                if let Some(declaration) =
+                    instance_declaration(address_space, &r.target_node, &r.reference_type)
+                {
+                    if !browse_names.contains(&declaration.browse_name) {
+                        browse_names.push(declaration.browse_name.clone());
+                        result.push(declaration);
+                    }
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Finds the InstanceDeclaration with the browse name in the type or one of its supertypes.
+pub(crate) fn find_instance_declaration(
+    address_space: &AddressSpace,
+    type_id: &NodeId,
+    browse_name: &QualifiedName,
+) -> Option<InstanceDeclaration> {
+    instance_declarations(address_space, &type_hierarchy(address_space, type_id))
+        .into_iter()
+        .find(|d| d.browse_name == *browse_name)
+}
+
+fn instance_declaration(
+    address_space: &AddressSpace,
+    node_id: &NodeId,
+    reference_type_id: &NodeId,
+) -> Option<InstanceDeclaration> {
+    let node = address_space.find_node(node_id)?;
+    let modelling_rule = address_space
+        .find_references(node_id, Some((ReferenceTypeId::HasModellingRule, false)))?
+        .iter()
+        .find_map(|r| ModellingRule::from_node_id(&r.target_node))?;
+    let type_definition = address_space
+        .find_references(node_id, Some((ReferenceTypeId::HasTypeDefinition, false)))
+        .and_then(|references| references.first().map(|r| r.target_node.clone()));
+    Some(InstanceDeclaration {
+        node_id: node_id.clone(),
+        browse_name: node.as_node().browse_name(),
+        node_class: node.node_class(),
+        reference_type_id: reference_type_id.clone(),
+        modelling_rule,
+        type_definition,
+    })
+}
+
+/// Makes a node id for a child that is derived from its parent's node id and its browse name,
+/// e.g. `ns=2;s=Pump1/Speed`. The same parent and browse name always produce the same node id.
+pub(crate) fn child_node_id(parent_id: &NodeId, browse_name: &QualifiedName) -> NodeId {
+    let parent = match parent_id.identifier {
+        Identifier::String(ref s) => s.as_ref().to_string(),
+        ref identifier => identifier.to_string(),
+    };
+    NodeId::new(
+        parent_id.namespace,
+        format!("{}/{}", parent, browse_name.name.as_ref()),
+    )
+}
+
+/// A function that returns the node id for a node with the parent and browse name
+pub type NodeIdFn = dyn FnMut(&NodeId, &QualifiedName) -> NodeId;
+
+/// Decides the node ids of the nodes created by [`AddressSpace::instantiate`].
+///
+/// [`AddressSpace::instantiate`]: ../address_space/struct.AddressSpace.html#method.instantiate
+pub enum NodeIdStrategy {
+    /// The instance is given a string node id of its browse name in the namespace, and each
+    /// child is given its parent's node id followed by its own browse name, e.g.
+    /// `ns=2;s=Pump1` and `ns=2;s=Pump1/Speed`.
+    BrowsePath(u16),
+    /// Every node is given the next numeric node id in the namespace.
+    Numeric(u16),
+    /// The function is called with the parent's node id and the browse name of each node.
+    Custom(Box<NodeIdFn>),
+}
+
+impl NodeIdStrategy {
+    fn instance_node_id(&mut self, parent_id: &NodeId, browse_name: &QualifiedName) -> NodeId {
+        match self {
+            NodeIdStrategy::BrowsePath(namespace) => {
+                NodeId::new(*namespace, browse_name.name.as_ref().to_string())
+            }
+            _ => self.child_node_id(parent_id, browse_name),
+        }
+    }
+
+    fn child_node_id(&mut self, parent_id: &NodeId, browse_name: &QualifiedName) -> NodeId {
+        match self {
+            NodeIdStrategy::BrowsePath(_) => child_node_id(parent_id, browse_name),
+            NodeIdStrategy::Numeric(namespace) => NodeId::next_numeric(*namespace),
+            NodeIdStrategy::Custom(f) => f(parent_id, browse_name),
+        }
+    }
+}
+
+/// Options for [`AddressSpace::instantiate_with_options`]. Children are identified by their
+/// browse path, i.e. the browse names from the instance to the child separated by `/`, starting
+/// with the browse name of the instance itself, e.g. `Pump1/Motor/Temperature`. These are the
+/// same paths that the result of the instantiation is keyed by.
+///
+/// [`AddressSpace::instantiate_with_options`]: ../address_space/struct.AddressSpace.html#method.instantiate_with_options
+#[derive(Debug, Default, Clone)]
+pub struct InstantiateOptions {
+    /// Browse paths of the Optional children to create
+    optional: Vec<String>,
+    /// Browse paths of placeholders and the browse names of the nodes to create for them
+    placeholders: Vec<(String, QualifiedName)>,
+    /// Reference type from the parent to the instance
+    reference_type_id: Option<NodeId>,
+}
+
+impl InstantiateOptions {
+    pub fn new() -> InstantiateOptions {
+        InstantiateOptions::default()
+    }
+
+    /// Opts in to creating an Optional (or ExposesItsArray) child, e.g. `Pump1/Location`.
+    pub fn optional<T>(mut self, browse_path: T) -> Self
+    where
+        T: Into<String>,
+    {
+        self.optional.push(browse_path.into());
+        self
+    }
+
+    /// Creates a node with the browse name in place of a MandatoryPlaceholder or
+    /// OptionalPlaceholder child. The browse path is that of the placeholder, e.g.
+    /// `Pump1/<Sensor>`. A placeholder may be used more than once to create several nodes.
+    /// Children of the created node are identified by paths through its own browse name, e.g.
+    /// `Pump1/Pressure/EURange`.
+    pub fn placeholder<T, B>(mut self, browse_path: T, browse_name: B) -> Self
+    where
+        T: Into<String>,
+        B: Into<QualifiedName>,
+    {
+        self.placeholders
+            .push((browse_path.into(), browse_name.into()));
+        self
+    }
+
+    /// Sets the reference type from the parent to the instance. The default is Organizes when
+    /// the parent is a folder, or HasComponent otherwise.
+    pub fn reference_type<T>(mut self, reference_type_id: T) -> Self
+    where
+        T: Into<NodeId>,
+    {
+        self.reference_type_id = Some(reference_type_id.into());
+        self
+    }
+}
+
+/// Walks InstanceDeclarations and creates the nodes of an instance.
+struct Instantiator<'a> {
+    address_space: &'a mut AddressSpace,
+    node_id_strategy: NodeIdStrategy,
+    options: &'a InstantiateOptions,
+    /// The created nodes by their browse path, including the instance itself
+    nodes: BTreeMap<String, NodeId>,
+    /// The InstanceDeclarations that the node being created is nested in. A declaration that is
+    /// nested in itself, e.g. through a Mandatory child with the type that declares it, would
+    /// otherwise be instantiated forever.
+    declaration_path: Vec<NodeId>,
+}
+
+impl<'a> Instantiator<'a> {
+    /// Creates the children of the instance from the InstanceDeclarations of the sources, then
+    /// recurses into the children. An error is returned if a declaration is nested in itself.
+    fn instantiate_children(
+        &mut self,
+        instance_id: &NodeId,
+        instance_path: &str,
+        sources: &[NodeId],
+    ) -> Result<(), StatusCode> {
+        let declarations = instance_declarations(self.address_space, sources);
+        for declaration in declarations.iter() {
+            let browse_path = join_browse_path(instance_path, &declaration.browse_name);
+            let browse_names = match declaration.modelling_rule {
+                ModellingRule::Mandatory => vec![declaration.browse_name.clone()],
+                ModellingRule::Optional | ModellingRule::ExposesItsArray => {
+                    if self.options.optional.contains(&browse_path) {
+                        vec![declaration.browse_name.clone()]
+                    } else {
+                        vec![]
+                    }
+                }
+                ModellingRule::MandatoryPlaceholder | ModellingRule::OptionalPlaceholder => {
+                    let browse_names = self
+                        .options
+                        .placeholders
+                        .iter()
+                        .filter(|(path, _)| *path == browse_path)
+                        .map(|(_, browse_name)| browse_name.clone())
+                        .collect::<Vec<_>>();
+                    if browse_names.is_empty()
+                        && declaration.modelling_rule == ModellingRule::MandatoryPlaceholder
+                    {
+                        warn!(
+                            "Instance {} has no node for the mandatory placeholder {}",
+                            instance_id, browse_path
+                        );
+                    }
+                    browse_names
+                }
+            };
+            for browse_name in browse_names {
+                if has_child(self.address_space, instance_id, &browse_name) {
+                    // The instance already has the child, e.g. it was added explicitly
+                    continue;
+                }
+                if self.declaration_path.contains(&declaration.node_id) {
+                    error!(
+                        "InstanceDeclaration {} cannot be instantiated at {} because it is nested in itself",
+                        declaration.node_id, browse_path
+                    );
+                    return Err(StatusCode::BadInvalidArgument);
+                }
+                let node_id = self
+                    .node_id_strategy
+                    .child_node_id(instance_id, &browse_name);
+                if let Some(child_id) = instantiate_declaration(
+                    self.address_space,
+                    instance_id,
+                    declaration,
+                    browse_name.clone(),
+                    node_id,
+                )? {
+                    let child_path = join_browse_path(instance_path, &browse_name);
+                    self.nodes.insert(child_path.clone(), child_id.clone());
+                    // Children declared on the declaration itself override those of its type
+                    // definition
+                    let mut sources = vec![declaration.node_id.clone()];
+                    if let Some(ref type_definition) = declaration.type_definition {
+                        sources.extend(type_hierarchy(self.address_space, type_definition));
+                    }
+                    self.declaration_path.push(declaration.node_id.clone());
+                    let result = self.instantiate_children(&child_id, &child_path, &sources);
+                    self.declaration_path.pop();
+                    result?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the nodes that were created, after an error
+    fn delete_nodes(&mut self) {
+        let address_space = &mut self.address_space;
+        self.nodes.values().for_each(|node_id| {
+            let _ = address_space.delete(node_id, true);
+        });
+    }
+}
+
+fn join_browse_path(parent_path: &str, browse_name: &QualifiedName) -> String {
+    if parent_path.is_empty() {
+        browse_name.name.as_ref().to_string()
+    } else {
+        format!("{}/{}", parent_path, browse_name.name.as_ref())
+    }
+}
+
+/// Creates the children of an instance for every Mandatory InstanceDeclaration of its type
+/// definition and supertypes, recursing into the children. The node ids of the children are
+/// derived from the instance's node id. Returns the node ids of the created nodes, or an error
+/// if the type cannot be instantiated, in which case no children are left behind.
+pub(crate) fn instantiate_mandatory(
+    address_space: &mut AddressSpace,
+    instance_id: &NodeId,
+    type_id: &NodeId,
+) -> Result<Vec<NodeId>, StatusCode> {
+    let options = InstantiateOptions::default();
+    let sources = type_hierarchy(address_space, type_id);
+    let mut instantiator = Instantiator {
+        address_space,
+        node_id_strategy: NodeIdStrategy::BrowsePath(instance_id.namespace),
+        options: &options,
+        nodes: BTreeMap::new(),
+        declaration_path: Vec::new(),
+    };
+    if let Err(status_code) = instantiator.instantiate_children(instance_id, "", &sources) {
+        instantiator.delete_nodes();
+        return Err(status_code);
+    }
+    Ok(instantiator.nodes.into_values().collect())
+}
+
+/// Creates an instance of the ObjectType or VariableType under the parent, including its
+/// children. See [`AddressSpace::instantiate_with_options`].
+///
+/// [`AddressSpace::instantiate_with_options`]: ../address_space/struct.AddressSpace.html#method.instantiate_with_options
+pub(crate) fn instantiate(
+    address_space: &mut AddressSpace,
+    type_id: &NodeId,
+    parent_id: &NodeId,
+    browse_name: QualifiedName,
+    mut node_id_strategy: NodeIdStrategy,
+    options: &InstantiateOptions,
+) -> Result<BTreeMap<String, NodeId>, StatusCode> {
+    let parent_node_class = match address_space.find_node(parent_id) {
+        Some(parent) => parent.node_class(),
+        None => {
+            error!(
+                "Cannot instantiate under parent {} which does not exist",
+                parent_id
+            );
+            return Err(StatusCode::BadParentNodeIdInvalid);
+        }
+    };
+    let node_id = node_id_strategy.instance_node_id(parent_id, &browse_name);
+    if address_space.node_exists(&node_id) {
+        error!("Cannot instantiate because node {} already exists", node_id);
+        return Err(StatusCode::BadNodeIdExists);
+    }
+    if has_child(address_space, parent_id, &browse_name) {
+        error!(
+            "Cannot instantiate because parent {} already has a child named {}",
+            parent_id, browse_name.name
+        );
+        return Err(StatusCode::BadBrowseNameDuplicated);
+    }
+
+    let display_name = LocalizedText::new("", browse_name.name.as_ref());
+    let node: NodeType = match address_space.find_node(type_id) {
+        Some(NodeType::ObjectType(ref object_type)) if !object_type.is_abstract() => Object::new(
+            &node_id,
+            browse_name.clone(),
+            display_name,
+            EventNotifier::empty(),
+        )
+        .into(),
+        Some(NodeType::VariableType(ref variable_type)) if !variable_type.is_abstract() => {
+            let value = variable_type
+                .value()
+                .and_then(|v| v.value)
+                .unwrap_or(Variant::Empty);
+            let mut variable = Variable::new_data_value(
+                &node_id,
+                browse_name.clone(),
+                display_name,
+                variable_type.data_type(),
+                Some(variable_type.value_rank()),
+                None,
+                value,
+            );
+            if let Some(array_dimensions) = variable_type.array_dimensions() {
+                variable.set_array_dimensions(&array_dimensions);
+            }
+            variable.into()
+        }
+        _ => {
+            error!(
+                "Cannot instantiate {} which is not a concrete ObjectType or VariableType",
+                type_id
+            );
+            return Err(StatusCode::BadTypeDefinitionInvalid);
+        }
+    };
+
+    let reference_type_id = options.reference_type_id.clone().unwrap_or_else(|| {
+        let is_folder = parent_node_class == NodeClass::Object
+            && address_space
+                .find_references(parent_id, Some((ReferenceTypeId::HasTypeDefinition, false)))
+                .and_then(|references| references.first().map(|r| r.target_node.clone()))
+                .map(|type_id| address_space.is_subtype(&type_id, &ObjectTypeId::FolderType.into()))
+                .unwrap_or(false);
+        if is_folder {
+            ReferenceTypeId::Organizes.into()
+        } else {
+            ReferenceTypeId::HasComponent.into()
+        }
+    });
+
+    address_space.insert(
+        node,
+        Some(&[(parent_id, &reference_type_id, ReferenceDirection::Inverse)]),
+    );
+    address_space.set_node_type(&node_id, type_id.clone());
+
+    let sources = type_hierarchy(address_space, type_id);
+    let instance_path = browse_name.name.as_ref().to_string();
+    let mut instantiator = Instantiator {
+        address_space,
+        node_id_strategy,
+        options,
+        nodes: BTreeMap::new(),
+        declaration_path: Vec::new(),
+    };
+    instantiator
+        .nodes
+        .insert(instance_path.clone(), node_id.clone());
+    if let Err(status_code) = instantiator.instantiate_children(&node_id, &instance_path, &sources)
+    {
+        instantiator.delete_nodes();
+        return Err(status_code);
+    }
+    Ok(instantiator.nodes)
+}
+
+/// Tests if the parent has a hierarchically referenced child with the browse name
+fn has_child(
+    address_space: &AddressSpace,
+    parent_id: &NodeId,
+    browse_name: &QualifiedName,
+) -> bool {
+    address_space
+        .find_hierarchical_references(parent_id)
+        .map(|children| {
+            children.iter().any(|child_id| {
+                address_space
+                    .find_node(child_id)
+                    .map(|child| child.as_node().browse_name() == *browse_name)
+                    .unwrap_or(false)
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Creates a copy of the InstanceDeclaration as a child of the parent and returns its node id.
+/// Only Objects and Variables are copied. Methods stay on the type, where they are found when they
+/// are called on the instance, so nothing is created for them. An error is returned if a node with
+/// the child's node id already exists.
+fn instantiate_declaration(
+    address_space: &mut AddressSpace,
+    parent_id: &NodeId,
+    declaration: &InstanceDeclaration,
+    browse_name: QualifiedName,
+    node_id: NodeId,
+) -> Result<Option<NodeId>, StatusCode> {
+    if address_space.node_exists(&node_id) {
+        error!(
+            "InstanceDeclaration {} cannot be instantiated because node {} already exists",
+            declaration.node_id, node_id
+        );
+        return Err(StatusCode::BadNodeIdExists);
+    }
+
+    let source = address_space
+        .find_node(&declaration.node_id)
+        .ok_or_else(|| {
+            error!(
+                "InstanceDeclaration {} cannot be instantiated because it does not exist",
+                declaration.node_id
+            );
+            StatusCode::BadNodeIdUnknown
+        })?;
+    let mut node: NodeType = match source {
+        NodeType::Object(ref source) => Object::new(
+            &node_id,
+            browse_name.clone(),
+            display_name(
+                source.display_name(),
+                &declaration.browse_name,
+                &browse_name,
+            ),
+            EventNotifier::empty(),
+        )
+        .into(),
+        NodeType::Variable(ref source) => {
+            let value = source
+                .value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0.0,
+                )
+                .value
+                .unwrap_or(Variant::Empty);
+            Variable::new_data_value(
+                &node_id,
+                browse_name.clone(),
+                display_name(
+                    source.display_name(),
+                    &declaration.browse_name,
+                    &browse_name,
+                ),
+                source.data_type(),
+                Some(source.value_rank()),
+                None,
+                value,
+            )
+            .into()
+        }
+        _ => {
+            debug!(
+                "InstanceDeclaration {} of class {:?} is not instantiated",
+                declaration.node_id, declaration.node_class
+            );
+            return Ok(None);
+        }
+    };
+
+    if let Some(source) = address_space.find_node(&declaration.node_id) {
+        let source = source.as_node();
+        COPIED_ATTRIBUTES.iter().for_each(|attribute_id| {
+            if let Some(value) = source
+                .get_attribute(
+                    TimestampsToReturn::Neither,
+                    *attribute_id,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                )
+                .and_then(|v| v.value)
+            {
+                let _ = node.as_mut_node().set_attribute(*attribute_id, value);
+            }
+        });
+    }
+
+    address_space.insert(
+        node,
+        Some(&[(
+            parent_id,
+            &declaration.reference_type_id,
+            ReferenceDirection::Inverse,
+        )]),
+    );
+    if let Some(ref type_definition) = declaration.type_definition {
+        address_space.set_node_type(&node_id, type_definition.clone());
+    }
+    Ok(Some(node_id))
+}
+
+/// The display name of an instantiated node is that of its declaration, unless the node was
+/// created for a placeholder, in which case it is the node's own browse name.
+fn display_name(
+    declaration_display_name: LocalizedText,
+    declaration_browse_name: &QualifiedName,
+    browse_name: &QualifiedName,
+) -> LocalizedText {
+    if declaration_browse_name == browse_name {
+        declaration_display_name
+    } else {
+        LocalizedText::new("", browse_name.name.as_ref())
+    }
+}
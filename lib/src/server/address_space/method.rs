@@ -10,7 +10,7 @@ use crate::sync::*;
 use crate::types::service_types::{Argument, MethodAttributes};
 
 use super::{
-    address_space::MethodCallback,
+    address_space::{AsyncMethodCallback, MethodCallback, SharedMethodCallback},
     base::Base,
     node::{Node, NodeBase},
     variable::VariableBuilder,
@@ -42,6 +42,12 @@ impl MethodBuilder {
         self
     }
 
+    /// Sets an async callback that the Call service awaits without holding the address space lock.
+    pub fn async_callback(mut self, callback: AsyncMethodCallback) -> Self {
+        self.node.set_async_callback(callback);
+        self
+    }
+
     fn args_to_variant(arguments: &[Argument]) -> Variant {
         let arguments = arguments
             .iter()
@@ -83,7 +89,9 @@ pub struct Method {
     executable: bool,
     user_executable: bool,
     #[derivative(Debug = "ignore")]
-    callback: Option<MethodCallback>,
+    callback: Option<SharedMethodCallback>,
+    #[derivative(Debug = "ignore")]
+    async_callback: Option<AsyncMethodCallback>,
 }
 
 impl Default for Method {
@@ -93,6 +101,7 @@ impl Default for Method {
             executable: false,
             user_executable: false,
             callback: None,
+            async_callback: None,
         }
     }
 }
@@ -165,6 +174,7 @@ impl Method {
             executable,
             user_executable,
             callback: None,
+            async_callback: None,
         }
     }
 
@@ -228,28 +238,46 @@ impl Method {
     }
 
     pub fn set_callback(&mut self, callback: MethodCallback) {
-        self.callback = Some(callback);
+        self.callback = Some(Arc::new(Mutex::new(callback)));
     }
 
     pub fn has_callback(&self) -> bool {
-        self.callback.is_some()
+        self.callback.is_some() || self.async_callback.is_some()
+    }
+
+    pub fn set_async_callback(&mut self, callback: AsyncMethodCallback) {
+        self.async_callback = Some(callback);
+    }
+
+    /// Returns the async callback of this method, if it has one
+    pub fn async_callback(&self) -> Option<AsyncMethodCallback> {
+        self.async_callback.clone()
     }
 
     pub fn call(
-        &mut self,
+        &self,
         session_id: &NodeId,
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
-    ) -> Result<CallMethodResult, StatusCode> {
-        if let Some(ref mut callback) = self.callback {
-            // Call the handler
-            callback.call(session_id, session_manager, request)
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        let callback = self.callback()?;
+        // Call the handler
+        let mut callback = trace_lock!(callback);
+        callback.call_with_diagnostics(session_id, session_manager, request)
+    }
+
+    /// Returns the callback of this method, which can be called after the address space lock is
+    /// released, or an error if it has none
+    pub(crate) fn callback(&self) -> Result<SharedMethodCallback, DiagnosticError> {
+        if let Some(ref callback) = self.callback {
+            Ok(callback.clone())
         } else {
             error!(
                 "Method call to {} has no handler, treating as invalid",
                 self.node_id()
             );
-            Err(StatusCode::BadMethodInvalid)
+            Err(DiagnosticError::new(StatusCode::BadMethodInvalid)
+                .with_additional_info(format!("Method {} has no handler", self.node_id())))
         }
     }
 }
@@ -13,28 +13,31 @@ use crate::types::{
 
 use crate::server::{callbacks::Method, session::SessionManager};
 
-/// Count the number of provided input arguments, comparing them to the expected number.
+/// Count the number of provided input arguments, comparing them to the expected number. The Call
+/// service checks this against InputArguments already, but a handler can be called directly.
 fn ensure_input_argument_count(
     request: &CallMethodRequest,
     expected: usize,
-) -> Result<(), StatusCode> {
-    if let Some(ref input_arguments) = request.input_arguments {
-        let actual = input_arguments.len();
-        if actual == expected {
-            Ok(())
-        } else if actual < expected {
-            debug!("Method call fails BadArgumentsMissing");
-            Err(StatusCode::BadArgumentsMissing)
-        } else {
-            debug!("Method call fails BadTooManyArguments");
-            Err(StatusCode::BadTooManyArguments)
-        }
-    } else if expected == 0 {
-        Ok(())
+) -> Result<(), DiagnosticError> {
+    let actual = request
+        .input_arguments
+        .as_ref()
+        .map(|input_arguments| input_arguments.len())
+        .unwrap_or(0);
+    let status_code = if actual == expected {
+        return Ok(());
+    } else if actual < expected {
+        StatusCode::BadArgumentsMissing
     } else {
-        debug!("Method call fails BadArgumentsMissing");
-        Err(StatusCode::BadArgumentsMissing)
-    }
+        StatusCode::BadTooManyArguments
+    };
+    debug!("Method call fails {}", status_code);
+    Err(
+        DiagnosticError::new(status_code).with_additional_info(format!(
+            "The method expects {} input arguments but was called with {}",
+            expected, actual
+        )),
+    )
 }
 
 /// Gets the input argument value, expecting it to the specified variant type. If it fails,
@@ -66,6 +69,25 @@ fn subscription_exists_on_other_session(
     })
 }
 
+/// Returns the error of a method call for a subscription that is not on the calling session
+fn subscription_error(
+    this_session_id: &NodeId,
+    session_manager: Arc<RwLock<SessionManager>>,
+    subscription_id: u32,
+) -> Result<CallMethodResult, DiagnosticError> {
+    if subscription_exists_on_other_session(this_session_id, session_manager, subscription_id) {
+        Err(
+            DiagnosticError::new(StatusCode::BadUserAccessDenied).with_additional_info(format!(
+                "Subscription {} belongs to another session",
+                subscription_id
+            )),
+        )
+    } else {
+        Err(DiagnosticError::new(StatusCode::BadSubscriptionIdInvalid)
+            .with_additional_info(format!("Subscription {} does not exist", subscription_id)))
+    }
+}
+
 /// This is the handler for Server.ResendData method call.
 pub struct ServerResendDataMethod;
 
@@ -76,6 +98,16 @@ impl Method for ServerResendDataMethod {
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
     ) -> Result<CallMethodResult, StatusCode> {
+        self.call_with_diagnostics(session_id, session_manager, request)
+            .map_err(StatusCode::from)
+    }
+
+    fn call_with_diagnostics(
+        &mut self,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
         debug!("Method handler for ResendData");
 
         // OPC UA part 5 - ResendData([in] UInt32 subscriptionId);
@@ -105,15 +137,11 @@ impl Method for ServerResendDataMethod {
                     });
                 };
             } else {
-                return Err(StatusCode::BadSessionIdInvalid);
+                return Err(StatusCode::BadSessionIdInvalid.into());
             }
         }
 
-        if subscription_exists_on_other_session(session_id, session_manager, *subscription_id) {
-            Err(StatusCode::BadUserAccessDenied)
-        } else {
-            Err(StatusCode::BadSubscriptionIdInvalid)
-        }
+        subscription_error(session_id, session_manager, *subscription_id)
     }
 }
 
@@ -127,6 +155,16 @@ impl Method for ServerGetMonitoredItemsMethod {
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
     ) -> Result<CallMethodResult, StatusCode> {
+        self.call_with_diagnostics(session_id, session_manager, request)
+            .map_err(StatusCode::from)
+    }
+
+    fn call_with_diagnostics(
+        &mut self,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
         debug!("Method handler for GetMonitoredItems");
 
         // OPC UA part 5 - GetMonitoredItems([in] UInt32 subscriptionId, [out] UInt32[] serverHandles, [out] UInt32[] clientHandles);
@@ -169,15 +207,14 @@ impl Method for ServerGetMonitoredItemsMethod {
                     });
                 };
             } else {
-                return Err(StatusCode::BadSessionIdInvalid);
+                return Err(StatusCode::BadSessionIdInvalid.into());
             }
         }
-        if subscription_exists_on_other_session(session_id, session_manager, *subscription_id) {
-            debug!("Method handler for GetMonitoredItems returns BadUserAccessDenied");
-            Err(StatusCode::BadUserAccessDenied)
-        } else {
-            debug!("Method handler for GetMonitoredItems returns BadSubscriptionIdInvalid");
-            Err(StatusCode::BadSubscriptionIdInvalid)
-        }
+        let result = subscription_error(session_id, session_manager, *subscription_id);
+        debug!(
+            "Method handler for GetMonitoredItems returns {:?}",
+            result.as_ref().map_err(|err| err.status_code())
+        );
+        result
     }
 }
@@ -440,9 +440,12 @@ macro_rules! node_base_impl {
 
 pub mod address_space;
 pub mod base;
+pub mod data_access;
 pub mod data_type;
+pub mod instantiate;
 pub mod method;
 pub mod node;
+mod nodeset_export;
 pub mod object;
 pub mod object_type;
 pub mod reference_type;
@@ -466,8 +469,8 @@ bitflags! {
         const HISTORY_WRITE = 8;
         // These can be uncommented if they become used
         // const SEMANTIC_CHANGE = 16;
-        // const STATUS_WRITE = 32;
-        // const TIMESTAMP_WRITE = 64;
+        const STATUS_WRITE = 32;
+        const TIMESTAMP_WRITE = 64;
     }
 }
 
@@ -477,9 +480,8 @@ bitflags! {
         const CURRENT_WRITE = 2;
         const HISTORY_READ = 4;
         const HISTORY_WRITE = 8;
-        // These can be uncommented if they become used
-        // const STATUS_WRITE = 32;
-        // const TIMESTAMP_WRITE = 64;
+        const STATUS_WRITE = 32;
+        const TIMESTAMP_WRITE = 64;
     }
 }
 
@@ -493,14 +495,19 @@ bitflags! {
 
 pub mod types {
     pub use super::address_space::AddressSpace;
+    pub use super::data_access::{
+        AnalogItemBuilder, MultiStateDiscreteBuilder, MultiStateValueDiscreteBuilder,
+        TwoStateDiscreteBuilder,
+    };
     pub use super::data_type::{DataType, DataTypeBuilder};
+    pub use super::instantiate::{InstantiateOptions, NodeIdStrategy};
     pub use super::method::{Method, MethodBuilder};
     pub use super::node::{NodeBase, NodeType};
     pub use super::object::{Object, ObjectBuilder};
     pub use super::object_type::{ObjectType, ObjectTypeBuilder};
     pub use super::reference_type::{ReferenceType, ReferenceTypeBuilder};
     pub use super::references::ReferenceDirection;
-    pub use super::variable::{Variable, VariableBuilder};
+    pub use super::variable::{ValueHandle, Variable, VariableBuilder};
     pub use super::variable_type::{VariableType, VariableTypeBuilder};
     pub use super::view::{View, ViewBuilder};
     pub use super::{AttrFnGetter, AttrFnSetter};
@@ -0,0 +1,721 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Exports the contents of an `AddressSpace` as a NodeSet2 XML document (OPC UA Part 6 Annex F).
+//!
+//! The export is a snapshot of the live address space. Values are read through any getters that
+//! are registered on variables, so the document reflects what a client would read at that moment.
+//! Values are written for the built-in types. Structured values held in extension objects are
+//! omitted because they can only be expressed through their binary encoding.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write as FmtWrite,
+    io::{self, Write},
+};
+
+use crate::types::{
+    node_ids::ReferenceTypeId, service_types::NodeClass, Array, DataValue, DateTime,
+    ExpandedNodeId, LocalizedText, NodeId, NumericRange, QualifiedName, TimestampsToReturn,
+    Variant, VariantTypeId,
+};
+
+use super::{
+    address_space::AddressSpace,
+    node::{HasNodeId, NodeType},
+    references::Reference,
+};
+
+const NODESET_NAMESPACE: &str = "http://opcfoundation.org/UA/2011/03/UANodeSet.xsd";
+const TYPES_NAMESPACE: &str = "http://opcfoundation.org/UA/2008/02/Types.xsd";
+
+/// Writes a NodeSet2 document for the nodes of an address space.
+pub(crate) struct NodeSetExporter<'a> {
+    address_space: &'a AddressSpace,
+    /// The nodes to export, sorted by node id so the output is stable between exports
+    nodes: Vec<&'a NodeType>,
+    /// Node ids of the exported nodes
+    exported: HashSet<NodeId>,
+    /// Maps namespace indexes in the address space to indexes in the document
+    namespace_map: HashMap<u16, u16>,
+    /// The namespace uris written to the document, in document index order starting from 1
+    namespace_uris: Vec<String>,
+    /// Aliases for the reference types and data types the nodes use
+    aliases: BTreeMap<String, NodeId>,
+    /// Reverse lookup of aliases
+    alias_names: HashMap<NodeId, String>,
+    /// Values of the exported variables and variable types, read once so that the namespace
+    /// table and the document agree even if a getter returns something different each time
+    values: HashMap<NodeId, Variant>,
+}
+
+impl<'a> NodeSetExporter<'a> {
+    /// Creates an exporter for the nodes in the supplied namespaces, or every node when no
+    /// namespaces are supplied.
+    pub fn new(address_space: &'a AddressSpace, namespaces: Option<&[u16]>) -> Self {
+        let mut nodes = address_space
+            .nodes()
+            .filter(|node| {
+                let node_id = node.node_id();
+                if let Some(namespaces) = namespaces {
+                    namespaces.contains(&node_id.namespace)
+                } else {
+                    true
+                }
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|node| node.node_id());
+        let exported = nodes.iter().map(|node| node.node_id()).collect();
+        let values = nodes
+            .iter()
+            .filter_map(|node| Self::node_value(node).map(|value| (node.node_id(), value)))
+            .collect();
+
+        let mut exporter = Self {
+            address_space,
+            nodes,
+            exported,
+            namespace_map: HashMap::new(),
+            namespace_uris: Vec::new(),
+            aliases: BTreeMap::new(),
+            alias_names: HashMap::new(),
+            values,
+        };
+        exporter.build_namespace_table();
+        exporter.build_aliases();
+        exporter
+    }
+
+    /// Collects every namespace index used by the exported nodes, their references and values
+    /// and assigns each one a document index.
+    fn build_namespace_table(&mut self) {
+        let mut used = BTreeSet::new();
+        for node in &self.nodes {
+            let n = node.as_node();
+            used.insert(n.node_id().namespace);
+            used.insert(n.browse_name().namespace_index);
+            for (r, _) in self.references_of(&n.node_id()) {
+                used.insert(r.reference_type.namespace);
+                used.insert(r.target_node.namespace);
+            }
+            match node {
+                NodeType::Variable(v) => {
+                    used.insert(v.data_type().namespace);
+                }
+                NodeType::VariableType(v) => {
+                    used.insert(v.data_type().namespace);
+                }
+                _ => {}
+            }
+            if let Some(value) = self.values.get(&n.node_id()) {
+                Self::collect_value_namespaces(value, &mut used);
+            }
+        }
+        used.remove(&0);
+
+        let namespaces = self.address_space.namespaces();
+        self.namespace_map.insert(0, 0);
+        for ns in used {
+            if let Some(uri) = namespaces.get(ns as usize) {
+                self.namespace_uris.push(uri.clone());
+                self.namespace_map
+                    .insert(ns, self.namespace_uris.len() as u16);
+            } else {
+                error!(
+                    "Namespace index {} is used by an exported node but is not registered",
+                    ns
+                );
+            }
+        }
+    }
+
+    fn collect_value_namespaces(value: &Variant, used: &mut BTreeSet<u16>) {
+        match value {
+            Variant::NodeId(v) => {
+                used.insert(v.namespace);
+            }
+            Variant::ExpandedNodeId(v) => {
+                used.insert(v.node_id.namespace);
+            }
+            Variant::QualifiedName(v) => {
+                used.insert(v.namespace_index);
+            }
+            Variant::Array(array) => array
+                .values
+                .iter()
+                .for_each(|v| Self::collect_value_namespaces(v, used)),
+            _ => {}
+        }
+    }
+
+    /// Aliases are created for every namespace 0 reference type and data type referred to by the
+    /// exported nodes, named after the browse name of the type.
+    fn build_aliases(&mut self) {
+        let mut candidates = HashSet::new();
+        for node in &self.nodes {
+            let node_id = node.node_id();
+            for (r, _) in self.references_of(&node_id) {
+                candidates.insert(r.reference_type.clone());
+            }
+            match node {
+                NodeType::Variable(v) => {
+                    candidates.insert(v.data_type());
+                }
+                NodeType::VariableType(v) => {
+                    candidates.insert(v.data_type());
+                }
+                _ => {}
+            }
+        }
+        for node_id in candidates.into_iter().filter(|n| n.namespace == 0) {
+            if let Some(node) = self.address_space.find_node(&node_id) {
+                if matches!(node, NodeType::ReferenceType(_) | NodeType::DataType(_)) {
+                    let name = node.as_node().browse_name().name.as_ref().to_string();
+                    if !name.is_empty() && !self.aliases.contains_key(&name) {
+                        self.alias_names.insert(node_id.clone(), name.clone());
+                        self.aliases.insert(name, node_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// All forward references of the node, plus the inverse references from nodes which are not
+    /// part of the export, since those would otherwise be lost.
+    fn references_of(&self, node_id: &NodeId) -> Vec<(Reference, bool)> {
+        let mut result = self
+            .address_space
+            .find_references::<NodeId>(node_id, None)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| (r, true))
+            .collect::<Vec<_>>();
+        if let Some(inverse) = self
+            .address_space
+            .find_inverse_references::<NodeId>(node_id, None)
+        {
+            result.extend(
+                inverse
+                    .into_iter()
+                    .filter(|r| !self.exported.contains(&r.target_node))
+                    .map(|r| (r, false)),
+            );
+        }
+        result
+    }
+
+    /// The parent of an instance, which is the source of an inverse HasComponent or HasProperty
+    /// reference, or else of any other hierarchical reference. If there are several candidates,
+    /// the one with the lowest node id is chosen so that the output is stable between exports.
+    fn parent_of(&self, node_id: &NodeId) -> Option<NodeId> {
+        let references = self.address_space.find_inverse_references(
+            node_id,
+            Some((ReferenceTypeId::HierarchicalReferences, true)),
+        )?;
+        let is_child = |r: &Reference| {
+            r.reference_type == ReferenceTypeId::HasComponent.into()
+                || r.reference_type == ReferenceTypeId::HasProperty.into()
+        };
+        references
+            .iter()
+            .filter(|r| is_child(r))
+            .map(|r| &r.target_node)
+            .min()
+            .or_else(|| references.iter().map(|r| &r.target_node).min())
+            .cloned()
+    }
+
+    fn node_value(node: &NodeType) -> Option<Variant> {
+        let value = match node {
+            NodeType::Variable(v) => {
+                let value: DataValue = v.value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0.0,
+                );
+                value.value
+            }
+            NodeType::VariableType(v) => v.value().and_then(|v| v.value),
+            _ => None,
+        };
+        value.filter(|v| !matches!(v, Variant::Empty))
+    }
+
+    fn map_node_id(&self, node_id: &NodeId) -> NodeId {
+        let namespace = self
+            .namespace_map
+            .get(&node_id.namespace)
+            .copied()
+            .unwrap_or(node_id.namespace);
+        NodeId::new(namespace, node_id.identifier.clone())
+    }
+
+    fn map_namespace(&self, namespace: u16) -> u16 {
+        self.namespace_map
+            .get(&namespace)
+            .copied()
+            .unwrap_or(namespace)
+    }
+
+    /// Formats a node id as it should appear in an attribute, i.e. as an alias if there is one
+    fn node_id_or_alias(&self, node_id: &NodeId) -> String {
+        if let Some(alias) = self.alias_names.get(node_id) {
+            alias.clone()
+        } else {
+            self.map_node_id(node_id).to_string()
+        }
+    }
+
+    fn browse_name(&self, browse_name: &QualifiedName) -> String {
+        let ns = self.map_namespace(browse_name.namespace_index);
+        if ns == 0 {
+            browse_name.name.as_ref().to_string()
+        } else {
+            format!("{}:{}", ns, browse_name.name.as_ref())
+        }
+    }
+
+    /// Writes the document to the supplied writer
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<UANodeSet xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:uax="{}" xmlns="{}" LastModified="{}">"#,
+            TYPES_NAMESPACE,
+            NODESET_NAMESPACE,
+            xml_date_time(&DateTime::from(self.address_space.last_modified()))
+        )?;
+
+        if !self.namespace_uris.is_empty() {
+            writeln!(out, "  <NamespaceUris>")?;
+            for uri in &self.namespace_uris {
+                writeln!(out, "    <Uri>{}</Uri>", escape(uri))?;
+            }
+            writeln!(out, "  </NamespaceUris>")?;
+        }
+
+        if !self.aliases.is_empty() {
+            writeln!(out, "  <Aliases>")?;
+            for (alias, node_id) in &self.aliases {
+                writeln!(
+                    out,
+                    r#"    <Alias Alias="{}">{}</Alias>"#,
+                    escape(alias),
+                    node_id
+                )?;
+            }
+            writeln!(out, "  </Aliases>")?;
+        }
+
+        for node in &self.nodes {
+            out.write_all(self.node_xml(node).as_bytes())?;
+        }
+
+        writeln!(out, "</UANodeSet>")
+    }
+
+    fn node_xml(&self, node: &NodeType) -> String {
+        let n = node.as_node();
+        let node_id = n.node_id();
+        let element = match node.node_class() {
+            NodeClass::Object => "UAObject",
+            NodeClass::Variable => "UAVariable",
+            NodeClass::Method => "UAMethod",
+            NodeClass::ObjectType => "UAObjectType",
+            NodeClass::VariableType => "UAVariableType",
+            NodeClass::ReferenceType => "UAReferenceType",
+            NodeClass::DataType => "UADataType",
+            NodeClass::View => "UAView",
+            NodeClass::Unspecified => unreachable!(),
+        };
+
+        let mut attributes = vec![
+            ("NodeId", self.map_node_id(&node_id).to_string()),
+            ("BrowseName", self.browse_name(&n.browse_name())),
+        ];
+        if let Some(write_mask) = n.write_mask() {
+            if !write_mask.is_empty() {
+                attributes.push(("WriteMask", write_mask.bits().to_string()));
+            }
+        }
+        if let Some(user_write_mask) = n.user_write_mask() {
+            if !user_write_mask.is_empty() {
+                attributes.push(("UserWriteMask", user_write_mask.bits().to_string()));
+            }
+        }
+        if matches!(
+            node,
+            NodeType::Object(_) | NodeType::Variable(_) | NodeType::Method(_)
+        ) {
+            if let Some(parent_id) = self.parent_of(&node_id) {
+                attributes.push(("ParentNodeId", self.map_node_id(&parent_id).to_string()));
+            }
+        }
+
+        let mut inverse_name = None;
+        match node {
+            NodeType::Object(v) => {
+                if !v.event_notifier().is_empty() {
+                    attributes.push(("EventNotifier", v.event_notifier().bits().to_string()));
+                }
+            }
+            NodeType::Variable(v) => {
+                attributes.push(("DataType", self.node_id_or_alias(&v.data_type())));
+                if v.value_rank() != -1 {
+                    attributes.push(("ValueRank", v.value_rank().to_string()));
+                }
+                if let Some(array_dimensions) = v.array_dimensions() {
+                    if !array_dimensions.is_empty() {
+                        attributes.push(("ArrayDimensions", join(&array_dimensions)));
+                    }
+                }
+                attributes.push(("AccessLevel", v.access_level().bits().to_string()));
+                attributes.push(("UserAccessLevel", v.user_access_level().bits().to_string()));
+                if let Some(minimum_sampling_interval) = v.minimum_sampling_interval() {
+                    attributes.push((
+                        "MinimumSamplingInterval",
+                        minimum_sampling_interval.to_string(),
+                    ));
+                }
+                if v.historizing() {
+                    attributes.push(("Historizing", "true".into()));
+                }
+            }
+            NodeType::Method(v) => {
+                if !v.executable() {
+                    attributes.push(("Executable", "false".into()));
+                }
+                if !v.user_executable() {
+                    attributes.push(("UserExecutable", "false".into()));
+                }
+            }
+            NodeType::ObjectType(v) => {
+                if v.is_abstract() {
+                    attributes.push(("IsAbstract", "true".into()));
+                }
+            }
+            NodeType::VariableType(v) => {
+                attributes.push(("DataType", self.node_id_or_alias(&v.data_type())));
+                if v.value_rank() != -1 {
+                    attributes.push(("ValueRank", v.value_rank().to_string()));
+                }
+                if let Some(array_dimensions) = v.array_dimensions() {
+                    if !array_dimensions.is_empty() {
+                        attributes.push(("ArrayDimensions", join(&array_dimensions)));
+                    }
+                }
+                if v.is_abstract() {
+                    attributes.push(("IsAbstract", "true".into()));
+                }
+            }
+            NodeType::ReferenceType(v) => {
+                if v.is_abstract() {
+                    attributes.push(("IsAbstract", "true".into()));
+                }
+                if v.symmetric() {
+                    attributes.push(("Symmetric", "true".into()));
+                }
+                inverse_name = v.inverse_name();
+            }
+            NodeType::DataType(v) => {
+                if v.is_abstract() {
+                    attributes.push(("IsAbstract", "true".into()));
+                }
+            }
+            NodeType::View(v) => {
+                if v.contains_no_loops() {
+                    attributes.push(("ContainsNoLoops", "true".into()));
+                }
+                if !v.event_notifier().is_empty() {
+                    attributes.push(("EventNotifier", v.event_notifier().bits().to_string()));
+                }
+            }
+        }
+
+        let mut xml = String::with_capacity(512);
+        let _ = write!(xml, "  <{}", element);
+        for (name, value) in attributes {
+            let _ = write!(xml, r#" {}="{}""#, name, escape(&value));
+        }
+        xml.push_str(">\n");
+
+        let _ = writeln!(
+            xml,
+            "    {}",
+            localized_text_element("DisplayName", &n.display_name())
+        );
+        if let Some(description) = n.description() {
+            let _ = writeln!(
+                xml,
+                "    {}",
+                localized_text_element("Description", &description)
+            );
+        }
+        if let Some(inverse_name) = inverse_name {
+            let _ = writeln!(
+                xml,
+                "    {}",
+                localized_text_element("InverseName", &inverse_name)
+            );
+        }
+
+        let mut references = self.references_of(&node_id);
+        references.sort_by(|(a, _), (b, _)| {
+            (&a.reference_type, &a.target_node).cmp(&(&b.reference_type, &b.target_node))
+        });
+        if !references.is_empty() {
+            xml.push_str("    <References>\n");
+            for (r, is_forward) in references {
+                let _ = write!(
+                    xml,
+                    r#"      <Reference ReferenceType="{}""#,
+                    escape(&self.node_id_or_alias(&r.reference_type))
+                );
+                if !is_forward {
+                    xml.push_str(r#" IsForward="false""#);
+                }
+                let _ = writeln!(
+                    xml,
+                    ">{}</Reference>",
+                    escape(&self.map_node_id(&r.target_node).to_string())
+                );
+            }
+            xml.push_str("    </References>\n");
+        }
+
+        if let Some(value) = self.values.get(&node_id) {
+            if let Some(value_xml) = self.variant_xml(value) {
+                let _ = writeln!(xml, "    <Value>{}</Value>", value_xml);
+            } else {
+                debug!(
+                    "Value of node {} cannot be exported, type {:?}",
+                    node_id,
+                    value.type_id()
+                );
+            }
+        }
+
+        let _ = writeln!(xml, "  </{}>", element);
+        xml
+    }
+
+    /// Produces the XML encoding of a variant's value, or `None` if the value cannot be expressed.
+    fn variant_xml(&self, value: &Variant) -> Option<String> {
+        match value {
+            Variant::Array(array) => self.array_xml(array),
+            value => {
+                let (name, content) = self.scalar_xml(value)?;
+                Some(format!(
+                    r#"<uax:{name}>{content}</uax:{name}>"#,
+                    name = name,
+                    content = content
+                ))
+            }
+        }
+    }
+
+    fn array_xml(&self, array: &Array) -> Option<String> {
+        if array.has_dimensions() && array.dimensions.len() > 1 {
+            // Matrices are not supported by this exporter
+            return None;
+        }
+        let mut elements = String::new();
+        let mut list_name = None;
+        for value in &array.values {
+            let (name, content) = self.scalar_xml(value)?;
+            let _ = write!(
+                elements,
+                r#"<uax:{name}>{content}</uax:{name}>"#,
+                name = name,
+                content = content
+            );
+            list_name = Some(name);
+        }
+        let list_name = match list_name {
+            Some(name) => name,
+            None => scalar_type_name(&array.value_type)?,
+        };
+        Some(format!(
+            r#"<uax:ListOf{name}>{elements}</uax:ListOf{name}>"#,
+            name = list_name,
+            elements = elements
+        ))
+    }
+
+    /// Returns the element name and the XML content of a scalar value
+    fn scalar_xml(&self, value: &Variant) -> Option<(&'static str, String)> {
+        let result = match value {
+            Variant::Boolean(v) => ("Boolean", v.to_string()),
+            Variant::SByte(v) => ("SByte", v.to_string()),
+            Variant::Byte(v) => ("Byte", v.to_string()),
+            Variant::Int16(v) => ("Int16", v.to_string()),
+            Variant::UInt16(v) => ("UInt16", v.to_string()),
+            Variant::Int32(v) => ("Int32", v.to_string()),
+            Variant::UInt32(v) => ("UInt32", v.to_string()),
+            Variant::Int64(v) => ("Int64", v.to_string()),
+            Variant::UInt64(v) => ("UInt64", v.to_string()),
+            Variant::Float(v) => ("Float", xml_float(*v)),
+            Variant::Double(v) => ("Double", xml_float(*v)),
+            Variant::String(v) => ("String", escape(v.as_ref())),
+            Variant::DateTime(v) => ("DateTime", xml_date_time(v)),
+            Variant::Guid(v) => ("Guid", format!("<uax:String>{}</uax:String>", v)),
+            Variant::StatusCode(v) => ("StatusCode", format!("<uax:Code>{}</uax:Code>", v.bits())),
+            Variant::ByteString(v) => ("ByteString", v.as_base64()),
+            Variant::XmlElement(v) => ("XmlElement", v.as_ref().to_string()),
+            Variant::QualifiedName(v) => (
+                "QualifiedName",
+                format!(
+                    "<uax:NamespaceIndex>{}</uax:NamespaceIndex><uax:Name>{}</uax:Name>",
+                    self.map_namespace(v.namespace_index),
+                    escape(v.name.as_ref())
+                ),
+            ),
+            Variant::LocalizedText(v) => ("LocalizedText", localized_text_content(v)),
+            Variant::NodeId(v) => (
+                "NodeId",
+                format!(
+                    "<uax:Identifier>{}</uax:Identifier>",
+                    escape(&self.map_node_id(v).to_string())
+                ),
+            ),
+            Variant::ExpandedNodeId(v) => (
+                "ExpandedNodeId",
+                format!(
+                    "<uax:Identifier>{}</uax:Identifier>",
+                    escape(&self.expanded_node_id(v))
+                ),
+            ),
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    fn expanded_node_id(&self, node_id: &ExpandedNodeId) -> String {
+        let mut result = String::new();
+        if node_id.server_index != 0 {
+            let _ = write!(result, "svr={};", node_id.server_index);
+        }
+        if !node_id.namespace_uri.is_null() {
+            let _ = write!(
+                result,
+                "nsu={};{}",
+                node_id.namespace_uri.as_ref(),
+                node_id.node_id.identifier
+            );
+        } else {
+            let _ = write!(result, "{}", self.map_node_id(&node_id.node_id));
+        }
+        result
+    }
+}
+
+fn scalar_type_name(type_id: &VariantTypeId) -> Option<&'static str> {
+    let name = match type_id {
+        VariantTypeId::Boolean => "Boolean",
+        VariantTypeId::SByte => "SByte",
+        VariantTypeId::Byte => "Byte",
+        VariantTypeId::Int16 => "Int16",
+        VariantTypeId::UInt16 => "UInt16",
+        VariantTypeId::Int32 => "Int32",
+        VariantTypeId::UInt32 => "UInt32",
+        VariantTypeId::Int64 => "Int64",
+        VariantTypeId::UInt64 => "UInt64",
+        VariantTypeId::Float => "Float",
+        VariantTypeId::Double => "Double",
+        VariantTypeId::String => "String",
+        VariantTypeId::DateTime => "DateTime",
+        VariantTypeId::Guid => "Guid",
+        VariantTypeId::StatusCode => "StatusCode",
+        VariantTypeId::ByteString => "ByteString",
+        VariantTypeId::XmlElement => "XmlElement",
+        VariantTypeId::QualifiedName => "QualifiedName",
+        VariantTypeId::LocalizedText => "LocalizedText",
+        VariantTypeId::NodeId => "NodeId",
+        VariantTypeId::ExpandedNodeId => "ExpandedNodeId",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn localized_text_element(name: &str, text: &LocalizedText) -> String {
+    if text.locale.is_null() || text.locale.is_empty() {
+        format!(
+            "<{name}>{}</{name}>",
+            escape(text.text.as_ref()),
+            name = name
+        )
+    } else {
+        format!(
+            r#"<{name} Locale="{}">{}</{name}>"#,
+            escape(text.locale.as_ref()),
+            escape(text.text.as_ref()),
+            name = name
+        )
+    }
+}
+
+fn localized_text_content(text: &LocalizedText) -> String {
+    let mut result = String::new();
+    if !text.locale.is_null() {
+        let _ = write!(
+            result,
+            "<uax:Locale>{}</uax:Locale>",
+            escape(text.locale.as_ref())
+        );
+    }
+    if !text.text.is_null() {
+        let _ = write!(
+            result,
+            "<uax:Text>{}</uax:Text>",
+            escape(text.text.as_ref())
+        );
+    }
+    result
+}
+
+/// Formats a Float or Double from its own type, so that a Float is not written with the digits
+/// of its widened value
+fn xml_float<T>(v: T) -> String
+where
+    T: Into<f64> + ToString + Copy,
+{
+    let f: f64 = v.into();
+    if f.is_nan() {
+        "NaN".into()
+    } else if f.is_infinite() {
+        if f > 0.0 { "INF" } else { "-INF" }.into()
+    } else {
+        v.to_string()
+    }
+}
+
+fn xml_date_time(v: &DateTime) -> String {
+    v.as_chrono()
+        .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+}
+
+fn join(values: &[u32]) -> String {
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Escapes the characters that are not allowed to appear literally in XML text or attributes.
+pub(crate) fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
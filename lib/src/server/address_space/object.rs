@@ -33,6 +33,17 @@ impl ObjectBuilder {
         )
     }
 
+    pub fn has_modelling_rule<T>(self, type_id: T) -> Self
+    where
+        T: Into<NodeId>,
+    {
+        self.reference(
+            type_id,
+            ReferenceTypeId::HasModellingRule,
+            ReferenceDirection::Forward,
+        )
+    }
+
     pub fn has_event_source<T>(self, source_id: T) -> Self
     where
         T: Into<NodeId>,
@@ -16,7 +16,7 @@ use crate::server::{
         node::{Node, NodeBase},
         AccessLevel, UserAccessLevel,
     },
-    callbacks::{AttributeGetter, AttributeSetter},
+    callbacks::{AsyncAttributeGetter, AsyncAttributeSetter, AttributeGetter, AttributeSetter},
 };
 
 // This is a builder object for constructing variable nodes programmatically.
@@ -27,7 +27,7 @@ node_builder_impl_property_of!(VariableBuilder);
 
 impl VariableBuilder {
     /// Sets the value of the variable.
-    pub fn value<V>(mut self, value: V) -> Self
+    pub fn value<V>(self, value: V) -> Self
     where
         V: Into<Variant>,
     {
@@ -124,6 +124,20 @@ impl VariableBuilder {
         self
     }
 
+    /// Sets an async value getter for the variable. The Read service awaits the getter without
+    /// holding the address space lock.
+    pub fn async_value_getter(mut self, getter: Arc<dyn AsyncAttributeGetter>) -> Self {
+        self.node.set_async_value_getter(getter);
+        self
+    }
+
+    /// Sets an async value setter for the variable. The Write service awaits the setter without
+    /// holding the address space lock.
+    pub fn async_value_setter(mut self, setter: Arc<dyn AsyncAttributeSetter>) -> Self {
+        self.node.set_async_value_setter(setter);
+        self
+    }
+
     /// Add a reference to the variable indicating it has a type of another node.
     pub fn has_type_definition<T>(self, type_id: T) -> Self
     where
@@ -149,6 +163,41 @@ impl VariableBuilder {
     }
 }
 
+/// A handle to the value of a [`Variable`]. The value is held behind a lock of its own, so a
+/// handle reads and updates it without any lock on the `AddressSpace` that holds the variable.
+/// Updates through a handle bypass any value setter of the variable and are seen by reads and
+/// monitored items that are not served by a value getter.
+///
+/// [`Variable`]: ./struct.Variable.html
+#[derive(Debug, Clone)]
+pub struct ValueHandle {
+    value: Arc<RwLock<DataValue>>,
+}
+
+impl ValueHandle {
+    /// Returns a copy of the current value
+    pub fn get(&self) -> DataValue {
+        trace_read_lock!(self.value).clone()
+    }
+
+    /// Sets the value and its status and timestamps
+    pub fn set<V>(
+        &self,
+        value: V,
+        status_code: StatusCode,
+        server_timestamp: &DateTime,
+        source_timestamp: &DateTime,
+    ) where
+        V: Into<Variant>,
+    {
+        let mut data_value = trace_write_lock!(self.value);
+        data_value.value = Some(value.into());
+        data_value.status = Some(status_code);
+        data_value.server_timestamp = Some(*server_timestamp);
+        data_value.source_timestamp = Some(*source_timestamp);
+    }
+}
+
 // Note we use derivative builder macro so we can skip over the value getter / setter
 
 /// A `Variable` is a type of node within the `AddressSpace`.
@@ -159,7 +208,9 @@ pub struct Variable {
     data_type: NodeId,
     historizing: bool,
     value_rank: i32,
-    value: DataValue,
+    /// The value is locked separately from the address space so it can be updated through a
+    /// shared reference
+    value: Arc<RwLock<DataValue>>,
     access_level: u8,
     user_access_level: u8,
     array_dimensions: Option<Vec<u32>>,
@@ -168,6 +219,10 @@ pub struct Variable {
     value_setter: Option<Arc<Mutex<dyn AttributeSetter + Send>>>,
     #[derivative(Debug = "ignore")]
     value_getter: Option<Arc<Mutex<dyn AttributeGetter + Send>>>,
+    #[derivative(Debug = "ignore")]
+    async_value_setter: Option<Arc<dyn AsyncAttributeSetter>>,
+    #[derivative(Debug = "ignore")]
+    async_value_getter: Option<Arc<dyn AsyncAttributeGetter>>,
 }
 
 impl Default for Variable {
@@ -177,13 +232,15 @@ impl Default for Variable {
             data_type: NodeId::null(),
             historizing: false,
             value_rank: -1,
-            value: Variant::Empty.into(),
+            value: Arc::new(RwLock::new(Variant::Empty.into())),
             access_level: UserAccessLevel::CURRENT_READ.bits(),
             user_access_level: AccessLevel::CURRENT_READ.bits(),
             array_dimensions: None,
             minimum_sampling_interval: None,
             value_getter: None,
             value_setter: None,
+            async_value_getter: None,
+            async_value_setter: None,
         }
     }
 }
@@ -481,7 +538,7 @@ impl Variable {
                 })
                 .unwrap_or_default()
         } else {
-            let data_value = &self.value;
+            let data_value = trace_read_lock!(self.value);
             let mut result = DataValue {
                 server_picoseconds: data_value.server_picoseconds,
                 server_timestamp: data_value.server_timestamp,
@@ -512,11 +569,36 @@ impl Variable {
     }
 
     /// Sets the variable's `Variant` value. The timestamps for the change are updated to now.
-    pub fn set_value<V>(&mut self, index_range: NumericRange, value: V) -> Result<(), StatusCode>
+    pub fn set_value<V>(&self, index_range: NumericRange, value: V) -> Result<(), StatusCode>
     where
         V: Into<Variant>,
     {
-        let mut value = value.into();
+        self.set_data_value(index_range, DataValue::value_only(value))
+    }
+
+    /// Sets the variable's value from a `DataValue`. The status and timestamps of the data value
+    /// are kept when they are set, otherwise the status is Good and the timestamps are now.
+    pub fn set_data_value(
+        &self,
+        index_range: NumericRange,
+        data_value: DataValue,
+    ) -> Result<(), StatusCode> {
+        self.set_data_value_with(index_range, data_value, || {})
+    }
+
+    /// Sets the variable's value from a `DataValue` like `set_data_value`, calling `on_set` after
+    /// the value is set but before it is unlocked. Anything `on_set` does, e.g. recording the
+    /// change, is therefore ordered the same way as the changes it records.
+    pub fn set_data_value_with<F>(
+        &self,
+        index_range: NumericRange,
+        data_value: DataValue,
+        on_set: F,
+    ) -> Result<(), StatusCode>
+    where
+        F: FnOnce(),
+    {
+        let mut value = data_value.value.clone().unwrap_or_default();
 
         // A special case is required here for when the variable is a single dimension
         // byte array and the value is a ByteString.
@@ -539,34 +621,51 @@ impl Variable {
                 &self.node_id(),
                 AttributeId::Value,
                 index_range,
-                value.into(),
-            )
+                DataValue {
+                    value: Some(value),
+                    ..data_value
+                },
+            )?;
+            on_set();
+            Ok(())
         } else {
             let now = DateTime::now();
+            let mut current = trace_write_lock!(self.value);
+            let current = &mut *current;
             if index_range.has_range() {
-                self.set_value_range(value, index_range, StatusCode::Good, &now, &now)
+                match current.value {
+                    Some(ref mut full_value) => full_value.set_range_of(index_range, &value)?,
+                    None => return Err(StatusCode::BadIndexRangeInvalid),
+                }
             } else {
-                self.set_value_direct(value, StatusCode::Good, &now, &now)
+                current.value = Some(value);
             }
+            current.status = Some(data_value.status.unwrap_or(StatusCode::Good));
+            current.server_timestamp = Some(data_value.server_timestamp.unwrap_or(now));
+            current.source_timestamp = Some(data_value.source_timestamp.unwrap_or(now));
+            on_set();
+            Ok(())
         }
     }
 
     // Set a range value
     pub fn set_value_range(
-        &mut self,
+        &self,
         value: Variant,
         index_range: NumericRange,
         status_code: StatusCode,
         server_timestamp: &DateTime,
         source_timestamp: &DateTime,
     ) -> Result<(), StatusCode> {
-        match self.value.value {
+        let mut data_value = trace_write_lock!(self.value);
+        let data_value = &mut *data_value;
+        match data_value.value {
             Some(ref mut full_value) => {
                 // Overwrite a partial section of the value
                 full_value.set_range_of(index_range, &value)?;
-                self.value.status = Some(status_code);
-                self.value.server_timestamp = Some(*server_timestamp);
-                self.value.source_timestamp = Some(*source_timestamp);
+                data_value.status = Some(status_code);
+                data_value.server_timestamp = Some(*server_timestamp);
+                data_value.source_timestamp = Some(*source_timestamp);
                 Ok(())
             }
             None => Err(StatusCode::BadIndexRangeInvalid),
@@ -575,7 +674,7 @@ impl Variable {
 
     /// Sets the variable's `DataValue`
     pub fn set_value_direct<V>(
-        &mut self,
+        &self,
         value: V,
         status_code: StatusCode,
         server_timestamp: &DateTime,
@@ -584,13 +683,19 @@ impl Variable {
     where
         V: Into<Variant>,
     {
-        self.value.value = Some(value.into());
-        self.value.status = Some(status_code);
-        self.value.server_timestamp = Some(*server_timestamp);
-        self.value.source_timestamp = Some(*source_timestamp);
+        self.value_handle()
+            .set(value, status_code, server_timestamp, source_timestamp);
         Ok(())
     }
 
+    /// Returns a handle through which the value can be read and updated without holding a lock
+    /// on the address space.
+    pub fn value_handle(&self) -> ValueHandle {
+        ValueHandle {
+            value: self.value.clone(),
+        }
+    }
+
     /// Sets a getter function that will be called to get the value of this variable.
     pub fn set_value_getter(&mut self, value_getter: Arc<Mutex<dyn AttributeGetter + Send>>) {
         self.value_getter = Some(value_getter);
@@ -601,6 +706,28 @@ impl Variable {
         self.value_setter = Some(value_setter);
     }
 
+    /// Sets an async getter that will be awaited by the Read service to get the value of this
+    /// variable.
+    pub fn set_async_value_getter(&mut self, value_getter: Arc<dyn AsyncAttributeGetter>) {
+        self.async_value_getter = Some(value_getter);
+    }
+
+    /// Returns the async getter of this variable, if it has one
+    pub fn async_value_getter(&self) -> Option<Arc<dyn AsyncAttributeGetter>> {
+        self.async_value_getter.clone()
+    }
+
+    /// Sets an async setter that will be awaited by the Write service to set the value of this
+    /// variable.
+    pub fn set_async_value_setter(&mut self, value_setter: Arc<dyn AsyncAttributeSetter>) {
+        self.async_value_setter = Some(value_setter);
+    }
+
+    /// Returns the async setter of this variable, if it has one
+    pub fn async_value_setter(&self) -> Option<Arc<dyn AsyncAttributeSetter>> {
+        self.async_value_setter.clone()
+    }
+
     /// Gets the minimum sampling interval, if the attribute was set
     pub fn minimum_sampling_interval(&self) -> Option<f64> {
         self.minimum_sampling_interval
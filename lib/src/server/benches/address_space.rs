@@ -1,10 +1,41 @@
 #[macro_use]
 extern crate criterion;
 
-use criterion::Criterion;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::server::address_space::{address_space::AddressSpace, references::References};
-use crate::types::node_ids::ReferenceTypeId;
+use criterion::{BenchmarkId, Criterion, Throughput};
+
+use opcua::server::address_space::{address_space::AddressSpace, references::References};
+use opcua::server::prelude::{DateTime, NodeId, StatusCode, Variable};
+use opcua::sync::RwLock;
+use opcua::types::node_ids::ReferenceTypeId;
+
+/// Number of variables that sessions read and that are updated
+const VARIABLE_COUNT: usize = 1000;
+/// Number of variables read by each request of a session
+const READS_PER_REQUEST: usize = 100;
+/// Number of requests made by each session
+const REQUESTS_PER_SESSION: usize = 100;
+
+/// How the values of variables are updated while sessions read them
+#[derive(Clone, Copy, Debug)]
+enum Updates {
+    /// Values are not updated
+    None,
+    /// Values are updated under the address space write lock
+    WriteLock,
+    /// Values are updated under the address space read lock
+    ReadLock,
+    /// Values are updated through value handles without any address space lock
+    ValueHandles,
+}
 
 fn populate_address_space() {
     let _address_space = AddressSpace::new();
@@ -12,7 +43,7 @@ fn populate_address_space() {
 
 fn address_space_benchmark(c: &mut Criterion) {
     // This benchmark tests how long it takes to populate the address space
-    c.bench_function("address_space", |b| b.iter(|| populate_address_space()));
+    c.bench_function("address_space", |b| b.iter(populate_address_space));
 }
 
 fn reference_type_matches(references: &References) {
@@ -34,5 +65,139 @@ fn reference_type_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, address_space_benchmark, reference_type_benchmark);
+fn variables_address_space() -> (Arc<RwLock<AddressSpace>>, Arc<Vec<NodeId>>) {
+    let mut address_space = AddressSpace::new();
+    let ns = address_space.register_namespace("urn:bench").unwrap();
+    let folder_id = address_space
+        .add_folder("Bench", "Bench", &NodeId::objects_folder_id())
+        .unwrap();
+    let node_ids = (0..VARIABLE_COUNT)
+        .map(|i| NodeId::new(ns, format!("v{:04}", i)))
+        .collect::<Vec<_>>();
+    let variables = node_ids
+        .iter()
+        .map(|node_id| {
+            let name = node_id.identifier.to_string();
+            Variable::new(node_id, name.as_str(), name.as_str(), 0i32)
+        })
+        .collect();
+    let _ = address_space.add_variables(variables, &folder_id);
+    (Arc::new(RwLock::new(address_space)), Arc::new(node_ids))
+}
+
+/// Updates every variable in a loop until it is told to stop
+fn update_values(
+    address_space: Arc<RwLock<AddressSpace>>,
+    node_ids: Arc<Vec<NodeId>>,
+    updates: Updates,
+    running: Arc<AtomicBool>,
+) {
+    let handles = {
+        let address_space = address_space.read();
+        node_ids
+            .iter()
+            .map(|node_id| address_space.value_handle(node_id.clone()).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let mut value = 0i32;
+    while running.load(Ordering::Relaxed) {
+        value = value.wrapping_add(1);
+        let now = DateTime::now();
+        match updates {
+            Updates::None => thread::sleep(Duration::from_millis(1)),
+            Updates::WriteLock => {
+                let address_space = address_space.write();
+                node_ids.iter().for_each(|node_id| {
+                    address_space.set_variable_value_by_ref(node_id, value, &now, &now);
+                });
+            }
+            Updates::ReadLock => {
+                let address_space = address_space.read();
+                node_ids.iter().for_each(|node_id| {
+                    address_space.set_variable_value_by_ref(node_id, value, &now, &now);
+                });
+            }
+            Updates::ValueHandles => handles
+                .iter()
+                .for_each(|handle| handle.set(value, StatusCode::Good, &now, &now)),
+        }
+    }
+}
+
+/// Returns how long it takes for concurrent sessions to make their read requests while values
+/// are updated
+fn concurrent_reads(
+    address_space: &Arc<RwLock<AddressSpace>>,
+    node_ids: &Arc<Vec<NodeId>>,
+    sessions: usize,
+    updates: Updates,
+) -> Duration {
+    let running = Arc::new(AtomicBool::new(true));
+    let updater = {
+        let (address_space, node_ids, running) =
+            (address_space.clone(), node_ids.clone(), running.clone());
+        thread::spawn(move || update_values(address_space, node_ids, updates, running))
+    };
+    let start = Instant::now();
+    let sessions = (0..sessions)
+        .map(|session| {
+            let (address_space, node_ids) = (address_space.clone(), node_ids.clone());
+            thread::spawn(move || {
+                for request in 0..REQUESTS_PER_SESSION {
+                    // Like the Read service, a request reads all of its values under one lock
+                    let address_space = address_space.read();
+                    let offset = (session + request) * READS_PER_REQUEST;
+                    for i in offset..offset + READS_PER_REQUEST {
+                        let node_id = &node_ids[i % VARIABLE_COUNT];
+                        let _ = address_space.get_variable_value(node_id);
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    sessions.into_iter().for_each(|s| s.join().unwrap());
+    let elapsed = start.elapsed();
+    running.store(false, Ordering::Relaxed);
+    updater.join().unwrap();
+    elapsed
+}
+
+fn concurrent_read_benchmark(c: &mut Criterion) {
+    // This benchmark tests how many values concurrent sessions can read while the values are updated
+    let (address_space, node_ids) = variables_address_space();
+    for updates in &[
+        Updates::None,
+        Updates::WriteLock,
+        Updates::ReadLock,
+        Updates::ValueHandles,
+    ] {
+        let mut group = c.benchmark_group(format!("concurrent_reads/{:?}", updates));
+        group.sample_size(10);
+        for sessions in &[1usize, 4, 16, 64] {
+            let reads = sessions * REQUESTS_PER_SESSION * READS_PER_REQUEST;
+            group.throughput(Throughput::Elements(reads as u64));
+            group.bench_with_input(
+                BenchmarkId::from_parameter(sessions),
+                sessions,
+                |b, &sessions| {
+                    b.iter_custom(|iters| {
+                        (0..iters)
+                            .map(|_| {
+                                concurrent_reads(&address_space, &node_ids, sessions, *updates)
+                            })
+                            .sum()
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(
+    benches,
+    address_space_benchmark,
+    reference_type_benchmark,
+    concurrent_read_benchmark
+);
 criterion_main!(benches);
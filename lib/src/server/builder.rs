@@ -7,7 +7,9 @@ use std::path::PathBuf;
 use crate::core::config::Config;
 
 use super::{
-    config::{ServerConfig, ServerEndpoint, ServerUserToken, ANONYMOUS_USER_TOKEN_ID},
+    config::{
+        RedundancyConfig, ServerConfig, ServerEndpoint, ServerUserToken, ANONYMOUS_USER_TOKEN_ID,
+    },
     constants,
     server::Server,
 };
@@ -81,6 +83,7 @@ impl ServerBuilder {
                     user: "sample1".to_string(),
                     pass: Some("sample1pwd".to_string()),
                     x509: None,
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -90,6 +93,7 @@ impl ServerBuilder {
                     user: "sample_x509".to_string(),
                     pass: None,
                     x509: Some("./users/sample-x509.der".to_string()),
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -99,6 +103,7 @@ impl ServerBuilder {
                     user: "unused".to_string(),
                     pass: Some("unused1".to_string()),
                     x509: None,
+                    jwt: None,
                     thumbprint: None,
                 },
             )
@@ -239,6 +244,22 @@ impl ServerBuilder {
         self
     }
 
+    /// Sets the path to a journal file that persists changes made to the address space through
+    /// the node management services and writes to persistent nodes.
+    pub fn journal_path<T>(mut self, journal_path: T) -> Self
+    where
+        T: Into<PathBuf>,
+    {
+        self.config.journal_path = Some(journal_path.into());
+        self
+    }
+
+    /// Sets the redundant set of servers that the server is part of
+    pub fn redundancy(mut self, redundancy: RedundancyConfig) -> Self {
+        self.config.redundancy = Some(redundancy);
+        self
+    }
+
     /// Adds an endpoint to the list of endpoints the client knows of.
     pub fn endpoint<T>(mut self, endpoint_id: T, endpoint: ServerEndpoint) -> Self
     where
@@ -319,6 +340,12 @@ impl ServerBuilder {
         self
     }
 
+    /// Set the time in millis allowed for an async getter, setter or method to complete
+    pub fn async_operation_timeout_ms(mut self, async_operation_timeout_ms: u32) -> Self {
+        self.config.limits.async_operation_timeout_ms = async_operation_timeout_ms;
+        self
+    }
+
     /// Set the max array length in elements
     pub fn max_array_length(mut self, max_array_length: usize) -> Self {
         self.config.limits.max_array_length = max_array_length;
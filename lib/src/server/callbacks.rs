@@ -4,13 +4,16 @@
 
 //! Callbacks that a server implementation may register with the library
 
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+pub use tokio_util::sync::CancellationToken;
 
 use crate::sync::*;
 use crate::types::{
     service_types::{CallMethodRequest, CallMethodResult, TimestampsToReturn},
     status_code::StatusCode,
-    AttributeId, DataValue, NodeId, NumericRange, QualifiedName,
+    AttributeId, DataValue, DiagnosticError, NodeId, NumericRange, QualifiedName,
 };
 
 use super::session::{Session, SessionManager};
@@ -54,6 +57,97 @@ pub trait AttributeSetter {
     ) -> Result<(), StatusCode>;
 }
 
+tokio::task_local! {
+    static REQUEST_CANCELLATION: CancellationToken;
+}
+
+/// Returns the cancellation token of the request that an async callback is serving. The token is
+/// cancelled when the client cancels the request with the Cancel service, after which the response
+/// is `BadRequestCancelledByClient` and the callback's future is dropped. A callback that hands work
+/// to another task can pass the token along so that the task stops early.
+///
+/// Outside of a request, e.g. when called from a spawned task, the token is never cancelled.
+pub fn request_cancellation_token() -> CancellationToken {
+    REQUEST_CANCELLATION
+        .try_with(|token| token.clone())
+        .unwrap_or_default()
+}
+
+/// Runs the future of a request so that its callbacks observe the cancellation token
+pub(crate) async fn with_request_cancellation<F>(token: CancellationToken, future: F) -> F::Output
+where
+    F: Future,
+{
+    REQUEST_CANCELLATION.scope(token, future).await
+}
+
+/// A read of the Value attribute of a variable that has an [`AsyncAttributeGetter`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsyncReadValue {
+    /// The variable to read
+    pub node_id: NodeId,
+    /// Timestamps the caller wants in the value
+    pub timestamps_to_return: TimestampsToReturn,
+    /// Range of the value to read, if it is an array. The getter returns the whole value and the
+    /// Read service applies the range to it.
+    pub index_range: NumericRange,
+    /// Data encoding of the value
+    pub data_encoding: QualifiedName,
+    /// Maximum age of the value, see [`AttributeGetter`]
+    pub max_age: f64,
+}
+
+/// An async attribute getter obtains the value of a variable on demand like an [`AttributeGetter`],
+/// but it is awaited by the Read service without holding the `AddressSpace` lock. This suits
+/// getters that talk to a device over the network.
+///
+/// All the reads of a Read request that share a getter are passed to `get_many` together, so an
+/// implementation may override it to fetch the values in a single device request.
+///
+/// A successful read also updates the value held by the variable, which is what subscriptions
+/// sample. A failed read has the status code of the error, and the details of the error are
+/// returned as diagnostic info to a client that asks for them.
+#[async_trait]
+pub trait AsyncAttributeGetter: Send + Sync {
+    /// Returns the value for the read, or none.
+    async fn get(&self, read: &AsyncReadValue) -> Result<Option<DataValue>, DiagnosticError>;
+
+    /// Returns a value for every read, in the same order. The default implementation calls `get`
+    /// for every read concurrently.
+    async fn get_many(
+        &self,
+        reads: &[AsyncReadValue],
+    ) -> Vec<Result<Option<DataValue>, DiagnosticError>> {
+        futures::future::join_all(reads.iter().map(|read| self.get(read))).await
+    }
+
+    /// The time allowed for a call to `get_many`. Reads that take longer fail with `BadTimeout`.
+    /// The default of `None` uses the server's `async_operation_timeout_ms` limit.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An async attribute setter sets the value of a variable like an [`AttributeSetter`], but it is
+/// awaited by the Write service without holding the `AddressSpace` lock.
+#[async_trait]
+pub trait AsyncAttributeSetter: Send + Sync {
+    /// Sets the attribute on the specified node
+    async fn set(
+        &self,
+        node_id: &NodeId,
+        attribute_id: AttributeId,
+        index_range: NumericRange,
+        data_value: DataValue,
+    ) -> Result<(), DiagnosticError>;
+
+    /// The time allowed for a call to `set`. Writes that take longer fail with `BadTimeout`.
+    /// The default of `None` uses the server's `async_operation_timeout_ms` limit.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
 /// Called by RegisterNodes service
 pub trait RegisterNodes {
     /// Called when a client calls the RegisterNodes service. This implementation should return a list
@@ -98,4 +192,37 @@ pub trait Method {
         session_manager: Arc<RwLock<SessionManager>>,
         request: &CallMethodRequest,
     ) -> Result<CallMethodResult, StatusCode>;
+
+    /// Called by the Method service to handle the call. The details of an error are returned as
+    /// diagnostic info to a client that asks for them. The default implementation calls `call`,
+    /// so an implementation only overrides it to return those details.
+    fn call_with_diagnostics(
+        &mut self,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        self.call(session_id, session_manager, request)
+            .map_err(DiagnosticError::from)
+    }
+}
+
+/// An async method is called like a [`Method`], but it is awaited by the Call service without
+/// holding the `AddressSpace` lock.
+#[async_trait]
+pub trait AsyncMethod: Send + Sync {
+    /// Called when a client calls the method. The object and method of the request have been
+    /// validated against the address space before the call.
+    async fn call(
+        &self,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError>;
+
+    /// The time allowed for a call. Calls that take longer fail with `BadTimeout`. The default of
+    /// `None` uses the server's `async_operation_timeout_ms` limit.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
 }
@@ -91,6 +91,8 @@ struct WriteState {
     pub writer: OwnedWriteHalf,
     /// Write buffer (protected since it might be accessed by publish response / event activity)
     pub send_buffer: Arc<Mutex<MessageWriter>>,
+    /// Traffic counters
+    pub stats: Arc<TransportStats>,
 }
 
 /// This is the thing that handles input and output for the open connection associated with the
@@ -120,6 +122,8 @@ pub struct TcpTransport {
     pending_chunks: Vec<MessageChunk>,
     /// Sessions associated with this connection. Normally there would be one, but potentially there could be more
     session_manager: Arc<RwLock<SessionManager>>,
+    /// Traffic counters of the connection
+    stats: Arc<TransportStats>,
 }
 
 impl Transport for TcpTransport {
@@ -150,6 +154,10 @@ impl Transport for TcpTransport {
     fn session_manager(&self) -> Arc<RwLock<SessionManager>> {
         self.session_manager.clone()
     }
+
+    fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
 }
 
 impl TcpTransport {
@@ -194,6 +202,7 @@ impl TcpTransport {
             last_received_sequence_number: 0,
             pending_chunks: Vec::with_capacity(2),
             session_manager,
+            stats: Arc::new(TransportStats::default()),
         }
     }
 
@@ -249,6 +258,8 @@ impl TcpTransport {
             error!("Write IO error {:?}", err);
             let mut transport = trace_write_lock!(write_state.transport);
             transport.finish(StatusCode::BadCommunicationError);
+        } else {
+            write_state.stats.on_bytes_sent(bytes_to_write.len());
         }
         write_state
     }
@@ -320,11 +331,13 @@ impl TcpTransport {
         transport: Arc<RwLock<TcpTransport>>,
         send_buffer: Arc<Mutex<MessageWriter>>,
     ) -> Result<(), StatusCode> {
+        let stats = trace_read_lock!(transport).stats();
         let mut write_state = WriteState {
             transport: transport.clone(),
             writer,
             send_buffer,
             secure_channel,
+            stats,
         };
 
         // The writing task waits for messages that are to be sent
@@ -352,7 +365,13 @@ impl TcpTransport {
                         send_buffer.write_ack(&ack)?;
                     }
                     msg => {
+                        let last_sequence_number = send_buffer.last_sent_sequence_number();
                         send_buffer.write(request_id, msg, &secure_channel)?;
+                        write_state.stats.on_chunks_sent(u64::from(
+                            send_buffer
+                                .last_sent_sequence_number()
+                                .wrapping_sub(last_sequence_number),
+                        ));
                     }
                 }
             }
@@ -578,6 +597,8 @@ impl TcpTransport {
             secure_channel.decoding_options()
         };
 
+        self.stats.on_chunk_received(chunk.data.len());
+
         let message_header = chunk.message_header(&decoding_options)?;
 
         if message_header.is_final == MessageIsFinalType::FinalError {
@@ -654,6 +675,13 @@ impl TcpTransport {
             self.client_protocol_version,
             request,
         )?;
+        if let SupportedMessage::OpenSecureChannelRequest(ref request) = request {
+            if request.request_type == SecurityTokenRequestType::Renew
+                && !matches!(response, SupportedMessage::ServiceFault(_))
+            {
+                self.stats.on_secure_channel_renewal();
+            }
+        }
         let _ = sender.send_message(request_id, response);
         Ok(())
     }
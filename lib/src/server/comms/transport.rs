@@ -6,7 +6,13 @@
 //! Provides a level of abstraction for the server to call through when it doesn't require specific
 //! knowledge of the transport it is using.
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use crate::sync::*;
 use crate::types::status_code::StatusCode;
@@ -21,6 +27,62 @@ pub enum TransportState {
     Finished(StatusCode),
 }
 
+/// Counters of the traffic over a transport. The counters are shared with the tasks that read and
+/// write to the connection so they are atomic.
+#[derive(Debug, Default)]
+pub struct TransportStats {
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    chunks_received: AtomicU64,
+    chunks_sent: AtomicU64,
+    secure_channel_renewals: AtomicU64,
+}
+
+impl TransportStats {
+    /// Returns the number of bytes received in chunks
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes sent
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of message chunks received
+    pub fn chunks_received(&self) -> u64 {
+        self.chunks_received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of message chunks sent
+    pub fn chunks_sent(&self) -> u64 {
+        self.chunks_sent.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times the client renewed the secure channel's security token
+    pub fn secure_channel_renewals(&self) -> u64 {
+        self.secure_channel_renewals.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn on_chunk_received(&self, bytes: usize) {
+        self.chunks_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_chunks_sent(&self, chunks: u64) {
+        self.chunks_sent.fetch_add(chunks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_bytes_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn on_secure_channel_renewal(&self) {
+        self.secure_channel_renewals.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Represents a transport layer, the thing responsible for maintaining an open channel and transferring
 /// data between the server and the client.
 pub trait Transport {
@@ -43,4 +105,6 @@ pub trait Transport {
     fn client_address(&self) -> Option<SocketAddr>;
     /// Returns the session map for the connection
     fn session_manager(&self) -> Arc<RwLock<SessionManager>>;
+    /// Returns the traffic counters of the connection
+    fn stats(&self) -> Arc<TransportStats>;
 }
@@ -12,7 +12,10 @@ use std::{
 use crate::{
     core::{comms::url::url_matches_except_host, config::Config},
     crypto::{CertificateStore, SecurityPolicy, Thumbprint},
-    types::{service_types::ApplicationType, DecodingOptions, MessageSecurityMode, UAString},
+    types::{
+        service_types::{ApplicationType, RedundancySupport},
+        DecodingOptions, MessageSecurityMode, UAString,
+    },
 };
 
 use super::constants;
@@ -42,6 +45,10 @@ pub struct ServerUserToken {
     // X509 file path (as a string)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub x509: Option<String>,
+    /// How an issued JWT is verified. The subject of the token identifies the user, the user name
+    /// only names the token in logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<ServerJwtConfig>,
     #[serde(skip)]
     pub thumbprint: Option<Thumbprint>,
 }
@@ -56,6 +63,7 @@ impl ServerUserToken {
             user: user.into(),
             pass: Some(pass.into()),
             x509: None,
+            jwt: None,
             thumbprint: None,
         }
     }
@@ -69,6 +77,21 @@ impl ServerUserToken {
             user: user.into(),
             pass: None,
             x509: Some(cert_path.to_string_lossy().to_string()),
+            jwt: None,
+            thumbprint: None,
+        }
+    }
+
+    /// Create a token that accepts JWTs issued by an authorization service.
+    pub fn jwt<T>(user: T, jwt: ServerJwtConfig) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerUserToken {
+            user: user.into(),
+            pass: None,
+            x509: None,
+            jwt: Some(jwt),
             thumbprint: None,
         }
     }
@@ -102,24 +125,38 @@ impl ServerUserToken {
             error!("User token {} has an empty user name.", id);
             valid = false;
         }
-        if self.pass.is_some() && self.x509.is_some() {
+        let kinds = [self.pass.is_some(), self.x509.is_some(), self.jwt.is_some()]
+            .iter()
+            .filter(|kind| **kind)
+            .count();
+        if kinds > 1 {
             error!(
-                "User token {} holds a password and certificate info - it cannot be both.",
+                "User token {} holds more than one of a password, certificate info or JWT config - it can only be one.",
                 id
             );
             valid = false;
-        } else if self.pass.is_none() && self.x509.is_none() {
+        } else if kinds == 0 {
             error!(
-                "User token {} fails to provide a password or certificate info.",
+                "User token {} fails to provide a password, certificate info or JWT config.",
                 id
             );
             valid = false;
         }
+        if let Some(ref jwt) = self.jwt {
+            if jwt.keys_path.is_empty() {
+                error!("User token {} has an empty JWT keys path.", id);
+                valid = false;
+            }
+        }
         valid
     }
 
     pub fn is_user_pass(&self) -> bool {
-        self.x509.is_none()
+        self.x509.is_none() && self.jwt.is_none()
+    }
+
+    pub fn is_jwt(&self) -> bool {
+        self.jwt.is_some()
     }
 
     pub fn is_x509(&self) -> bool {
@@ -127,6 +164,58 @@ impl ServerUserToken {
     }
 }
 
+/// Describes how the JWTs issued by an authorization service are verified and how their claims
+/// are mapped to roles.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ServerJwtConfig {
+    /// Path to a JWKS file, or a PEM / DER encoded public key or certificate of the issuer. The
+    /// file is read each time a token is verified so keys may be rotated without a restart.
+    pub keys_path: String,
+    /// The issuer that the `iss` claim must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// The audience that the `aud` claim must contain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    /// Seconds of clock skew allowed when testing the expiry of a token
+    #[serde(default)]
+    pub leeway: u64,
+    /// The claim holding the role names of the user. It may be a dot separated path to a
+    /// nested claim.
+    #[serde(default = "ServerJwtConfig::default_roles_claim")]
+    pub roles_claim: String,
+    /// Maps the role names in the roles claim to roles. A role is either the name of a well known
+    /// role, e.g. "Operator", or the node id of a role object. Unmapped names are ignored.
+    #[serde(default)]
+    pub roles: BTreeMap<String, String>,
+    /// The issuer endpoint url advertised in the user token policy, i.e. the JSON object which
+    /// tells clients where to request tokens from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer_endpoint_url: Option<String>,
+}
+
+impl ServerJwtConfig {
+    /// Creates a config which verifies tokens with the keys in the file
+    pub fn new<T>(keys_path: T) -> Self
+    where
+        T: Into<String>,
+    {
+        ServerJwtConfig {
+            keys_path: keys_path.into(),
+            issuer: None,
+            audience: None,
+            leeway: 0,
+            roles_claim: Self::default_roles_claim(),
+            roles: BTreeMap::new(),
+            issuer_endpoint_url: None,
+        }
+    }
+
+    fn default_roles_claim() -> String {
+        "roles".to_string()
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Limits {
     /// Indicates if clients are able to modify the address space through the node management service
@@ -157,6 +246,16 @@ pub struct Limits {
     pub send_buffer_size: usize,
     /// Receive buffer size in bytes
     pub receive_buffer_size: usize,
+    /// Time in millis allowed for an async getter, setter or method to complete before the
+    /// operation fails with `BadTimeout`
+    #[serde(default = "Limits::default_async_operation_timeout_ms")]
+    pub async_operation_timeout_ms: u32,
+}
+
+impl Limits {
+    fn default_async_operation_timeout_ms() -> u32 {
+        constants::DEFAULT_ASYNC_OPERATION_TIMEOUT_MS
+    }
 }
 
 impl Default for Limits {
@@ -176,6 +275,7 @@ impl Default for Limits {
             min_publishing_interval: constants::MIN_PUBLISHING_INTERVAL,
             send_buffer_size: SEND_BUFFER_SIZE,
             receive_buffer_size: RECEIVE_BUFFER_SIZE,
+            async_operation_timeout_ms: constants::DEFAULT_ASYNC_OPERATION_TIMEOUT_MS,
         }
     }
 }
@@ -517,6 +617,17 @@ impl ServerEndpoint {
         false
     }
 
+    /// Tests if this endpoint supports issued JWT tokens. It does this by looking to see
+    /// if any of the users allowed to access this endpoint are JWT users.
+    pub fn supports_jwt(&self, server_tokens: &BTreeMap<String, ServerUserToken>) -> bool {
+        self.user_token_ids.iter().any(|user_token_id| {
+            server_tokens
+                .get(user_token_id)
+                .map(|user_token| user_token.is_jwt())
+                .unwrap_or(false)
+        })
+    }
+
     pub fn supports_user_token_id(&self, id: &str) -> bool {
         self.user_token_ids.contains(id)
     }
@@ -529,6 +640,62 @@ pub struct Performance {
     pub single_threaded_executor: bool,
 }
 
+/// Describes the redundant set of servers that the server is part of. Only non-transparent
+/// redundancy is supported, where clients fail over between the servers of the set.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RedundancyConfig {
+    /// The redundancy support of the set, one of "Cold", "Warm", "Hot" or "HotAndMirrored"
+    pub mode: String,
+    /// The application URIs of the other servers in the set
+    #[serde(default)]
+    pub peer_server_uris: Vec<String>,
+    /// How often in milliseconds the health function is called to update the service level
+    #[serde(default = "RedundancyConfig::default_health_check_interval_ms")]
+    pub health_check_interval_ms: u64,
+}
+
+impl RedundancyConfig {
+    /// Creates a config for a set with the redundancy support and the other servers
+    pub fn new(redundancy_support: RedundancySupport, peer_server_uris: Vec<String>) -> Self {
+        RedundancyConfig {
+            mode: format!("{:?}", redundancy_support),
+            peer_server_uris,
+            health_check_interval_ms: Self::default_health_check_interval_ms(),
+        }
+    }
+
+    fn default_health_check_interval_ms() -> u64 {
+        1000
+    }
+
+    /// Returns the redundancy support of the mode, or `None` if it is not a non-transparent mode
+    pub fn redundancy_support(&self) -> Option<RedundancySupport> {
+        match self.mode.as_str() {
+            "Cold" => Some(RedundancySupport::Cold),
+            "Warm" => Some(RedundancySupport::Warm),
+            "Hot" => Some(RedundancySupport::Hot),
+            "HotAndMirrored" => Some(RedundancySupport::HotAndMirrored),
+            _ => None,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let mut valid = true;
+        if self.redundancy_support().is_none() {
+            error!(
+                "Redundancy mode {} is invalid. It must be Cold, Warm, Hot or HotAndMirrored",
+                self.mode
+            );
+            valid = false;
+        }
+        if self.health_check_interval_ms == 0 {
+            error!("Redundancy health check interval is invalid");
+            valid = false;
+        }
+        valid
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct ServerConfig {
     /// An id for this server
@@ -567,6 +734,13 @@ pub struct ServerConfig {
     pub default_endpoint: Option<String>,
     /// Endpoints supported by the server
     pub endpoints: BTreeMap<String, ServerEndpoint>,
+    /// Path to a journal file. When set, changes made through the node management services and
+    /// writes to persistent nodes are recorded and replayed when the server starts.
+    #[serde(default)]
+    pub journal_path: Option<PathBuf>,
+    /// The redundant set of servers that this server is part of, if any
+    #[serde(default)]
+    pub redundancy: Option<RedundancyConfig>,
 }
 
 impl Config for ServerConfig {
@@ -616,6 +790,11 @@ impl Config for ServerConfig {
             error!("Server configuration is invalid. Discovery urls not set");
             valid = false;
         }
+        if let Some(ref redundancy) = self.redundancy {
+            if !redundancy.is_valid() {
+                valid = false;
+            }
+        }
         valid
     }
 
@@ -671,6 +850,8 @@ impl Default for ServerConfig {
             performance: Performance {
                 single_threaded_executor: false,
             },
+            journal_path: None,
+            redundancy: None,
         }
     }
 }
@@ -727,6 +908,8 @@ impl ServerConfig {
             performance: Performance {
                 single_threaded_executor: false,
             },
+            journal_path: None,
+            redundancy: None,
         }
     }
 
@@ -5,18 +5,100 @@
 //! Provides diagnostics structures and functions for gathering information about the running
 //! state of a server.
 
-use crate::types::service_types::ServerDiagnosticsSummaryDataType;
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use crate::sync::*;
+use crate::types::node_ids::ObjectId;
+use crate::types::service_types::{
+    SamplingIntervalDiagnosticsDataType, ServerDiagnosticsSummaryDataType,
+    SessionDiagnosticsDataType, SessionSecurityDiagnosticsDataType,
+    SubscriptionDiagnosticsDataType,
+};
 use crate::{deregister_runtime_component, register_runtime_component};
 
 use crate::core::RUNTIME;
 
-use super::{session::Session, subscriptions::subscription::Subscription};
+use super::{
+    session::Session,
+    session_diagnostics::{add_sampling_interval, SessionDiagnostics},
+    subscriptions::subscription::Subscription,
+};
+
+/// Upper bounds in seconds of the buckets of the service latency histograms
+pub const SERVICE_LATENCY_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0,
+];
+
+/// A histogram of the time taken by the server to respond to the requests of a service.
+#[derive(Clone, Serialize, Debug, Default)]
+pub struct LatencyHistogram {
+    /// Number of observations in each of `SERVICE_LATENCY_BUCKETS`, not including the lower buckets
+    bucket_counts: [u64; SERVICE_LATENCY_BUCKETS.len()],
+    /// Number of observations
+    count: u64,
+    /// Sum of the observations in seconds
+    sum: f64,
+}
+
+impl LatencyHistogram {
+    /// Adds an observation to the histogram
+    pub fn observe(&mut self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        if let Some(idx) = SERVICE_LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.bucket_counts[idx] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// Returns the number of observations
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the observations in seconds
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns the upper bound of each bucket with the number of observations less than or
+    /// equal to it. The `+Inf` bucket is the count.
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        SERVICE_LATENCY_BUCKETS
+            .iter()
+            .zip(self.bucket_counts.iter())
+            .map(|(le, count)| {
+                total += count;
+                (*le, total)
+            })
+            .collect()
+    }
+}
 
 /// Structure that captures di                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                          agnostics information for the server
 #[derive(Clone, Serialize, Debug)]
 pub struct ServerDiagnostics {
     /// This is a live summary of the server diagnostics
     server_diagnostics_summary: ServerDiagnosticsSummaryDataType,
+    /// Flag that switches the collection of diagnostics on or off. It is shared with the
+    /// diagnostics of each session.
+    #[serde(skip)]
+    enabled: Arc<AtomicBool>,
+    /// Diagnostics of the sessions currently established in the server
+    #[serde(skip)]
+    sessions: Vec<Arc<RwLock<SessionDiagnostics>>>,
+    /// The most monitored items seen for each sampling interval
+    #[serde(skip)]
+    max_monitored_item_counts: Vec<(f64, u32)>,
+    /// Latencies of requests by the type of request
+    #[serde(skip)]
+    service_latencies: HashMap<ObjectId, LatencyHistogram>,
 }
 
 const SERVER_DIAGNOSTICS: &str = "ServerDiagnostics";
@@ -26,6 +108,10 @@ impl Default for ServerDiagnostics {
         register_runtime_component!(SERVER_DIAGNOSTICS);
         Self {
             server_diagnostics_summary: ServerDiagnosticsSummaryDataType::default(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            sessions: Vec::new(),
+            max_monitored_item_counts: Vec::new(),
+            service_latencies: HashMap::new(),
         }
     }
 }
@@ -43,25 +129,146 @@ impl ServerDiagnostics {
         &self.server_diagnostics_summary
     }
 
+    /// Tests if diagnostics are being collected. This is the value of the `EnabledFlag` of the
+    /// server diagnostics.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Switches the collection of diagnostics on or off. While it is off, counters stop
+    /// accumulating and the diagnostics arrays are empty.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns the flag that is shared with the session diagnostics
+    pub(crate) fn enabled(&self) -> Arc<AtomicBool> {
+        self.enabled.clone()
+    }
+
+    /// Returns the diagnostics of every session
+    pub fn session_diagnostics_array(&self) -> Vec<SessionDiagnosticsDataType> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        self.sessions
+            .iter()
+            .map(|s| trace_read_lock!(s).session_diagnostics())
+            .collect()
+    }
+
+    /// Returns the security diagnostics of every session
+    pub fn session_security_diagnostics_array(&self) -> Vec<SessionSecurityDiagnosticsDataType> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        self.sessions
+            .iter()
+            .map(|s| trace_read_lock!(s).session_security_diagnostics().clone())
+            .collect()
+    }
+
+    /// Returns the diagnostics of every subscription in every session
+    pub fn subscription_diagnostics_array(&self) -> Vec<SubscriptionDiagnosticsDataType> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        self.sessions
+            .iter()
+            .flat_map(|s| trace_read_lock!(s).subscription_diagnostics().to_vec())
+            .collect()
+    }
+
+    /// Returns the monitored items of every session grouped by their sampling interval. The
+    /// maximum count of an interval is the most that has been observed by this call.
+    pub fn sampling_interval_diagnostics_array(
+        &mut self,
+    ) -> Vec<SamplingIntervalDiagnosticsDataType> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        let mut diagnostics = Vec::new();
+        for session in &self.sessions {
+            let session = trace_read_lock!(session);
+            for d in session.sampling_interval_diagnostics() {
+                add_sampling_interval(
+                    &mut diagnostics,
+                    d.sampling_interval,
+                    d.monitored_item_count,
+                    d.disabled_monitored_item_count,
+                );
+            }
+        }
+        for d in diagnostics.iter_mut() {
+            if let Some(max) = self
+                .max_monitored_item_counts
+                .iter_mut()
+                .find(|(sampling_interval, _)| *sampling_interval == d.sampling_interval)
+            {
+                max.1 = max.1.max(d.monitored_item_count);
+                d.max_monitored_item_count = max.1;
+            } else {
+                self.max_monitored_item_counts
+                    .push((d.sampling_interval, d.monitored_item_count));
+                d.max_monitored_item_count = d.monitored_item_count;
+            }
+        }
+        diagnostics
+    }
+
+    /// Returns the latency histogram of each service that has been called, ordered by the name
+    /// of the service, e.g. "Read".
+    pub fn service_latencies(&self) -> Vec<(String, LatencyHistogram)> {
+        let mut service_latencies = self
+            .service_latencies
+            .iter()
+            .map(|(request_type, histogram)| {
+                // Request types are named like ReadRequest_Encoding_DefaultBinary
+                let name = format!("{:?}", request_type);
+                let name = name.trim_end_matches("Request_Encoding_DefaultBinary");
+                (name.to_string(), histogram.clone())
+            })
+            .collect::<Vec<_>>();
+        service_latencies.sort_by(|a, b| a.0.cmp(&b.0));
+        service_latencies
+    }
+
+    /// Records the time taken to respond to a request
+    pub(crate) fn on_service_latency(&mut self, request_type: ObjectId, latency: Duration) {
+        if self.is_enabled() {
+            self.service_latencies
+                .entry(request_type)
+                .or_default()
+                .observe(latency);
+        }
+    }
+
     /// Increment the number of requests that were rejected due to security constraints since the server was
     /// started (or restarted). The requests include all Services defined in Part 4, also requests
     /// to create sessions.
     pub(crate) fn on_rejected_security_session(&mut self) {
-        self.server_diagnostics_summary
-            .security_rejected_session_count += 1;
+        if self.is_enabled() {
+            self.server_diagnostics_summary
+                .security_rejected_session_count += 1;
+        }
     }
 
     /// Increment the number of requests that were rejected since the server was started (or restarted). The
     /// requests include all Services defined in Part 4, also requests to create sessions. This
     /// number includes the securityRejectedRequestsCount.
     pub(crate) fn on_rejected_session(&mut self) {
-        self.server_diagnostics_summary.rejected_session_count += 1;
+        if self.is_enabled() {
+            self.server_diagnostics_summary.rejected_session_count += 1;
+        }
     }
 
     /// Increment the number of client sessions currently established in the server.
-    pub(crate) fn on_create_session(&mut self, _session: &Session) {
+    pub(crate) fn on_create_session(&mut self, session: &Session) {
+        self.sessions.push(session.session_diagnostics());
         self.server_diagnostics_summary.current_session_count += 1;
-        self.server_diagnostics_summary.cumulated_session_count += 1;
+        if self.is_enabled() {
+            self.server_diagnostics_summary.cumulated_session_count += 1;
+        }
         debug!(
             "Incrementing current session count to {}",
             self.server_diagnostics_summary.current_session_count
@@ -69,7 +276,10 @@ impl ServerDiagnostics {
     }
 
     /// Decrement the number of client sessions currently established in the server.
-    pub(crate) fn on_destroy_session(&mut self, _session: &Session) {
+    pub(crate) fn on_destroy_session(&mut self, session: &Session) {
+        let session_diagnostics = session.session_diagnostics();
+        self.sessions
+            .retain(|s| !Arc::ptr_eq(s, &session_diagnostics));
         self.server_diagnostics_summary.current_session_count -= 1;
         debug!(
             "Decrementing current session count to {}",
@@ -80,7 +290,9 @@ impl ServerDiagnostics {
     /// Increment the number of subscriptions currently established in the server.
     pub(crate) fn on_create_subscription(&mut self, _subscription: &Subscription) {
         self.server_diagnostics_summary.current_subscription_count += 1;
-        self.server_diagnostics_summary.cumulated_subscription_count += 1;
+        if self.is_enabled() {
+            self.server_diagnostics_summary.cumulated_subscription_count += 1;
+        }
     }
 
     /// Decrement the number of subscriptions currently established in the server.
@@ -90,7 +302,9 @@ impl ServerDiagnostics {
 
     /// Increment the number of client sessions that were closed due to timeout since the server was started (or restarted).
     pub(crate) fn on_session_timeout(&mut self) {
-        self.server_diagnostics_summary.session_timeout_count += 1;
+        if self.is_enabled() {
+            self.server_diagnostics_summary.session_timeout_count += 1;
+        }
     }
 
     // --- These are not yet called by anything
@@ -11,7 +11,7 @@ use crate::types::{
         EventFilter, EventFilterResult, FilterOperator, SimpleAttributeOperand,
     },
     status_code::StatusCode,
-    AttributeId, DateTimeUtc, NodeId, Variant,
+    AttributeId, DateTimeUtc, NodeId, Variant, VariantTypeId,
 };
 
 use crate::server::{
@@ -167,6 +167,84 @@ fn validate_select_clause(
     }
 }
 
+/// Tests if the operand of an operator must be a literal. The type and reference type operands
+/// of the node operators must be node ids supplied by the client.
+fn requires_literal(filter_operator: FilterOperator, operand_idx: usize) -> bool {
+    matches!(
+        (filter_operator, operand_idx),
+        (FilterOperator::OfType, 0) | (FilterOperator::InView, 0) | (FilterOperator::RelatedTo, 0..=2)
+    )
+}
+
+/// Tests if the literal is a node id of a node whose node class is accepted by the filter.
+fn is_node_of_class<F>(value: &Variant, address_space: &AddressSpace, filter: F) -> bool
+where
+    F: Fn(&NodeType) -> bool,
+{
+    let node_id = match value {
+        Variant::NodeId(node_id) => node_id.as_ref(),
+        Variant::ExpandedNodeId(node_id) => &node_id.node_id,
+        _ => return false,
+    };
+    address_space.find_node(node_id).map(filter).unwrap_or(false)
+}
+
+/// Checks that a literal operand is compatible with the operator it is passed to.
+fn validate_literal(
+    filter_operator: FilterOperator,
+    operand_idx: usize,
+    value: &Variant,
+    address_space: &AddressSpace,
+) -> StatusCode {
+    let valid = match (filter_operator, operand_idx) {
+        (FilterOperator::OfType, 0) | (FilterOperator::RelatedTo, 0..=1) => {
+            is_node_of_class(value, address_space, |node| {
+                matches!(node, NodeType::ObjectType(_) | NodeType::VariableType(_))
+            })
+        }
+        (FilterOperator::InView, 0) => {
+            is_node_of_class(value, address_space, |node| matches!(node, NodeType::View(_)))
+        }
+        (FilterOperator::RelatedTo, 2) => is_node_of_class(value, address_space, |node| {
+            matches!(node, NodeType::ReferenceType(_))
+        }),
+        (FilterOperator::RelatedTo, 3) => {
+            matches!(value.convert(VariantTypeId::UInt32), Variant::UInt32(hops) if hops > 0)
+        }
+        (FilterOperator::RelatedTo, 4..=5)
+        | (FilterOperator::And, _)
+        | (FilterOperator::Or, _)
+        | (FilterOperator::Not, _) => {
+            matches!(value.convert(VariantTypeId::Boolean), Variant::Boolean(_))
+        }
+        (FilterOperator::Cast, 1) => {
+            matches!(value, Variant::NodeId(_) | Variant::ExpandedNodeId(_))
+        }
+        (FilterOperator::Like, 1) => matches!(value, Variant::String(_)),
+        (FilterOperator::BitwiseAnd, _) | (FilterOperator::BitwiseOr, _) => matches!(
+            value.type_id(),
+            VariantTypeId::SByte
+                | VariantTypeId::Byte
+                | VariantTypeId::Int16
+                | VariantTypeId::UInt16
+                | VariantTypeId::Int32
+                | VariantTypeId::UInt32
+                | VariantTypeId::Int64
+                | VariantTypeId::UInt64
+        ),
+        _ => true,
+    };
+    if valid {
+        StatusCode::Good
+    } else {
+        error!(
+            "Literal {:?} is not a valid operand {} of {:?}",
+            value, operand_idx, filter_operator
+        );
+        StatusCode::BadFilterLiteralInvalid
+    }
+}
+
 fn validate_where_clause(
     where_clause: &ContentFilter,
     address_space: &AddressSpace,
@@ -184,39 +262,26 @@ fn validate_where_clause(
     // examples using the ContentFilter structure.
 
     if let Some(ref elements) = where_clause.elements {
-        let element_results = elements.iter().map(|e| {
+        let element_results = elements.iter().enumerate().map(|(element_idx, e)| {
             let (status_code, operand_status_codes) = if e.filter_operands.is_none() {
                 // All operators need at least one operand
                 (StatusCode::BadFilterOperandCountMismatch, None)
             } else {
                 let filter_operands = e.filter_operands.as_ref().unwrap();
 
-                // The right number of operators? The spec implies it is okay to pass
-                // more operands than the required #, but less is an error.
-                let operand_count_mismatch = match e.filter_operator {
-                    FilterOperator::Equals => filter_operands.len() < 2,
-                    FilterOperator::IsNull => filter_operands.len() < 1,
-                    FilterOperator::GreaterThan => filter_operands.len() < 2,
-                    FilterOperator::LessThan => filter_operands.len() < 2,
-                    FilterOperator::GreaterThanOrEqual => filter_operands.len() < 2,
-                    FilterOperator::LessThanOrEqual => filter_operands.len() < 2,
-                    FilterOperator::Like => filter_operands.len() < 2,
-                    FilterOperator::Not => filter_operands.len() < 1,
-                    FilterOperator::Between => filter_operands.len() < 3,
-                    FilterOperator::InList => filter_operands.len() < 2, // 2..n
-                    FilterOperator::And => filter_operands.len() < 2,
-                    FilterOperator::Or => filter_operands.len() < 2,
-                    FilterOperator::Cast => filter_operands.len() < 2,
-                    FilterOperator::BitwiseAnd => filter_operands.len() < 2,
-                    FilterOperator::BitwiseOr => filter_operands.len() < 2,
-                    _ => true,
-                };
+                // The right number of operators?
+                let operand_count_mismatch =
+                    filter_operands.len() < operator::min_operand_count(e.filter_operator);
 
                 // Check if the operands look okay
-                let operand_status_codes = filter_operands.iter().map(|e| {
+                let operand_status_codes = filter_operands.iter().enumerate().map(|(operand_idx, o)| {
                     // Look to see if any operand cannot be parsed
-                    match <Operand>::try_from(e) {
+                    match <Operand>::try_from(o) {
                         Ok(operand) => {
+                            if requires_literal(e.filter_operator, operand_idx) && !operand.is_literal() {
+                                error!("Operand {} of {:?} must be a literal", operand_idx, e.filter_operator);
+                                return StatusCode::BadFilterOperandInvalid;
+                            }
                             match operand {
                                 Operand::AttributeOperand(_) => {
                                     // AttributeOperand may not be used in an EventFilter where clause
@@ -224,15 +289,20 @@ fn validate_where_clause(
                                     StatusCode::BadFilterOperandInvalid
                                 }
                                 Operand::ElementOperand(ref o) => {
-                                    // Check that operands have to have an index <= number of elements
+                                    // An element operand must refer to an element after the one it is part of,
+                                    // which rules out references to itself or circular references.
                                     if o.index as usize >= elements.len() {
                                         error!("Invalid element operand is out of range");
-                                        StatusCode::BadFilterOperandInvalid
+                                        StatusCode::BadFilterElementInvalid
+                                    } else if o.index as usize <= element_idx {
+                                        error!("Invalid element operand refers to itself or a preceding element");
+                                        StatusCode::BadFilterElementInvalid
                                     } else {
                                         StatusCode::Good
                                     }
-                                    // TODO operand should not refer to itself either directly or through circular
-                                    //  references
+                                }
+                                Operand::LiteralOperand(ref o) => {
+                                    validate_literal(e.filter_operator, operand_idx, &o.value, address_space)
                                 }
                                 Operand::SimpleAttributeOperand(ref o) => {
                                     // The structure requires the node id of an event type supported
@@ -258,7 +328,6 @@ fn validate_where_clause(
                                         StatusCode::BadFilterOperandInvalid
                                     }
                                 }
-                                _ => StatusCode::Good
                             }
                         }
                         Err(err) => {
@@ -305,7 +374,7 @@ fn validate_where_clause(
 
 #[test]
 fn validate_where_clause_test() {
-    use crate::types::service_types::ContentFilterElement;
+    use crate::types::{service_types::ContentFilterElement, ObjectTypeId, UAString};
 
     let address_space = AddressSpace::new();
 
@@ -388,6 +457,82 @@ fn validate_where_clause_test() {
         );
     }
 
-    // TODO check operands are compatible with operator
-    // TODO check for ElementOperands which are cyclical or out of range
+    // check operands are compatible with operator
+    {
+        let where_clause = ContentFilter {
+            elements: Some(vec![
+                ContentFilterElement::from((
+                    FilterOperator::OfType,
+                    vec![Operand::literal(NodeId::objects_folder_id())],
+                )),
+                ContentFilterElement::from((
+                    FilterOperator::Like,
+                    vec![Operand::literal("Hello"), Operand::literal(10)],
+                )),
+                ContentFilterElement::from((
+                    FilterOperator::InView,
+                    vec![Operand::simple_attribute(
+                        ObjectTypeId::BaseEventType,
+                        "SourceNode",
+                        AttributeId::Value,
+                        UAString::null(),
+                    )],
+                )),
+                ContentFilterElement::from((
+                    FilterOperator::OfType,
+                    vec![Operand::literal(Into::<NodeId>::into(ObjectTypeId::BaseEventType))],
+                )),
+            ]),
+        };
+        let result = validate_where_clause(&where_clause, &address_space).unwrap();
+        let element_results = result.element_results.unwrap();
+        assert_eq!(
+            element_results[0].status_code,
+            StatusCode::BadFilterOperatorInvalid
+        );
+        assert_eq!(
+            element_results[0].operand_status_codes,
+            Some(vec![StatusCode::BadFilterLiteralInvalid])
+        );
+        assert_eq!(
+            element_results[1].operand_status_codes,
+            Some(vec![StatusCode::Good, StatusCode::BadFilterLiteralInvalid])
+        );
+        assert_eq!(
+            element_results[2].operand_status_codes,
+            Some(vec![StatusCode::BadFilterOperandInvalid])
+        );
+        assert_eq!(element_results[3].status_code, StatusCode::Good);
+    }
+
+    // check for ElementOperands which are cyclical or out of range
+    {
+        let where_clause = ContentFilter {
+            elements: Some(vec![
+                ContentFilterElement::from((
+                    FilterOperator::And,
+                    vec![Operand::element(1), Operand::element(2)],
+                )),
+                ContentFilterElement::from((FilterOperator::Not, vec![Operand::element(1)])),
+                ContentFilterElement::from((FilterOperator::Not, vec![Operand::element(0)])),
+                ContentFilterElement::from((FilterOperator::Not, vec![Operand::element(4)])),
+            ]),
+        };
+        let result = validate_where_clause(&where_clause, &address_space).unwrap();
+        let operand_status_codes = result
+            .element_results
+            .unwrap()
+            .into_iter()
+            .map(|e| e.operand_status_codes.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            operand_status_codes,
+            vec![
+                vec![StatusCode::Good, StatusCode::Good],
+                vec![StatusCode::BadFilterElementInvalid],
+                vec![StatusCode::BadFilterElementInvalid],
+                vec![StatusCode::BadFilterElementInvalid],
+            ]
+        );
+    }
 }
@@ -41,6 +41,21 @@ fn make_filter_operands(filter_operands: &[ExtensionObject]) -> Result<Vec<Opera
     }
 }
 
+/// Returns the number of operands the operator requires. The spec implies it is okay to pass
+/// more operands than the required number, but less is an error.
+pub(crate) fn min_operand_count(filter_operator: FilterOperator) -> usize {
+    match filter_operator {
+        FilterOperator::IsNull
+        | FilterOperator::Not
+        | FilterOperator::InView
+        | FilterOperator::OfType => 1,
+        FilterOperator::Between => 3,
+        FilterOperator::RelatedTo => 6,
+        // InList takes 2..n
+        _ => 2,
+    }
+}
+
 /// Evaluates the expression
 pub(crate) fn evaluate(
     object_id: &NodeId,
@@ -55,6 +70,13 @@ pub(crate) fn evaluate(
             // doesn't have to be done on each evaluation, e.g. turn ContentFilterElement into a ServerContentFilterElement
             // which has the operands .
             let operands = make_filter_operands(filter_operands)?;
+            if operands.len() < min_operand_count(element.filter_operator) {
+                warn!(
+                    "evaluate() called with too few operands for {:?}",
+                    element.filter_operator
+                );
+                return Err(StatusCode::BadFilterOperandCountMismatch);
+            }
             match element.filter_operator {
                 FilterOperator::Equals => eq(
                     object_id,
@@ -161,7 +183,27 @@ pub(crate) fn evaluate(
                     elements,
                     address_space,
                 ),
-                _ => Err(StatusCode::BadFilterOperatorUnsupported),
+                FilterOperator::InView => in_view(
+                    object_id,
+                    &operands[..],
+                    used_elements,
+                    elements,
+                    address_space,
+                ),
+                FilterOperator::OfType => of_type(
+                    object_id,
+                    &operands[..],
+                    used_elements,
+                    elements,
+                    address_space,
+                ),
+                FilterOperator::RelatedTo => related_to(
+                    object_id,
+                    &operands[..],
+                    used_elements,
+                    elements,
+                    address_space,
+                ),
             }
         } else {
             // All operators need at least one operand
@@ -1010,3 +1052,181 @@ pub(crate) fn bitwise_or(
         address_space,
     )
 }
+
+/// Gets the value of an operand which is expected to be a node id.
+fn node_id_of(
+    object_id: &NodeId,
+    operand: &Operand,
+    used_elements: &mut HashSet<u32>,
+    elements: &[ContentFilterElement],
+    address_space: &AddressSpace,
+) -> Result<NodeId, StatusCode> {
+    match value_of(object_id, operand, used_elements, elements, address_space)? {
+        Variant::NodeId(node_id) => Ok(*node_id),
+        Variant::ExpandedNodeId(node_id) => Ok(node_id.node_id),
+        v => {
+            error!("Operand value {:?} is not a node id", v);
+            Err(StatusCode::BadFilterOperandInvalid)
+        }
+    }
+}
+
+/// Tests if the type definition of the node is the type, or a subtype of it if subtypes are
+/// included.
+fn is_of_type(
+    node_id: &NodeId,
+    type_id: &NodeId,
+    include_subtypes: bool,
+    address_space: &AddressSpace,
+) -> bool {
+    match address_space.references().get_type_id(node_id) {
+        Some(ref node_type_id) if include_subtypes => {
+            address_space.is_subtype(node_type_id, type_id)
+        }
+        Some(ref node_type_id) => node_type_id == type_id,
+        None => false,
+    }
+}
+
+// TRUE if the target Node is contained in the View defined by operand[0], i.e. it can be reached
+// by following hierarchical references from the view.
+pub(crate) fn in_view(
+    object_id: &NodeId,
+    operands: &[Operand],
+    used_elements: &mut HashSet<u32>,
+    elements: &[ContentFilterElement],
+    address_space: &AddressSpace,
+) -> Result<Variant, StatusCode> {
+    let view_id = node_id_of(
+        object_id,
+        &operands[0],
+        used_elements,
+        elements,
+        address_space,
+    )?;
+    if let Some(NodeType::View(_)) = address_space.find_node(&view_id) {
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![view_id];
+        while let Some(node_id) = to_visit.pop() {
+            if let Some(children) = address_space.find_hierarchical_references(&node_id) {
+                for child in children {
+                    if child == *object_id {
+                        return Ok(true.into());
+                    } else if visited.insert(child.clone()) {
+                        to_visit.push(child);
+                    }
+                }
+            }
+        }
+        Ok(false.into())
+    } else {
+        error!("InView operand {:?} is not a view", view_id);
+        Err(StatusCode::BadFilterOperandInvalid)
+    }
+}
+
+// TRUE if the target Node is of type operand[0] or of a subtype of operand[0].
+pub(crate) fn of_type(
+    object_id: &NodeId,
+    operands: &[Operand],
+    used_elements: &mut HashSet<u32>,
+    elements: &[ContentFilterElement],
+    address_space: &AddressSpace,
+) -> Result<Variant, StatusCode> {
+    let type_id = node_id_of(
+        object_id,
+        &operands[0],
+        used_elements,
+        elements,
+        address_space,
+    )?;
+    Ok(is_of_type(object_id, &type_id, true, address_space).into())
+}
+
+// TRUE if the target Node is of type operand[0] and is related to a Node of type operand[1] by
+// references of type operand[2]. Operand[3] is the number of hops between the two nodes, operand[4]
+// includes subtypes of the node types and operand[5] includes subtypes of the reference type.
+//
+// Operand[0] and operand[1] must be node ids, i.e. nesting another RelatedTo element is not
+// supported.
+pub(crate) fn related_to(
+    object_id: &NodeId,
+    operands: &[Operand],
+    used_elements: &mut HashSet<u32>,
+    elements: &[ContentFilterElement],
+    address_space: &AddressSpace,
+) -> Result<Variant, StatusCode> {
+    let mut node_id_operand =
+        |operand| node_id_of(object_id, operand, used_elements, elements, address_space);
+    let source_type_id = node_id_operand(&operands[0])?;
+    let target_type_id = node_id_operand(&operands[1])?;
+    let reference_type_id = node_id_operand(&operands[2])?;
+    let hops = match value_as(
+        object_id,
+        VariantTypeId::UInt32,
+        &operands[3],
+        used_elements,
+        elements,
+        address_space,
+    )? {
+        Variant::UInt32(hops) if hops > 0 => hops,
+        _ => {
+            error!("RelatedTo has an invalid number of hops");
+            return Err(StatusCode::BadFilterOperandInvalid);
+        }
+    };
+    let mut bool_operand = |operand| {
+        value_as(
+            object_id,
+            VariantTypeId::Boolean,
+            operand,
+            used_elements,
+            elements,
+            address_space,
+        )
+        .map(|v| v == Variant::Boolean(true))
+    };
+    let include_type_subtypes = bool_operand(&operands[4])?;
+    let include_reference_subtypes = bool_operand(&operands[5])?;
+
+    if !is_of_type(
+        object_id,
+        &source_type_id,
+        include_type_subtypes,
+        address_space,
+    ) {
+        return Ok(false.into());
+    }
+
+    // Follow the references out to the number of hops, visiting each node once so cycles in
+    // the address space terminate.
+    let mut visited = HashSet::new();
+    visited.insert(object_id.clone());
+    let mut nodes = vec![object_id.clone()];
+    for _ in 0..hops {
+        nodes = nodes
+            .iter()
+            .filter_map(|node_id| {
+                address_space.find_references(
+                    node_id,
+                    Some((reference_type_id.clone(), include_reference_subtypes)),
+                )
+            })
+            .flatten()
+            .map(|r| r.target_node)
+            .filter(|node_id| visited.insert(node_id.clone()))
+            .collect();
+        if nodes.is_empty() {
+            return Ok(false.into());
+        }
+    }
+    let related = nodes.iter().any(|node_id| {
+        is_of_type(
+            node_id,
+            &target_type_id,
+            include_type_subtypes,
+            address_space,
+        )
+    });
+    Ok(related.into())
+}
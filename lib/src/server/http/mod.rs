@@ -2,142 +2,247 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
-use std::{
-    path::PathBuf,
-    sync::{mpsc, Arc},
-    thread,
+//! An http server that exposes the server's metrics in the OpenMetrics text format so they can be
+//! scraped by Prometheus or any other compatible monitoring system.
+//!
+//! The server has the following endpoints:
+//!
+//! * `GET /metrics` - returns the metrics. The request must carry the scrape token in an
+//!   `Authorization: Bearer <token>` header.
+//! * `POST /server/abort` - aborts the OPC UA server. The endpoint only exists if an abort token
+//!   has been set and the request must carry that token in the same way.
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, thread};
+
+use hyper::{
+    header::{self, HeaderMap, HeaderValue},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, StatusCode as HttpStatusCode,
 };
-
-use actix_web::{actix, fs, http, server, App, HttpRequest, HttpResponse, Responder};
 use tokio::time::{interval_at, Duration, Instant};
 
 use crate::sync::*;
+use crate::types::status_code::StatusCode;
 
-use crate::server::{metrics::ServerMetrics, server::Connections, state::ServerState};
+use crate::server::{
+    metrics::{ServerMetrics, OPEN_METRICS_CONTENT_TYPE},
+    server::{Connections, Server},
+    state::ServerState,
+};
 
-/// This is our metrics service, the thing called to handle requests coming from hyper
-#[derive(Clone)]
-struct HttpState {
+/// Shared state of the request handlers
+pub(crate) struct HttpState {
     server_state: Arc<RwLock<ServerState>>,
     connections: Arc<RwLock<Connections>>,
     server_metrics: Arc<RwLock<ServerMetrics>>,
+    scrape_token: String,
+    abort_token: Option<String>,
 }
 
-fn abort(req: &HttpRequest<HttpState>) -> impl Responder {
-    if cfg!(debug_assertions) {
-        let state = req.state();
-        // Abort the server from the command
-        let mut server_state = state.server_state.write();
-        server_state.abort();
-        HttpResponse::Ok().content_type("text/plain").body("OK")
-    } else {
-        // Abort is only enabled in debug mode
-        HttpResponse::Ok()
-            .content_type("text/plain")
-            .body("NOT IMPLEMENTED")
-    }
+/// An http server that serves the metrics of an OPC UA server. The server stops when the OPC UA
+/// server is aborted.
+///
+/// ```no_run
+/// use opcua::server::{http::MetricsServer, prelude::*};
+///
+/// let server = Server::new(ServerConfig::load(&std::path::PathBuf::from("server.conf")).unwrap());
+/// let _ = MetricsServer::new(&server, "127.0.0.1:8585", "scrape-secret").run();
+/// server.run();
+/// ```
+pub struct MetricsServer {
+    address: String,
+    state: HttpState,
 }
 
-fn metrics(req: &HttpRequest<HttpState>) -> impl Responder {
-    use std::ops::Deref;
+impl MetricsServer {
+    /// Creates a metrics server for the OPC UA server that binds to the address, e.g.
+    /// `127.0.0.1:8585`. Scrape requests must supply the scrape token as a bearer token. An empty
+    /// token matches no request.
+    pub fn new(server: &Server, address: &str, scrape_token: &str) -> MetricsServer {
+        MetricsServer {
+            address: address.to_string(),
+            state: HttpState {
+                server_state: server.server_state(),
+                connections: server.connections(),
+                server_metrics: server.server_metrics(),
+                scrape_token: scrape_token.to_string(),
+                abort_token: None,
+            },
+        }
+    }
 
-    let state = req.state();
+    /// Enables the abort endpoint. Abort requests must supply the abort token as a bearer token.
+    /// The token should differ from the scrape token so a monitoring system cannot stop the server.
+    pub fn abort_token(mut self, abort_token: &str) -> Self {
+        self.state.abort_token = Some(abort_token.to_string());
+        self
+    }
 
-    // Send metrics data as json
-    let json = {
-        // Careful with the ordering here to avoid potential deadlock. Metrics are locked
-        // several times in scope to avoid deadlocks issues.
-        {
-            let server_state = state.server_state.read();
-            let mut server_metrics = state.server_metrics.write();
-            server_metrics.update_from_server_state(&server_state);
+    /// Serves requests until the OPC UA server is aborted. The function fails if the address is
+    /// invalid or cannot be bound.
+    pub async fn serve(self) -> Result<(), StatusCode> {
+        let address: SocketAddr = self.address.parse().map_err(|_| {
+            error!("Metrics server address {} is invalid", self.address);
+            StatusCode::BadConfigurationError
+        })?;
+
+        let server_state = self.state.server_state.clone();
+        let state = Arc::new(self.state);
+        let make_service = make_service_fn(move |_| {
+            let state = state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = handle_request(&state, &request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        let server = hyper::Server::try_bind(&address)
+            .map_err(|err| {
+                error!("Metrics server cannot bind to {}, error = {}", address, err);
+                StatusCode::BadCommunicationError
+            })?
+            .serve(make_service);
+        info!(
+            "Metrics server is running on http://{}/metrics to provide OPC UA server metrics",
+            address
+        );
+
+        server
+            .with_graceful_shutdown(async move {
+                let mut timer = interval_at(Instant::now(), Duration::from_secs(1));
+                loop {
+                    timer.tick().await;
+                    if trace_read_lock!(server_state).is_abort() {
+                        info!("Metrics server will be stopped");
+                        break;
+                    }
+                }
+            })
+            .await
+            .map_err(|err| {
+                error!("Metrics server failed, error = {}", err);
+                StatusCode::BadCommunicationError
+            })
+    }
+
+    /// Runs the metrics server on a thread of its own, for servers that do not otherwise have a
+    /// tokio runtime to spawn `serve()` on.
+    pub fn run(self) -> thread::JoinHandle<Result<(), StatusCode>> {
+        thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(self.serve())
+        })
+    }
+}
+
+/// Dispatches a request to the endpoint for its path
+pub(crate) fn handle_request<T>(state: &HttpState, request: &Request<T>) -> Response<Body> {
+    match request.uri().path() {
+        "/metrics" => {
+            if request.method() != Method::GET {
+                status_response(HttpStatusCode::METHOD_NOT_ALLOWED)
+            } else if !is_authorized(request.headers(), &state.scrape_token) {
+                unauthorized_response()
+            } else {
+                metrics(state)
+            }
         }
+        "/server/abort" => match state.abort_token {
+            None => status_response(HttpStatusCode::NOT_FOUND),
+            Some(_) if request.method() != Method::POST => {
+                status_response(HttpStatusCode::METHOD_NOT_ALLOWED)
+            }
+            Some(ref abort_token) if !is_authorized(request.headers(), abort_token) => {
+                unauthorized_response()
+            }
+            Some(_) => {
+                info!("Server is being aborted by a request to the metrics server");
+                trace_write_lock!(state.server_state).abort();
+                status_response(HttpStatusCode::OK)
+            }
+        },
+        _ => status_response(HttpStatusCode::NOT_FOUND),
+    }
+}
 
-        // Take a copy of connections
-        let connections = {
-            let connections = state.connections.read();
-            connections.clone()
-        };
-        let mut server_metrics = state.server_metrics.write();
+fn metrics(state: &HttpState) -> Response<Body> {
+    // Careful with the ordering here to avoid potential deadlock. Metrics are locked
+    // several times in scope to avoid deadlocks issues.
+    {
+        let server_state = trace_read_lock!(state.server_state);
+        let mut server_metrics = trace_write_lock!(state.server_metrics);
+        server_metrics.update_from_server_state(&server_state);
+    }
+
+    // Take a copy of connections
+    let connections = {
+        let connections = trace_read_lock!(state.connections);
+        connections.clone()
+    };
+    let text = {
+        let mut server_metrics = trace_write_lock!(state.server_metrics);
         server_metrics.update_from_connections(connections);
-        serde_json::to_string_pretty(server_metrics.deref()).unwrap()
+        server_metrics.to_open_metrics()
     };
 
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(json)
+    let mut response = Response::new(Body::from(text));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(OPEN_METRICS_CONTENT_TYPE),
+    );
+    response
 }
 
-/// Runs an http server on the specified binding address, serving out the supplied server metrics
-pub fn run_http_server(
-    address: &str,
-    content_path: &str,
-    server_state: Arc<RwLock<ServerState>>,
-    connections: Arc<RwLock<Connections>>,
-    server_metrics: Arc<RwLock<ServerMetrics>>,
-) {
-    let address = String::from(address);
-    let base_path = PathBuf::from(content_path);
+/// Tests if the headers carry the token as a bearer token
+fn is_authorized(headers: &HeaderMap, token: &str) -> bool {
+    let credentials = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match credentials {
+        Some(credentials) if !token.is_empty() => constant_time_eq(credentials, token),
+        _ => false,
+    }
+}
 
-    let (tx, rx) = mpsc::channel();
+/// Compares strings in a time that does not depend on where they differ, so the comparison does
+/// not leak how much of a guessed token is correct.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
 
-    let server_state_http = server_state.clone();
-    thread::spawn(move || {
-        info!(
-            "HTTP server is running on http://{}/ to provide OPC UA server metrics",
-            address
-        );
-        let sys = actix::System::new("http-server");
-        let addr = server::new(move || {
-            App::with_state(HttpState {
-                server_state: server_state_http.clone(),
-                connections: connections.clone(),
-                server_metrics: server_metrics.clone(),
-            })
-            .resource("/server/metrics", |r| {
-                r.method(http::Method::GET).f(metrics)
-            })
-            .resource("/server/abort", |r| r.method(http::Method::GET).f(abort))
-            .handler(
-                "/",
-                fs::StaticFiles::new(base_path.clone())
-                    .unwrap()
-                    .index_file("index.html"),
-            )
-        })
-        .bind(&address)
-        .unwrap()
-        .start();
-
-        // Give the address info to the quit task
-        let _ = tx.send(addr);
-
-        // Run
-        let _ = sys.run();
-    });
-
-    // Get the address info from the http server thread
-    let addr = rx.recv().unwrap();
-
-    // Spawn tokio to monitor for quit and to shutdown the http server
-    thread::spawn(move || {
-        tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .unwrap()
-            .block_on(async move {
-                let mut timer = interval_at(Instant::now(), Duration::from_secs(1));
-                loop {
-                    {
-                        let server_state = trace_read_lock!(server_state);
-                        if server_state.is_abort() {
-                            let _ = addr.send(server::StopServer { graceful: false });
-                            info!("HTTP server will be stopped");
-                            break;
-                        }
-                    }
-                    timer.tick().await;
-                }
-            });
-    });
+fn status_response(status: HttpStatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::from(
+        status.canonical_reason().unwrap_or_default().to_string(),
+    ));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+fn unauthorized_response() -> Response<Body> {
+    let mut response = status_response(HttpStatusCode::UNAUTHORIZED);
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+#[cfg(test)]
+impl MetricsServer {
+    pub(crate) fn state(&self) -> &HttpState {
+        &self.state
+    }
 }
@@ -9,15 +9,45 @@ pub(crate) const POLICY_ID_USER_PASS_NONE: &str = "userpass_none";
 pub(crate) const POLICY_ID_USER_PASS_RSA_15: &str = "userpass_rsa_15";
 pub(crate) const POLICY_ID_USER_PASS_RSA_OAEP: &str = "userpass_rsa_oaep";
 pub(crate) const POLICY_ID_X509: &str = "x509";
+pub(crate) const POLICY_ID_ISSUED_JWT: &str = "issued_jwt";
 
 pub enum IdentityToken {
     None,
     AnonymousIdentityToken(AnonymousIdentityToken),
     UserNameIdentityToken(UserNameIdentityToken),
     X509IdentityToken(X509IdentityToken),
+    IssuedIdentityToken(IssuedIdentityToken),
     Invalid(ExtensionObject),
 }
 
+/// The user that an identity token has been authenticated as
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedUser {
+    /// The id of the matching user token in the server config
+    pub user_token_id: String,
+    /// The subject of an issued token
+    pub subject: Option<String>,
+    /// The roles granted to the user, i.e. the node ids of role objects
+    pub roles: Vec<NodeId>,
+}
+
+impl AuthenticatedUser {
+    /// Creates a user with the role implied by the user token id, i.e. `Anonymous` for the
+    /// anonymous user and `AuthenticatedUser` for any other.
+    pub fn new(user_token_id: String) -> Self {
+        let role = if user_token_id == crate::server::config::ANONYMOUS_USER_TOKEN_ID {
+            ObjectId::WellKnownRole_Anonymous
+        } else {
+            ObjectId::WellKnownRole_AuthenticatedUser
+        };
+        AuthenticatedUser {
+            user_token_id,
+            subject: None,
+            roles: vec![role.into()],
+        }
+    }
+}
+
 impl IdentityToken {
     pub fn new(o: &ExtensionObject, decoding_options: &DecodingOptions) -> Self {
         if o.is_empty() {
@@ -50,6 +80,13 @@ impl IdentityToken {
                         IdentityToken::Invalid(o.clone())
                     }
                 }
+                ObjectId::IssuedIdentityToken_Encoding_DefaultBinary => {
+                    if let Ok(token) = o.decode_inner::<IssuedIdentityToken>(decoding_options) {
+                        IdentityToken::IssuedIdentityToken(token)
+                    } else {
+                        IdentityToken::Invalid(o.clone())
+                    }
+                }
                 _ => IdentityToken::Invalid(o.clone()),
             }
         } else {
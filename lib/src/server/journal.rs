@@ -0,0 +1,322 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides a journal that persists changes made to the address space through the node management
+//! services, and writes to nodes marked as persistent, so they survive a restart of the server.
+//!
+//! The journal is an append-only file of binary encoded records. Each record is a single byte tag
+//! followed by the OPC UA binary encoding of the request item that produced the change. When the
+//! server starts, the records are replayed in order against the address space.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::sync::*;
+use crate::types::{status_code::StatusCode, *};
+
+use crate::server::{
+    address_space::{types::*, AddressSpace},
+    services::node_management::NodeManagementService,
+};
+
+const TAG_ADD_NODE: u8 = 1;
+const TAG_ADD_REFERENCE: u8 = 2;
+const TAG_DELETE_NODE: u8 = 3;
+const TAG_DELETE_REFERENCE: u8 = 4;
+const TAG_WRITE: u8 = 5;
+
+/// A change to the address space that is recorded in the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JournalEntry {
+    /// A node was added. The requested node id is always the node id that was assigned to the node.
+    AddNode(AddNodesItem),
+    /// A reference was added
+    AddReference(AddReferencesItem),
+    /// A node was deleted
+    DeleteNode(DeleteNodesItem),
+    /// A reference was deleted
+    DeleteReference(DeleteReferencesItem),
+    /// An attribute of a persistent node was written
+    Write(WriteValue),
+}
+
+impl JournalEntry {
+    fn encode<S: Write>(&self, stream: &mut S) -> EncodingResult<usize> {
+        match self {
+            JournalEntry::AddNode(item) => {
+                Ok(write_u8(stream, TAG_ADD_NODE)? + item.encode(stream)?)
+            }
+            JournalEntry::AddReference(item) => {
+                Ok(write_u8(stream, TAG_ADD_REFERENCE)? + item.encode(stream)?)
+            }
+            JournalEntry::DeleteNode(item) => {
+                Ok(write_u8(stream, TAG_DELETE_NODE)? + item.encode(stream)?)
+            }
+            JournalEntry::DeleteReference(item) => {
+                Ok(write_u8(stream, TAG_DELETE_REFERENCE)? + item.encode(stream)?)
+            }
+            JournalEntry::Write(item) => Ok(write_u8(stream, TAG_WRITE)? + item.encode(stream)?),
+        }
+    }
+
+    fn decode<S: std::io::Read>(
+        stream: &mut S,
+        decoding_options: &DecodingOptions,
+    ) -> EncodingResult<Self> {
+        let entry = match read_u8(stream)? {
+            TAG_ADD_NODE => JournalEntry::AddNode(AddNodesItem::decode(stream, decoding_options)?),
+            TAG_ADD_REFERENCE => {
+                JournalEntry::AddReference(AddReferencesItem::decode(stream, decoding_options)?)
+            }
+            TAG_DELETE_NODE => {
+                JournalEntry::DeleteNode(DeleteNodesItem::decode(stream, decoding_options)?)
+            }
+            TAG_DELETE_REFERENCE => JournalEntry::DeleteReference(DeleteReferencesItem::decode(
+                stream,
+                decoding_options,
+            )?),
+            TAG_WRITE => JournalEntry::Write(WriteValue::decode(stream, decoding_options)?),
+            tag => {
+                error!("Journal contains an unrecognized record tag {}", tag);
+                return Err(StatusCode::BadDecodingError);
+            }
+        };
+        Ok(entry)
+    }
+
+    /// Applies the entry to the address space. Access checks are not performed because the entry
+    /// was already permitted when it was first recorded.
+    fn apply(
+        &self,
+        address_space: &mut AddressSpace,
+        decoding_options: &DecodingOptions,
+    ) -> StatusCode {
+        match self {
+            JournalEntry::AddNode(item) => {
+                NodeManagementService::apply_add_node(address_space, item, decoding_options).0
+            }
+            JournalEntry::AddReference(item) => {
+                NodeManagementService::apply_add_reference(address_space, item)
+            }
+            JournalEntry::DeleteNode(item) => {
+                NodeManagementService::apply_delete_node(address_space, item)
+            }
+            JournalEntry::DeleteReference(item) => {
+                NodeManagementService::apply_delete_reference(address_space, item)
+            }
+            JournalEntry::Write(item) => Self::apply_write(address_space, item),
+        }
+    }
+
+    fn apply_write(address_space: &mut AddressSpace, item: &WriteValue) -> StatusCode {
+        let attribute_id = match AttributeId::from_u32(item.attribute_id) {
+            Ok(attribute_id) => attribute_id,
+            Err(_) => return StatusCode::BadAttributeIdInvalid,
+        };
+        let index_range = match item.index_range.as_ref().parse::<NumericRange>() {
+            Ok(index_range) => index_range,
+            Err(_) => return StatusCode::BadIndexRangeInvalid,
+        };
+        let value = match item.value.value {
+            Some(ref value) => value.clone(),
+            None => return StatusCode::BadTypeMismatch,
+        };
+        let result = match address_space.find_node_mut(&item.node_id) {
+            Some(NodeType::Variable(ref mut variable)) if attribute_id == AttributeId::Value => {
+                variable.set_value(index_range, value)
+            }
+            Some(node) => node.as_mut_node().set_attribute(attribute_id, value),
+            None => Err(StatusCode::BadNodeIdUnknown),
+        };
+        result.err().unwrap_or(StatusCode::Good)
+    }
+}
+
+/// The journal records changes to the address space into a file so they can be replayed when the
+/// server is restarted. A journal is enabled by setting a `journal_path` in the [`ServerConfig`].
+///
+/// [`ServerConfig`]: ../config/struct.ServerConfig.html
+pub struct Journal {
+    /// Path to the journal file
+    path: PathBuf,
+    /// The file, opened for append when the first entry is recorded
+    file: Mutex<Option<Arc<File>>>,
+    /// Set when entries have been written to the file since it was last synced
+    unsynced: AtomicBool,
+}
+
+impl Journal {
+    pub fn new<T>(path: T) -> Journal
+    where
+        T: Into<PathBuf>,
+    {
+        Journal {
+            path: path.into(),
+            file: Mutex::new(None),
+            unsynced: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the path to the journal file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends an entry to the journal. Failures are logged but otherwise do not affect the
+    /// service that made the change. The entry is written while the caller holds the address
+    /// space lock so entries are in the order of the changes, but it is only durable after a call
+    /// to [`Journal::sync`], which the caller makes once it has released the lock.
+    pub(crate) fn record(&self, entry: JournalEntry) {
+        let mut buffer = Vec::new();
+        if let Err(err) = entry.encode(&mut buffer) {
+            error!("Cannot encode journal entry {:?}, error = {}", entry, err);
+            return;
+        }
+        let mut file = trace_lock!(self.file);
+        if file.is_none() {
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+            {
+                Ok(f) => *file = Some(Arc::new(f)),
+                Err(err) => {
+                    error!(
+                        "Cannot open journal file {}, error = {}",
+                        self.path.display(),
+                        err
+                    );
+                    return;
+                }
+            }
+        }
+        let mut f = file.as_ref().unwrap().as_ref();
+        if let Err(err) = f.write_all(&buffer) {
+            error!(
+                "Cannot write to journal file {}, error = {}",
+                self.path.display(),
+                err
+            );
+        } else {
+            self.unsynced.store(true, Ordering::Release);
+        }
+    }
+
+    /// Flushes the entries recorded since the last sync to disk. The journal lock is not held
+    /// while the file syncs, so other entries may be recorded meanwhile.
+    pub(crate) fn sync(&self) {
+        if !self.unsynced.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let file = trace_lock!(self.file).clone();
+        if let Some(file) = file {
+            if let Err(err) = file.sync_data() {
+                error!(
+                    "Cannot sync journal file {}, error = {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+
+    /// Reads every entry from the journal file. A missing file is treated as an empty journal. A
+    /// damaged record, e.g. one truncated by a crash during a write, ends the journal. The file
+    /// is truncated to remove it so that the entries recorded from now on can be read back.
+    pub(crate) fn read_entries(
+        &self,
+        decoding_options: &DecodingOptions,
+    ) -> Result<Vec<JournalEntry>, StatusCode> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                error!(
+                    "Cannot read journal file {}, error = {}",
+                    self.path.display(),
+                    err
+                );
+                return Err(StatusCode::BadResourceUnavailable);
+            }
+        };
+        let mut stream = Cursor::new(&data[..]);
+        let mut entries = Vec::new();
+        while (stream.position() as usize) < data.len() {
+            let offset = stream.position();
+            match JournalEntry::decode(&mut stream, decoding_options) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    warn!(
+                        "Journal file {} has a damaged record after {} entries, error = {}. The journal is truncated to {} bytes to remove it.",
+                        self.path.display(),
+                        entries.len(),
+                        err,
+                        offset
+                    );
+                    self.truncate(offset)?;
+                    break;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    fn truncate(&self, len: u64) -> Result<(), StatusCode> {
+        OpenOptions::new()
+            .write(true)
+            .open(&self.path)
+            .and_then(|file| {
+                file.set_len(len)?;
+                file.sync_all()
+            })
+            .map_err(|err| {
+                error!(
+                    "Cannot truncate journal file {}, error = {}",
+                    self.path.display(),
+                    err
+                );
+                StatusCode::BadResourceUnavailable
+            })
+    }
+
+    /// Replays the journal against the address space and returns the number of entries that were
+    /// applied successfully.
+    pub fn replay(
+        &self,
+        address_space: &mut AddressSpace,
+        decoding_options: &DecodingOptions,
+    ) -> Result<usize, StatusCode> {
+        let entries = self.read_entries(decoding_options)?;
+        let total = entries.len();
+        let applied = entries
+            .iter()
+            .filter(|entry| {
+                let status_code = entry.apply(address_space, decoding_options);
+                if status_code.is_bad() {
+                    warn!(
+                        "Journal entry {:?} could not be replayed, status = {}",
+                        entry, status_code
+                    );
+                    false
+                } else {
+                    true
+                }
+            })
+            .count();
+        info!(
+            "Replayed {} of {} entries from journal {}",
+            applied,
+            total,
+            self.path.display()
+        );
+        Ok(applied)
+    }
+}
@@ -5,6 +5,11 @@
 //! Provides debug metric of server state that can be used by anything that wants
 //! to see what is happening in the server. State is updated by the server as sessions are added, removed,
 //! and when subscriptions / monitored items are added, removed.
+//!
+//! The metrics can be rendered in the OpenMetrics text format for scraping by a monitoring
+//! system such as Prometheus.
+
+use std::fmt::{Display, Write};
 
 use crate::runtime_components;
 use crate::types::DateTime;
@@ -40,6 +45,11 @@ pub struct Connection {
     // state
     pub client_address: String,
     pub transport_state: String,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub chunks_received: u64,
+    pub chunks_sent: u64,
+    pub secure_channel_renewals: u64,
 }
 
 #[derive(Serialize)]
@@ -48,9 +58,14 @@ pub struct Session {
     pub session_activated: bool,
     pub session_terminated: bool,
     pub session_terminated_at: String,
+    pub monitored_item_count: usize,
     pub subscriptions: subscriptions::Metrics,
 }
 
+/// The content type of the text produced by `ServerMetrics::to_open_metrics()`
+pub const OPEN_METRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
 impl Default for ServerMetrics {
     fn default() -> Self {
         // Sample metrics
@@ -90,6 +105,7 @@ impl ServerMetrics {
                 user: String::from("User identity tokens have been removed"),
                 pass: None,
                 x509: None,
+                jwt: None,
                 thumbprint: None,
             },
         );
@@ -122,7 +138,7 @@ impl ServerMetrics {
             .iter()
             .map(|c| {
                 // Carefully extract info while minimizing chance of deadlock
-                let (client_address, transport_state, session_manager) = {
+                let (client_address, transport_state, session_manager, stats) = {
                     let connection = trace_read_lock!(c);
                     let client_address =
                         if let Some(ref client_address) = connection.client_address() {
@@ -142,6 +158,7 @@ impl ServerMetrics {
                         client_address,
                         transport_state,
                         connection.session_manager(),
+                        connection.stats(),
                     )
                 };
                 let session_manager = trace_read_lock!(session_manager);
@@ -158,12 +175,14 @@ impl ServerMetrics {
                         } else {
                             String::new()
                         };
+                        let monitored_item_count = session.subscriptions().monitored_item_count();
                         let subscriptions = session.subscriptions().metrics();
                         Session {
                             id,
                             session_activated,
                             session_terminated,
                             session_terminated_at,
+                            monitored_item_count,
                             subscriptions,
                         }
                     })
@@ -173,9 +192,256 @@ impl ServerMetrics {
                 Connection {
                     client_address,
                     transport_state,
+                    bytes_received: stats.bytes_received(),
+                    bytes_sent: stats.bytes_sent(),
+                    chunks_received: stats.chunks_received(),
+                    chunks_sent: stats.chunks_sent(),
+                    secure_channel_renewals: stats.secure_channel_renewals(),
                     sessions,
                 }
             })
             .collect();
     }
+
+    /// Renders the metrics in the OpenMetrics text format. The content type of the text is
+    /// `OPEN_METRICS_CONTENT_TYPE`.
+    pub fn to_open_metrics(&self) -> String {
+        let mut w = OpenMetricsWriter::default();
+
+        w.family(
+            "opcua_server_uptime_seconds",
+            "gauge",
+            "Time since the server started",
+        );
+        w.sample(
+            "opcua_server_uptime_seconds",
+            &[],
+            self.server.uptime_ms as f64 / 1000.0,
+        );
+
+        let summary = self.diagnostics.server_diagnostics_summary();
+        let gauges = [
+            (
+                "opcua_server_sessions",
+                "Sessions currently established",
+                summary.current_session_count,
+            ),
+            (
+                "opcua_server_subscriptions",
+                "Subscriptions currently established",
+                summary.current_subscription_count,
+            ),
+            (
+                "opcua_server_publishing_intervals",
+                "Distinct publishing intervals in use",
+                summary.publishing_interval_count,
+            ),
+            (
+                "opcua_server_views",
+                "Views currently in use",
+                summary.server_view_count,
+            ),
+        ];
+        for (name, help, value) in gauges.iter() {
+            w.family(name, "gauge", help);
+            w.sample(name, &[], value);
+        }
+        let counters = [
+            (
+                "opcua_server_sessions_cumulated",
+                "Sessions established since the server started",
+                summary.cumulated_session_count,
+            ),
+            (
+                "opcua_server_sessions_rejected",
+                "Session requests that were rejected",
+                summary.rejected_session_count,
+            ),
+            (
+                "opcua_server_sessions_security_rejected",
+                "Session requests rejected for security reasons",
+                summary.security_rejected_session_count,
+            ),
+            (
+                "opcua_server_sessions_timed_out",
+                "Sessions closed because they timed out",
+                summary.session_timeout_count,
+            ),
+            (
+                "opcua_server_sessions_aborted",
+                "Sessions closed because of an error",
+                summary.session_abort_count,
+            ),
+            (
+                "opcua_server_subscriptions_cumulated",
+                "Subscriptions created since the server started",
+                summary.cumulated_subscription_count,
+            ),
+            (
+                "opcua_server_requests_rejected",
+                "Requests that were rejected",
+                summary.rejected_requests_count,
+            ),
+            (
+                "opcua_server_requests_security_rejected",
+                "Requests rejected for security reasons",
+                summary.security_rejected_requests_count,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            w.family(name, "counter", help);
+            w.sample(&format!("{}_total", name), &[], value);
+        }
+
+        // Per connection counters
+        type ConnectionValue = fn(&Connection) -> u64;
+        let connection_counters: [(&str, &str, ConnectionValue); 5] = [
+            (
+                "opcua_connection_received_bytes",
+                "Bytes received in message chunks",
+                |c| c.bytes_received,
+            ),
+            ("opcua_connection_sent_bytes", "Bytes sent", |c| {
+                c.bytes_sent
+            }),
+            (
+                "opcua_connection_received_chunks",
+                "Message chunks received",
+                |c| c.chunks_received,
+            ),
+            ("opcua_connection_sent_chunks", "Message chunks sent", |c| {
+                c.chunks_sent
+            }),
+            (
+                "opcua_connection_secure_channel_renewals",
+                "Renewals of the secure channel token",
+                |c| c.secure_channel_renewals,
+            ),
+        ];
+        for (name, help, value) in connection_counters.iter() {
+            w.family(name, "counter", help);
+            for c in &self.connections {
+                w.sample(
+                    &format!("{}_total", name),
+                    &[("client_address", &c.client_address)],
+                    value(c),
+                );
+            }
+        }
+
+        // Per session gauges
+        type SessionValue = fn(&Session) -> usize;
+        let session_gauges: [(&str, &str, SessionValue); 4] = [
+            (
+                "opcua_session_publish_request_queue_depth",
+                "Publish requests waiting for notifications",
+                |s| s.subscriptions.publish_request_queue_len,
+            ),
+            (
+                "opcua_session_retransmission_queue_depth",
+                "Notifications waiting to be acknowledged",
+                |s| s.subscriptions.retransmission_queue_len,
+            ),
+            (
+                "opcua_session_subscriptions",
+                "Subscriptions of the session",
+                |s| s.subscriptions.subscriptions.len(),
+            ),
+            (
+                "opcua_session_monitored_items",
+                "Monitored items of the session",
+                |s| s.monitored_item_count,
+            ),
+        ];
+        for (name, help, value) in session_gauges.iter() {
+            w.family(name, "gauge", help);
+            for c in &self.connections {
+                for s in &c.sessions {
+                    let labels = [
+                        ("client_address", c.client_address.as_str()),
+                        ("session_id", s.id.as_str()),
+                    ];
+                    w.sample(name, &labels, value(s));
+                }
+            }
+        }
+
+        // Latency of the services
+        let name = "opcua_service_request_duration_seconds";
+        w.family(name, "histogram", "Time taken to respond to requests");
+        for (service, histogram) in self.diagnostics.service_latencies() {
+            for (le, count) in histogram.cumulative_buckets() {
+                // Bucket bounds are written with a decimal point, e.g. "1.0"
+                let le = format!("{:?}", le);
+                w.sample(
+                    &format!("{}_bucket", name),
+                    &[("service", &service), ("le", &le)],
+                    count,
+                );
+            }
+            w.sample(
+                &format!("{}_bucket", name),
+                &[("service", &service), ("le", "+Inf")],
+                histogram.count(),
+            );
+            w.sample(
+                &format!("{}_count", name),
+                &[("service", &service)],
+                histogram.count(),
+            );
+            w.sample(
+                &format!("{}_sum", name),
+                &[("service", &service)],
+                histogram.sum(),
+            );
+        }
+
+        w.finish()
+    }
+}
+
+/// Helper for writing metric families and samples in the OpenMetrics text format
+#[derive(Default)]
+struct OpenMetricsWriter {
+    text: String,
+}
+
+impl OpenMetricsWriter {
+    fn family(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.text, "# TYPE {} {}", name, metric_type);
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+    }
+
+    fn sample<T: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: T) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            self.text.push('{');
+            for (i, (label, label_value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.text.push(',');
+                }
+                let _ = write!(
+                    self.text,
+                    "{}=\"{}\"",
+                    label,
+                    escape_label_value(label_value)
+                );
+            }
+            self.text.push('}');
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    fn finish(mut self) -> String {
+        self.text.push_str("# EOF\n");
+        self.text
+    }
+}
+
+/// Escapes a label value, where backslash, double quote and line feed must be escaped
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
 }
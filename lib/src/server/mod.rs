@@ -57,7 +57,10 @@ pub mod diagnostics;
 #[macro_use]
 pub mod events;
 pub mod historical;
+pub mod journal;
 pub mod metrics;
+pub mod node_manager;
+pub mod redundancy;
 pub mod server;
 pub mod session;
 pub mod state;
@@ -77,6 +80,8 @@ pub mod prelude {
         config::*,
         events::event::*,
         historical::*,
+        node_manager::*,
+        redundancy::*,
         server::*,
         subscriptions::*,
         util::*,
@@ -123,6 +128,8 @@ pub mod constants {
     pub const DEFAULT_KEEP_ALIVE_COUNT: u32 = 10;
     /// Maximum keep alive count
     pub const MAX_KEEP_ALIVE_COUNT: u32 = 30000;
+    /// Default time in millis allowed for an async getter, setter or method to complete
+    pub const DEFAULT_ASYNC_OPERATION_TIMEOUT_MS: u32 = 10000;
     /// Maximum browse continuation points
     pub const MAX_BROWSE_CONTINUATION_POINTS: usize = 20;
     /// Maximum history continuation points
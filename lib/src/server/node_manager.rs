@@ -0,0 +1,423 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Node managers serve the nodes of a namespace on behalf of the address space.
+//!
+//! Every node of the built-in [`AddressSpace`] is held in memory behind a single lock. A server
+//! that exposes a very large or changing set of nodes, e.g. the tags of a historian or the points
+//! of a device, can instead register a [`NodeManager`] for a namespace with
+//! [`AddressSpace::register_node_manager`]. Every Browse, Read, Write, Call and
+//! TranslateBrowsePathsToNodeIds operation on a node in that namespace, and every monitored item
+//! of such a node, is delegated to the node manager, which may produce the nodes on demand from a
+//! database or a device driver.
+//!
+//! The node manager of a node is chosen by the namespace index of its node id. To make the nodes
+//! of a node manager visible to a client that browses from the root, add a node with the node id
+//! of the root of the node manager to the address space with a reference from a built-in node,
+//! e.g. the Objects folder. Browsing that node is delegated to the node manager.
+//!
+//! The built-in address space is a node manager too, so an `Arc<RwLock<AddressSpace>>` holding
+//! the nodes of a namespace may be registered for it. Do not register the server's own address
+//! space, which would deadlock.
+//!
+//! [`AddressSpace`]: ../address_space/address_space/struct.AddressSpace.html
+//! [`AddressSpace::register_node_manager`]: ../address_space/address_space/struct.AddressSpace.html#method.register_node_manager
+//! [`NodeManager`]: trait.NodeManager.html
+
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::sync::*;
+use crate::types::{
+    service_types::{
+        BrowseDescription, BrowseDirection, CallMethodRequest, CallMethodResult, ReadValueId,
+        ReferenceDescription, RelativePath, TimestampsToReturn, WriteValue,
+    },
+    status_code::StatusCode,
+    AttributeId, BrowseDescriptionResultMask, DataValue, DiagnosticError, NodeId, NumericRange,
+    QualifiedName,
+};
+
+use crate::server::{
+    address_space::{relative_path, AddressSpace},
+    services::{
+        attribute::{status_data_value, AttributeService},
+        view::ViewService,
+    },
+    session::{Session, SessionManager},
+};
+
+/// The session that makes a request of a node manager
+#[derive(Clone)]
+pub struct RequestContext {
+    session_id: NodeId,
+    session_manager: Arc<RwLock<SessionManager>>,
+}
+
+impl RequestContext {
+    pub(crate) fn new(
+        session_id: NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+    ) -> RequestContext {
+        RequestContext {
+            session_id,
+            session_manager,
+        }
+    }
+
+    /// Makes the context of a request from the session that makes it
+    pub(crate) fn for_session(
+        session: &Arc<RwLock<Session>>,
+        session_manager: Arc<RwLock<SessionManager>>,
+    ) -> RequestContext {
+        let session_id = {
+            let session = trace_read_lock!(session);
+            session.session_id().clone()
+        };
+        RequestContext::new(session_id, session_manager)
+    }
+
+    /// The id of the session that makes the request
+    pub fn session_id(&self) -> &NodeId {
+        &self.session_id
+    }
+
+    /// The session manager of the server
+    pub fn session_manager(&self) -> Arc<RwLock<SessionManager>> {
+        self.session_manager.clone()
+    }
+
+    /// Finds the session that makes the request. The session is gone if it was closed while the
+    /// request was in progress.
+    pub fn session(&self) -> Option<Arc<RwLock<Session>>> {
+        let session_manager = trace_read_lock!(self.session_manager);
+        session_manager.find_session_by_id(&self.session_id)
+    }
+}
+
+/// A node manager serves the nodes of a namespace. The services await a node manager without
+/// holding the `AddressSpace` lock, so an implementation may fetch nodes and values from a
+/// database or a device.
+///
+/// Node managers are only asked about nodes in the namespaces they are registered for, but a
+/// reference returned by `browse` may have a target in any namespace.
+#[async_trait]
+pub trait NodeManager: Send + Sync {
+    /// Returns the references of the node that match the browse description. The references are
+    /// paged into continuation points by the caller. Fails with `BadNodeIdUnknown` if the node
+    /// does not exist.
+    async fn browse(
+        &self,
+        context: &RequestContext,
+        node_to_browse: &BrowseDescription,
+    ) -> Result<Vec<ReferenceDescription>, StatusCode>;
+
+    /// Returns a value for every read, in the same order. A read that fails has the status code
+    /// of the failure and no value. Timestamps the client did not ask for are removed by the
+    /// caller.
+    async fn read(
+        &self,
+        context: &RequestContext,
+        nodes_to_read: &[ReadValueId],
+        max_age: f64,
+        timestamps_to_return: TimestampsToReturn,
+    ) -> Vec<DataValue>;
+
+    /// Returns a result for every write, in the same order. The default implementation fails
+    /// every write with `BadNotWritable`.
+    async fn write(
+        &self,
+        _context: &RequestContext,
+        nodes_to_write: &[WriteValue],
+    ) -> Vec<Result<(), DiagnosticError>> {
+        vec![Err(StatusCode::BadNotWritable.into()); nodes_to_write.len()]
+    }
+
+    /// Calls the method of the request. The node manager of the object of the request is called.
+    /// The default implementation fails with `BadMethodInvalid`.
+    async fn call(
+        &self,
+        _context: &RequestContext,
+        _request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        Err(StatusCode::BadMethodInvalid.into())
+    }
+
+    /// Returns the nodes that the relative path leads to from the starting node. The default
+    /// implementation follows each element of the path with `browse`.
+    async fn translate_browse_path(
+        &self,
+        context: &RequestContext,
+        starting_node: &NodeId,
+        relative_path: &RelativePath,
+    ) -> Result<Vec<NodeId>, StatusCode> {
+        let elements = match relative_path.elements {
+            Some(ref elements) if !elements.is_empty() => elements,
+            _ => return Err(StatusCode::BadNothingToDo),
+        };
+        let mut matching_nodes = vec![starting_node.clone()];
+        for element in elements {
+            if element.target_name.is_null() {
+                return Err(StatusCode::BadBrowseNameInvalid);
+            }
+            let mut next_matching_nodes = Vec::new();
+            for node_id in &matching_nodes {
+                let node_to_browse = BrowseDescription {
+                    node_id: node_id.clone(),
+                    browse_direction: if element.is_inverse {
+                        BrowseDirection::Inverse
+                    } else {
+                        BrowseDirection::Forward
+                    },
+                    reference_type_id: element.reference_type_id.clone(),
+                    include_subtypes: element.include_subtypes,
+                    node_class_mask: 0,
+                    result_mask: BrowseDescriptionResultMask::RESULT_MASK_BROWSE_NAME.bits(),
+                };
+                match self.browse(context, &node_to_browse).await {
+                    Ok(references) => next_matching_nodes.extend(
+                        references
+                            .into_iter()
+                            .filter(|r| r.browse_name == element.target_name)
+                            .map(|r| r.node_id.node_id),
+                    ),
+                    Err(StatusCode::BadNodeIdUnknown) if node_id == starting_node => {
+                        return Err(StatusCode::BadNodeIdUnknown)
+                    }
+                    Err(_) => {}
+                }
+            }
+            if next_matching_nodes.is_empty() {
+                return Err(StatusCode::BadNoMatch);
+            }
+            matching_nodes = next_matching_nodes;
+        }
+        Ok(matching_nodes)
+    }
+
+    /// Called when a client creates a monitored item of a node. An error fails the creation of
+    /// the item with its status code. The default implementation accepts every item.
+    fn register_monitored_item(
+        &self,
+        _item_to_monitor: &ReadValueId,
+        _sampling_interval: f64,
+    ) -> Result<(), StatusCode> {
+        Ok(())
+    }
+
+    /// Called when a monitored item of a node is deleted, including when its subscription or
+    /// session is deleted.
+    fn unregister_monitored_item(&self, _item_to_monitor: &ReadValueId) {}
+
+    /// Returns the latest value of a monitored item, or none if the value is not known. This is
+    /// called on every sampling interval of the item while subscriptions are locked, so it must
+    /// not block. An implementation would typically return a value that it caches from the
+    /// updates of its data source for the items that are registered.
+    fn sample(&self, _item_to_monitor: &ReadValueId) -> Option<DataValue> {
+        None
+    }
+
+    /// The time allowed for a call to an async function. Operations that take longer fail with
+    /// `BadTimeout`. The default of `None` uses the server's `async_operation_timeout_ms` limit.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An address space serves its nodes as a node manager
+#[async_trait]
+impl NodeManager for RwLock<AddressSpace> {
+    async fn browse(
+        &self,
+        _context: &RequestContext,
+        node_to_browse: &BrowseDescription,
+    ) -> Result<Vec<ReferenceDescription>, StatusCode> {
+        let address_space = trace_read_lock!(self);
+        if !address_space.node_exists(&node_to_browse.node_id) {
+            Err(StatusCode::BadNodeIdUnknown)
+        } else {
+            Ok(ViewService::reference_descriptions(
+                &address_space,
+                node_to_browse,
+            ))
+        }
+    }
+
+    async fn read(
+        &self,
+        context: &RequestContext,
+        nodes_to_read: &[ReadValueId],
+        max_age: f64,
+        timestamps_to_return: TimestampsToReturn,
+    ) -> Vec<DataValue> {
+        if let Some(session) = context.session() {
+            let session = trace_read_lock!(session);
+            let address_space = trace_read_lock!(self);
+            nodes_to_read
+                .iter()
+                .map(|node_to_read| {
+                    AttributeService::read_node_value(
+                        &session,
+                        &address_space,
+                        node_to_read,
+                        max_age,
+                        timestamps_to_return,
+                    )
+                })
+                .collect()
+        } else {
+            vec![status_data_value(StatusCode::BadSessionIdInvalid); nodes_to_read.len()]
+        }
+    }
+
+    async fn write(
+        &self,
+        context: &RequestContext,
+        nodes_to_write: &[WriteValue],
+    ) -> Vec<Result<(), DiagnosticError>> {
+        if let Some(session) = context.session() {
+            let session = trace_read_lock!(session);
+            let mut address_space = trace_write_lock!(self);
+            nodes_to_write
+                .iter()
+                .map(|node_to_write| {
+                    AttributeService::write_node_value(
+                        &session,
+                        &mut address_space,
+                        node_to_write,
+                        || {},
+                    )
+                })
+                .collect()
+        } else {
+            vec![Err(StatusCode::BadSessionIdInvalid.into()); nodes_to_write.len()]
+        }
+    }
+
+    async fn call(
+        &self,
+        context: &RequestContext,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        // The arguments are validated like those of a call to the address space itself
+        let (async_callback, callback, request) = {
+            let address_space = trace_read_lock!(self);
+            let async_callback = address_space.async_method_callback(request);
+            let callback = if async_callback.is_none() {
+                Some(address_space.method_callback(request)?)
+            } else {
+                None
+            };
+            let request = match address_space.validate_input_arguments(request) {
+                Ok(request) => request,
+                Err(result) => return result,
+            };
+            (async_callback, callback, request)
+        };
+        let result = if let Some(callback) = async_callback {
+            callback
+                .call(context.session_id(), context.session_manager(), &request)
+                .await?
+        } else if let Some(callback) = callback {
+            let mut callback = trace_lock!(callback);
+            callback.call_with_diagnostics(
+                context.session_id(),
+                context.session_manager(),
+                &request,
+            )?
+        } else {
+            unreachable!()
+        };
+        let address_space = trace_read_lock!(self);
+        address_space.validate_output_arguments(&request, result)
+    }
+
+    async fn translate_browse_path(
+        &self,
+        _context: &RequestContext,
+        starting_node: &NodeId,
+        relative_path: &RelativePath,
+    ) -> Result<Vec<NodeId>, StatusCode> {
+        let address_space = trace_read_lock!(self);
+        relative_path::find_nodes_relative_path(&address_space, starting_node, relative_path)
+    }
+
+    fn sample(&self, item_to_monitor: &ReadValueId) -> Option<DataValue> {
+        let attribute_id = AttributeId::from_u32(item_to_monitor.attribute_id).ok()?;
+        let address_space = trace_read_lock!(self);
+        address_space
+            .find_node(&item_to_monitor.node_id)?
+            .as_node()
+            .get_attribute(
+                TimestampsToReturn::Both,
+                attribute_id,
+                NumericRange::None,
+                &QualifiedName::null(),
+            )
+    }
+}
+
+/// A node manager held by a monitored item. Node managers are equal if they are the same one.
+#[derive(Clone)]
+pub(crate) struct NodeManagerRef(pub Arc<dyn NodeManager>);
+
+impl fmt::Debug for NodeManagerRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("NodeManager")
+    }
+}
+
+impl PartialEq for NodeManagerRef {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            Arc::as_ptr(&self.0) as *const u8,
+            Arc::as_ptr(&other.0) as *const u8,
+        )
+    }
+}
+
+/// The operations of a request on nodes that belong to node managers. Operations are grouped by
+/// their node manager so each node manager is called once with all of its operations.
+pub(crate) struct ManagedOperations<T> {
+    batches: Vec<ManagedBatch<T>>,
+}
+
+/// A node manager and its operations, each with the index of its result
+type ManagedBatch<T> = (Arc<dyn NodeManager>, Vec<(usize, T)>);
+
+impl<T> ManagedOperations<T> {
+    pub fn new() -> ManagedOperations<T> {
+        ManagedOperations {
+            batches: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Adds the operation of the result at the index
+    pub fn add(&mut self, node_manager: Arc<dyn NodeManager>, idx: usize, operation: T) {
+        let batch = self.batches.iter_mut().find(|(m, _)| {
+            std::ptr::eq(
+                Arc::as_ptr(m) as *const u8,
+                Arc::as_ptr(&node_manager) as *const u8,
+            )
+        });
+        if let Some((_, operations)) = batch {
+            operations.push((idx, operation));
+        } else {
+            self.batches.push((node_manager, vec![(idx, operation)]));
+        }
+    }
+
+    /// Returns each node manager with its operations, and the indices of their results
+    pub fn into_batches(self) -> impl Iterator<Item = (Arc<dyn NodeManager>, Vec<usize>, Vec<T>)> {
+        self.batches.into_iter().map(|(node_manager, operations)| {
+            let (indices, operations) = operations.into_iter().unzip();
+            (node_manager, indices, operations)
+        })
+    }
+}
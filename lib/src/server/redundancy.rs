@@ -0,0 +1,287 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Provides support for non-transparent redundancy, where a client fails over between the
+//! servers of a redundant set as described in OPC UA Part 4 6.6.2. Each server publishes the
+//! URIs of the set in `Server/ServerRedundancy` and its health in `Server/ServiceLevel`, which a
+//! health function may drive.
+//!
+//! A server may mirror its subscriptions to a peer with a [`SubscriptionMirror`]. The peer keeps
+//! them in its [`MirroredSubscriptions`] until a client that failed over transfers them to its
+//! session on the peer with the TransferSubscriptions service, so a hot and mirrored pair can be
+//! built from two servers. Triggering links and queued notifications are not mirrored.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    sync::Arc,
+};
+
+use crate::sync::*;
+use crate::types::{service_types::MonitoredItemCreateRequest, *};
+
+use crate::server::state::ServerState;
+
+/// A function that returns the service level of the server, 0-255 worst to best. OPC UA Part 4
+/// 6.6.2.4.2 divides service levels into subranges, i.e. 0 is maintenance, 1 is no data, 2-199
+/// is degraded and 200-255 is healthy.
+pub type HealthFunction = Box<dyn Fn(&ServerState) -> u8 + Send + Sync>;
+
+/// A monitored item of a mirrored subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirroredMonitoredItem {
+    /// The id of the monitored item, which it keeps when it is transferred
+    pub monitored_item_id: u32,
+    /// The timestamps to return with values of the monitored item
+    pub timestamps_to_return: TimestampsToReturn,
+    /// The monitored item as it was requested, with the parameters and monitoring mode of the
+    /// most recent changes
+    pub request: MonitoredItemCreateRequest,
+}
+
+impl BinaryEncoder<MirroredMonitoredItem> for MirroredMonitoredItem {
+    fn byte_len(&self) -> usize {
+        self.monitored_item_id.byte_len()
+            + self.timestamps_to_return.byte_len()
+            + self.request.byte_len()
+    }
+
+    fn encode<S: Write>(&self, stream: &mut S) -> EncodingResult<usize> {
+        let mut size = self.monitored_item_id.encode(stream)?;
+        size += self.timestamps_to_return.encode(stream)?;
+        size += self.request.encode(stream)?;
+        Ok(size)
+    }
+
+    fn decode<S: Read>(stream: &mut S, decoding_options: &DecodingOptions) -> EncodingResult<Self> {
+        Ok(MirroredMonitoredItem {
+            monitored_item_id: u32::decode(stream, decoding_options)?,
+            timestamps_to_return: TimestampsToReturn::decode(stream, decoding_options)?,
+            request: MonitoredItemCreateRequest::decode(stream, decoding_options)?,
+        })
+    }
+}
+
+/// A subscription as it is mirrored to a peer. The subscription is binary encodable so that it
+/// can be sent to a peer in another process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirroredSubscription {
+    /// The id of the subscription, which it keeps when it is transferred
+    pub subscription_id: u32,
+    /// The user of the session that created the subscription. Only a session of the same user
+    /// may transfer it.
+    pub client_user_id: UAString,
+    /// The revised publishing interval in milliseconds
+    pub publishing_interval: f64,
+    /// The revised lifetime count
+    pub lifetime_count: u32,
+    /// The revised maximum keep alive count
+    pub max_keep_alive_count: u32,
+    /// The maximum number of notifications per publish
+    pub max_notifications_per_publish: u32,
+    /// The priority of the subscription
+    pub priority: u8,
+    /// Whether publishing is enabled
+    pub publishing_enabled: bool,
+    /// The monitored items of the subscription by their id
+    pub monitored_items: BTreeMap<u32, MirroredMonitoredItem>,
+}
+
+impl BinaryEncoder<MirroredSubscription> for MirroredSubscription {
+    fn byte_len(&self) -> usize {
+        self.subscription_id.byte_len()
+            + self.client_user_id.byte_len()
+            + self.publishing_interval.byte_len()
+            + self.lifetime_count.byte_len()
+            + self.max_keep_alive_count.byte_len()
+            + self.max_notifications_per_publish.byte_len()
+            + self.priority.byte_len()
+            + self.publishing_enabled.byte_len()
+            + 4
+            + self
+                .monitored_items
+                .values()
+                .map(|item| item.byte_len())
+                .sum::<usize>()
+    }
+
+    fn encode<S: Write>(&self, stream: &mut S) -> EncodingResult<usize> {
+        let mut size = self.subscription_id.encode(stream)?;
+        size += self.client_user_id.encode(stream)?;
+        size += self.publishing_interval.encode(stream)?;
+        size += self.lifetime_count.encode(stream)?;
+        size += self.max_keep_alive_count.encode(stream)?;
+        size += self.max_notifications_per_publish.encode(stream)?;
+        size += self.priority.encode(stream)?;
+        size += self.publishing_enabled.encode(stream)?;
+        size += write_i32(stream, self.monitored_items.len() as i32)?;
+        for item in self.monitored_items.values() {
+            size += item.encode(stream)?;
+        }
+        Ok(size)
+    }
+
+    fn decode<S: Read>(stream: &mut S, decoding_options: &DecodingOptions) -> EncodingResult<Self> {
+        let subscription_id = u32::decode(stream, decoding_options)?;
+        let client_user_id = UAString::decode(stream, decoding_options)?;
+        let publishing_interval = f64::decode(stream, decoding_options)?;
+        let lifetime_count = u32::decode(stream, decoding_options)?;
+        let max_keep_alive_count = u32::decode(stream, decoding_options)?;
+        let max_notifications_per_publish = u32::decode(stream, decoding_options)?;
+        let priority = u8::decode(stream, decoding_options)?;
+        let publishing_enabled = bool::decode(stream, decoding_options)?;
+        let len = read_i32(stream)?;
+        if len < 0 || len as usize > decoding_options.max_array_length {
+            error!(
+                "Mirrored subscription has an invalid number of monitored items {}",
+                len
+            );
+            return Err(StatusCode::BadDecodingError);
+        }
+        let mut monitored_items = BTreeMap::new();
+        for _ in 0..len {
+            let item = MirroredMonitoredItem::decode(stream, decoding_options)?;
+            monitored_items.insert(item.monitored_item_id, item);
+        }
+        Ok(MirroredSubscription {
+            subscription_id,
+            client_user_id,
+            publishing_interval,
+            lifetime_count,
+            max_keep_alive_count,
+            max_notifications_per_publish,
+            priority,
+            publishing_enabled,
+            monitored_items,
+        })
+    }
+}
+
+/// Receives the subscriptions of a server to mirror them to a peer. A peer in the same process
+/// can be given the [`MirroredSubscriptions`] of the other server. Otherwise an implementation
+/// sends the encoded subscriptions to the peer, which puts them into its own
+/// [`MirroredSubscriptions`].
+///
+/// The functions are called while a service is handled, so they should not block.
+pub trait SubscriptionMirror {
+    /// Called with the whole subscription when it is created or changes
+    fn update_subscription(&self, subscription: &MirroredSubscription);
+
+    /// Called when a subscription is deleted, or expires
+    fn delete_subscription(&self, subscription_id: u32);
+}
+
+/// The subscriptions that a peer has mirrored to this server, which clients that fail over from
+/// the peer may transfer to their sessions.
+#[derive(Default)]
+pub struct MirroredSubscriptions {
+    subscriptions: RwLock<BTreeMap<u32, MirroredSubscription>>,
+}
+
+impl SubscriptionMirror for MirroredSubscriptions {
+    fn update_subscription(&self, subscription: &MirroredSubscription) {
+        let mut subscriptions = trace_write_lock!(self.subscriptions);
+        subscriptions.insert(subscription.subscription_id, subscription.clone());
+    }
+
+    fn delete_subscription(&self, subscription_id: u32) {
+        let mut subscriptions = trace_write_lock!(self.subscriptions);
+        subscriptions.remove(&subscription_id);
+    }
+}
+
+impl MirroredSubscriptions {
+    pub fn new() -> MirroredSubscriptions {
+        MirroredSubscriptions::default()
+    }
+
+    /// Returns the number of mirrored subscriptions
+    pub fn len(&self) -> usize {
+        trace_read_lock!(self.subscriptions).len()
+    }
+
+    /// Tests if there are no mirrored subscriptions
+    pub fn is_empty(&self) -> bool {
+        trace_read_lock!(self.subscriptions).is_empty()
+    }
+
+    /// Returns the ids of the mirrored subscriptions
+    pub fn subscription_ids(&self) -> Vec<u32> {
+        trace_read_lock!(self.subscriptions)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Tests if there is a mirrored subscription with the id
+    pub fn contains(&self, subscription_id: u32) -> bool {
+        trace_read_lock!(self.subscriptions).contains_key(&subscription_id)
+    }
+
+    /// Returns a mirrored subscription
+    pub fn get(&self, subscription_id: u32) -> Option<MirroredSubscription> {
+        trace_read_lock!(self.subscriptions)
+            .get(&subscription_id)
+            .cloned()
+    }
+
+    /// Removes a mirrored subscription so that it can be transferred to a session
+    pub(crate) fn take(&self, subscription_id: u32) -> Option<MirroredSubscription> {
+        trace_write_lock!(self.subscriptions).remove(&subscription_id)
+    }
+}
+
+/// Mirrors the subscriptions of the server's sessions to a peer, if the server has a mirror. Only
+/// subscriptions created after the mirror is set are mirrored.
+#[derive(Default)]
+pub(crate) struct SubscriptionMirroring {
+    mirror: RwLock<Option<Arc<dyn SubscriptionMirror + Send + Sync>>>,
+    subscriptions: Mutex<BTreeMap<u32, MirroredSubscription>>,
+}
+
+impl SubscriptionMirroring {
+    pub fn set_mirror(&self, mirror: Option<Arc<dyn SubscriptionMirror + Send + Sync>>) {
+        *trace_write_lock!(self.mirror) = mirror;
+    }
+
+    /// Tests if subscriptions are mirrored, so that callers only make a mirrored subscription if
+    /// it is needed.
+    pub fn is_mirroring(&self) -> bool {
+        trace_read_lock!(self.mirror).is_some()
+    }
+
+    /// Mirrors a new subscription
+    pub fn insert(&self, subscription: MirroredSubscription) {
+        if let Some(ref mirror) = *trace_read_lock!(self.mirror) {
+            mirror.update_subscription(&subscription);
+            trace_lock!(self.subscriptions).insert(subscription.subscription_id, subscription);
+        }
+    }
+
+    /// Changes a mirrored subscription and mirrors the change
+    pub fn update<F>(&self, subscription_id: u32, f: F)
+    where
+        F: FnOnce(&mut MirroredSubscription),
+    {
+        if let Some(ref mirror) = *trace_read_lock!(self.mirror) {
+            let mut subscriptions = trace_lock!(self.subscriptions);
+            if let Some(subscription) = subscriptions.get_mut(&subscription_id) {
+                f(subscription);
+                mirror.update_subscription(subscription);
+            }
+        }
+    }
+
+    /// Deletes a mirrored subscription
+    pub fn delete(&self, subscription_id: u32) {
+        if let Some(ref mirror) = *trace_read_lock!(self.mirror) {
+            if trace_lock!(self.subscriptions)
+                .remove(&subscription_id)
+                .is_some()
+            {
+                mirror.delete_subscription(subscription_id);
+            }
+        }
+    }
+}
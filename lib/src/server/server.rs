@@ -26,7 +26,9 @@ use crate::server::{
     constants,
     diagnostics::ServerDiagnostics,
     events::audit::AuditLog,
+    journal::Journal,
     metrics::ServerMetrics,
+    redundancy::{MirroredSubscriptions, SubscriptionMirroring},
     session::SessionManager,
     state::{OperationalLimits, ServerState},
     util::PollingAction,
@@ -112,8 +114,27 @@ impl Server {
         let diagnostics = Arc::new(RwLock::new(ServerDiagnostics::default()));
         let min_publishing_interval_ms = config.limits.min_publishing_interval * 1000.0;
         let min_sampling_interval_ms = config.limits.min_sampling_interval * 1000.0;
+        let journal = config.journal_path.as_ref().map(Journal::new);
+        let redundancy = config.redundancy.as_ref().and_then(|redundancy| {
+            let mut server_uris = vec![config.application_uri.clone()];
+            server_uris.extend(redundancy.peer_server_uris.iter().cloned());
+            redundancy
+                .redundancy_support()
+                .map(|redundancy_support| (redundancy_support, server_uris))
+        });
+        // The subscriptions of a redundant server start from a random id, so that a subscription
+        // transferred from a peer is unlikely to have the id of one of the server's own.
+        let last_subscription_id = if redundancy.is_some() {
+            let mut bytes = [0u8; 4];
+            random::bytes(&mut bytes);
+            u32::from_le_bytes(bytes) >> 1
+        } else {
+            0
+        };
         let send_buffer_size = config.limits.send_buffer_size;
         let receive_buffer_size = config.limits.receive_buffer_size;
+        let async_operation_timeout =
+            Duration::from_millis(config.limits.async_operation_timeout_ms as u64);
 
         // Security, pki auto create cert
         let application_description = if config.create_sample_keypair {
@@ -165,7 +186,7 @@ impl Server {
             config,
             server_certificate,
             server_pkey,
-            last_subscription_id: 0,
+            last_subscription_id,
             max_subscriptions,
             max_monitored_items_per_sub,
             max_monitored_item_queue_size,
@@ -182,6 +203,11 @@ impl Server {
             historical_data_provider: None,
             historical_event_provider: None,
             operational_limits: OperationalLimits::default(),
+            async_operation_timeout,
+            journal,
+            health_function: None,
+            subscription_mirroring: Arc::new(SubscriptionMirroring::default()),
+            mirrored_subscriptions: Arc::new(MirroredSubscriptions::new()),
             send_buffer_size,
             receive_buffer_size,
         };
@@ -190,6 +216,9 @@ impl Server {
         {
             let mut address_space = trace_write_lock!(address_space);
             address_space.set_server_state(server_state.clone());
+            if let Some((redundancy_support, server_uris)) = redundancy {
+                address_space.set_server_redundancy(redundancy_support, &server_uris);
+            }
         }
 
         // Server metrics
@@ -320,6 +349,15 @@ impl Server {
         // Put the server into a running state
         {
             let mut server = trace_write_lock!(server);
+            // Restore changes made to the address space during previous runs. A journal that
+            // cannot be replayed would lose the changes recorded from now on, so the server stops.
+            if let Err(err) = server.replay_journal() {
+                error!(
+                    "Server cannot start because the journal could not be replayed, error = {}",
+                    err
+                );
+                return;
+            }
             // Running
             {
                 let mut server_state = trace_write_lock!(server.server_state);
@@ -336,6 +374,9 @@ impl Server {
 
             // Start any pending polling action timers
             server.start_pending_polling_actions();
+
+            // Start a timer that updates the service level from the health function
+            server.start_health_check_timer();
         }
 
         // Start a server abort task loop
@@ -379,6 +420,26 @@ impl Server {
         info!("main server task is finished");
     }
 
+    /// Replays the journal, if there is one, against the address space. This happens when the
+    /// server starts running so that any nodes the application creates beforehand can be
+    /// referenced by the journaled changes.
+    fn replay_journal(&self) -> Result<(), StatusCode> {
+        let server_state = trace_read_lock!(self.server_state);
+        if let Some(ref journal) = server_state.journal {
+            let decoding_options = server_state.decoding_options();
+            let mut address_space = trace_write_lock!(self.address_space);
+            if let Err(err) = journal.replay(&mut address_space, &decoding_options) {
+                error!(
+                    "Journal {} could not be replayed, error = {}",
+                    journal.path().display(),
+                    err
+                );
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
     /// Returns the current [`ServerState`] for the server.
     ///
     /// [`ServerState`]: ../state/struct.ServerState.html
@@ -578,6 +639,43 @@ impl Server {
         });
     }
 
+    /// Starts a timer that calls the health function, if there is one, and writes the service
+    /// level that it returns to the address space when the level changes.
+    fn start_health_check_timer(&self) {
+        let interval_ms = {
+            let server_state = trace_read_lock!(self.server_state);
+            if server_state.health_function.is_none() {
+                return;
+            }
+            let config = trace_read_lock!(server_state.config);
+            config
+                .redundancy
+                .as_ref()
+                .map(|redundancy| redundancy.health_check_interval_ms)
+                .unwrap_or(1000)
+        };
+        let server_state = self.server_state.clone();
+        let address_space = self.address_space.clone();
+        let last_service_level = Mutex::new(None);
+        let _ = PollingAction::spawn(self.server_state.clone(), interval_ms, move || {
+            let service_level = {
+                let server_state = trace_read_lock!(server_state);
+                server_state
+                    .health_function
+                    .as_ref()
+                    .map(|health_function| health_function(&server_state))
+            };
+            if let Some(service_level) = service_level {
+                let mut last_service_level = trace_lock!(last_service_level);
+                if *last_service_level != Some(service_level) {
+                    *last_service_level = Some(service_level);
+                    let mut address_space = trace_write_lock!(address_space);
+                    address_space.set_service_level(service_level, &DateTime::now());
+                }
+            }
+        });
+    }
+
     /// Creates a polling action that happens continuously on an interval while the server
     /// is running. For example, a server might run a polling action every 100ms to synchronous
     /// address space state between variables and their physical backends.
@@ -4,6 +4,8 @@
 
 use std::{result::Result, sync::Arc};
 
+use futures::future;
+
 use crate::{
     core::supported_message::SupportedMessage,
     sync::*,
@@ -16,11 +18,17 @@ use crate::server::{
         variable::Variable,
         AddressSpace, UserAccessLevel,
     },
-    services::Service,
+    callbacks::{AsyncAttributeGetter, AsyncAttributeSetter, AsyncReadValue},
+    journal::JournalEntry,
+    node_manager::{ManagedOperations, RequestContext},
+    services::{async_operation_timeout, operation_results, Service, ServiceResponse},
     session::Session,
     state::ServerState,
 };
 
+#[cfg(test)]
+use crate::server::services::unregistered_request_context;
+
 enum ReadDetails {
     ReadEventDetails(ReadEventDetails),
     ReadRawModifiedDetails(ReadRawModifiedDetails),
@@ -57,6 +65,7 @@ impl AttributeService {
     /// elements or to read ranges of elements of the composite. Servers may make historical
     /// values available to Clients using this Service, although the historical values themselves
     /// are not visible in the AddressSpace.
+    #[cfg(test)]
     pub fn read(
         &self,
         server_state: Arc<RwLock<ServerState>>,
@@ -64,49 +73,152 @@ impl AttributeService {
         address_space: Arc<RwLock<AddressSpace>>,
         request: &ReadRequest,
     ) -> SupportedMessage {
+        let context = unregistered_request_context(&session);
+        self.read_deferred(server_state, session, context, address_space, request)
+            .wait()
+    }
+
+    /// Reads like `read`, except that values of variables with an async getter, and of nodes of
+    /// node managers, are fetched by a deferred response that is awaited without holding the
+    /// address space lock.
+    pub(crate) fn read_deferred(
+        &self,
+        server_state: Arc<RwLock<ServerState>>,
+        session: Arc<RwLock<Session>>,
+        context: RequestContext,
+        address_space: Arc<RwLock<AddressSpace>>,
+        request: &ReadRequest,
+    ) -> ServiceResponse {
         if is_empty_option_vec!(request.nodes_to_read) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
+                .into()
         } else if request.max_age < 0f64 {
             // Negative values are invalid for max_age
             warn!("ReadRequest max age is invalid");
             self.service_fault(&request.request_header, StatusCode::BadMaxAgeInvalid)
+                .into()
         } else if request.timestamps_to_return == TimestampsToReturn::Invalid {
             warn!("ReadRequest invalid timestamps to return");
             self.service_fault(
                 &request.request_header,
                 StatusCode::BadTimestampsToReturnInvalid,
             )
+            .into()
         } else {
-            let server_state = trace_read_lock!(server_state);
-            let nodes_to_read = request.nodes_to_read.as_ref().unwrap();
-            if nodes_to_read.len() <= server_state.operational_limits.max_nodes_per_read {
+            let (results, async_reads, managed_reads, timeout) = {
+                let server_state = trace_read_lock!(server_state);
+                let nodes_to_read = request.nodes_to_read.as_ref().unwrap();
+                if nodes_to_read.len() > server_state.operational_limits.max_nodes_per_read {
+                    warn!("ReadRequest too many nodes to read {}", nodes_to_read.len());
+                    return self
+                        .service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                        .into();
+                }
                 // Read nodes and their attributes
                 let session = trace_read_lock!(session);
                 let address_space = trace_read_lock!(address_space);
                 let timestamps_to_return = request.timestamps_to_return;
-                let results = nodes_to_read
+                let mut async_reads = AsyncReads::new();
+                let mut managed_reads = ManagedOperations::new();
+                let results: Vec<DataValue> = nodes_to_read
                     .iter()
-                    .map(|node_to_read| {
-                        Self::read_node_value(
+                    .enumerate()
+                    .map(|(idx, node_to_read)| {
+                        if let Some(node_manager) =
+                            address_space.node_manager_for(&node_to_read.node_id)
+                        {
+                            // The value is filled in when the node manager completes
+                            managed_reads.add(node_manager, idx, node_to_read.clone());
+                            DataValue::null()
+                        } else if let Some((getter, read)) = Self::async_read(
                             &session,
                             &address_space,
                             node_to_read,
                             request.max_age,
                             timestamps_to_return,
-                        )
+                        ) {
+                            // The value is filled in when the getter completes
+                            async_reads.add(getter, idx, read);
+                            DataValue::null()
+                        } else {
+                            Self::read_node_value(
+                                &session,
+                                &address_space,
+                                node_to_read,
+                                request.max_age,
+                                timestamps_to_return,
+                            )
+                        }
                     })
                     .collect();
+                (
+                    results,
+                    async_reads,
+                    managed_reads,
+                    server_state.async_operation_timeout,
+                )
+            };
 
-                let diagnostic_infos = None;
-                let response = ReadResponse {
-                    response_header: ResponseHeader::new_good(&request.request_header),
-                    results: Some(results),
-                    diagnostic_infos,
-                };
-                response.into()
+            if async_reads.is_empty() && managed_reads.is_empty() {
+                ServiceResponse::Ready(Self::read_response(
+                    &request.request_header,
+                    results,
+                    Vec::new(),
+                ))
             } else {
-                warn!("ReadRequest too many nodes to read {}", nodes_to_read.len());
-                self.service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                let request_header = request.request_header.clone();
+                let (max_age, timestamps_to_return) =
+                    (request.max_age, request.timestamps_to_return);
+                ServiceResponse::Deferred(Box::pin(async move {
+                    let mut results = results;
+                    let mut errors = vec![None; results.len()];
+                    let (values, managed_values) = future::join(
+                        async_reads.read(timeout, &request_header),
+                        Self::managed_read(
+                            managed_reads,
+                            &context,
+                            max_age,
+                            timestamps_to_return,
+                            timeout,
+                            &request_header,
+                        ),
+                    )
+                    .await;
+                    for (idx, value) in managed_values {
+                        results[idx] = Self::filter_timestamps(value, timestamps_to_return);
+                    }
+                    {
+                        // Successful reads become the value held by the variable
+                        let mut address_space = trace_write_lock!(address_space);
+                        let now = DateTime::now();
+                        for (idx, read, value) in values.into_iter() {
+                            let value = match value {
+                                Ok(value) => value,
+                                Err(err) => {
+                                    let value = status_data_value(err.status_code());
+                                    errors[idx] = Some(err);
+                                    value
+                                }
+                            };
+                            if !value.status().is_bad() {
+                                if let (Some(v), Some(variable)) = (
+                                    value.value.as_ref(),
+                                    address_space.find_variable_mut_by_ref(&read.node_id),
+                                ) {
+                                    let _ = variable.set_value_direct(
+                                        v.clone(),
+                                        value.status(),
+                                        value.server_timestamp.as_ref().unwrap_or(&now),
+                                        value.source_timestamp.as_ref().unwrap_or(&now),
+                                    );
+                                }
+                            }
+                            let value = Self::value_range_of(value, read.index_range);
+                            results[idx] = Self::filter_timestamps(value, timestamps_to_return);
+                        }
+                    }
+                    Self::read_response(&request_header, results, errors)
+                }))
             }
         }
     }
@@ -150,6 +262,7 @@ impl AttributeService {
     /// constructed Attribute values whose elements are indexed, such as an array, this Service
     /// allows Clients to write the entire set of indexed values as a composite, to write individual
     /// elements or to write ranges of elements of the composite.
+    #[cfg(test)]
     pub fn write(
         &self,
         server_state: Arc<RwLock<ServerState>>,
@@ -157,41 +270,243 @@ impl AttributeService {
         address_space: Arc<RwLock<AddressSpace>>,
         request: &WriteRequest,
     ) -> SupportedMessage {
+        let context = unregistered_request_context(&session);
+        self.write_deferred(server_state, session, context, address_space, request)
+            .wait()
+    }
+
+    /// Writes like `write`, except that values of variables with an async setter, and nodes of
+    /// node managers, are written by a deferred response that is awaited without holding the
+    /// address space lock.
+    pub(crate) fn write_deferred(
+        &self,
+        server_state: Arc<RwLock<ServerState>>,
+        session: Arc<RwLock<Session>>,
+        context: RequestContext,
+        address_space: Arc<RwLock<AddressSpace>>,
+        request: &WriteRequest,
+    ) -> ServiceResponse {
         if is_empty_option_vec!(request.nodes_to_write) {
             debug!("Empty list passed to write {:?}", request);
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
+                .into()
         } else {
             // TODO audit - generate AuditWriteUpdateEventType event
-            let server_state = trace_read_lock!(server_state);
-            let session = trace_read_lock!(session);
-            let mut address_space = trace_write_lock!(address_space);
+            let (results, async_writes, managed_writes, timeout) = {
+                let server_state = trace_read_lock!(server_state);
+                let session = trace_read_lock!(session);
 
-            let nodes_to_write = request.nodes_to_write.as_ref().unwrap();
-            if nodes_to_write.len() <= server_state.operational_limits.max_nodes_per_write {
-                let results = nodes_to_write
+                let nodes_to_write = request.nodes_to_write.as_ref().unwrap();
+                if nodes_to_write.len() > server_state.operational_limits.max_nodes_per_write {
+                    warn!(
+                        "WriteRequest too many nodes to write {}",
+                        nodes_to_write.len()
+                    );
+                    return self
+                        .service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                        .into();
+                }
+                let mut async_writes = Vec::new();
+                let mut managed_writes = ManagedOperations::new();
+                // Writes to node managers and async setters are deferred, and their results are
+                // filled in when they complete
+                let mut defer =
+                    |idx: usize, address_space: &AddressSpace, node_to_write: &WriteValue| {
+                        if let Some(node_manager) =
+                            address_space.node_manager_for(&node_to_write.node_id)
+                        {
+                            managed_writes.add(node_manager, idx, node_to_write.clone());
+                            true
+                        } else if let Some((setter, index_range, node_to_write)) =
+                            Self::async_write(&session, address_space, node_to_write)
+                        {
+                            async_writes.push((idx, setter, index_range, node_to_write));
+                            true
+                        } else {
+                            false
+                        }
+                    };
+                // Writes are made in the order of the request. Values are locked separately from
+                // the nodes that hold them, so a batch that only writes values needs just a read
+                // lock. Writes to other attributes change nodes and need a write lock. A write to
+                // a persistent node is journaled before the value is unlocked, so that concurrent
+                // writes are journaled in the order they are made.
+                let writes_attributes = nodes_to_write
                     .iter()
-                    .map(|node_to_write| {
-                        Self::write_node_value(&session, &mut address_space, node_to_write)
-                    })
-                    .collect();
+                    .any(|node_to_write| node_to_write.attribute_id != AttributeId::Value as u32);
+                let results: Vec<Result<(), DiagnosticError>> = if writes_attributes {
+                    let mut address_space = trace_write_lock!(address_space);
+                    nodes_to_write
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, node_to_write)| {
+                            if defer(idx, &address_space, node_to_write) {
+                                return Ok(());
+                            }
+                            let persistent = address_space.is_persistent(&node_to_write.node_id);
+                            Self::write_node_value(
+                                &session,
+                                &mut address_space,
+                                node_to_write,
+                                || {
+                                    if persistent {
+                                        server_state.journal_entry(|| {
+                                            JournalEntry::Write(node_to_write.clone())
+                                        });
+                                    }
+                                },
+                            )
+                        })
+                        .collect()
+                } else {
+                    let address_space = trace_read_lock!(address_space);
+                    nodes_to_write
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, node_to_write)| {
+                            if defer(idx, &address_space, node_to_write) {
+                                return Ok(());
+                            }
+                            let persistent = address_space.is_persistent(&node_to_write.node_id);
+                            Self::write_variable_value(
+                                &session,
+                                &address_space,
+                                node_to_write,
+                                || {
+                                    if persistent {
+                                        server_state.journal_entry(|| {
+                                            JournalEntry::Write(node_to_write.clone())
+                                        });
+                                    }
+                                },
+                            )
+                        })
+                        .collect()
+                };
+                server_state.sync_journal();
+                (
+                    results,
+                    async_writes,
+                    managed_writes,
+                    server_state.async_operation_timeout,
+                )
+            };
 
-                let diagnostic_infos = None;
-                WriteResponse {
-                    response_header: ResponseHeader::new_good(&request.request_header),
-                    results: Some(results),
-                    diagnostic_infos,
-                }
-                .into()
+            if async_writes.is_empty() && managed_writes.is_empty() {
+                ServiceResponse::Ready(Self::write_response(&request.request_header, results))
             } else {
-                warn!(
-                    "WriteRequest too many nodes to write {}",
-                    nodes_to_write.len()
-                );
-                self.service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                let request_header = request.request_header.clone();
+                ServiceResponse::Deferred(Box::pin(async move {
+                    let mut results = results;
+                    let request_header = &request_header;
+                    let writes = async_writes.into_iter().map(
+                        |(idx, setter, index_range, node_to_write)| async move {
+                            let timeout =
+                                async_operation_timeout(timeout, setter.timeout(), request_header);
+                            let set = setter.set(
+                                &node_to_write.node_id,
+                                AttributeId::Value,
+                                index_range,
+                                node_to_write.value.clone(),
+                            );
+                            let result = match tokio::time::timeout(timeout, set).await {
+                                Ok(result) => result,
+                                Err(_) => {
+                                    warn!(
+                                        "Async setter of {} did not complete within {} ms",
+                                        node_to_write.node_id,
+                                        timeout.as_millis()
+                                    );
+                                    Err(DiagnosticError::new(StatusCode::BadTimeout)
+                                        .with_additional_info(format!(
+                                            "The setter of node {} did not complete within {} ms",
+                                            node_to_write.node_id,
+                                            timeout.as_millis()
+                                        )))
+                                }
+                            };
+                            (idx, node_to_write, result)
+                        },
+                    );
+                    let (writes, managed_results) = future::join(
+                        future::join_all(writes),
+                        Self::managed_write(managed_writes, &context, timeout, request_header),
+                    )
+                    .await;
+                    for (idx, result) in managed_results {
+                        results[idx] = result;
+                    }
+                    {
+                        let server_state = trace_read_lock!(server_state);
+                        let address_space = trace_read_lock!(address_space);
+                        for (idx, node_to_write, result) in writes {
+                            if result.is_ok() && address_space.is_persistent(&node_to_write.node_id)
+                            {
+                                server_state.journal_entry(|| JournalEntry::Write(node_to_write));
+                            }
+                            results[idx] = result;
+                        }
+                        drop(address_space);
+                        server_state.sync_journal();
+                    }
+                    Self::write_response(request_header, results)
+                }))
             }
         }
     }
 
+    /// Makes the response to a read from the value of each read and the errors of any reads that
+    /// failed with details. The diagnostics of other failed reads are made from their status.
+    fn read_response(
+        request_header: &RequestHeader,
+        results: Vec<DataValue>,
+        errors: Vec<Option<DiagnosticError>>,
+    ) -> SupportedMessage {
+        let mut diagnostics = DiagnosticInfoBuilder::new(request_header);
+        let diagnostic_infos = if diagnostics.operation_level_requested() {
+            let mut errors = errors;
+            errors.resize(results.len(), None);
+            let errors = results
+                .iter()
+                .zip(errors)
+                .map(|(value, error)| {
+                    error.or_else(|| {
+                        let status = value.status();
+                        if status.is_bad() {
+                            Some(status.into())
+                        } else {
+                            None
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            diagnostics.operation_diagnostic_infos(errors.iter().map(Option::as_ref))
+        } else {
+            None
+        };
+        ReadResponse {
+            response_header: diagnostics.response_header(request_header, &StatusCode::Good.into()),
+            results: Some(results),
+            diagnostic_infos,
+        }
+        .into()
+    }
+
+    /// Makes the response to a write from the result of each write
+    fn write_response(
+        request_header: &RequestHeader,
+        results: Vec<Result<(), DiagnosticError>>,
+    ) -> SupportedMessage {
+        let (response_header, results, diagnostic_infos) =
+            operation_results(request_header, results);
+        WriteResponse {
+            response_header,
+            results: Some(results),
+            diagnostic_infos,
+        }
+        .into()
+    }
+
     /// Used to update or update historical values
     pub fn history_update(
         &self,
@@ -497,7 +812,7 @@ impl AttributeService {
         }
     }
 
-    fn read_node_value(
+    pub(crate) fn read_node_value(
         session: &Session,
         address_space: &AddressSpace,
         node_to_read: &ReadValueId,
@@ -720,138 +1035,574 @@ impl AttributeService {
     }
     */
 
-    /// Determine if the value is writable to a Variable node's data type
+    /// Checks that the status and timestamps of a value to write to a Variable can be written,
+    /// which depends on the StatusWrite and TimestampWrite bits of the user access level
+    fn validate_status_and_timestamps_to_write(
+        session: &Session,
+        node: &NodeType,
+        node_to_write: &WriteValue,
+    ) -> Result<(), DiagnosticError> {
+        let value = &node_to_write.value;
+        let user_access_level = Self::user_access_level(session, node, AttributeId::Value);
+        if value.status.is_some() && !user_access_level.contains(UserAccessLevel::STATUS_WRITE) {
+            error!("Server does not support writing the status of this value");
+            return Err(DiagnosticError::new(StatusCode::BadWriteNotSupported)
+                .with_additional_info(format!(
+                    "The status of node {} cannot be written",
+                    node_to_write.node_id
+                )));
+        }
+        let has_timestamp = value.source_timestamp.is_some()
+            || value.source_picoseconds.is_some()
+            || value.server_timestamp.is_some()
+            || value.server_picoseconds.is_some();
+        if has_timestamp && !user_access_level.contains(UserAccessLevel::TIMESTAMP_WRITE) {
+            error!("Server does not support writing the timestamps of this value");
+            return Err(DiagnosticError::new(StatusCode::BadWriteNotSupported)
+                .with_additional_info(format!(
+                    "The timestamps of node {} cannot be written",
+                    node_to_write.node_id
+                )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the shape of the value to write agrees with the value rank and array
+    /// dimensions of a Variable node
+    fn validate_value_shape(
+        address_space: &AddressSpace,
+        variable: &Variable,
+        index_range: &NumericRange,
+        value: &Variant,
+    ) -> Result<(), DiagnosticError> {
+        let value_rank = variable.value_rank();
+        let dimensions = value.dimensions();
+        if index_range.has_range() {
+            let range_dimensions = match index_range {
+                NumericRange::MultipleRanges(ranges) => ranges.len(),
+                _ => 1,
+            };
+            let valid = match value_rank {
+                // Only strings and byte strings can have ranges written to a scalar
+                -1 => {
+                    let data_type = variable.data_type();
+                    address_space.is_subtype(&data_type, &DataTypeId::String.into())
+                        || address_space.is_subtype(&data_type, &DataTypeId::ByteString.into())
+                }
+                value_rank if value_rank > 0 => range_dimensions == value_rank as usize,
+                _ => true,
+            };
+            // The number of values written to a single dimension must match the range
+            let range_size = match index_range {
+                NumericRange::Index(_) => Some(1),
+                NumericRange::Range(min, max) => Some(max - min + 1),
+                _ => None,
+            };
+            let valid = valid
+                && match (range_size, dimensions.as_slice()) {
+                    (Some(range_size), [len]) => range_size == *len,
+                    _ => true,
+                };
+            if !valid {
+                return Err(DiagnosticError::new(StatusCode::BadIndexRangeInvalid)
+                    .with_additional_info(format!(
+                        "Index range {:?} cannot be written to a variable with value rank {}",
+                        index_range, value_rank
+                    )));
+            }
+            return Ok(());
+        }
+
+        let array_dimensions = variable.array_dimensions();
+        if Variant::dimensions_match(value_rank, array_dimensions.as_deref(), &dimensions) {
+            Ok(())
+        } else {
+            error!("Shape of value is invalid for writing to attribute");
+            Err(DiagnosticError::new(StatusCode::BadTypeMismatch).with_additional_info(format!(
+                "A value with dimensions {:?} cannot be written to a variable with value rank {} and array dimensions {:?}",
+                dimensions, value_rank, array_dimensions
+            )))
+        }
+    }
+
+    /// Checks that the value can be written to a Variable node and returns the value to write,
+    /// which is converted to the data type of the variable if it is of a different type
     fn validate_value_to_write(
         address_space: &AddressSpace,
         variable: &Variable,
+        index_range: &NumericRange,
         value: &Variant,
-    ) -> bool {
+    ) -> Result<Variant, DiagnosticError> {
         // Get the value rank and data type of the variable
         let value_rank = variable.value_rank();
         let node_data_type = variable.data_type();
 
-        let valid = if let Variant::Empty = value {
+        if let Variant::Empty = value {
             // Assigning an empty value is permissible
-            true
-        } else if let Some(value_data_type) = value.scalar_data_type() {
-            // Value is scalar. Check if the data type matches
-            let data_type_matches = address_space.is_subtype(&value_data_type, &node_data_type);
-            if !data_type_matches {
-                // Check if the value to write is a byte string and the receiving node type a byte array.
-                // This code is a mess just for some weird edge case in the spec that a write from
-                // a byte string to a byte array should succeed
-                match value {
-                    Variant::ByteString(_) => {
-                        if node_data_type == DataTypeId::Byte.into() {
-                            match value_rank {
-                                -2 | -3 | 1 => true,
-                                _ => false,
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                    _ => data_type_matches,
-                }
-            } else {
-                true
+            return Ok(Variant::Empty);
+        }
+        // A write from a byte string to a byte array should succeed, which is some weird edge
+        // case in the spec
+        if let Variant::ByteString(_) = value {
+            if node_data_type == DataTypeId::Byte.into() && matches!(value_rank, -3 | -2 | 1) {
+                return Ok(value.clone());
             }
-        } else if let Some(value_data_type) = value.array_data_type() {
-            // TODO check that value is array of same dimensions
-            address_space.is_subtype(&value_data_type, &node_data_type)
-        } else {
-            // Value should have a data type
-            false
-        };
-        if !valid {
+        }
+
+        Self::validate_value_shape(address_space, variable, index_range, value)?;
+
+        let converted = address_space.convert_to_data_type(value, &node_data_type);
+        converted.ok_or_else(|| {
             debug!("Variable value validation did not pass, check value {:?} against var {} data type of {}", value, variable.node_id(), node_data_type);
+            error!("Data type of value is invalid for writing to attribute");
+            DiagnosticError::new(StatusCode::BadTypeMismatch).with_additional_info(format!(
+                "A value of type {:?} cannot be written to a variable with data type {} and value rank {}",
+                value.type_id(),
+                node_data_type,
+                value_rank
+            ))
+        })
+    }
+
+    /// Validates the write and returns the attribute, index range and value to write. The value
+    /// of a Variable is converted to the data type of the variable.
+    fn validate_write(
+        session: &Session,
+        address_space: &AddressSpace,
+        node_to_write: &WriteValue,
+    ) -> Result<(AttributeId, NumericRange, Variant), DiagnosticError> {
+        let node = address_space
+            .find_node(&node_to_write.node_id)
+            .ok_or_else(|| {
+                warn!("Cannot find node id {}", node_to_write.node_id);
+                DiagnosticError::new(StatusCode::BadNodeIdUnknown)
+                    .with_additional_info(format!("Node {} does not exist", node_to_write.node_id))
+            })?;
+        let attribute_id = AttributeId::from_u32(node_to_write.attribute_id).map_err(|_| {
+            warn!("Attribute id {} is invalid", node_to_write.attribute_id);
+            DiagnosticError::new(StatusCode::BadAttributeIdInvalid).with_additional_info(format!(
+                "Attribute id {} is invalid",
+                node_to_write.attribute_id
+            ))
+        })?;
+        if !Self::is_writable(session, node, attribute_id) {
+            return Err(
+                DiagnosticError::new(StatusCode::BadNotWritable).with_additional_info(format!(
+                    "Attribute {:?} of node {} is not writable by the session",
+                    attribute_id, node_to_write.node_id
+                )),
+            );
+        }
+        if attribute_id != AttributeId::Value && !node_to_write.index_range.is_null() {
+            // Index ranges are not supported on anything other than a value attribute
+            error!("Server does not support indexes for attributes other than Value");
+            return Err(DiagnosticError::new(StatusCode::BadWriteNotSupported)
+                .with_additional_info("Index ranges can only be written to the Value attribute"));
         }
-        valid
+        if attribute_id == AttributeId::Value {
+            if let NodeType::Variable(_) = node {
+                Self::validate_status_and_timestamps_to_write(session, node, node_to_write)?;
+            }
+        }
+        let index_range = node_to_write
+            .index_range
+            .as_ref()
+            .parse::<NumericRange>()
+            .map_err(|_| {
+                error!("Index range is invalid");
+                DiagnosticError::new(StatusCode::BadIndexRangeInvalid).with_additional_info(
+                    format!("Index range \"{}\" is invalid", node_to_write.index_range),
+                )
+            })?;
+        let value = node_to_write.value.value.as_ref().ok_or_else(|| {
+            error!("Server does not support missing value in write");
+            DiagnosticError::new(StatusCode::BadTypeMismatch)
+                .with_additional_info("The data value to write has no value")
+        })?;
+
+        // Note, that ALL attributes should check for subtypes but only Variable::Value does.
+        // There should be a general purpose fn attribute_type(attribute_id) helper on the node
+        // impl that returns a datatype for the attribute regardless of node.
+        if attribute_id == AttributeId::Value {
+            if let NodeType::Variable(ref variable) = node {
+                let value =
+                    Self::validate_value_to_write(address_space, variable, &index_range, value)?;
+                return Ok((attribute_id, index_range, value));
+            }
+            // Other types don't have this attr but they will reject later during set
+        }
+        Ok((attribute_id, index_range, value.clone()))
     }
 
-    fn write_node_value(
+    pub(crate) fn write_node_value<F>(
         session: &Session,
         address_space: &mut AddressSpace,
         node_to_write: &WriteValue,
-    ) -> StatusCode {
-        if let Some(node) = address_space.find_node(&node_to_write.node_id) {
-            if let Ok(attribute_id) = AttributeId::from_u32(node_to_write.attribute_id) {
-                let index_range = node_to_write.index_range.as_ref().parse::<NumericRange>();
-
-                if !Self::is_writable(session, node, attribute_id) {
-                    StatusCode::BadNotWritable
-                } else if attribute_id != AttributeId::Value && !node_to_write.index_range.is_null()
-                {
-                    // Index ranges are not supported on anything other than a value attribute
-                    error!("Server does not support indexes for attributes other than Value");
-                    StatusCode::BadWriteNotSupported
-                //                 else if node_to_write.value.server_timestamp.is_some() || node_to_write.value.server_picoseconds.is_some() ||
-                //                    node_to_write.value.source_timestamp.is_some() || node_to_write.value.source_picoseconds.is_some() {
-                //                    error!("Server does not support timestamps in write");
-                //                    StatusCode::BadWriteNotSupported
-                } else if index_range.is_err() {
-                    error!("Index range is invalid");
-                    StatusCode::BadIndexRangeInvalid
-                } else if let Some(ref value) = node_to_write.value.value {
-                    let index_range = index_range.unwrap();
-
-                    // This is a band-aid for Variable::Value which should check if the data type
-                    // matches the written value. Note, that ALL attributes should check for subtypes
-                    // but they don't. There should be a general purpose fn attribute_type(attribute_id) helper
-                    // on the node impl that returns a datatype for the attribute regardless of node.
-                    let data_type_valid = if attribute_id == AttributeId::Value {
-                        match node {
-                            NodeType::Variable(ref variable) => {
-                                Self::validate_value_to_write(address_space, variable, value)
-                            }
-                            _ => true, // Other types don't have this attr but they will reject later during set
-                        }
-                    } else {
-                        true
-                    };
-                    if !data_type_valid {
-                        error!("Data type of value is invalid for writing to attribute");
-                        StatusCode::BadTypeMismatch
-                    } else {
-                        let node = address_space.find_node_mut(&node_to_write.node_id).unwrap();
-                        let result = if attribute_id == AttributeId::Value {
-                            match node {
-                                NodeType::Variable(ref mut variable) => variable
-                                    .set_value(index_range, value.clone())
-                                    .map_err(|err| {
-                                        error!(
-                                            "Value could not be set to node {} Value, error = {}",
-                                            node_to_write.node_id, err
-                                        );
-                                        err
-                                    }),
-                                _ => Err(StatusCode::BadAttributeIdInvalid),
-                            }
-                        } else {
-                            let node = node.as_mut_node();
-                            node.set_attribute(attribute_id, value.clone())
-                                .map_err(|err| {
-                                    error!("Value could not be set to node {} attribute {:?}, error = {}", node_to_write.node_id, attribute_id, err);
-                                    err
-                                })
-                        };
-                        if let Err(err) = result {
-                            err
-                        } else {
-                            StatusCode::Good
-                        }
+        on_written: F,
+    ) -> Result<(), DiagnosticError>
+    where
+        F: FnOnce(),
+    {
+        if node_to_write.attribute_id == AttributeId::Value as u32 {
+            Self::write_variable_value(session, address_space, node_to_write, on_written)
+        } else {
+            let (attribute_id, _, value) =
+                Self::validate_write(session, address_space, node_to_write)?;
+            let node = address_space
+                .find_node_mut(&node_to_write.node_id)
+                .unwrap()
+                .as_mut_node();
+            node.set_attribute(attribute_id, value)
+                .map(|_| on_written())
+                .map_err(|err| {
+                    error!(
+                        "Value could not be set to node {} attribute {:?}, error = {}",
+                        node_to_write.node_id, attribute_id, err
+                    );
+                    DiagnosticError::new(err).with_additional_info(format!(
+                        "Attribute {:?} of node {} could not be set",
+                        attribute_id, node_to_write.node_id
+                    ))
+                })
+        }
+    }
+
+    /// Writes the Value attribute of a variable, which only needs a shared reference to the
+    /// address space because values are locked separately from the nodes that hold them.
+    /// `on_written` is called after a successful write, before the value is unlocked.
+    fn write_variable_value<F>(
+        session: &Session,
+        address_space: &AddressSpace,
+        node_to_write: &WriteValue,
+        on_written: F,
+    ) -> Result<(), DiagnosticError>
+    where
+        F: FnOnce(),
+    {
+        let (_, index_range, value) = Self::validate_write(session, address_space, node_to_write)?;
+        let data_value = DataValue {
+            value: Some(value),
+            ..node_to_write.value.clone()
+        };
+        match address_space.find_node(&node_to_write.node_id) {
+            Some(NodeType::Variable(ref variable)) => variable
+                .set_data_value_with(index_range, data_value, on_written)
+                .map_err(|err| {
+                    error!(
+                        "Value could not be set to node {} Value, error = {}",
+                        node_to_write.node_id, err
+                    );
+                    DiagnosticError::new(err).with_additional_info(format!(
+                        "The Value of node {} could not be set",
+                        node_to_write.node_id
+                    ))
+                }),
+            _ => Err(
+                DiagnosticError::new(StatusCode::BadAttributeIdInvalid).with_additional_info(
+                    format!(
+                        "Node {} is not a variable and has no Value",
+                        node_to_write.node_id
+                    ),
+                ),
+            ),
+        }
+    }
+
+    /// Returns the async setter, index range and write with the converted value for a valid write
+    /// to the value of a variable that has an async setter
+    fn async_write(
+        session: &Session,
+        address_space: &AddressSpace,
+        node_to_write: &WriteValue,
+    ) -> Option<(Arc<dyn AsyncAttributeSetter>, NumericRange, WriteValue)> {
+        if node_to_write.attribute_id != AttributeId::Value as u32 {
+            return None;
+        }
+        let setter = match address_space.find_node(&node_to_write.node_id) {
+            Some(NodeType::Variable(ref variable)) => variable.async_value_setter()?,
+            _ => return None,
+        };
+        let (_, index_range, value) =
+            Self::validate_write(session, address_space, node_to_write).ok()?;
+        let mut node_to_write = node_to_write.clone();
+        node_to_write.value.value = Some(value);
+        Some((setter, index_range, node_to_write))
+    }
+
+    /// Returns the async getter and the read for a valid read of the value of a variable that has
+    /// an async getter
+    fn async_read(
+        session: &Session,
+        address_space: &AddressSpace,
+        node_to_read: &ReadValueId,
+        max_age: f64,
+        timestamps_to_return: TimestampsToReturn,
+    ) -> Option<(Arc<dyn AsyncAttributeGetter>, AsyncReadValue)> {
+        if node_to_read.attribute_id != AttributeId::Value as u32 {
+            return None;
+        }
+        let node = address_space.find_node(&node_to_read.node_id)?;
+        let getter = match node {
+            NodeType::Variable(ref variable) => variable.async_value_getter()?,
+            _ => return None,
+        };
+        let index_range = node_to_read
+            .index_range
+            .as_ref()
+            .parse::<NumericRange>()
+            .ok()?;
+        if Self::is_readable(session, node, AttributeId::Value)
+            && Self::is_supported_data_encoding(&node_to_read.data_encoding)
+        {
+            let read = AsyncReadValue {
+                node_id: node_to_read.node_id.clone(),
+                timestamps_to_return,
+                index_range,
+                data_encoding: node_to_read.data_encoding.clone(),
+                max_age,
+            };
+            Some((getter, read))
+        } else {
+            // The read fails in the usual way
+            None
+        }
+    }
+
+    /// Calls every node manager with its reads at the same time. Returns the index and value of
+    /// every read.
+    async fn managed_read(
+        reads: ManagedOperations<ReadValueId>,
+        context: &RequestContext,
+        max_age: f64,
+        timestamps_to_return: TimestampsToReturn,
+        server_timeout: std::time::Duration,
+        request_header: &RequestHeader,
+    ) -> Vec<(usize, DataValue)> {
+        let batches = reads
+            .into_batches()
+            .map(|(node_manager, indices, reads)| async move {
+                let timeout =
+                    async_operation_timeout(server_timeout, node_manager.timeout(), request_header);
+                let read = node_manager.read(context, &reads, max_age, timestamps_to_return);
+                let values = match tokio::time::timeout(timeout, read).await {
+                    Ok(values) if values.len() == reads.len() => values,
+                    Ok(values) => {
+                        error!(
+                            "Node manager returned {} values for {} reads",
+                            values.len(),
+                            reads.len()
+                        );
+                        vec![status_data_value(StatusCode::BadInternalError); reads.len()]
                     }
-                } else {
-                    error!("Server does not support missing value in write");
-                    StatusCode::BadTypeMismatch
+                    Err(_) => {
+                        warn!(
+                            "Node manager did not complete {} reads within {} ms",
+                            reads.len(),
+                            timeout.as_millis()
+                        );
+                        vec![status_data_value(StatusCode::BadTimeout); reads.len()]
+                    }
+                };
+                indices.into_iter().zip(values).collect::<Vec<_>>()
+            });
+        future::join_all(batches)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Calls every node manager with its writes at the same time. Returns the index and result of
+    /// every write.
+    async fn managed_write(
+        writes: ManagedOperations<WriteValue>,
+        context: &RequestContext,
+        server_timeout: std::time::Duration,
+        request_header: &RequestHeader,
+    ) -> Vec<(usize, Result<(), DiagnosticError>)> {
+        let batches = writes
+            .into_batches()
+            .map(|(node_manager, indices, writes)| async move {
+                let timeout =
+                    async_operation_timeout(server_timeout, node_manager.timeout(), request_header);
+                let write = node_manager.write(context, &writes);
+                let results = match tokio::time::timeout(timeout, write).await {
+                    Ok(results) if results.len() == writes.len() => results,
+                    Ok(results) => {
+                        error!(
+                            "Node manager returned {} results for {} writes",
+                            results.len(),
+                            writes.len()
+                        );
+                        let err = DiagnosticError::new(StatusCode::BadInternalError)
+                            .with_additional_info(format!(
+                                "The node manager returned {} results for {} writes",
+                                results.len(),
+                                writes.len()
+                            ));
+                        vec![Err(err); writes.len()]
+                    }
+                    Err(_) => {
+                        warn!(
+                            "Node manager did not complete {} writes within {} ms",
+                            writes.len(),
+                            timeout.as_millis()
+                        );
+                        let err = DiagnosticError::new(StatusCode::BadTimeout)
+                            .with_additional_info(format!(
+                                "The node manager did not complete within {} ms",
+                                timeout.as_millis()
+                            ));
+                        vec![Err(err); writes.len()]
+                    }
+                };
+                indices.into_iter().zip(results).collect::<Vec<_>>()
+            });
+        future::join_all(batches)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Returns the range of the value that the caller asked for
+    fn value_range_of(mut value: DataValue, index_range: NumericRange) -> DataValue {
+        if index_range.has_range() {
+            if let Some(v) = value.value.take() {
+                match v.range_of(index_range) {
+                    Ok(v) => value.value = Some(v),
+                    Err(status_code) => value.status = Some(status_code),
                 }
-            } else {
-                warn!("Attribute id {} is invalid", node_to_write.attribute_id);
-                StatusCode::BadAttributeIdInvalid
             }
+        }
+        value
+    }
+
+    /// Removes the timestamps that the caller did not ask for
+    fn filter_timestamps(
+        mut value: DataValue,
+        timestamps_to_return: TimestampsToReturn,
+    ) -> DataValue {
+        match timestamps_to_return {
+            TimestampsToReturn::Source => {
+                value.server_timestamp = None;
+                value.server_picoseconds = None;
+            }
+            TimestampsToReturn::Server => {
+                value.source_timestamp = None;
+                value.source_picoseconds = None;
+            }
+            TimestampsToReturn::Both => {}
+            TimestampsToReturn::Neither | TimestampsToReturn::Invalid => {
+                value.source_timestamp = None;
+                value.source_picoseconds = None;
+                value.server_timestamp = None;
+                value.server_picoseconds = None;
+            }
+        }
+        value
+    }
+}
+
+/// The reads of a request that are made by async getters. Reads are grouped by their getter so
+/// each getter is called once with all of its reads.
+struct AsyncReads {
+    batches: Vec<AsyncReadBatch>,
+}
+
+/// A getter and its reads, each with the index of its result
+type AsyncReadBatch = (Arc<dyn AsyncAttributeGetter>, Vec<(usize, AsyncReadValue)>);
+
+impl AsyncReads {
+    fn new() -> AsyncReads {
+        AsyncReads {
+            batches: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Adds the read of the result at the index
+    fn add(&mut self, getter: Arc<dyn AsyncAttributeGetter>, idx: usize, read: AsyncReadValue) {
+        let batch = self.batches.iter_mut().find(|(g, _)| {
+            std::ptr::eq(
+                Arc::as_ptr(g) as *const u8,
+                Arc::as_ptr(&getter) as *const u8,
+            )
+        });
+        if let Some((_, reads)) = batch {
+            reads.push((idx, read));
         } else {
-            warn!("Cannot find node id {}", node_to_write.node_id);
-            StatusCode::BadNodeIdUnknown
+            self.batches.push((getter, vec![(idx, read)]));
         }
     }
+
+    /// Calls every getter with its reads at the same time. Returns the index, read and value of
+    /// every read.
+    async fn read(
+        self,
+        server_timeout: std::time::Duration,
+        request_header: &RequestHeader,
+    ) -> Vec<(usize, AsyncReadValue, Result<DataValue, DiagnosticError>)> {
+        let batches = self.batches.into_iter().map(|(getter, reads)| async move {
+            let timeout = async_operation_timeout(server_timeout, getter.timeout(), request_header);
+            let read_values = reads
+                .iter()
+                .map(|(_, read)| read.clone())
+                .collect::<Vec<_>>();
+            let values = match tokio::time::timeout(timeout, getter.get_many(&read_values)).await {
+                Ok(values) if values.len() == reads.len() => values
+                    .into_iter()
+                    .map(|value| value.map(|value| value.unwrap_or_default()))
+                    .collect(),
+                Ok(values) => {
+                    error!(
+                        "Async getter returned {} values for {} reads",
+                        values.len(),
+                        reads.len()
+                    );
+                    let err = DiagnosticError::new(StatusCode::BadInternalError)
+                        .with_additional_info(format!(
+                            "The getter returned {} values for {} reads",
+                            values.len(),
+                            reads.len()
+                        ));
+                    vec![Err(err); reads.len()]
+                }
+                Err(_) => {
+                    warn!(
+                        "Async getter did not complete {} reads within {} ms",
+                        reads.len(),
+                        timeout.as_millis()
+                    );
+                    let err =
+                        DiagnosticError::new(StatusCode::BadTimeout).with_additional_info(format!(
+                            "The getter did not complete within {} ms",
+                            timeout.as_millis()
+                        ));
+                    vec![Err(err); reads.len()]
+                }
+            };
+            reads
+                .into_iter()
+                .zip(values)
+                .map(|((idx, read), value)| (idx, read, value))
+                .collect::<Vec<_>>()
+        });
+        future::join_all(batches)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// Returns a data value with the status code and no value
+pub(crate) fn status_data_value(status_code: StatusCode) -> DataValue {
+    DataValue {
+        status: Some(status_code),
+        ..DataValue::null()
+    }
 }
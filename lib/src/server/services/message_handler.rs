@@ -3,6 +3,7 @@
 // Copyright (C) 2017-2022 Adam Lock
 
 use std::sync::Arc;
+use std::time::Instant;
 
 use chrono::Utc;
 
@@ -15,11 +16,12 @@ use crate::types::{status_code::StatusCode, *};
 use crate::server::{
     address_space::AddressSpace,
     comms::tcp_transport::MessageSender,
+    node_manager::RequestContext,
     services::{
-        attribute::AttributeService, discovery::DiscoveryService, method::MethodService,
-        monitored_item::MonitoredItemService, node_management::NodeManagementService,
-        query::QueryService, session::SessionService, subscription::SubscriptionService,
-        view::ViewService,
+        attribute::AttributeService, cancellable_response, discovery::DiscoveryService,
+        method::MethodService, monitored_item::MonitoredItemService,
+        node_management::NodeManagementService, query::QueryService, session::SessionService,
+        subscription::SubscriptionService, view::ViewService, ServiceResponse,
     },
     session::{Session, SessionManager},
     session_diagnostics::*,
@@ -56,6 +58,8 @@ pub(crate) struct MessageHandler {
     subscription_service: SubscriptionService,
     /// View service
     view_service: ViewService,
+    /// Time that handling of the current message started
+    started: Instant,
 }
 
 impl MessageHandler {
@@ -81,6 +85,7 @@ impl MessageHandler {
             session_service: SessionService::new(),
             view_service: ViewService::new(),
             subscription_service: SubscriptionService::new(),
+            started: Instant::now(),
         }
     }
 
@@ -98,6 +103,7 @@ impl MessageHandler {
 
         let server_state = self.server_state.clone();
         let address_space = self.address_space.clone();
+        self.started = Instant::now();
 
         let response = match message {
             // Discovery Service Set, OPC UA Part 4, Section 5.4
@@ -223,11 +229,16 @@ impl MessageHandler {
 
             // View Service Set, OPC UA Part 4, Section 5.8
             SupportedMessage::BrowseRequest(request) => {
-                self.validate_service_request(message, BROWSE_COUNT, |session, _| {
-                    Some(
-                        self.view_service
-                            .browse(server_state, session, address_space, request),
-                    )
+                self.validate_service_request(message, BROWSE_COUNT, |session, session_manager| {
+                    let context = RequestContext::for_session(&session, session_manager);
+                    let response = self.view_service.browse_deferred(
+                        server_state,
+                        session.clone(),
+                        context,
+                        address_space,
+                        request,
+                    );
+                    self.send_deferred(request_id, message, sender, session, BROWSE_COUNT, response)
                 })
             }
             SupportedMessage::BrowseNextRequest(request) => {
@@ -242,12 +253,24 @@ impl MessageHandler {
                 .validate_service_request(
                     message,
                     TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT,
-                    |_, _| {
-                        Some(self.view_service.translate_browse_paths_to_node_ids(
-                            server_state,
-                            address_space,
-                            request,
-                        ))
+                    |session, session_manager| {
+                        let context = RequestContext::for_session(&session, session_manager);
+                        let response = self
+                            .view_service
+                            .translate_browse_paths_to_node_ids_deferred(
+                                server_state,
+                                context,
+                                address_space,
+                                request,
+                            );
+                        self.send_deferred(
+                            request_id,
+                            message,
+                            sender,
+                            session,
+                            TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT,
+                            response,
+                        )
                     },
                 ),
             SupportedMessage::RegisterNodesRequest(request) => {
@@ -292,11 +315,16 @@ impl MessageHandler {
 
             // Attribute Service Set, OPC UA Part 4, Section 5.10
             SupportedMessage::ReadRequest(request) => {
-                self.validate_service_request(message, READ_COUNT, |session, _| {
-                    Some(
-                        self.attribute_service
-                            .read(server_state, session, address_space, request),
-                    )
+                self.validate_service_request(message, READ_COUNT, |session, session_manager| {
+                    let context = RequestContext::for_session(&session, session_manager);
+                    let response = self.attribute_service.read_deferred(
+                        server_state,
+                        session.clone(),
+                        context,
+                        address_space,
+                        request,
+                    );
+                    self.send_deferred(request_id, message, sender, session, READ_COUNT, response)
                 })
             }
             SupportedMessage::HistoryReadRequest(request) => {
@@ -310,13 +338,16 @@ impl MessageHandler {
                 })
             }
             SupportedMessage::WriteRequest(request) => {
-                self.validate_service_request(message, WRITE_COUNT, |session, _| {
-                    Some(self.attribute_service.write(
+                self.validate_service_request(message, WRITE_COUNT, |session, session_manager| {
+                    let context = RequestContext::for_session(&session, session_manager);
+                    let response = self.attribute_service.write_deferred(
                         server_state,
-                        session,
+                        session.clone(),
+                        context,
                         address_space,
                         request,
-                    ))
+                    );
+                    self.send_deferred(request_id, message, sender, session, WRITE_COUNT, response)
                 })
             }
             SupportedMessage::HistoryUpdateRequest(request) => {
@@ -337,13 +368,14 @@ impl MessageHandler {
                         let session = trace_read_lock!(session);
                         session.session_id().clone()
                     };
-                    Some(self.method_service.call(
+                    let response = self.method_service.call_deferred(
                         server_state,
                         &session_id,
                         session_manager,
                         address_space,
                         request,
-                    ))
+                    );
+                    self.send_deferred(request_id, message, sender, session, CALL_COUNT, response)
                 })
             }
 
@@ -388,15 +420,18 @@ impl MessageHandler {
                 }),
 
             // Subscription Service Set, OPC UA Part 4, Section 5.13
-            SupportedMessage::CreateSubscriptionRequest(request) => {
-                self.validate_service_request(message, CREATE_SUBSCRIPTION_COUNT, |session, _| {
+            SupportedMessage::CreateSubscriptionRequest(request) => self.validate_service_request(
+                message,
+                CREATE_SUBSCRIPTION_COUNT,
+                |session, session_manager| {
                     Some(self.subscription_service.create_subscription(
                         server_state,
+                        session_manager,
                         session,
                         request,
                     ))
-                })
-            }
+                },
+            ),
             SupportedMessage::ModifySubscriptionRequest(request) => {
                 self.validate_service_request(message, MODIFY_SUBSCRIPTION_COUNT, |session, _| {
                     Some(self.subscription_service.modify_subscription(
@@ -423,26 +458,36 @@ impl MessageHandler {
                 })
             }
             SupportedMessage::TransferSubscriptionsRequest(request) => self
-                .validate_service_request(message, TRANSFER_SUBSCRIPTIONS_COUNT, |session, _| {
-                    Some(
-                        self.subscription_service
-                            .transfer_subscriptions(session, request),
-                    )
-                }),
+                .validate_service_request(
+                    message,
+                    TRANSFER_SUBSCRIPTIONS_COUNT,
+                    |session, session_manager| {
+                        Some(self.subscription_service.transfer_subscriptions(
+                            server_state,
+                            session,
+                            session_manager,
+                            address_space,
+                            request,
+                        ))
+                    },
+                ),
             SupportedMessage::PublishRequest(request) => {
-                self.validate_service_request(message, "", |session, _| {
-                    // TODO publish request diagnostics have to be done asynchronously too
-
+                self.validate_service_request(message, PUBLISH_COUNT, |session, _| {
                     // Unlike other calls which return immediately, this one is asynchronous - the
                     // request is queued and the response will come back out of sequence some time in
                     // the future.
-                    self.subscription_service.async_publish(
+                    let response = self.subscription_service.async_publish(
                         &Utc::now(),
-                        session,
+                        session.clone(),
                         address_space,
                         request_id,
                         request,
-                    )
+                    );
+                    if response.is_none() {
+                        // The queued request is counted now since a tick produces its response
+                        Self::diag_queued_request(session, PUBLISH_COUNT);
+                    }
+                    response
                 })
             }
             SupportedMessage::RepublishRequest(request) => {
@@ -463,11 +508,58 @@ impl MessageHandler {
 
         if let Some(response) = response {
             let _ = sender.send_message(request_id, response);
+            Self::diag_service_latency(
+                &self.server_state,
+                Self::request_type(message),
+                self.started,
+            );
         }
 
         Ok(())
     }
 
+    /// Returns a response that is ready, or spawns a task that sends a deferred response when it
+    /// completes. The task also records the service diagnostics for the deferred response.
+    ///
+    /// A deferred request is in flight until its response is sent, so the Cancel service may
+    /// cancel it. A cancelled request drops its callbacks and is answered with
+    /// `BadRequestCancelledByClient`.
+    fn send_deferred(
+        &self,
+        request_id: u32,
+        request: &SupportedMessage,
+        sender: &MessageSender,
+        session: Arc<RwLock<Session>>,
+        diagnostic_key: &'static str,
+        response: ServiceResponse,
+    ) -> Option<SupportedMessage> {
+        match response {
+            ServiceResponse::Ready(response) => Some(response),
+            ServiceResponse::Deferred(response) => {
+                let request_header = request.request_header().clone();
+                let token = {
+                    let mut session = trace_write_lock!(session);
+                    session.register_in_flight_request(request_id, request_header.request_handle)
+                };
+                let sender = sender.clone();
+                let server_state = self.server_state.clone();
+                let request_type = Self::request_type(request);
+                let started = self.started;
+                tokio::spawn(async move {
+                    let response = cancellable_response(&request_header, token, response).await;
+                    {
+                        let mut session = trace_write_lock!(session);
+                        session.deregister_in_flight_request(request_id);
+                    }
+                    Self::diag_service_response(session, true, &response, diagnostic_key);
+                    sender.send_message(request_id, response);
+                    Self::diag_service_latency(&server_state, request_type, started);
+                });
+                None
+            }
+        }
+    }
+
     /// Tests if this request should be rejected because of a session timeout
     fn is_session_timed_out(
         session: Arc<RwLock<Session>>,
@@ -604,6 +696,37 @@ impl MessageHandler {
         }
     }
 
+    /// Returns the object id of the type of a request
+    fn request_type(request: &SupportedMessage) -> Option<ObjectId> {
+        if request.is_request() {
+            request.node_id().as_object_id().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Records the time taken to respond to a request in the service latency histograms
+    fn diag_service_latency(
+        server_state: &Arc<RwLock<ServerState>>,
+        request_type: Option<ObjectId>,
+        started: Instant,
+    ) {
+        if let Some(request_type) = request_type {
+            let server_state = trace_read_lock!(server_state);
+            let mut diagnostics = trace_write_lock!(server_state.diagnostics);
+            diagnostics.on_service_latency(request_type, started.elapsed());
+        }
+    }
+
+    /// Increment count of a service call that was queued without a response
+    fn diag_queued_request(session: Arc<RwLock<Session>>, diagnostic_key: &'static str) {
+        let session = trace_read_lock!(session);
+        let session_diagnostics = session.session_diagnostics();
+        let mut session_diagnostics = trace_write_lock!(session_diagnostics);
+        session_diagnostics.request();
+        session_diagnostics.service_success(diagnostic_key);
+    }
+
     /// Increment count of service call in session diagnostics
     fn diag_service_response(
         session: Arc<RwLock<Session>>,
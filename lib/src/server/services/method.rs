@@ -4,12 +4,18 @@
 
 use std::sync::Arc;
 
+use futures::future;
+
 use crate::core::supported_message::SupportedMessage;
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::SessionManager, state::ServerState,
+    address_space::AddressSpace,
+    node_manager::RequestContext,
+    services::{async_operation_timeout, Service, ServiceResponse},
+    session::SessionManager,
+    state::ServerState,
 };
 
 /// The method service. Allows a client to call a method on the server.
@@ -26,6 +32,7 @@ impl MethodService {
         MethodService {}
     }
 
+    #[cfg(test)]
     pub fn call(
         &self,
         server_state: Arc<RwLock<ServerState>>,
@@ -34,62 +41,224 @@ impl MethodService {
         address_space: Arc<RwLock<AddressSpace>>,
         request: &CallRequest,
     ) -> SupportedMessage {
+        self.call_deferred(
+            server_state,
+            session_id,
+            session_manager,
+            address_space,
+            request,
+        )
+        .wait()
+    }
+
+    /// Calls like `call`, except that methods with an async callback, and methods of objects of
+    /// node managers, are called by a deferred response that is awaited without holding the
+    /// address space lock.
+    pub(crate) fn call_deferred(
+        &self,
+        server_state: Arc<RwLock<ServerState>>,
+        session_id: &NodeId,
+        session_manager: Arc<RwLock<SessionManager>>,
+        address_space: Arc<RwLock<AddressSpace>>,
+        request: &CallRequest,
+    ) -> ServiceResponse {
         if let Some(ref calls) = request.methods_to_call {
             let server_state = trace_read_lock!(server_state);
             if calls.len() <= server_state.operational_limits.max_nodes_per_method_call {
-                let mut address_space = trace_write_lock!(address_space);
-
-                let results: Vec<CallMethodResult> = calls
-                    .iter()
-                    .map(|request| {
-                        trace!(
-                            "Calling to {:?} on {:?}",
-                            request.method_id,
-                            request.object_id
-                        );
+                let mut sync_calls = Vec::new();
+                let mut async_calls = Vec::new();
+                let mut managed_calls = Vec::new();
+                let mut results: Vec<Result<CallMethodResult, DiagnosticError>> = {
+                    // The address space is only locked to find the handler of each call, so a slow
+                    // method does not hold up other services
+                    let address_space = trace_read_lock!(address_space);
+                    calls
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, request)| {
+                            trace!(
+                                "Calling to {:?} on {:?}",
+                                request.method_id,
+                                request.object_id
+                            );
 
-                        // Note: Method invocations that modify the address space, write a value, or modify the
-                        // state of the system (acknowledge, batch sequencing or other system changes) must
-                        // generate an AuditUpdateMethodEventType or a subtype of it.
+                            // Note: Method invocations that modify the address space, write a value, or modify the
+                            // state of the system (acknowledge, batch sequencing or other system changes) must
+                            // generate an AuditUpdateMethodEventType or a subtype of it.
 
-                        // Call the method via whatever is registered in the address space
-                        match address_space.call_method(
-                            &server_state,
+                            if let Some(node_manager) =
+                                address_space.node_manager_for(&request.object_id)
+                            {
+                                // The result is filled in when the node manager completes
+                                managed_calls.push((idx, node_manager, request.clone()));
+                                return Ok(Self::call_method_result(StatusCode::Good));
+                            }
+                            if let Some(callback) = address_space.async_method_callback(request) {
+                                // The result is filled in when the callback completes
+                                let request = match address_space.validate_input_arguments(request)
+                                {
+                                    Ok(request) => request,
+                                    Err(result) => return result,
+                                };
+                                async_calls.push((idx, callback, request));
+                                return Ok(Self::call_method_result(StatusCode::Good));
+                            }
+                            // The result is filled in when the callback is called below
+                            let callback = address_space.method_callback(request)?;
+                            let request = match address_space.validate_input_arguments(request) {
+                                Ok(request) => request,
+                                Err(result) => return result,
+                            };
+                            sync_calls.push((idx, callback, request));
+                            Ok(Self::call_method_result(StatusCode::Good))
+                        })
+                        .collect()
+                };
+                // Call the method via whatever is registered in the address space
+                let sync_calls = sync_calls
+                    .into_iter()
+                    .map(|(idx, callback, request)| {
+                        let mut callback = trace_lock!(callback);
+                        let result = callback.call_with_diagnostics(
                             session_id,
                             session_manager.clone(),
-                            request,
-                        ) {
-                            Ok(response) => response,
-                            Err(status_code) => {
-                                // Call didn't work for some reason
-                                error!(
-                                    "Call to {:?} on {:?} failed with status code {}",
-                                    request.method_id, request.object_id, status_code
+                            &request,
+                        );
+                        (idx, request, result)
+                    })
+                    .collect::<Vec<_>>();
+                if !sync_calls.is_empty() {
+                    let address_space = trace_read_lock!(address_space);
+                    for (idx, request, result) in sync_calls {
+                        results[idx] = result.and_then(|result| {
+                            address_space.validate_output_arguments(&request, result)
+                        });
+                    }
+                }
+                for (request, result) in calls.iter().zip(results.iter()) {
+                    if let Err(ref err) = result {
+                        // Call didn't work for some reason
+                        error!(
+                            "Call to {:?} on {:?} failed with {}",
+                            request.method_id, request.object_id, err
+                        );
+                    }
+                }
+                // Produce response
+                if async_calls.is_empty() && managed_calls.is_empty() {
+                    ServiceResponse::Ready(Self::call_response(&request.request_header, results))
+                } else {
+                    let server_timeout = server_state.async_operation_timeout;
+                    let request_header = request.request_header.clone();
+                    let context = RequestContext::new(session_id.clone(), session_manager.clone());
+                    let session_id = session_id.clone();
+                    ServiceResponse::Deferred(Box::pin(async move {
+                        let mut results = results;
+                        let (request_header, session_id, context, address_space) =
+                            (&request_header, &session_id, &context, &address_space);
+                        let calls = async_calls.into_iter().map(|(idx, callback, request)| {
+                            let session_manager = session_manager.clone();
+                            async move {
+                                let timeout = async_operation_timeout(
+                                    server_timeout,
+                                    callback.timeout(),
+                                    request_header,
                                 );
-                                CallMethodResult {
-                                    status_code,
-                                    input_argument_results: None,
-                                    input_argument_diagnostic_infos: None,
-                                    output_arguments: None,
+                                let call = callback.call(session_id, session_manager, &request);
+                                let result = match tokio::time::timeout(timeout, call).await {
+                                    Ok(result) => result.and_then(|result| {
+                                        let address_space = trace_read_lock!(address_space);
+                                        address_space.validate_output_arguments(&request, result)
+                                    }),
+                                    Err(_) => Err(DiagnosticError::new(StatusCode::BadTimeout)
+                                        .with_additional_info(format!(
+                                            "Method {} did not complete within {:?}",
+                                            request.method_id, timeout
+                                        ))),
+                                };
+                                if let Err(ref err) = result {
+                                    error!(
+                                        "Call to {:?} on {:?} failed with {}",
+                                        request.method_id, request.object_id, err
+                                    );
                                 }
+                                (idx, result)
                             }
+                        });
+                        let managed_calls = managed_calls.into_iter().map(
+                            |(idx, node_manager, request)| async move {
+                                let timeout = async_operation_timeout(
+                                    server_timeout,
+                                    node_manager.timeout(),
+                                    request_header,
+                                );
+                                let call = node_manager.call(context, &request);
+                                let result = match tokio::time::timeout(timeout, call).await {
+                                    Ok(result) => result,
+                                    Err(_) => Err(DiagnosticError::new(StatusCode::BadTimeout)
+                                        .with_additional_info(format!(
+                                            "Method {} did not complete within {:?}",
+                                            request.method_id, timeout
+                                        ))),
+                                };
+                                if let Err(ref err) = result {
+                                    error!(
+                                        "Call to {:?} on {:?} failed with {}",
+                                        request.method_id, request.object_id, err
+                                    );
+                                }
+                                (idx, result)
+                            },
+                        );
+                        let (calls, managed_calls) =
+                            future::join(future::join_all(calls), future::join_all(managed_calls))
+                                .await;
+                        for (idx, result) in calls.into_iter().chain(managed_calls) {
+                            results[idx] = result;
                         }
-                    })
-                    .collect();
-                // Produce response
-                let response = CallResponse {
-                    response_header: ResponseHeader::new_good(&request.request_header),
-                    results: Some(results),
-                    diagnostic_infos: None,
-                };
-                response.into()
+                        Self::call_response(request_header, results)
+                    }))
+                }
             } else {
                 error!("Call request, too many calls {}", calls.len());
                 self.service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                    .into()
             }
         } else {
             warn!("Call has nothing to do");
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
+                .into()
+        }
+    }
+
+    /// Produces the call response, with the diagnostic infos of failed calls if the request
+    /// asks for them.
+    fn call_response(
+        request_header: &RequestHeader,
+        results: Vec<Result<CallMethodResult, DiagnosticError>>,
+    ) -> SupportedMessage {
+        let mut diagnostics = DiagnosticInfoBuilder::new(request_header);
+        let diagnostic_infos = diagnostics
+            .operation_diagnostic_infos(results.iter().map(|result| result.as_ref().err()));
+        let response_header = diagnostics.response_header(request_header, &StatusCode::Good.into());
+        let results = results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|err| Self::call_method_result(err.status_code())))
+            .collect();
+        CallResponse {
+            response_header,
+            results: Some(results),
+            diagnostic_infos,
+        }
+        .into()
+    }
+
+    fn call_method_result(status_code: StatusCode) -> CallMethodResult {
+        CallMethodResult {
+            status_code,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: None,
         }
     }
 }
@@ -2,8 +2,26 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
+use std::{future::Future, pin::Pin, time::Duration};
+#[cfg(test)]
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+
 use crate::core::supported_message::SupportedMessage;
-use crate::types::{status_code::StatusCode, RequestHeader, ServiceFault};
+use crate::types::{
+    status_code::StatusCode, DiagnosticError, DiagnosticInfo, DiagnosticInfoBuilder,
+    RequestHeader, ResponseHeader, ServiceFault,
+};
+
+use crate::server::callbacks::with_request_cancellation;
+#[cfg(test)]
+use crate::server::{
+    node_manager::RequestContext,
+    session::{Session, SessionManager},
+};
+#[cfg(test)]
+use crate::sync::*;
 
 pub mod message_handler;
 
@@ -26,6 +44,100 @@ trait Service {
     }
 }
 
+/// A response that completes once the async callbacks of the request complete
+pub(crate) type DeferredResponse = Pin<Box<dyn Future<Output = SupportedMessage> + Send>>;
+
+/// The response of a service that may call async callbacks
+pub(crate) enum ServiceResponse {
+    /// The response is ready to send
+    Ready(SupportedMessage),
+    /// The response must be awaited
+    Deferred(DeferredResponse),
+}
+
+impl From<SupportedMessage> for ServiceResponse {
+    fn from(response: SupportedMessage) -> Self {
+        ServiceResponse::Ready(response)
+    }
+}
+
+impl ServiceResponse {
+    /// Waits for the response. A deferred response is completed on a runtime of its own, so this
+    /// must not be called from async code.
+    #[cfg(test)]
+    pub fn wait(self) -> SupportedMessage {
+        match self {
+            ServiceResponse::Ready(response) => response,
+            ServiceResponse::Deferred(response) => tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap()
+                .block_on(response),
+        }
+    }
+}
+
+/// Makes the context of a request by a session that is not registered with a session manager
+#[cfg(test)]
+pub(crate) fn unregistered_request_context(session: &Arc<RwLock<Session>>) -> RequestContext {
+    RequestContext::for_session(session, Arc::new(RwLock::new(SessionManager::default())))
+}
+
+/// Returns the status code of each operation of a request, their diagnostic infos if the request
+/// asks for them, and the response header with the string table of the diagnostic infos.
+pub(crate) fn operation_results(
+    request_header: &RequestHeader,
+    results: Vec<Result<(), DiagnosticError>>,
+) -> (ResponseHeader, Vec<StatusCode>, Option<Vec<DiagnosticInfo>>) {
+    let mut diagnostics = DiagnosticInfoBuilder::new(request_header);
+    let diagnostic_infos =
+        diagnostics.operation_diagnostic_infos(results.iter().map(|result| result.as_ref().err()));
+    let response_header = diagnostics.response_header(request_header, &StatusCode::Good.into());
+    let results = results
+        .into_iter()
+        .map(|result| match result {
+            Ok(_) => StatusCode::Good,
+            Err(err) => err.status_code(),
+        })
+        .collect();
+    (response_header, results, diagnostic_infos)
+}
+
+/// Awaits a deferred response unless the token is cancelled first, in which case the response and
+/// the callbacks it awaits are dropped and the request fails with `BadRequestCancelledByClient`.
+/// Callbacks observe the token through `request_cancellation_token`.
+pub(crate) async fn cancellable_response(
+    request_header: &RequestHeader,
+    token: CancellationToken,
+    response: DeferredResponse,
+) -> SupportedMessage {
+    tokio::select! {
+        response = with_request_cancellation(token.clone(), response) => response,
+        _ = token.cancelled() => {
+            debug!(
+                "Request with handle {} was cancelled by the client",
+                request_header.request_handle
+            );
+            ServiceFault::new(request_header, StatusCode::BadRequestCancelledByClient).into()
+        }
+    }
+}
+
+/// Returns the time allowed for an async callback, which is its own timeout or else the server's,
+/// shortened to the timeout hint of the request if it has one.
+pub(crate) fn async_operation_timeout(
+    server_timeout: Duration,
+    callback_timeout: Option<Duration>,
+    request_header: &RequestHeader,
+) -> Duration {
+    let timeout = callback_timeout.unwrap_or(server_timeout);
+    if request_header.timeout_hint > 0 {
+        timeout.min(Duration::from_millis(request_header.timeout_hint as u64))
+    } else {
+        timeout
+    }
+}
+
 pub mod attribute;
 pub mod discovery;
 pub mod method;
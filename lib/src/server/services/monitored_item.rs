@@ -9,7 +9,8 @@ use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::Session, state::ServerState,
+    address_space::AddressSpace, redundancy::MirroredMonitoredItem, services::Service,
+    session::Session, state::ServerState,
 };
 
 /// The monitored item service. Allows client to create, modify and delete monitored items on a subscription.
@@ -46,16 +47,34 @@ impl MonitoredItemService {
             if let Some(subscription) = session.subscriptions_mut().get_mut(request.subscription_id)
             {
                 let now = chrono::Utc::now();
-                let results = Some(subscription.create_monitored_items(
+                let results = subscription.create_monitored_items(
                     &server_state,
                     &address_space,
                     &now,
                     request.timestamps_to_return,
                     items_to_create,
-                ));
+                );
+                session
+                    .subscription_mirroring()
+                    .update(request.subscription_id, |subscription| {
+                        results
+                            .iter()
+                            .zip(items_to_create)
+                            .filter(|(result, _)| result.status_code.is_good())
+                            .for_each(|(result, item_to_create)| {
+                                subscription.monitored_items.insert(
+                                    result.monitored_item_id,
+                                    MirroredMonitoredItem {
+                                        monitored_item_id: result.monitored_item_id,
+                                        timestamps_to_return: request.timestamps_to_return,
+                                        request: item_to_create.clone(),
+                                    },
+                                );
+                            });
+                    });
                 let response = CreateMonitoredItemsResponse {
                     response_header: ResponseHeader::new_good(&request.request_header),
-                    results,
+                    results: Some(results),
                     diagnostic_infos: None,
                 };
                 response.into()
@@ -87,15 +106,33 @@ impl MonitoredItemService {
             // Find subscription and modify items in it
             let subscription_id = request.subscription_id;
             if let Some(subscription) = session.subscriptions_mut().get_mut(subscription_id) {
-                let results = Some(subscription.modify_monitored_items(
+                let results = subscription.modify_monitored_items(
                     &server_state,
                     &address_space,
                     request.timestamps_to_return,
                     items_to_modify,
-                ));
+                );
+                session
+                    .subscription_mirroring()
+                    .update(subscription_id, |subscription| {
+                        results
+                            .iter()
+                            .zip(items_to_modify)
+                            .filter(|(result, _)| result.status_code.is_good())
+                            .for_each(|(_, item_to_modify)| {
+                                if let Some(item) = subscription
+                                    .monitored_items
+                                    .get_mut(&item_to_modify.monitored_item_id)
+                                {
+                                    item.timestamps_to_return = request.timestamps_to_return;
+                                    item.request.requested_parameters =
+                                        item_to_modify.requested_parameters.clone();
+                                }
+                            });
+                    });
                 ModifyMonitoredItemsResponse {
                     response_header: ResponseHeader::new_good(&request.request_header),
-                    results,
+                    results: Some(results),
                     diagnostic_infos: None,
                 }
                 .into()
@@ -126,7 +163,22 @@ impl MonitoredItemService {
                 let results = monitored_item_ids
                     .iter()
                     .map(|i| subscription.set_monitoring_mode(*i, monitoring_mode))
-                    .collect();
+                    .collect::<Vec<_>>();
+                session
+                    .subscription_mirroring()
+                    .update(subscription_id, |subscription| {
+                        results
+                            .iter()
+                            .zip(monitored_item_ids)
+                            .filter(|(result, _)| result.is_good())
+                            .for_each(|(_, monitored_item_id)| {
+                                if let Some(item) =
+                                    subscription.monitored_items.get_mut(monitored_item_id)
+                                {
+                                    item.request.monitoring_mode = monitoring_mode;
+                                }
+                            });
+                    });
                 SetMonitoringModeResponse {
                     response_header: ResponseHeader::new_good(&request.request_header),
                     results: Some(results),
@@ -214,11 +266,22 @@ impl MonitoredItemService {
             // Find subscription and delete items from it
             let subscription_id = request.subscription_id;
             if let Some(subscription) = session.subscriptions_mut().get_mut(subscription_id) {
-                let results = Some(subscription.delete_monitored_items(monitored_item_ids));
+                let results = subscription.delete_monitored_items(monitored_item_ids);
+                session
+                    .subscription_mirroring()
+                    .update(subscription_id, |subscription| {
+                        results
+                            .iter()
+                            .zip(monitored_item_ids)
+                            .filter(|(result, _)| result.is_good())
+                            .for_each(|(_, monitored_item_id)| {
+                                subscription.monitored_items.remove(monitored_item_id);
+                            });
+                    });
                 let diagnostic_infos = None;
                 let response = DeleteMonitoredItemsResponse {
                     response_header: ResponseHeader::new_good(&request.request_header),
-                    results,
+                    results: Some(results),
                     diagnostic_infos,
                 };
                 response.into()
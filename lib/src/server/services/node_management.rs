@@ -6,10 +6,15 @@ use std::{result::Result, sync::Arc};
 
 use crate::core::supported_message::SupportedMessage;
 use crate::sync::*;
-use crate::types::{node_ids::ObjectId, status_code::StatusCode, *};
+use crate::types::{
+    node_ids::{ObjectId, ReferenceTypeId},
+    status_code::StatusCode,
+    *,
+};
 
 use crate::server::{
-    address_space::{relative_path, types::*, AddressSpace},
+    address_space::{instantiate, relative_path, types::*, AddressSpace},
+    journal::JournalEntry,
     services::Service,
     session::Session,
     state::ServerState,
@@ -49,7 +54,7 @@ impl NodeManagementService {
                     let mut address_space = trace_write_lock!(address_space);
 
                     let decoding_options = server_state.decoding_options();
-                    let results = nodes_to_add
+                    let results: Vec<AddNodesResult> = nodes_to_add
                         .iter()
                         .map(|node_to_add| {
                             let (status_code, added_node_id) = Self::add_node(
@@ -58,12 +63,24 @@ impl NodeManagementService {
                                 node_to_add,
                                 &decoding_options,
                             );
+                            if status_code.is_good() {
+                                // The journal records the assigned node id so a replay recreates
+                                // the node with the same id
+                                server_state.journal_entry(|| {
+                                    let mut node_to_add = node_to_add.clone();
+                                    node_to_add.requested_new_node_id =
+                                        added_node_id.clone().into();
+                                    JournalEntry::AddNode(node_to_add)
+                                });
+                            }
                             AddNodesResult {
                                 status_code,
                                 added_node_id,
                             }
                         })
                         .collect();
+                    drop(address_space);
+                    server_state.sync_journal();
                     let response = AddNodesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
                         results: Some(results),
@@ -100,10 +117,19 @@ impl NodeManagementService {
                 {
                     let session = trace_read_lock!(session);
                     let mut address_space = trace_write_lock!(address_space);
-                    let results = references_to_add
+                    let results: Vec<StatusCode> = references_to_add
                         .iter()
-                        .map(|r| Self::add_reference(&session, &mut address_space, r))
+                        .map(|r| {
+                            let status_code = Self::add_reference(&session, &mut address_space, r);
+                            if status_code.is_good() {
+                                server_state
+                                    .journal_entry(|| JournalEntry::AddReference(r.clone()));
+                            }
+                            status_code
+                        })
                         .collect();
+                    drop(address_space);
+                    server_state.sync_journal();
                     AddReferencesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
                         results: Some(results),
@@ -140,12 +166,21 @@ impl NodeManagementService {
                 {
                     let session = trace_read_lock!(session);
                     let mut address_space = trace_write_lock!(address_space);
-                    let results = nodes_to_delete
+                    let results: Vec<StatusCode> = nodes_to_delete
                         .iter()
                         .map(|node_to_delete| {
-                            Self::delete_node(&session, &mut address_space, node_to_delete)
+                            let status_code =
+                                Self::delete_node(&session, &mut address_space, node_to_delete);
+                            if status_code.is_good() {
+                                server_state.journal_entry(|| {
+                                    JournalEntry::DeleteNode(node_to_delete.clone())
+                                });
+                            }
+                            status_code
                         })
                         .collect();
+                    drop(address_space);
+                    server_state.sync_journal();
                     let response = DeleteNodesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
                         results: Some(results),
@@ -182,10 +217,20 @@ impl NodeManagementService {
                 {
                     let session = trace_read_lock!(session);
                     let mut address_space = trace_write_lock!(address_space);
-                    let results = references_to_delete
+                    let results: Vec<StatusCode> = references_to_delete
                         .iter()
-                        .map(|r| Self::delete_reference(&session, &mut address_space, r))
+                        .map(|r| {
+                            let status_code =
+                                Self::delete_reference(&session, &mut address_space, r);
+                            if status_code.is_good() {
+                                server_state
+                                    .journal_entry(|| JournalEntry::DeleteReference(r.clone()));
+                            }
+                            status_code
+                        })
                         .collect();
+                    drop(address_space);
+                    server_state.sync_journal();
                     DeleteReferencesResponse {
                         response_header: ResponseHeader::new_good(&request.request_header),
                         results: Some(results),
@@ -305,9 +350,19 @@ impl NodeManagementService {
     ) -> (StatusCode, NodeId) {
         if !session.can_modify_address_space() {
             // No permission to modify address space
-            return (StatusCode::BadUserAccessDenied, NodeId::null());
+            (StatusCode::BadUserAccessDenied, NodeId::null())
+        } else {
+            Self::apply_add_node(address_space, item, decoding_options)
         }
+    }
 
+    /// Adds the node to the address space without checking if the session is allowed to. Nodes
+    /// added this way are marked persistent.
+    pub(crate) fn apply_add_node(
+        address_space: &mut AddressSpace,
+        item: &AddNodesItem,
+        decoding_options: &DecodingOptions,
+    ) -> (StatusCode, NodeId) {
         let requested_new_node_id = &item.requested_new_node_id;
         if requested_new_node_id.server_index != 0 {
             // Server index is supposed to be 0
@@ -337,7 +392,7 @@ impl NodeManagementService {
         // Test duplicate browse name to same parent
         let browse_name = if item.browse_name.namespace_index != 0 {
             format!(
-                "{}:{}",
+                "/{}:{}",
                 item.browse_name.namespace_index,
                 item.browse_name.name.as_ref()
             )
@@ -366,9 +421,6 @@ impl NodeManagementService {
                 requested_new_node_id.node_id.clone()
             };
 
-            // TODO test data model constraint
-            // BadReferenceNotAllowed
-
             // Check the type definition is valid
             if !address_space
                 .is_valid_type_definition(item.node_class, &item.type_definition.node_id)
@@ -379,13 +431,23 @@ impl NodeManagementService {
             }
 
             // Check that the parent node exists
-            if !item.parent_node_id.server_index == 0
-                || !address_space.node_exists(&item.parent_node_id.node_id)
+            let parent_node_class = match address_space.find_node(&item.parent_node_id.node_id) {
+                Some(parent) if item.parent_node_id.server_index == 0 => parent.node_class(),
+                _ => {
+                    error!(
+                        "node cannot be created because parent node id is invalid or does not exist"
+                    );
+                    return (StatusCode::BadParentNodeIdInvalid, NodeId::null());
+                }
+            };
+
+            // Test the data model constraints of the reference from the parent and of the
+            // type definition of the parent
+            let reference_type_id: NodeId = reference_type_id.into();
+            if let Err(status_code) =
+                Self::validate_child(address_space, item, &reference_type_id, parent_node_class)
             {
-                error!(
-                    "node cannot be created because parent node id is invalid or does not exist"
-                );
-                return (StatusCode::BadParentNodeIdInvalid, NodeId::null());
+                return (status_code, NodeId::null());
             }
 
             // Create a node
@@ -402,12 +464,31 @@ impl NodeManagementService {
                     Some(&[(
                         &item.parent_node_id.node_id,
                         &reference_type_id,
-                        ReferenceDirection::Forward,
+                        ReferenceDirection::Inverse,
                     )]),
                 );
-                // Object / Variable types must add a reference to the type
+                address_space.set_persistent(&new_node_id, true);
+                // Object / Variable types must add a reference to the type, and create the
+                // mandatory children declared by the type
                 if item.node_class == NodeClass::Object || item.node_class == NodeClass::Variable {
-                    address_space.set_node_type(&new_node_id, item.type_definition.node_id.clone());
+                    let type_definition = &item.type_definition.node_id;
+                    address_space.set_node_type(&new_node_id, type_definition.clone());
+                    match instantiate::instantiate_mandatory(
+                        address_space,
+                        &new_node_id,
+                        type_definition,
+                    ) {
+                        Ok(node_ids) => node_ids
+                            .iter()
+                            .for_each(|node_id| address_space.set_persistent(node_id, true)),
+                        Err(status_code) => {
+                            error!(
+                                "node cannot be created because its type definition cannot be instantiated"
+                            );
+                            let _ = address_space.delete(&new_node_id, true);
+                            return (status_code, NodeId::null());
+                        }
+                    }
                 }
                 (StatusCode::Good, new_node_id)
             } else {
@@ -429,7 +510,17 @@ impl NodeManagementService {
         if !session.can_modify_address_space() {
             // No permission to modify address space
             StatusCode::BadUserAccessDenied
-        } else if !item.target_server_uri.is_null() {
+        } else {
+            Self::apply_add_reference(address_space, item)
+        }
+    }
+
+    /// Adds the reference without checking if the session is allowed to.
+    pub(crate) fn apply_add_reference(
+        address_space: &mut AddressSpace,
+        item: &AddReferencesItem,
+    ) -> StatusCode {
+        if !item.target_server_uri.is_null() {
             StatusCode::BadServerUriInvalid
         } else if item.target_node_id.server_index != 0 {
             StatusCode::BadReferenceLocalOnly
@@ -452,8 +543,28 @@ impl NodeManagementService {
                     &item.target_node_id.node_id,
                     reference_type_id,
                 ) {
-                    // TODO test data model constraint
-                    // BadReferenceNotAllowed
+                    let (source_node_id, target_node_id) = if item.is_forward {
+                        (&item.source_node_id, &item.target_node_id.node_id)
+                    } else {
+                        (&item.target_node_id.node_id, &item.source_node_id)
+                    };
+                    let source_node_class = address_space
+                        .find_node(source_node_id)
+                        .unwrap()
+                        .node_class();
+                    let target_node_class = address_space
+                        .find_node(target_node_id)
+                        .unwrap()
+                        .node_class();
+                    if let Err(status_code) = Self::validate_reference(
+                        address_space,
+                        &reference_type_id.into(),
+                        source_node_id,
+                        source_node_class,
+                        target_node_class,
+                    ) {
+                        return status_code;
+                    }
                     if item.is_forward {
                         address_space.insert_reference(
                             &item.source_node_id,
@@ -479,6 +590,158 @@ impl NodeManagementService {
         }
     }
 
+    /// Tests the reference from the parent to a new node and, if the parent has a type definition,
+    /// that the new node matches any InstanceDeclaration of the same browse name.
+    fn validate_child(
+        address_space: &AddressSpace,
+        item: &AddNodesItem,
+        reference_type_id: &NodeId,
+        parent_node_class: NodeClass,
+    ) -> Result<(), StatusCode> {
+        let parent_node_id = &item.parent_node_id.node_id;
+        if !address_space.is_subtype(
+            reference_type_id,
+            &ReferenceTypeId::HierarchicalReferences.into(),
+        ) {
+            error!(
+                "node cannot be created because the reference from the parent is not hierarchical"
+            );
+            return Err(StatusCode::BadReferenceNotAllowed);
+        }
+        Self::validate_reference(
+            address_space,
+            reference_type_id,
+            parent_node_id,
+            parent_node_class,
+            item.node_class,
+        )?;
+
+        let parent_type_id = address_space
+            .find_references(
+                parent_node_id,
+                Some((ReferenceTypeId::HasTypeDefinition, false)),
+            )
+            .and_then(|references| references.first().map(|r| r.target_node.clone()));
+        if let Some(parent_type_id) = parent_type_id {
+            if let Some(declaration) = instantiate::find_instance_declaration(
+                address_space,
+                &parent_type_id,
+                &item.browse_name,
+            ) {
+                if declaration.node_class != item.node_class {
+                    error!("node cannot be created because its node class does not match the instance declaration");
+                    return Err(StatusCode::BadNodeClassInvalid);
+                }
+                if !address_space.is_subtype(reference_type_id, &declaration.reference_type_id) {
+                    error!("node cannot be created because its reference type does not match the instance declaration");
+                    return Err(StatusCode::BadReferenceNotAllowed);
+                }
+                if let Some(ref type_definition) = declaration.type_definition {
+                    if !address_space.is_subtype(&item.type_definition.node_id, type_definition) {
+                        error!("node cannot be created because its type definition does not match the instance declaration");
+                        return Err(StatusCode::BadTypeDefinitionInvalid);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Tests a reference against the data model constraints of its reference type, i.e. which
+    /// node classes may be the source and target of the reference. Abstract reference types
+    /// cannot be used.
+    fn validate_reference(
+        address_space: &AddressSpace,
+        reference_type_id: &NodeId,
+        source_node_id: &NodeId,
+        source_node_class: NodeClass,
+        target_node_class: NodeClass,
+    ) -> Result<(), StatusCode> {
+        match address_space.find_node(reference_type_id) {
+            Some(NodeType::ReferenceType(reference_type)) => {
+                if reference_type.is_abstract() {
+                    error!("reference type {} is abstract", reference_type_id);
+                    return Err(StatusCode::BadReferenceNotAllowed);
+                }
+            }
+            _ => {
+                error!("reference type {} does not exist", reference_type_id);
+                return Err(StatusCode::BadReferenceTypeIdInvalid);
+            }
+        }
+
+        let is_a = |base_type_id: ReferenceTypeId| {
+            address_space.is_subtype(reference_type_id, &base_type_id.into())
+        };
+        let allowed = if is_a(ReferenceTypeId::HasSubtype) {
+            source_node_class == target_node_class
+                && matches!(
+                    source_node_class,
+                    NodeClass::ObjectType
+                        | NodeClass::VariableType
+                        | NodeClass::ReferenceType
+                        | NodeClass::DataType
+                )
+        } else if is_a(ReferenceTypeId::HasProperty) {
+            // Properties cannot have properties
+            target_node_class == NodeClass::Variable
+                && !(source_node_class == NodeClass::Variable
+                    && Self::is_property(address_space, source_node_id))
+        } else if is_a(ReferenceTypeId::HasComponent) {
+            match source_node_class {
+                NodeClass::Variable | NodeClass::VariableType => {
+                    target_node_class == NodeClass::Variable
+                }
+                NodeClass::Object
+                | NodeClass::ObjectType
+                | NodeClass::DataType
+                | NodeClass::View => {
+                    matches!(
+                        target_node_class,
+                        NodeClass::Object | NodeClass::Variable | NodeClass::Method
+                    )
+                }
+                _ => false,
+            }
+        } else if is_a(ReferenceTypeId::Organizes) {
+            matches!(source_node_class, NodeClass::Object | NodeClass::View)
+        } else if is_a(ReferenceTypeId::HasEventSource) {
+            matches!(
+                source_node_class,
+                NodeClass::Object | NodeClass::ObjectType | NodeClass::View
+            ) && matches!(
+                target_node_class,
+                NodeClass::Object | NodeClass::ObjectType | NodeClass::View
+            )
+        } else if is_a(ReferenceTypeId::HasTypeDefinition) {
+            (source_node_class == NodeClass::Object && target_node_class == NodeClass::ObjectType)
+                || (source_node_class == NodeClass::Variable
+                    && target_node_class == NodeClass::VariableType)
+        } else if is_a(ReferenceTypeId::HasModellingRule) {
+            target_node_class == NodeClass::Object
+        } else if is_a(ReferenceTypeId::HasEncoding) {
+            source_node_class == NodeClass::DataType && target_node_class == NodeClass::Object
+        } else {
+            true
+        };
+        if allowed {
+            Ok(())
+        } else {
+            error!(
+                "reference {} from {:?} to {:?} is not allowed by the data model",
+                reference_type_id, source_node_class, target_node_class
+            );
+            Err(StatusCode::BadReferenceNotAllowed)
+        }
+    }
+
+    /// Tests if the node is the target of a HasProperty reference
+    fn is_property(address_space: &AddressSpace, node_id: &NodeId) -> bool {
+        address_space
+            .find_inverse_references(node_id, Some((ReferenceTypeId::HasProperty, true)))
+            .is_some()
+    }
+
     fn delete_node(
         session: &Session,
         address_space: &mut AddressSpace,
@@ -487,7 +750,17 @@ impl NodeManagementService {
         if !session.can_modify_address_space() {
             // No permission to modify address space
             StatusCode::BadUserAccessDenied
-        } else if address_space.delete(&item.node_id, item.delete_target_references) {
+        } else {
+            Self::apply_delete_node(address_space, item)
+        }
+    }
+
+    /// Deletes the node without checking if the session is allowed to.
+    pub(crate) fn apply_delete_node(
+        address_space: &mut AddressSpace,
+        item: &DeleteNodesItem,
+    ) -> StatusCode {
+        if address_space.delete(&item.node_id, item.delete_target_references) {
             StatusCode::Good
         } else {
             error!("node cannot be deleted");
@@ -500,13 +773,23 @@ impl NodeManagementService {
         address_space: &mut AddressSpace,
         item: &DeleteReferencesItem,
     ) -> StatusCode {
-        let node_id = &item.source_node_id;
-        let target_node_id = &item.target_node_id.node_id;
-
         if !session.can_modify_address_space() {
             // No permission to modify address space
             StatusCode::BadUserAccessDenied
-        } else if item.target_node_id.server_index != 0 {
+        } else {
+            Self::apply_delete_reference(address_space, item)
+        }
+    }
+
+    /// Deletes the reference without checking if the session is allowed to.
+    pub(crate) fn apply_delete_reference(
+        address_space: &mut AddressSpace,
+        item: &DeleteReferencesItem,
+    ) -> StatusCode {
+        let node_id = &item.source_node_id;
+        let target_node_id = &item.target_node_id.node_id;
+
+        if item.target_node_id.server_index != 0 {
             error!("reference cannot be added because only local references are supported");
             StatusCode::BadReferenceLocalOnly
         } else if node_id.is_null() || !address_space.node_exists(node_id) {
@@ -16,6 +16,7 @@ use crate::server::{
     identity_token::IdentityToken,
     services::{audit, Service},
     session::{Session, SessionManager},
+    session_diagnostics::SessionDiagnostics,
     state::ServerState,
 };
 
@@ -165,6 +166,7 @@ impl SessionService {
                 session.set_endpoint_url(request.endpoint_url.clone());
                 session.set_security_policy_uri(security_policy.to_uri());
                 session.set_user_identity(IdentityToken::None);
+                session.set_authenticated_user(None);
                 session.set_client_certificate(client_certificate);
                 session.set_session_nonce(server_nonce.clone());
                 session.set_session_name(request.session_name.clone());
@@ -179,6 +181,17 @@ impl SessionService {
                     request,
                 );
 
+                {
+                    let session_diagnostics = session.session_diagnostics();
+                    let mut session_diagnostics = trace_write_lock!(session_diagnostics);
+                    session_diagnostics.set_session_info(
+                        &session,
+                        &request.client_description,
+                        &request.server_uri,
+                        secure_channel.security_mode(),
+                    );
+                }
+
                 // Create a session id in the address space
                 session.register_session(address_space);
 
@@ -248,8 +261,9 @@ impl SessionService {
             StatusCode::Good
         };
 
+        let mut authenticated_user = None;
         if service_result.is_good() {
-            if let Err(err) = server_state.authenticate_endpoint(
+            match server_state.authenticate_user(
                 request,
                 endpoint_url,
                 security_policy,
@@ -257,8 +271,11 @@ impl SessionService {
                 &request.user_identity_token,
                 session.session_nonce(),
             ) {
-                error!("activate_session, invalid endpoint");
-                service_result = err;
+                Ok(user) => authenticated_user = Some(user),
+                Err(err) => {
+                    error!("activate_session, invalid endpoint");
+                    service_result = err;
+                }
             }
         }
 
@@ -296,7 +313,13 @@ impl SessionService {
                 &request.user_identity_token,
                 &server_state.decoding_options(),
             ));
+            session.set_authenticated_user(authenticated_user);
             session.set_locale_ids(request.locale_ids.clone());
+            {
+                let session_diagnostics = session.session_diagnostics();
+                let mut session_diagnostics = trace_write_lock!(session_diagnostics);
+                session_diagnostics.set_activated(&session);
+            }
 
             let diagnostic_infos = None;
 
@@ -367,10 +390,40 @@ impl SessionService {
                     );
                 }
 
+                // Subscriptions that are deleted with the session are no longer mirrored
+                if request.delete_subscriptions {
+                    let subscription_mirroring = session.subscription_mirroring();
+                    session
+                        .subscriptions()
+                        .subscriptions()
+                        .keys()
+                        .for_each(|subscription_id| {
+                            subscription_mirroring.delete(*subscription_id)
+                        });
+                }
+
                 session.set_authentication_token(NodeId::null());
                 session.set_user_identity(IdentityToken::None);
+                session.set_authenticated_user(None);
                 session.set_activated(false);
-                audit::log_close_session(&server_state, &session, address_space, true, request);
+                audit::log_close_session(
+                    &server_state,
+                    &session,
+                    address_space.clone(),
+                    true,
+                    request,
+                );
+            }
+
+            // Remove the session's diagnostics from the address space without holding the
+            // session lock
+            let session_id = {
+                let session = trace_read_lock!(session);
+                session.session_id().clone()
+            };
+            {
+                let mut address_space = trace_write_lock!(address_space);
+                SessionDiagnostics::deregister_session(&session_id, &mut address_space);
             }
 
             {
@@ -391,16 +444,26 @@ impl SessionService {
         }
     }
 
+    /// Cancels the outstanding requests of the session that have the request handle of the
+    /// Cancel request. Only requests that are awaiting async callbacks are outstanding, since all
+    /// other requests are answered before the next request is processed.
     pub fn cancel(
         &self,
         _server_state: Arc<RwLock<ServerState>>,
-        _session: Arc<RwLock<Session>>,
+        session: Arc<RwLock<Session>>,
         request: &CancelRequest,
     ) -> SupportedMessage {
-        // This service call currently does nothing
+        let cancel_count = {
+            let mut session = trace_write_lock!(session);
+            session.cancel_requests(request.request_handle)
+        };
+        debug!(
+            "Cancel request for handle {} cancelled {} requests",
+            request.request_handle, cancel_count
+        );
         CancelResponse {
             response_header: ResponseHeader::new_good(&request.request_header),
-            cancel_count: 0,
+            cancel_count,
         }
         .into()
     }
@@ -2,14 +2,18 @@
 // SPDX-License-Identifier: MPL-2.0
 // Copyright (C) 2017-2022 Adam Lock
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 
 use crate::core::supported_message::SupportedMessage;
 use crate::sync::*;
 use crate::types::{status_code::StatusCode, *};
 
 use crate::server::{
-    address_space::AddressSpace, services::Service, session::Session, state::ServerState,
+    address_space::AddressSpace,
+    redundancy::{MirroredSubscription, SubscriptionMirror},
+    services::Service,
+    session::{Session, SessionManager},
+    state::ServerState,
     subscriptions::subscription::Subscription,
 };
 
@@ -32,10 +36,22 @@ impl SubscriptionService {
     pub fn create_subscription(
         &self,
         server_state: Arc<RwLock<ServerState>>,
+        session_manager: Arc<RwLock<SessionManager>>,
         session: Arc<RwLock<Session>>,
         request: &CreateSubscriptionRequest,
     ) -> SupportedMessage {
         let mut server_state = trace_write_lock!(server_state);
+        // Subscription ids are unique across the sessions, so the id is checked against the
+        // subscriptions of every session before the session is locked for the new one
+        let subscription_id = {
+            let session_manager = trace_read_lock!(session_manager);
+            server_state.create_subscription_id(|subscription_id| {
+                session_manager.sessions.values().any(|session| {
+                    let session = trace_read_lock!(session);
+                    session.subscriptions().contains(subscription_id)
+                })
+            })
+        };
         let mut session = trace_write_lock!(session);
 
         let subscriptions = session.subscriptions_mut();
@@ -45,8 +61,6 @@ impl SubscriptionService {
         {
             self.service_fault(&request.request_header, StatusCode::BadTooManySubscriptions)
         } else {
-            let subscription_id = server_state.create_subscription_id();
-
             // Check the requested publishing interval and keep alive values
             let (revised_publishing_interval, revised_max_keep_alive_count, revised_lifetime_count) =
                 Self::revise_subscription_values(
@@ -58,7 +72,7 @@ impl SubscriptionService {
 
             // Create a new subscription
             let publishing_enabled = request.publishing_enabled;
-            let subscription = Subscription::new(
+            let mut subscription = Subscription::new(
                 server_state.diagnostics.clone(),
                 subscription_id,
                 publishing_enabled,
@@ -67,8 +81,25 @@ impl SubscriptionService {
                 revised_max_keep_alive_count,
                 request.priority,
             );
+            subscription.set_max_notifications_per_publish(request.max_notifications_per_publish);
             subscriptions.insert(subscription_id, subscription);
 
+            // Mirror the subscription to the peer of a redundant server
+            let subscription_mirroring = session.subscription_mirroring();
+            if subscription_mirroring.is_mirroring() {
+                subscription_mirroring.insert(MirroredSubscription {
+                    subscription_id,
+                    client_user_id: session.client_user_id(),
+                    publishing_interval: revised_publishing_interval,
+                    lifetime_count: revised_lifetime_count,
+                    max_keep_alive_count: revised_max_keep_alive_count,
+                    max_notifications_per_publish: request.max_notifications_per_publish,
+                    priority: request.priority,
+                    publishing_enabled,
+                    monitored_items: BTreeMap::new(),
+                });
+            }
+
             // Create the response
             CreateSubscriptionResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
@@ -116,7 +147,18 @@ impl SubscriptionService {
             subscription.set_priority(request.priority);
             subscription.reset_lifetime_counter();
             subscription.reset_keep_alive_counter();
-            // ...max_notifications_per_publish??
+            subscription.set_max_notifications_per_publish(request.max_notifications_per_publish);
+            subscription.on_modify();
+            session
+                .subscription_mirroring()
+                .update(subscription_id, |subscription| {
+                    subscription.publishing_interval = revised_publishing_interval;
+                    subscription.lifetime_count = revised_lifetime_count;
+                    subscription.max_keep_alive_count = revised_max_keep_alive_count;
+                    subscription.max_notifications_per_publish =
+                        request.max_notifications_per_publish;
+                    subscription.priority = request.priority;
+                });
 
             ModifySubscriptionResponse {
                 response_header: ResponseHeader::new_good(&request.request_header),
@@ -138,6 +180,7 @@ impl SubscriptionService {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
             let mut session = trace_write_lock!(session);
+            let subscription_mirroring = session.subscription_mirroring();
             let subscription_ids = request.subscription_ids.as_ref().unwrap();
             let results = {
                 let publishing_enabled = request.publishing_enabled;
@@ -146,7 +189,11 @@ impl SubscriptionService {
                 for subscription_id in subscription_ids {
                     if let Some(subscription) = subscriptions.get_mut(*subscription_id) {
                         subscription.set_publishing_enabled(publishing_enabled);
+                        subscription.on_set_publishing_mode(publishing_enabled);
                         subscription.reset_lifetime_counter();
+                        subscription_mirroring.update(*subscription_id, |subscription| {
+                            subscription.publishing_enabled = publishing_enabled
+                        });
                         results.push(StatusCode::Good);
                     } else {
                         results.push(StatusCode::BadSubscriptionIdInvalid);
@@ -164,24 +211,59 @@ impl SubscriptionService {
         }
     }
 
-    /// Handles a TransferSubscriptionsRequest
+    /// Handles a TransferSubscriptionsRequest. Subscriptions that the peer of a redundant server
+    /// mirrored to this server are transferred to the session, if it has the same user as the
+    /// session that created them.
     pub fn transfer_subscriptions(
         &self,
-        _session: Arc<RwLock<Session>>,
+        server_state: Arc<RwLock<ServerState>>,
+        session: Arc<RwLock<Session>>,
+        session_manager: Arc<RwLock<SessionManager>>,
+        address_space: Arc<RwLock<AddressSpace>>,
         request: &TransferSubscriptionsRequest,
     ) -> SupportedMessage {
         if is_empty_option_vec!(request.subscription_ids) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
+            let server_state = trace_read_lock!(server_state);
             let subscription_ids = request.subscription_ids.as_ref().unwrap();
+            // TODO subscriptions belonging to other sessions of this server should be transferred
+            //  across to this one too.
+            let in_use = {
+                let session_manager = trace_read_lock!(session_manager);
+                subscription_ids
+                    .iter()
+                    .map(|subscription_id| {
+                        session_manager.sessions.values().any(|session| {
+                            let session = trace_read_lock!(session);
+                            session.subscriptions().contains(*subscription_id)
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let mut session = trace_write_lock!(session);
+            let address_space = trace_read_lock!(address_space);
+            let now = chrono::Utc::now();
             let results = {
-                // TODO this is a stub. The real thing should look up subscriptions belonging to
-                //  other sessions and transfer them across to this one.
                 let results = subscription_ids
                     .iter()
-                    .map(|_subscription_id| TransferResult {
-                        status_code: StatusCode::BadSubscriptionIdInvalid,
-                        available_sequence_numbers: None,
+                    .zip(in_use)
+                    .map(|(subscription_id, in_use)| {
+                        let status_code = if in_use {
+                            StatusCode::BadSubscriptionIdInvalid
+                        } else {
+                            Self::transfer_mirrored_subscription(
+                                &server_state,
+                                &mut session,
+                                &address_space,
+                                &now,
+                                *subscription_id,
+                            )
+                        };
+                        TransferResult {
+                            status_code,
+                            available_sequence_numbers: None,
+                        }
                     })
                     .collect::<Vec<TransferResult>>();
                 Some(results)
@@ -196,6 +278,90 @@ impl SubscriptionService {
         }
     }
 
+    /// Transfers a subscription that the peer of a redundant server mirrored to this server. The
+    /// subscription and its monitored items keep their ids, and the monitored items report their
+    /// values as if they were new.
+    fn transfer_mirrored_subscription(
+        server_state: &ServerState,
+        session: &mut Session,
+        address_space: &AddressSpace,
+        now: &DateTimeUtc,
+        subscription_id: u32,
+    ) -> StatusCode {
+        let mirrored_subscriptions = server_state.mirrored_subscriptions();
+        let mut mirrored_subscription = match mirrored_subscriptions.take(subscription_id) {
+            Some(mirrored_subscription) => mirrored_subscription,
+            None => return StatusCode::BadSubscriptionIdInvalid,
+        };
+        let status_code = if mirrored_subscription.client_user_id != session.client_user_id() {
+            StatusCode::BadUserAccessDenied
+        } else if server_state.max_subscriptions > 0
+            && session.subscriptions().len() >= server_state.max_subscriptions
+        {
+            StatusCode::BadTooManySubscriptions
+        } else {
+            StatusCode::Good
+        };
+        if status_code.is_bad() {
+            // The subscription stays for a session that may transfer it
+            mirrored_subscriptions.update_subscription(&mirrored_subscription);
+            return status_code;
+        }
+
+        let (revised_publishing_interval, revised_max_keep_alive_count, revised_lifetime_count) =
+            Self::revise_subscription_values(
+                server_state,
+                mirrored_subscription.publishing_interval,
+                mirrored_subscription.max_keep_alive_count,
+                mirrored_subscription.lifetime_count,
+            );
+        let mut subscription = Subscription::new(
+            server_state.diagnostics.clone(),
+            subscription_id,
+            mirrored_subscription.publishing_enabled,
+            revised_publishing_interval,
+            revised_lifetime_count,
+            revised_max_keep_alive_count,
+            mirrored_subscription.priority,
+        );
+        subscription
+            .set_max_notifications_per_publish(mirrored_subscription.max_notifications_per_publish);
+        let items_to_create = mirrored_subscription
+            .monitored_items
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        let results = subscription.create_mirrored_monitored_items(
+            server_state,
+            address_space,
+            now,
+            &items_to_create,
+        );
+        for (result, item) in results.iter().zip(&items_to_create) {
+            if result.status_code.is_bad() {
+                warn!(
+                    "Monitored item {} of transferred subscription {} could not be created, error = {}",
+                    item.monitored_item_id, subscription_id, result.status_code
+                );
+                mirrored_subscription
+                    .monitored_items
+                    .remove(&item.monitored_item_id);
+            }
+        }
+        session
+            .subscriptions_mut()
+            .insert(subscription_id, subscription);
+
+        // The subscription is mirrored from this server from now on
+        mirrored_subscription.publishing_interval = revised_publishing_interval;
+        mirrored_subscription.lifetime_count = revised_lifetime_count;
+        mirrored_subscription.max_keep_alive_count = revised_max_keep_alive_count;
+        session
+            .subscription_mirroring()
+            .insert(mirrored_subscription);
+        StatusCode::Good
+    }
+
     /// Handles a DeleteSubscriptionsRequest
     pub fn delete_subscriptions(
         &self,
@@ -206,6 +372,7 @@ impl SubscriptionService {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
         } else {
             let mut session = trace_write_lock!(session);
+            let subscription_mirroring = session.subscription_mirroring();
             let subscription_ids = request.subscription_ids.as_ref().unwrap();
             let results = {
                 let subscriptions = session.subscriptions_mut();
@@ -215,6 +382,7 @@ impl SubscriptionService {
                     .map(|subscription_id| {
                         let subscription = subscriptions.remove(*subscription_id);
                         if subscription.is_some() {
+                            subscription_mirroring.delete(*subscription_id);
                             StatusCode::Good
                         } else {
                             StatusCode::BadSubscriptionIdInvalid
@@ -271,6 +439,9 @@ impl SubscriptionService {
         let result = session
             .subscriptions()
             .find_notification_message(request.subscription_id, request.retransmit_sequence_number);
+        if let Some(subscription) = session.subscriptions_mut().get_mut(request.subscription_id) {
+            subscription.on_republish(result.is_ok());
+        }
         if let Ok(notification_message) = result {
             session.reset_subscription_lifetime_counter(request.subscription_id);
             let response = RepublishResponse {
@@ -5,6 +5,8 @@
 use std::result::Result;
 use std::sync::Arc;
 
+use futures::future;
+
 use crate::core::supported_message::SupportedMessage;
 use crate::crypto::random;
 use crate::sync::*;
@@ -13,10 +15,15 @@ use crate::types::{node_ids::ReferenceTypeId, status_code::StatusCode, *};
 use crate::server::{
     address_space::{relative_path, AddressSpace},
     continuation_point::BrowseContinuationPoint,
-    services::Service,
+    node_manager::RequestContext,
+    services::{async_operation_timeout, Service, ServiceResponse},
     session::Session,
     state::ServerState,
 };
+
+#[cfg(test)]
+use crate::server::{services::unregistered_request_context, session::SessionManager};
+
 /// The view service. Allows the client to browse the address space of the server.
 pub(crate) struct ViewService;
 
@@ -31,6 +38,7 @@ impl ViewService {
         ViewService {}
     }
 
+    #[cfg(test)]
     pub fn browse(
         &self,
         server_state: Arc<RwLock<ServerState>>,
@@ -38,62 +46,168 @@ impl ViewService {
         address_space: Arc<RwLock<AddressSpace>>,
         request: &BrowseRequest,
     ) -> SupportedMessage {
+        let context = unregistered_request_context(&session);
+        self.browse_deferred(server_state, session, context, address_space, request)
+            .wait()
+    }
+
+    /// Browses like `browse`, except that nodes of node managers are browsed by a deferred
+    /// response that is awaited without holding the address space lock.
+    pub(crate) fn browse_deferred(
+        &self,
+        server_state: Arc<RwLock<ServerState>>,
+        session: Arc<RwLock<Session>>,
+        context: RequestContext,
+        address_space: Arc<RwLock<AddressSpace>>,
+        request: &BrowseRequest,
+    ) -> ServiceResponse {
         if is_empty_option_vec!(request.nodes_to_browse) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
+                .into()
         } else {
-            let server_state = trace_read_lock!(server_state);
-            let mut session = trace_write_lock!(session);
-            let address_space = trace_read_lock!(address_space);
-
-            let view = &request.view;
-            if !view.view_id.is_null() || !view.timestamp.is_null() {
-                // Views are not supported
-                info!("Browse request ignored because view was specified (views not supported)");
-                self.service_fault(&request.request_header, StatusCode::BadViewIdUnknown)
-            } else {
+            let (results, managed_browses, max_references_per_node, timeout) = {
+                let server_state = trace_read_lock!(server_state);
+                let mut session = trace_write_lock!(session);
+                let address_space = trace_read_lock!(address_space);
+
+                let view = &request.view;
+                if !view.view_id.is_null() || !view.timestamp.is_null() {
+                    // Views are not supported
+                    info!(
+                        "Browse request ignored because view was specified (views not supported)"
+                    );
+                    return self
+                        .service_fault(&request.request_header, StatusCode::BadViewIdUnknown)
+                        .into();
+                }
                 // debug!("Browse request = {:#?}", request);
                 let nodes_to_browse = request.nodes_to_browse.as_ref().unwrap();
-                if nodes_to_browse.len() <= server_state.operational_limits.max_nodes_per_browse {
-                    // Max references per node. This should be server configurable but the constant
-                    // is generous. TODO this value needs to adapt for the max message size
-                    const DEFAULT_MAX_REFERENCES_PER_NODE: u32 = 255;
-                    let max_references_per_node = if request.requested_max_references_per_node == 0
-                    {
-                        // Client imposes no limit
-                        DEFAULT_MAX_REFERENCES_PER_NODE
-                    } else if request.requested_max_references_per_node
-                        > DEFAULT_MAX_REFERENCES_PER_NODE
-                    {
-                        // Client limit exceeds default
-                        DEFAULT_MAX_REFERENCES_PER_NODE
-                    } else {
-                        request.requested_max_references_per_node
-                    };
-                    // Browse the nodes
-                    let results = Some(Self::browse_nodes(
-                        &mut session,
-                        &address_space,
-                        nodes_to_browse,
-                        max_references_per_node as usize,
-                    ));
-                    let diagnostic_infos = None;
-                    BrowseResponse {
-                        response_header: ResponseHeader::new_good(&request.request_header),
-                        results,
-                        diagnostic_infos,
-                    }
-                    .into()
-                } else {
+                if nodes_to_browse.len() > server_state.operational_limits.max_nodes_per_browse {
                     error!(
                         "Browse request too many nodes to browse {}",
                         nodes_to_browse.len()
                     );
-                    self.service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                    return self
+                        .service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                        .into();
                 }
+                // Max references per node. This should be server configurable but the constant
+                // is generous. TODO this value needs to adapt for the max message size
+                const DEFAULT_MAX_REFERENCES_PER_NODE: u32 = 255;
+                let max_references_per_node = if request.requested_max_references_per_node == 0 {
+                    // Client imposes no limit
+                    DEFAULT_MAX_REFERENCES_PER_NODE
+                } else if request.requested_max_references_per_node
+                    > DEFAULT_MAX_REFERENCES_PER_NODE
+                {
+                    // Client limit exceeds default
+                    DEFAULT_MAX_REFERENCES_PER_NODE
+                } else {
+                    request.requested_max_references_per_node
+                } as usize;
+                // Browse the nodes
+                let mut managed_browses = Vec::new();
+                let results: Vec<BrowseResult> = nodes_to_browse
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, node_to_browse)| {
+                        if let Some(node_manager) =
+                            address_space.node_manager_for(&node_to_browse.node_id)
+                        {
+                            // The result is filled in when the node manager completes
+                            managed_browses.push((idx, node_manager, node_to_browse.clone()));
+                            return Self::browse_error(StatusCode::Good);
+                        }
+                        Self::browse_node(
+                            &mut session,
+                            &address_space,
+                            node_to_browse,
+                            max_references_per_node,
+                        )
+                        .unwrap_or_else(Self::browse_error)
+                    })
+                    .collect();
+                (
+                    results,
+                    managed_browses,
+                    max_references_per_node,
+                    server_state.async_operation_timeout,
+                )
+            };
+
+            if managed_browses.is_empty() {
+                ServiceResponse::Ready(Self::browse_response(&request.request_header, results))
+            } else {
+                let request_header = request.request_header.clone();
+                ServiceResponse::Deferred(Box::pin(async move {
+                    let mut results = results;
+                    let (request_header, context) = (&request_header, &context);
+                    let browses = managed_browses.into_iter().map(
+                        |(idx, node_manager, node_to_browse)| async move {
+                            let timeout = async_operation_timeout(
+                                timeout,
+                                node_manager.timeout(),
+                                request_header,
+                            );
+                            let browse = node_manager.browse(context, &node_to_browse);
+                            let references = match tokio::time::timeout(timeout, browse).await {
+                                Ok(references) => references,
+                                Err(_) => {
+                                    warn!(
+                                        "Node manager did not browse {} within {} ms",
+                                        node_to_browse.node_id,
+                                        timeout.as_millis()
+                                    );
+                                    Err(StatusCode::BadTimeout)
+                                }
+                            };
+                            (idx, references)
+                        },
+                    );
+                    let browses = future::join_all(browses).await;
+                    {
+                        // References that exceed the limit are held by a continuation point
+                        let mut session = trace_write_lock!(session);
+                        let address_space = trace_read_lock!(address_space);
+                        for (idx, references) in browses {
+                            results[idx] = match references {
+                                Ok(references) => Self::reference_description_to_browse_result(
+                                    &mut session,
+                                    &address_space,
+                                    &references,
+                                    0,
+                                    max_references_per_node,
+                                ),
+                                Err(status_code) => Self::browse_error(status_code),
+                            };
+                        }
+                    }
+                    Self::browse_response(request_header, results)
+                }))
             }
         }
     }
 
+    fn browse_response(
+        request_header: &RequestHeader,
+        results: Vec<BrowseResult>,
+    ) -> SupportedMessage {
+        BrowseResponse {
+            response_header: ResponseHeader::new_good(request_header),
+            results: Some(results),
+            diagnostic_infos: None,
+        }
+        .into()
+    }
+
+    fn browse_error(status_code: StatusCode) -> BrowseResult {
+        BrowseResult {
+            status_code,
+            continuation_point: ByteString::null(),
+            references: None,
+        }
+    }
+
     pub fn browse_next(
         &self,
         session: Arc<RwLock<Session>>,
@@ -136,14 +250,39 @@ impl ViewService {
         }
     }
 
+    #[cfg(test)]
     pub fn translate_browse_paths_to_node_ids(
         &self,
         server_state: Arc<RwLock<ServerState>>,
         address_space: Arc<RwLock<AddressSpace>>,
         request: &TranslateBrowsePathsToNodeIdsRequest,
     ) -> SupportedMessage {
+        let context = RequestContext::new(
+            NodeId::null(),
+            Arc::new(RwLock::new(SessionManager::default())),
+        );
+        self.translate_browse_paths_to_node_ids_deferred(
+            server_state,
+            context,
+            address_space,
+            request,
+        )
+        .wait()
+    }
+
+    /// Translates like `translate_browse_paths_to_node_ids`, except that paths that start from a
+    /// node of a node manager are translated by a deferred response that is awaited without
+    /// holding the address space lock.
+    pub(crate) fn translate_browse_paths_to_node_ids_deferred(
+        &self,
+        server_state: Arc<RwLock<ServerState>>,
+        context: RequestContext,
+        address_space: Arc<RwLock<AddressSpace>>,
+        request: &TranslateBrowsePathsToNodeIdsRequest,
+    ) -> ServiceResponse {
         if is_empty_option_vec!(request.browse_paths) {
             self.service_fault(&request.request_header, StatusCode::BadNothingToDo)
+                .into()
         } else {
             let server_state = trace_read_lock!(server_state);
             let address_space = trace_read_lock!(address_space);
@@ -152,7 +291,8 @@ impl ViewService {
                 .operational_limits
                 .max_nodes_per_translate_browse_paths_to_node_ids;
             if browse_paths.len() <= max_browse_paths_per_translate {
-                let results = browse_paths
+                let mut managed_translates = Vec::new();
+                let results: Vec<BrowsePathResult> = browse_paths
                     .iter()
                     .enumerate()
                     .map(|(i, browse_path)| {
@@ -163,52 +303,64 @@ impl ViewService {
                                 status_code: StatusCode::BadNothingToDo,
                                 targets: None,
                             }
+                        } else if let Some(node_manager) = address_space.node_manager_for(&node_id)
+                        {
+                            // The result is filled in when the node manager completes
+                            managed_translates.push((i, node_manager, browse_path.clone()));
+                            Self::browse_path_result(Ok(Vec::new()))
                         } else {
                             // Starting from the node_id, find paths
-                            match relative_path::find_nodes_relative_path(
+                            Self::browse_path_result(relative_path::find_nodes_relative_path(
                                 &address_space,
                                 &node_id,
                                 &browse_path.relative_path,
-                            ) {
-                                Err(err) => {
-                                    trace!(
-                                        "Browse path result for find nodes returned in error {}",
-                                        err.name()
-                                    );
-                                    BrowsePathResult {
-                                        status_code: err,
-                                        targets: None,
-                                    }
-                                }
-                                Ok(result) => {
-                                    let targets = if !result.is_empty() {
-                                        use std::u32;
-                                        let targets = result
-                                            .iter()
-                                            .map(|node_id| BrowsePathTarget {
-                                                target_id: ExpandedNodeId::new(node_id.clone()),
-                                                remaining_path_index: u32::MAX,
-                                            })
-                                            .collect();
-                                        Some(targets)
-                                    } else {
-                                        None
-                                    };
-                                    BrowsePathResult {
-                                        status_code: StatusCode::Good,
-                                        targets,
-                                    }
-                                }
-                            }
+                            ))
                         }
                     })
                     .collect();
-                TranslateBrowsePathsToNodeIdsResponse {
-                    response_header: ResponseHeader::new_good(&request.request_header),
-                    results: Some(results),
-                    diagnostic_infos: None,
+                if managed_translates.is_empty() {
+                    ServiceResponse::Ready(Self::translate_response(
+                        &request.request_header,
+                        results,
+                    ))
+                } else {
+                    let server_timeout = server_state.async_operation_timeout;
+                    let request_header = request.request_header.clone();
+                    ServiceResponse::Deferred(Box::pin(async move {
+                        let mut results = results;
+                        let (request_header, context) = (&request_header, &context);
+                        let translates = managed_translates.into_iter().map(
+                            |(idx, node_manager, browse_path)| async move {
+                                let timeout = async_operation_timeout(
+                                    server_timeout,
+                                    node_manager.timeout(),
+                                    request_header,
+                                );
+                                let translate = node_manager.translate_browse_path(
+                                    context,
+                                    &browse_path.starting_node,
+                                    &browse_path.relative_path,
+                                );
+                                let result = match tokio::time::timeout(timeout, translate).await {
+                                    Ok(result) => result,
+                                    Err(_) => {
+                                        warn!(
+                                            "Node manager did not translate a browse path from {} within {} ms",
+                                            browse_path.starting_node,
+                                            timeout.as_millis()
+                                        );
+                                        Err(StatusCode::BadTimeout)
+                                    }
+                                };
+                                (idx, result)
+                            },
+                        );
+                        for (idx, result) in future::join_all(translates).await {
+                            results[idx] = Self::browse_path_result(result);
+                        }
+                        Self::translate_response(request_header, results)
+                    }))
                 }
-                .into()
             } else {
                 error!(
                     "Browse paths size {} exceeds max nodes {}",
@@ -216,10 +368,57 @@ impl ViewService {
                     max_browse_paths_per_translate
                 );
                 self.service_fault(&request.request_header, StatusCode::BadTooManyOperations)
+                    .into()
             }
         }
     }
 
+    /// Makes the result of a browse path from the nodes that it leads to
+    fn browse_path_result(result: Result<Vec<NodeId>, StatusCode>) -> BrowsePathResult {
+        match result {
+            Err(err) => {
+                trace!(
+                    "Browse path result for find nodes returned in error {}",
+                    err.name()
+                );
+                BrowsePathResult {
+                    status_code: err,
+                    targets: None,
+                }
+            }
+            Ok(result) => {
+                let targets = if !result.is_empty() {
+                    let targets = result
+                        .iter()
+                        .map(|node_id| BrowsePathTarget {
+                            target_id: ExpandedNodeId::new(node_id.clone()),
+                            remaining_path_index: u32::MAX,
+                        })
+                        .collect();
+                    Some(targets)
+                } else {
+                    None
+                };
+                BrowsePathResult {
+                    status_code: StatusCode::Good,
+                    targets,
+                }
+            }
+        }
+    }
+
+    fn translate_response(
+        request_header: &RequestHeader,
+        results: Vec<BrowsePathResult>,
+    ) -> SupportedMessage {
+        TranslateBrowsePathsToNodeIdsResponse {
+            response_header: ResponseHeader::new_good(request_header),
+            results: Some(results),
+            diagnostic_infos: None,
+        }
+        .into()
+    }
+
     pub fn register_nodes(
         &self,
         server_state: Arc<RwLock<ServerState>>,
@@ -301,37 +500,9 @@ impl ViewService {
         }
     }
 
-    fn browse_nodes(
-        session: &mut Session,
-        address_space: &AddressSpace,
-        nodes_to_browse: &[BrowseDescription],
-        max_references_per_node: usize,
-    ) -> Vec<BrowseResult> {
-        nodes_to_browse
-            .iter()
-            .map(|node_to_browse| {
-                match Self::browse_node(
-                    session,
-                    address_space,
-                    0,
-                    node_to_browse,
-                    max_references_per_node,
-                ) {
-                    Ok(browse_result) => browse_result,
-                    Err(status_code) => BrowseResult {
-                        status_code,
-                        continuation_point: ByteString::null(),
-                        references: None,
-                    },
-                }
-            })
-            .collect()
-    }
-
     fn browse_node(
         session: &mut Session,
         address_space: &AddressSpace,
-        starting_index: usize,
         node_to_browse: &BrowseDescription,
         max_references_per_node: usize,
     ) -> Result<BrowseResult, StatusCode> {
@@ -342,6 +513,23 @@ impl ViewService {
 
         //debug!("Node to browse = {:?}", node_to_browse);
 
+        let reference_descriptions = Self::reference_descriptions(address_space, node_to_browse);
+
+        Ok(Self::reference_description_to_browse_result(
+            session,
+            address_space,
+            &reference_descriptions,
+            0,
+            max_references_per_node,
+        ))
+    }
+
+    /// Returns the descriptions of the references of an existing node that match the browse
+    /// description
+    pub(crate) fn reference_descriptions(
+        address_space: &AddressSpace,
+        node_to_browse: &BrowseDescription,
+    ) -> Vec<ReferenceDescription> {
         // Request may wish to filter by a kind of reference
         let reference_type_id = if node_to_browse.reference_type_id.is_null() {
             None
@@ -367,11 +555,8 @@ impl ViewService {
 
         // Construct descriptions for each reference
         let mut reference_descriptions: Vec<ReferenceDescription> =
-            Vec::with_capacity(max_references_per_node);
+            Vec::with_capacity(references.len());
         for (idx, reference) in references.iter().enumerate() {
-            if idx < starting_index {
-                continue;
-            }
             let target_node_id = reference.target_node.clone();
             if target_node_id.is_null() {
                 continue;
@@ -458,14 +643,7 @@ impl ViewService {
             };
             reference_descriptions.push(reference_description);
         }
-
-        Ok(Self::reference_description_to_browse_result(
-            session,
-            address_space,
-            &reference_descriptions,
-            0,
-            max_references_per_node,
-        ))
+        reference_descriptions
     }
 
     fn browse_from_continuation_point(
@@ -10,6 +10,7 @@ use std::{
 };
 
 use chrono::Utc;
+use tokio_util::sync::CancellationToken;
 
 use crate::crypto::X509;
 use crate::sync::*;
@@ -19,7 +20,8 @@ use crate::server::{
     address_space::{AddressSpace, UserAccessLevel},
     continuation_point::BrowseContinuationPoint,
     diagnostics::ServerDiagnostics,
-    identity_token::IdentityToken,
+    identity_token::{AuthenticatedUser, IdentityToken},
+    redundancy::SubscriptionMirroring,
     session_diagnostics::SessionDiagnostics,
     state::ServerState,
     subscriptions::subscription::TickReason,
@@ -84,8 +86,7 @@ impl SessionManager {
             let mut session = trace_write_lock!(session);
             session.set_terminated();
             let mut space = trace_write_lock!(address_space);
-            let diagnostics = trace_write_lock!(session.session_diagnostics);
-            diagnostics.deregister_session(&session, &mut space);
+            SessionDiagnostics::deregister_session(session.session_id(), &mut space);
         }
     }
 
@@ -168,6 +169,8 @@ pub struct Session {
     session_timeout: f64,
     /// User identity token
     user_identity: IdentityToken,
+    /// The user that the identity token is authenticated as
+    authenticated_user: Option<AuthenticatedUser>,
     /// Session's preferred locale ids
     locale_ids: Option<Vec<UAString>>,
     /// Negotiated max request message size
@@ -199,6 +202,11 @@ pub struct Session {
     last_service_request_timestamp: DateTimeUtc,
     /// Subscriptions associated with the session
     subscriptions: Subscriptions,
+    /// Mirrors the subscriptions to a peer of a redundant server
+    subscription_mirroring: Arc<SubscriptionMirroring>,
+    /// Requests that are awaiting async callbacks, by request id, with their request handle and
+    /// the token that cancels them
+    in_flight_requests: HashMap<u32, (u32, CancellationToken)>,
 }
 
 impl Drop for Session {
@@ -213,9 +221,16 @@ impl Session {
     #[cfg(test)]
     pub fn new_no_certificate_store() -> Session {
         let max_browse_continuation_points = super::constants::MAX_BROWSE_CONTINUATION_POINTS;
+        let session_id = next_session_id();
+        let diagnostics = Arc::new(RwLock::new(ServerDiagnostics::default()));
+        let session_diagnostics = {
+            let diagnostics = trace_read_lock!(diagnostics);
+            SessionDiagnostics::new(session_id.clone(), diagnostics.enabled())
+        };
         let session = Session {
             subscriptions: Subscriptions::new(100, PUBLISH_REQUEST_TIMEOUT),
-            session_id: next_session_id(),
+            subscription_mirroring: Arc::new(SubscriptionMirroring::default()),
+            session_id,
             secure_channel_id: 0,
             activated: false,
             terminate_session: false,
@@ -228,6 +243,7 @@ impl Session {
             session_name: UAString::null(),
             session_timeout: 0f64,
             user_identity: IdentityToken::None,
+            authenticated_user: None,
             locale_ids: None,
             max_request_message_size: 0,
             max_response_message_size: 0,
@@ -235,9 +251,10 @@ impl Session {
             max_browse_continuation_points,
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
             can_modify_address_space: true,
-            diagnostics: Arc::new(RwLock::new(ServerDiagnostics::default())),
-            session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
+            diagnostics,
+            session_diagnostics: Arc::new(RwLock::new(session_diagnostics)),
             last_service_request_timestamp: Utc::now(),
+            in_flight_requests: HashMap::new(),
         };
 
         {
@@ -254,14 +271,21 @@ impl Session {
         let server_state = trace_read_lock!(server_state);
         let max_subscriptions = server_state.max_subscriptions;
         let diagnostics = server_state.diagnostics.clone();
+        let subscription_mirroring = server_state.subscription_mirroring.clone();
         let can_modify_address_space = {
             let config = trace_read_lock!(server_state.config);
             config.limits.clients_can_modify_address_space
         };
+        let session_id = next_session_id();
+        let session_diagnostics = {
+            let diagnostics = trace_read_lock!(diagnostics);
+            SessionDiagnostics::new(session_id.clone(), diagnostics.enabled())
+        };
 
         let session = Session {
             subscriptions: Subscriptions::new(max_subscriptions, PUBLISH_REQUEST_TIMEOUT),
-            session_id: next_session_id(),
+            subscription_mirroring,
+            session_id,
             secure_channel_id: 0,
             activated: false,
             terminate_session: false,
@@ -274,6 +298,7 @@ impl Session {
             session_name: UAString::null(),
             session_timeout: 0f64,
             user_identity: IdentityToken::None,
+            authenticated_user: None,
             locale_ids: None,
             max_request_message_size: 0,
             max_response_message_size: 0,
@@ -282,8 +307,9 @@ impl Session {
             browse_continuation_points: VecDeque::with_capacity(max_browse_continuation_points),
             can_modify_address_space,
             diagnostics,
-            session_diagnostics: Arc::new(RwLock::new(SessionDiagnostics::default())),
+            session_diagnostics: Arc::new(RwLock::new(session_diagnostics)),
             last_service_request_timestamp: Utc::now(),
+            in_flight_requests: HashMap::new(),
         };
         {
             let mut diagnostics = trace_write_lock!(session.diagnostics);
@@ -358,14 +384,52 @@ impl Session {
         self.endpoint_url = endpoint_url;
     }
 
+    pub fn max_response_message_size(&self) -> u32 {
+        self.max_response_message_size
+    }
+
+    pub fn security_policy_uri(&self) -> &str {
+        &self.security_policy_uri
+    }
+
     pub fn set_security_policy_uri(&mut self, security_policy_uri: &str) {
         self.security_policy_uri = security_policy_uri.to_string();
     }
 
+    pub(crate) fn user_identity(&self) -> &IdentityToken {
+        &self.user_identity
+    }
+
     pub fn set_user_identity(&mut self, user_identity: IdentityToken) {
         self.user_identity = user_identity;
     }
 
+    /// Returns the user that the session is authenticated as, if it is activated
+    pub fn authenticated_user(&self) -> Option<&AuthenticatedUser> {
+        self.authenticated_user.as_ref()
+    }
+
+    pub(crate) fn set_authenticated_user(&mut self, authenticated_user: Option<AuthenticatedUser>) {
+        self.authenticated_user = authenticated_user;
+    }
+
+    /// Returns the roles granted to the user of the session, i.e. the node ids of role objects
+    pub fn roles(&self) -> &[NodeId] {
+        self.authenticated_user
+            .as_ref()
+            .map(|user| user.roles.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Tests if the user of the session has been granted the role
+    pub fn has_role<T>(&self, role: T) -> bool
+    where
+        T: Into<NodeId>,
+    {
+        let role = role.into();
+        self.roles().contains(&role)
+    }
+
     pub fn last_service_request_timestamp(&self) -> DateTimeUtc {
         self.last_service_request_timestamp
     }
@@ -421,6 +485,10 @@ impl Session {
         &mut self.subscriptions
     }
 
+    pub(crate) fn subscription_mirroring(&self) -> Arc<SubscriptionMirroring> {
+        self.subscription_mirroring.clone()
+    }
+
     pub(crate) fn enqueue_publish_request(
         &mut self,
         now: &DateTimeUtc,
@@ -438,7 +506,20 @@ impl Session {
         address_space: &AddressSpace,
         reason: TickReason,
     ) -> Result<(), StatusCode> {
-        self.subscriptions.tick(now, address_space, reason)
+        // Subscriptions which expire in the tick are no longer mirrored
+        let subscription_ids = if self.subscription_mirroring.is_mirroring() {
+            self.subscriptions.subscriptions().keys().cloned().collect()
+        } else {
+            Vec::new()
+        };
+        let result = self.subscriptions.tick(now, address_space, reason);
+        subscription_ids
+            .into_iter()
+            .filter(|subscription_id| !self.subscriptions.contains(*subscription_id))
+            .for_each(|subscription_id| self.subscription_mirroring.delete(subscription_id));
+        let mut session_diagnostics = trace_write_lock!(self.session_diagnostics);
+        session_diagnostics.update_subscriptions(&self.subscriptions);
+        result
     }
 
     /// Reset the lifetime counter on the subscription, e.g. because a service references the
@@ -522,13 +603,80 @@ impl Session {
     pub(crate) fn effective_user_access_level(
         &self,
         user_access_level: UserAccessLevel,
-        _node_id: &NodeId,
+        node_id: &NodeId,
         _attribute_id: AttributeId,
     ) -> UserAccessLevel {
         // TODO session could modify the user_access_level further here via user / groups
+        let mut user_access_level = user_access_level;
+        if !self.has_user_identity() {
+            // Anonymous sessions cannot switch diagnostics on or off, or see the security
+            // diagnostics of every session
+            if *node_id == VariableId::Server_ServerDiagnostics_EnabledFlag.into() {
+                user_access_level.remove(UserAccessLevel::CURRENT_WRITE);
+            } else if *node_id
+                == VariableId::Server_ServerDiagnostics_SessionsDiagnosticsSummary_SessionSecurityDiagnosticsArray.into()
+            {
+                user_access_level.remove(UserAccessLevel::CURRENT_READ);
+            }
+        }
+        if self.is_security_diagnostics_of_other_session(node_id)
+            && !self.has_role(ObjectId::WellKnownRole_SecurityAdmin)
+        {
+            // The security diagnostics of a session are only visible to that session and to
+            // security administrators
+            user_access_level.remove(UserAccessLevel::CURRENT_READ);
+        }
+        if self.is_observer() {
+            // An observer may read and subscribe, but not change anything
+            user_access_level.remove(
+                UserAccessLevel::CURRENT_WRITE
+                    | UserAccessLevel::HISTORY_WRITE
+                    | UserAccessLevel::STATUS_WRITE
+                    | UserAccessLevel::TIMESTAMP_WRITE,
+            );
+        }
         user_access_level
     }
 
+    /// Tests if the user of the session has the Observer role and no role that allows it to
+    /// change values
+    fn is_observer(&self) -> bool {
+        self.has_role(ObjectId::WellKnownRole_Observer)
+            && ![
+                ObjectId::WellKnownRole_Operator,
+                ObjectId::WellKnownRole_Engineer,
+                ObjectId::WellKnownRole_Supervisor,
+                ObjectId::WellKnownRole_ConfigureAdmin,
+                ObjectId::WellKnownRole_SecurityAdmin,
+            ]
+            .iter()
+            .any(|role| self.has_role(role))
+    }
+
+    /// Tests if the session was activated with a user name, certificate or issued token rather
+    /// than anonymously
+    fn has_user_identity(&self) -> bool {
+        matches!(
+            self.user_identity,
+            IdentityToken::UserNameIdentityToken(_)
+                | IdentityToken::X509IdentityToken(_)
+                | IdentityToken::IssuedIdentityToken(_)
+        )
+    }
+
+    /// Tests if the node is the SessionSecurityDiagnostics variable of another session
+    fn is_security_diagnostics_of_other_session(&self, node_id: &NodeId) -> bool {
+        const NAME: &str = "SessionSecurityDiagnostics";
+        match node_id.identifier {
+            Identifier::String(ref id) => {
+                node_id.namespace == self.session_id.namespace
+                    && id.as_ref().ends_with(&format!(".{}", NAME))
+                    && *node_id != SessionDiagnostics::variable_node_id(&self.session_id, NAME)
+            }
+            _ => false,
+        }
+    }
+
     /// Helper function to return the client user id from the identity token or None of there is no user id
     ///
     /// This conforms to OPC Part 5 6.4.3 ClientUserId
@@ -543,6 +691,12 @@ impl Session {
                     UAString::from("Invalid certificate")
                 }
             }
+            IdentityToken::IssuedIdentityToken(_) => self
+                .authenticated_user
+                .as_ref()
+                .and_then(|user| user.subject.as_ref())
+                .map(UAString::from)
+                .unwrap_or_else(|| UAString::from("issued token")),
             IdentityToken::Invalid(_) => UAString::from("invalid"),
         }
     }
@@ -555,15 +709,52 @@ impl Session {
         self.terminate_session = true;
     }
 
+    /// Registers a request that is awaiting async callbacks so that it can be cancelled by the
+    /// Cancel service. Returns the token that the request observes.
+    pub(crate) fn register_in_flight_request(
+        &mut self,
+        request_id: u32,
+        request_handle: u32,
+    ) -> CancellationToken {
+        let token = CancellationToken::new();
+        self.in_flight_requests
+            .insert(request_id, (request_handle, token.clone()));
+        token
+    }
+
+    /// Removes a request once its response has been produced
+    pub(crate) fn deregister_in_flight_request(&mut self, request_id: u32) {
+        self.in_flight_requests.remove(&request_id);
+    }
+
+    /// Returns the number of requests that are awaiting async callbacks
+    pub fn in_flight_request_count(&self) -> usize {
+        self.in_flight_requests.len()
+    }
+
+    /// Cancels the in-flight requests with the request handle and returns how many were
+    /// cancelled. Cancelled requests are answered with `BadRequestCancelledByClient`.
+    pub fn cancel_requests(&mut self, request_handle: u32) -> u32 {
+        let mut cancel_count = 0;
+        self.in_flight_requests.retain(|_, (handle, token)| {
+            if *handle == request_handle {
+                token.cancel();
+                cancel_count += 1;
+                false
+            } else {
+                true
+            }
+        });
+        cancel_count
+    }
+
     pub(crate) fn register_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
-        let session_diagnostics = trace_read_lock!(self.session_diagnostics);
         let mut address_space = trace_write_lock!(address_space);
-        session_diagnostics.register_session(self, &mut address_space);
+        SessionDiagnostics::register_session(&self.session_diagnostics, self, &mut address_space);
     }
 
     pub(crate) fn deregister_session(&self, address_space: Arc<RwLock<AddressSpace>>) {
-        let session_diagnostics = trace_read_lock!(self.session_diagnostics);
         let mut address_space = trace_write_lock!(address_space);
-        session_diagnostics.deregister_session(self, &mut address_space);
+        SessionDiagnostics::deregister_session(&self.session_id, &mut address_space);
     }
 }
@@ -1,146 +1,314 @@
 use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
-use crate::types::{node_ids::ObjectTypeId, service_types::ServiceCounterDataType};
+use crate::sync::*;
+use crate::types::{
+    node_ids::{DataTypeId, ObjectId, ObjectTypeId, VariableTypeId},
+    profiles,
+    service_types::{
+        ApplicationDescription, ApplicationType, MessageSecurityMode,
+        SamplingIntervalDiagnosticsDataType, ServiceCounterDataType, SessionDiagnosticsDataType,
+        SessionSecurityDiagnosticsDataType, SubscriptionDiagnosticsDataType,
+    },
+    *,
+};
 
 use super::{
-    address_space::{address_space::AddressSpace, object::ObjectBuilder},
+    address_space::{
+        address_space::AddressSpace, object::ObjectBuilder, variable::VariableBuilder, AttrFnGetter,
+    },
+    identity_token::IdentityToken,
     session::Session,
+    subscriptions::subscriptions::Subscriptions,
 };
 
-/// This object tracks session diagnostics for exposure through the address space
-
+/// This object tracks session diagnostics for exposure through the address space. It is shared
+/// between the session, the server diagnostics and the getters of the session's diagnostics
+/// nodes. It must not be held locked while locking the address space or the server diagnostics.
+#[derive(Debug)]
 pub(crate) struct SessionDiagnostics {
+    /// Flag shared with the server diagnostics that switches collection on or off
+    enabled: Arc<AtomicBool>,
+    session_id: NodeId,
+    session_name: UAString,
+    client_description: Option<ApplicationDescription>,
+    server_uri: UAString,
+    endpoint_url: UAString,
+    locale_ids: Option<Vec<UAString>>,
+    actual_session_timeout: f64,
+    max_response_message_size: u32,
+    client_connection_time: DateTime,
+    client_last_contact_time: DateTime,
+    current_subscriptions_count: u32,
+    current_monitored_items_count: u32,
+    current_publish_requests_in_queue: u32,
     total_request_count: u32,
     unauthorized_request_count: u32,
     service_counters: HashMap<&'static str, ServiceCounterDataType>,
+    security_diagnostics: SessionSecurityDiagnosticsDataType,
+    subscription_diagnostics: Vec<SubscriptionDiagnosticsDataType>,
+    sampling_interval_diagnostics: Vec<SamplingIntervalDiagnosticsDataType>,
 }
 
-impl Default for SessionDiagnostics {
-    fn default() -> Self {
+impl SessionDiagnostics {
+    pub(crate) fn new(session_id: NodeId, enabled: Arc<AtomicBool>) -> Self {
+        let now = DateTime::now();
         Self {
+            enabled,
+            session_id: session_id.clone(),
+            session_name: UAString::null(),
+            client_description: None,
+            server_uri: UAString::null(),
+            endpoint_url: UAString::null(),
+            locale_ids: None,
+            actual_session_timeout: 0f64,
+            max_response_message_size: 0,
+            client_connection_time: now,
+            client_last_contact_time: now,
+            current_subscriptions_count: 0,
+            current_monitored_items_count: 0,
+            current_publish_requests_in_queue: 0,
             total_request_count: 0,
             unauthorized_request_count: 0,
             service_counters: HashMap::new(),
+            security_diagnostics: SessionSecurityDiagnosticsDataType {
+                session_id,
+                client_user_id_of_session: UAString::null(),
+                client_user_id_history: None,
+                authentication_mechanism: UAString::null(),
+                encoding: UAString::from("UA Binary"),
+                transport_protocol: UAString::from(profiles::TRANSPORT_PROFILE_URI_BINARY),
+                security_mode: MessageSecurityMode::Invalid,
+                security_policy_uri: UAString::null(),
+                client_certificate: ByteString::null(),
+            },
+            subscription_diagnostics: Vec::new(),
+            sampling_interval_diagnostics: Vec::new(),
         }
     }
-}
 
-impl SessionDiagnostics {
-    /// Registers a session object
-    pub(crate) fn register_session(&self, session: &Session, address_space: &mut AddressSpace) {
-        // TODO SessionDiagnosticsObjectType
+    /// Tests if diagnostics are being collected
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Captures the information about a session once it has been created
+    pub(crate) fn set_session_info(
+        &mut self,
+        session: &Session,
+        client_description: &ApplicationDescription,
+        server_uri: &UAString,
+        security_mode: MessageSecurityMode,
+    ) {
+        self.session_name = session.session_name().clone();
+        self.client_description = Some(client_description.clone());
+        self.server_uri = server_uri.clone();
+        self.endpoint_url = session.endpoint_url().clone();
+        self.actual_session_timeout = session.session_timeout();
+        self.max_response_message_size = session.max_response_message_size();
+        self.client_connection_time = DateTime::now();
+        self.security_diagnostics.security_mode = security_mode;
+        self.security_diagnostics.security_policy_uri =
+            UAString::from(session.security_policy_uri());
+        self.security_diagnostics.client_certificate = session
+            .client_certificate()
+            .as_ref()
+            .map(|cert| cert.as_byte_string())
+            .unwrap_or_else(ByteString::null);
+    }
+
+    /// Captures the locales and user of a session once it has been activated
+    pub(crate) fn set_activated(&mut self, session: &Session) {
+        self.locale_ids = session.locale_ids().clone();
+
+        let client_user_id = session.client_user_id();
+        let security_diagnostics = &mut self.security_diagnostics;
+        if !client_user_id.is_null() {
+            let history = security_diagnostics
+                .client_user_id_history
+                .get_or_insert_with(Vec::new);
+            if history.last() != Some(&client_user_id) {
+                history.push(client_user_id.clone());
+            }
+        }
+        security_diagnostics.client_user_id_of_session = client_user_id;
+        security_diagnostics.authentication_mechanism =
+            UAString::from(match session.user_identity() {
+                IdentityToken::None | IdentityToken::AnonymousIdentityToken(_) => "Anonymous",
+                IdentityToken::UserNameIdentityToken(_) => "UserName",
+                IdentityToken::X509IdentityToken(_) => "Certificate",
+                IdentityToken::IssuedIdentityToken(_) => "IssuedToken",
+                IdentityToken::Invalid(_) => "Invalid",
+            });
+    }
+
+    /// Takes a snapshot of the session's subscriptions
+    pub(crate) fn update_subscriptions(&mut self, subscriptions: &Subscriptions) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.current_subscriptions_count = subscriptions.len() as u32;
+        self.current_monitored_items_count = subscriptions.monitored_item_count() as u32;
+        self.current_publish_requests_in_queue = subscriptions.publish_request_queue_len() as u32;
+        self.subscription_diagnostics = subscriptions.subscription_diagnostics(&self.session_id);
+
+        let mut sampling_interval_diagnostics = Vec::new();
+        subscriptions
+            .subscriptions()
+            .values()
+            .flat_map(|s| s.sampling_intervals())
+            .for_each(|(sampling_interval, disabled)| {
+                add_sampling_interval(
+                    &mut sampling_interval_diagnostics,
+                    sampling_interval,
+                    1,
+                    if disabled { 1 } else { 0 },
+                )
+            });
+        self.sampling_interval_diagnostics = sampling_interval_diagnostics;
+    }
 
+    /// Registers a session object with diagnostics variables beneath the sessions diagnostics
+    /// summary
+    pub(crate) fn register_session(
+        session_diagnostics: &Arc<RwLock<SessionDiagnostics>>,
+        session: &Session,
+        address_space: &mut AddressSpace,
+    ) {
         let session_id = session.session_id();
         debug!("register_session for session id {}", session_id);
 
+        // Browse name shall be session name, session id is the node id
+        let session_name = if session.session_name().is_empty() {
+            format!("{}", session_id)
+        } else {
+            session.session_name().to_string()
+        };
         debug!("Adding an object node for the session id {}", session_id);
-        let _ = ObjectBuilder::new(
+        let _ = ObjectBuilder::new(session_id, session_name.as_str(), session_name.as_str())
+            .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
+            .component_of(ObjectId::Server_ServerDiagnostics_SessionsDiagnosticsSummary)
+            .insert(address_space);
+
+        Self::add_variable(
+            address_space,
+            session_diagnostics,
+            session_id,
+            "SessionDiagnostics",
+            VariableTypeId::SessionDiagnosticsVariableType,
+            DataTypeId::SessionDiagnosticsDataType,
+            |d| {
+                let value = d.session_diagnostics();
+                Variant::from(ExtensionObject::from_encodable(
+                    ObjectId::SessionDiagnosticsDataType_Encoding_DefaultBinary,
+                    &value,
+                ))
+            },
+        );
+        Self::add_variable(
+            address_space,
+            session_diagnostics,
             session_id,
-            format!("{}", session_id),
-            format!("{}", session_id),
-        )
-        .has_type_definition(ObjectTypeId::SessionDiagnosticsObjectType)
-        .insert(address_space);
-
-        // Now add variables
-        /*
-             12816 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics),
-           12817 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionId),
-           12818 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SessionName),
-           12819 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientDescription),
-           12820 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ServerUri),
-           12821 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_EndpointUrl),
-           12822 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_LocaleIds),
-           12823 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ActualSessionTimeout),
-           12824 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_MaxResponseMessageSize),
-           12825 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientConnectionTime),
-           12826 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ClientLastContactTime),
-           12827 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentSubscriptionsCount),
-           12828 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentMonitoredItemsCount),
-           12829 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CurrentPublishRequestsInQueue),
-
-           12830 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TotalRequestCount),
-           12831 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnauthorizedRequestCount),
-
-           12832 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ReadCount),
-           12833 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryReadCount),
-           12834 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_WriteCount),
-           12835 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_HistoryUpdateCount),
-           12836 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CallCount),
-           12837 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateMonitoredItemsCount),
-           12838 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifyMonitoredItemsCount),
-           12839 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetMonitoringModeCount),
-           12840 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetTriggeringCount),
-           12841 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteMonitoredItemsCount),
-           12842 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_CreateSubscriptionCount),
-           12843 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_ModifySubscriptionCount),
-           12844 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_SetPublishingModeCount),
-           12845 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_PublishCount),
-           12846 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RepublishCount),
-           12847 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TransferSubscriptionsCount),
-           12848 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteSubscriptionsCount),
-           12849 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddNodesCount),
-           12850 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_AddReferencesCount),
-           12851 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteNodesCount),
-           12852 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_DeleteReferencesCount),
-           12853 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseCount),
-           12854 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_BrowseNextCount),
-           12855 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_TranslateBrowsePathsToNodeIdsCount),
-           12856 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryFirstCount),
-           12857 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_QueryNextCount),
-           12858 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_RegisterNodesCount),
-           12859 => Ok(VariableId::SessionDiagnosticsArrayType_SessionDiagnostics_UnregisterNodesCount),
-        */
-
-        // Browse name shall be session name
-        // session id is the nodeid
-
-        // SessionDiagnostics - SessionDiagnosticsDataType
-        //   SessionId - NodeId
-        //   SessionName - String
-        //   ClientDescription - Application Description
-        //   ServerUri - String
-        //   EndpointUrl - String
-        //   LocaleId - LocaleId[]
-        //   MaxResponseMessageSize - UInt32
-        //   ActualSessionTimeout - Duration
-        //   ClientConnectionTime - UtcTime
-        //   ClientLastContactTime - UtcTime
-        //   CurrentSubscriptionsCount - UInt32
-        //   CurrentMonitoredItemsCount - UInt32
-        //   CurrentPublishRequestsInQueue - UInt32
-        //   TotalRequestCount - ServiceCounterData
-        //   UnauthorizedRequestCount - UInt32
-        //   ReadCount - ServiceCounterData
-        //   HistoryReadCount - ServiceCounterData
-        //   WriteCount - ServiceCounterData
-        //   HistoryUpdateCount
-        // SessionSecurityDiagnostics - SessionSecurityDiagnosticDataType
-        // SeubscriptionDiagnosticsArray - SubscriptionDiagnosticsArray
+            "SessionSecurityDiagnostics",
+            VariableTypeId::SessionSecurityDiagnosticsType,
+            DataTypeId::SessionSecurityDiagnosticsDataType,
+            |d| {
+                Variant::from(ExtensionObject::from_encodable(
+                    ObjectId::SessionSecurityDiagnosticsDataType_Encoding_DefaultBinary,
+                    d.session_security_diagnostics(),
+                ))
+            },
+        );
+        Self::add_variable(
+            address_space,
+            session_diagnostics,
+            session_id,
+            "SubscriptionDiagnosticsArray",
+            VariableTypeId::SubscriptionDiagnosticsArrayType,
+            DataTypeId::SubscriptionDiagnosticsDataType,
+            |d| {
+                extension_object_array(
+                    ObjectId::SubscriptionDiagnosticsDataType_Encoding_DefaultBinary,
+                    d.subscription_diagnostics(),
+                )
+            },
+        );
+    }
+
+    /// Adds a diagnostics variable to the session object whose value is fetched from the
+    /// session diagnostics
+    fn add_variable<F>(
+        address_space: &mut AddressSpace,
+        session_diagnostics: &Arc<RwLock<SessionDiagnostics>>,
+        session_id: &NodeId,
+        name: &str,
+        variable_type: VariableTypeId,
+        data_type: DataTypeId,
+        value: F,
+    ) where
+        F: Fn(&SessionDiagnostics) -> Variant + Send + 'static,
+    {
+        let node_id = Self::variable_node_id(session_id, name);
+        let value_rank = if data_type == DataTypeId::SubscriptionDiagnosticsDataType {
+            1
+        } else {
+            -1
+        };
+        let session_diagnostics = session_diagnostics.clone();
+        let getter = AttrFnGetter::new_boxed(move |_, timestamps_to_return, _, _, _, _| {
+            let session_diagnostics = trace_read_lock!(session_diagnostics);
+            let mut data_value = DataValue::from(value(&session_diagnostics));
+            let now = DateTime::now();
+            data_value.set_timestamps(timestamps_to_return, now, now);
+            Ok(Some(data_value))
+        });
+        let _ = VariableBuilder::new(&node_id, name, name)
+            .data_type(data_type)
+            .value_rank(value_rank)
+            .value_getter(getter)
+            .has_type_definition(variable_type)
+            .component_of(session_id.clone())
+            .insert(address_space);
+    }
+
+    /// Returns the node id of the diagnostics variable with the name on the session object
+    pub(crate) fn variable_node_id(session_id: &NodeId, name: &str) -> NodeId {
+        match session_id.identifier {
+            Identifier::String(ref id) => {
+                NodeId::new(session_id.namespace, format!("{}.{}", id, name))
+            }
+            ref identifier => NodeId::new(session_id.namespace, format!("{}.{}", identifier, name)),
+        }
     }
 
     /// Deregisters a session object
-    pub(crate) fn deregister_session(&self, session: &Session, address_space: &mut AddressSpace) {
-        address_space.delete(session.session_id(), true);
+    pub(crate) fn deregister_session(session_id: &NodeId, address_space: &mut AddressSpace) {
+        address_space.delete(session_id, true);
     }
 
     /// Called on every request
     pub(crate) fn request(&mut self) {
-        self.total_request_count += 1;
+        if self.is_enabled() {
+            self.total_request_count += 1;
+            self.client_last_contact_time = DateTime::now();
+        }
     }
 
     /// Called on an authorized request
     pub(crate) fn unauthorized_request(&mut self) {
-        self.unauthorized_request_count += 1;
-        self.total_request_count += 1;
+        if self.is_enabled() {
+            self.unauthorized_request_count += 1;
+            self.total_request_count += 1;
+            self.client_last_contact_time = DateTime::now();
+        }
     }
 
     /// Fetches a snapshot of the current service counter value
-    pub(crate) fn service_counter(
-        &mut self,
-        diagnostic_key: &'static str,
-    ) -> ServiceCounterDataType {
-        if let Some(counter) = self.service_counters.get_mut(diagnostic_key) {
+    pub(crate) fn service_counter(&self, diagnostic_key: &'static str) -> ServiceCounterDataType {
+        if let Some(counter) = self.service_counters.get(diagnostic_key) {
             counter.clone()
         } else {
             ServiceCounterDataType::default()
@@ -149,6 +317,9 @@ impl SessionDiagnostics {
 
     /// Increments the service counter for a successful service call
     pub(crate) fn service_success(&mut self, diagnostic_key: &'static str) {
+        if !self.is_enabled() {
+            return;
+        }
         if let Some(counter) = self.service_counters.get_mut(diagnostic_key) {
             counter.success();
         } else {
@@ -160,6 +331,9 @@ impl SessionDiagnostics {
 
     /// Increments the service counter for a failed service call
     pub(crate) fn service_error(&mut self, diagnostic_key: &'static str) {
+        if !self.is_enabled() {
+            return;
+        }
         if let Some(counter) = self.service_counters.get_mut(diagnostic_key) {
             counter.error();
         } else {
@@ -168,6 +342,129 @@ impl SessionDiagnostics {
             self.service_counters.insert(diagnostic_key, counter);
         }
     }
+
+    /// Returns the diagnostics of the session
+    pub(crate) fn session_diagnostics(&self) -> SessionDiagnosticsDataType {
+        let client_description =
+            self.client_description
+                .clone()
+                .unwrap_or_else(|| ApplicationDescription {
+                    application_uri: UAString::null(),
+                    product_uri: UAString::null(),
+                    application_name: LocalizedText::null(),
+                    application_type: ApplicationType::Client,
+                    gateway_server_uri: UAString::null(),
+                    discovery_profile_uri: UAString::null(),
+                    discovery_urls: None,
+                });
+        // Errors are counted by the service counters
+        let error_count = self
+            .service_counters
+            .values()
+            .map(|counter| counter.error_count)
+            .sum();
+        SessionDiagnosticsDataType {
+            session_id: self.session_id.clone(),
+            session_name: self.session_name.clone(),
+            client_description,
+            server_uri: self.server_uri.clone(),
+            endpoint_url: self.endpoint_url.clone(),
+            locale_ids: self.locale_ids.clone(),
+            actual_session_timeout: self.actual_session_timeout,
+            max_response_message_size: self.max_response_message_size,
+            client_connection_time: self.client_connection_time,
+            client_last_contact_time: self.client_last_contact_time,
+            current_subscriptions_count: self.current_subscriptions_count,
+            current_monitored_items_count: self.current_monitored_items_count,
+            current_publish_requests_in_queue: self.current_publish_requests_in_queue,
+            total_request_count: ServiceCounterDataType {
+                total_count: self.total_request_count,
+                error_count,
+            },
+            unauthorized_request_count: self.unauthorized_request_count,
+            read_count: self.service_counter(READ_COUNT),
+            history_read_count: self.service_counter(HISTORY_READ_COUNT),
+            write_count: self.service_counter(WRITE_COUNT),
+            history_update_count: self.service_counter(HISTORY_UPDATE_COUNT),
+            call_count: self.service_counter(CALL_COUNT),
+            create_monitored_items_count: self.service_counter(CREATE_MONITORED_ITEMS_COUNT),
+            modify_monitored_items_count: self.service_counter(MODIFY_MONITORED_ITEMS_COUNT),
+            set_monitoring_mode_count: self.service_counter(SET_MONITORING_MODE_COUNT),
+            set_triggering_count: self.service_counter(SET_TRIGGERING_COUNT),
+            delete_monitored_items_count: self.service_counter(DELETE_MONITORED_ITEMS_COUNT),
+            create_subscription_count: self.service_counter(CREATE_SUBSCRIPTION_COUNT),
+            modify_subscription_count: self.service_counter(MODIFY_SUBSCRIPTION_COUNT),
+            set_publishing_mode_count: self.service_counter(SET_PUBLISHING_MODE_COUNT),
+            publish_count: self.service_counter(PUBLISH_COUNT),
+            republish_count: self.service_counter(REPUBLISH_COUNT),
+            transfer_subscriptions_count: self.service_counter(TRANSFER_SUBSCRIPTIONS_COUNT),
+            delete_subscriptions_count: self.service_counter(DELETE_SUBSCRIPTIONS_COUNT),
+            add_nodes_count: self.service_counter(ADD_NODES_COUNT),
+            add_references_count: self.service_counter(ADD_REFERENCES_COUNT),
+            delete_nodes_count: self.service_counter(DELETE_NODES_COUNT),
+            delete_references_count: self.service_counter(DELETE_REFERENCES_COUNT),
+            browse_count: self.service_counter(BROWSE_COUNT),
+            browse_next_count: self.service_counter(BROWSE_NEXT_COUNT),
+            translate_browse_paths_to_node_ids_count: self
+                .service_counter(TRANSLATE_BROWSE_PATHS_TO_NODE_IDS_COUNT),
+            query_first_count: ServiceCounterDataType::default(),
+            query_next_count: ServiceCounterDataType::default(),
+            register_nodes_count: self.service_counter(REGISTER_NODES_COUNT),
+            unregister_nodes_count: self.service_counter(UNREGISTER_NODES_COUNT),
+        }
+    }
+
+    /// Returns the security diagnostics of the session
+    pub(crate) fn session_security_diagnostics(&self) -> &SessionSecurityDiagnosticsDataType {
+        &self.security_diagnostics
+    }
+
+    /// Returns the diagnostics of the session's subscriptions as of the last tick
+    pub(crate) fn subscription_diagnostics(&self) -> &[SubscriptionDiagnosticsDataType] {
+        &self.subscription_diagnostics
+    }
+
+    /// Returns the sampling intervals of the session's monitored items as of the last tick
+    pub(crate) fn sampling_interval_diagnostics(&self) -> &[SamplingIntervalDiagnosticsDataType] {
+        &self.sampling_interval_diagnostics
+    }
+}
+
+/// Adds monitored items to the diagnostics entry for the sampling interval, creating the entry
+/// if there isn't one.
+pub(crate) fn add_sampling_interval(
+    diagnostics: &mut Vec<SamplingIntervalDiagnosticsDataType>,
+    sampling_interval: f64,
+    monitored_item_count: u32,
+    disabled_monitored_item_count: u32,
+) {
+    if let Some(d) = diagnostics
+        .iter_mut()
+        .find(|d| d.sampling_interval == sampling_interval)
+    {
+        d.monitored_item_count += monitored_item_count;
+        d.disabled_monitored_item_count += disabled_monitored_item_count;
+        d.max_monitored_item_count = d.max_monitored_item_count.max(d.monitored_item_count);
+    } else {
+        diagnostics.push(SamplingIntervalDiagnosticsDataType {
+            sampling_interval,
+            monitored_item_count,
+            max_monitored_item_count: monitored_item_count,
+            disabled_monitored_item_count,
+        });
+    }
+}
+
+/// Makes an array variant of extension objects from the encodable values
+pub(crate) fn extension_object_array<T>(object_id: ObjectId, values: &[T]) -> Variant
+where
+    T: BinaryEncoder<T>,
+{
+    let values = values
+        .iter()
+        .map(|v| Variant::from(ExtensionObject::from_encodable(object_id, v)))
+        .collect::<Vec<Variant>>();
+    Variant::from((VariantTypeId::ExtensionObject, values))
 }
 
 pub(crate) const READ_COUNT: &str = "ReadCount";
@@ -183,7 +480,7 @@ pub(crate) const DELETE_MONITORED_ITEMS_COUNT: &str = "DeleteMonitoredItemsCount
 pub(crate) const CREATE_SUBSCRIPTION_COUNT: &str = "CreateSubscriptionCount";
 pub(crate) const MODIFY_SUBSCRIPTION_COUNT: &str = "ModifySubscriptionCount";
 pub(crate) const SET_PUBLISHING_MODE_COUNT: &str = "SetPublishingModeCount";
-//pub(crate) const PUBLISH_COUNT: &str = "PublishCount";
+pub(crate) const PUBLISH_COUNT: &str = "PublishCount";
 pub(crate) const REPUBLISH_COUNT: &str = "RepublishCount";
 pub(crate) const TRANSFER_SUBSCRIPTIONS_COUNT: &str = "TransferSubscriptionsCount";
 pub(crate) const DELETE_SUBSCRIPTIONS_COUNT: &str = "DeleteSubscriptionsCount";
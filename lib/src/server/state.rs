@@ -4,24 +4,27 @@
 
 //! Provides server state information, such as status, configuration, running servers and so on.
 
-use std::sync::Arc;
+use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use crate::core::prelude::*;
-use crate::crypto::{user_identity, PrivateKey, SecurityPolicy, X509};
+use crate::crypto::{
+    jwt::{self, JwtKeys, JwtValidation, ISSUED_TOKEN_TYPE_JWT},
+    user_identity, PrivateKey, SecurityPolicy, X509,
+};
 use crate::sync::*;
 use crate::types::{
     profiles,
     service_types::{
         ActivateSessionRequest, AnonymousIdentityToken, ApplicationDescription, ApplicationType,
-        EndpointDescription, RegisteredServer, ServerState as ServerStateType, SignatureData,
-        UserNameIdentityToken, UserTokenPolicy, UserTokenType, X509IdentityToken,
+        EndpointDescription, IssuedIdentityToken, RegisteredServer, ServerState as ServerStateType,
+        SignatureData, UserNameIdentityToken, UserTokenPolicy, UserTokenType, X509IdentityToken,
     },
     status_code::StatusCode,
 };
 
 use crate::server::{
     callbacks::{RegisterNodes, UnregisterNodes},
-    config::{ServerConfig, ServerEndpoint},
+    config::{ServerConfig, ServerEndpoint, ServerJwtConfig},
     constants,
     diagnostics::ServerDiagnostics,
     events::{
@@ -30,8 +33,13 @@ use crate::server::{
     },
     historical::{HistoricalDataProvider, HistoricalEventProvider},
     identity_token::{
-        IdentityToken, POLICY_ID_ANONYMOUS, POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15,
-        POLICY_ID_USER_PASS_RSA_OAEP, POLICY_ID_X509,
+        AuthenticatedUser, IdentityToken, POLICY_ID_ANONYMOUS, POLICY_ID_ISSUED_JWT,
+        POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15, POLICY_ID_USER_PASS_RSA_OAEP,
+        POLICY_ID_X509,
+    },
+    journal::{Journal, JournalEntry},
+    redundancy::{
+        HealthFunction, MirroredSubscriptions, SubscriptionMirror, SubscriptionMirroring,
     },
 };
 
@@ -112,6 +120,8 @@ pub struct ServerState {
     pub max_lifetime_count: u32,
     /// Operational limits
     pub(crate) operational_limits: OperationalLimits,
+    /// Time allowed for async getters, setters and methods to complete
+    pub async_operation_timeout: std::time::Duration,
     /// Current state
     pub state: ServerStateType,
     /// Sets the abort flag that terminates the associated server
@@ -128,6 +138,14 @@ pub struct ServerState {
     pub(crate) historical_data_provider: Option<Box<dyn HistoricalDataProvider + Send + Sync>>,
     /// Callback for historical events
     pub(crate) historical_event_provider: Option<Box<dyn HistoricalEventProvider + Send + Sync>>,
+    /// Journal of address space changes, if persistence is enabled
+    pub(crate) journal: Option<Journal>,
+    /// Function that returns the service level of the server
+    pub(crate) health_function: Option<HealthFunction>,
+    /// Mirrors the subscriptions of the server to a peer
+    pub(crate) subscription_mirroring: Arc<SubscriptionMirroring>,
+    /// Subscriptions that a peer has mirrored to the server
+    pub(crate) mirrored_subscriptions: Arc<MirroredSubscriptions>,
     /// Size of the send buffer in bytes
     pub send_buffer_size: usize,
     /// Size of the receive buffer in bytes
@@ -254,7 +272,7 @@ impl ServerState {
         config: &ServerConfig,
         endpoint: &ServerEndpoint,
     ) -> Vec<UserTokenPolicy> {
-        let mut user_identity_tokens = Vec::with_capacity(3);
+        let mut user_identity_tokens = Vec::with_capacity(4);
 
         // Anonymous policy
         if endpoint.supports_anonymous() {
@@ -287,6 +305,28 @@ impl ServerState {
                 security_policy_uri: UAString::from(SecurityPolicy::Basic128Rsa15.to_uri()),
             });
         }
+        // Issued JWT policy, the token is encrypted like a password per the endpoint's security policy
+        if endpoint.supports_jwt(&config.user_tokens) {
+            let issuer_endpoint_url = endpoint
+                .user_token_ids
+                .iter()
+                .filter_map(|id| config.user_tokens.get(id))
+                .find_map(|user_token| {
+                    user_token
+                        .jwt
+                        .as_ref()
+                        .and_then(|jwt| jwt.issuer_endpoint_url.as_ref())
+                });
+            user_identity_tokens.push(UserTokenPolicy {
+                policy_id: UAString::from(POLICY_ID_ISSUED_JWT),
+                token_type: UserTokenType::IssuedToken,
+                issued_token_type: UAString::from(ISSUED_TOKEN_TYPE_JWT),
+                issuer_endpoint_url: issuer_endpoint_url
+                    .map(UAString::from)
+                    .unwrap_or_else(UAString::null),
+                security_policy_uri: UAString::null(),
+            });
+        }
 
         if user_identity_tokens.is_empty() {
             debug!(
@@ -420,9 +460,24 @@ impl ServerState {
         }
     }
 
-    pub fn create_subscription_id(&mut self) -> u32 {
-        self.last_subscription_id += 1;
-        self.last_subscription_id
+    /// Creates the id of a new subscription. The id is never 0, nor the id of a mirrored
+    /// subscription or one that `in_use` says is in use by a session. A subscription keeps its id
+    /// when it is transferred from the peer of a redundant server, so the ids that the server
+    /// issues may already be taken.
+    pub fn create_subscription_id<F>(&mut self, in_use: F) -> u32
+    where
+        F: Fn(u32) -> bool,
+    {
+        loop {
+            self.last_subscription_id = self.last_subscription_id.wrapping_add(1);
+            let subscription_id = self.last_subscription_id;
+            if subscription_id != 0
+                && !self.mirrored_subscriptions.contains(subscription_id)
+                && !in_use(subscription_id)
+            {
+                break subscription_id;
+            }
+        }
     }
 
     /// Authenticates access to an endpoint. The endpoint is described by its path, policy, mode and
@@ -440,6 +495,28 @@ impl ServerState {
         user_identity_token: &ExtensionObject,
         server_nonce: &ByteString,
     ) -> Result<String, StatusCode> {
+        self.authenticate_user(
+            request,
+            endpoint_url,
+            security_policy,
+            security_mode,
+            user_identity_token,
+            server_nonce,
+        )
+        .map(|user| user.user_token_id)
+    }
+
+    /// Authenticates the user identity token against the endpoint, returning the user and the
+    /// roles that it is granted.
+    pub fn authenticate_user(
+        &self,
+        request: &ActivateSessionRequest,
+        endpoint_url: &str,
+        security_policy: SecurityPolicy,
+        security_mode: MessageSecurityMode,
+        user_identity_token: &ExtensionObject,
+        server_nonce: &ByteString,
+    ) -> Result<AuthenticatedUser, StatusCode> {
         // Get security from endpoint url
         let config = trace_read_lock!(self.config);
 
@@ -451,7 +528,7 @@ impl ServerState {
                     Err(StatusCode::BadIdentityTokenInvalid)
                 }
                 IdentityToken::AnonymousIdentityToken(token) => {
-                    Self::authenticate_anonymous_token(endpoint, &token)
+                    Self::authenticate_anonymous_token(endpoint, &token).map(AuthenticatedUser::new)
                 }
                 IdentityToken::UserNameIdentityToken(token) => self
                     .authenticate_username_identity_token(
@@ -460,15 +537,26 @@ impl ServerState {
                         &token,
                         &self.server_pkey,
                         server_nonce,
+                    )
+                    .map(AuthenticatedUser::new),
+                IdentityToken::X509IdentityToken(token) => self
+                    .authenticate_x509_identity_token(
+                        &config,
+                        endpoint,
+                        &token,
+                        &request.user_token_signature,
+                        &self.server_certificate,
+                        server_nonce,
+                    )
+                    .map(AuthenticatedUser::new),
+                IdentityToken::IssuedIdentityToken(token) => self
+                    .authenticate_issued_identity_token(
+                        &config,
+                        endpoint,
+                        &token,
+                        &self.server_pkey,
+                        server_nonce,
                     ),
-                IdentityToken::X509IdentityToken(token) => self.authenticate_x509_identity_token(
-                    &config,
-                    endpoint,
-                    &token,
-                    &request.user_token_signature,
-                    &self.server_certificate,
-                    server_nonce,
-                ),
                 IdentityToken::Invalid(o) => {
                     error!("User identity token type {:?} is unsupported", o.node_id);
                     Err(StatusCode::BadIdentityTokenInvalid)
@@ -657,6 +745,113 @@ impl ServerState {
         }
     }
 
+    /// Authenticates an issued identity token holding a JWT. The token is decrypted, then verified
+    /// against the keys of each JWT user token of the endpoint in turn. The function returns the
+    /// first user token that verifies the JWT along with the roles mapped from its claims.
+    fn authenticate_issued_identity_token(
+        &self,
+        config: &ServerConfig,
+        endpoint: &ServerEndpoint,
+        token: &IssuedIdentityToken,
+        server_key: &Option<PrivateKey>,
+        server_nonce: &ByteString,
+    ) -> Result<AuthenticatedUser, StatusCode> {
+        if !endpoint.supports_jwt(&config.user_tokens) {
+            error!("Endpoint doesn't support issued JWT tokens");
+            return Err(StatusCode::BadIdentityTokenRejected);
+        } else if token.policy_id.as_ref() != POLICY_ID_ISSUED_JWT {
+            error!("Token doesn't possess the correct policy id");
+            return Err(StatusCode::BadIdentityTokenInvalid);
+        }
+        let token_data = if token.encryption_algorithm.is_empty() {
+            token.plaintext_token_data()?
+        } else if let Some(ref server_key) = server_key {
+            user_identity::decrypt_issued_identity_token(token, server_nonce.as_ref(), server_key)?
+        } else {
+            error!("Issued identity token is encrypted but no server private key was supplied");
+            return Err(StatusCode::BadIdentityTokenInvalid);
+        };
+
+        let mut result = Err(StatusCode::BadIdentityTokenRejected);
+        for user_token_id in &endpoint.user_token_ids {
+            let jwt_config = match config.user_tokens.get(user_token_id) {
+                Some(server_user_token) => match server_user_token.jwt {
+                    Some(ref jwt_config) => jwt_config,
+                    None => continue,
+                },
+                None => continue,
+            };
+            let keys = match JwtKeys::read(&PathBuf::from(&jwt_config.keys_path)) {
+                Ok(keys) => keys,
+                Err(status_code) => {
+                    // Another user token may still validate the token
+                    error!(
+                        "Keys of user token {} cannot be read from {}, status code = {}",
+                        user_token_id, jwt_config.keys_path, status_code
+                    );
+                    continue;
+                }
+            };
+            let validation = JwtValidation {
+                issuer: jwt_config.issuer.clone(),
+                audience: jwt_config.audience.clone(),
+                leeway: jwt_config.leeway,
+            };
+            match jwt::decode_jwt(&token_data, &keys, &validation) {
+                Ok(jwt) => {
+                    let mut user = AuthenticatedUser::new(user_token_id.clone());
+                    user.subject = jwt.subject().map(String::from);
+                    Self::jwt_roles(jwt_config, &jwt.claim_values(&jwt_config.roles_claim))
+                        .into_iter()
+                        .for_each(|role| {
+                            if !user.roles.contains(&role) {
+                                user.roles.push(role);
+                            }
+                        });
+                    debug!(
+                        "Issued token of \"{:?}\" is authenticated as user token {} with roles {:?}",
+                        user.subject, user_token_id, user.roles
+                    );
+                    return Ok(user);
+                }
+                Err(err) => result = Err(err),
+            }
+        }
+        result
+    }
+
+    /// Maps the role names of a JWT to roles, either the well known roles or the node ids of roles
+    fn jwt_roles(jwt_config: &ServerJwtConfig, role_names: &[String]) -> Vec<NodeId> {
+        role_names
+            .iter()
+            .filter_map(|role_name| jwt_config.roles.get(role_name))
+            .filter_map(|role| {
+                let well_known_role = match role.as_str() {
+                    "Anonymous" => Some(ObjectId::WellKnownRole_Anonymous),
+                    "AuthenticatedUser" => Some(ObjectId::WellKnownRole_AuthenticatedUser),
+                    "Observer" => Some(ObjectId::WellKnownRole_Observer),
+                    "Operator" => Some(ObjectId::WellKnownRole_Operator),
+                    "Engineer" => Some(ObjectId::WellKnownRole_Engineer),
+                    "Supervisor" => Some(ObjectId::WellKnownRole_Supervisor),
+                    "ConfigureAdmin" => Some(ObjectId::WellKnownRole_ConfigureAdmin),
+                    "SecurityAdmin" => Some(ObjectId::WellKnownRole_SecurityAdmin),
+                    _ => None,
+                };
+                match well_known_role {
+                    Some(well_known_role) => Some(well_known_role.into()),
+                    None => NodeId::from_str(role)
+                        .map_err(|_| {
+                            error!(
+                                "JWT role \"{}\" is not a well known role or a node id",
+                                role
+                            )
+                        })
+                        .ok(),
+                }
+            })
+            .collect()
+    }
+
     pub fn set_historical_data_provider(
         &mut self,
         historical_data_provider: Box<dyn HistoricalDataProvider + Send + Sync>,
@@ -671,6 +866,50 @@ impl ServerState {
         self.historical_event_provider = Some(historical_event_provider);
     }
 
+    /// Sets a function that returns the service level of the server. While the server runs, the
+    /// function is called on the health check interval of the redundancy config, or every second,
+    /// and its result is written to `Server/ServiceLevel`.
+    pub fn set_health_function<F>(&mut self, health_function: F)
+    where
+        F: Fn(&ServerState) -> u8 + Send + Sync + 'static,
+    {
+        self.health_function = Some(Box::new(health_function));
+    }
+
+    /// Sets the mirror that the subscriptions of the server are mirrored to, e.g. the
+    /// `MirroredSubscriptions` of a peer.
+    pub fn set_subscription_mirror(&self, mirror: Arc<dyn SubscriptionMirror + Send + Sync>) {
+        self.subscription_mirroring.set_mirror(Some(mirror));
+    }
+
+    /// Returns the subscriptions that a peer has mirrored to the server
+    pub fn mirrored_subscriptions(&self) -> Arc<MirroredSubscriptions> {
+        self.mirrored_subscriptions.clone()
+    }
+
+    /// Returns the journal of address space changes, if persistence is enabled
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    /// Records an entry in the journal. The entry is only constructed if persistence is enabled.
+    pub(crate) fn journal_entry<F>(&self, f: F)
+    where
+        F: FnOnce() -> JournalEntry,
+    {
+        if let Some(ref journal) = self.journal {
+            journal.record(f());
+        }
+    }
+
+    /// Makes the entries recorded in the journal durable. This should be called after the
+    /// address space lock is released so that other services are not held up by the sync.
+    pub(crate) fn sync_journal(&self) {
+        if let Some(ref journal) = self.journal {
+            journal.sync();
+        }
+    }
+
     pub(crate) fn raise_and_log<T>(&self, event: T) -> Result<NodeId, ()>
     where
         T: AuditEvent + Event,
@@ -4,11 +4,12 @@
 
 use std::collections::{BTreeSet, VecDeque};
 use std::result::Result;
+use std::sync::Arc;
 
 use crate::types::{
     node_ids::ObjectId,
     service_types::{
-        DataChangeFilter, EventFieldList, EventFilter, MonitoredItemCreateRequest,
+        DataChangeFilter, DeadbandType, EventFieldList, EventFilter, MonitoredItemCreateRequest,
         MonitoredItemModifyRequest, MonitoredItemNotification, ReadValueId, TimestampsToReturn,
     },
     status_code::StatusCode,
@@ -18,6 +19,7 @@ use crate::types::{
 use crate::server::{
     address_space::{node::Node, AddressSpace, EventNotifier},
     events::event_filter,
+    node_manager::{NodeManager, NodeManagerRef},
     state::ServerState,
 };
 
@@ -95,15 +97,23 @@ pub(crate) struct MonitoredItem {
     client_handle: u32,
     sampling_interval: Duration,
     filter: FilterType,
+    /// The EURange of the monitored node, which a percent deadband is relative to. Only set for a
+    /// percent deadband.
+    eu_range: Option<(f64, f64)>,
     discard_oldest: bool,
     queue_size: usize,
     /// The notification queue is arranged from oldest to newest, i.e. pop front gets the oldest
     /// message, pop back gets the most recent.
     notification_queue: VecDeque<Notification>,
     queue_overflow: bool,
+    /// The number of times the queue overflowed and a notification was discarded
+    queue_overflow_count: u32,
     timestamps_to_return: TimestampsToReturn,
     last_sample_time: DateTimeUtc,
     last_data_value: Option<DataValue>,
+    /// The node manager of the monitored node, which is sampled instead of the address space
+    #[serde(skip)]
+    node_manager: Option<NodeManagerRef>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -144,13 +154,16 @@ impl MonitoredItem {
             client_handle: request.requested_parameters.client_handle,
             sampling_interval,
             filter,
+            eu_range: None,
             discard_oldest: request.requested_parameters.discard_oldest,
             timestamps_to_return,
             last_sample_time: *now,
             last_data_value: None,
+            node_manager: None,
             queue_size,
             notification_queue: VecDeque::with_capacity(queue_size),
             queue_overflow: false,
+            queue_overflow_count: 0,
         })
     }
 
@@ -168,6 +181,7 @@ impl MonitoredItem {
             &request.requested_parameters.filter,
             &server_state.decoding_options(),
         )?;
+        self.eu_range = None;
         self.sampling_interval = Self::sanitize_sampling_interval(
             server_state,
             request.requested_parameters.sampling_interval,
@@ -196,6 +210,26 @@ impl MonitoredItem {
         self.validate_filter(address_space)
     }
 
+    /// Registers the item with the node manager of its node, which is sampled for values from now
+    /// on. Fails if the node manager rejects the item.
+    pub(crate) fn register_with(
+        &mut self,
+        node_manager: Arc<dyn NodeManager>,
+    ) -> Result<(), StatusCode> {
+        node_manager.register_monitored_item(&self.item_to_monitor, self.sampling_interval)?;
+        self.node_manager = Some(NodeManagerRef(node_manager));
+        Ok(())
+    }
+
+    /// Unregisters the item from the node manager of its node, if it has one
+    pub(crate) fn unregister(&self) {
+        if let Some(ref node_manager) = self.node_manager {
+            node_manager
+                .0
+                .unregister_monitored_item(&self.item_to_monitor);
+        }
+    }
+
     /// Adds or removes other monitored items which will be triggered when this monitored item changes
     pub fn set_triggering(&mut self, items_to_add: &[u32], items_to_remove: &[u32]) {
         // Spec says to process remove items before adding new ones.
@@ -208,21 +242,36 @@ impl MonitoredItem {
     }
 
     /// Validates the filter associated with the monitored item and returns the filter result
-    /// encoded in an extension object.
+    /// encoded in an extension object. A percent deadband requires the monitored node to have an
+    /// EURange, which is read again whenever the value is sampled.
     pub fn validate_filter(
-        &self,
+        &mut self,
         address_space: &AddressSpace,
     ) -> Result<ExtensionObject, StatusCode> {
-        // Event filter must be validated
-        let filter_result = if let FilterType::EventFilter(ref event_filter) = self.filter {
-            let filter_result = event_filter::validate(event_filter, address_space)?;
-            ExtensionObject::from_encodable(
-                ObjectId::EventFilterResult_Encoding_DefaultBinary,
-                &filter_result,
-            )
-        } else {
-            // DataChangeFilter has no result
-            ExtensionObject::null()
+        let filter_result = match self.filter {
+            FilterType::EventFilter(ref event_filter) => {
+                // Event filter must be validated
+                let filter_result = event_filter::validate(event_filter, address_space)?;
+                ExtensionObject::from_encodable(
+                    ObjectId::EventFilterResult_Encoding_DefaultBinary,
+                    &filter_result,
+                )
+            }
+            FilterType::DataChangeFilter(ref filter) => {
+                if filter.deadband_type == DeadbandType::Percent as u32 {
+                    self.eu_range = address_space.eu_range(&self.item_to_monitor.node_id);
+                    if self.eu_range.is_none() {
+                        error!(
+                            "Percent deadband requested on node {:?} which has no EURange",
+                            self.item_to_monitor.node_id
+                        );
+                        return Err(StatusCode::BadFilterNotAllowed);
+                    }
+                }
+                // DataChangeFilter has no result
+                ExtensionObject::null()
+            }
+            FilterType::None => ExtensionObject::null(),
         };
         Ok(filter_result)
     }
@@ -335,19 +384,7 @@ impl MonitoredItem {
         }
     }
 
-    fn check_for_data_change(
-        &mut self,
-        _address_space: &AddressSpace,
-        resend_data: bool,
-        attribute_id: AttributeId,
-        node: &dyn Node,
-    ) -> bool {
-        let data_value = node.get_attribute(
-            TimestampsToReturn::Neither,
-            attribute_id,
-            NumericRange::None,
-            &QualifiedName::null(),
-        );
+    fn check_for_data_change(&mut self, resend_data: bool, data_value: Option<DataValue>) -> bool {
         if let Some(mut data_value) = data_value {
             // Test for data change
             let data_change = if resend_data {
@@ -359,7 +396,7 @@ impl MonitoredItem {
                 match self.filter {
                     FilterType::None => data_value.value != last_data_value.value,
                     FilterType::DataChangeFilter(ref filter) => {
-                        !filter.compare(&data_value, last_data_value, None)
+                        !filter.compare(&data_value, last_data_value, self.eu_range)
                     }
                     _ => {
                         // Unrecognized filter
@@ -425,7 +462,7 @@ impl MonitoredItem {
         }
     }
 
-    fn is_event_filter(&self) -> bool {
+    pub(crate) fn is_event_filter(&self) -> bool {
         matches!(self.filter, FilterType::EventFilter(_))
     }
 
@@ -443,7 +480,15 @@ impl MonitoredItem {
         if self.monitoring_mode == MonitoringMode::Disabled {
             panic!("Should not check value while monitoring mode is disabled");
         }
-        let changed = if let Some(node) = address_space.find_node(&self.item_to_monitor.node_id) {
+        let changed = if let Some(ref node_manager) = self.node_manager {
+            // Events are not sampled from node managers
+            if self.is_event_filter() {
+                false
+            } else {
+                let data_value = node_manager.0.sample(&self.item_to_monitor);
+                self.check_for_data_change(resend_data, data_value)
+            }
+        } else if let Some(node) = address_space.find_node(&self.item_to_monitor.node_id) {
             match AttributeId::from_u32(self.item_to_monitor.attribute_id) {
                 Ok(attribute_id) => {
                     let node = node.as_node();
@@ -457,12 +502,23 @@ impl MonitoredItem {
                                 false
                             }
                         }
-                        _ => self.check_for_data_change(
-                            address_space,
-                            resend_data,
-                            attribute_id,
-                            node,
-                        ),
+                        _ => {
+                            let data_value = node.get_attribute(
+                                TimestampsToReturn::Neither,
+                                attribute_id,
+                                NumericRange::None,
+                                &QualifiedName::null(),
+                            );
+                            if self.eu_range.is_some() {
+                                // The EURange property may be written while the item is monitored
+                                if let Some(eu_range) =
+                                    address_space.eu_range(&self.item_to_monitor.node_id)
+                                {
+                                    self.eu_range = Some(eu_range);
+                                }
+                            }
+                            self.check_for_data_change(resend_data, data_value)
+                        }
                     }
                 }
                 Err(_) => {
@@ -517,6 +573,7 @@ impl MonitoredItem {
                     Some(notification.value.status() | StatusCode::OVERFLOW);
             }
             self.queue_overflow = true;
+            self.queue_overflow_count += 1;
         }
         self.notification_queue.push_back(notification);
     }
@@ -606,6 +663,11 @@ impl MonitoredItem {
         self.queue_size
     }
 
+    /// Returns the number of times the queue overflowed and a notification was discarded
+    pub fn queue_overflow_count(&self) -> u32 {
+        self.queue_overflow_count
+    }
+
     #[cfg(test)]
     pub fn queue_overflow(&self) -> bool {
         self.queue_overflow
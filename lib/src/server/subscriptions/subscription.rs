@@ -9,7 +9,8 @@ use crate::sync::*;
 use crate::types::{
     service_types::{
         MonitoredItemCreateRequest, MonitoredItemCreateResult, MonitoredItemModifyRequest,
-        MonitoredItemModifyResult, NotificationMessage, TimestampsToReturn,
+        MonitoredItemModifyResult, NotificationMessage, SubscriptionDiagnosticsDataType,
+        TimestampsToReturn,
     },
     status_code::StatusCode,
     *,
@@ -21,6 +22,7 @@ use crate::server::{
     address_space::AddressSpace,
     constants,
     diagnostics::ServerDiagnostics,
+    redundancy::MirroredMonitoredItem,
     state::ServerState,
     subscriptions::monitored_item::{MonitoredItem, Notification, TickResult},
 };
@@ -106,6 +108,21 @@ pub(crate) enum TickReason {
     TickTimerFired,
 }
 
+/// Counts the activity of a subscription that is reported by its diagnostics
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct SubscriptionCounters {
+    modify_count: u32,
+    enable_count: u32,
+    disable_count: u32,
+    republish_request_count: u32,
+    republish_message_count: u32,
+    publish_request_count: u32,
+    data_change_notifications_count: u32,
+    event_notifications_count: u32,
+    late_publish_request_count: u32,
+    discarded_message_count: u32,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Subscription {
     /// Subscription id
@@ -120,6 +137,8 @@ pub struct Subscription {
     ///  needs to send notifications the highest priority subscription should
     /// be sent first.
     priority: u8,
+    /// The maximum number of notifications that the client wishes to receive in a publish response
+    max_notifications_per_publish: u32,
     /// Map of monitored items
     monitored_items: HashMap<u32, MonitoredItem>,
     /// State of the subscription
@@ -159,6 +178,8 @@ pub struct Subscription {
     /// Stops the subscription calling diagnostics on drop
     #[serde(skip)]
     diagnostics_on_drop: bool,
+    /// Counters reported by the subscription's diagnostics
+    counters: SubscriptionCounters,
 }
 
 impl Drop for Subscription {
@@ -166,6 +187,10 @@ impl Drop for Subscription {
         if self.diagnostics_on_drop {
             let mut diagnostics = trace_write_lock!(self.diagnostics);
             diagnostics.on_destroy_subscription(self);
+            // Node managers stop sampling the items of the subscription
+            self.monitored_items
+                .values()
+                .for_each(MonitoredItem::unregister);
         }
     }
 }
@@ -184,6 +209,7 @@ impl Subscription {
             subscription_id,
             publishing_interval,
             priority,
+            max_notifications_per_publish: 0,
             monitored_items: HashMap::with_capacity(constants::DEFAULT_MONITORED_ITEM_CAPACITY),
             max_lifetime_counter: lifetime_counter,
             max_keep_alive_counter: keep_alive_counter,
@@ -202,6 +228,7 @@ impl Subscription {
             notifications: VecDeque::with_capacity(100),
             diagnostics,
             diagnostics_on_drop: true,
+            counters: SubscriptionCounters::default(),
         };
         {
             let mut diagnostics = trace_write_lock!(subscription.diagnostics);
@@ -244,7 +271,12 @@ impl Subscription {
         items_to_create
             .iter()
             .map(|item_to_create| {
-                if !address_space.node_exists(&item_to_create.item_to_monitor.node_id) {
+                // Nodes of a node manager are not in the address space
+                let node_manager =
+                    address_space.node_manager_for(&item_to_create.item_to_monitor.node_id);
+                if node_manager.is_none()
+                    && !address_space.node_exists(&item_to_create.item_to_monitor.node_id)
+                {
                     Self::monitored_item_create_error(StatusCode::BadNodeIdUnknown)
                 } else {
                     // TODO validate the attribute id for the type of node
@@ -259,7 +291,7 @@ impl Subscription {
                         server_state,
                         item_to_create,
                     ) {
-                        Ok(monitored_item) => {
+                        Ok(mut monitored_item) => {
                             if server_state.max_monitored_items_per_sub == 0
                                 || self.monitored_items.len()
                                     <= server_state.max_monitored_items_per_sub
@@ -267,7 +299,15 @@ impl Subscription {
                                 let revised_sampling_interval = monitored_item.sampling_interval();
                                 let revised_queue_size = monitored_item.queue_size() as u32;
                                 // Validate the filter before registering the item
-                                match monitored_item.validate_filter(address_space) {
+                                let filter_result = monitored_item
+                                    .validate_filter(address_space)
+                                    .and_then(|filter_result| {
+                                        if let Some(node_manager) = node_manager {
+                                            monitored_item.register_with(node_manager)?;
+                                        }
+                                        Ok(filter_result)
+                                    });
+                                match filter_result {
                                     Ok(filter_result) => {
                                         // Register the item with the subscription
                                         self.monitored_items
@@ -364,6 +404,33 @@ impl Subscription {
         }
     }
 
+    /// Creates the monitored items of a subscription that is transferred from a peer server,
+    /// keeping the ids that the items have on the peer.
+    pub(crate) fn create_mirrored_monitored_items(
+        &mut self,
+        server_state: &ServerState,
+        address_space: &AddressSpace,
+        now: &DateTimeUtc,
+        items_to_create: &[MirroredMonitoredItem],
+    ) -> Vec<MonitoredItemCreateResult> {
+        let results = items_to_create
+            .iter()
+            .map(|item_to_create| {
+                self.next_monitored_item_id = item_to_create.monitored_item_id;
+                self.create_monitored_items(
+                    server_state,
+                    address_space,
+                    now,
+                    item_to_create.timestamps_to_return,
+                    std::slice::from_ref(&item_to_create.request),
+                )
+                .remove(0)
+            })
+            .collect();
+        self.next_monitored_item_id = self.monitored_items.keys().max().map_or(1, |id| id + 1);
+        results
+    }
+
     /// Delete the specified monitored items (by item id), returning a status code for each
     pub fn delete_monitored_items(&mut self, items_to_delete: &[u32]) -> Vec<StatusCode> {
         self.reset_lifetime_counter();
@@ -371,7 +438,10 @@ impl Subscription {
             .iter()
             .map(
                 |item_to_delete| match self.monitored_items.remove(item_to_delete) {
-                    Some(_) => StatusCode::Good,
+                    Some(monitored_item) => {
+                        monitored_item.unregister();
+                        StatusCode::Good
+                    }
                     None => StatusCode::BadMonitoredItemIdInvalid,
                 },
             )
@@ -456,6 +526,13 @@ impl Subscription {
         };
         self.resend_data = false;
 
+        let (notification, notification_counts) = match notification {
+            Some((notification, data_changes, events)) => {
+                (Some(notification), (data_changes, events))
+            }
+            None => (None, (0, 0)),
+        };
+
         let notifications_available = !self.notifications.is_empty() || notification.is_some();
         let more_notifications = self.notifications.len() > 1;
 
@@ -476,18 +553,19 @@ impl Subscription {
                 "subscription tick - update_state_result = {:?}",
                 update_state_result
             );
+            if notification.is_some()
+                && update_state_result.update_state_action == UpdateStateAction::ReturnNotifications
+            {
+                self.counters.data_change_notifications_count += notification_counts.0;
+                self.counters.event_notifications_count += notification_counts.1;
+            }
             self.handle_state_result(now, update_state_result, notification);
         }
     }
 
     fn enqueue_notification(&mut self, notification: NotificationMessage) {
-        use std::u32;
         // For sanity, check the sequence number is the expected sequence number.
-        let expected_sequence_number = if self.last_sequence_number == u32::MAX {
-            1
-        } else {
-            self.last_sequence_number + 1
-        };
+        let expected_sequence_number = self.next_sequence_number();
         if notification.sequence_number != expected_sequence_number {
             panic!(
                 "Notification's sequence number is not sequential, expecting {}, got {}",
@@ -713,6 +791,7 @@ impl Subscription {
                     // State #8
                     self.start_publishing_timer();
                     self.state = SubscriptionState::Late;
+                    self.counters.late_publish_request_count += 1;
                     return UpdateStateResult::new(
                         HandledState::IntervalElapsed8,
                         UpdateStateAction::None,
@@ -819,6 +898,7 @@ impl Subscription {
                     // State #17
                     self.start_publishing_timer();
                     self.state = SubscriptionState::Late;
+                    self.counters.late_publish_request_count += 1;
                     return UpdateStateResult::new(
                         HandledState::KeepAlive17,
                         UpdateStateAction::None,
@@ -839,14 +919,15 @@ impl Subscription {
     /// collected together when the publish interval elapsed flag is `true`.
     ///
     /// The function returns a `notifications` and a `more_notifications` boolean to indicate if the notifications
-    /// are available.
+    /// are available. The notification message is returned with the number of data changes and
+    /// events that it holds.
     fn tick_monitored_items(
         &mut self,
         now: &DateTimeUtc,
         address_space: &AddressSpace,
         publishing_interval_elapsed: bool,
         resend_data: bool,
-    ) -> Option<NotificationMessage> {
+    ) -> Option<(NotificationMessage, u32, u32)> {
         let mut triggered_items: BTreeSet<u32> = BTreeSet::new();
         let mut monitored_item_notifications = Vec::with_capacity(self.monitored_items.len() * 2);
 
@@ -948,7 +1029,7 @@ impl Subscription {
             );
 
             // Collect all datachange notifications
-            let data_change_notifications = monitored_item_notifications
+            let data_change_notifications: Vec<_> = monitored_item_notifications
                 .iter()
                 .filter(|v| matches!(v, Notification::MonitoredItemNotification(_)))
                 .map(|v| {
@@ -961,7 +1042,7 @@ impl Subscription {
                 .collect();
 
             // Collect event notifications
-            let event_notifications = monitored_item_notifications
+            let event_notifications: Vec<_> = monitored_item_notifications
                 .iter()
                 .filter(|v| matches!(v, Notification::Event(_)))
                 .map(|v| {
@@ -974,13 +1055,17 @@ impl Subscription {
                 .collect();
 
             // Make a notification
+            let counts = (
+                data_change_notifications.len() as u32,
+                event_notifications.len() as u32,
+            );
             let notification = NotificationMessage::data_change(
                 next_sequence_number,
                 DateTime::from(*now),
                 data_change_notifications,
                 event_notifications,
             );
-            Some(notification)
+            Some((notification, counts.0, counts.1))
         } else {
             None
         }
@@ -1084,6 +1169,127 @@ impl Subscription {
         self.reset_lifetime_counter();
     }
 
+    pub fn max_notifications_per_publish(&self) -> u32 {
+        self.max_notifications_per_publish
+    }
+
+    pub(crate) fn set_max_notifications_per_publish(&mut self, max_notifications_per_publish: u32) {
+        self.max_notifications_per_publish = max_notifications_per_publish;
+    }
+
+    /// Counts a ModifySubscription request for the subscription
+    pub(crate) fn on_modify(&mut self) {
+        self.counters.modify_count += 1;
+    }
+
+    /// Counts a SetPublishingMode request that enables or disables the subscription
+    pub(crate) fn on_set_publishing_mode(&mut self, publishing_enabled: bool) {
+        if publishing_enabled {
+            self.counters.enable_count += 1;
+        } else {
+            self.counters.disable_count += 1;
+        }
+    }
+
+    /// Counts a Republish request for the subscription and whether the message was republished
+    pub(crate) fn on_republish(&mut self, republished: bool) {
+        self.counters.republish_request_count += 1;
+        if republished {
+            self.counters.republish_message_count += 1;
+        }
+    }
+
+    /// Counts a publish request that is used to send a notification message of the subscription
+    pub(crate) fn on_publish_request(&mut self) {
+        self.counters.publish_request_count += 1;
+    }
+
+    /// Counts a notification message that is discarded before the client acknowledged it
+    pub(crate) fn on_discarded_message(&mut self) {
+        self.counters.discarded_message_count += 1;
+    }
+
+    /// Returns the sampling interval of every monitored item, and whether the item is disabled.
+    /// Items that sample at the publishing interval return the publishing interval.
+    pub(crate) fn sampling_intervals(&self) -> impl Iterator<Item = (Duration, bool)> + '_ {
+        self.monitored_items.values().map(move |monitored_item| {
+            let sampling_interval = if monitored_item.sampling_interval() < 0f64 {
+                self.publishing_interval
+            } else {
+                monitored_item.sampling_interval()
+            };
+            let disabled = monitored_item.monitoring_mode() == MonitoringMode::Disabled;
+            (sampling_interval, disabled)
+        })
+    }
+
+    /// Returns the diagnostics of the subscription. The unacknowledged messages are held by the
+    /// session, so their count is supplied.
+    pub(crate) fn diagnostics(
+        &self,
+        session_id: &NodeId,
+        unacknowledged_message_count: u32,
+    ) -> SubscriptionDiagnosticsDataType {
+        let mut disabled_monitored_item_count = 0;
+        let mut monitoring_queue_overflow_count = 0;
+        let mut event_queue_over_flow_count = 0;
+        for monitored_item in self.monitored_items.values() {
+            if monitored_item.monitoring_mode() == MonitoringMode::Disabled {
+                disabled_monitored_item_count += 1;
+            }
+            if monitored_item.is_event_filter() {
+                event_queue_over_flow_count += monitored_item.queue_overflow_count();
+            } else {
+                monitoring_queue_overflow_count += monitored_item.queue_overflow_count();
+            }
+        }
+        let counters = &self.counters;
+        SubscriptionDiagnosticsDataType {
+            session_id: session_id.clone(),
+            subscription_id: self.subscription_id,
+            priority: self.priority,
+            publishing_interval: self.publishing_interval,
+            max_keep_alive_count: self.max_keep_alive_counter,
+            max_lifetime_count: self.max_lifetime_counter,
+            max_notifications_per_publish: self.max_notifications_per_publish,
+            publishing_enabled: self.publishing_enabled,
+            modify_count: counters.modify_count,
+            enable_count: counters.enable_count,
+            disable_count: counters.disable_count,
+            republish_request_count: counters.republish_request_count,
+            // Each republish request asks for one message
+            republish_message_request_count: counters.republish_request_count,
+            republish_message_count: counters.republish_message_count,
+            transfer_request_count: 0,
+            transferred_to_alt_client_count: 0,
+            transferred_to_same_client_count: 0,
+            publish_request_count: counters.publish_request_count,
+            data_change_notifications_count: counters.data_change_notifications_count,
+            event_notifications_count: counters.event_notifications_count,
+            notifications_count: counters.data_change_notifications_count
+                + counters.event_notifications_count,
+            late_publish_request_count: counters.late_publish_request_count,
+            current_keep_alive_count: self.keep_alive_counter,
+            current_lifetime_count: self.lifetime_counter,
+            unacknowledged_message_count,
+            discarded_message_count: counters.discarded_message_count,
+            monitored_item_count: self.monitored_items.len() as u32,
+            disabled_monitored_item_count,
+            monitoring_queue_overflow_count,
+            next_sequence_number: self.next_sequence_number(),
+            event_queue_over_flow_count,
+        }
+    }
+
+    /// Returns the sequence number of the next notification message
+    fn next_sequence_number(&self) -> u32 {
+        if self.last_sequence_number == u32::MAX {
+            1
+        } else {
+            self.last_sequence_number + 1
+        }
+    }
+
     pub(crate) fn set_diagnostics_on_drop(&mut self, diagnostics_on_drop: bool) {
         self.diagnostics_on_drop = diagnostics_on_drop;
     }
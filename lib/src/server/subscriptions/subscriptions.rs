@@ -5,7 +5,10 @@
 use std::collections::{BTreeMap, VecDeque};
 
 use crate::types::{
-    service_types::{NotificationMessage, PublishRequest, PublishResponse, ServiceFault},
+    service_types::{
+        NotificationMessage, PublishRequest, PublishResponse, ServiceFault,
+        SubscriptionDiagnosticsDataType,
+    },
     status_code::StatusCode,
     *,
 };
@@ -238,6 +241,7 @@ impl Subscriptions {
                 if !self.publish_request_queue.is_empty() {
                     if let Some(notification_message) = subscription.take_notification() {
                         let publish_request = self.publish_request_queue.pop_back().unwrap();
+                        subscription.on_publish_request();
                         // Consume the publish request and queue the notification onto the transmission queue
                         self.transmission_queue.push_front((
                             subscription_id,
@@ -491,7 +495,44 @@ impl Subscriptions {
                 .take(remove_count)
                 .map(|(k, _)| *k)
                 .collect::<Vec<_>>();
+            for (subscription_id, _) in &sequence_nrs_to_remove {
+                if let Some(subscription) = self.subscriptions.get_mut(subscription_id) {
+                    subscription.on_discarded_message();
+                }
+            }
             self.remove_notifications(&sequence_nrs_to_remove);
         }
     }
+
+    /// Returns the number of publish requests that are queued waiting for notifications
+    pub(crate) fn publish_request_queue_len(&self) -> usize {
+        self.publish_request_queue.len()
+    }
+
+    /// Returns the number of monitored items across all subscriptions
+    pub(crate) fn monitored_item_count(&self) -> usize {
+        self.subscriptions
+            .values()
+            .map(|s| s.monitored_items_len())
+            .sum()
+    }
+
+    /// Returns the diagnostics of every subscription
+    pub(crate) fn subscription_diagnostics(
+        &self,
+        session_id: &NodeId,
+    ) -> Vec<SubscriptionDiagnosticsDataType> {
+        self.subscriptions
+            .values()
+            .map(|subscription| {
+                let subscription_id = subscription.subscription_id();
+                let unacknowledged_message_count = self
+                    .retransmission_queue
+                    .keys()
+                    .filter(|k| k.0 == subscription_id)
+                    .count() as u32;
+                subscription.diagnostics(session_id, unacknowledged_message_count)
+            })
+            .collect()
+    }
 }
@@ -656,6 +656,43 @@ fn variable_builder() {
     ));
 }
 
+#[test]
+fn variable_value_handle() {
+    let mut address_space = AddressSpace::new();
+    let node_id = NodeId::new(1, "Counter");
+    VariableBuilder::new(&node_id, "Counter", "Counter")
+        .data_type(DataTypeId::Int32)
+        .value(0i32)
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+    assert!(address_space
+        .value_handle(NodeId::new(1, "Missing"))
+        .is_none());
+
+    // Values are set through a shared reference to the address space
+    let address_space = Arc::new(RwLock::new(address_space));
+    let now = DateTime::now();
+    {
+        let address_space = trace_read_lock!(address_space);
+        assert!(address_space.set_variable_value(node_id.clone(), 1i32, &now, &now));
+    }
+
+    // A handle sets the value without any lock on the address space
+    let handle = {
+        let address_space = trace_read_lock!(address_space);
+        address_space.value_handle(node_id.clone()).unwrap()
+    };
+    assert_eq!(handle.get().value, Some(Variant::from(1i32)));
+    let _lock = trace_write_lock!(address_space);
+    handle.set(2i32, StatusCode::UncertainLastUsableValue, &now, &now);
+    drop(_lock);
+
+    let address_space = trace_read_lock!(address_space);
+    let value = address_space.get_variable_value(node_id).unwrap();
+    assert_eq!(value.value, Some(Variant::from(2i32)));
+    assert_eq!(value.status, Some(StatusCode::UncertainLastUsableValue));
+}
+
 #[test]
 fn method_builder() {
     let mut address_space = AddressSpace::new();
@@ -952,3 +989,572 @@ fn hierarchical_references() {
     assert!(refs.contains(&ObjectId::Server_ServerCapabilities_AggregateFunctions.into()));
     assert!(refs.contains(&ObjectId::HistoryServerCapabilities.into()));
 }
+
+#[test]
+fn export_nodeset() {
+    let mut address_space = AddressSpace::new();
+    let _ = address_space.register_namespace("urn:other").unwrap();
+    let ns = address_space
+        .register_namespace("urn:export & test")
+        .unwrap();
+
+    let folder_id = NodeId::new(ns, "folder");
+    address_space.add_folder_with_id(
+        &folder_id,
+        QualifiedName::new(ns, "Folder"),
+        "Folder",
+        &NodeId::objects_folder_id(),
+    );
+    VariableBuilder::new(&NodeId::new(ns, "v1"), "v1", "Variable <1>")
+        .data_type(DataTypeId::Int32)
+        .value(42i32)
+        .writable()
+        .component_of(folder_id.clone())
+        .insert(&mut address_space);
+    VariableBuilder::new(&NodeId::new(ns, "v2"), "v2", "v2")
+        .data_type(DataTypeId::String)
+        .value_rank(1)
+        .value((
+            VariantTypeId::String,
+            vec![Variant::from("a"), Variant::from("b")],
+        ))
+        .component_of(folder_id.clone())
+        .insert(&mut address_space);
+    // The folder organizes v3 as well as having it as a component
+    let other_folder_id = NodeId::new(ns, "another folder");
+    address_space.add_folder_with_id(
+        &other_folder_id,
+        QualifiedName::new(ns, "AnotherFolder"),
+        "AnotherFolder",
+        &NodeId::objects_folder_id(),
+    );
+    VariableBuilder::new(&NodeId::new(ns, "v3"), "v3", "v3")
+        .data_type(DataTypeId::Float)
+        .value(0.1f32)
+        .organized_by(other_folder_id.clone())
+        .component_of(folder_id.clone())
+        .insert(&mut address_space);
+
+    let xml = address_space.export_nodeset_to_string(Some(&[ns]));
+
+    // Only the exported namespace is in the table, and it is renumbered to 1
+    assert!(
+        xml.contains("<NamespaceUris>\n    <Uri>urn:export &amp; test</Uri>\n  </NamespaceUris>")
+    );
+    assert!(!xml.contains("urn:other"));
+    assert!(xml.contains(r#"<Alias Alias="HasComponent">i=47</Alias>"#));
+    assert!(xml.contains(r#"<Alias Alias="Int32">i=6</Alias>"#));
+
+    // Nodes outside of the namespace are not exported, but references to them are kept
+    assert!(!xml.contains(r#"<UAObject NodeId="i=85""#));
+    assert!(xml.contains(
+        r#"<UAObject NodeId="ns=1;s=folder" BrowseName="1:Folder" ParentNodeId="i=85">"#
+    ));
+    assert!(
+        xml.contains(r#"<Reference ReferenceType="Organizes" IsForward="false">i=85</Reference>"#)
+    );
+    assert!(xml.contains(r#"<Reference ReferenceType="HasComponent">ns=1;s=v1</Reference>"#));
+
+    // Variables and their values
+    assert!(xml.contains(r#"<UAVariable NodeId="ns=1;s=v1" BrowseName="v1" ParentNodeId="ns=1;s=folder" DataType="Int32" AccessLevel="3" UserAccessLevel="3">"#));
+    assert!(xml.contains("<DisplayName>Variable &lt;1&gt;</DisplayName>"));
+    assert!(xml.contains("<Value><uax:Int32>42</uax:Int32></Value>"));
+    assert!(xml.contains(r#"ValueRank="1""#));
+    assert!(xml.contains(
+        "<Value><uax:ListOfString><uax:String>a</uax:String><uax:String>b</uax:String></uax:ListOfString></Value>"
+    ));
+
+    // The parent of a component is the node that has it as a component, and a Float is written
+    // with its own digits
+    assert!(xml.contains(
+        r#"<UAVariable NodeId="ns=1;s=v3" BrowseName="v3" ParentNodeId="ns=1;s=folder" DataType="Float""#
+    ));
+    assert!(xml.contains("<Value><uax:Float>0.1</uax:Float></Value>"));
+    assert_eq!(xml, address_space.export_nodeset_to_string(Some(&[ns])));
+
+    // Exporting everything includes the standard nodes
+    let xml = address_space.export_nodeset_to_string(None);
+    assert!(xml.contains(r#"<UAObject NodeId="i=2253" BrowseName="Server""#));
+    assert!(xml.contains("<Uri>urn:export &amp; test</Uri>"));
+}
+
+/// Adds a DeviceType with a subtype PumpType that has a Motor of MotorType, and a placeholder
+/// for sensors.
+fn add_device_types(address_space: &mut AddressSpace) -> (NodeId, NodeId) {
+    let device_type_id = NodeId::new(1, "DeviceType");
+    ObjectTypeBuilder::new(&device_type_id, "DeviceType", "DeviceType")
+        .subtype_of(ObjectTypeId::BaseObjectType)
+        .insert(address_space);
+    VariableBuilder::new(
+        &NodeId::new(1, "DeviceType.SerialNumber"),
+        "SerialNumber",
+        "SerialNumber",
+    )
+    .data_type(DataTypeId::String)
+    .value("")
+    .property_of(device_type_id.clone())
+    .has_type_definition(VariableTypeId::PropertyType)
+    .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+    .insert(address_space);
+    VariableBuilder::new(
+        &NodeId::new(1, "DeviceType.Location"),
+        "Location",
+        "Location",
+    )
+    .data_type(DataTypeId::String)
+    .value("")
+    .property_of(device_type_id.clone())
+    .has_type_definition(VariableTypeId::PropertyType)
+    .has_modelling_rule(ObjectId::ModellingRule_Optional)
+    .insert(address_space);
+
+    let motor_type_id = NodeId::new(1, "MotorType");
+    ObjectTypeBuilder::new(&motor_type_id, "MotorType", "MotorType")
+        .subtype_of(ObjectTypeId::BaseObjectType)
+        .insert(address_space);
+    VariableBuilder::new(&NodeId::new(1, "MotorType.Speed"), "Speed", "Speed")
+        .data_type(DataTypeId::Double)
+        .value(0f64)
+        .component_of(motor_type_id.clone())
+        .has_type_definition(VariableTypeId::BaseDataVariableType)
+        .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+        .insert(address_space);
+    VariableBuilder::new(
+        &NodeId::new(1, "MotorType.Temperature"),
+        "Temperature",
+        "Temperature",
+    )
+    .data_type(DataTypeId::Double)
+    .value(20f64)
+    .component_of(motor_type_id.clone())
+    .has_type_definition(VariableTypeId::BaseDataVariableType)
+    .has_modelling_rule(ObjectId::ModellingRule_Optional)
+    .insert(address_space);
+
+    let pump_type_id = NodeId::new(1, "PumpType");
+    ObjectTypeBuilder::new(&pump_type_id, "PumpType", "PumpType")
+        .subtype_of(device_type_id)
+        .insert(address_space);
+    ObjectBuilder::new(&NodeId::new(1, "PumpType.Motor"), "Motor", "Motor")
+        .component_of(pump_type_id.clone())
+        .has_type_definition(motor_type_id.clone())
+        .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+        .insert(address_space);
+    VariableBuilder::new(&NodeId::new(1, "PumpType.Sensor"), "<Sensor>", "<Sensor>")
+        .data_type(DataTypeId::Double)
+        .value(0f64)
+        .component_of(pump_type_id.clone())
+        .has_type_definition(VariableTypeId::BaseDataVariableType)
+        .has_modelling_rule(ObjectId::ModellingRule_OptionalPlaceholder)
+        .insert(address_space);
+
+    (pump_type_id, motor_type_id)
+}
+
+#[test]
+fn instantiate_mandatory() {
+    let mut address_space = AddressSpace::new();
+    let (pump_type_id, motor_type_id) = add_device_types(&mut address_space);
+
+    let nodes = address_space
+        .instantiate(
+            pump_type_id.clone(),
+            &NodeId::objects_folder_id(),
+            "Pump1",
+            NodeIdStrategy::BrowsePath(1),
+        )
+        .unwrap();
+
+    // Mandatory children of the type and its supertype are created, optional ones are not
+    let paths = nodes.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            "Pump1",
+            "Pump1/Motor",
+            "Pump1/Motor/Speed",
+            "Pump1/SerialNumber"
+        ]
+    );
+    assert_eq!(nodes["Pump1"], NodeId::new(1, "Pump1"));
+    assert_eq!(
+        nodes["Pump1/Motor/Speed"],
+        NodeId::new(1, "Pump1/Motor/Speed")
+    );
+
+    // Type definitions and references are as declared
+    let pump1 = &nodes["Pump1"];
+    assert!(address_space.has_reference(
+        &NodeId::objects_folder_id(),
+        pump1,
+        ReferenceTypeId::Organizes
+    ));
+    assert!(address_space.has_reference(pump1, &pump_type_id, ReferenceTypeId::HasTypeDefinition));
+    let motor = &nodes["Pump1/Motor"];
+    assert!(address_space.has_reference(pump1, motor, ReferenceTypeId::HasComponent));
+    assert!(address_space.has_reference(motor, &motor_type_id, ReferenceTypeId::HasTypeDefinition));
+    assert!(address_space.has_reference(
+        pump1,
+        &nodes["Pump1/SerialNumber"],
+        ReferenceTypeId::HasProperty
+    ));
+    assert!(address_space.has_reference(
+        &nodes["Pump1/SerialNumber"],
+        &VariableTypeId::PropertyType.into(),
+        ReferenceTypeId::HasTypeDefinition
+    ));
+
+    // A second instance with the same browse name is rejected
+    assert_eq!(
+        address_space
+            .instantiate(
+                pump_type_id,
+                &NodeId::objects_folder_id(),
+                "Pump1",
+                NodeIdStrategy::Numeric(1),
+            )
+            .unwrap_err(),
+        StatusCode::BadBrowseNameDuplicated
+    );
+
+    // Abstract or non-type nodes cannot be instantiated
+    assert_eq!(
+        address_space
+            .instantiate(
+                ObjectTypeId::BaseEventType,
+                &NodeId::objects_folder_id(),
+                "Event",
+                NodeIdStrategy::Numeric(1),
+            )
+            .unwrap_err(),
+        StatusCode::BadTypeDefinitionInvalid
+    );
+}
+
+#[test]
+fn instantiate_recursive_type() {
+    let mut address_space = AddressSpace::new();
+
+    // The type has a Mandatory child of the same type through another type
+    let a_type_id = NodeId::new(1, "AType");
+    let b_type_id = NodeId::new(1, "BType");
+    for (type_id, name) in &[(&a_type_id, "AType"), (&b_type_id, "BType")] {
+        ObjectTypeBuilder::new(type_id, *name, *name)
+            .subtype_of(ObjectTypeId::BaseObjectType)
+            .insert(&mut address_space);
+    }
+    ObjectBuilder::new(&NodeId::new(1, "AType.B"), "B", "B")
+        .component_of(a_type_id.clone())
+        .has_type_definition(b_type_id.clone())
+        .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+        .insert(&mut address_space);
+    ObjectBuilder::new(&NodeId::new(1, "BType.A"), "A", "A")
+        .component_of(b_type_id)
+        .has_type_definition(a_type_id.clone())
+        .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+        .insert(&mut address_space);
+
+    assert_eq!(
+        address_space
+            .instantiate(
+                a_type_id,
+                &NodeId::objects_folder_id(),
+                "A1",
+                NodeIdStrategy::BrowsePath(1),
+            )
+            .unwrap_err(),
+        StatusCode::BadInvalidArgument
+    );
+    // The nodes that were created before the error was found are removed
+    for node_id in &["A1", "A1/B", "A1/B/A"] {
+        assert!(address_space.find_node(&NodeId::new(1, *node_id)).is_none());
+    }
+}
+
+#[test]
+fn instantiate_existing_child_node_id() {
+    let mut address_space = AddressSpace::new();
+    let (pump_type_id, _) = add_device_types(&mut address_space);
+
+    // A node that is not a child of the instance has the node id its Mandatory child would get
+    let conflicting_id = NodeId::new(1, "Pump3/Motor/Speed");
+    VariableBuilder::new(&conflicting_id, "Speed", "Speed")
+        .data_type(DataTypeId::Double)
+        .value(0f64)
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+
+    assert_eq!(
+        address_space
+            .instantiate(
+                pump_type_id,
+                &NodeId::objects_folder_id(),
+                "Pump3",
+                NodeIdStrategy::BrowsePath(1),
+            )
+            .unwrap_err(),
+        StatusCode::BadNodeIdExists
+    );
+    // The nodes that were created before the error was found are removed, the existing node is not
+    for node_id in &["Pump3", "Pump3/Motor", "Pump3/SerialNumber"] {
+        assert!(address_space.find_node(&NodeId::new(1, *node_id)).is_none());
+    }
+    assert!(address_space.node_exists(&conflicting_id));
+}
+
+#[test]
+fn instantiate_with_options() {
+    let mut address_space = AddressSpace::new();
+    let (pump_type_id, _) = add_device_types(&mut address_space);
+
+    let options = InstantiateOptions::new()
+        .optional("Pump2/Location")
+        .optional("Pump2/Motor/Temperature")
+        .placeholder("Pump2/<Sensor>", "Pressure")
+        .placeholder("Pump2/<Sensor>", "Flow");
+    let nodes = address_space
+        .instantiate_with_options(
+            pump_type_id,
+            &NodeId::objects_folder_id(),
+            "Pump2",
+            NodeIdStrategy::Numeric(1),
+            &options,
+        )
+        .unwrap();
+
+    let paths = nodes.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        vec![
+            "Pump2",
+            "Pump2/Flow",
+            "Pump2/Location",
+            "Pump2/Motor",
+            "Pump2/Motor/Speed",
+            "Pump2/Motor/Temperature",
+            "Pump2/Pressure",
+            "Pump2/SerialNumber",
+        ]
+    );
+    assert!(nodes.values().all(|node_id| node_id.namespace == 1
+        && node_id.is_numeric()
+        && address_space.node_exists(node_id)));
+
+    // Values are copied from the declaration and placeholder nodes are named after themselves
+    let temperature = address_space
+        .find_variable_by_ref(&nodes["Pump2/Motor/Temperature"])
+        .unwrap();
+    assert_eq!(temperature.value_rank(), -1);
+    let value = temperature.value(
+        TimestampsToReturn::Neither,
+        NumericRange::None,
+        &QualifiedName::null(),
+        0.0,
+    );
+    assert_eq!(value.value, Some(Variant::Double(20f64)));
+    let pressure = address_space.find_node(&nodes["Pump2/Pressure"]).unwrap();
+    assert_eq!(
+        pressure.as_node().browse_name(),
+        QualifiedName::from("Pressure")
+    );
+    assert_eq!(
+        pressure.as_node().display_name(),
+        LocalizedText::from("Pressure")
+    );
+}
+
+fn property_value(address_space: &AddressSpace, node_id: &NodeId, browse_name: &str) -> Variant {
+    address_space
+        .find_property(node_id, browse_name)
+        .unwrap()
+        .value(
+            TimestampsToReturn::Neither,
+            NumericRange::None,
+            &QualifiedName::null(),
+            0.0,
+        )
+        .value
+        .unwrap()
+}
+
+#[test]
+fn analog_item_builder() {
+    let mut address_space = AddressSpace::new();
+
+    let node_id = NodeId::new(1, "Temperature");
+    let units = EUInformation::from_unece_code("CEL", "°C", "degree Celsius").unwrap();
+    assert_eq!(units.unit_id, 4408652);
+    assert!(EUInformation::from_unece_code("ABCD", "", "").is_none());
+
+    assert!(AnalogItemBuilder::new_unit_range(
+        VariableBuilder::new(&node_id, "Temperature", "Temperature")
+            .data_type(DataTypeId::Double)
+            .value(20f64)
+            .organized_by(ObjectId::ObjectsFolder),
+    )
+    .eu_range(-20f64, 120f64)
+    .instrument_range(-50f64, 150f64)
+    .engineering_units(units.clone())
+    .insert(&mut address_space));
+
+    assert!(address_space.has_reference(
+        &node_id,
+        &VariableTypeId::AnalogUnitRangeType.into(),
+        ReferenceTypeId::HasTypeDefinition
+    ));
+    assert_eq!(address_space.eu_range(&node_id), Some((-20f64, 120f64)));
+    if let Variant::ExtensionObject(value) =
+        property_value(&address_space, &node_id, "EngineeringUnits")
+    {
+        let value = value
+            .decode_inner::<EUInformation>(&DecodingOptions::default())
+            .unwrap();
+        assert_eq!(value.unit_id, units.unit_id);
+        assert_eq!(value.display_name.text, units.display_name.text);
+        assert_eq!(value.namespace_uri, units.namespace_uri);
+    } else {
+        panic!("EngineeringUnits is not an extension object");
+    }
+    assert!(address_space
+        .find_property(&node_id, "InstrumentRange")
+        .is_some());
+
+    // A variable that is not an analog item has no EURange
+    assert_eq!(
+        address_space.eu_range(&ObjectId::ObjectsFolder.into()),
+        None
+    );
+}
+
+#[test]
+#[should_panic]
+fn analog_item_builder_no_eu_range() {
+    let mut address_space = AddressSpace::new();
+    AnalogItemBuilder::new(
+        VariableBuilder::new(&NodeId::new(1, "Level"), "Level", "Level").value(0f64),
+    )
+    .insert(&mut address_space);
+}
+
+#[test]
+fn discrete_item_builders() {
+    let mut address_space = AddressSpace::new();
+
+    let two_state_id = NodeId::new(1, "Running");
+    TwoStateDiscreteBuilder::new(
+        VariableBuilder::new(&two_state_id, "Running", "Running")
+            .data_type(DataTypeId::Boolean)
+            .value(false),
+        "Running",
+        "Stopped",
+    )
+    .insert(&mut address_space);
+    assert_eq!(
+        property_value(&address_space, &two_state_id, "TrueState"),
+        Variant::from(LocalizedText::from("Running"))
+    );
+    assert_eq!(
+        property_value(&address_space, &two_state_id, "FalseState"),
+        Variant::from(LocalizedText::from("Stopped"))
+    );
+
+    let multi_state_id = NodeId::new(1, "Mode");
+    MultiStateDiscreteBuilder::new(
+        VariableBuilder::new(&multi_state_id, "Mode", "Mode")
+            .data_type(DataTypeId::UInt32)
+            .value(0u32),
+        vec!["Manual", "Automatic"],
+    )
+    .insert(&mut address_space);
+    if let Variant::Array(array) = property_value(&address_space, &multi_state_id, "EnumStrings") {
+        assert_eq!(
+            array.values,
+            vec![
+                Variant::from(LocalizedText::from("Manual")),
+                Variant::from(LocalizedText::from("Automatic"))
+            ]
+        );
+    } else {
+        panic!("EnumStrings is not an array");
+    }
+
+    let value_id = NodeId::new(1, "Valve");
+    let enum_values = [
+        EnumValueType {
+            value: 1,
+            display_name: LocalizedText::from("Open"),
+            description: LocalizedText::null(),
+        },
+        EnumValueType {
+            value: 4,
+            display_name: LocalizedText::from("Closed"),
+            description: LocalizedText::null(),
+        },
+    ];
+    MultiStateValueDiscreteBuilder::new(
+        VariableBuilder::new(&value_id, "Valve", "Valve")
+            .data_type(DataTypeId::Int32)
+            .value(4i32)
+            .organized_by(ObjectId::ObjectsFolder),
+        &enum_values,
+    )
+    .insert(&mut address_space);
+    assert!(address_space.has_reference(
+        &value_id,
+        &VariableTypeId::MultiStateValueDiscreteType.into(),
+        ReferenceTypeId::HasTypeDefinition
+    ));
+    assert!(address_space.has_reference(
+        &ObjectId::ObjectsFolder.into(),
+        &value_id,
+        ReferenceTypeId::Organizes
+    ));
+    assert_eq!(
+        property_value(&address_space, &value_id, "ValueAsText"),
+        Variant::from(LocalizedText::from("Closed"))
+    );
+    if let Variant::Array(array) = property_value(&address_space, &value_id, "EnumValues") {
+        assert_eq!(array.values.len(), 2);
+    } else {
+        panic!("EnumValues is not an array");
+    }
+}
+
+#[test]
+fn server_redundancy() {
+    let mut address_space = AddressSpace::new();
+    let server_redundancy_id: NodeId = ObjectId::Server_ServerRedundancy.into();
+    let server_uris = vec!["urn:a".to_string(), "urn:b".to_string()];
+    address_space.set_server_redundancy(RedundancySupport::Warm, &server_uris);
+
+    let redundancy_support =
+        property_value(&address_space, &server_redundancy_id, "RedundancySupport");
+    assert_eq!(
+        redundancy_support,
+        Variant::Int32(RedundancySupport::Warm as i32)
+    );
+
+    // The server redundancy is a non-transparent redundancy object with the uris of the set
+    let type_definitions = address_space
+        .find_references(
+            &server_redundancy_id,
+            Some((ReferenceTypeId::HasTypeDefinition, false)),
+        )
+        .unwrap();
+    assert_eq!(type_definitions.len(), 1);
+    assert_eq!(
+        type_definitions[0].target_node,
+        ObjectTypeId::NonTransparentRedundancyType.into()
+    );
+    assert_eq!(
+        property_value(&address_space, &server_redundancy_id, "ServerUriArray"),
+        Variant::from(&server_uris[..])
+    );
+
+    // Setting it again updates the uris
+    address_space.set_server_redundancy(RedundancySupport::Hot, &server_uris[..1]);
+    assert_eq!(
+        property_value(&address_space, &server_redundancy_id, "ServerUriArray"),
+        Variant::from(&server_uris[..1])
+    );
+}
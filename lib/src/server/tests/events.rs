@@ -9,7 +9,9 @@ use crate::types::{
 };
 
 use crate::server::{
-    address_space::{object_type::ObjectTypeBuilder, variable::VariableBuilder, AddressSpace},
+    address_space::{
+        object_type::ObjectTypeBuilder, variable::VariableBuilder, view::ViewBuilder, AddressSpace,
+    },
     events::event::{BaseEventType, Event},
     events::event_filter,
     events::operator,
@@ -788,6 +790,182 @@ fn test_bitwise_and() {
     })
 }
 
+#[test]
+fn test_of_type() {
+    do_operator_test(|address_space, object_id, used_elements, elements| {
+        let expected = vec![
+            (TestEventType::event_type_id(), true),
+            (ObjectTypeId::BaseEventType.into(), true),
+            (ObjectTypeId::FolderType.into(), false),
+        ];
+        expected.into_iter().for_each(|(type_id, expected)| {
+            let operands = &[Operand::literal(type_id)];
+            let result = operator::of_type(
+                object_id,
+                &operands[..],
+                used_elements,
+                elements,
+                address_space,
+            )
+            .unwrap();
+            assert_eq!(result, Variant::Boolean(expected));
+        });
+    })
+}
+
+#[test]
+fn test_in_view() {
+    crate::console_logging::init();
+
+    let mut address_space = address_space();
+    let view_id = NodeId::new(2, "View1");
+    ViewBuilder::new(&view_id, "View1", "View1")
+        .organized_by(ObjectId::ViewsFolder)
+        .organizes(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+    let other_view_id = NodeId::new(2, "View2");
+    ViewBuilder::new(&other_view_id, "View2", "View2")
+        .organized_by(ObjectId::ViewsFolder)
+        .organizes(ObjectId::TypesFolder)
+        .insert(&mut address_space);
+
+    let mut used_elements = HashSet::new();
+    let expected = vec![(view_id, true), (other_view_id, false)];
+    expected.into_iter().for_each(|(view_id, expected)| {
+        let operands = &[Operand::literal(view_id)];
+        let result = operator::in_view(
+            &event_id(),
+            &operands[..],
+            &mut used_elements,
+            &[],
+            &address_space,
+        )
+        .unwrap();
+        assert_eq!(result, Variant::Boolean(expected));
+    });
+
+    // The operand must be a view
+    let operands = &[Operand::literal(NodeId::objects_folder_id())];
+    assert!(operator::in_view(
+        &event_id(),
+        &operands[..],
+        &mut used_elements,
+        &[],
+        &address_space,
+    )
+    .is_err());
+}
+
+#[test]
+fn test_related_to() {
+    do_operator_test(|address_space, object_id, used_elements, elements| {
+        // The event has properties, e.g. Foo, which are of PropertyType
+        let expected = vec![
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasProperty,
+                1,
+                false,
+                false,
+                true,
+            ),
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasProperty,
+                2,
+                false,
+                false,
+                false,
+            ),
+            (
+                ObjectTypeId::BaseEventType.into(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasProperty,
+                1,
+                false,
+                false,
+                false,
+            ),
+            (
+                ObjectTypeId::BaseEventType.into(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasProperty,
+                1,
+                true,
+                false,
+                true,
+            ),
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::BaseVariableType,
+                ReferenceTypeId::HasProperty,
+                1,
+                false,
+                false,
+                false,
+            ),
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::BaseVariableType,
+                ReferenceTypeId::HasProperty,
+                1,
+                true,
+                false,
+                true,
+            ),
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasChild,
+                1,
+                false,
+                false,
+                false,
+            ),
+            (
+                TestEventType::event_type_id(),
+                VariableTypeId::PropertyType,
+                ReferenceTypeId::HasChild,
+                1,
+                false,
+                true,
+                true,
+            ),
+        ];
+        expected.into_iter().for_each(
+            |(
+                source_type_id,
+                target_type_id,
+                reference_type_id,
+                hops,
+                include_type_subtypes,
+                include_reference_subtypes,
+                expected,
+            )| {
+                let operands = &[
+                    Operand::literal(source_type_id),
+                    Operand::literal(Into::<NodeId>::into(target_type_id)),
+                    Operand::literal(Into::<NodeId>::into(reference_type_id)),
+                    Operand::literal(hops as u32),
+                    Operand::literal(include_type_subtypes),
+                    Operand::literal(include_reference_subtypes),
+                ];
+                let result = operator::related_to(
+                    object_id,
+                    &operands[..],
+                    used_elements,
+                    elements,
+                    address_space,
+                )
+                .unwrap();
+                assert_eq!(result, Variant::Boolean(expected));
+            },
+        );
+    })
+}
+
 #[test]
 fn test_where_clause() {
     crate::console_logging::init();
@@ -830,6 +1008,29 @@ fn test_where_clause() {
     let result = event_filter::evaluate_where_clause(&object_id, &f, &address_space);
     assert_eq!(result.unwrap(), true.into());
 
+    // Events of a type or its subtypes
+    let f = ContentFilterBuilder::new()
+        .of_type(ObjectTypeId::BaseEventType)
+        .build();
+    let result = event_filter::evaluate_where_clause(&event_id(), &f, &address_space);
+    assert_eq!(result.unwrap(), true.into());
+    let result = event_filter::evaluate_where_clause(&object_id, &f, &address_space);
+    assert_eq!(result.unwrap(), false.into());
+
+    // Events with a property
+    let f = ContentFilterBuilder::new()
+        .related_to(
+            TestEventType::event_type_id(),
+            VariableTypeId::PropertyType,
+            ReferenceTypeId::HasProperty,
+            1,
+            false,
+            false,
+        )
+        .build();
+    let result = event_filter::evaluate_where_clause(&event_id(), &f, &address_space);
+    assert_eq!(result.unwrap(), true.into());
+
     // Do some relative path comparisons against the event to ensure content filters appear to work
     let expected = vec![
         // Valid
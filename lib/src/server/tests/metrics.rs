@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use crate::server::{
+    diagnostics::{LatencyHistogram, SERVICE_LATENCY_BUCKETS},
+    metrics::{Connection, ServerMetrics, Session},
+    subscriptions::subscriptions,
+};
+
+use super::*;
+
+fn make_connection(client_address: &str, session_id: &str) -> Connection {
+    Connection {
+        sessions: vec![Session {
+            id: session_id.to_string(),
+            session_activated: true,
+            session_terminated: false,
+            session_terminated_at: String::new(),
+            monitored_item_count: 7,
+            subscriptions: subscriptions::Metrics {
+                subscriptions: Vec::new(),
+                publish_request_queue_len: 3,
+                publish_response_queue_len: 0,
+                transmission_queue_len: 0,
+                retransmission_queue_len: 1,
+            },
+        }],
+        client_address: client_address.to_string(),
+        transport_state: "ProcessMessages".to_string(),
+        bytes_received: 1000,
+        bytes_sent: 2000,
+        chunks_received: 10,
+        chunks_sent: 20,
+        secure_channel_renewals: 2,
+    }
+}
+
+#[test]
+fn latency_histogram() {
+    let mut histogram = LatencyHistogram::default();
+    histogram.observe(Duration::from_micros(100));
+    histogram.observe(Duration::from_millis(3));
+    histogram.observe(Duration::from_millis(3));
+    histogram.observe(Duration::from_secs(5));
+
+    assert_eq!(histogram.count(), 4);
+    assert!((histogram.sum() - 5.0061).abs() < 1e-9);
+
+    let buckets = histogram.cumulative_buckets();
+    assert_eq!(buckets.len(), SERVICE_LATENCY_BUCKETS.len());
+    assert_eq!(buckets[0], (0.0005, 1));
+    assert_eq!(buckets[2], (0.0025, 1));
+    assert_eq!(buckets[3], (0.005, 3));
+    // The observation over the largest bucket is only in the count
+    assert_eq!(*buckets.last().unwrap(), (1.0, 3));
+}
+
+#[test]
+fn open_metrics_text() {
+    let mut metrics = ServerMetrics::new();
+    metrics.server.uptime_ms = 1500;
+    metrics
+        .connections
+        .push(make_connection("127.0.0.1:1234", "ns=1;s=\"a\\b\"\nc"));
+
+    let text = metrics.to_open_metrics();
+    let lines = text.lines().collect::<Vec<_>>();
+
+    assert_eq!(*lines.last().unwrap(), "# EOF");
+    assert!(lines.contains(&"# TYPE opcua_server_uptime_seconds gauge"));
+    assert!(lines.contains(&"opcua_server_uptime_seconds 1.5"));
+    assert!(lines.contains(&"# TYPE opcua_server_sessions_cumulated counter"));
+    assert!(lines.contains(&"opcua_server_sessions_cumulated_total 0"));
+    assert!(lines.contains(
+        &"opcua_connection_received_bytes_total{client_address=\"127.0.0.1:1234\"} 1000"
+    ));
+    assert!(
+        lines.contains(&"opcua_connection_sent_chunks_total{client_address=\"127.0.0.1:1234\"} 20")
+    );
+    assert!(lines.contains(
+        &"opcua_connection_secure_channel_renewals_total{client_address=\"127.0.0.1:1234\"} 2"
+    ));
+    // Label values are escaped
+    assert!(lines.contains(&"opcua_session_publish_request_queue_depth{client_address=\"127.0.0.1:1234\",session_id=\"ns=1;s=\\\"a\\\\b\\\"\\nc\"} 3"));
+    assert!(lines.contains(&"opcua_session_monitored_items{client_address=\"127.0.0.1:1234\",session_id=\"ns=1;s=\\\"a\\\\b\\\"\\nc\"} 7"));
+    // Every family has a single TYPE line
+    let type_lines = lines.iter().filter(|l| l.starts_with("# TYPE ")).count();
+    let families = lines
+        .iter()
+        .filter(|l| l.starts_with("# TYPE "))
+        .collect::<std::collections::HashSet<_>>();
+    assert_eq!(type_lines, families.len());
+}
+
+#[test]
+fn open_metrics_service_latency() {
+    let mut metrics = ServerMetrics::new();
+    metrics.diagnostics.on_service_latency(
+        ObjectId::ReadRequest_Encoding_DefaultBinary,
+        Duration::from_millis(2),
+    );
+    metrics.diagnostics.on_service_latency(
+        ObjectId::ReadRequest_Encoding_DefaultBinary,
+        Duration::from_secs(2),
+    );
+    metrics.diagnostics.on_service_latency(
+        ObjectId::BrowseRequest_Encoding_DefaultBinary,
+        Duration::from_millis(2),
+    );
+
+    let services = metrics
+        .diagnostics
+        .service_latencies()
+        .into_iter()
+        .map(|(service, _)| service)
+        .collect::<Vec<_>>();
+    assert_eq!(services, vec!["Browse", "Read"]);
+
+    let text = metrics.to_open_metrics();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"# TYPE opcua_service_request_duration_seconds histogram"));
+    assert!(lines.contains(
+        &"opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"0.001\"} 0"
+    ));
+    assert!(lines.contains(
+        &"opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"0.0025\"} 1"
+    ));
+    assert!(lines
+        .contains(&"opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"1.0\"} 1"));
+    assert!(lines.contains(
+        &"opcua_service_request_duration_seconds_bucket{service=\"Read\",le=\"+Inf\"} 2"
+    ));
+    assert!(lines.contains(&"opcua_service_request_duration_seconds_count{service=\"Read\"} 2"));
+    assert!(lines.contains(&"opcua_service_request_duration_seconds_sum{service=\"Read\"} 2.002"));
+    assert!(lines.contains(&"opcua_service_request_duration_seconds_count{service=\"Browse\"} 1"));
+
+    // Latencies are not recorded while diagnostics are disabled
+    metrics.diagnostics.set_enabled(false);
+    metrics.diagnostics.on_service_latency(
+        ObjectId::ReadRequest_Encoding_DefaultBinary,
+        Duration::from_millis(2),
+    );
+    metrics.diagnostics.set_enabled(true);
+    let read = metrics
+        .diagnostics
+        .service_latencies()
+        .into_iter()
+        .find(|(service, _)| service == "Read")
+        .unwrap();
+    assert_eq!(read.1.count(), 2);
+}
+
+#[cfg(feature = "http")]
+mod http {
+    use hyper::{header, Body, Method, Request, StatusCode};
+
+    use crate::server::{
+        http::{handle_request, MetricsServer},
+        metrics::OPEN_METRICS_CONTENT_TYPE,
+        server::Server,
+    };
+
+    use super::*;
+
+    fn request(method: Method, path: &str, token: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        request.body(Body::empty()).unwrap()
+    }
+
+    fn is_abort(server: &Server) -> bool {
+        let server_state = server.server_state();
+        let server_state = trace_read_lock!(server_state);
+        server_state.is_abort()
+    }
+
+    #[test]
+    fn scrape_requires_token() {
+        let server = ServerBuilder::new_sample().server().unwrap();
+        let metrics_server = MetricsServer::new(&server, "127.0.0.1:0", "scrape");
+        let state = metrics_server.state();
+
+        let response = handle_request(state, &request(Method::GET, "/metrics", None));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+
+        let response = handle_request(state, &request(Method::GET, "/metrics", Some("scrapf")));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = handle_request(state, &request(Method::GET, "/metrics", Some("scrape")));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            OPEN_METRICS_CONTENT_TYPE
+        );
+
+        let response = handle_request(state, &request(Method::POST, "/metrics", Some("scrape")));
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = handle_request(state, &request(Method::GET, "/index.html", Some("scrape")));
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // No abort token means there is no abort endpoint
+        let response = handle_request(
+            state,
+            &request(Method::POST, "/server/abort", Some("scrape")),
+        );
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(!is_abort(&server));
+    }
+
+    #[test]
+    fn empty_token_is_never_authorized() {
+        let server = ServerBuilder::new_sample().server().unwrap();
+        let metrics_server = MetricsServer::new(&server, "127.0.0.1:0", "");
+        let state = metrics_server.state();
+
+        let response = handle_request(state, &request(Method::GET, "/metrics", Some("")));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn abort_requires_abort_token() {
+        let server = ServerBuilder::new_sample().server().unwrap();
+        let metrics_server =
+            MetricsServer::new(&server, "127.0.0.1:0", "scrape").abort_token("abort");
+        let state = metrics_server.state();
+
+        // The scrape token cannot abort the server
+        let response = handle_request(
+            state,
+            &request(Method::POST, "/server/abort", Some("scrape")),
+        );
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = handle_request(state, &request(Method::GET, "/server/abort", Some("abort")));
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(!is_abort(&server));
+
+        let response = handle_request(
+            state,
+            &request(Method::POST, "/server/abort", Some("abort")),
+        );
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(is_abort(&server));
+    }
+}
@@ -10,13 +10,14 @@ use crate::types::{status_code::StatusCode, *};
 use crate::server::{
     address_space::{address_space::*, variable::*},
     builder::ServerBuilder,
-    config::ServerConfig,
+    config::{RedundancyConfig, ServerConfig},
     session::*,
     subscriptions::*,
 };
 
 mod address_space;
 mod events;
+mod metrics;
 mod services;
 mod subscriptions;
 
@@ -100,6 +101,29 @@ pub fn server_config_invalid() {
     assert_eq!(config.is_valid(), false);
 }
 
+#[test]
+pub fn server_config_redundancy() {
+    let mut redundancy = RedundancyConfig::new(
+        service_types::RedundancySupport::Hot,
+        vec!["urn:peer".to_string()],
+    );
+    assert_eq!(redundancy.mode, "Hot");
+    assert_eq!(
+        redundancy.redundancy_support(),
+        Some(service_types::RedundancySupport::Hot)
+    );
+    let mut config = ServerBuilder::new_anonymous("foo")
+        .redundancy(redundancy.clone())
+        .config();
+    assert!(config.is_valid());
+
+    // Transparent redundancy is not supported
+    redundancy.mode = "Transparent".to_string();
+    assert!(redundancy.redundancy_support().is_none());
+    config.redundancy = Some(redundancy);
+    assert!(!config.is_valid());
+}
+
 #[test]
 pub fn expired_publish_requests() {
     let now = chrono::Utc::now();
@@ -1,6 +1,20 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use async_trait::async_trait;
 use chrono::Duration;
 
-use crate::server::{address_space::AccessLevel, services::attribute::AttributeService};
+use crate::server::{
+    address_space::AccessLevel,
+    callbacks::{
+        request_cancellation_token, AsyncAttributeGetter, AsyncAttributeSetter, AsyncReadValue,
+    },
+    identity_token::{AuthenticatedUser, IdentityToken},
+    services::{
+        attribute::AttributeService, cancellable_response, session::SessionService,
+        unregistered_request_context, ServiceResponse,
+    },
+    session_diagnostics::SessionDiagnostics,
+};
 use crate::supported_message_as;
 use crate::sync::*;
 use crate::types::{Variant, WriteMask};
@@ -313,7 +327,7 @@ fn write() {
             node_ids
         };
 
-        let mut data_value_empty = DataValue::new_now(100 as i32);
+        let mut data_value_empty = DataValue::value_only(100_i32);
         data_value_empty.value = None;
 
         // This is a cross section of variables and other kinds of nodes that we want to write to
@@ -322,7 +336,7 @@ fn write() {
             write_value(
                 &node_ids[0],
                 AttributeId::Value,
-                DataValue::new_now(100 as i32),
+                DataValue::value_only(100_i32),
             ),
             // 2. a variable with a bad attribute (IsAbstract doesn't exist on a var)
             write_value(
@@ -334,7 +348,7 @@ fn write() {
             write_value(
                 &node_ids[2],
                 AttributeId::Value,
-                DataValue::new_now(200 as i32),
+                DataValue::value_only(200_i32),
             ),
             // 4. a node of some kind other than variable
             write_value(
@@ -352,7 +366,7 @@ fn write() {
             write_value(
                 &NodeId::new(2, "vxxx"),
                 AttributeId::Value,
-                DataValue::new_now(100i32),
+                DataValue::value_only(100i32),
             ),
             // 7. wrong type for attribute
             write_value(
@@ -421,7 +435,7 @@ fn write_bytestring_to_byte_array() {
         let nodes_to_write = vec![write_value(
             &node_id,
             AttributeId::Value,
-            DataValue::new_now(bytes),
+            DataValue::value_only(bytes),
         )];
 
         // Do a write
@@ -489,13 +503,13 @@ fn write_index_range() {
                 &node_id_1,
                 AttributeId::Value,
                 format!("{}", index),
-                DataValue::new_now(index_bytes),
+                DataValue::value_only(index_bytes),
             ),
             write_value_index_range(
                 &node_id_2,
                 AttributeId::Value,
                 format!("{}:{}", range_min, range_max),
-                DataValue::new_now(range_value),
+                DataValue::value_only(range_value),
             ),
         ];
 
@@ -553,6 +567,777 @@ fn write_index_range() {
     });
 }
 
+#[test]
+fn write_type_checks() {
+    // Test that written values are checked against the data type, value rank and array dimensions
+    // of variables, and converted to the data type when they are of another type
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let scalar_id = NodeId::next_numeric(2);
+        let duration_id = NodeId::next_numeric(2);
+        let enumeration_id = NodeId::next_numeric(2);
+        let array_id = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let variables = [
+                (&scalar_id, DataTypeId::Int32, -1, Variant::from(0i32)),
+                (&duration_id, DataTypeId::Duration, -1, Variant::from(0f64)),
+                (
+                    &enumeration_id,
+                    DataTypeId::NodeClass,
+                    -1,
+                    Variant::from(0i32),
+                ),
+                (
+                    &array_id,
+                    DataTypeId::Int32,
+                    1,
+                    Variant::from(vec![0i32; 3]),
+                ),
+            ];
+            for (i, (node_id, data_type, value_rank, value)) in variables.iter().enumerate() {
+                let builder = VariableBuilder::new(node_id, var_name(i), "")
+                    .data_type(*data_type)
+                    .value_rank(*value_rank)
+                    .value(value.clone())
+                    .organized_by(ObjectId::RootFolder)
+                    .writable();
+                let builder = if *value_rank == 1 {
+                    builder.array_dimensions(&[3])
+                } else {
+                    builder
+                };
+                let _ = builder.insert(&mut address_space);
+            }
+        }
+
+        let write = |node_id: &NodeId, index_range: &str, value: Variant| {
+            write_value_index_range(
+                node_id,
+                AttributeId::Value,
+                index_range,
+                DataValue::value_only(value),
+            )
+        };
+        let nodes_to_write = vec![
+            // 1. a value that is implicitly converted to the data type
+            write(&scalar_id, "", Variant::from(5i16)),
+            // 2. a value that cannot be converted to the data type
+            write(&scalar_id, "", Variant::from("x")),
+            // 3. an array written to a scalar
+            write(&scalar_id, "", Variant::from(vec![1i32, 2])),
+            // 4. an index range written to a scalar
+            write(&scalar_id, "1", Variant::from(vec![1i32])),
+            // 5. a value converted to the built-in type of a subtype
+            write(&duration_id, "", Variant::from(1.5f32)),
+            // 6. an enumeration is written as an Int32
+            write(&enumeration_id, "", Variant::from(2u16)),
+            // 7. an array within the array dimensions
+            write(&array_id, "", Variant::from(vec![1i32, 2, 3])),
+            // 8. an array exceeding the array dimensions
+            write(&array_id, "", Variant::from(vec![1i32, 2, 3, 4])),
+            // 9. a scalar written to an array
+            write(&array_id, "", Variant::from(1i32)),
+            // 10. an index range with a different number of values
+            write(&array_id, "0:1", Variant::from(vec![1i32, 2, 3])),
+        ];
+        let response = write_request(
+            server_state,
+            session,
+            address_space.clone(),
+            ats,
+            nodes_to_write,
+        );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![
+                StatusCode::Good,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadIndexRangeInvalid,
+                StatusCode::Good,
+                StatusCode::Good,
+                StatusCode::Good,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadTypeMismatch,
+                StatusCode::BadIndexRangeInvalid,
+            ]
+        );
+
+        validate_variable_value(address_space.clone(), &scalar_id, |v| {
+            assert_eq!(*v, Variant::Int32(5));
+        });
+        validate_variable_value(address_space.clone(), &duration_id, |v| {
+            assert_eq!(*v, Variant::Double(1.5));
+        });
+        validate_variable_value(address_space.clone(), &enumeration_id, |v| {
+            assert_eq!(*v, Variant::Int32(2));
+        });
+        validate_variable_value(address_space, &array_id, |v| {
+            assert_eq!(*v, Variant::from(vec![1i32, 2, 3]));
+        });
+    });
+}
+
+#[test]
+fn write_status_and_timestamps() {
+    // Test that the status and timestamps of a value are only written with the access to do so
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_id_1 = NodeId::next_numeric(2);
+        let node_id_2 = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&node_id_1, var_name(0), "")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .organized_by(ObjectId::RootFolder)
+                .writable()
+                .insert(&mut address_space);
+            let _ = VariableBuilder::new(&node_id_2, var_name(1), "")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .organized_by(ObjectId::RootFolder)
+                .access_level(
+                    AccessLevel::CURRENT_READ
+                        | AccessLevel::CURRENT_WRITE
+                        | AccessLevel::STATUS_WRITE
+                        | AccessLevel::TIMESTAMP_WRITE,
+                )
+                .user_access_level(
+                    UserAccessLevel::CURRENT_READ
+                        | UserAccessLevel::CURRENT_WRITE
+                        | UserAccessLevel::STATUS_WRITE
+                        | UserAccessLevel::TIMESTAMP_WRITE,
+                )
+                .insert(&mut address_space);
+        }
+
+        let source_timestamp = DateTime::ymd_hms(2020, 1, 2, 3, 4, 5);
+        let mut status_only = DataValue::value_only(1i32);
+        status_only.status = Some(StatusCode::UncertainLastUsableValue);
+        let mut timestamp_only = DataValue::value_only(1i32);
+        timestamp_only.source_timestamp = Some(source_timestamp);
+        let mut data_value = DataValue::value_only(2i32);
+        data_value.status = Some(StatusCode::UncertainLastUsableValue);
+        data_value.source_timestamp = Some(source_timestamp);
+
+        let nodes_to_write = vec![
+            write_value(&node_id_1, AttributeId::Value, status_only),
+            write_value(&node_id_1, AttributeId::Value, timestamp_only),
+            write_value(&node_id_2, AttributeId::Value, data_value),
+        ];
+        let response = write_request(
+            server_state,
+            session,
+            address_space.clone(),
+            ats,
+            nodes_to_write,
+        );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![
+                StatusCode::BadWriteNotSupported,
+                StatusCode::BadWriteNotSupported,
+                StatusCode::Good
+            ]
+        );
+
+        let address_space = trace_read_lock!(address_space);
+        let variable = address_space.find_variable_by_ref(&node_id_2).unwrap();
+        let value = variable.value(
+            TimestampsToReturn::Both,
+            NumericRange::None,
+            &QualifiedName::null(),
+            0.,
+        );
+        assert_eq!(value.value, Some(Variant::Int32(2)));
+        assert_eq!(value.status, Some(StatusCode::UncertainLastUsableValue));
+        assert_eq!(value.source_timestamp, Some(source_timestamp));
+    });
+}
+
+#[test]
+fn write_in_request_order() {
+    // Test that the writes of a request are made in order, so a value write is checked against
+    // the user access level written before it in the same request
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_id = NodeId::next_numeric(2);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let _ = VariableBuilder::new(&node_id, var_name(0), "")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .organized_by(ObjectId::RootFolder)
+                .access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE)
+                .user_access_level(UserAccessLevel::CURRENT_READ)
+                .insert(&mut address_space);
+            let node = address_space.find_node_mut(&node_id).unwrap();
+            node.as_mut_node()
+                .set_write_mask(WriteMask::USER_ACCESS_LEVEL);
+        }
+
+        let user_access_level = UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE;
+        let nodes_to_write = vec![
+            // 1. a value before write access is granted
+            write_value(&node_id, AttributeId::Value, DataValue::value_only(1i32)),
+            // 2. grant write access
+            write_value(
+                &node_id,
+                AttributeId::UserAccessLevel,
+                DataValue::value_only(user_access_level.bits()),
+            ),
+            // 3. a value after write access is granted
+            write_value(&node_id, AttributeId::Value, DataValue::value_only(2i32)),
+        ];
+        let response = write_request(
+            server_state,
+            session,
+            address_space.clone(),
+            ats,
+            nodes_to_write,
+        );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![
+                StatusCode::BadNotWritable,
+                StatusCode::Good,
+                StatusCode::Good
+            ]
+        );
+
+        validate_variable_value(address_space, &node_id, |v| {
+            assert_eq!(*v, Variant::Int32(2));
+        });
+    });
+}
+
+#[test]
+fn diagnostics_access() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let enabled_flag: NodeId = VariableId::Server_ServerDiagnostics_EnabledFlag.into();
+        let write_enabled_flag = |session: Arc<RwLock<Session>>| {
+            let response = write_request(
+                server_state.clone(),
+                session,
+                address_space.clone(),
+                ats,
+                vec![write_value(
+                    &enabled_flag,
+                    AttributeId::Value,
+                    DataValue::value_only(false),
+                )],
+            );
+            response.results.unwrap()[0]
+        };
+
+        // An anonymous session cannot switch diagnostics off
+        assert_eq!(
+            write_enabled_flag(session.clone()),
+            StatusCode::BadNotWritable
+        );
+        trace_write_lock!(session).set_user_identity(IdentityToken::UserNameIdentityToken(
+            UserNameIdentityToken {
+                policy_id: UAString::from("userpass"),
+                user_name: UAString::from("sample1"),
+                password: ByteString::null(),
+                encryption_algorithm: UAString::null(),
+            },
+        ));
+        assert_eq!(write_enabled_flag(session.clone()), StatusCode::Good);
+
+        // A session can read its own security diagnostics but not those of another session
+        let other_session = Arc::new(RwLock::new(Session::new(server_state.clone())));
+        trace_read_lock!(session).register_session(address_space.clone());
+        trace_read_lock!(other_session).register_session(address_space.clone());
+        let security_diagnostics = |session: &Arc<RwLock<Session>>| {
+            SessionDiagnostics::variable_node_id(
+                trace_read_lock!(session).session_id(),
+                "SessionSecurityDiagnostics",
+            )
+        };
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Neither,
+            nodes_to_read: Some(vec![
+                read_value(&security_diagnostics(&session), AttributeId::Value),
+                read_value(&security_diagnostics(&other_session), AttributeId::Value),
+            ]),
+        };
+        let response = ats.read(server_state, session, address_space, &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+        assert!(results[0].value.is_some());
+        assert_eq!(results[1].status, Some(StatusCode::BadNotReadable));
+    });
+}
+
+#[test]
+fn observer_cannot_write() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let (_, node_ids) = add_many_vars_to_address_space(address_space.clone(), 1);
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let v = address_space.find_variable_mut(&node_ids[0]).unwrap();
+            v.set_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE);
+            v.set_user_access_level(UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE);
+        }
+        let write = |roles: Vec<ObjectId>| {
+            let mut user = AuthenticatedUser::new("sample1".to_string());
+            user.roles.extend(roles.iter().map(NodeId::from));
+            trace_write_lock!(session).set_authenticated_user(Some(user));
+            let response = write_request(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                ats,
+                vec![write_value(
+                    &node_ids[0],
+                    AttributeId::Value,
+                    DataValue::value_only(1i32),
+                )],
+            );
+            response.results.unwrap()[0]
+        };
+
+        // An observer can only read, unless another role allows it to write
+        assert_eq!(
+            write(vec![ObjectId::WellKnownRole_Observer]),
+            StatusCode::BadNotWritable
+        );
+        assert_eq!(
+            write(vec![
+                ObjectId::WellKnownRole_Observer,
+                ObjectId::WellKnownRole_Operator
+            ]),
+            StatusCode::Good
+        );
+        assert_eq!(write(vec![]), StatusCode::Good);
+    });
+}
+
+/// Returns the node id as the value of every read, and counts the batches it is called with
+struct AsyncGetter {
+    batches: AtomicUsize,
+    delay: Option<std::time::Duration>,
+}
+
+impl AsyncGetter {
+    fn new(delay: Option<std::time::Duration>) -> Arc<AsyncGetter> {
+        Arc::new(AsyncGetter {
+            batches: AtomicUsize::new(0),
+            delay,
+        })
+    }
+}
+
+#[async_trait]
+impl AsyncAttributeGetter for AsyncGetter {
+    async fn get(&self, read: &AsyncReadValue) -> Result<Option<DataValue>, DiagnosticError> {
+        Ok(Some(DataValue::new_now(read.node_id.to_string())))
+    }
+
+    async fn get_many(
+        &self,
+        reads: &[AsyncReadValue],
+    ) -> Vec<Result<Option<DataValue>, DiagnosticError>> {
+        self.batches.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let mut values = Vec::with_capacity(reads.len());
+        for read in reads {
+            values.push(self.get(read).await);
+        }
+        values
+    }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        Some(std::time::Duration::from_millis(100))
+    }
+}
+
+#[test]
+fn read_async_getter() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+
+        let getter = AsyncGetter::new(None);
+        let slow_getter = AsyncGetter::new(Some(std::time::Duration::from_secs(5)));
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            for idx in [0, 1, 3] {
+                let v = address_space
+                    .find_variable_mut_by_ref(&node_ids[idx])
+                    .unwrap();
+                v.set_async_value_getter(getter.clone());
+            }
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[2])
+                .unwrap();
+            v.set_async_value_getter(slow_getter.clone());
+        }
+
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(
+                node_ids[0..5]
+                    .iter()
+                    .map(|node_id| read_value(node_id, AttributeId::Value))
+                    .collect(),
+            ),
+        };
+        let response = ats.read(server_state, session, address_space.clone(), &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+
+        // Reads that share a getter are made in one batch
+        assert_eq!(getter.batches.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            results[0].value,
+            Some(Variant::from(node_ids[0].to_string()))
+        );
+        assert!(results[0].source_timestamp.is_some());
+        assert_eq!(
+            results[1].value,
+            Some(Variant::from(node_ids[1].to_string()))
+        );
+        // A getter that is too slow times out
+        assert_eq!(results[2].status, Some(StatusCode::BadTimeout));
+        // Access is checked before the getter is called
+        assert_eq!(results[3].status, Some(StatusCode::BadNotReadable));
+        // Variables without an async getter are read as usual
+        assert_eq!(results[4].value, Some(Variant::Int32(4)));
+
+        // The value read is held by the variable
+        validate_variable_value(address_space, &node_ids[0], |v| {
+            assert_eq!(*v, Variant::from(node_ids[0].to_string()));
+        });
+    });
+}
+
+/// Returns the same array for every read
+struct ArrayGetter;
+
+#[async_trait]
+impl AsyncAttributeGetter for ArrayGetter {
+    async fn get(&self, _read: &AsyncReadValue) -> Result<Option<DataValue>, DiagnosticError> {
+        Ok(Some(DataValue::new_now(vec![1i32, 2, 3, 4])))
+    }
+}
+
+#[test]
+fn read_async_getter_index_range() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[0])
+                .unwrap();
+            v.set_async_value_getter(Arc::new(ArrayGetter));
+        }
+
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(vec![
+                read_value_range(&node_ids[0], AttributeId::Value, UAString::from("1:2")),
+                read_value_range(&node_ids[0], AttributeId::Value, UAString::from("5:6")),
+            ]),
+        };
+        let response = ats.read(server_state, session, address_space.clone(), &request);
+        let response: ReadResponse = supported_message_as!(response, ReadResponse);
+        let results = response.results.unwrap();
+
+        // The index range is applied to the value the getter returns
+        assert_eq!(results[0].value, Some(Variant::from(vec![2i32, 3])));
+        assert_eq!(results[1].value, None);
+        assert_eq!(results[1].status, Some(StatusCode::BadIndexRangeNoData));
+
+        // The variable holds the whole value
+        validate_variable_value(address_space, &node_ids[0], |v| {
+            assert_eq!(*v, Variant::from(vec![1i32, 2, 3, 4]));
+        });
+    });
+}
+
+/// Never returns a value, but records when the request it serves is cancelled
+struct PendingGetter {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl AsyncAttributeGetter for PendingGetter {
+    async fn get(&self, _read: &AsyncReadValue) -> Result<Option<DataValue>, DiagnosticError> {
+        let token = request_cancellation_token();
+        let cancelled = self.cancelled.clone();
+        tokio::spawn(async move {
+            token.cancelled().await;
+            cancelled.store(true, Ordering::SeqCst);
+        });
+        futures::future::pending().await
+    }
+}
+
+#[test]
+fn read_async_getter_cancelled() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+        let getter = Arc::new(PendingGetter {
+            cancelled: Arc::new(AtomicBool::new(false)),
+        });
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[0])
+                .unwrap();
+            v.set_async_value_getter(getter.clone());
+        }
+
+        let request = ReadRequest {
+            request_header: make_request_header(),
+            max_age: 0f64,
+            timestamps_to_return: TimestampsToReturn::Both,
+            nodes_to_read: Some(vec![read_value(&node_ids[0], AttributeId::Value)]),
+        };
+        let response = match ats.read_deferred(
+            server_state.clone(),
+            session.clone(),
+            unregistered_request_context(&session),
+            address_space,
+            &request,
+        ) {
+            ServiceResponse::Deferred(response) => response,
+            ServiceResponse::Ready(_) => panic!("The read should be deferred"),
+        };
+        let request_handle = request.request_header.request_handle;
+        let token = {
+            let mut session = trace_write_lock!(session);
+            session.register_in_flight_request(1, request_handle)
+        };
+
+        let cancel = |request_handle| {
+            let request = CancelRequest {
+                request_header: make_request_header(),
+                request_handle,
+            };
+            let response =
+                SessionService::new().cancel(server_state.clone(), session.clone(), &request);
+            supported_message_as!(response, CancelResponse).cancel_count
+        };
+
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let request_header = request.request_header.clone();
+                let response = tokio::spawn(async move {
+                    cancellable_response(&request_header, token, response).await
+                });
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+                // Only the request with the handle is cancelled
+                assert_eq!(cancel(request_handle + 1), 0);
+                assert_eq!(cancel(request_handle), 1);
+                assert_eq!(cancel(request_handle), 0);
+
+                let response = response.await.unwrap();
+                let response = supported_message_as!(response, ServiceFault);
+                assert_eq!(
+                    response.response_header.service_result,
+                    StatusCode::BadRequestCancelledByClient
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            });
+
+        // The getter observed the cancellation
+        assert!(getter.cancelled.load(Ordering::SeqCst));
+        let session = trace_read_lock!(session);
+        assert_eq!(session.in_flight_request_count(), 0);
+    });
+}
+
+/// Records the values written to it
+struct AsyncSetter {
+    values: Mutex<Vec<(NodeId, Variant)>>,
+    delay: Option<std::time::Duration>,
+}
+
+#[async_trait]
+impl AsyncAttributeSetter for AsyncSetter {
+    async fn set(
+        &self,
+        node_id: &NodeId,
+        _attribute_id: AttributeId,
+        _index_range: NumericRange,
+        data_value: DataValue,
+    ) -> Result<(), DiagnosticError> {
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let mut values = trace_lock!(self.values);
+        values.push((node_id.clone(), data_value.value.unwrap()));
+        Ok(())
+    }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        Some(std::time::Duration::from_millis(100))
+    }
+}
+
+#[test]
+fn write_async_setter() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let (_, node_ids) = add_many_vars_to_address_space(address_space.clone(), 3);
+        let setter = Arc::new(AsyncSetter {
+            values: Mutex::new(Vec::new()),
+            delay: None,
+        });
+        let slow_setter = Arc::new(AsyncSetter {
+            values: Mutex::new(Vec::new()),
+            delay: Some(std::time::Duration::from_secs(5)),
+        });
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            for node_id in node_ids.iter() {
+                let v = address_space.find_variable_mut_by_ref(node_id).unwrap();
+                v.set_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE);
+                v.set_user_access_level(
+                    UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE,
+                );
+            }
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[0])
+                .unwrap();
+            v.set_async_value_setter(setter.clone());
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[1])
+                .unwrap();
+            v.set_async_value_setter(slow_setter.clone());
+        }
+
+        let nodes_to_write = vec![
+            write_value(
+                &node_ids[0],
+                AttributeId::Value,
+                DataValue::value_only(100i32),
+            ),
+            write_value(
+                &node_ids[1],
+                AttributeId::Value,
+                DataValue::value_only(101i32),
+            ),
+            write_value(
+                &node_ids[2],
+                AttributeId::Value,
+                DataValue::value_only(102i32),
+            ),
+            // The value is type checked before the setter is called
+            write_value(&node_ids[0], AttributeId::Value, DataValue::value_only("x")),
+        ];
+        let response = write_request(
+            server_state,
+            session,
+            address_space.clone(),
+            ats,
+            nodes_to_write,
+        );
+        assert_eq!(
+            response.results.unwrap(),
+            vec![
+                StatusCode::Good,
+                StatusCode::BadTimeout,
+                StatusCode::Good,
+                StatusCode::BadTypeMismatch
+            ]
+        );
+        let values = trace_lock!(setter.values);
+        assert_eq!(*values, vec![(node_ids[0].clone(), Variant::Int32(100))]);
+        validate_variable_value(address_space, &node_ids[2], |v| {
+            assert_eq!(*v, Variant::Int32(102));
+        });
+    });
+}
+
+#[test]
+fn write_return_diagnostics() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let node_ids = node_ids(address_space.clone());
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            let v = address_space
+                .find_variable_mut_by_ref(&node_ids[0])
+                .unwrap();
+            v.set_access_level(AccessLevel::CURRENT_READ | AccessLevel::CURRENT_WRITE);
+            v.set_user_access_level(UserAccessLevel::CURRENT_READ | UserAccessLevel::CURRENT_WRITE);
+        }
+        let unknown_id = NodeId::new(1, "Unknown");
+        let mut request_header = make_request_header();
+        request_header.return_diagnostics = DiagnosticBits::OPERATIONAL_LEVEL_SYMBOLIC_ID
+            | DiagnosticBits::OPERATIONAL_LEVEL_ADDITIONAL_INFO;
+        let request = WriteRequest {
+            request_header,
+            nodes_to_write: Some(vec![
+                write_value(
+                    &node_ids[0],
+                    AttributeId::Value,
+                    DataValue::value_only(1i32),
+                ),
+                write_value(&unknown_id, AttributeId::Value, DataValue::value_only(1i32)),
+            ]),
+        };
+        let response = ats.write(server_state, session, address_space, &request);
+        let response: WriteResponse = supported_message_as!(response, WriteResponse);
+        assert_eq!(
+            response.results.unwrap(),
+            vec![StatusCode::Good, StatusCode::BadNodeIdUnknown]
+        );
+
+        // The failed write explains why it failed
+        let diagnostic_infos = response.diagnostic_infos.unwrap();
+        assert_eq!(diagnostic_infos[0], DiagnosticInfo::null());
+        let diagnostic_info = &diagnostic_infos[1];
+        assert!(diagnostic_info.localized_text.is_none());
+        assert_eq!(
+            diagnostic_info.additional_info,
+            Some(UAString::from(format!(
+                "Node {} does not exist",
+                unknown_id
+            )))
+        );
+        let string_table = response.response_header.string_table.unwrap();
+        assert_eq!(
+            string_table[diagnostic_info.symbolic_id.unwrap() as usize],
+            UAString::from("BadNodeIdUnknown")
+        );
+    });
+}
+
+#[test]
+fn service_fault_return_diagnostics() {
+    do_attribute_service_test(|server_state, session, address_space, ats| {
+        let mut request_header = make_request_header();
+        request_header.return_diagnostics = DiagnosticBits::SERVICE_LEVEL_SYMBOLIC_ID;
+        let request = WriteRequest {
+            request_header,
+            nodes_to_write: None,
+        };
+        let response = ats.write(server_state, session, address_space, &request);
+        let response: ServiceFault = supported_message_as!(response, ServiceFault);
+        let response_header = response.response_header;
+        assert_eq!(response_header.service_result, StatusCode::BadNothingToDo);
+        let string_table = response_header.string_table.unwrap();
+        assert_eq!(
+            string_table[response_header.service_diagnostics.symbolic_id.unwrap() as usize],
+            UAString::from("BadNothingToDo")
+        );
+    });
+}
+
 // #[test] fn write_null_value() { /* Write an empty variant to a value and see that it is allowed */}
 
 struct DataProvider;
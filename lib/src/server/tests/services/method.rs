@@ -1,11 +1,14 @@
+use async_trait::async_trait;
+
 use crate::supported_message_as;
 use crate::sync::*;
 use crate::types::{
     node_ids::{MethodId, ObjectId},
-    service_types::{CallMethodRequest, CallMethodResult, CallRequest, CallResponse},
+    service_types::{Argument, CallMethodRequest, CallMethodResult, CallRequest, CallResponse},
     status_code::StatusCode,
 };
 
+use crate::server::callbacks::{self, AsyncMethod};
 use crate::server::services::{
     method::MethodService, monitored_item::MonitoredItemService, subscription::SubscriptionService,
 };
@@ -137,6 +140,44 @@ fn call_getmonitoreditems_invalid_object_id() {
     });
 }
 
+#[test]
+fn call_return_diagnostics() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let session_id = {
+            let session = trace_read_lock!(session);
+            session.session_id().clone()
+        };
+        let mut request_header = RequestHeader::dummy();
+        request_header.return_diagnostics = DiagnosticBits::OPERATIONAL_LEVEL_ADDITIONAL_INFO;
+        let response = s.call(
+            server_state,
+            &session_id,
+            session_manager,
+            address_space,
+            &CallRequest {
+                request_header,
+                methods_to_call: Some(vec![new_call_method_request(
+                    ObjectId::Server,
+                    MethodId::Server_GetMonitoredItems,
+                    None,
+                )]),
+            },
+        );
+        let response: CallResponse = supported_message_as!(response, CallResponse);
+        let results = response.results.unwrap();
+        assert_eq!(results[0].status_code, StatusCode::BadArgumentsMissing);
+        // Only the additional info was asked for so there is no string table
+        assert!(response.response_header.string_table.is_none());
+        let diagnostic_infos = response.diagnostic_infos.unwrap();
+        assert_eq!(
+            diagnostic_infos[0].additional_info,
+            Some(UAString::from(
+                "The method expects 1 input arguments but was called with 0"
+            ))
+        );
+    });
+}
+
 #[test]
 fn call_getmonitoreditems_invalid_method_id() {
     do_method_service_test(|server_state, session_manager, session, address_space, s| {
@@ -201,7 +242,7 @@ fn call_getmonitoreditems_too_many_args() {
 fn call_getmonitoreditems_incorrect_args() {
     do_method_service_test(|server_state, session_manager, session, address_space, s| {
         // Call with incorrect arg
-        let args: Vec<Variant> = vec![100u8.into()];
+        let args: Vec<Variant> = vec![Guid::new().into()];
         let request = new_call_method_request(
             ObjectId::Server,
             MethodId::Server_GetMonitoredItems,
@@ -217,6 +258,10 @@ fn call_getmonitoreditems_incorrect_args() {
         )
         .unwrap();
         assert_eq!(response.status_code, StatusCode::BadInvalidArgument);
+        assert_eq!(
+            response.input_argument_results,
+            Some(vec![StatusCode::BadTypeMismatch])
+        );
     });
 }
 
@@ -255,7 +300,12 @@ fn call_getmonitoreditems() {
             let subscription_id = {
                 let request = create_subscription_request();
                 let response: CreateSubscriptionResponse = supported_message_as!(
-                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    ss.create_subscription(
+                        server_state.clone(),
+                        session_manager.clone(),
+                        session.clone(),
+                        &request,
+                    ),
                     CreateSubscriptionResponse
                 );
                 response.subscription_id
@@ -367,7 +417,12 @@ fn call_resend_data() {
             let subscription_id = {
                 let request = create_subscription_request();
                 let response: CreateSubscriptionResponse = supported_message_as!(
-                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    ss.create_subscription(
+                        server_state.clone(),
+                        session_manager.clone(),
+                        session.clone(),
+                        &request,
+                    ),
                     CreateSubscriptionResponse
                 );
                 response.subscription_id
@@ -390,3 +445,276 @@ fn call_resend_data() {
         }
     });
 }
+
+/// Returns the input arguments as output arguments after an optional delay
+struct AsyncEcho {
+    delay: Option<std::time::Duration>,
+}
+
+#[async_trait]
+impl AsyncMethod for AsyncEcho {
+    async fn call(
+        &self,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: request.input_arguments.clone(),
+        })
+    }
+}
+
+#[test]
+fn call_async_method() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let object_id = NodeId::new(1, "Device");
+        let echo_id = NodeId::new(1, "Device.Echo");
+        let slow_echo_id = NodeId::new(1, "Device.SlowEcho");
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            ObjectBuilder::new(&object_id, "Device", "Device")
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+            MethodBuilder::new(&echo_id, "Echo", "Echo")
+                .component_of(object_id.clone())
+                .async_callback(Arc::new(AsyncEcho { delay: None }))
+                .insert(&mut address_space);
+            MethodBuilder::new(&slow_echo_id, "SlowEcho", "SlowEcho")
+                .component_of(object_id.clone())
+                .async_callback(Arc::new(AsyncEcho { delay: None }))
+                .insert(&mut address_space);
+            // Replace the callback with one that is too slow
+            address_space.register_async_method_handler(
+                slow_echo_id.clone(),
+                Arc::new(AsyncEcho {
+                    delay: Some(std::time::Duration::from_secs(5)),
+                }),
+            );
+        }
+
+        let session_id = {
+            let session = trace_read_lock!(session);
+            session.session_id().clone()
+        };
+        let mut request_header = RequestHeader::dummy();
+        // The timeout hint of the request limits the time allowed for the calls
+        request_header.timeout_hint = 100;
+        let args = Some(vec![Variant::from("hello")]);
+        let response = s.call(
+            server_state,
+            &session_id,
+            session_manager,
+            address_space,
+            &CallRequest {
+                request_header,
+                methods_to_call: Some(vec![
+                    new_call_method_request(object_id.clone(), echo_id, args.clone()),
+                    new_call_method_request(object_id.clone(), slow_echo_id, args.clone()),
+                    new_call_method_request(object_id, MethodId::Server_GetMonitoredItems, None),
+                ]),
+            },
+        );
+        let response: CallResponse = supported_message_as!(response, CallResponse);
+        let results = response.results.unwrap();
+        assert_eq!(results[0].status_code, StatusCode::Good);
+        assert_eq!(results[0].output_arguments, args);
+        assert_eq!(results[1].status_code, StatusCode::BadTimeout);
+        assert_eq!(results[2].status_code, StatusCode::BadMethodInvalid);
+    });
+}
+
+/// Writes to the address space from a method, which is called without holding its lock
+struct SetCounter {
+    address_space: Arc<RwLock<AddressSpace>>,
+    counter_id: NodeId,
+}
+
+impl callbacks::Method for SetCounter {
+    fn call(
+        &mut self,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        let value = request.input_arguments.as_ref().unwrap()[0].clone();
+        let mut address_space = self
+            .address_space
+            .try_write()
+            .ok_or(StatusCode::BadInternalError)?;
+        let now = DateTime::now();
+        address_space.set_variable_value(self.counter_id.clone(), value, &now, &now);
+        // Structural changes can be made too
+        let _ = address_space.register_namespace("urn:SetCounter");
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: None,
+        })
+    }
+}
+
+#[test]
+fn call_method_without_address_space_lock() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let object_id = NodeId::new(1, "Device");
+        let method_id = NodeId::new(1, "Device.SetCounter");
+        let counter_id = NodeId::new(1, "Device.Counter");
+        {
+            let address_space_ref = address_space.clone();
+            let mut address_space = trace_write_lock!(address_space);
+            ObjectBuilder::new(&object_id, "Device", "Device")
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+            VariableBuilder::new(&counter_id, "Counter", "Counter")
+                .data_type(DataTypeId::Int32)
+                .value(0i32)
+                .component_of(object_id.clone())
+                .insert(&mut address_space);
+            MethodBuilder::new(&method_id, "SetCounter", "SetCounter")
+                .component_of(object_id.clone())
+                .callback(Box::new(SetCounter {
+                    address_space: address_space_ref.clone(),
+                    counter_id: counter_id.clone(),
+                }))
+                .insert(&mut address_space);
+        }
+
+        let result = call_single(
+            s,
+            server_state,
+            session_manager,
+            session,
+            address_space.clone(),
+            new_call_method_request(object_id, method_id, Some(vec![Variant::from(7i32)])),
+        )
+        .unwrap();
+        assert_eq!(result.status_code, StatusCode::Good);
+        let address_space = trace_read_lock!(address_space);
+        assert_eq!(
+            address_space.get_variable_value(counter_id).unwrap().value,
+            Some(Variant::from(7i32))
+        );
+    });
+}
+
+/// Adds two Int32 input arguments, failing if they are not of that type
+pub(super) struct Add;
+
+impl callbacks::Method for Add {
+    fn call(
+        &mut self,
+        _session_id: &NodeId,
+        _session_manager: Arc<RwLock<SessionManager>>,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, StatusCode> {
+        let sum = match request.input_arguments.as_deref() {
+            Some([Variant::Int32(a), Variant::Int32(b)]) => a + b,
+            _ => return Err(StatusCode::BadInvalidArgument),
+        };
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: Some(vec![Variant::from(sum)]),
+        })
+    }
+}
+
+#[test]
+fn call_validates_arguments() {
+    do_method_service_test(|server_state, session_manager, session, address_space, s| {
+        let object_id = NodeId::new(1, "Calculator");
+        let add_id = NodeId::new(1, "Calculator.Add");
+        let add_guid_id = NodeId::new(1, "Calculator.AddGuid");
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            ObjectBuilder::new(&object_id, "Calculator", "Calculator")
+                .organized_by(ObjectId::ObjectsFolder)
+                .insert(&mut address_space);
+            let input_args: [Argument; 2] = [
+                ("A", DataTypeId::Int32).into(),
+                ("B", DataTypeId::Int32).into(),
+            ];
+            MethodBuilder::new(&add_id, "Add", "Add")
+                .component_of(object_id.clone())
+                .input_args(&mut address_space, &input_args)
+                .output_args(&mut address_space, &[("Sum", DataTypeId::Int64).into()])
+                .callback(Box::new(Add))
+                .insert(&mut address_space);
+            // The output argument of this method cannot be converted from what it returns
+            MethodBuilder::new(&add_guid_id, "AddGuid", "AddGuid")
+                .component_of(object_id.clone())
+                .input_args(&mut address_space, &input_args)
+                .output_args(&mut address_space, &[("Sum", DataTypeId::Guid).into()])
+                .callback(Box::new(Add))
+                .insert(&mut address_space);
+        }
+
+        let session_id = {
+            let session = trace_read_lock!(session);
+            session.session_id().clone()
+        };
+        let call = |method_id: &NodeId, args: Vec<Variant>| {
+            new_call_method_request(object_id.clone(), method_id.clone(), Some(args))
+        };
+        let response = s.call(
+            server_state,
+            &session_id,
+            session_manager,
+            address_space,
+            &CallRequest {
+                request_header: RequestHeader::dummy(),
+                methods_to_call: Some(vec![
+                    // 1. arguments that are converted to the data type of the arguments
+                    call(&add_id, vec![Variant::from(1i16), Variant::from(2i32)]),
+                    // 2. an argument of the wrong type
+                    call(
+                        &add_id,
+                        vec![Variant::from(1i32), Variant::from(Guid::new())],
+                    ),
+                    // 3. an array for a scalar argument
+                    call(
+                        &add_id,
+                        vec![Variant::from(1i32), Variant::from(vec![2i32])],
+                    ),
+                    // 4. too few arguments
+                    call(&add_id, vec![Variant::from(1i32)]),
+                    // 5. an output argument of the wrong type
+                    call(&add_guid_id, vec![Variant::from(1i32), Variant::from(2i32)]),
+                    // 6. a null argument
+                    call(&add_id, vec![Variant::from(1i32), Variant::Empty]),
+                ]),
+            },
+        );
+        let response: CallResponse = supported_message_as!(response, CallResponse);
+        let results = response.results.unwrap();
+
+        // 1. the output argument is converted to the data type of the output argument
+        assert_eq!(results[0].status_code, StatusCode::Good);
+        assert_eq!(
+            results[0].input_argument_results,
+            Some(vec![StatusCode::Good, StatusCode::Good])
+        );
+        assert_eq!(results[0].output_arguments, Some(vec![Variant::Int64(3)]));
+        // 2., 3. and 6. the status of each argument is returned
+        for result in [&results[1], &results[2], &results[5]] {
+            assert_eq!(result.status_code, StatusCode::BadInvalidArgument);
+            assert_eq!(
+                result.input_argument_results,
+                Some(vec![StatusCode::Good, StatusCode::BadTypeMismatch])
+            );
+        }
+        // 4. too few arguments
+        assert_eq!(results[3].status_code, StatusCode::BadArgumentsMissing);
+        // 5. an output argument of the wrong type
+        assert_eq!(results[4].status_code, StatusCode::BadInternalError);
+    });
+}
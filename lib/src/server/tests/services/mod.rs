@@ -114,6 +114,13 @@ where
     );
 }
 
+/// Makes a session manager with only the session, for services that look at every session
+fn session_manager_of(session: &Arc<RwLock<Session>>) -> Arc<RwLock<SessionManager>> {
+    let mut session_manager = SessionManager::default();
+    session_manager.register_session(session.clone());
+    Arc::new(RwLock::new(session_manager))
+}
+
 /// Creates a blank subscription request
 fn create_subscription_request(
     max_keep_alive_count: u32,
@@ -171,6 +178,7 @@ pub mod discovery;
 pub mod method;
 pub mod monitored_item;
 pub mod node_management;
+pub mod node_manager;
 pub mod session;
 pub mod subscription;
 pub mod view;
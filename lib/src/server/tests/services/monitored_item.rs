@@ -519,6 +519,123 @@ fn monitored_item_data_change_filter() {
     )
 }
 
+fn analog_item_node_id() -> NodeId {
+    NodeId::new(1, 100)
+}
+
+fn make_create_request_percent_deadband(node_id: NodeId) -> MonitoredItemCreateRequest {
+    let filter = ExtensionObject::from_encodable(
+        ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+        &DataChangeFilter {
+            trigger: DataChangeTrigger::StatusValue,
+            deadband_type: DeadbandType::Percent as u32,
+            deadband_value: 10f64,
+        },
+    );
+    make_create_request(-1f64, 5, node_id, AttributeId::Value, filter)
+}
+
+#[test]
+fn monitored_item_percent_deadband() {
+    do_subscription_service_test(
+        |server_state,
+         _session,
+         _address_space,
+         _ss: SubscriptionService,
+         _mis: MonitoredItemService| {
+            let mut address_space = make_address_space();
+            let server_state = trace_read_lock!(server_state);
+
+            AnalogItemBuilder::new(
+                VariableBuilder::new(&analog_item_node_id(), "Analog", "Analog")
+                    .data_type(DataTypeId::Double)
+                    .value(50f64)
+                    .organized_by(ObjectId::ObjectsFolder),
+            )
+            .eu_range(0f64, 200f64)
+            .insert(&mut address_space);
+
+            // A node without an EURange cannot have a percent deadband
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                1,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_percent_deadband(test_var_node_id()),
+            )
+            .unwrap();
+            assert_eq!(
+                monitored_item.validate_filter(&address_space).unwrap_err(),
+                StatusCode::BadFilterNotAllowed
+            );
+
+            let mut monitored_item = MonitoredItem::new(
+                &chrono::Utc::now(),
+                2,
+                TimestampsToReturn::Both,
+                &server_state,
+                &make_create_request_percent_deadband(analog_item_node_id()),
+            )
+            .unwrap();
+            assert!(monitored_item.validate_filter(&address_space).is_ok());
+
+            let now = Utc::now();
+            let timestamp = DateTime::now();
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+
+            // 5% of the range is within the deadband
+            let _ = address_space.set_variable_value(
+                analog_item_node_id(),
+                60f64,
+                &timestamp,
+                &timestamp,
+            );
+            monitored_item.tick(&now, &address_space, true, false);
+            assert_eq!(monitored_item.notification_queue().len(), 1);
+
+            // 12.5% of the range is not
+            let _ = address_space.set_variable_value(
+                analog_item_node_id(),
+                75f64,
+                &timestamp,
+                &timestamp,
+            );
+            assert_eq!(
+                monitored_item.tick(&now, &address_space, true, false),
+                TickResult::ReportValueChanged
+            );
+            assert_eq!(monitored_item.notification_queue().len(), 2);
+
+            // The deadband follows a change to the EURange
+            let eu_range_id = address_space
+                .find_property(&analog_item_node_id(), "EURange")
+                .unwrap()
+                .node_id();
+            let eu_range = ExtensionObject::from_encodable(
+                ObjectId::Range_Encoding_DefaultBinary,
+                &Range {
+                    low: 0f64,
+                    high: 1000f64,
+                },
+            );
+            let _ = address_space.set_variable_value(eu_range_id, eu_range, &timestamp, &timestamp);
+
+            // 4% of the new range is within the deadband
+            let _ = address_space.set_variable_value(
+                analog_item_node_id(),
+                115f64,
+                &timestamp,
+                &timestamp,
+            );
+            monitored_item.tick(&now, &address_space, true, false);
+            assert_eq!(monitored_item.notification_queue().len(), 2);
+        },
+    )
+}
+
 #[test]
 fn monitored_item_event_filter() {
     // create an address space
@@ -642,7 +759,12 @@ fn unknown_node_id() {
             let subscription_id = {
                 let request = create_subscription_request(0, 0);
                 let response: CreateSubscriptionResponse = supported_message_as!(
-                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    ss.create_subscription(
+                        server_state.clone(),
+                        session_manager_of(&session),
+                        session.clone(),
+                        &request,
+                    ),
                     CreateSubscriptionResponse
                 );
                 response.subscription_id
@@ -691,7 +813,12 @@ fn monitored_item_triggers() {
             let subscription_id = {
                 let request = create_subscription_request(0, 0);
                 let response: CreateSubscriptionResponse = supported_message_as!(
-                    ss.create_subscription(server_state.clone(), session.clone(), &request),
+                    ss.create_subscription(
+                        server_state.clone(),
+                        session_manager_of(&session),
+                        session.clone(),
+                        &request,
+                    ),
                     CreateSubscriptionResponse
                 );
                 response.subscription_id
@@ -1,7 +1,11 @@
-use crate::server::services::node_management::NodeManagementService;
+use crate::server::services::{
+    attribute::AttributeService, node_management::NodeManagementService,
+};
 use crate::supported_message_as;
 use crate::sync::*;
-use crate::types::node_ids::{DataTypeId, MethodId, ObjectId, ObjectTypeId, ReferenceTypeId};
+use crate::types::node_ids::{
+    DataTypeId, MethodId, ObjectId, ObjectTypeId, ReferenceTypeId, VariableTypeId,
+};
 
 use super::*;
 
@@ -760,3 +764,584 @@ fn delete_references_target_node_id_invalid() {
         StatusCode::BadTargetNodeIdInvalid,
     );
 }
+
+#[test]
+fn add_nodes_reference_not_allowed() {
+    // A variable cannot be a component of a reference type
+    do_add_node_test_with_expected_error(
+        true,
+        AddNodesItem {
+            parent_node_id: ReferenceTypeId::Organizes.into(),
+            reference_type_id: ReferenceTypeId::HasComponent.into(),
+            requested_new_node_id: ExpandedNodeId::null(),
+            browse_name: QualifiedName::from("boo"),
+            node_class: NodeClass::Variable,
+            node_attributes: variable_attributes("foo"),
+            type_definition: VariableTypeId::BaseDataVariableType.into(),
+        },
+        StatusCode::BadReferenceNotAllowed,
+    );
+
+    // The reference from the parent must be hierarchical
+    do_add_node_test_with_expected_error(
+        true,
+        AddNodesItem {
+            parent_node_id: ObjectId::ObjectsFolder.into(),
+            reference_type_id: ReferenceTypeId::HasTypeDefinition.into(),
+            requested_new_node_id: ExpandedNodeId::null(),
+            browse_name: QualifiedName::from("boo"),
+            node_class: NodeClass::Object,
+            node_attributes: object_attributes("foo"),
+            type_definition: ObjectTypeId::BaseObjectType.into(),
+        },
+        StatusCode::BadReferenceNotAllowed,
+    );
+
+    // Abstract reference types cannot be used
+    do_add_node_test_with_expected_error(
+        true,
+        AddNodesItem {
+            parent_node_id: ObjectId::ObjectsFolder.into(),
+            reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+            requested_new_node_id: ExpandedNodeId::null(),
+            browse_name: QualifiedName::from("boo"),
+            node_class: NodeClass::Object,
+            node_attributes: object_attributes("foo"),
+            type_definition: ObjectTypeId::BaseObjectType.into(),
+        },
+        StatusCode::BadReferenceNotAllowed,
+    );
+}
+
+#[test]
+fn add_references_reference_not_allowed() {
+    // A property must be a variable
+    do_add_references_test(
+        true,
+        AddReferencesItem {
+            source_node_id: ObjectId::RootFolder.into(),
+            reference_type_id: ReferenceTypeId::HasProperty.into(),
+            is_forward: true,
+            target_server_uri: UAString::null(),
+            target_node_id: ObjectId::Server.into(),
+            target_node_class: NodeClass::Object,
+        },
+        StatusCode::BadReferenceNotAllowed,
+    );
+
+    // Subtypes must be of the same node class
+    do_add_references_test(
+        true,
+        AddReferencesItem {
+            source_node_id: ObjectTypeId::BaseObjectType.into(),
+            reference_type_id: ReferenceTypeId::HasSubtype.into(),
+            is_forward: true,
+            target_server_uri: UAString::null(),
+            target_node_id: var_node_id(1).into(),
+            target_node_class: NodeClass::Variable,
+        },
+        StatusCode::BadReferenceNotAllowed,
+    );
+}
+
+/// Adds an object type with a mandatory Speed component and an optional Limit property
+fn add_pump_type(address_space: Arc<RwLock<AddressSpace>>) -> NodeId {
+    let mut address_space = trace_write_lock!(address_space);
+    let pump_type_id = NodeId::new(1, "PumpType");
+    ObjectTypeBuilder::new(&pump_type_id, "PumpType", "PumpType")
+        .subtype_of(ObjectTypeId::BaseObjectType)
+        .insert(&mut address_space);
+    VariableBuilder::new(&NodeId::new(1, "PumpType.Speed"), "Speed", "Speed")
+        .data_type(DataTypeId::Double)
+        .value(0f64)
+        .component_of(pump_type_id.clone())
+        .has_type_definition(VariableTypeId::BaseDataVariableType)
+        .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+        .insert(&mut address_space);
+    VariableBuilder::new(&NodeId::new(1, "PumpType.Limit"), "Limit", "Limit")
+        .data_type(DataTypeId::Double)
+        .value(100f64)
+        .property_of(pump_type_id.clone())
+        .has_type_definition(VariableTypeId::PropertyType)
+        .has_modelling_rule(ObjectId::ModellingRule_Optional)
+        .insert(&mut address_space);
+    pump_type_id
+}
+
+fn add_node(
+    server_state: Arc<RwLock<ServerState>>,
+    session: Arc<RwLock<Session>>,
+    address_space: Arc<RwLock<AddressSpace>>,
+    nms: &NodeManagementService,
+    item: AddNodesItem,
+) -> AddNodesResult {
+    let response = nms.add_nodes(
+        server_state,
+        session,
+        address_space,
+        &AddNodesRequest {
+            request_header: RequestHeader::dummy(),
+            nodes_to_add: Some(vec![item]),
+        },
+    );
+    let response: AddNodesResponse = supported_message_as!(response, AddNodesResponse);
+    response.results.unwrap().remove(0)
+}
+
+#[test]
+fn add_nodes_references_from_parent() {
+    // The reference is from the parent to the new node, not the other way around
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let result = add_node(
+            server_state,
+            session,
+            address_space.clone(),
+            &nms,
+            AddNodesItem {
+                parent_node_id: ObjectId::ObjectsFolder.into(),
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                requested_new_node_id: ExpandedNodeId::null(),
+                browse_name: QualifiedName::from("boo"),
+                node_class: NodeClass::Object,
+                node_attributes: object_attributes("foo"),
+                type_definition: ObjectTypeId::BaseObjectType.into(),
+            },
+        );
+        assert_eq!(result.status_code, StatusCode::Good);
+
+        let objects_folder_id = NodeId::objects_folder_id();
+        let address_space = trace_read_lock!(address_space);
+        assert!(address_space.has_reference(
+            &objects_folder_id,
+            &result.added_node_id,
+            ReferenceTypeId::Organizes
+        ));
+        assert!(!address_space.has_reference(
+            &result.added_node_id,
+            &objects_folder_id,
+            ReferenceTypeId::Organizes
+        ));
+    });
+}
+
+#[test]
+fn add_nodes_browse_name_duplicated_in_namespace() {
+    // A browse name with a namespace is a duplicate of a child with the same name and namespace
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let item = |browse_name: QualifiedName| AddNodesItem {
+            parent_node_id: ObjectId::ObjectsFolder.into(),
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            requested_new_node_id: ExpandedNodeId::null(),
+            browse_name,
+            node_class: NodeClass::Object,
+            node_attributes: object_attributes("foo"),
+            type_definition: ObjectTypeId::BaseObjectType.into(),
+        };
+        let add = |browse_name: QualifiedName| {
+            add_node(
+                server_state.clone(),
+                session.clone(),
+                address_space.clone(),
+                &nms,
+                item(browse_name),
+            )
+            .status_code
+        };
+        assert_eq!(add(QualifiedName::new(1, "boo")), StatusCode::Good);
+        assert_eq!(
+            add(QualifiedName::new(1, "boo")),
+            StatusCode::BadBrowseNameDuplicated
+        );
+        // The same name in another namespace is not a duplicate
+        assert_eq!(add(QualifiedName::new(2, "boo")), StatusCode::Good);
+    });
+}
+
+#[test]
+fn add_nodes_instantiates_type() {
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let pump_type_id = add_pump_type(address_space.clone());
+
+        let result = add_node(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            AddNodesItem {
+                parent_node_id: ObjectId::ObjectsFolder.into(),
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                requested_new_node_id: NodeId::new(1, "Pump1").into(),
+                browse_name: QualifiedName::new(1, "Pump1"),
+                node_class: NodeClass::Object,
+                node_attributes: object_attributes("Pump1"),
+                type_definition: pump_type_id.into(),
+            },
+        );
+        assert_eq!(result.status_code, StatusCode::Good);
+        let pump_id = result.added_node_id;
+
+        // The mandatory child is created, the optional one is not
+        let speed_id = NodeId::new(1, "Pump1/Speed");
+        {
+            let address_space = trace_read_lock!(address_space);
+            assert!(address_space.has_reference(
+                &ObjectId::ObjectsFolder.into(),
+                &pump_id,
+                ReferenceTypeId::Organizes
+            ));
+            assert!(address_space.has_reference(
+                &pump_id,
+                &speed_id,
+                ReferenceTypeId::HasComponent
+            ));
+            assert!(address_space.has_reference(
+                &speed_id,
+                &VariableTypeId::BaseDataVariableType.into(),
+                ReferenceTypeId::HasTypeDefinition
+            ));
+            let speed = address_space.find_variable(speed_id.clone()).unwrap();
+            assert_eq!(speed.data_type(), DataTypeId::Double.into());
+            assert!(address_space
+                .find_node(&NodeId::new(1, "Pump1/Limit"))
+                .is_none());
+        }
+
+        // The optional property must be added with the reference type it was declared with
+        let limit =
+            |reference_type_id: ReferenceTypeId, type_definition: VariableTypeId| AddNodesItem {
+                parent_node_id: pump_id.clone().into(),
+                reference_type_id: reference_type_id.into(),
+                requested_new_node_id: ExpandedNodeId::null(),
+                browse_name: QualifiedName::from("Limit"),
+                node_class: NodeClass::Variable,
+                node_attributes: variable_attributes("Limit"),
+                type_definition: type_definition.into(),
+            };
+        let result = add_node(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            limit(
+                ReferenceTypeId::HasComponent,
+                VariableTypeId::BaseDataVariableType,
+            ),
+        );
+        assert_eq!(result.status_code, StatusCode::BadReferenceNotAllowed);
+        let result = add_node(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            limit(
+                ReferenceTypeId::HasProperty,
+                VariableTypeId::BaseDataVariableType,
+            ),
+        );
+        assert_eq!(result.status_code, StatusCode::BadTypeDefinitionInvalid);
+        let result = add_node(
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &nms,
+            limit(ReferenceTypeId::HasProperty, VariableTypeId::PropertyType),
+        );
+        assert_eq!(result.status_code, StatusCode::Good);
+        let limit_id = result.added_node_id;
+
+        // Deleting the instance deletes its children and leaves no references to them
+        let response = nms.delete_nodes(
+            server_state,
+            session,
+            address_space.clone(),
+            &DeleteNodesRequest {
+                request_header: RequestHeader::dummy(),
+                nodes_to_delete: Some(vec![DeleteNodesItem {
+                    node_id: pump_id.clone(),
+                    delete_target_references: true,
+                }]),
+            },
+        );
+        let response: DeleteNodesResponse = supported_message_as!(response, DeleteNodesResponse);
+        assert_eq!(response.results.unwrap()[0], StatusCode::Good);
+        let address_space = trace_read_lock!(address_space);
+        for node_id in &[pump_id, speed_id, limit_id] {
+            assert!(address_space.find_node(node_id).is_none());
+            assert!(!address_space.references().reference_to_node_exists(node_id));
+        }
+    });
+}
+
+#[test]
+fn add_nodes_recursive_type() {
+    // A type with a Mandatory child of the same type cannot be instantiated
+    do_node_management_service_test(true, |server_state, session, address_space, nms| {
+        let loop_type_id = NodeId::new(1, "LoopType");
+        {
+            let mut address_space = trace_write_lock!(address_space);
+            ObjectTypeBuilder::new(&loop_type_id, "LoopType", "LoopType")
+                .subtype_of(ObjectTypeId::BaseObjectType)
+                .insert(&mut address_space);
+            ObjectBuilder::new(&NodeId::new(1, "LoopType.Next"), "Next", "Next")
+                .component_of(loop_type_id.clone())
+                .has_type_definition(loop_type_id.clone())
+                .has_modelling_rule(ObjectId::ModellingRule_Mandatory)
+                .insert(&mut address_space);
+        }
+
+        let result = add_node(
+            server_state,
+            session,
+            address_space.clone(),
+            &nms,
+            AddNodesItem {
+                parent_node_id: ObjectId::ObjectsFolder.into(),
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                requested_new_node_id: NodeId::new(1, "Loop1").into(),
+                browse_name: QualifiedName::new(1, "Loop1"),
+                node_class: NodeClass::Object,
+                node_attributes: object_attributes("Loop1"),
+                type_definition: loop_type_id.into(),
+            },
+        );
+        assert_eq!(result.status_code, StatusCode::BadInvalidArgument);
+
+        // Nothing of the instance is left behind
+        let address_space = trace_read_lock!(address_space);
+        let loop_id = NodeId::new(1, "Loop1");
+        assert!(address_space.find_node(&loop_id).is_none());
+        assert!(address_space
+            .find_node(&NodeId::new(1, "Loop1/Next"))
+            .is_none());
+        assert!(!address_space.has_reference(
+            &ObjectId::ObjectsFolder.into(),
+            &loop_id,
+            ReferenceTypeId::Organizes
+        ));
+    });
+}
+
+#[test]
+fn journal_replay() {
+    // Changes made through the node management services and writes to persistent nodes are
+    // journaled and can be replayed into a fresh address space
+    crate::console_logging::init();
+
+    let mut journal_path = std::env::temp_dir();
+    journal_path.push(format!("opcua-journal-test-{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&journal_path);
+
+    let new_service_test = || {
+        let st =
+            ServiceTest::new_with_server(ServerBuilder::new_sample().journal_path(&journal_path));
+        {
+            let mut session = trace_write_lock!(st.session);
+            session.set_can_modify_address_space(true);
+        }
+        let _ = add_many_vars_to_address_space(st.address_space.clone(), 10);
+        st
+    };
+
+    let added_node_id = {
+        let st = new_service_test();
+        {
+            let mut address_space = trace_write_lock!(st.address_space);
+            let node = address_space.find_node_mut(&var_node_id(1)).unwrap();
+            let _ = node.as_mut_node().set_attribute(
+                AttributeId::AccessLevel,
+                Variant::from(AccessLevel::CURRENT_WRITE.bits()),
+            );
+            let _ = node.as_mut_node().set_attribute(
+                AttributeId::UserAccessLevel,
+                Variant::from(UserAccessLevel::CURRENT_WRITE.bits()),
+            );
+            address_space.set_persistent(&var_node_id(1), true);
+        }
+
+        let nms = NodeManagementService::new();
+        let response = nms.add_nodes(
+            st.server_state.clone(),
+            st.session.clone(),
+            st.address_space.clone(),
+            &AddNodesRequest {
+                request_header: RequestHeader::dummy(),
+                nodes_to_add: Some(vec![AddNodesItem {
+                    parent_node_id: ObjectId::ObjectsFolder.into(),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    requested_new_node_id: ExpandedNodeId::null(),
+                    browse_name: QualifiedName::from("journaled"),
+                    node_class: NodeClass::Object,
+                    node_attributes: object_attributes("journaled"),
+                    type_definition: ObjectTypeId::BaseObjectType.into(),
+                }]),
+            },
+        );
+        let response: AddNodesResponse = supported_message_as!(response, AddNodesResponse);
+        let added_node_id = response.results.unwrap()[0].added_node_id.clone();
+        {
+            let address_space = trace_read_lock!(st.address_space);
+            assert!(address_space.is_persistent(&added_node_id));
+        }
+
+        let response = nms.add_references(
+            st.server_state.clone(),
+            st.session.clone(),
+            st.address_space.clone(),
+            &AddReferencesRequest {
+                request_header: RequestHeader::dummy(),
+                references_to_add: Some(vec![AddReferencesItem {
+                    source_node_id: added_node_id.clone(),
+                    reference_type_id: ReferenceTypeId::Organizes.into(),
+                    is_forward: true,
+                    target_server_uri: UAString::null(),
+                    target_node_id: var_node_id(3).into(),
+                    target_node_class: NodeClass::Variable,
+                }]),
+            },
+        );
+        let response: AddReferencesResponse =
+            supported_message_as!(response, AddReferencesResponse);
+        assert_eq!(response.results.unwrap()[0], StatusCode::Good);
+
+        let response = nms.delete_nodes(
+            st.server_state.clone(),
+            st.session.clone(),
+            st.address_space.clone(),
+            &DeleteNodesRequest {
+                request_header: RequestHeader::dummy(),
+                nodes_to_delete: Some(vec![DeleteNodesItem {
+                    node_id: var_node_id(2),
+                    delete_target_references: true,
+                }]),
+            },
+        );
+        let response: DeleteNodesResponse = supported_message_as!(response, DeleteNodesResponse);
+        assert_eq!(response.results.unwrap()[0], StatusCode::Good);
+
+        // Write to a persistent and a non-persistent node
+        let ats = AttributeService::new();
+        let nodes_to_write = vec![
+            WriteValue {
+                node_id: var_node_id(1),
+                attribute_id: AttributeId::Value as u32,
+                index_range: UAString::null(),
+                value: DataValue::value_only(99i32),
+            },
+            WriteValue {
+                node_id: var_node_id(4),
+                attribute_id: AttributeId::Value as u32,
+                index_range: UAString::null(),
+                value: DataValue::value_only(99i32),
+            },
+        ];
+        let _ = ats.write(
+            st.server_state.clone(),
+            st.session.clone(),
+            st.address_space.clone(),
+            &WriteRequest {
+                request_header: RequestHeader::dummy(),
+                nodes_to_write: Some(nodes_to_write),
+            },
+        );
+
+        added_node_id
+    };
+
+    // Replay the journal into a fresh server
+    let st = new_service_test();
+    {
+        let server_state = trace_read_lock!(st.server_state);
+        let mut address_space = trace_write_lock!(st.address_space);
+        let journal = server_state.journal().unwrap();
+        let applied = journal
+            .replay(&mut address_space, &DecodingOptions::default())
+            .unwrap();
+        assert_eq!(applied, 4);
+
+        assert!(address_space.find_node(&added_node_id).is_some());
+        assert!(address_space.has_reference(
+            &added_node_id,
+            &var_node_id(3),
+            ReferenceTypeId::Organizes
+        ));
+        assert!(address_space.find_node(&var_node_id(2)).is_none());
+
+        let value_of = |node_id: &NodeId| match address_space.find_node(node_id) {
+            Some(NodeType::Variable(v)) => v
+                .value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0.,
+                )
+                .value
+                .unwrap(),
+            _ => panic!(),
+        };
+        assert_eq!(value_of(&var_node_id(1)), Variant::Int32(99));
+        assert_eq!(value_of(&var_node_id(4)), Variant::Int32(4));
+    }
+
+    let _ = std::fs::remove_file(&journal_path);
+}
+
+#[test]
+fn journal_truncated_record() {
+    // A record damaged by a crash during a write is removed on replay so that the entries
+    // recorded afterwards are not lost behind it
+    use crate::server::journal::{Journal, JournalEntry};
+    use std::io::Write;
+
+    let mut journal_path = std::env::temp_dir();
+    journal_path.push(format!(
+        "opcua-journal-truncated-test-{}.bin",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&journal_path);
+
+    let delete_reference = |target: usize| {
+        JournalEntry::DeleteReference(DeleteReferencesItem {
+            source_node_id: ObjectId::ObjectsFolder.into(),
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            is_forward: true,
+            target_node_id: var_node_id(target).into(),
+            delete_bidirectional: true,
+        })
+    };
+
+    let journal = Journal::new(&journal_path);
+    journal.record(delete_reference(1));
+    journal.record(delete_reference(2));
+    journal.sync();
+    let good_len = std::fs::metadata(&journal_path).unwrap().len();
+
+    // Append the start of a record, as if the server crashed while writing it
+    {
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&journal_path)
+            .unwrap();
+        file.write_all(&[4, 0, 1]).unwrap();
+    }
+
+    let journal = Journal::new(&journal_path);
+    let entries = journal.read_entries(&DecodingOptions::default()).unwrap();
+    assert_eq!(entries, vec![delete_reference(1), delete_reference(2)]);
+    assert_eq!(std::fs::metadata(&journal_path).unwrap().len(), good_len);
+
+    // Entries recorded after the replay are read back the next time
+    journal.record(delete_reference(3));
+    journal.sync();
+    let journal = Journal::new(&journal_path);
+    let entries = journal.read_entries(&DecodingOptions::default()).unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            delete_reference(1),
+            delete_reference(2),
+            delete_reference(3)
+        ]
+    );
+
+    let _ = std::fs::remove_file(&journal_path);
+}
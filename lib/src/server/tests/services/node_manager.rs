@@ -0,0 +1,677 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use crate::server::{
+    node_manager::{NodeManager, RequestContext},
+    services::{
+        attribute::AttributeService, method::MethodService, monitored_item::MonitoredItemService,
+        subscription::SubscriptionService, unregistered_request_context, view::ViewService,
+    },
+    subscriptions::monitored_item::{MonitoredItem, Notification},
+};
+use crate::supported_message_as;
+use crate::types::service_types::Argument;
+
+use super::{method::Add, *};
+
+const HISTORIAN_NAMESPACE: &str = "urn:historian";
+const TAG_COUNT: i32 = 10;
+
+/// A node manager with a root folder of virtual tags, whose values are their number until
+/// they are written
+#[derive(Default)]
+struct Historian {
+    ns: u16,
+    written: Mutex<HashMap<NodeId, i32>>,
+    monitored: Mutex<Vec<NodeId>>,
+}
+
+impl Historian {
+    fn root(&self) -> NodeId {
+        NodeId::new(self.ns, "Historian")
+    }
+
+    fn tag(&self, i: i32) -> NodeId {
+        NodeId::new(self.ns, format!("Tag{}", i))
+    }
+
+    /// Returns the number of the tag with the node id
+    fn tag_number(node_id: &NodeId) -> Option<i32> {
+        match node_id.identifier {
+            Identifier::String(ref s) => s.as_ref().strip_prefix("Tag")?.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn value(&self, node_id: &NodeId) -> Option<i32> {
+        let i = Self::tag_number(node_id).filter(|i| (0..TAG_COUNT).contains(i))?;
+        Some(*self.written.lock().get(node_id).unwrap_or(&i))
+    }
+}
+
+#[async_trait]
+impl NodeManager for Historian {
+    async fn browse(
+        &self,
+        _context: &RequestContext,
+        node_to_browse: &BrowseDescription,
+    ) -> Result<Vec<ReferenceDescription>, StatusCode> {
+        if node_to_browse.node_id != self.root() {
+            return Err(StatusCode::BadNodeIdUnknown);
+        }
+        Ok((0..TAG_COUNT)
+            .map(|i| ReferenceDescription {
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                is_forward: true,
+                node_id: self.tag(i).into(),
+                browse_name: QualifiedName::new(self.ns, format!("Tag{}", i)),
+                display_name: format!("Tag{}", i).into(),
+                node_class: NodeClass::Variable,
+                type_definition: VariableTypeId::BaseDataVariableType.into(),
+            })
+            .collect())
+    }
+
+    async fn read(
+        &self,
+        _context: &RequestContext,
+        nodes_to_read: &[ReadValueId],
+        _max_age: f64,
+        _timestamps_to_return: TimestampsToReturn,
+    ) -> Vec<DataValue> {
+        nodes_to_read
+            .iter()
+            .map(|node_to_read| match self.value(&node_to_read.node_id) {
+                Some(value) if node_to_read.attribute_id == AttributeId::Value as u32 => {
+                    DataValue::new_now(value)
+                }
+                Some(_) => DataValue {
+                    status: Some(StatusCode::BadAttributeIdInvalid),
+                    ..DataValue::null()
+                },
+                None => DataValue {
+                    status: Some(StatusCode::BadNodeIdUnknown),
+                    ..DataValue::null()
+                },
+            })
+            .collect()
+    }
+
+    async fn write(
+        &self,
+        _context: &RequestContext,
+        nodes_to_write: &[WriteValue],
+    ) -> Vec<Result<(), DiagnosticError>> {
+        nodes_to_write
+            .iter()
+            .map(|node_to_write| {
+                match (
+                    self.value(&node_to_write.node_id),
+                    &node_to_write.value.value,
+                ) {
+                    (Some(_), Some(Variant::Int32(value))) => {
+                        self.written
+                            .lock()
+                            .insert(node_to_write.node_id.clone(), *value);
+                        Ok(())
+                    }
+                    (Some(_), _) => Err(StatusCode::BadTypeMismatch.into()),
+                    (None, _) => Err(StatusCode::BadNodeIdUnknown.into()),
+                }
+            })
+            .collect()
+    }
+
+    async fn call(
+        &self,
+        _context: &RequestContext,
+        request: &CallMethodRequest,
+    ) -> Result<CallMethodResult, DiagnosticError> {
+        if request.object_id != self.root() {
+            return Err(StatusCode::BadNodeIdUnknown.into());
+        }
+        Ok(CallMethodResult {
+            status_code: StatusCode::Good,
+            input_argument_results: None,
+            input_argument_diagnostic_infos: None,
+            output_arguments: Some(vec![Variant::from(TAG_COUNT)]),
+        })
+    }
+
+    fn register_monitored_item(
+        &self,
+        item_to_monitor: &ReadValueId,
+        _sampling_interval: f64,
+    ) -> Result<(), StatusCode> {
+        if self.value(&item_to_monitor.node_id).is_some() {
+            self.monitored.lock().push(item_to_monitor.node_id.clone());
+            Ok(())
+        } else {
+            Err(StatusCode::BadNodeIdUnknown)
+        }
+    }
+
+    fn unregister_monitored_item(&self, item_to_monitor: &ReadValueId) {
+        self.monitored
+            .lock()
+            .retain(|node_id| *node_id != item_to_monitor.node_id);
+    }
+
+    fn sample(&self, item_to_monitor: &ReadValueId) -> Option<DataValue> {
+        self.value(&item_to_monitor.node_id).map(DataValue::new_now)
+    }
+}
+
+/// Registers a historian with the address space and mounts its root under the objects folder
+fn register_historian(st: &ServiceTest) -> Arc<Historian> {
+    let mut address_space = trace_write_lock!(st.address_space);
+    let ns = address_space
+        .register_namespace(HISTORIAN_NAMESPACE)
+        .unwrap();
+    let historian = Arc::new(Historian {
+        ns,
+        ..Default::default()
+    });
+    let default_namespace = address_space.default_namespace();
+    assert_eq!(
+        address_space.register_node_manager(HISTORIAN_NAMESPACE, historian.clone()),
+        Ok(ns)
+    );
+    assert_eq!(address_space.default_namespace(), default_namespace);
+    ObjectBuilder::new(&historian.root(), "Historian", "Historian")
+        .is_folder()
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut address_space);
+    historian
+}
+
+fn context(st: &ServiceTest) -> RequestContext {
+    RequestContext::for_session(&st.session, st.session_manager.clone())
+}
+
+fn read_value_id(node_id: NodeId) -> ReadValueId {
+    ReadValueId {
+        node_id,
+        attribute_id: AttributeId::Value as u32,
+        index_range: UAString::null(),
+        data_encoding: QualifiedName::null(),
+    }
+}
+
+fn read(st: &ServiceTest, nodes_to_read: Vec<NodeId>) -> Vec<DataValue> {
+    let request = ReadRequest {
+        request_header: make_request_header(),
+        max_age: 0f64,
+        timestamps_to_return: TimestampsToReturn::Both,
+        nodes_to_read: Some(nodes_to_read.into_iter().map(read_value_id).collect()),
+    };
+    let response = AttributeService::new()
+        .read_deferred(
+            st.server_state.clone(),
+            st.session.clone(),
+            context(st),
+            st.address_space.clone(),
+            &request,
+        )
+        .wait();
+    supported_message_as!(response, ReadResponse)
+        .results
+        .unwrap()
+}
+
+fn browse(st: &ServiceTest, node_id: NodeId, max_references_per_node: u32) -> BrowseResult {
+    let request = BrowseRequest {
+        request_header: make_request_header(),
+        view: ViewDescription {
+            view_id: NodeId::null(),
+            timestamp: DateTime::null(),
+            view_version: 0,
+        },
+        requested_max_references_per_node: max_references_per_node,
+        nodes_to_browse: Some(vec![BrowseDescription {
+            node_id,
+            browse_direction: BrowseDirection::Forward,
+            reference_type_id: ReferenceTypeId::Organizes.into(),
+            include_subtypes: true,
+            node_class_mask: 0,
+            result_mask: BrowseDescriptionResultMask::all().bits(),
+        }]),
+    };
+    let response = ViewService::new()
+        .browse_deferred(
+            st.server_state.clone(),
+            st.session.clone(),
+            context(st),
+            st.address_space.clone(),
+            &request,
+        )
+        .wait();
+    supported_message_as!(response, BrowseResponse)
+        .results
+        .unwrap()
+        .remove(0)
+}
+
+#[test]
+fn register_node_manager() {
+    let st = ServiceTest::new();
+    let mut address_space = trace_write_lock!(st.address_space);
+    let historian = Arc::new(Historian::default());
+    // The namespace of OPC UA cannot be delegated
+    assert_eq!(
+        address_space.register_node_manager("http://opcfoundation.org/UA/", historian.clone()),
+        Err(StatusCode::BadInvalidArgument)
+    );
+    assert!(address_space.node_manager(0).is_none());
+    // Nor can a namespace that cannot be registered
+    assert_eq!(
+        address_space.register_node_manager("", historian.clone()),
+        Err(StatusCode::BadInvalidArgument)
+    );
+
+    let ns = address_space
+        .register_node_manager(HISTORIAN_NAMESPACE, historian)
+        .unwrap();
+    assert_eq!(address_space.namespace_index(HISTORIAN_NAMESPACE), Some(ns));
+    assert!(address_space.node_manager(ns).is_some());
+}
+
+#[test]
+fn read_from_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+
+    // Reads of managed and built-in nodes may be mixed in one request
+    let results = read(
+        &st,
+        vec![
+            historian.tag(5),
+            VariableId::Server_ServerStatus_State.into(),
+            historian.tag(TAG_COUNT),
+        ],
+    );
+    assert_eq!(results[0].value, Some(Variant::Int32(5)));
+    assert!(results[0].source_timestamp.is_some());
+    assert_eq!(results[1].status(), StatusCode::Good);
+    assert_eq!(results[2].status(), StatusCode::BadNodeIdUnknown);
+}
+
+#[test]
+fn write_to_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+
+    let nodes_to_write = vec![
+        WriteValue {
+            node_id: historian.tag(3),
+            attribute_id: AttributeId::Value as u32,
+            index_range: UAString::null(),
+            value: Variant::Int32(42).into(),
+        },
+        WriteValue {
+            node_id: historian.tag(4),
+            attribute_id: AttributeId::Value as u32,
+            index_range: UAString::null(),
+            value: Variant::from("x").into(),
+        },
+    ];
+    let request = WriteRequest {
+        request_header: make_request_header(),
+        nodes_to_write: Some(nodes_to_write),
+    };
+    let response = AttributeService::new()
+        .write_deferred(
+            st.server_state.clone(),
+            st.session.clone(),
+            context(&st),
+            st.address_space.clone(),
+            &request,
+        )
+        .wait();
+    let results = supported_message_as!(response, WriteResponse)
+        .results
+        .unwrap();
+    assert_eq!(results, vec![StatusCode::Good, StatusCode::BadTypeMismatch]);
+
+    let results = read(&st, vec![historian.tag(3)]);
+    assert_eq!(results[0].value, Some(Variant::Int32(42)));
+}
+
+#[test]
+fn browse_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+
+    // The root of the historian is mounted in the objects folder
+    let result = browse(&st, ObjectId::ObjectsFolder.into(), 1000);
+    assert!(result
+        .references
+        .unwrap()
+        .iter()
+        .any(|r| r.node_id.node_id == historian.root()));
+
+    // The tags of the historian are paged by continuation points
+    let result = browse(&st, historian.root(), 4);
+    assert_eq!(result.status_code, StatusCode::Good);
+    let references = result.references.unwrap();
+    assert_eq!(references.len(), 4);
+    assert_eq!(references[0].node_id.node_id, historian.tag(0));
+    assert!(!result.continuation_point.is_null());
+
+    let request = BrowseNextRequest {
+        request_header: make_request_header(),
+        release_continuation_points: false,
+        continuation_points: Some(vec![result.continuation_point]),
+    };
+    let response =
+        ViewService::new().browse_next(st.session.clone(), st.address_space.clone(), &request);
+    let result = supported_message_as!(response, BrowseNextResponse)
+        .results
+        .unwrap()
+        .remove(0);
+    let references = result.references.unwrap();
+    assert_eq!(references[0].node_id.node_id, historian.tag(4));
+
+    // Unknown nodes of the historian
+    let result = browse(&st, historian.tag(1), 0);
+    assert_eq!(result.status_code, StatusCode::BadNodeIdUnknown);
+}
+
+#[test]
+fn translate_browse_path_in_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+
+    let browse_path = |target_name: &str| BrowsePath {
+        starting_node: historian.root(),
+        relative_path: RelativePath {
+            elements: Some(vec![RelativePathElement {
+                reference_type_id: ReferenceTypeId::Organizes.into(),
+                is_inverse: false,
+                include_subtypes: true,
+                target_name: QualifiedName::new(historian.ns, target_name),
+            }]),
+        },
+    };
+    let request = TranslateBrowsePathsToNodeIdsRequest {
+        request_header: make_request_header(),
+        browse_paths: Some(vec![browse_path("Tag7"), browse_path("Tag70")]),
+    };
+    let response = ViewService::new()
+        .translate_browse_paths_to_node_ids_deferred(
+            st.server_state.clone(),
+            context(&st),
+            st.address_space.clone(),
+            &request,
+        )
+        .wait();
+    let results = supported_message_as!(response, TranslateBrowsePathsToNodeIdsResponse)
+        .results
+        .unwrap();
+    assert_eq!(results[0].status_code, StatusCode::Good);
+    assert_eq!(
+        results[0].targets.as_ref().unwrap()[0].target_id.node_id,
+        historian.tag(7)
+    );
+    assert_eq!(results[1].status_code, StatusCode::BadNoMatch);
+}
+
+#[test]
+fn call_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+
+    let session_id = {
+        let session = trace_read_lock!(st.session);
+        session.session_id().clone()
+    };
+    let request = CallRequest {
+        request_header: make_request_header(),
+        methods_to_call: Some(vec![CallMethodRequest {
+            object_id: historian.root(),
+            method_id: NodeId::new(historian.ns, "TagCount"),
+            input_arguments: None,
+        }]),
+    };
+    let response = MethodService::new().call(
+        st.server_state.clone(),
+        &session_id,
+        st.session_manager.clone(),
+        st.address_space.clone(),
+        &request,
+    );
+    let result = supported_message_as!(response, CallResponse)
+        .results
+        .unwrap()
+        .remove(0);
+    assert_eq!(result.status_code, StatusCode::Good);
+    assert_eq!(
+        result.output_arguments,
+        Some(vec![Variant::Int32(TAG_COUNT)])
+    );
+}
+
+#[test]
+fn monitored_items_of_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+    let (ss, mis) = (SubscriptionService::new(), MonitoredItemService::new());
+
+    let subscription_id = {
+        let request = create_subscription_request(0, 0);
+        let response: CreateSubscriptionResponse = supported_message_as!(
+            ss.create_subscription(
+                st.server_state.clone(),
+                st.session_manager.clone(),
+                st.session.clone(),
+                &request
+            ),
+            CreateSubscriptionResponse
+        );
+        response.subscription_id
+    };
+
+    // Items of unknown nodes are rejected by the node manager
+    let request = create_monitored_items_request(
+        subscription_id,
+        vec![historian.tag(1), historian.tag(TAG_COUNT)],
+    );
+    let response: CreateMonitoredItemsResponse = supported_message_as!(
+        mis.create_monitored_items(
+            st.server_state.clone(),
+            st.session.clone(),
+            st.address_space.clone(),
+            &request
+        ),
+        CreateMonitoredItemsResponse
+    );
+    let results = response.results.unwrap();
+    assert_eq!(results[0].status_code, StatusCode::Good);
+    assert_eq!(results[1].status_code, StatusCode::BadNodeIdUnknown);
+    assert_eq!(*historian.monitored.lock(), vec![historian.tag(1)]);
+
+    let request = DeleteMonitoredItemsRequest {
+        request_header: make_request_header(),
+        subscription_id,
+        monitored_item_ids: Some(vec![results[0].monitored_item_id]),
+    };
+    let response = mis.delete_monitored_items(st.session.clone(), &request);
+    let _ = supported_message_as!(response, DeleteMonitoredItemsResponse);
+    assert!(historian.monitored.lock().is_empty());
+}
+
+#[test]
+fn sample_node_manager() {
+    let st = ServiceTest::new();
+    let historian = register_historian(&st);
+    let server_state = trace_read_lock!(st.server_state);
+    let address_space = trace_read_lock!(st.address_space);
+
+    let request = MonitoredItemCreateRequest {
+        item_to_monitor: read_value_id(historian.tag(2)),
+        monitoring_mode: MonitoringMode::Reporting,
+        requested_parameters: MonitoringParameters {
+            client_handle: 1,
+            sampling_interval: -1f64,
+            filter: ExtensionObject::null(),
+            queue_size: 5,
+            discard_oldest: true,
+        },
+    };
+    let mut monitored_item = MonitoredItem::new(
+        &chrono::Utc::now(),
+        1,
+        TimestampsToReturn::Both,
+        &server_state,
+        &request,
+    )
+    .unwrap();
+    monitored_item.register_with(historian.clone()).unwrap();
+
+    let now = chrono::Utc::now();
+    let tick = |monitored_item: &mut MonitoredItem| {
+        let _ = monitored_item.tick(&now, &address_space, true, false);
+        monitored_item
+            .all_notifications()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|n| match n {
+                Notification::MonitoredItemNotification(n) => n.value.value.unwrap(),
+                _ => panic!(),
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(tick(&mut monitored_item), vec![Variant::Int32(2)]);
+    assert!(tick(&mut monitored_item).is_empty());
+    historian.written.lock().insert(historian.tag(2), 20);
+    assert_eq!(tick(&mut monitored_item), vec![Variant::Int32(20)]);
+}
+
+/// Returns an address space to hold the nodes of a namespace of the server, with the same
+/// namespace index
+fn other_address_space(st: &ServiceTest) -> (u16, AddressSpace) {
+    let ns = {
+        let mut address_space = trace_write_lock!(st.address_space);
+        address_space
+            .register_namespace(HISTORIAN_NAMESPACE)
+            .unwrap()
+    };
+    let mut other = AddressSpace::new();
+    while other.namespaces().len() < ns as usize {
+        let namespace = format!("urn:other{}", other.namespaces().len());
+        other.register_namespace(&namespace).unwrap();
+    }
+    assert_eq!(other.register_namespace(HISTORIAN_NAMESPACE), Ok(ns));
+    (ns, other)
+}
+
+#[test]
+fn address_space_as_node_manager() {
+    let st = ServiceTest::new();
+    let (ns, mut other) = other_address_space(&st);
+    let node_id = NodeId::new(ns, "v1");
+    VariableBuilder::new(&node_id, "v1", "v1")
+        .data_type(DataTypeId::Int32)
+        .value(11)
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut other);
+    let other = Arc::new(RwLock::new(other));
+    {
+        let mut address_space = trace_write_lock!(st.address_space);
+        assert_eq!(
+            address_space.register_node_manager(HISTORIAN_NAMESPACE, other),
+            Ok(ns)
+        );
+    }
+
+    let results = read(&st, vec![node_id.clone()]);
+    assert_eq!(results[0].value, Some(Variant::Int32(11)));
+
+    // A session that the session manager does not know cannot read the address space
+    let request = ReadRequest {
+        request_header: make_request_header(),
+        max_age: 0f64,
+        timestamps_to_return: TimestampsToReturn::Both,
+        nodes_to_read: Some(vec![read_value_id(node_id)]),
+    };
+    let response = AttributeService::new()
+        .read_deferred(
+            st.server_state.clone(),
+            st.session.clone(),
+            unregistered_request_context(&st.session),
+            st.address_space.clone(),
+            &request,
+        )
+        .wait();
+    let results = supported_message_as!(response, ReadResponse)
+        .results
+        .unwrap();
+    assert_eq!(results[0].status(), StatusCode::BadSessionIdInvalid);
+}
+
+#[test]
+fn address_space_as_node_manager_validates_arguments() {
+    let st = ServiceTest::new();
+    let (ns, mut other) = other_address_space(&st);
+    let object_id = NodeId::new(ns, "Calculator");
+    let add_id = NodeId::new(ns, "Calculator.Add");
+    let add_guid_id = NodeId::new(ns, "Calculator.AddGuid");
+    ObjectBuilder::new(&object_id, "Calculator", "Calculator")
+        .organized_by(ObjectId::ObjectsFolder)
+        .insert(&mut other);
+    let input_args: [Argument; 2] = [
+        ("A", DataTypeId::Int32).into(),
+        ("B", DataTypeId::Int32).into(),
+    ];
+    MethodBuilder::new(&add_id, "Add", "Add")
+        .component_of(object_id.clone())
+        .input_args(&mut other, &input_args)
+        .output_args(&mut other, &[("Sum", DataTypeId::Int64).into()])
+        .callback(Box::new(Add))
+        .insert(&mut other);
+    MethodBuilder::new(&add_guid_id, "AddGuid", "AddGuid")
+        .component_of(object_id.clone())
+        .input_args(&mut other, &input_args)
+        .output_args(&mut other, &[("Sum", DataTypeId::Guid).into()])
+        .callback(Box::new(Add))
+        .insert(&mut other);
+    let other = Arc::new(RwLock::new(other));
+
+    let context = context(&st);
+    let call = |method_id: &NodeId, args: Vec<Variant>| {
+        let request = CallMethodRequest {
+            object_id: object_id.clone(),
+            method_id: method_id.clone(),
+            input_arguments: Some(args),
+        };
+        futures::executor::block_on(other.call(&context, &request))
+    };
+
+    // The arguments are converted to the data types of the method's arguments
+    let result = call(&add_id, vec![Variant::from(1i16), Variant::from(2i32)]).unwrap();
+    assert_eq!(result.status_code, StatusCode::Good);
+    assert_eq!(result.output_arguments, Some(vec![Variant::Int64(3)]));
+
+    // A null argument does not match
+    let result = call(&add_id, vec![Variant::from(1i32), Variant::Empty]).unwrap();
+    assert_eq!(result.status_code, StatusCode::BadInvalidArgument);
+    assert_eq!(
+        result.input_argument_results,
+        Some(vec![StatusCode::Good, StatusCode::BadTypeMismatch])
+    );
+
+    // Missing arguments and output arguments of the wrong type are errors
+    assert_eq!(
+        call(&add_id, vec![Variant::from(1i32)])
+            .unwrap_err()
+            .status_code(),
+        StatusCode::BadArgumentsMissing
+    );
+    assert_eq!(
+        call(&add_guid_id, vec![Variant::from(1i32), Variant::from(2i32)])
+            .unwrap_err()
+            .status_code(),
+        StatusCode::BadInternalError
+    );
+}
@@ -1,10 +1,18 @@
-use crate::crypto::{random, user_identity::make_user_name_identity_token, SecurityPolicy};
+use openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Signer};
+
+use crate::crypto::{
+    random,
+    user_identity::{make_issued_identity_token, make_user_name_identity_token},
+    SecurityPolicy,
+};
 use crate::types::{ActivateSessionRequest, RequestHeader, SignatureData};
 
 use crate::server::{
     builder::ServerBuilder,
+    config::{ServerEndpoint, ServerJwtConfig, ServerUserToken},
     identity_token::{
-        POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15, POLICY_ID_USER_PASS_RSA_OAEP,
+        POLICY_ID_ISSUED_JWT, POLICY_ID_USER_PASS_NONE, POLICY_ID_USER_PASS_RSA_15,
+        POLICY_ID_USER_PASS_RSA_OAEP,
     },
     services::session::SessionService,
     state::ServerState,
@@ -320,3 +328,196 @@ fn user_name_pass_token() {
         },
     );
 }
+
+/// Signs the claims into a JWT with the RSA key
+fn make_jwt(alg: &str, claims: serde_json::Value, key: &PKey<openssl::pkey::Private>) -> String {
+    let encode =
+        |json: serde_json::Value| base64::encode_config(json.to_string(), base64::URL_SAFE_NO_PAD);
+    let signed = format!(
+        "{}.{}",
+        encode(serde_json::json!({ "alg": alg, "typ": "JWT" })),
+        encode(claims)
+    );
+    let mut signer = Signer::new(MessageDigest::sha256(), key).unwrap();
+    signer.update(signed.as_bytes()).unwrap();
+    let signature = signer.sign_to_vec().unwrap();
+    format!(
+        "{}.{}",
+        signed,
+        base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+    )
+}
+
+fn make_issued_token_extension_object(
+    security_policy: SecurityPolicy,
+    server_nonce: &ByteString,
+    server_cert: &Option<X509>,
+    token: &str,
+) -> ExtensionObject {
+    let user_token_policy = crate::types::service_types::UserTokenPolicy {
+        policy_id: UAString::from(POLICY_ID_ISSUED_JWT),
+        token_type: UserTokenType::IssuedToken,
+        issued_token_type: UAString::from(crate::crypto::ISSUED_TOKEN_TYPE_JWT),
+        issuer_endpoint_url: UAString::null(),
+        security_policy_uri: UAString::null(),
+    };
+    let token = make_issued_identity_token(
+        security_policy,
+        &user_token_policy,
+        server_nonce.as_ref(),
+        server_cert,
+        token,
+    )
+    .unwrap();
+    ExtensionObject::from_encodable(ObjectId::IssuedIdentityToken_Encoding_DefaultBinary, &token)
+}
+
+#[test]
+fn issued_jwt_token() {
+    crate::console_logging::init();
+
+    // The issuer's key and the public key that the server verifies tokens with
+    let issuer_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let keys_path = make_test_file("issued_jwt_token_keys.pem");
+    std::fs::write(&keys_path, issuer_key.public_key_to_pem().unwrap()).unwrap();
+
+    let mut jwt_config = ServerJwtConfig::new(keys_path.to_string_lossy());
+    jwt_config.issuer = Some("https://sso.example.com".into());
+    jwt_config.audience = Some("opcua".into());
+    jwt_config
+        .roles
+        .insert("plant-operators".into(), "Operator".into());
+    jwt_config
+        .roles
+        .insert("custom".into(), "ns=1;s=CustomRole".into());
+
+    // A user token whose keys cannot be read is skipped
+    let missing_keys_config =
+        ServerJwtConfig::new(make_test_file("issued_jwt_token_missing.pem").to_string_lossy());
+    let user_token_ids = vec!["missing_keys_user".to_string(), "jwt_user".to_string()];
+    let server_builder = ServerBuilder::new_sample()
+        .pki_dir("./pki_issued_jwt_token")
+        .user_token(
+            "missing_keys_user",
+            ServerUserToken::jwt("missing", missing_keys_config),
+        )
+        .user_token("jwt_user", ServerUserToken::jwt("sso", jwt_config))
+        .endpoint(
+            "jwt_none",
+            ServerEndpoint::new_none("/jwt", &user_token_ids),
+        )
+        .endpoint(
+            "jwt_basic256sha256",
+            ServerEndpoint::new_basic256sha256_sign_encrypt("/jwt", &user_token_ids),
+        );
+    let st = ServiceTest::new_with_server(server_builder);
+    let server_state = st.server_state.read();
+    let server_cert = server_state.server_certificate.clone();
+    let server_nonce = random::byte_string(32);
+    let request = dummy_activate_session_request();
+
+    const ENDPOINT_URL: &str = "opc.tcp://localhost:4855/jwt";
+
+    // The endpoints advertise the issued token policy
+    let endpoints = server_state
+        .new_endpoint_descriptions(ENDPOINT_URL)
+        .unwrap();
+    assert_eq!(endpoints.len(), 2);
+    endpoints.iter().for_each(|endpoint| {
+        let policy = endpoint.find_policy(UserTokenType::IssuedToken).unwrap();
+        assert_eq!(policy.policy_id.as_ref(), POLICY_ID_ISSUED_JWT);
+        assert_eq!(
+            policy.issued_token_type.as_ref(),
+            crate::crypto::ISSUED_TOKEN_TYPE_JWT
+        );
+    });
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": "https://sso.example.com",
+        "aud": ["account", "opcua"],
+        "sub": "jdoe",
+        "exp": now + 300,
+        "roles": ["plant-operators", "custom", "unmapped"],
+    });
+    let authenticate = |security_policy, security_mode, token: &str| {
+        let token =
+            make_issued_token_extension_object(security_policy, &server_nonce, &server_cert, token);
+        server_state.authenticate_user(
+            &request,
+            ENDPOINT_URL,
+            security_policy,
+            security_mode,
+            &token,
+            &server_nonce,
+        )
+    };
+
+    // A valid token authenticates with the roles mapped from its claims, plain and encrypted
+    let token = make_jwt("RS256", claims.clone(), &issuer_key);
+    let user = authenticate(SecurityPolicy::None, MessageSecurityMode::None, &token).unwrap();
+    assert_eq!(user.user_token_id, "jwt_user");
+    assert_eq!(user.subject.as_deref(), Some("jdoe"));
+    assert_eq!(
+        user.roles,
+        vec![
+            ObjectId::WellKnownRole_AuthenticatedUser.into(),
+            ObjectId::WellKnownRole_Operator.into(),
+            NodeId::new(1, "CustomRole"),
+        ]
+    );
+    let user = authenticate(
+        SecurityPolicy::Basic256Sha256,
+        MessageSecurityMode::SignAndEncrypt,
+        &token,
+    )
+    .unwrap();
+    assert_eq!(user.subject.as_deref(), Some("jdoe"));
+
+    // Tokens with the wrong issuer, audience or that have expired are rejected
+    let rejected = |changes: serde_json::Value| {
+        let mut claims = claims.clone();
+        changes
+            .as_object()
+            .unwrap()
+            .iter()
+            .for_each(|(k, v)| claims[k] = v.clone());
+        let token = make_jwt("RS256", claims, &issuer_key);
+        authenticate(SecurityPolicy::None, MessageSecurityMode::None, &token).unwrap_err()
+    };
+    let iss = serde_json::json!({ "iss": "https://evil.example.com" });
+    assert_eq!(rejected(iss), StatusCode::BadIdentityTokenRejected);
+    let aud = serde_json::json!({ "aud": "account" });
+    assert_eq!(rejected(aud), StatusCode::BadIdentityTokenRejected);
+    let exp = serde_json::json!({ "exp": now - 300 });
+    assert_eq!(rejected(exp), StatusCode::BadIdentityTokenRejected);
+    let nbf = serde_json::json!({ "nbf": now + 300 });
+    assert_eq!(rejected(nbf), StatusCode::BadIdentityTokenRejected);
+
+    // Tokens signed by another key or which claim to be unsigned are rejected
+    let other_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let token = make_jwt("RS256", claims.clone(), &other_key);
+    let result = authenticate(SecurityPolicy::None, MessageSecurityMode::None, &token);
+    assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenRejected);
+    let token = make_jwt("none", claims.clone(), &issuer_key);
+    let result = authenticate(SecurityPolicy::None, MessageSecurityMode::None, &token);
+    assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenRejected);
+
+    // Malformed tokens are invalid
+    let result = authenticate(SecurityPolicy::None, MessageSecurityMode::None, "not.a.jwt");
+    assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenInvalid);
+
+    // Endpoints without JWT users reject issued tokens
+    let token = make_jwt("RS256", claims, &issuer_key);
+    let token =
+        make_issued_token_extension_object(SecurityPolicy::None, &server_nonce, &None, &token);
+    let result = server_state.authenticate_endpoint(
+        &request,
+        "opc.tcp://localhost:4855/",
+        SecurityPolicy::None,
+        MessageSecurityMode::None,
+        &token,
+        &server_nonce,
+    );
+    assert_eq!(result.unwrap_err(), StatusCode::BadIdentityTokenRejected);
+}
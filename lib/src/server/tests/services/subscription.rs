@@ -1,4 +1,4 @@
-use std::ops::Add;
+use std::{collections::BTreeMap, ops::Add};
 
 use chrono::Utc;
 
@@ -20,8 +20,9 @@ fn create_subscription(
 ) -> u32 {
     let request = create_subscription_request(0, 0);
     debug!("{:#?}", request);
+    let session_manager = session_manager_of(&session);
     let response: CreateSubscriptionResponse = supported_message_as!(
-        ss.create_subscription(server_state, session, &request),
+        ss.create_subscription(server_state, session_manager, session, &request),
         CreateSubscriptionResponse
     );
     debug!("{:#?}", response);
@@ -89,8 +90,9 @@ fn keepalive_test(
     do_subscription_service_test(|server_state, session, _, ss, _| {
         // Create subscription
         let request = create_subscription_request(keep_alive, lifetime);
+        let session_manager = session_manager_of(&session);
         let response: CreateSubscriptionResponse = supported_message_as!(
-            ss.create_subscription(server_state, session, &request),
+            ss.create_subscription(server_state, session_manager, session, &request),
             CreateSubscriptionResponse
         );
         debug!("{:#?}", response);
@@ -458,3 +460,300 @@ fn republish() {
         );
     })
 }
+
+#[test]
+fn subscription_diagnostics() {
+    do_subscription_service_test(|server_state, session, address_space, ss, mis| {
+        let subscription_id = create_subscription(server_state.clone(), session.clone(), &ss);
+        create_monitored_item(
+            subscription_id,
+            VariableId::Server_ServerStatus_StartTime,
+            server_state.clone(),
+            session.clone(),
+            address_space.clone(),
+            &mis,
+        );
+        {
+            let mut session = trace_write_lock!(session);
+            session
+                .subscriptions_mut()
+                .get_mut(subscription_id)
+                .unwrap()
+                .set_state(SubscriptionState::Normal);
+        }
+
+        // Publish and tick so the publish request is used to send a notification
+        let now = Utc::now();
+        let _ = ss.async_publish(
+            &now,
+            session.clone(),
+            address_space.clone(),
+            1001,
+            &publish_request(None),
+        );
+        {
+            let now = now.add(chrono::Duration::seconds(2));
+            let mut session = trace_write_lock!(session);
+            let address_space = trace_read_lock!(address_space);
+            let _ = session.tick_subscriptions(&now, &address_space, TickReason::TickTimerFired);
+        }
+
+        let diagnostics = trace_read_lock!(server_state).diagnostics.clone();
+        let subscription_diagnostics = trace_read_lock!(diagnostics).subscription_diagnostics_array();
+        assert_eq!(subscription_diagnostics.len(), 1);
+        let d = &subscription_diagnostics[0];
+        assert_eq!(d.subscription_id, subscription_id);
+        assert_eq!(d.max_notifications_per_publish, 5);
+        assert_eq!(d.publish_request_count, 1);
+        assert_eq!(d.data_change_notifications_count, 1);
+        assert_eq!(d.notifications_count, 1);
+        assert_eq!(d.monitored_item_count, 1);
+        assert_eq!(d.unacknowledged_message_count, 1);
+        assert_eq!(d.next_sequence_number, 2);
+
+        let sampling_interval_diagnostics =
+            trace_write_lock!(diagnostics).sampling_interval_diagnostics_array();
+        assert_eq!(sampling_interval_diagnostics.len(), 1);
+        assert_eq!(sampling_interval_diagnostics[0].monitored_item_count, 1);
+
+        let session_diagnostics = trace_read_lock!(diagnostics).session_diagnostics_array();
+        assert_eq!(session_diagnostics.len(), 1);
+        assert_eq!(session_diagnostics[0].current_subscriptions_count, 1);
+        assert_eq!(session_diagnostics[0].current_monitored_items_count, 1);
+
+        // The session's own node exposes its subscriptions
+        let session_id = {
+            let session = trace_read_lock!(session);
+            session.register_session(address_space.clone());
+            session.session_id().clone()
+        };
+        let address_space = trace_read_lock!(address_space);
+        let node_id = match session_id.identifier {
+            Identifier::String(ref id) => NodeId::new(
+                session_id.namespace,
+                format!("{}.SubscriptionDiagnosticsArray", id),
+            ),
+            _ => panic!("session id should be a string"),
+        };
+        let value = address_space
+            .find_variable(node_id)
+            .unwrap()
+            .value(
+                TimestampsToReturn::Neither,
+                NumericRange::None,
+                &QualifiedName::null(),
+                0f64,
+            )
+            .value
+            .unwrap();
+        match value {
+            Variant::Array(array) => assert_eq!(array.values.len(), 1),
+            _ => panic!("expected an array, got {:?}", value),
+        }
+    })
+}
+
+#[test]
+fn diagnostics_enabled_flag() {
+    do_subscription_service_test(|server_state, session, address_space, ss, _| {
+        let _ = create_subscription(server_state.clone(), session.clone(), &ss);
+
+        let read_enabled_flag = |address_space: &AddressSpace| {
+            address_space
+                .find_variable(VariableId::Server_ServerDiagnostics_EnabledFlag)
+                .unwrap()
+                .value(
+                    TimestampsToReturn::Neither,
+                    NumericRange::None,
+                    &QualifiedName::null(),
+                    0f64,
+                )
+                .value
+                .unwrap()
+        };
+
+        let diagnostics = trace_read_lock!(server_state).diagnostics.clone();
+        {
+            let mut session = trace_write_lock!(session);
+            let address_space = trace_read_lock!(address_space);
+            assert_eq!(read_enabled_flag(&address_space), Variant::Boolean(true));
+            let _ =
+                session.tick_subscriptions(&Utc::now(), &address_space, TickReason::TickTimerFired);
+        }
+        assert_eq!(
+            trace_read_lock!(diagnostics)
+                .subscription_diagnostics_array()
+                .len(),
+            1
+        );
+
+        // Writing the flag switches off diagnostics
+        let mut address_space = trace_write_lock!(address_space);
+        let variable = address_space
+            .find_variable_mut(VariableId::Server_ServerDiagnostics_EnabledFlag)
+            .unwrap();
+        assert!(variable
+            .access_level()
+            .contains(AccessLevel::CURRENT_WRITE));
+        assert_eq!(
+            variable.set_value(NumericRange::None, Variant::from(1i32)),
+            Err(StatusCode::BadTypeMismatch)
+        );
+        variable
+            .set_value(NumericRange::None, Variant::Boolean(false))
+            .unwrap();
+        assert_eq!(read_enabled_flag(&address_space), Variant::Boolean(false));
+        assert!(!trace_read_lock!(diagnostics).is_enabled());
+        assert!(trace_read_lock!(diagnostics)
+            .subscription_diagnostics_array()
+            .is_empty());
+    })
+}
+
+#[test]
+fn transfer_mirrored_subscription() {
+    // Server a mirrors its subscriptions to server b
+    let st_a = ServiceTest::new();
+    let st_b = ServiceTest::new();
+    {
+        let mirrored_subscriptions = trace_read_lock!(st_b.server_state).mirrored_subscriptions();
+        trace_read_lock!(st_a.server_state).set_subscription_mirror(mirrored_subscriptions);
+    }
+    let ss = SubscriptionService::new();
+    let mis = MonitoredItemService::new();
+
+    let subscription_id = create_subscription(st_a.server_state.clone(), st_a.session.clone(), &ss);
+    create_monitored_item(
+        subscription_id,
+        VariableId::Server_ServerStatus_StartTime,
+        st_a.server_state.clone(),
+        st_a.session.clone(),
+        st_a.address_space.clone(),
+        &mis,
+    );
+
+    let mirrored_subscriptions = trace_read_lock!(st_b.server_state).mirrored_subscriptions();
+    let mirrored_subscription = mirrored_subscriptions.get(subscription_id).unwrap();
+    assert_eq!(mirrored_subscription.max_notifications_per_publish, 5);
+    assert_eq!(mirrored_subscription.monitored_items.len(), 1);
+
+    // The mirrored subscription survives being sent to a peer in another process
+    let mut stream = std::io::Cursor::new(mirrored_subscription.encode_to_vec());
+    assert_eq!(
+        MirroredSubscription::decode(&mut stream, &DecodingOptions::test()).unwrap(),
+        mirrored_subscription
+    );
+
+    // A client that fails over to server b transfers the subscription to its session
+    let transfer_subscriptions = |st: &ServiceTest| {
+        let request = TransferSubscriptionsRequest {
+            request_header: RequestHeader::dummy(),
+            subscription_ids: Some(vec![subscription_id]),
+            send_initial_values: true,
+        };
+        let response: TransferSubscriptionsResponse = supported_message_as!(
+            ss.transfer_subscriptions(
+                st.server_state.clone(),
+                st.session.clone(),
+                st.session_manager.clone(),
+                st.address_space.clone(),
+                &request
+            ),
+            TransferSubscriptionsResponse
+        );
+        response.results.unwrap()[0].status_code
+    };
+    assert_eq!(transfer_subscriptions(&st_b), StatusCode::Good);
+    assert!(mirrored_subscriptions.is_empty());
+    {
+        let session = trace_read_lock!(st_b.session);
+        let subscription = session
+            .subscriptions()
+            .subscriptions()
+            .get(&subscription_id)
+            .unwrap();
+        assert_eq!(subscription.monitored_items_len(), 1);
+        assert_eq!(subscription.max_notifications_per_publish(), 5);
+    }
+
+    // The subscription can only be transferred once
+    assert_eq!(
+        transfer_subscriptions(&st_b),
+        StatusCode::BadSubscriptionIdInvalid
+    );
+
+    // Deleting the subscription on server a deletes the mirror
+    trace_read_lock!(st_b.server_state)
+        .mirrored_subscriptions()
+        .update_subscription(&mirrored_subscription);
+    let request = DeleteSubscriptionsRequest {
+        request_header: RequestHeader::dummy(),
+        subscription_ids: Some(vec![subscription_id]),
+    };
+    let _ = ss.delete_subscriptions(st_a.session.clone(), &request);
+    assert!(mirrored_subscriptions.is_empty());
+}
+
+#[test]
+fn create_subscription_skips_ids_in_use() {
+    let st = ServiceTest::new();
+    let ss = SubscriptionService::new();
+    let create_subscription_in = |st: &ServiceTest| {
+        let request = create_subscription_request(0, 0);
+        let response: CreateSubscriptionResponse = supported_message_as!(
+            ss.create_subscription(
+                st.server_state.clone(),
+                st.session_manager.clone(),
+                st.session.clone(),
+                &request
+            ),
+            CreateSubscriptionResponse
+        );
+        response.subscription_id
+    };
+    let subscription = |server_state: &ServerState, subscription_id| {
+        Subscription::new(
+            server_state.diagnostics.clone(),
+            subscription_id,
+            true,
+            100f64,
+            30,
+            10,
+            0,
+        )
+    };
+    let other_session = Arc::new(RwLock::new(Session::new(st.server_state.clone())));
+    {
+        let mut server_state = trace_write_lock!(st.server_state);
+        server_state.last_subscription_id = 10;
+
+        // Id 11 is mirrored from a peer, 12 is used by another session and 13 by this one
+        server_state
+            .mirrored_subscriptions()
+            .update_subscription(&MirroredSubscription {
+                subscription_id: 11,
+                client_user_id: UAString::null(),
+                publishing_interval: 100f64,
+                lifetime_count: 30,
+                max_keep_alive_count: 10,
+                max_notifications_per_publish: 0,
+                priority: 0,
+                publishing_enabled: true,
+                monitored_items: BTreeMap::new(),
+            });
+        trace_write_lock!(other_session)
+            .subscriptions_mut()
+            .insert(12, subscription(&server_state, 12));
+        trace_write_lock!(st.session_manager).register_session(other_session);
+        trace_write_lock!(st.session)
+            .subscriptions_mut()
+            .insert(13, subscription(&server_state, 13));
+    }
+    let subscription_id = create_subscription_in(&st);
+    assert_eq!(subscription_id, 14);
+
+    // The id wraps around without being 0
+    trace_write_lock!(st.server_state).last_subscription_id = u32::MAX;
+    let subscription_id = create_subscription_in(&st);
+    assert_eq!(subscription_id, 1);
+}
@@ -4,9 +4,17 @@
 
 //! Contains the implementation of `DiagnosticInfo`.
 
-use std::io::{Read, Write};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+};
 
-use crate::types::{encoding::*, status_codes::StatusCode, string::UAString};
+use crate::types::{
+    date_time::DateTime, encoding::*, extension_object::ExtensionObject,
+    localized_text::LocalizedText, request_header::RequestHeader, response_header::ResponseHeader,
+    status_codes::StatusCode, string::UAString,
+};
 
 bitflags! {
     pub struct DiagnosticInfoMask: u8 {
@@ -216,3 +224,303 @@ impl DiagnosticInfo {
         encoding_mask
     }
 }
+
+/// An error with the details that explain it. A server returns the details as `DiagnosticInfo`
+/// to a client that asks for them through the `return_diagnostics` of its request header.
+///
+/// An error can be made from a `StatusCode`, so callbacks that return one may use `?` on results
+/// with a status code error.
+#[derive(PartialEq, Debug, Clone)]
+pub struct DiagnosticError {
+    /// The status code of the error
+    pub status_code: StatusCode,
+    /// A symbolic id for the error. The name of the status code is used if there is none.
+    pub symbolic_id: Option<String>,
+    /// The namespace that qualifies the symbolic id
+    pub namespace_uri: Option<String>,
+    /// A human readable summary of the error. The description of the status code is used if
+    /// there is none.
+    pub localized_text: Option<Box<LocalizedText>>,
+    /// Detailed application specific information, e.g. the reason a value was rejected
+    pub additional_info: Option<String>,
+    /// A status code provided by an underlying system, e.g. a device
+    pub inner_status_code: Option<StatusCode>,
+    /// The error of the underlying system
+    pub inner_error: Option<Box<DiagnosticError>>,
+}
+
+impl From<StatusCode> for DiagnosticError {
+    fn from(status_code: StatusCode) -> Self {
+        DiagnosticError::new(status_code)
+    }
+}
+
+impl From<DiagnosticError> for StatusCode {
+    fn from(error: DiagnosticError) -> Self {
+        error.status_code
+    }
+}
+
+impl fmt::Display for DiagnosticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.status_code)?;
+        if let Some(ref additional_info) = self.additional_info {
+            write!(f, " ({})", additional_info)?;
+        }
+        if let Some(ref inner_error) = self.inner_error {
+            write!(f, ", caused by {}", inner_error)?;
+        } else if let Some(inner_status_code) = self.inner_status_code {
+            write!(f, ", caused by {}", inner_status_code)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DiagnosticError {}
+
+impl DiagnosticError {
+    pub fn new(status_code: StatusCode) -> DiagnosticError {
+        DiagnosticError {
+            status_code,
+            symbolic_id: None,
+            namespace_uri: None,
+            localized_text: None,
+            additional_info: None,
+            inner_status_code: None,
+            inner_error: None,
+        }
+    }
+
+    /// Sets the symbolic id and the namespace that qualifies it
+    pub fn with_symbolic_id<S, T>(mut self, namespace_uri: S, symbolic_id: T) -> Self
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        self.namespace_uri = Some(namespace_uri.into());
+        self.symbolic_id = Some(symbolic_id.into());
+        self
+    }
+
+    /// Sets the human readable summary of the error
+    pub fn with_localized_text<T>(mut self, localized_text: T) -> Self
+    where
+        T: Into<LocalizedText>,
+    {
+        self.localized_text = Some(Box::new(localized_text.into()));
+        self
+    }
+
+    /// Sets the detailed information of the error
+    pub fn with_additional_info<S>(mut self, additional_info: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.additional_info = Some(additional_info.into());
+        self
+    }
+
+    /// Sets the status code of the underlying system
+    pub fn with_inner_status_code(mut self, inner_status_code: StatusCode) -> Self {
+        self.inner_status_code = Some(inner_status_code);
+        self
+    }
+
+    /// Sets the error of the underlying system. Its status code becomes the inner status code.
+    pub fn with_inner_error(mut self, inner_error: DiagnosticError) -> Self {
+        self.inner_status_code = Some(inner_error.status_code);
+        self.inner_error = Some(Box::new(inner_error));
+        self
+    }
+
+    /// Returns the status code of the error
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+}
+
+/// Builds the `DiagnosticInfo` of a response and the string table of its response header that
+/// the diagnostic infos index into. Only the details asked for by the `return_diagnostics` bits
+/// of the request are returned.
+#[derive(Debug, Clone)]
+pub struct DiagnosticInfoBuilder {
+    /// The diagnostics asked for by the request
+    return_diagnostics: DiagnosticBits,
+    /// The strings that diagnostic infos refer to
+    string_table: Vec<UAString>,
+    /// The index of each string in the table
+    string_indices: HashMap<String, i32>,
+}
+
+impl DiagnosticInfoBuilder {
+    /// The bits of the service level diagnostics. The same bits shifted by this amount are the
+    /// operation level diagnostics.
+    const OPERATION_LEVEL_SHIFT: u32 = 5;
+    /// All of the service level bits
+    const SERVICE_LEVEL: DiagnosticBits = DiagnosticBits::from_bits_truncate(0x0000_001f);
+
+    pub fn new(request_header: &RequestHeader) -> DiagnosticInfoBuilder {
+        DiagnosticInfoBuilder {
+            return_diagnostics: request_header.return_diagnostics,
+            string_table: Vec::new(),
+            string_indices: HashMap::new(),
+        }
+    }
+
+    /// Tests if the request asked for any service level diagnostics
+    pub fn service_level_requested(&self) -> bool {
+        !self.service_level_bits().is_empty()
+    }
+
+    /// Tests if the request asked for any operation level diagnostics
+    pub fn operation_level_requested(&self) -> bool {
+        !self.operation_level_bits().is_empty()
+    }
+
+    fn service_level_bits(&self) -> DiagnosticBits {
+        self.return_diagnostics & Self::SERVICE_LEVEL
+    }
+
+    fn operation_level_bits(&self) -> DiagnosticBits {
+        // The operation level bits are moved to the place of the service level bits
+        DiagnosticBits::from_bits_truncate(
+            self.return_diagnostics.bits() >> Self::OPERATION_LEVEL_SHIFT,
+        ) & Self::SERVICE_LEVEL
+    }
+
+    /// Returns the service level diagnostic info of the error
+    pub fn service_diagnostics(&mut self, error: &DiagnosticError) -> DiagnosticInfo {
+        let bits = self.service_level_bits();
+        self.diagnostic_info(error, bits)
+    }
+
+    /// Returns the operation level diagnostic info of the error, or a null diagnostic info if
+    /// the operation succeeded.
+    pub fn operation_diagnostics(&mut self, error: Option<&DiagnosticError>) -> DiagnosticInfo {
+        if let Some(error) = error {
+            let bits = self.operation_level_bits();
+            self.diagnostic_info(error, bits)
+        } else {
+            DiagnosticInfo::null()
+        }
+    }
+
+    /// Returns the diagnostic infos of a list of operations in the same order, or `None` if the
+    /// request did not ask for operation level diagnostics or no operation has an error.
+    pub fn operation_diagnostic_infos<'a, I>(&mut self, errors: I) -> Option<Vec<DiagnosticInfo>>
+    where
+        I: IntoIterator<Item = Option<&'a DiagnosticError>>,
+    {
+        if !self.operation_level_requested() {
+            return None;
+        }
+        let errors = errors.into_iter().collect::<Vec<_>>();
+        if errors.iter().all(|error| error.is_none()) {
+            None
+        } else {
+            Some(
+                errors
+                    .into_iter()
+                    .map(|error| self.operation_diagnostics(error))
+                    .collect(),
+            )
+        }
+    }
+
+    /// Sets the string table of the response header to the strings that the diagnostic infos
+    /// built so far refer to.
+    pub fn set_string_table(self, response_header: &mut ResponseHeader) {
+        if !self.string_table.is_empty() {
+            response_header.string_table = Some(self.string_table);
+        }
+    }
+
+    /// Returns a response header for the service result that has service diagnostics if the
+    /// result is bad, and the string table of every diagnostic info built so far.
+    pub fn response_header(
+        self,
+        request_header: &RequestHeader,
+        service_result: &DiagnosticError,
+    ) -> ResponseHeader {
+        self.timestamped_response_header(DateTime::now(), request_header, service_result)
+    }
+
+    /// Returns a response header like `response_header` with the supplied timestamp
+    pub fn timestamped_response_header(
+        mut self,
+        timestamp: DateTime,
+        request_header: &RequestHeader,
+        service_result: &DiagnosticError,
+    ) -> ResponseHeader {
+        let service_diagnostics =
+            if service_result.status_code.is_bad() && self.service_level_requested() {
+                self.service_diagnostics(service_result)
+            } else {
+                DiagnosticInfo::null()
+            };
+        let mut response_header = ResponseHeader {
+            timestamp,
+            request_handle: request_header.request_handle,
+            service_result: service_result.status_code,
+            service_diagnostics,
+            string_table: None,
+            additional_header: ExtensionObject::null(),
+        };
+        self.set_string_table(&mut response_header);
+        response_header
+    }
+
+    fn string_index(&mut self, value: &str) -> i32 {
+        if let Some(index) = self.string_indices.get(value) {
+            *index
+        } else {
+            let index = self.string_table.len() as i32;
+            self.string_table.push(UAString::from(value));
+            self.string_indices.insert(value.to_string(), index);
+            index
+        }
+    }
+
+    /// Makes a diagnostic info with the details selected by the service level bits
+    fn diagnostic_info(&mut self, error: &DiagnosticError, bits: DiagnosticBits) -> DiagnosticInfo {
+        let mut diagnostic_info = DiagnosticInfo::null();
+        if bits.contains(DiagnosticBits::SERVICE_LEVEL_SYMBOLIC_ID) {
+            let symbolic_id = error
+                .symbolic_id
+                .as_deref()
+                .unwrap_or_else(|| error.status_code.name());
+            diagnostic_info.symbolic_id = Some(self.string_index(symbolic_id));
+            if let Some(ref namespace_uri) = error.namespace_uri {
+                diagnostic_info.namespace_uri = Some(self.string_index(namespace_uri));
+            }
+        }
+        if bits.contains(DiagnosticBits::SERVICE_LEVEL_LOCALIZED_TEXT) {
+            if let Some(ref localized_text) = error.localized_text {
+                if !localized_text.locale.is_empty() {
+                    diagnostic_info.locale =
+                        Some(self.string_index(localized_text.locale.as_ref()));
+                }
+                diagnostic_info.localized_text =
+                    Some(self.string_index(localized_text.text.as_ref()));
+            } else {
+                diagnostic_info.localized_text =
+                    Some(self.string_index(error.status_code.description()));
+            }
+        }
+        if bits.contains(DiagnosticBits::SERVICE_LEVEL_ADDITIONAL_INFO) {
+            if let Some(ref additional_info) = error.additional_info {
+                diagnostic_info.additional_info = Some(UAString::from(additional_info.as_str()));
+            }
+        }
+        if bits.contains(DiagnosticBits::SERVICE_LEVEL_LOCALIZED_INNER_STATUS_CODE) {
+            diagnostic_info.inner_status_code = error.inner_status_code;
+        }
+        if bits.contains(DiagnosticBits::SERVICE_LEVEL_LOCALIZED_INNER_DIAGNOSTICS) {
+            if let Some(ref inner_error) = error.inner_error {
+                diagnostic_info.inner_diagnostic_info =
+                    Some(Box::new(self.diagnostic_info(inner_error, bits)));
+            }
+        }
+        diagnostic_info
+    }
+}
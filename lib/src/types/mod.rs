@@ -217,12 +217,14 @@ pub mod service_types;
 pub mod status_code;
 pub mod string;
 pub mod variant;
+pub mod variant_value;
 
 pub use crate::types::{
     argument::*, array::*, attribute::*, basic_types::*, byte_string::*, data_types::*,
     data_value::*, date_time::*, diagnostic_info::*, encoding::*, extension_object::*, guid::*,
     localized_text::*, node_id::*, node_ids::*, numeric_range::*, operand::*, qualified_name::*,
     request_header::*, response_header::*, service_types::*, status_code::*, string::*, variant::*,
+    variant_value::*,
 };
 
 #[cfg(test)]
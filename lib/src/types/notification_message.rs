@@ -8,7 +8,7 @@ use crate::types::{
     diagnostic_info::DiagnosticInfo,
     encoding::DecodingOptions,
     extension_object::ExtensionObject,
-    node_id::Identifier,
+    node_id::{Identifier, NodeId},
     node_ids::ObjectId,
     service_types::{
         DataChangeNotification, EventFieldList, EventNotificationList, MonitoredItemNotification,
@@ -139,4 +139,26 @@ impl NotificationMessage {
             None
         }
     }
+
+    /// Extract the status change notifications from the message. Unparseable notifications will
+    /// be ignored.
+    pub fn status_changes(
+        &self,
+        decoding_options: &DecodingOptions,
+    ) -> Vec<StatusChangeNotification> {
+        if let Some(ref notification_data) = self.notification_data {
+            let status_change_id: NodeId =
+                ObjectId::StatusChangeNotification_Encoding_DefaultBinary.into();
+            notification_data
+                .iter()
+                .filter(|n| n.node_id == status_change_id)
+                .filter_map(|n| {
+                    n.decode_inner::<StatusChangeNotification>(decoding_options)
+                        .ok()
+                })
+                .collect()
+        } else {
+            Vec::new()
+        }
+    }
 }
@@ -90,6 +90,12 @@ impl From<&str> for LiteralOperand {
     }
 }
 
+impl From<NodeId> for LiteralOperand {
+    fn from(v: NodeId) -> Self {
+        Self::from(Variant::from(v))
+    }
+}
+
 impl From<()> for LiteralOperand {
     fn from(_v: ()) -> Self {
         Self::from(Variant::from(()))
@@ -407,6 +413,56 @@ impl ContentFilterBuilder {
         self.add_element(FilterOperator::BitwiseOr, vec![o1.into(), o2.into()])
     }
 
+    pub fn in_view<T>(self, view_id: T) -> Self
+    where
+        T: Into<NodeId>,
+    {
+        self.add_element(
+            FilterOperator::InView,
+            vec![Operand::literal(view_id.into())],
+        )
+    }
+
+    pub fn of_type<T>(self, type_id: T) -> Self
+    where
+        T: Into<NodeId>,
+    {
+        self.add_element(
+            FilterOperator::OfType,
+            vec![Operand::literal(type_id.into())],
+        )
+    }
+
+    /// Adds a RelatedTo element, which is true if the node is of the source type and references a
+    /// node of the target type, `hops` references away. Subtypes of the node types and the
+    /// reference type are optionally included.
+    pub fn related_to<T, S, R>(
+        self,
+        source_type_id: T,
+        target_type_id: S,
+        reference_type_id: R,
+        hops: u32,
+        include_type_subtypes: bool,
+        include_reference_subtypes: bool,
+    ) -> Self
+    where
+        T: Into<NodeId>,
+        S: Into<NodeId>,
+        R: Into<NodeId>,
+    {
+        self.add_element(
+            FilterOperator::RelatedTo,
+            vec![
+                Operand::literal(source_type_id.into()),
+                Operand::literal(target_type_id.into()),
+                Operand::literal(reference_type_id.into()),
+                Operand::literal(hops),
+                Operand::literal(include_type_subtypes),
+                Operand::literal(include_reference_subtypes),
+            ],
+        )
+    }
+
     pub fn build(self) -> ContentFilter {
         ContentFilter {
             elements: Some(self.elements),
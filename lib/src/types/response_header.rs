@@ -8,9 +8,9 @@ use std::{
 };
 
 use crate::types::{
-    data_types::*, date_time::DateTime, diagnostic_info::DiagnosticInfo, encoding::*,
-    extension_object::ExtensionObject, request_header::RequestHeader, status_codes::StatusCode,
-    string::UAString,
+    data_types::*, date_time::DateTime, diagnostic_info::DiagnosticInfo,
+    diagnostic_info::DiagnosticInfoBuilder, encoding::*, extension_object::ExtensionObject,
+    request_header::RequestHeader, status_codes::StatusCode, string::UAString,
 };
 
 /// The `ResponseHeader` contains information common to every response from server to client.
@@ -87,14 +87,12 @@ impl ResponseHeader {
         request_header: &RequestHeader,
         service_result: StatusCode,
     ) -> ResponseHeader {
-        ResponseHeader {
+        // A bad result has service diagnostics if the request asks for them
+        DiagnosticInfoBuilder::new(request_header).timestamped_response_header(
             timestamp,
-            request_handle: request_header.request_handle,
-            service_result,
-            service_diagnostics: DiagnosticInfo::default(),
-            string_table: None,
-            additional_header: ExtensionObject::null(),
-        }
+            request_header,
+            &service_result.into(),
+        )
     }
 
     /// For testing, nothing else
@@ -566,7 +566,7 @@ impl BinaryEncoder<IdType> for IdType {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
 pub enum NodeClass {
     Unspecified = 0,
     Object = 1,
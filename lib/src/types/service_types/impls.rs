@@ -16,10 +16,11 @@ use crate::types::{
     response_header::ResponseHeader,
     service_types::{
         enums::DeadbandType, AnonymousIdentityToken, ApplicationDescription, ApplicationType,
-        Argument, CallMethodRequest, DataChangeFilter, DataChangeTrigger, EndpointDescription,
-        MessageSecurityMode, MonitoredItemCreateRequest, MonitoringMode, MonitoringParameters,
-        ReadValueId, ServerDiagnosticsSummaryDataType, ServiceCounterDataType, ServiceFault,
-        SignatureData, UserNameIdentityToken, UserTokenPolicy, UserTokenType,
+        Argument, CallMethodRequest, DataChangeFilter, DataChangeTrigger, EUInformation,
+        EndpointDescription, IssuedIdentityToken, MessageSecurityMode, MonitoredItemCreateRequest,
+        MonitoringMode, MonitoringParameters, ReadValueId, ServerDiagnosticsSummaryDataType,
+        ServiceCounterDataType, ServiceFault, SignatureData, UserNameIdentityToken,
+        UserTokenPolicy, UserTokenType,
     },
     status_codes::StatusCode,
     string::UAString,
@@ -243,6 +244,18 @@ impl UserNameIdentityToken {
     }
 }
 
+impl IssuedIdentityToken {
+    // Get the plaintext token data as a string, if possible.
+    pub fn plaintext_token_data(&self) -> Result<String, StatusCode> {
+        if !self.encryption_algorithm.is_empty() || self.token_data.is_null() {
+            Err(StatusCode::BadIdentityTokenInvalid)
+        } else {
+            String::from_utf8(self.token_data.as_ref().to_vec())
+                .map_err(|_| StatusCode::BadIdentityTokenInvalid)
+        }
+    }
+}
+
 impl<'a> From<&'a NodeId> for ReadValueId {
     fn from(node_id: &'a NodeId) -> Self {
         Self::from(node_id.clone())
@@ -499,3 +512,35 @@ impl ServiceCounterDataType {
         self.error_count += 1;
     }
 }
+
+impl EUInformation {
+    /// The namespace of engineering units identified by their UNECE common code
+    pub const UNECE_NAMESPACE_URI: &'static str = "http://www.opcfoundation.org/UA/units/un/cefact";
+
+    /// Creates the engineering units for a UNECE common code such as "CEL" for degrees Celsius
+    /// or "KGM" for kilograms. The unit id is the code packed into an integer as described in
+    /// Part 8. Returns `None` if the code is not 1 to 3 ASCII characters long.
+    pub fn from_unece_code<S, T>(
+        common_code: &str,
+        display_name: S,
+        description: T,
+    ) -> Option<EUInformation>
+    where
+        S: Into<LocalizedText>,
+        T: Into<LocalizedText>,
+    {
+        if common_code.is_empty() || common_code.len() > 3 || !common_code.is_ascii() {
+            None
+        } else {
+            let unit_id = common_code
+                .bytes()
+                .fold(0i32, |unit_id, c| (unit_id << 8) | c as i32);
+            Some(EUInformation {
+                namespace_uri: UAString::from(Self::UNECE_NAMESPACE_URI),
+                unit_id,
+                display_name: display_name.into(),
+                description: description.into(),
+            })
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::types::*;
+
+fn request_header(return_diagnostics: DiagnosticBits) -> RequestHeader {
+    let mut request_header = RequestHeader::dummy();
+    request_header.return_diagnostics = return_diagnostics;
+    request_header
+}
+
+fn string_at(response_header: &ResponseHeader, index: Option<i32>) -> &str {
+    let string_table = response_header.string_table.as_ref().unwrap();
+    string_table[index.unwrap() as usize].as_ref()
+}
+
+#[test]
+fn diagnostic_info_not_requested() {
+    let request_header = request_header(DiagnosticBits::empty());
+    let error = DiagnosticError::new(StatusCode::BadNodeIdUnknown).with_additional_info("Unknown");
+
+    let mut diagnostics = DiagnosticInfoBuilder::new(&request_header);
+    assert!(!diagnostics.service_level_requested());
+    assert!(!diagnostics.operation_level_requested());
+    assert!(diagnostics
+        .operation_diagnostic_infos(vec![Some(&error)])
+        .is_none());
+
+    let response_header = diagnostics.response_header(&request_header, &error);
+    assert_eq!(response_header.service_result, StatusCode::BadNodeIdUnknown);
+    assert_eq!(response_header.service_diagnostics, DiagnosticInfo::null());
+    assert!(response_header.string_table.is_none());
+}
+
+#[test]
+fn diagnostic_info_service_level() {
+    let request_header = request_header(
+        DiagnosticBits::SERVICE_LEVEL_SYMBOLIC_ID
+            | DiagnosticBits::SERVICE_LEVEL_LOCALIZED_TEXT
+            | DiagnosticBits::SERVICE_LEVEL_LOCALIZED_INNER_STATUS_CODE,
+    );
+    let error = DiagnosticError::new(StatusCode::BadUserAccessDenied)
+        .with_additional_info("Not shown")
+        .with_inner_status_code(StatusCode::BadCommunicationError);
+
+    let diagnostics = DiagnosticInfoBuilder::new(&request_header);
+    assert!(diagnostics.service_level_requested());
+    assert!(!diagnostics.operation_level_requested());
+
+    let response_header = diagnostics.response_header(&request_header, &error);
+    let service_diagnostics = &response_header.service_diagnostics;
+    assert_eq!(
+        string_at(&response_header, service_diagnostics.symbolic_id),
+        "BadUserAccessDenied"
+    );
+    assert_eq!(
+        string_at(&response_header, service_diagnostics.localized_text),
+        StatusCode::BadUserAccessDenied.description()
+    );
+    assert!(service_diagnostics.locale.is_none());
+    assert!(service_diagnostics.additional_info.is_none());
+    assert_eq!(
+        service_diagnostics.inner_status_code,
+        Some(StatusCode::BadCommunicationError)
+    );
+
+    // A good result has no diagnostics
+    let diagnostics = DiagnosticInfoBuilder::new(&request_header);
+    let response_header = diagnostics.response_header(&request_header, &StatusCode::Good.into());
+    assert_eq!(response_header.service_diagnostics, DiagnosticInfo::null());
+    assert!(response_header.string_table.is_none());
+}
+
+#[test]
+fn diagnostic_info_operation_level() {
+    let request_header = request_header(
+        DiagnosticBits::OPERATIONAL_LEVEL_SYMBOLIC_ID
+            | DiagnosticBits::OPERATIONAL_LEVEL_LOCALIZED_TEXT
+            | DiagnosticBits::OPERATIONAL_LEVEL_ADDITIONAL_INFO
+            | DiagnosticBits::OPERATIONAL_LEVEL_INNER_DIAGNOSTICS,
+    );
+    let error1 = DiagnosticError::new(StatusCode::BadTypeMismatch)
+        .with_localized_text(LocalizedText::new("en", "Wrong type"))
+        .with_additional_info("Expected a Double");
+    let error2 = DiagnosticError::new(StatusCode::BadDeviceFailure)
+        .with_symbolic_id("urn:device", "Offline")
+        .with_inner_error(DiagnosticError::new(StatusCode::BadTypeMismatch));
+
+    let mut diagnostics = DiagnosticInfoBuilder::new(&request_header);
+    assert!(!diagnostics.service_level_requested());
+    assert!(diagnostics.operation_level_requested());
+
+    // Operations without errors have no diagnostic infos
+    assert!(diagnostics
+        .operation_diagnostic_infos(vec![None, None])
+        .is_none());
+
+    let diagnostic_infos = diagnostics
+        .operation_diagnostic_infos(vec![Some(&error1), None, Some(&error2)])
+        .unwrap();
+    assert_eq!(diagnostic_infos.len(), 3);
+    assert_eq!(diagnostic_infos[1], DiagnosticInfo::null());
+
+    // Service diagnostics are not requested
+    let response_header = diagnostics.response_header(&request_header, &error1);
+    assert_eq!(response_header.service_diagnostics, DiagnosticInfo::null());
+
+    let info1 = &diagnostic_infos[0];
+    assert_eq!(
+        string_at(&response_header, info1.symbolic_id),
+        "BadTypeMismatch"
+    );
+    assert!(info1.namespace_uri.is_none());
+    assert_eq!(string_at(&response_header, info1.locale), "en");
+    assert_eq!(
+        string_at(&response_header, info1.localized_text),
+        "Wrong type"
+    );
+    assert_eq!(
+        info1.additional_info,
+        UAString::from("Expected a Double").into()
+    );
+    assert!(info1.inner_status_code.is_none());
+
+    let info2 = &diagnostic_infos[2];
+    assert_eq!(string_at(&response_header, info2.symbolic_id), "Offline");
+    assert_eq!(
+        string_at(&response_header, info2.namespace_uri),
+        "urn:device"
+    );
+    assert!(info2.additional_info.is_none());
+    let inner_info = info2.inner_diagnostic_info.as_ref().unwrap();
+    // Strings are only added to the table once
+    assert_eq!(inner_info.symbolic_id, info1.symbolic_id);
+    assert_eq!(response_header.string_table.as_ref().unwrap().len(), 7);
+}
+
+#[test]
+fn diagnostic_error_status_code() {
+    let error = DiagnosticError::from(StatusCode::BadTimeout).with_additional_info("Too slow");
+    assert_eq!(error.status_code(), StatusCode::BadTimeout);
+    assert_eq!(StatusCode::from(error.clone()), StatusCode::BadTimeout);
+    assert_eq!(error.to_string(), "BadTimeout (Too slow)");
+}
@@ -1,4 +1,5 @@
 mod date_time;
+mod diagnostic_info;
 mod encoding;
 mod node_id;
 mod serde;
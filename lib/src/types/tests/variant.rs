@@ -5,8 +5,9 @@ use crate::types::{
     numeric_range::NumericRange,
     status_code::StatusCode,
     variant::{Variant, VariantTypeId},
-    ByteString, DataTypeId, DataValue, DateTime, DiagnosticInfo, ExpandedNodeId, Guid,
-    LocalizedText, NodeId, QualifiedName, UAString,
+    variant_value::VariantValue,
+    ByteString, DataTypeId, DataValue, DateTime, DiagnosticInfo, ExpandedNodeId, ExtensionObject,
+    Guid, LocalizedText, NodeClass, NodeId, ObjectId, QualifiedName, Range, UAString,
 };
 
 #[test]
@@ -1430,6 +1431,42 @@ fn variant_convert_expanded_node_id() {
     );
 }
 
+#[test]
+fn variant_convert_array() {
+    use crate::types::array::Array;
+
+    let v = Variant::from(
+        Array::new_multi(
+            VariantTypeId::Int32,
+            vec![Variant::from(1i32), Variant::from(2i32)],
+            vec![1u32, 2u32],
+        )
+        .unwrap(),
+    );
+    assert_eq!(v.convert(VariantTypeId::Int32), v);
+    // The elements are converted and the dimensions are kept
+    assert_eq!(
+        v.convert(VariantTypeId::Int64),
+        Variant::from(
+            Array::new_multi(
+                VariantTypeId::Int64,
+                vec![Variant::from(1i64), Variant::from(2i64)],
+                vec![1u32, 2u32],
+            )
+            .unwrap()
+        )
+    );
+    // Every element must convert
+    let v = Variant::from(
+        Array::new_single(
+            VariantTypeId::Guid,
+            vec![Variant::from(Guid::new()), Variant::from(Guid::new())],
+        )
+        .unwrap(),
+    );
+    ensure_conversion_fails(&v, &[VariantTypeId::Int32, VariantTypeId::Boolean]);
+}
+
 #[test]
 fn variant_cast_expanded_node_id() {
     let v = Variant::from(ExpandedNodeId {
@@ -1466,3 +1503,97 @@ fn variant_bytestring_to_bytearray() {
 }
 
 // TODO arrays
+
+#[test]
+fn variant_value_scalars() {
+    assert_eq!(i32::from_variant(&Variant::Int32(5)), Ok(5));
+    assert_eq!(5i32.into_variant(), Variant::Int32(5));
+    // Implicit conversions
+    assert_eq!(i32::from_variant(&Variant::Int16(5)), Ok(5));
+    assert_eq!(f64::from_variant(&Variant::Int32(5)), Ok(5.0));
+    assert_eq!(
+        String::from_variant(&Variant::from("hello")),
+        Ok("hello".to_string())
+    );
+    assert_eq!(
+        NodeId::from_variant(&Variant::from(NodeId::new(2, "v1"))),
+        Ok(NodeId::new(2, "v1"))
+    );
+    // Mismatches
+    assert_eq!(
+        bool::from_variant(&Variant::from("hello")),
+        Err(StatusCode::BadTypeMismatch)
+    );
+    assert_eq!(
+        i32::from_variant(&Variant::Empty),
+        Err(StatusCode::BadTypeMismatch)
+    );
+}
+
+#[test]
+fn variant_value_vec_and_option() {
+    let value = vec![1u32, 2, 3].into_variant();
+    assert!(value.is_array_of_type(VariantTypeId::UInt32));
+    assert_eq!(Vec::<u32>::from_variant(&value), Ok(vec![1, 2, 3]));
+    assert_eq!(Vec::<u32>::from_variant(&Variant::Empty), Ok(Vec::new()));
+    assert_eq!(
+        Vec::<u32>::from_variant(&Variant::UInt32(1)),
+        Err(StatusCode::BadTypeMismatch)
+    );
+    assert_eq!(
+        Vec::<bool>::from_variant(&value),
+        Err(StatusCode::BadTypeMismatch)
+    );
+    // An empty vec is an empty array of the type
+    let value = Vec::<String>::new().into_variant();
+    assert!(value.is_array_of_type(VariantTypeId::String));
+
+    assert_eq!(Option::<u32>::from_variant(&Variant::Empty), Ok(None));
+    assert_eq!(
+        Option::<u32>::from_variant(&Variant::UInt32(1)),
+        Ok(Some(1))
+    );
+    assert_eq!(Option::<u32>::None.into_variant(), Variant::Empty);
+}
+
+#[test]
+fn variant_value_enums_and_structures() {
+    let value = NodeClass::Variable.into_variant();
+    assert_eq!(value, Variant::Int32(2));
+    assert_eq!(NodeClass::from_variant(&value), Ok(NodeClass::Variable));
+    assert_eq!(
+        NodeClass::from_variant(&Variant::Int32(3)),
+        Err(StatusCode::BadTypeMismatch)
+    );
+
+    let range = Range {
+        low: 1.0,
+        high: 10.0,
+    };
+    let value = range.clone().into_variant();
+    assert_eq!(value.type_id(), VariantTypeId::ExtensionObject);
+    assert_eq!(Range::from_variant(&value), Ok(range.clone()));
+    let ranges = vec![range.clone(), range.clone()].into_variant();
+    assert_eq!(Vec::<Range>::from_variant(&ranges).unwrap().len(), 2);
+    // An extension object holding another type
+    let value = Variant::from(ExtensionObject::from_encodable(
+        ObjectId::EUInformation_Encoding_DefaultBinary,
+        &range,
+    ));
+    assert_eq!(
+        Range::from_variant(&value),
+        Err(StatusCode::BadTypeMismatch)
+    );
+}
+
+#[test]
+fn variant_dimensions() {
+    assert!(Variant::from(1).dimensions().is_empty());
+    assert_eq!(Variant::from(vec![1, 2, 3]).dimensions(), vec![3]);
+    assert!(Variant::dimensions_match(-1, None, &[]));
+    assert!(!Variant::dimensions_match(-1, None, &[3]));
+    assert!(Variant::dimensions_match(1, Some(&[4]), &[3]));
+    assert!(!Variant::dimensions_match(1, Some(&[2]), &[3]));
+    assert!(Variant::dimensions_match(-3, None, &[]));
+    assert!(Variant::dimensions_match(0, None, &[2, 2]));
+}
@@ -172,6 +172,7 @@ impl TryFrom<&NodeId> for VariantTypeId {
             if let Identifier::Numeric(type_id) = value.identifier {
                 match type_id {
                     type_id if type_id == DataTypeId::Boolean as u32 => Ok(VariantTypeId::Boolean),
+                    type_id if type_id == DataTypeId::SByte as u32 => Ok(VariantTypeId::SByte),
                     type_id if type_id == DataTypeId::Byte as u32 => Ok(VariantTypeId::Byte),
                     type_id if type_id == DataTypeId::Int16 as u32 => Ok(VariantTypeId::Int16),
                     type_id if type_id == DataTypeId::UInt16 as u32 => Ok(VariantTypeId::UInt16),
@@ -1476,9 +1477,25 @@ impl Variant {
                 VariantTypeId::Int64 => (v as i64).into(),
                 _ => Variant::Empty,
             },
-            Variant::Array(_) => {
-                // TODO Arrays including converting array of length 1 to scalar of same type
-                Variant::Empty
+            Variant::Array(ref array) => {
+                // Each element is converted to the target type and the dimensions are kept. Every
+                // element must convert for the array to convert.
+                // TODO converting array of length 1 to scalar of same type
+                if array.value_type == target_type {
+                    return self.clone();
+                }
+                let values = array
+                    .values
+                    .iter()
+                    .map(|v| v.convert(target_type))
+                    .collect::<Vec<Variant>>();
+                if values.contains(&Variant::Empty) {
+                    Variant::Empty
+                } else {
+                    Array::new_multi(target_type, values, array.dimensions.clone())
+                        .map(Variant::from)
+                        .unwrap_or(Variant::Empty)
+                }
             }
             // XmlElement everything is X
             _ => Variant::Empty,
@@ -1557,6 +1574,42 @@ impl Variant {
         }
     }
 
+    /// Returns the dimensions of the value, which are empty for a scalar
+    pub fn dimensions(&self) -> Vec<u32> {
+        match self {
+            Variant::Array(array) if array.has_dimensions() => array.dimensions.clone(),
+            Variant::Array(array) => vec![array.values.len() as u32],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Tests if the dimensions of a value agree with a value rank and array dimensions. Array
+    /// dimensions are the maximum length of each dimension, or 0 when it is unknown.
+    pub fn dimensions_match(
+        value_rank: i32,
+        array_dimensions: Option<&[u32]>,
+        dimensions: &[u32],
+    ) -> bool {
+        let rank_matches = match value_rank {
+            -3 => dimensions.len() <= 1,
+            -2 => true,
+            -1 => dimensions.is_empty(),
+            0 => !dimensions.is_empty(),
+            value_rank => dimensions.len() == value_rank as usize,
+        };
+        rank_matches
+            && match array_dimensions {
+                Some(array_dimensions) if !dimensions.is_empty() => {
+                    array_dimensions.len() == dimensions.len()
+                        && array_dimensions
+                            .iter()
+                            .zip(dimensions.iter())
+                            .all(|(max, len)| *max == 0 || len <= max)
+                }
+                _ => true,
+            }
+    }
+
     /// Converts the numeric type to a double or returns None
     pub fn as_f64(&self) -> Option<f64> {
         match *self {
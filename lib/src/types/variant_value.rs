@@ -0,0 +1,222 @@
+// OPCUA for Rust
+// SPDX-License-Identifier: MPL-2.0
+// Copyright (C) 2017-2022 Adam Lock
+
+//! Contains the `VariantValue` trait which converts Rust types to and from a `Variant`, so
+//! that values can be read and written without matching on the `Variant` by hand.
+
+use std::io::Cursor;
+
+use crate::types::{
+    byte_string::ByteString,
+    date_time::DateTime,
+    encoding::{BinaryEncoder, DecodingOptions},
+    extension_object::ExtensionObject,
+    guid::Guid,
+    localized_text::LocalizedText,
+    node_id::{ExpandedNodeId, NodeId},
+    qualified_name::QualifiedName,
+    service_types::*,
+    status_code::StatusCode,
+    string::UAString,
+    variant::{Variant, VariantTypeId},
+};
+
+/// A type which can be converted to and from a `Variant`. It is implemented for the scalar types
+/// of a `Variant`, for a `Vec` of them as an array, for `Option` where a null value is `None`,
+/// for the generated enums and for the generated structures which are held in an
+/// `ExtensionObject`.
+pub trait VariantValue: Sized {
+    /// The type of the `Variant` that holds the value, or the elements of an array
+    fn variant_type_id() -> VariantTypeId;
+
+    /// Converts the value from a `Variant`. Numeric values are converted implicitly, e.g. an
+    /// `Int16` can be converted to an `i32`. A value that cannot be converted is
+    /// `BadTypeMismatch`.
+    fn from_variant(value: &Variant) -> Result<Self, StatusCode>;
+
+    /// Converts the value into a `Variant`
+    fn into_variant(self) -> Variant;
+}
+
+macro_rules! variant_value_impl {
+    ($rtype: ty, $vtype: ident, $value: ident => $from: expr) => {
+        impl VariantValue for $rtype {
+            fn variant_type_id() -> VariantTypeId {
+                VariantTypeId::$vtype
+            }
+
+            fn from_variant(value: &Variant) -> Result<Self, StatusCode> {
+                match value.convert(VariantTypeId::$vtype) {
+                    Variant::$vtype($value) => Ok($from),
+                    _ => Err(StatusCode::BadTypeMismatch),
+                }
+            }
+
+            fn into_variant(self) -> Variant {
+                Variant::from(self)
+            }
+        }
+    };
+}
+
+variant_value_impl!(bool, Boolean, v => v);
+variant_value_impl!(i8, SByte, v => v);
+variant_value_impl!(u8, Byte, v => v);
+variant_value_impl!(i16, Int16, v => v);
+variant_value_impl!(u16, UInt16, v => v);
+variant_value_impl!(i32, Int32, v => v);
+variant_value_impl!(u32, UInt32, v => v);
+variant_value_impl!(i64, Int64, v => v);
+variant_value_impl!(u64, UInt64, v => v);
+variant_value_impl!(f32, Float, v => v);
+variant_value_impl!(f64, Double, v => v);
+variant_value_impl!(String, String, v => v.as_ref().to_string());
+variant_value_impl!(UAString, String, v => v);
+variant_value_impl!(DateTime, DateTime, v => *v);
+variant_value_impl!(Guid, Guid, v => *v);
+variant_value_impl!(StatusCode, StatusCode, v => v);
+variant_value_impl!(ByteString, ByteString, v => v);
+variant_value_impl!(QualifiedName, QualifiedName, v => *v);
+variant_value_impl!(LocalizedText, LocalizedText, v => *v);
+variant_value_impl!(NodeId, NodeId, v => *v);
+variant_value_impl!(ExpandedNodeId, ExpandedNodeId, v => *v);
+variant_value_impl!(ExtensionObject, ExtensionObject, v => *v);
+
+impl<T> VariantValue for Vec<T>
+where
+    T: VariantValue,
+{
+    fn variant_type_id() -> VariantTypeId {
+        T::variant_type_id()
+    }
+
+    /// Converts a single dimension array. A null value is an empty `Vec`.
+    fn from_variant(value: &Variant) -> Result<Self, StatusCode> {
+        match value {
+            Variant::Empty => Ok(Vec::new()),
+            Variant::Array(array) if !array.has_dimensions() || array.dimensions.len() == 1 => {
+                array.values.iter().map(T::from_variant).collect()
+            }
+            _ => Err(StatusCode::BadTypeMismatch),
+        }
+    }
+
+    fn into_variant(self) -> Variant {
+        let values = self
+            .into_iter()
+            .map(T::into_variant)
+            .collect::<Vec<Variant>>();
+        Variant::from((T::variant_type_id(), values))
+    }
+}
+
+impl<T> VariantValue for Option<T>
+where
+    T: VariantValue,
+{
+    fn variant_type_id() -> VariantTypeId {
+        T::variant_type_id()
+    }
+
+    fn from_variant(value: &Variant) -> Result<Self, StatusCode> {
+        match value {
+            Variant::Empty => Ok(None),
+            value => T::from_variant(value).map(Some),
+        }
+    }
+
+    fn into_variant(self) -> Variant {
+        self.map(T::into_variant).unwrap_or(Variant::Empty)
+    }
+}
+
+/// Generated structures are held in an `ExtensionObject` whose node id is the id of their
+/// binary encoding.
+impl<T> VariantValue for T
+where
+    T: MessageInfo + BinaryEncoder<T>,
+{
+    fn variant_type_id() -> VariantTypeId {
+        VariantTypeId::ExtensionObject
+    }
+
+    fn from_variant(value: &Variant) -> Result<Self, StatusCode> {
+        match value {
+            Variant::ExtensionObject(extension_object) => {
+                let decoded = extension_object
+                    .decode_inner::<T>(&DecodingOptions::default())
+                    .map_err(|_| StatusCode::BadTypeMismatch)?;
+                if extension_object.node_id == decoded.object_id().into() {
+                    Ok(decoded)
+                } else {
+                    Err(StatusCode::BadTypeMismatch)
+                }
+            }
+            _ => Err(StatusCode::BadTypeMismatch),
+        }
+    }
+
+    fn into_variant(self) -> Variant {
+        Variant::from(ExtensionObject::from_encodable(self.object_id(), &self))
+    }
+}
+
+/// Generated enums are held in an `Int32`
+macro_rules! variant_value_enum_impl {
+    ($($etype: ty),*) => {
+        $(
+            impl VariantValue for $etype {
+                fn variant_type_id() -> VariantTypeId {
+                    VariantTypeId::Int32
+                }
+
+                fn from_variant(value: &Variant) -> Result<Self, StatusCode> {
+                    let value = i32::from_variant(value)?;
+                    let mut stream = Cursor::new(value.to_le_bytes());
+                    Self::decode(&mut stream, &DecodingOptions::default())
+                        .map_err(|_| StatusCode::BadTypeMismatch)
+                }
+
+                fn into_variant(self) -> Variant {
+                    Variant::Int32(self as i32)
+                }
+            }
+        )*
+    };
+}
+
+variant_value_enum_impl!(
+    NamingRuleType,
+    OpenFileMode,
+    IdentityCriteriaType,
+    TrustListMasks,
+    PubSubState,
+    OverrideValueHandling,
+    DataSetOrderingType,
+    BrokerTransportQualityOfService,
+    DiagnosticsLevel,
+    PubSubDiagnosticsCounterClassification,
+    IdType,
+    NodeClass,
+    StructureType,
+    ApplicationType,
+    MessageSecurityMode,
+    UserTokenType,
+    SecurityTokenRequestType,
+    NodeAttributesMask,
+    BrowseDirection,
+    BrowseResultMask,
+    FilterOperator,
+    TimestampsToReturn,
+    HistoryUpdateType,
+    PerformUpdateType,
+    MonitoringMode,
+    DataChangeTrigger,
+    DeadbandType,
+    RedundancySupport,
+    ServerState,
+    ModelChangeStructureVerbMask,
+    AxisScaleEnumeration,
+    ExceptionDeviationFormat
+);
@@ -9,7 +9,7 @@
 //! * Stress variables that change rapidly
 //! * Method
 //! * Events
-//! * Http server with metrics (http://localhost:8585)
+//! * Http server with metrics in OpenMetrics format (http://localhost:8585/metrics)
 //!
 //! If you want a simpler`simple-server`
 //!
@@ -34,7 +34,8 @@ struct Args {
     help: bool,
     raise_events: bool,
     config_path: PathBuf,
-    content_path: PathBuf,
+    metrics_token: String,
+    abort_token: Option<String>,
 }
 
 impl Default for Args {
@@ -50,19 +51,12 @@ impl Default for Args {
             }
         }
 
-        let content_path = if !PathBuf::from("./index.html").exists() {
-            // For docker image or custom deployment
-            PathBuf::from(".")
-        } else {
-            // Server src dir
-            PathBuf::from("../../lib/server/html")
-        };
-
         Self {
             help: false,
             raise_events,
             config_path,
-            content_path,
+            metrics_token: String::from("demo-metrics"),
+            abort_token: None,
         }
     }
 }
@@ -80,13 +74,17 @@ impl Args {
         } else {
             (config_path == default.config_path) && default.raise_events
         };
-        let content_path = default.content_path;
+        let metrics_token = args
+            .value_from_str("--metrics-token")
+            .unwrap_or(default.metrics_token);
+        let abort_token = args.opt_value_from_str("--abort-token")?;
 
         Ok(Args {
             help: args.contains(["-h", "--help"]),
             raise_events,
             config_path,
-            content_path,
+            metrics_token,
+            abort_token,
         })
     }
 
@@ -97,9 +95,13 @@ impl Args {
 Usage:
   -h, --help                 Show help
   -r, --raise-events         Raise events on a timer (default: {:?})"
-  -c, --config [config-file] Path to a configuration file (default: {})"#,
+  -c, --config [config-file] Path to a configuration file (default: {})
+  --metrics-token [token]    Bearer token for scraping http://localhost:8585/metrics (default: {})
+  --abort-token [token]      Bearer token that enables aborting the server with
+                             POST http://localhost:8585/server/abort (default: disabled)"#,
             args.raise_events,
-            args.config_path.to_str().as_ref().unwrap()
+            args.config_path.to_str().as_ref().unwrap(),
+            args.metrics_token
         );
     }
 }
@@ -140,23 +142,17 @@ fn main() {
         historical::add_providers(&mut server);
 
         // Start the http server, used for metrics
-        start_http_server(&server, args.content_path.to_str().unwrap());
+        start_http_server(&server, &args.metrics_token, args.abort_token.as_deref());
 
         // Run the server. This does not ordinarily exit so you must Ctrl+C to terminate
         server.run();
     }
 }
 
-fn start_http_server(server: &Server, content_path: &str) {
-    let server_state = server.server_state();
-    let connections = server.connections();
-    let metrics = server.server_metrics();
-    // The index.html is in a path relative to the working dir.
-    let _ = http::run_http_server(
-        "127.0.0.1:8585",
-        content_path,
-        server_state,
-        connections,
-        metrics,
-    );
+fn start_http_server(server: &Server, metrics_token: &str, abort_token: Option<&str>) {
+    let mut metrics_server = http::MetricsServer::new(server, "127.0.0.1:8585", metrics_token);
+    if let Some(abort_token) = abort_token {
+        metrics_server = metrics_server.abort_token(abort_token);
+    }
+    let _ = metrics_server.run();
 }
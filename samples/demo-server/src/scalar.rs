@@ -269,7 +269,7 @@ fn set_dynamic_timers(server: &mut Server, ns: u16) {
 
     // Standard change timers
     server.add_polling_action(250, move || {
-        let mut address_space = address_space.write();
+        let address_space = address_space.read();
         // Scalar
         let now = DateTime::now();
         SCALAR_TYPES.iter().for_each(|sn| {
@@ -320,7 +320,7 @@ fn set_stress_timer(server: &mut Server, node_ids: Vec<NodeId>) {
     let address_space = server.address_space();
     server.add_polling_action(100, move || {
         let mut rng = rand::thread_rng();
-        let mut address_space = address_space.write();
+        let address_space = address_space.read();
         let now = DateTime::now();
         node_ids.iter().for_each(|node_id| {
             let value: Variant = rng.gen::<i32>().into();
@@ -116,7 +116,8 @@ fn add_example_variables(server: &mut Server, ns: u16) {
             let mut data = data.lock();
             data.0 += 1;
             data.1 = !data.1;
-            let mut address_space = address_space.write();
+            // Values can be set under a read lock, so the update does not block readers
+            let address_space = address_space.read();
             let now = DateTime::now();
             let _ = address_space.set_variable_value(v1_node.clone(), data.0 as i32, &now, &now);
             let _ = address_space.set_variable_value(v2_node.clone(), data.1, &now, &now);
@@ -56,7 +56,7 @@ _.each(basic_types_import_map, (types, module) => {
 // Types that will be marked as serializable
 let serde_supported_types = ["ReadValueId", "DataChangeFilter", "EventFilter", "SimpleAttributeOperand", "ContentFilter",
     "ContentFilterElement", "MonitoredItemNotification", "ServerDiagnosticsSummaryDataType", "EventFieldList",
-    "DataChangeTrigger", "FilterOperator", "TimestampsToReturn", "MonitoringMode"];
+    "DataChangeTrigger", "FilterOperator", "TimestampsToReturn", "MonitoringMode", "NodeClass"];
 
 // The map from OPC UA types to their corresponding Rust types.
 let type_name_mappings = {